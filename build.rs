@@ -0,0 +1,92 @@
+// src/abi.rs からユーザー空間向けの C ヘッダ (rost_abi.h) と Rust のクレート (rost-abi) を作る
+// いつもは OUT_DIR に書き、ABI_OUT_DIR が指定されていればそこにも書く (make abi)
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+#[allow(dead_code)]
+#[path = "src/abi.rs"]
+mod abi;
+
+/// Rust の型を C の型と配列の長さ ("[N]" / 長さ 0 なら "[]") にする
+fn c_type(ty: &str) -> (String, String) {
+    let scalar = |ty: &str| match ty.trim() {
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "u64" => "uint64_t",
+        "i8" => "int8_t",
+        "i16" => "int16_t",
+        "i32" => "int32_t",
+        "i64" => "int64_t",
+        other => panic!("abi: unsupported field type {}", other),
+    };
+    match ty.strip_prefix('[').and_then(|ty| ty.strip_suffix(']')) {
+        Some(array) => {
+            let (element, len) = array.split_once(';').expect("abi: array without a length");
+            let len = len.trim();
+            let suffix = if len == "0" { String::from("[]") } else { format!("[{}]", len) };
+            (String::from(scalar(element)), suffix)
+        }
+        None => (String::from(scalar(ty)), String::new()),
+    }
+}
+
+fn c_header() -> String {
+    let mut out = String::new();
+    out.push_str("/* src/abi.rs から生成 (編集しないこと) */\n");
+    out.push_str("#ifndef ROST_ABI_H\n#define ROST_ABI_H\n\n#include <stdint.h>\n\n");
+    for (name, number) in abi::syscalls::SYSCALLS {
+        writeln!(out, "#define {} {}", name, number).unwrap();
+    }
+    out.push('\n');
+    for (name, value) in abi::CONSTANTS {
+        if *value < 0 {
+            writeln!(out, "#define {} ({})", name, value).unwrap();
+        } else {
+            writeln!(out, "#define {} {:#x}", name, value).unwrap();
+        }
+    }
+    for def in abi::STRUCTS {
+        writeln!(out, "\nstruct {} {{", def.c_name).unwrap();
+        for field in def.fields {
+            let (ty, suffix) = c_type(field.ty);
+            writeln!(out, "    {} {}{};", ty, field.name, suffix).unwrap();
+        }
+        out.push_str("};\n");
+        writeln!(out, "_Static_assert(sizeof(struct {}) == {}, \"struct {} layout\");",
+            def.c_name, def.size, def.c_name).unwrap();
+    }
+    out.push_str("\n#endif\n");
+    out
+}
+
+/// abi.rs をそのまま no_std のクレートにする
+fn write_crate(dir: &Path) {
+    let src = dir.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(dir.join("Cargo.toml"),
+        "[package]\nname = \"rost-abi\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[lib]\npath = \"src/lib.rs\"\n")
+        .unwrap();
+    let abi = fs::read_to_string("src/abi.rs").unwrap();
+    fs::write(src.join("lib.rs"), format!("#![no_std]\n{}", abi)).unwrap();
+}
+
+fn write_all(dir: &Path) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("rost_abi.h"), c_header()).unwrap();
+    write_crate(&dir.join("rost-abi"));
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/abi.rs");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=ABI_OUT_DIR");
+
+    write_all(Path::new(&env::var("OUT_DIR").unwrap()));
+    if let Ok(dir) = env::var("ABI_OUT_DIR") {
+        write_all(Path::new(&dir));
+    }
+}
@@ -0,0 +1,51 @@
+#!/bin/bash
+
+# cargo run / cargo test から呼ばれる QEMU ランナー
+# 引数のカーネル ELF を GRUB の ISO に詰めて起動する
+# テストバイナリ (target/.../deps/ 以下) の場合は isa-debug-exit の終了コードを変換する
+
+set -e
+
+KERNEL="$1"
+shift
+
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+
+mkdir -p "$WORK/iso/boot/grub"
+cp "$(dirname "$0")/iso/boot/grub/grub.cfg" "$WORK/iso/boot/grub/"
+cp "$KERNEL" "$WORK/iso/boot/kernel.elf"
+# make initramfs で作ったアーカイブがあれば一緒に載せる
+if [ -f "$(dirname "$0")/iso/boot/initramfs.cpio" ]; then
+    cp "$(dirname "$0")/iso/boot/initramfs.cpio" "$WORK/iso/boot/"
+fi
+grub-mkrescue -o "$WORK/kernel.iso" "$WORK/iso" 2>/dev/null
+
+QEMU_ARGS=(
+    -cdrom "$WORK/kernel.iso"
+    -serial stdio
+    -device isa-debug-exit,iobase=0xf4,iosize=0x04
+)
+# GDB_PORT=1234 なら COM2 を TCP に出す (カーネルの gdb オプションと組み合わせて
+# ホストで gdb -ex "target remote :1234" する)
+if [ -n "$GDB_PORT" ]; then
+    QEMU_ARGS+=(-serial "tcp::$GDB_PORT,server,nowait")
+fi
+
+case "$KERNEL" in
+    */deps/*)
+        # QemuExitCode::Success (0x10) → (0x10 << 1) | 1 = 33
+        set +e
+        timeout 300 qemu-system-x86_64 "${QEMU_ARGS[@]}" -display none -no-reboot "$@"
+        status=$?
+        set -e
+        case $status in
+            33) exit 0 ;;
+            124) echo "test timed out"; exit 1 ;;
+            *) exit 1 ;;
+        esac
+        ;;
+    *)
+        exec qemu-system-x86_64 "${QEMU_ARGS[@]}" "$@"
+        ;;
+esac
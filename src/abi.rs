@@ -0,0 +1,254 @@
+//! ユーザー空間との ABI (システムコール番号、定数、構造体の配置)
+//! build.rs もこのファイルを読み込んで C のヘッダとユーザー向けのクレートを作るので、core 以外に依存しないこと
+
+/// ヘッダの生成に使う構造体のフィールド
+#[derive(Debug, Clone, Copy)]
+pub struct Field {
+    pub name: &'static str,
+    /// Rust での型 ("u64", "[u8; 4]" など)
+    pub ty: &'static str,
+}
+
+/// ヘッダの生成に使う構造体の定義
+#[derive(Debug, Clone, Copy)]
+pub struct StructDef {
+    /// Rust での名前
+    pub name: &'static str,
+    /// C での名前 (struct <c_name>)
+    pub c_name: &'static str,
+    pub size: usize,
+    pub fields: &'static [Field],
+}
+
+macro_rules! syscalls {
+    ($($name:ident = $value:expr),* $(,)?) => {
+        $(pub const $name: u64 = $value;)*
+
+        /// (名前, 番号) の一覧
+        pub const SYSCALLS: &[(&str, u64)] = &[$((stringify!($name), $name)),*];
+    };
+}
+
+macro_rules! constants {
+    ($($name:ident: $ty:ty = $value:expr),* $(,)?) => {
+        $(pub const $name: $ty = $value;)*
+
+        /// (名前, 値) の一覧
+        pub const CONSTANTS: &[(&str, i64)] = &[$((stringify!($name), $name as i64)),*];
+    };
+}
+
+macro_rules! structs {
+    ($($(#[doc = $doc:literal])* struct $name:ident as $c_name:literal {
+        $($field:ident: $ty:tt),* $(,)?
+    })*) => {
+        $(
+            $(#[doc = $doc])*
+            #[repr(C)]
+            #[derive(Debug, Clone, Copy, Default)]
+            pub struct $name {
+                $(pub $field: $ty),*
+            }
+        )*
+
+        pub const STRUCTS: &[StructDef] = &[$(StructDef {
+            name: stringify!($name),
+            c_name: $c_name,
+            size: core::mem::size_of::<$name>(),
+            fields: &[$(Field { name: stringify!($field), ty: stringify!($ty) }),*],
+        }),*];
+    };
+}
+
+/// システムコール番号 (Linux と同じものは同じ番号)
+pub mod syscalls {
+    syscalls! {
+        SYS_READ = 0,
+        SYS_WRITE = 1,
+        SYS_PREAD64 = 17,
+        SYS_PWRITE64 = 18,
+        SYS_READV = 19,
+        SYS_WRITEV = 20,
+        SYS_OPEN = 2,
+        SYS_CLOSE = 3,
+        SYS_STAT = 4,
+        SYS_FSTAT = 5,
+        SYS_STATFS = 137,
+        SYS_LSEEK = 8,
+        SYS_DUP = 32,
+        SYS_DUP2 = 33,
+        SYS_EXIT = 60,
+        SYS_FORK = 57,
+        SYS_CLONE = 56,
+        SYS_ARCH_PRCTL = 158,
+        SYS_EXECVE = 59,
+        SYS_GETPID = 39,
+        SYS_GETPPID = 110,
+        SYS_GETTID = 186,
+        SYS_SLEEP = 35,
+        SYS_MMAP = 9,
+        SYS_MUNMAP = 11,
+        SYS_BRK = 12,
+        SYS_CLOCK_GETTIME = 228,
+        SYS_GETTIMEOFDAY = 96,
+        SYS_PIPE2 = 293,
+        SYS_GETCWD = 79,
+        SYS_CHDIR = 80,
+        SYS_RENAME = 82,
+        SYS_MKDIR = 83,
+        SYS_RMDIR = 84,
+        SYS_LINK = 86,
+        SYS_UNLINK = 87,
+        SYS_SYMLINK = 88,
+        SYS_READLINK = 89,
+        SYS_SOCKET = 41,
+        SYS_CONNECT = 42,
+        SYS_ACCEPT = 43,
+        SYS_SENDTO = 44,
+        SYS_RECVFROM = 45,
+        SYS_BIND = 49,
+        SYS_LISTEN = 50,
+        SYS_SYSINFO = 99,
+        SYS_GETRLIMIT = 97,
+        SYS_GETUID = 102,
+        SYS_GETGID = 104,
+        SYS_SETUID = 105,
+        SYS_SETGID = 106,
+        SYS_GETEUID = 107,
+        SYS_GETEGID = 108,
+        SYS_SETRLIMIT = 160,
+        SYS_IOPL = 172,
+        SYS_IOPERM = 173,
+        SYS_SCHED_SETAFFINITY = 203,
+        SYS_SCHED_GETAFFINITY = 204,
+        SYS_GETDENTS64 = 217,
+        SYS_OPENAT = 257,
+        SYS_MKDIRAT = 258,
+        SYS_NEWFSTATAT = 262,
+        SYS_UNLINKAT = 263,
+        SYS_RENAMEAT = 264,
+        SYS_READLINKAT = 267,
+        SYS_GETRANDOM = 318,
+        SYS_FTRUNCATE = 77,
+        // Linux には無い (glibc は /dev/shm を使う) ので独自の番号
+        SYS_SHM_OPEN = 512,
+        SYS_SHM_UNLINK = 513,
+        // 障害注入の設定 (独自)
+        SYS_FAULT_INJECT = 514,
+        SYS_POLL = 7,
+        SYS_FCNTL = 72,
+        SYS_MQ_OPEN = 240,
+        SYS_MQ_UNLINK = 241,
+        SYS_MQ_TIMEDSEND = 242,
+        SYS_MQ_TIMEDRECEIVE = 243,
+        SYS_MQ_GETSETATTR = 245,
+        SYS_SYNC = 162,
+        SYS_REBOOT = 169,
+    }
+}
+
+constants! {
+    // open フラグ
+    O_RDONLY: i32 = 0o0,
+    O_WRONLY: i32 = 0o1,
+    O_RDWR: i32 = 0o2,
+    O_ACCMODE: i32 = 0o3,
+    O_CREAT: i32 = 0o100,
+    O_EXCL: i32 = 0o200,
+    O_TRUNC: i32 = 0o1000,
+    O_APPEND: i32 = 0o2000,
+    O_NONBLOCK: i32 = 0o4000,
+    O_DIRECTORY: i32 = 0o200000,
+
+    // *at システムコール
+    // dirfd に AT_FDCWD を渡すとカレントディレクトリを基準にする
+    AT_FDCWD: i32 = -100,
+    // unlinkat でディレクトリを消す (rmdir と同じ)
+    AT_REMOVEDIR: i32 = 0x200,
+
+    // mmap の保護フラグ
+    PROT_READ: i32 = 0x1,
+    PROT_WRITE: i32 = 0x2,
+    PROT_EXEC: i32 = 0x4,
+    // mmap のフラグ
+    MAP_SHARED: i32 = 0x01,
+    MAP_PRIVATE: i32 = 0x02,
+    MAP_ANONYMOUS: i32 = 0x20,
+
+    // 初期スタックの補助ベクタ (auxv) の種類
+    AT_NULL: u64 = 0,
+    AT_PAGESZ: u64 = 6,
+    AT_ENTRY: u64 = 9,
+
+    // lseek の whence
+    SEEK_SET: i32 = 0,
+    SEEK_CUR: i32 = 1,
+    SEEK_END: i32 = 2,
+
+    // st_mode のファイル種別ビット
+    S_IFMT: u32 = 0o170000,
+    S_IFIFO: u32 = 0o010000,
+    S_IFCHR: u32 = 0o020000,
+    S_IFDIR: u32 = 0o040000,
+    S_IFREG: u32 = 0o100000,
+    S_IFLNK: u32 = 0o120000,
+    S_IFSOCK: u32 = 0o140000,
+
+    // getdents64 の d_type
+    DT_CHR: u8 = 2,
+    DT_DIR: u8 = 4,
+    DT_REG: u8 = 8,
+    DT_LNK: u8 = 10,
+
+    // ソケット
+    AF_INET: u16 = 2,
+    SOCK_STREAM: i32 = 1,
+    SOCK_DGRAM: i32 = 2,
+    // socket の type に OR するフラグ
+    SOCK_NONBLOCK: i32 = 0o4000,
+    SOCK_CLOEXEC: i32 = 0o2000000,
+    IPPROTO_TCP: i32 = 6,
+    IPPROTO_UDP: i32 = 17,
+}
+
+structs! {
+    /// stat/fstat が返すファイルのメタデータ
+    /// 時刻は Unix 時刻 (秒)
+    struct Stat as "stat" {
+        st_ino: u64,
+        st_mode: u32,
+        st_nlink: u64,
+        st_size: u64,
+        st_atime: u64,
+        st_mtime: u64,
+        st_ctime: u64,
+        st_uid: u32,
+        st_gid: u32,
+    }
+
+    /// getdents64 が返すエントリの先頭 (d_name は NUL 終端で、エントリは d_reclen バイト)
+    struct Dirent64 as "linux_dirent64" {
+        d_ino: u64,
+        d_off: i64,
+        d_reclen: u16,
+        d_type: u8,
+        d_name: [u8; 0],
+    }
+
+    /// ユーザー空間とやり取りする struct sockaddr_in
+    /// ポートとアドレスはネットワークバイトオーダー
+    struct SockAddrIn as "sockaddr_in" {
+        sin_family: u16,
+        sin_port: u16,
+        sin_addr: [u8; 4],
+        sin_zero: [u8; 8],
+    }
+}
+
+/// linux_dirent64 の d_name より前の部分 (d_ino, d_off, d_reclen, d_type)
+pub const DIRENT_HEADER_SIZE: usize = core::mem::offset_of!(Dirent64, d_name);
+
+// C の側と同じ配置になっていること
+const _: () = assert!(core::mem::size_of::<Stat>() == 64);
+const _: () = assert!(DIRENT_HEADER_SIZE == 19);
+const _: () = assert!(core::mem::size_of::<SockAddrIn>() == 16);
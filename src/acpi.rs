@@ -0,0 +1,301 @@
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+static MADT: Mutex<Option<MadtInfo>> = Mutex::new(None);
+static SLEEP: Mutex<Option<SleepInfo>> = Mutex::new(None);
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // 以下は revision >= 2 のみ
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// すべての ACPI システム記述テーブル共通のヘッダ
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInfo {
+    pub acpi_id: u32,
+    pub apic_id: u32,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// ISA IRQ → GSI の割り込みソースオーバーライド
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct MadtInfo {
+    pub local_apic_address: u64,
+    pub cpus: Vec<CpuInfo>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+/// 電源断 (S5) に必要な FADT と DSDT の情報
+#[derive(Debug, Clone, Copy)]
+pub struct SleepInfo {
+    /// SCI_EN が立っていなければここに acpi_enable を書いて ACPI モードにする
+    pub smi_cmd: u16,
+    pub acpi_enable: u8,
+    pub pm1a_control: u16,
+    /// 0 ならない
+    pub pm1b_control: u16,
+    /// DSDT の \_S5 パッケージにある SLP_TYPa / SLP_TYPb
+    pub slp_typa: u16,
+    pub slp_typb: u16,
+}
+
+fn checksum_ok(addr: u64, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+fn phys(addr: u64) -> u64 {
+    crate::memory::phys_to_virt(PhysAddr::new(addr)).as_u64()
+}
+
+/// BIOS 領域から RSDP を探す
+fn find_rsdp() -> Option<u64> {
+    // EBDA の先頭 1 KiB と 0xE0000..0x100000 を 16 バイト境界で探す
+    let ebda = unsafe { core::ptr::read_volatile(phys(0x40E) as *const u16) } as u64 * 16;
+    let ranges = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+
+    for (start, end) in ranges {
+        if start == 0 {
+            continue;
+        }
+        let mut addr = start;
+        while addr + 20 <= end {
+            let virt = phys(addr);
+            let signature = unsafe { core::slice::from_raw_parts(virt as *const u8, 8) };
+            if signature == b"RSD PTR " && checksum_ok(virt, 20) {
+                return Some(addr);
+            }
+            addr += 16;
+        }
+    }
+    None
+}
+
+/// 指定したシグネチャの ACPI テーブルの物理アドレスを返す
+pub fn find_table(signature: &[u8; 4]) -> Option<u64> {
+    let rsdp_addr = find_rsdp()?;
+    let rsdp = unsafe { &*(phys(rsdp_addr) as *const Rsdp) };
+
+    // revision 2 以降は 64bit ポインタの XSDT を使う
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (rsdp.rsdt_address as u64, 4)
+    };
+
+    let header = unsafe { *(phys(root) as *const SdtHeader) };
+    let count = (header.length as usize - core::mem::size_of::<SdtHeader>()) / entry_size;
+    let entries = phys(root) + core::mem::size_of::<SdtHeader>() as u64;
+
+    for i in 0..count {
+        let entry_addr = entries + (i * entry_size) as u64;
+        let table = unsafe {
+            if entry_size == 8 {
+                core::ptr::read_unaligned(entry_addr as *const u64)
+            } else {
+                core::ptr::read_unaligned(entry_addr as *const u32) as u64
+            }
+        };
+        let table_header = unsafe { *(phys(table) as *const SdtHeader) };
+        if &table_header.signature == signature
+            && checksum_ok(phys(table), table_header.length as usize)
+        {
+            return Some(table);
+        }
+    }
+    None
+}
+
+fn parse_madt(addr: u64) -> MadtInfo {
+    let base = phys(addr);
+    let header = unsafe { *(base as *const SdtHeader) };
+    let read_u8 = |offset: u64| unsafe { *((base + offset) as *const u8) };
+    let read_u16 = |offset: u64| unsafe { core::ptr::read_unaligned((base + offset) as *const u16) };
+    let read_u32 = |offset: u64| unsafe { core::ptr::read_unaligned((base + offset) as *const u32) };
+    let read_u64 = |offset: u64| unsafe { core::ptr::read_unaligned((base + offset) as *const u64) };
+
+    let mut info = MadtInfo {
+        local_apic_address: read_u32(36) as u64,
+        cpus: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    // エントリはヘッダ (36) + LAPIC アドレス (4) + フラグ (4) の後から始まる
+    let mut offset = 44u64;
+    while offset + 2 <= header.length as u64 {
+        let entry_type = read_u8(offset);
+        let entry_len = read_u8(offset + 1) as u64;
+        if entry_len < 2 {
+            break;
+        }
+
+        match entry_type {
+            // Processor Local APIC
+            0 => {
+                let flags = read_u32(offset + 4);
+                info.cpus.push(CpuInfo {
+                    acpi_id: read_u8(offset + 2) as u32,
+                    apic_id: read_u8(offset + 3) as u32,
+                    // bit0: 有効, bit1: オンライン化可能
+                    enabled: flags & 0b11 != 0,
+                });
+            }
+            // I/O APIC
+            1 => info.io_apics.push(IoApicInfo {
+                id: read_u8(offset + 2),
+                address: read_u32(offset + 4),
+                gsi_base: read_u32(offset + 8),
+            }),
+            // Interrupt Source Override
+            2 => info.overrides.push(InterruptOverride {
+                source: read_u8(offset + 3),
+                gsi: read_u32(offset + 4),
+                flags: read_u16(offset + 8),
+            }),
+            // Local APIC Address Override
+            5 => info.local_apic_address = read_u64(offset + 4),
+            // Processor Local x2APIC
+            9 => {
+                let flags = read_u32(offset + 8);
+                info.cpus.push(CpuInfo {
+                    acpi_id: read_u32(offset + 12),
+                    apic_id: read_u32(offset + 4),
+                    enabled: flags & 0b11 != 0,
+                });
+            }
+            _ => {}
+        }
+
+        offset += entry_len;
+    }
+
+    info
+}
+
+/// FADT から PM1 制御レジスタなどを、DSDT から \_S5 の値を読む
+fn parse_fadt(addr: u64) -> Result<SleepInfo, &'static str> {
+    let base = phys(addr);
+    let header = unsafe { *(base as *const SdtHeader) };
+    let read_u8 = |offset: u64| unsafe { *((base + offset) as *const u8) };
+    let read_u32 = |offset: u64| unsafe { core::ptr::read_unaligned((base + offset) as *const u32) };
+    let read_u64 = |offset: u64| unsafe { core::ptr::read_unaligned((base + offset) as *const u64) };
+
+    // ACPI 2.0 以降の X_DSDT があればそちらを使う
+    let mut dsdt = read_u32(40) as u64;
+    if header.length >= 148 && read_u64(140) != 0 {
+        dsdt = read_u64(140);
+    }
+    let pm1a_control = read_u32(64) as u16;
+    if dsdt == 0 || pm1a_control == 0 {
+        return Err("no DSDT or PM1a control block");
+    }
+    let (slp_typa, slp_typb) = parse_s5(dsdt).ok_or("\\_S5 not found in DSDT")?;
+
+    Ok(SleepInfo {
+        smi_cmd: read_u32(48) as u16,
+        acpi_enable: read_u8(52),
+        pm1a_control,
+        pm1b_control: read_u32(68) as u16,
+        slp_typa,
+        slp_typb,
+    })
+}
+
+/// DSDT の AML から Name(\_S5, Package() { SLP_TYPa, SLP_TYPb, ... }) を探す
+/// AML を解釈せず、NameOp と PackageOp の並びをバイト列から見つける
+fn parse_s5(dsdt: u64) -> Option<(u16, u16)> {
+    let header = unsafe { *(phys(dsdt) as *const SdtHeader) };
+    let bytes = unsafe { core::slice::from_raw_parts(phys(dsdt) as *const u8, header.length as usize) };
+    let start = core::mem::size_of::<SdtHeader>();
+
+    let position = (start + 2..bytes.len().saturating_sub(5)).find(|&i| {
+        &bytes[i..i + 4] == b"_S5_"
+            // NameOp (0x08) の直後か、ルートを示す '\' を挟んだ後
+            && (bytes[i - 1] == 0x08 || (bytes[i - 2] == 0x08 && bytes[i - 1] == b'\\'))
+            // PackageOp
+            && bytes[i + 4] == 0x12
+    })?;
+
+    // PkgLength (先頭バイトの上位2ビットが後続バイト数) と NumElements を飛ばす
+    let mut offset = position + 5;
+    offset += ((*bytes.get(offset)? & 0xC0) >> 6) as usize + 2;
+
+    let mut read_value = || {
+        // BytePrefix (0x0A) が付いていればその次のバイト、ZeroOp/OneOp はそのまま値になる
+        if *bytes.get(offset)? == 0x0A {
+            offset += 1;
+        }
+        let value = *bytes.get(offset)? as u16;
+        offset += 1;
+        Some(value)
+    };
+    let slp_typa = read_value()?;
+    let slp_typb = read_value()?;
+    Some((slp_typa, slp_typb))
+}
+
+pub fn init() -> Result<(), &'static str> {
+    if !crate::config::enabled(crate::config::Feature::Acpi) {
+        return Err("Disabled by config");
+    }
+    let madt_addr = find_table(b"APIC").ok_or("MADT not found")?;
+    let madt = parse_madt(madt_addr);
+    crate::info!("ACPI: {} CPU(s), {} I/O APIC(s), {} override(s)",
+        madt.cpus.len(), madt.io_apics.len(), madt.overrides.len());
+    *MADT.lock() = Some(madt);
+
+    // 電源断に使うだけなので、見つからなくても初期化は続ける
+    match find_table(b"FACP").ok_or("FADT not found").and_then(parse_fadt) {
+        Ok(sleep) => *SLEEP.lock() = Some(sleep),
+        Err(e) => crate::debug!("ACPI poweroff unavailable: {}", e),
+    }
+    Ok(())
+}
+
+pub fn madt() -> Option<MadtInfo> {
+    MADT.lock().clone()
+}
+
+pub fn sleep_info() -> Option<SleepInfo> {
+    *SLEEP.lock()
+}
@@ -0,0 +1,497 @@
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, Ordering};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+use crate::sync::IrqMutex;
+
+/// スラブで扱うブロックサイズ (Inode, OpenFile, Process などの小さなオブジェクト向け)
+/// サイズはアラインメントも兼ねるため2の累乗にする
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+const NUM_CLASSES: usize = 9;
+
+/// ヒープ拡張の上限
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+/// 一度に拡張する最小サイズ
+const HEAP_GROW_MIN: usize = 64 * 1024; // 64 KiB
+
+/// 追跡できる生存中の割り当ての数 (開番地法のハッシュ表)
+const TRACK_SLOTS: usize = 4096;
+/// 記録する呼び出し元の数
+const MAX_SITES: usize = 128;
+/// 呼び出し元として記録するスタックの深さ
+/// 先頭はたいてい alloc クレート内 (Box::new, RawVec など) なので、何段かたどって記録する
+pub const SITE_DEPTH: usize = 6;
+/// バックトレースの先頭のうちアロケータ自身のフレーム (track_alloc, alloc)
+const SKIP_FRAMES: usize = 2;
+
+/// 解放済みの毒の検査と赤ゾーン (cargo の heap-poison feature、デバッグ用)
+/// 割り当ての大きさが変わるので、起動後には切り替えられない
+pub const POISONING: bool = cfg!(feature = "heap-poison");
+/// 解放したメモリを埋める値 (Linux の POISON_FREE と同じ)
+pub const POISON_FREE: u8 = 0x6B;
+/// 割り当てたばかりのメモリを埋める値 (初期化し忘れを見つけやすくする)
+pub const POISON_INUSE: u8 = 0x5A;
+/// 赤ゾーンを埋める値
+const REDZONE_BYTE: u8 = 0xBB;
+/// 割り当ての前後に置く赤ゾーンの大きさ (前はアラインメントまで広げる)
+const REDZONE_SIZE: usize = 16;
+/// 解放済みブロックの先頭でフリーリストが使う部分 (毒の検査から外す)
+const FREE_HEADER_SIZE: usize = core::mem::size_of::<ListNode>();
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocStats {
+    pub slab_allocs: [u64; NUM_CLASSES],
+    pub slab_frees: [u64; NUM_CLASSES],
+    pub fallback_allocs: u64,
+    pub fallback_frees: u64,
+    pub heap_grows: u64,
+    pub failed_allocs: u64,
+    pub heap_size: usize,
+    pub heap_used: usize,
+    /// 解放されていない割り当て
+    pub live_allocs: u64,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+}
+
+impl AllocStats {
+    const fn new() -> Self {
+        Self {
+            slab_allocs: [0; NUM_CLASSES],
+            slab_frees: [0; NUM_CLASSES],
+            fallback_allocs: 0,
+            fallback_frees: 0,
+            heap_grows: 0,
+            failed_allocs: 0,
+            heap_size: 0,
+            heap_used: 0,
+            live_allocs: 0,
+            live_bytes: 0,
+            peak_bytes: 0,
+        }
+    }
+}
+
+/// 割り当てを行った呼び出し元ごとの生存中の割り当て
+#[derive(Debug, Clone, Copy)]
+pub struct AllocSite {
+    /// 戻りアドレス (呼び出し元から順に)
+    pub frames: [u64; SITE_DEPTH],
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl AllocSite {
+    const EMPTY: AllocSite = AllocSite { frames: [0; SITE_DEPTH], count: 0, bytes: 0 };
+}
+
+/// heap_report の結果
+#[derive(Debug, Clone)]
+pub struct HeapReport {
+    pub stats: AllocStats,
+    pub tracking: bool,
+    /// 生存中のバイト数が多い順
+    pub sites: Vec<AllocSite>,
+    /// 表が一杯で追跡できなかった割り当て
+    pub untracked: u64,
+}
+
+#[derive(Clone, Copy)]
+struct LiveAlloc {
+    /// 0 なら空きスロット
+    ptr: usize,
+    size: usize,
+    site: usize,
+}
+
+/// 生存中の割り当て (ポインタ → 呼び出し元) の表
+/// アロケータの中で使うので、ヒープを使わない固定長の表にする
+struct AllocTracker {
+    live: [LiveAlloc; TRACK_SLOTS],
+    len: usize,
+    sites: [AllocSite; MAX_SITES],
+    site_count: usize,
+    untracked: u64,
+}
+
+impl AllocTracker {
+    const fn new() -> Self {
+        Self {
+            live: [LiveAlloc { ptr: 0, size: 0, site: 0 }; TRACK_SLOTS],
+            len: 0,
+            sites: [AllocSite::EMPTY; MAX_SITES],
+            site_count: 0,
+            untracked: 0,
+        }
+    }
+
+    fn slot_of(ptr: usize) -> usize {
+        // 下位ビットはアラインメントでほぼ 0 なので捨てる
+        (ptr >> 3).wrapping_mul(0x9E37_79B9_7F4A_7C15) % TRACK_SLOTS
+    }
+
+    fn find_site(&mut self, frames: &[u64; SITE_DEPTH]) -> Option<usize> {
+        if let Some(index) = self.sites[..self.site_count].iter().position(|site| site.frames == *frames) {
+            return Some(index);
+        }
+        if self.site_count == MAX_SITES {
+            return None;
+        }
+        self.sites[self.site_count] = AllocSite { frames: *frames, count: 0, bytes: 0 };
+        self.site_count += 1;
+        Some(self.site_count - 1)
+    }
+
+    fn insert(&mut self, ptr: usize, size: usize, frames: &[u64; SITE_DEPTH]) {
+        // 探索が長くならないよう表の 3/4 までしか使わない
+        if self.len >= TRACK_SLOTS * 3 / 4 {
+            self.untracked += 1;
+            return;
+        }
+        let site = match self.find_site(frames) {
+            Some(site) => site,
+            None => {
+                self.untracked += 1;
+                return;
+            }
+        };
+        let mut slot = Self::slot_of(ptr);
+        while self.live[slot].ptr != 0 {
+            slot = (slot + 1) % TRACK_SLOTS;
+        }
+        self.live[slot] = LiveAlloc { ptr, size, site };
+        self.len += 1;
+        self.sites[site].count += 1;
+        self.sites[site].bytes += size;
+    }
+
+    fn remove(&mut self, ptr: usize) {
+        if self.len == 0 {
+            return;
+        }
+        let mut slot = Self::slot_of(ptr);
+        loop {
+            match self.live[slot].ptr {
+                0 => return, // 追跡を始める前の割り当て
+                p if p == ptr => break,
+                _ => slot = (slot + 1) % TRACK_SLOTS,
+            }
+        }
+
+        let entry = self.live[slot];
+        self.sites[entry.site].count -= 1;
+        self.sites[entry.site].bytes -= entry.size;
+        self.len -= 1;
+
+        // 後ろに続くエントリを詰めて、探索の連続性を保つ
+        let mut hole = slot;
+        let mut next = (slot + 1) % TRACK_SLOTS;
+        while self.live[next].ptr != 0 {
+            let home = Self::slot_of(self.live[next].ptr);
+            // home が (hole, next] の外なら hole に移せる
+            let movable = if hole <= next {
+                home <= hole || home > next
+            } else {
+                home <= hole && home > next
+            };
+            if movable {
+                self.live[hole] = self.live[next];
+                hole = next;
+            }
+            next = (next + 1) % TRACK_SLOTS;
+        }
+        self.live[hole].ptr = 0;
+    }
+}
+
+struct SlabAllocator {
+    list_heads: [Option<&'static mut ListNode>; NUM_CLASSES],
+    fallback: Heap,
+    stats: AllocStats,
+}
+
+impl SlabAllocator {
+    const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        Self {
+            list_heads: [EMPTY; NUM_CLASSES],
+            fallback: Heap::empty(),
+            stats: AllocStats::new(),
+        }
+    }
+
+    /// 小さいブロックの割り当てが尽きた場合や大きい割り当てに使う
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // ヒープを拡張して再試行
+        if self.grow(layout.size() + layout.align()).is_ok() {
+            if let Ok(ptr) = self.fallback.allocate_first_fit(layout) {
+                return ptr.as_ptr();
+            }
+        }
+
+        self.stats.failed_allocs += 1;
+        ptr::null_mut()
+    }
+
+    fn grow(&mut self, min_size: usize) -> Result<(), &'static str> {
+        let size = core::cmp::max(min_size, HEAP_GROW_MIN);
+        let size = size.next_multiple_of(4096);
+
+        if self.fallback.size() + size > HEAP_MAX_SIZE {
+            return Err("Heap limit reached");
+        }
+
+        let top = self.fallback.top() as u64;
+        crate::memory::map_heap_pages(x86_64::VirtAddr::new(top), size / 4096)?;
+
+        unsafe {
+            self.fallback.extend(size);
+        }
+        self.stats.heap_grows += 1;
+        Ok(())
+    }
+}
+
+/// スラブ + linked_list_allocator によるカーネルアロケータ
+pub struct KernelAllocator {
+    inner: Mutex<SlabAllocator>,
+    /// true なら割り当てごとに呼び出し元を記録する (バックトレースをたどるので遅い)
+    tracking: AtomicBool,
+    // 割り込み中の割り当てとの取り合いを避けるため割り込みを止めて持つ
+    tracker: IrqMutex<AllocTracker>,
+}
+
+impl KernelAllocator {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(SlabAllocator::new()),
+            tracking: AtomicBool::new(false),
+            tracker: IrqMutex::new(AllocTracker::new()),
+        }
+    }
+
+    /// 呼び出し元の記録を切り替える (有効にする前の割り当ては記録されない)
+    pub fn set_tracking(&self, enabled: bool) {
+        self.tracking.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn report(&self) -> HeapReport {
+        // ロック中に割り当てるとアロケータに再入するので、先に確保しておく
+        let mut sites = Vec::with_capacity(MAX_SITES);
+        let untracked = {
+            let tracker = self.tracker.lock();
+            sites.extend(tracker.sites[..tracker.site_count].iter().filter(|site| site.count > 0).copied());
+            tracker.untracked
+        };
+        sites.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        HeapReport {
+            stats: self.stats(),
+            tracking: self.tracking.load(Ordering::SeqCst),
+            sites,
+            untracked,
+        }
+    }
+
+    fn track_alloc(&self, ptr: *mut u8, size: usize) {
+        if !self.tracking.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut stack = [0u64; SKIP_FRAMES + SITE_DEPTH];
+        let depth = crate::backtrace::collect(&mut stack);
+        let mut frames = [0u64; SITE_DEPTH];
+        if depth > SKIP_FRAMES {
+            frames[..depth - SKIP_FRAMES].copy_from_slice(&stack[SKIP_FRAMES..depth]);
+        }
+        self.tracker.lock().insert(ptr as usize, size, &frames);
+    }
+
+    /// # Safety
+    /// [heap_start, heap_start + heap_size) はマップ済みでほかに使われていないこと (1度だけ呼ぶ)
+    pub unsafe fn init(&self, heap_start: *mut u8, heap_size: usize) {
+        self.inner.lock().fallback.init(heap_start, heap_size);
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        let allocator = self.inner.lock();
+        let mut stats = allocator.stats;
+        stats.heap_size = allocator.fallback.size();
+        stats.heap_used = allocator.fallback.used();
+        stats
+    }
+}
+
+/// 赤ゾーンを含めた実際の割り当てと、前の赤ゾーンの大きさ
+fn redzone_layout(layout: Layout) -> (Layout, usize) {
+    if !POISONING {
+        return (layout, 0);
+    }
+    let front = REDZONE_SIZE.next_multiple_of(layout.align());
+    let size = front + layout.size() + REDZONE_SIZE;
+    // 大きさはもとの割り当てから決まるので、解放時にも同じ値になる
+    (Layout::from_size_align(size, layout.align()).unwrap(), front)
+}
+
+/// 赤ゾーンが書き換えられていないか確かめる (書き換えられていればパニック)
+unsafe fn check_redzones(block: *mut u8, front: usize, layout: Layout) {
+    let before = core::slice::from_raw_parts(block, front);
+    let after = core::slice::from_raw_parts(block.add(front + layout.size()), REDZONE_SIZE);
+    if let Some(offset) = before.iter().rposition(|&b| b != REDZONE_BYTE) {
+        panic!("heap: {} byte(s) before {:p} (size {}) overwritten",
+            front - offset, block.add(front), layout.size());
+    }
+    if let Some(offset) = after.iter().position(|&b| b != REDZONE_BYTE) {
+        panic!("heap: byte {} past the end of {:p} (size {}) overwritten",
+            offset, block.add(front), layout.size());
+    }
+}
+
+/// 解放後に書き換えられていないか確かめる (フリーリストから再利用するブロック)
+unsafe fn check_poison(block: *mut u8, size: usize) {
+    let body = core::slice::from_raw_parts(block.add(FREE_HEADER_SIZE), size - FREE_HEADER_SIZE);
+    if let Some(offset) = body.iter().position(|&b| b != POISON_FREE) {
+        panic!("heap: use after free: {:p} modified at offset {} after it was freed",
+            block, FREE_HEADER_SIZE + offset);
+    }
+}
+
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required = core::cmp::max(layout.size(), layout.align());
+    BLOCK_SIZES.iter().position(|&size| size >= required)
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if crate::fault::should_fail(crate::fault::FaultPoint::Heap) {
+            return ptr::null_mut();
+        }
+        let (block_layout, front) = redzone_layout(layout);
+        let block = self.alloc_block(block_layout, layout.size());
+        if block.is_null() {
+            return block;
+        }
+        let ptr = block.add(front);
+        if POISONING {
+            ptr::write_bytes(block, REDZONE_BYTE, front);
+            ptr::write_bytes(ptr, POISON_INUSE, layout.size());
+            ptr::write_bytes(ptr.add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+        }
+        self.track_alloc(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.tracker.lock().remove(ptr as usize);
+        let (block_layout, front) = redzone_layout(layout);
+        let block = ptr.sub(front);
+        if POISONING {
+            check_redzones(block, front, layout);
+            // スラブのブロックは余りの部分も含めて埋める (再利用時にブロック全体を確かめる)
+            let size = list_index(&block_layout).map_or(block_layout.size(), |index| BLOCK_SIZES[index]);
+            ptr::write_bytes(block, POISON_FREE, size);
+        }
+        self.dealloc_block(block, block_layout, layout.size());
+    }
+}
+
+impl KernelAllocator {
+    /// requested は呼び出し側が頼んだ大きさ (赤ゾーンを含まない、統計用)
+    unsafe fn alloc_block(&self, layout: Layout, requested: usize) -> *mut u8 {
+        let mut allocator = self.inner.lock();
+        // フリーリストから再利用したブロックの大きさ
+        let mut reused = None;
+        let index = list_index(&layout);
+        let ptr = match index {
+            Some(index) => match allocator.list_heads[index].take() {
+                Some(node) => {
+                    allocator.list_heads[index] = node.next.take();
+                    reused = Some(BLOCK_SIZES[index]);
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    // フリーリストが空なので新しいブロックを確保
+                    let block_size = BLOCK_SIZES[index];
+                    let layout = Layout::from_size_align_unchecked(block_size, block_size);
+                    allocator.fallback_alloc(layout)
+                }
+            },
+            None => allocator.fallback_alloc(layout),
+        };
+        // 失敗した割り当ては failed_allocs だけに数える
+        if !ptr.is_null() {
+            let stats = &mut allocator.stats;
+            match index {
+                Some(index) => stats.slab_allocs[index] += 1,
+                None => stats.fallback_allocs += 1,
+            }
+            stats.live_allocs += 1;
+            stats.live_bytes += requested;
+            stats.peak_bytes = stats.peak_bytes.max(stats.live_bytes);
+        }
+        // パニックの表示で割り当てられるよう、ロックを外してから確かめる
+        drop(allocator);
+        if let (true, Some(size)) = (POISONING, reused) {
+            check_poison(ptr, size);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc_block(&self, ptr: *mut u8, layout: Layout, requested: usize) {
+        let mut allocator = self.inner.lock();
+        allocator.stats.live_allocs -= 1;
+        allocator.stats.live_bytes -= requested;
+        match list_index(&layout) {
+            Some(index) => {
+                // ブロックはヒープに返さずフリーリストに積む
+                allocator.stats.slab_frees[index] += 1;
+                let new_node = ListNode {
+                    next: allocator.list_heads[index].take(),
+                };
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => {
+                allocator.stats.fallback_frees += 1;
+                allocator.fallback.deallocate(NonNull::new_unchecked(ptr), layout);
+            }
+        }
+    }
+}
+
+pub fn print_stats() {
+    let stats = crate::memory::heap_stats();
+    crate::println!("Heap Statistics:");
+    crate::println!("  Heap size: {} KiB (grown {} times)", stats.heap_size / 1024, stats.heap_grows);
+    crate::println!("  Heap used: {} KiB", stats.heap_used / 1024);
+    for (i, size) in BLOCK_SIZES.iter().enumerate() {
+        crate::println!("  {:>4} B: alloc {} / free {}", size, stats.slab_allocs[i], stats.slab_frees[i]);
+    }
+    crate::println!("  large:  alloc {} / free {}", stats.fallback_allocs, stats.fallback_frees);
+    crate::println!("  failed: {}", stats.failed_allocs);
+}
+
+/// 解放されていない割り当てを呼び出し元ごとに表示する (リーク調査用)
+pub fn print_heap_report() {
+    let report = crate::memory::heap_report();
+    crate::println!("Live allocations: {} ({} bytes, peak {} bytes)",
+        report.stats.live_allocs, report.stats.live_bytes, report.stats.peak_bytes);
+    if !report.tracking {
+        crate::println!("  call site tracking is off (boot with heap_track)");
+    }
+    for site in report.sites.iter().take(10) {
+        crate::println!("  {:>8} bytes in {:>5} allocs at {:#x?}", site.bytes, site.count, site.frames);
+    }
+    if report.untracked > 0 {
+        crate::println!("  {} allocations not tracked (table full)", report.untracked);
+    }
+}
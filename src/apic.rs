@@ -0,0 +1,290 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::cpu::{self, Feature};
+
+const IA32_APIC_BASE_MSR: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ADDR_MASK: u64 = 0xF_FFFF_F000;
+
+// x2APIC では MMIO オフセット >> 4 を 0x800 に足した MSR でアクセスする
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+// Local APIC レジスタ (MMIO オフセット)
+const LAPIC_ID: u32 = 0x020;
+const LAPIC_TPR: u32 = 0x080;
+const LAPIC_EOI: u32 = 0x0B0;
+const LAPIC_SVR: u32 = 0x0F0;
+const LAPIC_ICR_LOW: u32 = 0x300;
+const LAPIC_ICR_HIGH: u32 = 0x310;
+const LAPIC_LVT_TIMER: u32 = 0x320;
+const LAPIC_LVT_LINT0: u32 = 0x350;
+const LAPIC_LVT_LINT1: u32 = 0x360;
+const LAPIC_LVT_ERROR: u32 = 0x370;
+
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+const SVR_ENABLE: u32 = 1 << 8;
+
+// ICR (プロセッサ間割り込み)
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_SEND_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+/// スプリアス割り込みのベクタ (EOI 不要)
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// MADT が無い場合の IOAPIC の標準アドレス
+pub const IOAPIC_DEFAULT_BASE: u64 = 0xFEC0_0000;
+
+const IOAPIC_REGSEL: u64 = 0x00;
+const IOAPIC_WINDOW: u64 = 0x10;
+const IOAPIC_REG_VERSION: u32 = 0x01;
+const IOAPIC_REG_REDTBL: u32 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+    /// 8259 PIC (APIC が使えない場合のフォールバック)
+    Pic = 0,
+    XApic = 1,
+    X2Apic = 2,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Pic as u8);
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+static IOAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+pub fn mode() -> Mode {
+    match MODE.load(Ordering::SeqCst) {
+        1 => Mode::XApic,
+        2 => Mode::X2Apic,
+        _ => Mode::Pic,
+    }
+}
+
+pub fn is_enabled() -> bool {
+    mode() != Mode::Pic
+}
+
+// Local APIC レジスタアクセス
+
+fn lapic_read(reg: u32) -> u32 {
+    match mode() {
+        Mode::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (reg >> 4)).read() as u32 },
+        _ => {
+            let addr = LAPIC_BASE.load(Ordering::SeqCst) + reg as u64;
+            unsafe { core::ptr::read_volatile(addr as *const u32) }
+        }
+    }
+}
+
+fn lapic_write(reg: u32, value: u32) {
+    match mode() {
+        Mode::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (reg >> 4)).write(value as u64) },
+        _ => {
+            let addr = LAPIC_BASE.load(Ordering::SeqCst) + reg as u64;
+            unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+        }
+    }
+}
+
+/// 現在のCPUの Local APIC ID
+pub fn lapic_id() -> u32 {
+    match mode() {
+        Mode::X2Apic => lapic_read(LAPIC_ID),
+        Mode::XApic => lapic_read(LAPIC_ID) >> 24,
+        Mode::Pic => 0,
+    }
+}
+
+// IOAPIC レジスタアクセス
+
+fn ioapic_read(reg: u32) -> u32 {
+    let base = IOAPIC_BASE.load(Ordering::SeqCst);
+    unsafe {
+        core::ptr::write_volatile((base + IOAPIC_REGSEL) as *mut u32, reg);
+        core::ptr::read_volatile((base + IOAPIC_WINDOW) as *const u32)
+    }
+}
+
+fn ioapic_write(reg: u32, value: u32) {
+    let base = IOAPIC_BASE.load(Ordering::SeqCst);
+    unsafe {
+        core::ptr::write_volatile((base + IOAPIC_REGSEL) as *mut u32, reg);
+        core::ptr::write_volatile((base + IOAPIC_WINDOW) as *mut u32, value);
+    }
+}
+
+fn ioapic_max_redirection() -> u32 {
+    (ioapic_read(IOAPIC_REG_VERSION) >> 16) & 0xFF
+}
+
+/// GSI を指定したベクタに割り当てる (エッジトリガ、アクティブハイ、固定配送)
+pub fn set_irq(gsi: u32, vector: u8, dest_apic_id: u32) {
+    let reg = IOAPIC_REG_REDTBL + gsi * 2;
+    ioapic_write(reg + 1, dest_apic_id << 24);
+    ioapic_write(reg, vector as u32);
+}
+
+pub fn mask_irq(gsi: u32) {
+    let reg = IOAPIC_REG_REDTBL + gsi * 2;
+    ioapic_write(reg, ioapic_read(reg) | LVT_MASKED);
+}
+
+/// ISA IRQ 番号を GSI に変換する
+/// MADT の割り込みソースオーバーライドを優先し、無ければ
+/// ほぼすべてのチップセットと同じく PIT (IRQ0) を GSI2 とみなす
+pub fn isa_irq_to_gsi(irq: u8) -> u32 {
+    if let Some(madt) = crate::acpi::madt() {
+        if let Some(entry) = madt.overrides.iter().find(|o| o.source == irq) {
+            return entry.gsi;
+        }
+        return irq as u32;
+    }
+    match irq {
+        0 => 2,
+        irq => irq as u32,
+    }
+}
+
+fn disable_pic() {
+    use x86_64::instructions::port::Port;
+    unsafe {
+        // 再マップ済みの PIC をすべてマスクする
+        Port::<u8>::new(0x21).write(0xFF);
+        Port::<u8>::new(0xA1).write(0xFF);
+    }
+}
+
+fn init_local_apic(x2apic: bool) -> Result<(), &'static str> {
+    let mut base_msr = Msr::new(IA32_APIC_BASE_MSR);
+    let base = unsafe { base_msr.read() };
+    let phys_base = match crate::acpi::madt() {
+        Some(madt) => madt.local_apic_address,
+        None => base & APIC_BASE_ADDR_MASK,
+    };
+
+    if x2apic {
+        unsafe { base_msr.write(base | APIC_BASE_ENABLE | APIC_BASE_X2APIC) };
+        MODE.store(Mode::X2Apic as u8, Ordering::SeqCst);
+    } else {
+        let virt = crate::memory::map_mmio(PhysAddr::new(phys_base), 4096)?;
+        LAPIC_BASE.store(virt.as_u64(), Ordering::SeqCst);
+        unsafe { base_msr.write(base | APIC_BASE_ENABLE) };
+        MODE.store(Mode::XApic as u8, Ordering::SeqCst);
+    }
+
+    // LVT はすべてマスクし、外部割り込みは IOAPIC 経由で受ける
+    setup_local_vectors();
+    Ok(())
+}
+
+fn setup_local_vectors() {
+    lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+    lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+    lapic_write(LAPIC_LVT_LINT1, LVT_MASKED);
+    lapic_write(LAPIC_LVT_ERROR, LVT_MASKED);
+    lapic_write(LAPIC_TPR, 0);
+    lapic_write(LAPIC_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// Local APIC タイマーを TSC-deadline モードにする
+/// 割り込みは IA32_TSC_DEADLINE MSR に書いた時刻に一度だけ発生する
+pub fn enable_tsc_deadline_timer(vector: u8) {
+    lapic_write(LAPIC_LVT_TIMER, LVT_TIMER_TSC_DEADLINE | vector as u32);
+}
+
+/// アプリケーションプロセッサ上で Local APIC を有効にする
+/// BSP と同じモードを使い、xAPIC の MMIO は全CPUで共通のアドレスになる
+pub fn init_ap() {
+    let mut base_msr = Msr::new(IA32_APIC_BASE_MSR);
+    let base = unsafe { base_msr.read() };
+    let enable = match mode() {
+        Mode::X2Apic => APIC_BASE_ENABLE | APIC_BASE_X2APIC,
+        Mode::XApic => APIC_BASE_ENABLE,
+        Mode::Pic => return,
+    };
+    unsafe { base_msr.write(base | enable) };
+    setup_local_vectors();
+}
+
+fn init_ioapic(virt: VirtAddr) {
+    use crate::interrupts::InterruptIndex;
+
+    IOAPIC_BASE.store(virt.as_u64(), Ordering::SeqCst);
+
+    for gsi in 0..=ioapic_max_redirection() {
+        mask_irq(gsi);
+    }
+
+    let apic_id = lapic_id();
+    set_irq(isa_irq_to_gsi(0), InterruptIndex::Timer as u8, apic_id);
+    set_irq(isa_irq_to_gsi(1), InterruptIndex::Keyboard as u8, apic_id);
+}
+
+/// Local APIC と IOAPIC を初期化し、PIC から切り替える
+/// APIC が使えない場合はエラーを返し、PIC をそのまま使い続ける
+pub fn init() -> Result<Mode, &'static str> {
+    if INITIALIZED.swap(true, Ordering::SeqCst) {
+        return Ok(mode());
+    }
+
+    let has_x2apic = cpu::has(Feature::X2Apic);
+    if !cpu::has(Feature::Apic) {
+        return Err("APIC not supported");
+    }
+
+    // Local APIC を有効にすると PIC の割り込みが届かなくなるので、
+    // 先に IOAPIC をマップできるか確かめておく
+    let ioapic_base = crate::acpi::madt()
+        .and_then(|madt| madt.io_apics.iter().find(|io| io.gsi_base == 0).map(|io| io.address as u64))
+        .unwrap_or(IOAPIC_DEFAULT_BASE);
+    let ioapic = crate::memory::map_mmio(PhysAddr::new(ioapic_base), 4096)?;
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        init_local_apic(has_x2apic)?;
+        init_ioapic(ioapic);
+        disable_pic();
+        Ok(mode())
+    })
+}
+
+/// Local APIC に割り込み処理完了を通知する
+pub fn end_of_interrupt() {
+    lapic_write(LAPIC_EOI, 0);
+}
+
+fn send_ipi_raw(dest_apic_id: u32, command: u32) {
+    match mode() {
+        Mode::X2Apic => unsafe {
+            Msr::new(X2APIC_MSR_BASE + (LAPIC_ICR_LOW >> 4))
+                .write(((dest_apic_id as u64) << 32) | command as u64);
+        },
+        Mode::XApic => {
+            lapic_write(LAPIC_ICR_HIGH, dest_apic_id << 24);
+            lapic_write(LAPIC_ICR_LOW, command);
+            while lapic_read(LAPIC_ICR_LOW) & ICR_SEND_PENDING != 0 {
+                core::hint::spin_loop();
+            }
+        }
+        Mode::Pic => {}
+    }
+}
+
+/// 指定した CPU に固定ベクタのプロセッサ間割り込みを送る
+pub fn send_ipi(dest_apic_id: u32, vector: u8) {
+    send_ipi_raw(dest_apic_id, ICR_LEVEL_ASSERT | vector as u32);
+}
+
+pub fn send_init(dest_apic_id: u32) {
+    send_ipi_raw(dest_apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT);
+}
+
+/// Startup IPI を送る。AP は page * 0x1000 番地からリアルモードで実行を開始する
+pub fn send_startup(dest_apic_id: u32, page: u8) {
+    send_ipi_raw(dest_apic_id, ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | page as u32);
+}
@@ -0,0 +1,133 @@
+//! フレームポインタをたどるスタックバックトレース
+//! `-C force-frame-pointers=yes` でビルドされていることが前提
+
+pub const MAX_FRAMES: usize = 32;
+
+#[repr(C)]
+struct StackFrame {
+    rbp: *const StackFrame,
+    return_address: u64,
+}
+
+/// 現在の rbp から呼び出し元の戻りアドレスを順に buf に書き出す
+pub fn collect(buf: &mut [u64]) -> usize {
+    walk(buf, is_valid_frame)
+}
+
+/// collect と同じだが、フレームがマップされているかも確かめてから読む
+/// 割り込みハンドラから割り込まれたコードのフレームまでたどるときに使う
+/// (フレームポインタを使わないコードの rbp はでたらめなことがある)
+pub fn collect_checked(buf: &mut [u64]) -> usize {
+    walk(buf, |rbp| is_valid_frame(rbp) && is_mapped(rbp))
+}
+
+fn walk(buf: &mut [u64], valid: impl Fn(*const StackFrame) -> bool) -> usize {
+    let mut rbp: *const StackFrame;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+    }
+
+    let mut count = 0;
+    while count < buf.len() && valid(rbp) {
+        let frame = unsafe { &*rbp };
+        if frame.return_address == 0 {
+            break;
+        }
+        buf[count] = frame.return_address;
+        count += 1;
+
+        // スタックは下位アドレス方向に伸びるので、呼び出し元のフレームは必ず上位にある
+        if (frame.rbp as u64) <= (rbp as u64) {
+            break;
+        }
+        rbp = frame.rbp;
+    }
+    count
+}
+
+fn is_valid_frame(rbp: *const StackFrame) -> bool {
+    let addr = rbp as u64;
+    addr != 0 && addr % 8 == 0 && x86_64::VirtAddr::try_new(addr).is_ok()
+}
+
+/// フレームの 16 バイトがすべてマップされているか
+fn is_mapped(rbp: *const StackFrame) -> bool {
+    let start = rbp as u64;
+    let end = start + core::mem::size_of::<StackFrame>() as u64 - 1;
+    [start, end].iter().all(|&addr| {
+        x86_64::VirtAddr::try_new(addr).ok().and_then(crate::memory::try_virt_to_phys).is_some()
+    })
+}
+
+pub fn print_backtrace() {
+    let mut frames = [0u64; MAX_FRAMES];
+    let count = collect(&mut frames);
+
+    crate::println!("Backtrace:");
+    for (i, addr) in frames[..count].iter().enumerate() {
+        crate::println!("  #{:<2} {}", i, crate::symbols::Sym(*addr));
+    }
+    if count == 0 {
+        crate::println!("  <no frames>");
+    }
+}
+
+/// パニック時に記録するレジスタ
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rflags: u64,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+}
+
+impl Registers {
+    /// 呼び出した場所のレジスタを読む
+    #[inline(always)]
+    pub fn capture() -> Self {
+        use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+        use x86_64::registers::rflags;
+
+        let (rsp, rbp, rip): (u64, u64, u64);
+        unsafe {
+            core::arch::asm!(
+                "mov {0}, rsp",
+                "mov {1}, rbp",
+                "lea {2}, [rip]",
+                out(reg) rsp,
+                out(reg) rbp,
+                out(reg) rip,
+                options(nomem, nostack),
+            );
+        }
+        Self {
+            rip,
+            rsp,
+            rbp,
+            rflags: rflags::read_raw(),
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read_raw(),
+            cr3: Cr3::read().0.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+        }
+    }
+
+    /// 名前と値の組 (クラッシュダンプに書く順)
+    pub fn named(&self) -> [(&'static str, u64); 8] {
+        [
+            ("rip", self.rip), ("rsp", self.rsp), ("rbp", self.rbp), ("rflags", self.rflags),
+            ("cr0", self.cr0), ("cr2", self.cr2), ("cr3", self.cr3), ("cr4", self.cr4),
+        ]
+    }
+}
+
+/// パニック時のレジスタダンプ
+pub fn print_registers() {
+    let registers = Registers::capture();
+    crate::println!("RIP: {:#018x}  RSP: {:#018x}  RBP: {:#018x}", registers.rip, registers.rsp, registers.rbp);
+    crate::println!("RFLAGS: {:#x}  CR2: {:#x}  CR3: {:#x}", registers.rflags, registers.cr2, registers.cr3);
+}
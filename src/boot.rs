@@ -1,9 +1,14 @@
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::arch::global_asm;
+use core::sync::atomic::{AtomicU32, Ordering};
+use x86_64::PhysAddr;
 
 global_asm!(r#"
     .set ALIGN,    1<<0
     .set MEMINFO,  1<<1
-    .set FLAGS,    ALIGN | MEMINFO
+    .set VIDEO,    1<<2
+    .set FLAGS,    ALIGN | MEMINFO | VIDEO
     .set MAGIC,    0x1BADB002
     .set CHECKSUM, -(MAGIC + FLAGS)
 
@@ -12,4 +17,194 @@ global_asm!(r#"
     .long MAGIC
     .long FLAGS
     .long CHECKSUM
+    /* アドレス指定 (flags の bit 16 を立てないので未使用) */
+    .long 0, 0, 0, 0, 0
+    /* 希望するビデオモード: リニアフレームバッファ 1024x768x32 */
+    /* grub.cfg の gfxpayload=text でテキストモードのままにできる */
+    .long 0
+    .long 1024
+    .long 768
+    .long 32
 "#);
+
+/// ブートローダーが eax に入れて渡すマジック値
+const BOOTLOADER_MAGIC: u32 = 0x2BADB002;
+/// flags: cmdline が有効
+const INFO_CMDLINE: u32 = 1 << 2;
+/// flags: mods_count / mods_addr が有効
+const INFO_MODS: u32 = 1 << 3;
+
+static INFO_ADDR: AtomicU32 = AtomicU32::new(0);
+
+/// GRUB が一緒に読み込んだモジュール (initramfs など)
+pub struct Module {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+    pub cmdline: String,
+}
+
+impl Module {
+    /// モジュールの内容 (物理メモリのオフセットマッピング越しに参照する)
+    pub fn data(&self) -> &'static [u8] {
+        let virt = crate::memory::phys_to_virt(self.start);
+        let len = (self.end.as_u64() - self.start.as_u64()) as usize;
+        unsafe { core::slice::from_raw_parts(virt.as_ptr(), len) }
+    }
+}
+
+/// エントリで受け取った Multiboot 情報構造体の物理アドレスを覚えておく
+pub fn init(magic: u32, info: u32) {
+    if magic == BOOTLOADER_MAGIC {
+        INFO_ADDR.store(info, Ordering::SeqCst);
+    }
+}
+
+fn read_u32(phys: u64) -> u32 {
+    let virt = crate::memory::phys_to_virt(PhysAddr::new(phys));
+    unsafe { core::ptr::read_unaligned(virt.as_ptr::<u32>()) }
+}
+
+fn read_cstr(phys: u64) -> String {
+    let mut bytes = Vec::new();
+    let virt = crate::memory::phys_to_virt(PhysAddr::new(phys)).as_ptr::<u8>();
+    while bytes.len() < 256 {
+        let byte = unsafe { *virt.add(bytes.len()) };
+        if byte == 0 {
+            break;
+        }
+        bytes.push(byte);
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// カーネルのコマンドライン (GRUB の multiboot 行のカーネルパス以降)
+/// メモリ管理の初期化後に呼ぶこと
+pub fn cmdline() -> String {
+    let info = INFO_ADDR.load(Ordering::SeqCst) as u64;
+    if info == 0 || read_u32(info) & INFO_CMDLINE == 0 {
+        return String::new();
+    }
+    match read_u32(info + 16) {
+        0 => String::new(),
+        addr => read_cstr(addr as u64),
+    }
+}
+
+/// モジュールの一覧 (メモリ管理の初期化後に呼ぶこと)
+pub fn modules() -> Vec<Module> {
+    let info = INFO_ADDR.load(Ordering::SeqCst) as u64;
+    if info == 0 || read_u32(info) & INFO_MODS == 0 {
+        return Vec::new();
+    }
+
+    let count = read_u32(info + 20) as u64;
+    let base = read_u32(info + 24) as u64;
+    (0..count)
+        .map(|i| {
+            // mod_start, mod_end, string, reserved
+            let entry = base + i * 16;
+            let cmdline = match read_u32(entry + 8) {
+                0 => String::new(),
+                addr => read_cstr(addr as u64),
+            };
+            Module {
+                start: PhysAddr::new(read_u32(entry) as u64),
+                end: PhysAddr::new(read_u32(entry + 4) as u64),
+                cmdline,
+            }
+        })
+        .collect()
+}
+
+/// flags: mem_lower / mem_upper が有効
+const INFO_MEMORY: u32 = 1 << 0;
+/// flags: mmap_length / mmap_addr が有効
+const INFO_MMAP: u32 = 1 << 6;
+/// メモリマップの type: 使用可能な RAM
+const MMAP_AVAILABLE: u32 = 1;
+
+/// ブートローダーが報告した物理メモリ領域
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub len: u64,
+    pub usable: bool,
+}
+
+/// 物理メモリマップ (メモリ管理の初期化後に呼ぶこと)
+/// メモリマップがなければ mem_lower / mem_upper から作る
+pub fn memory_regions() -> Vec<MemoryRegion> {
+    let info = INFO_ADDR.load(Ordering::SeqCst) as u64;
+    if info == 0 {
+        return Vec::new();
+    }
+    let flags = read_u32(info);
+
+    let mut regions = Vec::new();
+    if flags & INFO_MMAP != 0 {
+        let length = read_u32(info + 44) as u64;
+        let base = read_u32(info + 48) as u64;
+        let mut entry = base;
+        // 各エントリの先頭の size には size 自身が含まれない
+        while entry < base + length {
+            let size = read_u32(entry) as u64;
+            let start = read_u32(entry + 4) as u64 | (read_u32(entry + 8) as u64) << 32;
+            let len = read_u32(entry + 12) as u64 | (read_u32(entry + 16) as u64) << 32;
+            regions.push(MemoryRegion { start, len, usable: read_u32(entry + 20) == MMAP_AVAILABLE });
+            entry += size + 4;
+        }
+    } else if flags & INFO_MEMORY != 0 {
+        // 単位は KiB、上位メモリは 1MiB から始まる
+        let lower = read_u32(info + 4) as u64 * 1024;
+        let upper = read_u32(info + 8) as u64 * 1024;
+        regions.push(MemoryRegion { start: 0, len: lower, usable: true });
+        regions.push(MemoryRegion { start: 0x10_0000, len: upper, usable: true });
+    }
+    regions
+}
+
+/// flags: framebuffer_* が有効
+const INFO_FRAMEBUFFER: u32 = 1 << 12;
+/// framebuffer_type: 直接色指定 (RGB)
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// ブートローダーが設定したリニアフレームバッファ
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    pub addr: PhysAddr,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    /// 各色のビット位置と幅
+    pub red: (u8, u8),
+    pub green: (u8, u8),
+    pub blue: (u8, u8),
+}
+
+fn read_u8(phys: u64) -> u8 {
+    let virt = crate::memory::phys_to_virt(PhysAddr::new(phys));
+    unsafe { *virt.as_ptr::<u8>() }
+}
+
+/// RGB のフレームバッファがあれば返す (テキストモードで起動した場合は None)
+pub fn framebuffer() -> Option<FramebufferInfo> {
+    let info = INFO_ADDR.load(Ordering::SeqCst) as u64;
+    if info == 0 || read_u32(info) & INFO_FRAMEBUFFER == 0 {
+        return None;
+    }
+    if read_u8(info + 109) != FRAMEBUFFER_TYPE_RGB {
+        return None;
+    }
+
+    Some(FramebufferInfo {
+        addr: PhysAddr::new(read_u32(info + 88) as u64 | (read_u32(info + 92) as u64) << 32),
+        pitch: read_u32(info + 96),
+        width: read_u32(info + 100),
+        height: read_u32(info + 104),
+        bpp: read_u8(info + 108),
+        red: (read_u8(info + 110), read_u8(info + 111)),
+        green: (read_u8(info + 112), read_u8(info + 113)),
+        blue: (read_u8(info + 114), read_u8(info + 115)),
+    })
+}
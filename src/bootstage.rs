@@ -0,0 +1,173 @@
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+/// 記録できる段階の数
+const MAX_STAGES: usize = 48;
+/// 段階ごとに残す結果の説明の長さ (ヒープの初期化前も記録するので固定長)
+const DETAIL_LEN: usize = 56;
+/// これより長くかかった段階を一覧で目立たせる
+const SLOW_STAGE_US: u64 = 50_000;
+
+/// 初期化の段階の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// 使えないが起動は続けられる ([--])
+    Skipped,
+    Failed,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Skipped => "skipped",
+            Status::Failed => "FAILED",
+        }
+    }
+}
+
+/// 終わった段階の記録
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    pub name: &'static str,
+    pub status: Status,
+    /// TSC のサイクル数
+    pub cycles: u64,
+    detail: [u8; DETAIL_LEN],
+    detail_len: usize,
+}
+
+impl Record {
+    const EMPTY: Record = Record { name: "", status: Status::Ok, cycles: 0, detail: [0; DETAIL_LEN], detail_len: 0 };
+
+    /// 結果の説明 (長いものは切り詰めてある)
+    pub fn detail(&self) -> &str {
+        core::str::from_utf8(&self.detail[..self.detail_len]).unwrap_or("")
+    }
+}
+
+struct Log {
+    records: [Record; MAX_STAGES],
+    len: usize,
+}
+
+static LOG: Mutex<Log> = Mutex::new(Log { records: [Record::EMPTY; MAX_STAGES], len: 0 });
+
+/// 説明を固定長のバッファに書く (入りきらない分は捨てる)
+struct DetailWriter<'a> {
+    record: &'a mut Record,
+}
+
+impl Write for DetailWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = self.record.detail_len;
+            if len + c.len_utf8() > DETAIL_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.record.detail[len..]);
+            self.record.detail_len += c.len_utf8();
+        }
+        Ok(())
+    }
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// 実行中の段階 (ok / skip / fail / done のどれかで終える)
+#[must_use]
+pub struct Stage {
+    name: &'static str,
+    start: u64,
+}
+
+/// 段階を始める
+pub fn begin(name: &'static str) -> Stage {
+    Stage { name, start: rdtsc() }
+}
+
+impl Stage {
+    fn finish(self, status: Status, detail: Option<&dyn fmt::Display>) {
+        let mut record = Record::EMPTY;
+        record.name = self.name;
+        record.status = status;
+        record.cycles = rdtsc().wrapping_sub(self.start);
+        if let Some(detail) = detail {
+            let _ = write!(DetailWriter { record: &mut record }, "{}", detail);
+        }
+        let mut log = LOG.lock();
+        if log.len < MAX_STAGES {
+            let index = log.len;
+            log.records[index] = record;
+            log.len += 1;
+        }
+    }
+
+    /// 成功 ([OK] と表示する)
+    pub fn ok(self, message: impl fmt::Display) {
+        crate::println!("[OK] {}", message);
+        self.finish(Status::Ok, Some(&message));
+    }
+
+    /// 使えないが続ける ([--] と表示する)
+    pub fn skip(self, message: impl fmt::Display) {
+        crate::println!("[--] {}", message);
+        self.finish(Status::Skipped, Some(&message));
+    }
+
+    /// 失敗 (起動は続けるが、一覧でも目立たせる)
+    pub fn fail(self, message: impl fmt::Display) {
+        crate::println!("[NG] {}", message);
+        self.finish(Status::Failed, Some(&message));
+    }
+
+    /// 何も表示せずに時間だけ記録する
+    pub fn done(self) {
+        self.finish(Status::Ok, None);
+    }
+}
+
+/// 記録した段階 (順番どおり)
+pub fn records() -> alloc::vec::Vec<Record> {
+    let log = LOG.lock();
+    log.records[..log.len].to_vec()
+}
+
+/// サイクル数をマイクロ秒にする (TSC の周波数が分からなければ None)
+pub fn cycles_to_us(cycles: u64) -> Option<u64> {
+    match crate::drivers::timer::tsc_khz() {
+        0 => None,
+        khz => Some(cycles * 1000 / khz),
+    }
+}
+
+fn print_duration(cycles: u64) -> Option<u64> {
+    match cycles_to_us(cycles) {
+        Some(us) => {
+            crate::print!("{:>6}.{:03} ms", us / 1000, us % 1000);
+            Some(us)
+        }
+        None => {
+            crate::print!("{:>10} cyc", cycles);
+            None
+        }
+    }
+}
+
+/// 各段階の結果とかかった時間の一覧を表示する (起動の最後に呼ぶ)
+pub fn print_summary() {
+    let records = records();
+    let total: u64 = records.iter().map(|record| record.cycles).sum();
+    crate::println!("Boot stages:");
+    for record in &records {
+        crate::print!("  {:<12} {:<8}", record.name, record.status.name());
+        let slow = print_duration(record.cycles).is_some_and(|us| us >= SLOW_STAGE_US);
+        crate::println!("{}  {}", if slow { " (slow)" } else { "" }, record.detail());
+    }
+    crate::print!("  {:<12} {:<8}", "total", "");
+    print_duration(total);
+    crate::println!();
+}
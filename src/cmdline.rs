@@ -0,0 +1,66 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::log::Level;
+
+/// 起動時に解析したコマンドライン (キー, 値)
+static OPTIONS: Mutex<Option<Vec<(String, Option<String>)>>> = Mutex::new(None);
+
+/// "key=value" や "flag" を空白で区切った並びを解析する
+/// 値のない項目は None、同じキーが複数あれば後のものが優先される (get を参照)
+pub fn parse(line: &str) -> Vec<(String, Option<String>)> {
+    line.split_whitespace()
+        .map(|arg| match arg.split_once('=') {
+            Some((key, value)) => (String::from(key), Some(String::from(value))),
+            None => (String::from(arg), None),
+        })
+        .collect()
+}
+
+/// Multiboot のコマンドラインを解析し、ログレベルなどを反映する
+/// (メモリ管理とヒープの初期化後に呼ぶこと)
+pub fn init() {
+    let line = crate::boot::cmdline();
+    *OPTIONS.lock() = Some(parse(&line));
+
+    if let Some(name) = get("loglevel") {
+        match name.parse::<Level>() {
+            Ok(level) => crate::log::set_console_level(level),
+            Err(()) => crate::warn!("Unknown loglevel: {}", name),
+        }
+    }
+    if has("heap_track") {
+        crate::memory::set_heap_tracking(true);
+    }
+    if !line.is_empty() {
+        crate::info!("Command line: {}", line);
+    }
+}
+
+/// key の値 (key=value の value)
+/// 値のない項目や見つからない場合は None
+pub fn get(key: &str) -> Option<String> {
+    let options = OPTIONS.lock();
+    options.as_ref()?
+        .iter()
+        .rev()
+        .find(|(name, _)| name == key)
+        .and_then(|(_, value)| value.clone())
+}
+
+/// "64k" や "8M" のような K/M/G 接尾辞付きのサイズをバイト数にする
+pub fn parse_size(value: &str) -> Option<usize> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 10),
+        b'm' | b'M' => (&value[..value.len() - 1], 20),
+        b'g' | b'G' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+/// key が (値の有無にかかわらず) 指定されているか
+pub fn has(key: &str) -> bool {
+    let options = OPTIONS.lock();
+    options.as_ref().map_or(false, |options| options.iter().any(|(name, _)| name == key))
+}
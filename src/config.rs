@@ -0,0 +1,81 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// 起動時に切り替えられるカーネルの機能
+/// 既定値は cargo の feature で決まり、コマンドラインの <名前>=on / <名前>=off で上書きできる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// アプリケーションプロセッサを起動する
+    Smp,
+    /// ACPI テーブルから CPU や割り込みの構成を読む
+    Acpi,
+    /// ネットワークスタックを初期化する
+    Net,
+    /// コンソールをシリアルポートに出す (console=serial と同じ)
+    SerialConsole,
+}
+
+pub const FEATURES: [Feature; 4] = [Feature::Smp, Feature::Acpi, Feature::Net, Feature::SerialConsole];
+
+impl Feature {
+    /// コマンドラインで使う名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Smp => "smp",
+            Feature::Acpi => "acpi",
+            Feature::Net => "net",
+            Feature::SerialConsole => "serial_console",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        FEATURES.iter().copied().find(|feature| feature.name() == name)
+    }
+
+    /// ビルド時の既定値 (cargo の feature)
+    pub fn compiled_default(self) -> bool {
+        match self {
+            Feature::Smp => cfg!(feature = "smp"),
+            Feature::Acpi => cfg!(feature = "acpi"),
+            Feature::Net => cfg!(feature = "net"),
+            Feature::SerialConsole => cfg!(feature = "serial-console"),
+        }
+    }
+}
+
+/// "on" / "off" などをスイッチの値にする
+pub fn parse_switch(value: &str) -> Option<bool> {
+    match value {
+        "on" | "yes" | "1" | "true" => Some(true),
+        "off" | "no" | "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// feature が有効か (コマンドラインの指定が優先、読めない値なら既定値)
+/// コマンドラインの解析後に呼ぶこと
+pub fn enabled(feature: Feature) -> bool {
+    crate::cmdline::get(feature.name())
+        .and_then(|value| parse_switch(&value))
+        .unwrap_or_else(|| feature.compiled_default())
+}
+
+/// "smp=on acpi=on ..." の形の一覧 (起動ログや /proc 用)
+pub fn summary() -> String {
+    let items: Vec<String> = FEATURES.iter()
+        .map(|&feature| alloc::format!("{}={}", feature.name(), if enabled(feature) { "on" } else { "off" }))
+        .collect();
+    items.join(" ")
+}
+
+/// コマンドラインの値を確かめ、有効な構成をログに出す (cmdline::init の後に呼ぶ)
+pub fn init() {
+    for feature in FEATURES {
+        if let Some(value) = crate::cmdline::get(feature.name()) {
+            if parse_switch(&value).is_none() {
+                crate::warn!("Invalid value for {}: {} (expected on/off)", feature.name(), value);
+            }
+        }
+    }
+    crate::info!("Kernel config: {}", summary());
+}
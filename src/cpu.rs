@@ -0,0 +1,177 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count, CpuidResult};
+use spin::Once;
+
+/// CPUID で調べる機能
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Feature {
+    Apic,
+    X2Apic,
+    TscDeadline,
+    /// 周波数や省電力状態によらず一定の速さで進む TSC
+    InvariantTsc,
+    Sse42,
+    Xsave,
+    Avx,
+    Rdrand,
+    Rdseed,
+    Smep,
+    Smap,
+    Umip,
+    /// 実行禁止ビット (EFER.NXE)
+    Nx,
+    /// 1 GiB ページ
+    Pages1G,
+}
+
+pub const FEATURES: [Feature; 14] = [
+    Feature::Apic, Feature::X2Apic, Feature::TscDeadline, Feature::InvariantTsc, Feature::Sse42,
+    Feature::Xsave, Feature::Avx, Feature::Rdrand, Feature::Rdseed, Feature::Smep, Feature::Smap,
+    Feature::Umip, Feature::Nx, Feature::Pages1G,
+];
+
+impl Feature {
+    /// /proc/cpuinfo の flags と同じ名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Apic => "apic",
+            Feature::X2Apic => "x2apic",
+            Feature::TscDeadline => "tsc_deadline_timer",
+            Feature::InvariantTsc => "constant_tsc",
+            Feature::Sse42 => "sse4_2",
+            Feature::Xsave => "xsave",
+            Feature::Avx => "avx",
+            Feature::Rdrand => "rdrand",
+            Feature::Rdseed => "rdseed",
+            Feature::Smep => "smep",
+            Feature::Smap => "smap",
+            Feature::Umip => "umip",
+            Feature::Nx => "nx",
+            Feature::Pages1G => "pdpe1gb",
+        }
+    }
+
+    fn bit(self) -> u64 {
+        1 << self as u8
+    }
+}
+
+/// 起動時に CPUID で調べた CPU の情報 (ヒープの初期化前でも使えるよう固定長)
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    features: u64,
+}
+
+static INFO: Once<CpuInfo> = Once::new();
+
+impl CpuInfo {
+    /// 今の CPU で CPUID を実行して調べる
+    pub fn detect() -> Self {
+        let leaf0 = unsafe { __cpuid(0) };
+        let max_leaf = leaf0.eax;
+        let mut vendor = [0u8; 12];
+        vendor[0..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+        vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+        vendor[8..12].copy_from_slice(&leaf0.ecx.to_le_bytes());
+
+        // 対応していない leaf はすべて 0 として扱う
+        const EMPTY: CpuidResult = CpuidResult { eax: 0, ebx: 0, ecx: 0, edx: 0 };
+        let leaf1 = unsafe { __cpuid(1) };
+        let leaf7 = if max_leaf >= 7 { unsafe { __cpuid_count(7, 0) } } else { EMPTY };
+        let max_ext = unsafe { __cpuid(0x8000_0000) }.eax;
+        let ext = |leaf: u32| if max_ext >= leaf { unsafe { __cpuid(leaf) } } else { EMPTY };
+        let ext1 = ext(0x8000_0001);
+        let ext7 = ext(0x8000_0007);
+
+        let mut features = 0;
+        let mut set = |feature: Feature, present: bool| {
+            if present {
+                features |= feature.bit();
+            }
+        };
+        set(Feature::Apic, leaf1.edx & (1 << 9) != 0);
+        set(Feature::X2Apic, leaf1.ecx & (1 << 21) != 0);
+        set(Feature::TscDeadline, leaf1.ecx & (1 << 24) != 0);
+        set(Feature::InvariantTsc, ext7.edx & (1 << 8) != 0);
+        set(Feature::Sse42, leaf1.ecx & (1 << 20) != 0);
+        set(Feature::Xsave, leaf1.ecx & (1 << 26) != 0);
+        // AVX は xsave で状態を保存できるときだけ使える
+        set(Feature::Avx, leaf1.ecx & (1 << 26) != 0 && leaf1.ecx & (1 << 28) != 0);
+        set(Feature::Rdrand, leaf1.ecx & (1 << 30) != 0);
+        set(Feature::Rdseed, leaf7.ebx & (1 << 18) != 0);
+        set(Feature::Smep, leaf7.ebx & (1 << 7) != 0);
+        set(Feature::Smap, leaf7.ebx & (1 << 20) != 0);
+        set(Feature::Umip, leaf7.ecx & (1 << 2) != 0);
+        set(Feature::Nx, ext1.edx & (1 << 20) != 0);
+        set(Feature::Pages1G, ext1.edx & (1 << 26) != 0);
+
+        // ブランド文字列は 0x80000002..0x80000004 の 48 バイト
+        let mut brand = [0u8; 48];
+        if max_ext >= 0x8000_0004 {
+            for (i, leaf) in (0x8000_0002..=0x8000_0004).enumerate() {
+                let regs = unsafe { __cpuid(leaf) };
+                for (j, reg) in [regs.eax, regs.ebx, regs.ecx, regs.edx].iter().enumerate() {
+                    let offset = i * 16 + j * 4;
+                    brand[offset..offset + 4].copy_from_slice(&reg.to_le_bytes());
+                }
+            }
+        }
+
+        // family 0xF と 6 は拡張 model を、0xF は拡張 family も足す
+        let base_family = (leaf1.eax >> 8) & 0xF;
+        let mut family = base_family;
+        let mut model = (leaf1.eax >> 4) & 0xF;
+        if base_family == 0xF {
+            family += (leaf1.eax >> 20) & 0xFF;
+        }
+        if base_family == 0xF || base_family == 0x6 {
+            model |= ((leaf1.eax >> 16) & 0xF) << 4;
+        }
+
+        Self { vendor, brand, family, model, stepping: leaf1.eax & 0xF, features }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features & feature.bit() != 0
+    }
+
+    /// "GenuineIntel" / "AuthenticAMD" など
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    /// "Intel(R) Core(TM) ..." など (対応していなければ空)
+    pub fn brand(&self) -> &str {
+        let len = self.brand.iter().position(|&b| b == 0).unwrap_or(self.brand.len());
+        core::str::from_utf8(&self.brand[..len]).unwrap_or("").trim()
+    }
+}
+
+/// BSP の CPU の情報 (最初に呼ばれたときに調べる)
+/// AP も同じ機能を持つものとして扱う
+pub fn info() -> &'static CpuInfo {
+    INFO.call_once(CpuInfo::detect)
+}
+
+pub fn has(feature: Feature) -> bool {
+    info().has(feature)
+}
+
+/// CPU を調べ、ブランド名と使える機能を表示する (BSP で最初に呼ぶ)
+pub fn init() -> &'static CpuInfo {
+    let info = info();
+    if !info.brand().is_empty() {
+        crate::println!("     {}", info.brand());
+    }
+    crate::print!("     flags:");
+    for feature in FEATURES.iter().filter(|&&feature| info.has(feature)) {
+        crate::print!(" {}", feature.name());
+    }
+    crate::println!();
+    info
+}
@@ -0,0 +1,371 @@
+//! パニック時のクラッシュダンプ
+//! パニックのメッセージ・レジスタ・バックトレース・プロセス一覧・最近のログを
+//! 1行1レコードのテキストにしてシリアルポートに出し、crashdump=<デバイス名> があればそのデバイスにも書く
+//!
+//! 形式 (QEMU の -serial の出力から BEGIN_MARKER と END_MARKER の間を切り出して読む):
+//!   -----BEGIN CRASH DUMP-----
+//!   version 1
+//!   message <メッセージ>
+//!   location <ファイル>:<行>:<列>
+//!   cpu <番号> / pid <PID か -> / uptime_ms <ミリ秒>
+//!   reg <名前> <16進数>
+//!   frame <番号> <16進アドレス> <シンボル か ?>
+//!   process pid=<> ppid=<> state=<> threads=<> cpu=<> ticks=<> pages=<> name=<名前>
+//!   log <ログの1行>
+//!   truncated                            (バッファに入り切らなかったとき)
+//!   checksum crc32=<16進数> length=<バイト数>  (BEGIN の次の行からこの行の前までの CRC-32)
+//!   -----END CRASH DUMP-----
+//! 値の中の \ と制御文字は \\ \n \xNN のようにエスケープする
+//!
+//! デバイスには先頭のセクタにヘッダ (magic, version, 長さ, CRC-32) を置き、次のセクタから同じテキストを書く
+//! パニック中はロックを持ったまま止まった処理があるかもしれないので、メモリは確保せず、ロックは try_lock で取る
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use crate::backtrace::{Registers, MAX_FRAMES};
+use crate::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::partition::{crc32, GPT_SIGNATURE, MBR_SIGNATURE};
+
+pub const BEGIN_MARKER: &str = "-----BEGIN CRASH DUMP-----";
+pub const END_MARKER: &str = "-----END CRASH DUMP-----";
+const VERSION: u32 = 1;
+/// デバイスに書くときの先頭のセクタの識別子
+const MAGIC: &[u8; 8] = b"RCRASHDP";
+/// デバイスに書くときのヘッダ (magic, version, テキストの長さ, テキストの CRC-32) の大きさ
+pub const IMAGE_HEADER_SIZE: usize = SECTOR_SIZE;
+/// 使ってよいデバイスか確かめるときに読む先頭のセクタ数 (GPT のヘッダや ext2 のスーパーブロックを含む)
+const CHECKED_SECTORS: u64 = 8;
+/// ヘッダとテキストを合わせた大きさ (パニック時にはメモリを確保しないので静的に持つ)
+const IMAGE_SIZE: usize = 64 * 1024;
+/// checksum の行と終わりの行のために空けておくバイト数
+const TRAILER_RESERVE: usize = 128;
+/// ダンプに含める最近のログの量 (リングバッファの末尾から)
+const LOG_TAIL_BYTES: usize = 16 * 1024;
+
+/// ダンプを書いている途中のパニックではもう書かない
+static DUMPING: AtomicBool = AtomicBool::new(false);
+static IMAGE: Mutex<[u8; IMAGE_SIZE]> = Mutex::new([0; IMAGE_SIZE]);
+/// シリアルポートのほかに書き込むデバイス (コマンドラインの crashdump=<デバイス名>)
+static TARGET: spin::Once<Arc<dyn BlockDevice>> = spin::Once::new();
+/// 前回の起動でデバイスに書かれていたダンプ (/proc/crashdump)
+static PREVIOUS: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// text に書きながら、serial ならシリアルポートにも同じものを出す
+/// 入り切らなくなったら以降はどちらにも出さない
+struct DumpWriter<'a> {
+    text: &'a mut [u8],
+    len: usize,
+    truncated: bool,
+    serial: bool,
+}
+
+impl DumpWriter<'_> {
+    /// 本文として書く (checksum と終わりの行の分は空けておく)
+    fn put(&mut self, bytes: &[u8]) {
+        if self.truncated {
+            return;
+        }
+        if self.len + bytes.len() > self.text.len() - TRAILER_RESERVE {
+            self.truncated = true;
+            return;
+        }
+        self.force(bytes);
+    }
+
+    fn force(&mut self, bytes: &[u8]) {
+        let bytes = &bytes[..bytes.len().min(self.text.len() - self.len)];
+        self.text[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        if self.serial {
+            crate::serial::force_write(bytes);
+        }
+    }
+
+    /// 値の1バイトをエスケープして書く
+    fn put_escaped(&mut self, byte: u8) {
+        match byte {
+            b'\\' => self.put(b"\\\\"),
+            b'\n' => self.put(b"\\n"),
+            b'\r' => self.put(b"\\r"),
+            b'\t' => self.put(b"\\t"),
+            0x00..=0x1F | 0x7F => {
+                const HEX: &[u8; 16] = b"0123456789abcdef";
+                self.put(&[b'\\', b'x', HEX[byte as usize >> 4], HEX[byte as usize & 0xF]]);
+            }
+            _ => self.put(&[byte]),
+        }
+    }
+}
+
+impl Write for DumpWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.put(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// 書式化した値をエスケープして書く
+struct Escaped<'a, 'b>(&'a mut DumpWriter<'b>);
+
+impl Write for Escaped<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0.put_escaped(byte);
+        }
+        Ok(())
+    }
+}
+
+/// 今の状態のダンプを text に書き、書いたバイト数を返す
+/// serial ならシリアルポートにも同時に出す (途中でもう一度パニックしても、そこまでは残る)
+pub fn render(text: &mut [u8], message: &dyn fmt::Display, location: Option<&Location>, serial: bool) -> usize {
+    if text.len() <= TRAILER_RESERVE {
+        return 0;
+    }
+    let mut w = DumpWriter { text, len: 0, truncated: false, serial };
+    w.force(BEGIN_MARKER.as_bytes());
+    w.force(b"\n");
+    let start = w.len;
+
+    let _ = writeln!(w, "version {}", VERSION);
+    w.put(b"message ");
+    let _ = write!(Escaped(&mut w), "{}", message);
+    w.put(b"\n");
+    if let Some(location) = location {
+        w.put(b"location ");
+        let _ = write!(Escaped(&mut w), "{}", location);
+        w.put(b"\n");
+    }
+    let _ = writeln!(w, "cpu {}", crate::smp::cpu_id());
+    match crate::process::try_current_pid() {
+        Some(pid) => { let _ = writeln!(w, "pid {}", pid); }
+        None => w.put(b"pid -\n"),
+    }
+    let _ = writeln!(w, "uptime_ms {}", crate::drivers::timer::get_uptime_ms());
+
+    for (name, value) in Registers::capture().named() {
+        let _ = writeln!(w, "reg {} {:#018x}", name, value);
+    }
+
+    let mut frames = [0u64; MAX_FRAMES];
+    let count = crate::backtrace::collect(&mut frames);
+    for (i, &addr) in frames[..count].iter().enumerate() {
+        let _ = write!(w, "frame {} {:#018x} ", i, addr);
+        match crate::symbols::resolve(addr) {
+            Some(symbol) => { let _ = write!(Escaped(&mut w), "{}", symbol); }
+            None => w.put(b"?"),
+        }
+        w.put(b"\n");
+    }
+
+    let complete = crate::process::try_for_each_process(|process| {
+        let main = process.main_thread();
+        let _ = write!(w, "process pid={} ppid={} state={:?} threads={} cpu={} ticks={} pages={} name=",
+            process.pid, process.ppid, main.state, process.threads.len(), main.cpu,
+            process.cpu_ticks(), process.mapped_pages);
+        let _ = write!(Escaped(&mut w), "{}", process.name);
+        w.put(b"\n");
+    });
+    if !complete {
+        w.put(b"process unavailable\n");
+    }
+
+    // ログはリングバッファの行をそのまま1行ずつ書く
+    let mut line_start = true;
+    let complete = crate::log::for_each_tail_byte(LOG_TAIL_BYTES, |byte| {
+        if line_start {
+            w.put(b"log ");
+            line_start = false;
+        }
+        if byte == b'\n' {
+            w.put(b"\n");
+            line_start = true;
+        } else {
+            w.put_escaped(byte);
+        }
+    });
+    if !line_start {
+        w.put(b"\n");
+    }
+    if !complete {
+        w.put(b"log unavailable\n");
+    }
+
+    // 入り切らずに行の途中で切れていれば、改行してから閉じる
+    if w.truncated {
+        if w.text[w.len - 1] != b'\n' {
+            w.force(b"\n");
+        }
+        w.force(b"truncated\n");
+    }
+    let body = &w.text[start..w.len];
+    let (crc, length) = (crc32(body), body.len());
+    let mut trailer = [0u8; TRAILER_RESERVE];
+    let mut cursor = Cursor { buf: &mut trailer, len: 0 };
+    let _ = write!(cursor, "checksum crc32={:#010x} length={}\n{}\n", crc, length, END_MARKER);
+    let len = cursor.len;
+    w.force(&trailer[..len]);
+    w.len
+}
+
+/// 固定長のバッファに書式化する
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// text が壊れていない1つのダンプかを確かめ、本文 (BEGIN の次の行から checksum の行の前まで) の長さを返す
+pub fn verify(text: &[u8]) -> Result<usize, &'static str> {
+    let text = core::str::from_utf8(text).map_err(|_| "Crash dump is not UTF-8")?;
+    let body = text.strip_prefix(BEGIN_MARKER)
+        .and_then(|rest| rest.strip_prefix('\n'))
+        .ok_or("Missing crash dump header")?;
+    let rest = body.trim_end_matches('\n').strip_suffix(END_MARKER).ok_or("Missing crash dump trailer")?;
+    let checksum_start = rest.trim_end_matches('\n').rfind('\n').map_or(0, |pos| pos + 1);
+    let fields = rest[checksum_start..].trim_end().strip_prefix("checksum ").ok_or("Missing checksum")?;
+    let mut crc = None;
+    let mut length = None;
+    for field in fields.split(' ') {
+        match field.split_once('=') {
+            Some(("crc32", value)) => {
+                crc = u32::from_str_radix(value.trim_start_matches("0x"), 16).ok();
+            }
+            Some(("length", value)) => length = value.parse::<usize>().ok(),
+            _ => {}
+        }
+    }
+    let (crc, length) = crc.zip(length).ok_or("Malformed checksum")?;
+    if length != checksum_start || crc32(&body.as_bytes()[..length]) != crc {
+        return Err("Crash dump checksum mismatch");
+    }
+    Ok(length)
+}
+
+/// image の先頭にヘッダを書き、続く text_len バイトのテキストと合わせて device の先頭に書く
+/// パニック時に使うので、割り込みを使わない書き込みをする (ページキャッシュも通さない)
+/// ここでは中身を確かめずにセクタ 0 から上書きするので、device は check_device を通ったものにすること
+pub fn store(device: &dyn BlockDevice, image: &mut [u8], text_len: usize) -> Result<usize, &'static str> {
+    let size = (IMAGE_HEADER_SIZE + text_len).next_multiple_of(SECTOR_SIZE);
+    if size > image.len() {
+        return Err("Crash dump does not fit in the buffer");
+    }
+    if size as u64 > device.sector_count() * SECTOR_SIZE as u64 {
+        return Err("Crash dump does not fit on the device");
+    }
+    let crc = crc32(&image[IMAGE_HEADER_SIZE..IMAGE_HEADER_SIZE + text_len]);
+    let header = &mut image[..IMAGE_HEADER_SIZE];
+    header.fill(0);
+    header[0..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+    header[12..16].copy_from_slice(&(text_len as u32).to_le_bytes());
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    // 最後のセクタの残りは 0 で埋める
+    image[IMAGE_HEADER_SIZE + text_len..size].fill(0);
+    device.write_sectors_polled(0, &image[..size])?;
+    Ok(size)
+}
+
+/// device をダンプの書き込み先にしてよいか確かめる
+/// 先頭にダンプのヘッダがあるか、先頭のセクタがすべて 0 なら使ってよい
+/// パーティションテーブルやファイルシステムなど、ほかのデータがあれば上書きしないよう断る
+pub fn check_device(device: &dyn BlockDevice) -> Result<(), &'static str> {
+    let sectors = device.sector_count().min(CHECKED_SECTORS);
+    let mut head = vec![0u8; sectors as usize * SECTOR_SIZE];
+    device.read_sectors(0, &mut head)?;
+    if &head[0..8] == MAGIC {
+        return Ok(());
+    }
+    if head[510..512] == MBR_SIGNATURE || head.get(SECTOR_SIZE..SECTOR_SIZE + 8) == Some(GPT_SIGNATURE) {
+        return Err("Crashdump device has a partition table");
+    }
+    if head.iter().any(|&b| b != 0) {
+        return Err("Crashdump device is not empty");
+    }
+    Ok(())
+}
+
+/// device の先頭に書かれたダンプのテキストを読む
+pub fn load(device: &dyn BlockDevice) -> Result<Vec<u8>, &'static str> {
+    let mut header = [0u8; IMAGE_HEADER_SIZE];
+    device.read_sectors(0, &mut header)?;
+    if &header[0..8] != MAGIC {
+        return Err("No crash dump");
+    }
+    if u32::from_le_bytes(header[8..12].try_into().unwrap()) != VERSION {
+        return Err("Unsupported crash dump version");
+    }
+    let text_len = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[16..20].try_into().unwrap());
+    let size = text_len.next_multiple_of(SECTOR_SIZE);
+    if text_len > IMAGE_SIZE - IMAGE_HEADER_SIZE
+        || (IMAGE_HEADER_SIZE + size) as u64 > device.sector_count() * SECTOR_SIZE as u64
+    {
+        return Err("Truncated crash dump");
+    }
+    let mut text = vec![0u8; size];
+    device.read_sectors((IMAGE_HEADER_SIZE / SECTOR_SIZE) as u64, &mut text)?;
+    text.truncate(text_len);
+    if crc32(&text) != crc {
+        return Err("Crash dump checksum mismatch");
+    }
+    Ok(text)
+}
+
+/// パニックハンドラから呼ぶ: ダンプをシリアルポートに出し、設定されていればデバイスにも書く
+/// ダンプ中にもう一度パニックしたときは何もしない
+pub fn write(message: &dyn fmt::Display, location: Option<&Location>) {
+    if DUMPING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let Some(mut image) = IMAGE.try_lock() else {
+        return;
+    };
+    let len = render(&mut image[IMAGE_HEADER_SIZE..], message, location, true);
+    if let Some(device) = TARGET.get() {
+        match store(device.as_ref(), &mut image[..], len) {
+            Ok(size) => crate::println!("Crash dump written to {} ({} bytes)", device.name(), size),
+            Err(e) => crate::println!("Crash dump not written to {}: {}", device.name(), e),
+        }
+    }
+}
+
+/// 前回の起動でデバイスに書かれていたダンプ
+pub fn previous() -> Option<Vec<u8>> {
+    PREVIOUS.lock().clone()
+}
+
+/// コマンドラインの crashdump=<デバイス名> のデバイスにもダンプを書くようにする
+/// 前回の起動で書かれたダンプがあれば読んでおく (ブロックデバイスのドライバの初期化後に呼ぶ)
+/// ダンプはデバイスの先頭から上書きするので、空のディスクかパーティションを使うこと (check_device で確かめる)
+pub fn init() -> Result<String, &'static str> {
+    let name = crate::cmdline::get("crashdump").ok_or("No crashdump device")?;
+    let device = block::find(&name).and_then(block::get).ok_or("Crashdump device not found")?;
+    if device.sector_count() < ((IMAGE_HEADER_SIZE + SECTOR_SIZE) / SECTOR_SIZE) as u64 {
+        return Err("Crashdump device too small");
+    }
+    check_device(device.as_ref())?;
+    match load(device.as_ref()) {
+        Ok(text) => {
+            crate::warn!("Crash dump from a previous boot on {} ({} bytes, see /proc/crashdump)", name, text.len());
+            *PREVIOUS.lock() = Some(text);
+        }
+        Err(e) => crate::debug!("crashdump {}: {}", name, e),
+    }
+    TARGET.call_once(|| device);
+    Ok(name)
+}
@@ -0,0 +1,108 @@
+use crate::errno::Errno;
+use crate::smp::{cpu_id, MAX_CPUS};
+use crate::sync::IrqMutex;
+
+pub const ROOT_UID: u32 = 0;
+pub const ROOT_GID: u32 = 0;
+
+// 権限を確かめるアクセスの種類 (rwx の各ビットと同じ並び)
+pub const MAY_EXEC: u32 = 0o1;
+pub const MAY_WRITE: u32 = 0o2;
+pub const MAY_READ: u32 = 0o4;
+
+/// プロセスのユーザーID・グループID (fork で子に引き継ぐ)
+/// 権限の確認には実効ID (euid/egid) を使う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub euid: u32,
+    pub egid: u32,
+}
+
+impl Credentials {
+    pub const ROOT: Self = Self { uid: ROOT_UID, gid: ROOT_GID, euid: ROOT_UID, egid: ROOT_GID };
+
+    pub const fn new(uid: u32, gid: u32) -> Self {
+        Self { uid, gid, euid: uid, egid: gid }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.euid == ROOT_UID
+    }
+
+    /// owner/group が持つ mode (0o777) のファイルに mask のアクセスをしてよいか
+    /// root は所有者に関係なく許すが、誰にもないビット (/proc への書き込みなど) は許さない
+    pub fn may_access(&self, owner: u32, group: u32, mode: u32, mask: u32) -> bool {
+        if self.is_root() {
+            return [MAY_READ, MAY_WRITE, MAY_EXEC].iter()
+                .filter(|&&bit| mask & bit != 0)
+                .all(|&bit| mode & (bit * 0o111) != 0);
+        }
+        let class = if self.euid == owner {
+            mode >> 6
+        } else if self.egid == group {
+            mode >> 3
+        } else {
+            mode
+        };
+        class & mask == mask
+    }
+
+    /// root ならすべてのユーザーIDを uid に、そうでなければ実効IDを実ユーザーIDに戻すことだけできる
+    pub fn setuid(&mut self, uid: u32) -> Result<(), Errno> {
+        if self.is_root() {
+            self.uid = uid;
+        } else if uid != self.uid {
+            return Err(Errno::EPERM);
+        }
+        self.euid = uid;
+        Ok(())
+    }
+
+    pub fn setgid(&mut self, gid: u32) -> Result<(), Errno> {
+        if self.is_root() {
+            self.gid = gid;
+        } else if gid != self.gid {
+            return Err(Errno::EPERM);
+        }
+        self.egid = gid;
+        Ok(())
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::ROOT
+    }
+}
+
+// CPU ごとに with_credentials で差し替えている資格情報
+static OVERRIDES: IrqMutex<[Option<Credentials>; MAX_CPUS]> = IrqMutex::new([None; MAX_CPUS]);
+
+/// 現在のプロセスの資格情報 (カーネルコンテキストは root)
+/// with_credentials の中ではそちらを優先する
+pub fn current() -> Credentials {
+    if let Some(cred) = OVERRIDES.lock()[cpu_id()] {
+        return cred;
+    }
+    crate::process::with_current_process(|process| process.cred).unwrap_or(Credentials::ROOT)
+}
+
+/// このCPUで f を実行するあいだ、cred の権限で動いたことにする
+pub fn with_credentials<R>(cred: Credentials, f: impl FnOnce() -> R) -> R {
+    let previous = core::mem::replace(&mut OVERRIDES.lock()[cpu_id()], Some(cred));
+    let result = f();
+    OVERRIDES.lock()[cpu_id()] = previous;
+    result
+}
+
+pub fn setuid(uid: u32) -> Result<(), Errno> {
+    crate::process::with_current_process(|process| process.cred.setuid(uid))
+        .ok_or(Errno::ESRCH)?
+}
+
+pub fn setgid(gid: u32) -> Result<(), Errno> {
+    crate::process::with_current_process(|process| process.cred.setgid(gid))
+        .ok_or(Errno::ESRCH)?
+}
@@ -1 +1,116 @@
-pub fn run_complete_demo() {}
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::errno::Errno;
+use crate::fd::{FdTable, FileObject, OpenFile};
+use crate::filesystem::{self, O_NONBLOCK, O_RDONLY, O_WRONLY};
+use crate::{print, println};
+
+/// initramfs 内のテストプログラムを置くディレクトリ
+/// <name> が ELF かフラットバイナリの実行ファイル、<name>.out がその標準出力の期待値
+const TEST_DIR: &str = "/tests";
+const EXPECTED_SUFFIX: &str = ".out";
+
+/// テストプログラムを1つユーザーモードで実行し、終了コードと標準出力を返す
+/// 標準出力はパイプにつなぎ、出力がバッファより多くても止まらないよう非ブロッキングにする
+fn run_program(name: &str, image: &[u8]) -> Result<(i32, Vec<u8>), Errno> {
+    let mut process = crate::flat::create_any_process(name, image)?;
+    let (reader, writer) = crate::pipe::create();
+    let mut fds = FdTable::new();
+    fds.install(Arc::new(OpenFile::new(FileObject::ConsoleIn, O_RDONLY)));
+    fds.install(Arc::new(OpenFile::new(FileObject::PipeWrite(writer), O_WRONLY | O_NONBLOCK)));
+    fds.install(Arc::new(OpenFile::new(FileObject::ConsoleOut, O_WRONLY)));
+    process.fds = fds;
+
+    let code = crate::process::run_user_program(process);
+
+    // プロセスと一緒に書き込み側も閉じられているので、EOF まで読める
+    let mut output = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => break,
+            n => output.extend_from_slice(&buf[..n]),
+        }
+    }
+    Ok((code, output))
+}
+
+/// initramfs の /tests にあるプログラムを順に実行し、出力を期待値と比べる
+/// exec とユーザーモードの経路を通しで確かめるための起動時テスト
+pub fn run_complete_demo() {
+    let entries = match filesystem::list_directory(TEST_DIR) {
+        Ok(entries) => entries,
+        Err(_) => {
+            crate::debug!("demo: {} not found, skipping user tests", TEST_DIR);
+            return;
+        }
+    };
+
+    println!("\n=== User Program Tests ({}) ===", TEST_DIR);
+    let (mut passed, mut failed) = (0, 0);
+    for name in entries.iter().filter(|name| !name.ends_with(EXPECTED_SUFFIX)) {
+        let path = alloc::format!("{}/{}", TEST_DIR, name);
+        let expected_path = alloc::format!("{}{}", path, EXPECTED_SUFFIX);
+
+        let result = filesystem::read_file(&path).and_then(|image| run_program(name, &image));
+        let expected = filesystem::read_file(&expected_path).unwrap_or_default();
+        match result {
+            Ok((0, output)) if output == expected => {
+                println!("  [PASS] {}", name);
+                passed += 1;
+            }
+            Ok((0, output)) => {
+                println!("  [FAIL] {}: unexpected output", name);
+                println!("         expected: {:?}", String::from_utf8_lossy(&expected));
+                println!("         got:      {:?}", String::from_utf8_lossy(&output));
+                failed += 1;
+            }
+            Ok((code, _)) => {
+                println!("  [FAIL] {}: exit code {}", name, code);
+                failed += 1;
+            }
+            Err(e) => {
+                println!("  [FAIL] {}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    println!("=== {} passed, {} failed ===\n", passed, failed);
+}
+
+/// 比べるときにシミュレーションするティック数
+const SIM_TICKS: usize = 2000;
+
+/// 同じ負荷 (優先度の違う CPU を使い続けるタスク3つと、短く走っては眠る対話的なタスク1つ) を
+/// それぞれのポリシーでシミュレーションし、待ち時間・処理量・CPU の配分を並べて表示する
+pub fn compare_schedulers() {
+    use crate::sched::{self, SimTask};
+
+    let tasks = [
+        SimTask { priority: 5, run: 20, sleep: 0 },
+        SimTask { priority: 10, run: 20, sleep: 0 },
+        SimTask { priority: 15, run: 20, sleep: 0 },
+        SimTask { priority: 10, run: 1, sleep: 10 },
+    ];
+    let active = crate::process::sched_stats();
+    println!("=== Scheduler Policies ({} ticks, active: {}) ===",
+        SIM_TICKS, active.map_or("none", |(policy, _)| policy.name()));
+    println!("  policy  avg wait  max wait  preempt  jobs/1000t  cpu share (prio 5/10/15, interactive)");
+    for policy in sched::POLICIES {
+        let result = sched::simulate(policy, &tasks, SIM_TICKS);
+        let stats = &result.stats;
+        let avg = stats.avg_wait_centiticks();
+        print!("  {:<6} {:>5}.{:02}t {:>8}t {:>8} {:>11}  ", policy.name(), avg / 100, avg % 100,
+            stats.max_wait_ticks, stats.preemptions, stats.throughput_per_1000_ticks());
+        let shares: Vec<String> = result.cpu_ticks.iter()
+            .map(|&ticks| alloc::format!("{}%", ticks * 100 / SIM_TICKS))
+            .collect();
+        println!("{}", shares.join("/"));
+    }
+    if let Some((policy, stats)) = active {
+        let avg = stats.avg_wait_centiticks();
+        println!("  live ({}): {} ticks, {} dispatches, avg wait {}.{:02}t, {} preemptions\n", policy.name(),
+            stats.ticks, stats.dispatches, avg / 100, avg % 100, stats.preemptions);
+    }
+}
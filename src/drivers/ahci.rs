@@ -0,0 +1,408 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::pci;
+use crate::sync::WaitQueue;
+
+// PCI クラス (大容量記憶装置 / SATA / AHCI 1.0)
+const CLASS_STORAGE: u8 = 0x01;
+const SUBCLASS_SATA: u8 = 0x06;
+const PROG_IF_AHCI: u8 = 0x01;
+
+// HBA の汎用レジスタ
+const HBA_GHC: usize = 0x04;
+const HBA_IS: usize = 0x08;
+const HBA_PI: usize = 0x0C;
+const HBA_VS: usize = 0x10;
+const GHC_AE: u32 = 1 << 31;
+const GHC_IE: u32 = 1 << 1;
+
+// ポートのレジスタ (0x100 + ポート番号 * 0x80 から)
+const PORT_BASE: usize = 0x100;
+const PORT_SIZE: usize = 0x80;
+const PX_CLB: usize = 0x00;
+const PX_CLBU: usize = 0x04;
+const PX_FB: usize = 0x08;
+const PX_FBU: usize = 0x0C;
+const PX_IS: usize = 0x10;
+const PX_IE: usize = 0x14;
+const PX_CMD: usize = 0x18;
+const PX_TFD: usize = 0x20;
+const PX_SIG: usize = 0x24;
+const PX_SSTS: usize = 0x28;
+const PX_SERR: usize = 0x30;
+const PX_CI: usize = 0x38;
+
+const CMD_ST: u32 = 1 << 0;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BSY: u32 = 1 << 7;
+/// Task File Error (デバイスがエラーを返した)
+const IS_TFES: u32 = 1 << 30;
+/// 完了 (D2H Register FIS) とエラーで割り込む
+const IE_MASK: u32 = IS_TFES | 1 << 0;
+
+/// SStatus の DET=3 (デバイスあり、通信確立) と IPM=1 (アクティブ)
+const SSTS_DET_PRESENT: u32 = 3;
+const SSTS_IPM_ACTIVE: u32 = 1;
+/// SATA ディスクのシグネチャ (ATAPI などは扱わない)
+const SIG_ATA: u32 = 0x0000_0101;
+
+// ATA コマンド
+const ATA_IDENTIFY: u8 = 0xEC;
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+/// Register FIS (ホスト → デバイス)
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+
+// ポートごとの DMA 領域 (1ページ目) の配置
+const COMMAND_LIST_OFFSET: usize = 0; // 32 エントリ × 32 バイト
+const RECEIVED_FIS_OFFSET: usize = 1024; // 256 バイト
+const COMMAND_TABLE_OFFSET: usize = 2048; // ヘッダ 128 バイト + PRDT 1 エントリ
+const PRDT_OFFSET: usize = 0x80;
+/// データのバウンスバッファ (2ページ目以降)
+const BOUNCE_PAGES: usize = 8;
+/// 1コマンドで転送する最大セクタ数
+const MAX_SECTORS_PER_COMMAND: usize = BOUNCE_PAGES * 4096 / SECTOR_SIZE;
+/// コマンドの完了やポートの停止を待つ上限
+const TIMEOUT_MS: usize = 1000;
+/// 割り込みなしで待つときにレジスタを見る回数の上限
+/// (割り込みが止まっているとタイマーも進まないので、時間ではなく回数で区切る)
+const POLL_SPINS: usize = 100_000_000;
+
+static COMPLETION: WaitQueue = WaitQueue::new();
+/// 割り込みハンドラが触る HBA のレジスタ (ABAR を仮想アドレスにしたもの)
+static HBA: spin::Once<Hba> = spin::Once::new();
+
+#[derive(Clone, Copy)]
+struct Hba {
+    base: VirtAddr,
+}
+
+impl Hba {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base.as_u64() as usize + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base.as_u64() as usize + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn port_read(&self, port: usize, offset: usize) -> u32 {
+        self.read(PORT_BASE + port * PORT_SIZE + offset)
+    }
+
+    fn port_write(&self, port: usize, offset: usize, value: u32) {
+        self.write(PORT_BASE + port * PORT_SIZE + offset, value)
+    }
+
+    /// 条件が成り立つまで待つ (タイムアウトしたら false)
+    fn wait(&self, mut condition: impl FnMut(&Self) -> bool) -> bool {
+        let deadline = crate::drivers::timer::get_uptime_ms() + TIMEOUT_MS;
+        while !condition(self) {
+            if crate::drivers::timer::get_uptime_ms() >= deadline {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    /// wait と同じだが、タイマーを使わず回数で区切る (割り込みを止めたまま待つとき)
+    fn poll(&self, mut condition: impl FnMut(&Self) -> bool) -> bool {
+        (0..POLL_SPINS).any(|_| {
+            core::hint::spin_loop();
+            condition(self)
+        })
+    }
+}
+
+struct Port {
+    hba: Hba,
+    index: usize,
+    // 1ページ目: コマンドリスト・受信 FIS・コマンドテーブル、2ページ目以降: データ
+    memory: VirtAddr,
+    memory_phys: PhysAddr,
+}
+
+pub struct AhciDisk {
+    name: String,
+    port: Mutex<Port>,
+    sectors: u64,
+}
+
+impl Port {
+    fn bounce(&self) -> *mut u8 {
+        (self.memory.as_u64() + 4096) as *mut u8
+    }
+
+    /// コマンドエンジンを止める (CLB/FB を書き換える前に必要)
+    fn stop(&self) -> Result<(), &'static str> {
+        let cmd = self.hba.port_read(self.index, PX_CMD);
+        self.hba.port_write(self.index, PX_CMD, cmd & !(CMD_ST | CMD_FRE));
+        if !self.hba.wait(|hba| hba.port_read(self.index, PX_CMD) & (CMD_CR | CMD_FR) == 0) {
+            return Err("AHCI port did not stop");
+        }
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), &'static str> {
+        if !self.hba.wait(|hba| hba.port_read(self.index, PX_TFD) & (TFD_BSY | TFD_DRQ) == 0) {
+            return Err("AHCI port busy");
+        }
+        let cmd = self.hba.port_read(self.index, PX_CMD);
+        self.hba.port_write(self.index, PX_CMD, cmd | CMD_FRE);
+        self.hba.port_write(self.index, PX_CMD, cmd | CMD_FRE | CMD_ST);
+        Ok(())
+    }
+
+    /// コマンドリストと受信 FIS の領域を設定し、コマンドエンジンを動かす
+    fn init(&self) -> Result<(), &'static str> {
+        self.stop()?;
+        let phys = self.memory_phys.as_u64();
+        let command_list = phys + COMMAND_LIST_OFFSET as u64;
+        let received_fis = phys + RECEIVED_FIS_OFFSET as u64;
+        self.hba.port_write(self.index, PX_CLB, command_list as u32);
+        self.hba.port_write(self.index, PX_CLBU, (command_list >> 32) as u32);
+        self.hba.port_write(self.index, PX_FB, received_fis as u32);
+        self.hba.port_write(self.index, PX_FBU, (received_fis >> 32) as u32);
+
+        // コマンドヘッダ0 は常に同じコマンドテーブルを指す
+        let table = phys + COMMAND_TABLE_OFFSET as u64;
+        let header = (self.memory.as_u64() as usize + COMMAND_LIST_OFFSET) as *mut u32;
+        unsafe {
+            header.add(2).write_volatile(table as u32);
+            header.add(3).write_volatile((table >> 32) as u32);
+        }
+
+        // たまっているエラーと割り込み要因を消す
+        self.hba.port_write(self.index, PX_SERR, u32::MAX);
+        self.hba.port_write(self.index, PX_IS, u32::MAX);
+        self.hba.port_write(self.index, PX_IE, IE_MASK);
+        self.start()
+    }
+
+    /// コマンドスロット0 に ATA コマンドを組み立てて発行し、完了を待つ
+    /// データは len バイトまでバウンスバッファとの間で転送する
+    /// polled なら割り込みを待たずにレジスタを見続ける (エラー時のポートの立て直しもしない)
+    fn issue(&self, command: u8, lba: u64, count: u16, len: usize, write: bool, polled: bool) -> Result<(), &'static str> {
+        let table = self.memory.as_u64() as usize + COMMAND_TABLE_OFFSET;
+        let fis = table as *mut u8;
+        let bytes = lba.to_le_bytes();
+        let mut h2d = [0u8; 20];
+        h2d[0] = FIS_TYPE_REG_H2D;
+        h2d[1] = 0x80; // コマンドレジスタの更新
+        h2d[2] = command;
+        h2d[4..7].copy_from_slice(&bytes[0..3]);
+        h2d[7] = 1 << 6; // LBA モード
+        h2d[8..11].copy_from_slice(&bytes[3..6]);
+        h2d[12..14].copy_from_slice(&count.to_le_bytes());
+
+        let bounce = self.memory_phys.as_u64() + 4096;
+        let prdt = (table + PRDT_OFFSET) as *mut u32;
+        let header = (self.memory.as_u64() as usize + COMMAND_LIST_OFFSET) as *mut u32;
+        // DW0: FIS の長さ (ダブルワード単位)、書き込みフラグ、PRDT のエントリ数
+        let flags = (h2d.len() / 4) as u32 | if write { 1 << 6 } else { 0 } | 1 << 16;
+        unsafe {
+            core::ptr::copy_nonoverlapping(h2d.as_ptr(), fis, h2d.len());
+            prdt.write_volatile(bounce as u32);
+            prdt.add(1).write_volatile((bounce >> 32) as u32);
+            prdt.add(3).write_volatile((len as u32 - 1) | 1 << 31);
+            header.write_volatile(flags);
+            header.add(1).write_volatile(0);
+        }
+
+        let idle = |hba: &Hba| hba.port_read(self.index, PX_TFD) & (TFD_BSY | TFD_DRQ) == 0;
+        if !(if polled { self.hba.poll(idle) } else { self.hba.wait(idle) }) {
+            return Err("AHCI port busy");
+        }
+        self.hba.port_write(self.index, PX_CI, 1);
+
+        // 割り込みで起こされるが、条件はレジスタを直接見るので
+        // 割り込みが届かない構成でもタイマー割り込みごとに進む
+        let hba = self.hba;
+        let index = self.index;
+        let done = |hba: &Hba| hba.port_read(index, PX_CI) & 1 == 0 || hba.port_read(index, PX_TFD) & TFD_ERR != 0;
+        if polled {
+            if !hba.poll(done) {
+                return Err("AHCI command timed out");
+            }
+        } else {
+            COMPLETION.wait_until(|| done(&hba));
+        }
+
+        let error = self.hba.port_read(self.index, PX_TFD) & TFD_ERR != 0
+            || self.hba.port_read(self.index, PX_IS) & IS_TFES != 0;
+        self.hba.port_write(self.index, PX_IS, u32::MAX);
+        if error {
+            // エラーで止まったポートはコマンドエンジンを再起動して立て直す
+            if !polled {
+                let _ = self.stop().and_then(|_| self.start());
+            }
+            return Err("AHCI device error");
+        }
+        Ok(())
+    }
+
+    /// IDENTIFY DEVICE で総セクタ数 (LBA48) と型番を得る
+    fn identify(&self) -> Result<(u64, String), &'static str> {
+        self.issue(ATA_IDENTIFY, 0, 0, SECTOR_SIZE, false, false)?;
+        let mut data = [0u16; SECTOR_SIZE / 2];
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.bounce() as *const u16, data.as_mut_ptr(), data.len());
+        }
+        let sectors = (100..104).rev().fold(0u64, |sectors, word| sectors << 16 | data[word] as u64);
+        // 型番は語ごとにバイトが入れ替わった ASCII
+        let model: Vec<u8> = data[27..47].iter().flat_map(|word| word.to_be_bytes()).collect();
+        let model = String::from(core::str::from_utf8(&model).unwrap_or("").trim());
+        Ok((sectors, model))
+    }
+}
+
+impl BlockDevice for AhciDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let port = self.port.lock();
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE).enumerate() {
+            let sector = lba + (i * MAX_SECTORS_PER_COMMAND) as u64;
+            let count = chunk.len() / SECTOR_SIZE;
+            if sector + count as u64 > self.sectors {
+                return Err("Sector out of range");
+            }
+            port.issue(ATA_READ_DMA_EXT, sector, count as u16, chunk.len(), false, false)?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(port.bounce(), chunk.as_mut_ptr(), chunk.len());
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.write_chunks(&self.port.lock(), lba, buf, false)
+    }
+
+    fn write_sectors_polled(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let port = self.port.try_lock().ok_or("AHCI port busy")?;
+        self.write_chunks(&port, lba, buf, true)
+    }
+}
+
+impl AhciDisk {
+    fn write_chunks(&self, port: &Port, lba: u64, buf: &[u8], polled: bool) -> Result<(), &'static str> {
+        for (i, chunk) in buf.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE).enumerate() {
+            let sector = lba + (i * MAX_SECTORS_PER_COMMAND) as u64;
+            let count = chunk.len() / SECTOR_SIZE;
+            if sector + count as u64 > self.sectors {
+                return Err("Sector out of range");
+            }
+            unsafe {
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), port.bounce(), chunk.len());
+            }
+            port.issue(ATA_WRITE_DMA_EXT, sector, count as u16, chunk.len(), true, polled)?;
+        }
+        Ok(())
+    }
+}
+
+/// ポートごとの割り込み要因を消してから HBA 全体の要因を消し、待っている転送を起こす
+fn handle_interrupt() {
+    let Some(hba) = HBA.get() else { return };
+    let pending = hba.read(HBA_IS);
+    if pending == 0 {
+        return;
+    }
+    for port in (0..32).filter(|port| pending & (1 << port) != 0) {
+        // TFES はエラーの判定に使うので issue 側で消す
+        let status = hba.port_read(port, PX_IS);
+        hba.port_write(port, PX_IS, status & !IS_TFES);
+    }
+    hba.write(HBA_IS, pending);
+    COMPLETION.wake_all();
+}
+
+/// SATA ディスクがつながっていればポートを初期化してディスクを返す
+fn probe_port(hba: Hba, index: usize) -> Result<Option<AhciDisk>, &'static str> {
+    let ssts = hba.port_read(index, PX_SSTS);
+    if ssts & 0xF != SSTS_DET_PRESENT || (ssts >> 8) & 0xF != SSTS_IPM_ACTIVE {
+        return Ok(None);
+    }
+    if hba.port_read(index, PX_SIG) != SIG_ATA {
+        crate::debug!("ahci: port {} is not a SATA disk (signature {:#x})", index, hba.port_read(index, PX_SIG));
+        return Ok(None);
+    }
+
+    let (memory, memory_phys) = crate::memory::alloc_dma(1 + BOUNCE_PAGES)?;
+    let port = Port { hba, index, memory, memory_phys };
+    port.init()?;
+    let (sectors, model) = port.identify()?;
+    crate::info!("ahci: port {}: {} ({} sectors)", index, model, sectors);
+    Ok(Some(AhciDisk {
+        name: format!("ahci{}", index),
+        port: Mutex::new(port),
+        sectors,
+    }))
+}
+
+fn setup(info: &pci::PciDevice) -> Result<usize, &'static str> {
+    let (address, size) = match info.bars[5] {
+        pci::Bar::Memory { address, size, .. } => (address, size),
+        _ => return Err("BAR5 (ABAR) is not a memory BAR"),
+    };
+    info.address.enable_bus_master();
+    let base = crate::memory::map_mmio(PhysAddr::new(address), size as usize)?;
+    let hba = *HBA.call_once(|| Hba { base });
+
+    // AHCI モードにして割り込みを許可する
+    hba.write(HBA_GHC, hba.read(HBA_GHC) | GHC_AE);
+    let version = hba.read(HBA_VS);
+    crate::info!("ahci: AHCI {}.{} controller, ports {:#x}",
+        version >> 16, (version >> 8) & 0xFF, hba.read(HBA_PI));
+    if let Err(e) = crate::interrupts::register_irq(info.irq_line, handle_interrupt) {
+        crate::warn!("ahci: IRQ {} unavailable ({}), polling instead", info.irq_line, e);
+    }
+    hba.write(HBA_IS, u32::MAX);
+    hba.write(HBA_GHC, hba.read(HBA_GHC) | GHC_IE);
+
+    let implemented = hba.read(HBA_PI);
+    let mut disks = 0;
+    for index in (0..32).filter(|index| implemented & (1 << index) != 0) {
+        match probe_port(hba, index) {
+            Ok(Some(disk)) => {
+                block::register(Arc::new(disk));
+                disks += 1;
+            }
+            Ok(None) => {}
+            Err(e) => crate::warn!("ahci: port {}: {}", index, e),
+        }
+    }
+    Ok(disks)
+}
+
+/// AHCI コントローラを探して初期化し、つながっている SATA ディスクをブロックデバイスとして登録する
+pub fn init() -> Result<(), &'static str> {
+    let info = pci::claim("ahci", |device| {
+        device.class == CLASS_STORAGE && device.subclass == SUBCLASS_SATA && device.prog_if == PROG_IF_AHCI
+    }).ok_or("No AHCI controller")?;
+    match setup(&info) {
+        Ok(0) => Err("No SATA disks"),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            pci::release(info.address);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// セクタサイズ
+pub const SECTOR_SIZE: usize = 512;
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+/// セクタ単位で読み書きするブロックデバイス
+/// ディスクを使うファイルシステムはこのトレイト越しにドライバを使う
+pub trait BlockDevice: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// 総セクタ数
+    fn sector_count(&self) -> u64;
+
+    /// lba から buf.len() / SECTOR_SIZE セクタ読み込む
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str>;
+
+    /// lba から buf.len() / SECTOR_SIZE セクタ書き込む
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str>;
+
+    /// write_sectors と同じだが、割り込みやスケジューラを使わずに完了を待つ (パニック時のクラッシュダンプ用)
+    /// ロックが取れなければ待たずに失敗する。対応していないデバイスは常に失敗する
+    fn write_sectors_polled(&self, _lba: u64, _buf: &[u8]) -> Result<(), &'static str> {
+        Err("Polled writes not supported")
+    }
+}
+
+/// ブロックデバイスを登録し、番号を返す
+/// パーティションテーブルがあれば、各パーティションも続けて登録する
+pub fn register(device: Arc<dyn BlockDevice>) -> usize {
+    let index = add(device.clone());
+    // 読み込み中はデバイス一覧のロックを持たない
+    if let Err(e) = crate::drivers::partition::scan(device.clone()) {
+        crate::warn!("{}: partition table: {}", device.name(), e);
+    }
+    index
+}
+
+/// パーティションをブロックデバイスとして登録する (中のパーティションテーブルは読まない)
+pub fn register_partition(partition: Arc<dyn BlockDevice>) -> usize {
+    add(partition)
+}
+
+fn add(device: Arc<dyn BlockDevice>) -> usize {
+    let mut devices = DEVICES.lock();
+    crate::info!("Block device {}: {} ({} MiB)", devices.len(), device.name(),
+        device.sector_count() * SECTOR_SIZE as u64 / (1024 * 1024));
+    devices.push(device);
+    devices.len() - 1
+}
+
+pub fn get(index: usize) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().get(index).cloned()
+}
+
+pub fn count() -> usize {
+    DEVICES.lock().len()
+}
+
+/// 名前 (virtio-blk, ram0 など) でデバイスを探して番号を返す
+pub fn find(name: &str) -> Option<usize> {
+    DEVICES.lock().iter().position(|device| device.name() == name)
+}
+
+/// offset バイト目から buf に読む (ブロックデバイスのノード用、ページキャッシュ越し)
+/// デバイスの終わりまでしか読まないので、読んだバイト数を返す
+pub fn read_bytes(index: usize, offset: usize, buf: &mut [u8]) -> Result<usize, &'static str> {
+    let size = get(index).ok_or("No such block device")?.sector_count() as usize * SECTOR_SIZE;
+    let end = size.min(offset.saturating_add(buf.len()));
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut position = offset;
+    while position < end {
+        let within = position % SECTOR_SIZE;
+        let count = (SECTOR_SIZE - within).min(end - position);
+        crate::drivers::page_cache::read(index, (position / SECTOR_SIZE) as u64, &mut sector)?;
+        buf[position - offset..position - offset + count].copy_from_slice(&sector[within..within + count]);
+        position += count;
+    }
+    Ok(end.saturating_sub(offset))
+}
+
+/// offset バイト目から buf を書く (セクタの一部なら読んでから書き換える)
+/// デバイスの終わりを越える分は書かないので、書いたバイト数を返す
+pub fn write_bytes(index: usize, offset: usize, buf: &[u8]) -> Result<usize, &'static str> {
+    let size = get(index).ok_or("No such block device")?.sector_count() as usize * SECTOR_SIZE;
+    let end = size.min(offset.saturating_add(buf.len()));
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut position = offset;
+    while position < end {
+        let lba = (position / SECTOR_SIZE) as u64;
+        let within = position % SECTOR_SIZE;
+        let count = (SECTOR_SIZE - within).min(end - position);
+        if count < SECTOR_SIZE {
+            crate::drivers::page_cache::read(index, lba, &mut sector)?;
+        }
+        sector[within..within + count].copy_from_slice(&buf[position - offset..position - offset + count]);
+        crate::drivers::page_cache::write(index, lba, &sector)?;
+        position += count;
+    }
+    Ok(end.saturating_sub(offset))
+}
+
+/// 登録済みのデバイスの一覧 (番号順)
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
@@ -0,0 +1,160 @@
+use alloc::string::String;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use super::{framebuffer, vga, vt};
+
+/// print! の出力先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Backend {
+    /// VGA テキストモード (0xb8000)
+    VgaText = 0,
+    /// リニアフレームバッファ上のテキストコンソール
+    Framebuffer = 1,
+    /// シリアルポート (COM1)
+    Serial = 2,
+}
+
+/// print! の出力をまとめて書き出す単位 (バイト)
+const BATCH_SIZE: usize = 256;
+
+static BACKEND: AtomicU8 = AtomicU8::new(Backend::VgaText as u8);
+// true ならタイマーティックでまとめて画面に反映する (false なら書くたびに反映)
+static DEFERRED_FLUSH: AtomicBool = AtomicBool::new(false);
+
+pub fn backend() -> Backend {
+    match BACKEND.load(Ordering::Relaxed) {
+        1 => Backend::Framebuffer,
+        2 => Backend::Serial,
+        _ => Backend::VgaText,
+    }
+}
+
+/// 出力先を切り替える (フレームバッファがなければ切り替えられない)
+pub fn set_backend(backend: Backend) -> Result<(), &'static str> {
+    if backend == Backend::Framebuffer && !framebuffer::is_available() {
+        return Err("No framebuffer");
+    }
+    BACKEND.store(backend as u8, Ordering::SeqCst);
+    flush();
+    Ok(())
+}
+
+/// 出力をシャドウバッファに溜め、タイマーティックごとに反映するようにする
+/// (タイマーの初期化後に呼ぶこと)
+pub fn enable_deferred_flush() {
+    DEFERRED_FLUSH.store(true, Ordering::SeqCst);
+}
+
+/// シャドウバッファの変更を画面に反映する
+pub fn flush() {
+    match backend() {
+        Backend::VgaText => vga::flush(),
+        Backend::Framebuffer => framebuffer::flush(),
+        Backend::Serial => {}
+    }
+}
+
+/// タイマー割り込みから呼ばれる
+pub fn tick() {
+    if DEFERRED_FLUSH.load(Ordering::Relaxed) {
+        flush();
+    }
+}
+
+/// グラフィックスモードで起動していればフレームバッファに切り替える
+/// (テキストモードなら VGA のまま)
+/// コマンドラインの console=serial / console=vga で出力先を指定できる
+pub fn init() {
+    // console= の指定がなければ設定の serial_console に従う
+    let requested = crate::cmdline::get("console").or_else(|| {
+        crate::config::enabled(crate::config::Feature::SerialConsole).then(|| String::from("serial"))
+    });
+    match requested.as_deref() {
+        Some("serial") => {
+            let _ = set_backend(Backend::Serial);
+            crate::info!("Console on serial port");
+            return;
+        }
+        Some("vga") => return,
+        Some(name) => crate::warn!("Unknown console: {}", name),
+        None => {}
+    }
+
+    match framebuffer::init() {
+        Ok((cols, rows)) => {
+            let _ = set_backend(Backend::Framebuffer);
+            crate::info!("Framebuffer console: {}x{}", cols, rows);
+        }
+        Err(e) => crate::debug!("Framebuffer unavailable: {}", e),
+    }
+}
+
+/// 仮想端末 vt へ書く (シリアルには端末の区別がないので、どの端末の出力もそのまま流す)
+fn write_backend(vt: usize, s: &str) {
+    match backend() {
+        Backend::VgaText => vga::write_str(vt, s),
+        Backend::Framebuffer => framebuffer::write_str(vt, s),
+        Backend::Serial => crate::serial::_print(format_args!("{}", s)),
+    }
+}
+
+/// 書式化した断片をスタック上のバッファに溜め、まとめて出力先に渡す
+/// (断片ごとに出力先のロックを取り直さないで済む)
+struct Writer {
+    vt: usize,
+    buf: [u8; BATCH_SIZE],
+    len: usize,
+}
+
+impl Writer {
+    fn new(vt: usize) -> Self {
+        Self { vt, buf: [0; BATCH_SIZE], len: 0 }
+    }
+
+    fn flush_batch(&mut self) {
+        // 断片は丸ごと入れているので、文字の途中で切れることはない
+        if let Ok(s) = core::str::from_utf8(&self.buf[..self.len]) {
+            write_backend(self.vt, s);
+        }
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.len + s.len() > BATCH_SIZE {
+            self.flush_batch();
+        }
+        if s.len() > BATCH_SIZE {
+            write_backend(self.vt, s);
+        } else {
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+        }
+        Ok(())
+    }
+}
+
+fn print_to(vt: usize, args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mut writer = Writer::new(vt);
+    let _ = writer.write_fmt(args); // エラーは握りつぶす（panic させない）
+    writer.flush_batch();
+
+    // 割り込み禁止中 (panic や例外ハンドラ) はティックが来ないので、すぐに反映する
+    if !DEFERRED_FLUSH.load(Ordering::Relaxed) || !x86_64::instructions::interrupts::are_enabled() {
+        flush();
+    }
+}
+
+/// ユーザープロセスの標準出力など、仮想端末 vt に書く
+pub fn write_vt(vt: usize, s: &str) {
+    print_to(vt, format_args!("{}", s));
+}
+
+/// print! の出力はカーネルの端末 (VT1) に出る
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    print_to(vt::KERNEL_VT, args);
+}
@@ -0,0 +1,311 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr::write_volatile;
+use spin::Mutex;
+use crate::boot::FramebufferInfo;
+use super::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::vt::{self, NR_VTS};
+
+/// 文字セルの高さ (8x8 のグリフを縦に2倍して表示する)
+const CELL_HEIGHT: usize = GLYPH_HEIGHT * 2;
+/// タブストップの間隔 (桁)
+const TAB_WIDTH: usize = 8;
+const BACKSPACE: u8 = 0x08;
+
+static FRAMEBUFFER: Mutex<Option<Framebuffer>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb(0, 0, 0);
+    pub const WHITE: Rgb = Rgb(0xff, 0xff, 0xff);
+}
+
+/// テキストコンソールの1文字分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    byte: u8,
+    fg: Rgb,
+    bg: Rgb,
+}
+
+/// 仮想端末1つ分のテキスト画面
+struct TextScreen {
+    col: usize,
+    row: usize,
+    cells: Vec<Cell>,
+}
+
+/// ブートローダーが設定したリニアフレームバッファとその上のテキストコンソール
+/// 文字は端末ごとの cells に書くだけで、flush で表示中の端末の画面 (shown) と違うセルだけを描き直す
+pub struct Framebuffer {
+    base: *mut u8,
+    info: FramebufferInfo,
+    bytes_per_pixel: usize,
+    cols: usize,
+    rows: usize,
+    fg: Rgb,
+    bg: Rgb,
+    screens: Vec<TextScreen>,
+    /// 画面に描かれている内容
+    shown: Vec<Cell>,
+    /// cells と shown が食い違っている可能性のある行
+    dirty: Vec<bool>,
+}
+
+// MMIO 領域はロック越しにしか触らない
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    /// 色をピクセル形式 (各色のビット位置と幅) に変換する
+    fn encode(&self, color: Rgb) -> u32 {
+        let channel = |value: u8, (pos, size): (u8, u8)| {
+            ((value as u32) >> (8 - size.min(8))) << pos
+        };
+        channel(color.0, self.info.red) | channel(color.1, self.info.green) | channel(color.2, self.info.blue)
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, value: u32) {
+        let offset = y * self.info.pitch as usize + x * self.bytes_per_pixel;
+        unsafe {
+            let ptr = self.base.add(offset);
+            if self.bytes_per_pixel == 4 {
+                write_volatile(ptr as *mut u32, value);
+            } else {
+                for i in 0..self.bytes_per_pixel {
+                    write_volatile(ptr.add(i), (value >> (i * 8)) as u8);
+                }
+            }
+        }
+    }
+
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x < self.info.width as usize && y < self.info.height as usize {
+            let value = self.encode(color);
+            self.write_pixel(x, y, value);
+        }
+    }
+
+    /// 画面外にはみ出した部分は切り詰める
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let value = self.encode(color);
+        let x_end = (x + width).min(self.info.width as usize);
+        let y_end = (y + height).min(self.info.height as usize);
+        for py in y..y_end {
+            for px in x..x_end {
+                self.write_pixel(px, py, value);
+            }
+        }
+    }
+
+    fn blank(&self) -> Cell {
+        Cell { byte: b' ', fg: self.fg, bg: self.bg }
+    }
+
+    /// 文字セル (col, row) に1文字描く
+    fn draw_cell(&mut self, col: usize, row: usize, cell: Cell) {
+        let fg = self.encode(cell.fg);
+        let bg = self.encode(cell.bg);
+        let glyph = font::glyph(cell.byte);
+        for y in 0..CELL_HEIGHT {
+            let bits = glyph[y / 2];
+            for x in 0..GLYPH_WIDTH {
+                let value = if bits & (1 << x) != 0 { fg } else { bg };
+                self.write_pixel(col * GLYPH_WIDTH + x, row * CELL_HEIGHT + y, value);
+            }
+        }
+    }
+
+    /// 端末 vt の画面の行が変わった (表示中の端末なら描き直す)
+    fn mark_dirty(&mut self, vt: usize, row: usize) {
+        if vt == vt::active() {
+            self.dirty[row] = true;
+        }
+    }
+
+    /// 1行分上にずらし、最下行を空白にする
+    /// VRAM からの読み出しは遅いので画面はコピーせず、flush で変わったセルだけ描き直す
+    fn scroll_up(&mut self, vt: usize) {
+        let cols = self.cols;
+        let blank = self.blank();
+        let cells = &mut self.screens[vt].cells;
+        cells.copy_within(cols.., 0);
+        let len = cells.len();
+        cells[len - cols..].fill(blank);
+        if vt == vt::active() {
+            self.dirty.fill(true);
+        }
+    }
+
+    /// 表示中の端末の cells のうち画面と違うセルを描く
+    pub fn flush(&mut self) {
+        let vt = vt::active();
+        for row in 0..self.rows {
+            if !core::mem::replace(&mut self.dirty[row], false) {
+                continue;
+            }
+            for col in 0..self.cols {
+                let i = row * self.cols + col;
+                let cell = self.screens[vt].cells[i];
+                if self.shown[i] != cell {
+                    self.draw_cell(col, row, cell);
+                    self.shown[i] = cell;
+                }
+            }
+        }
+    }
+
+    fn new_line(&mut self, vt: usize) {
+        let screen = &mut self.screens[vt];
+        screen.col = 0;
+        if screen.row < self.rows - 1 {
+            screen.row += 1;
+        } else {
+            self.scroll_up(vt);
+        }
+    }
+
+    fn write_byte(&mut self, vt: usize, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(vt),
+            b'\r' => self.screens[vt].col = 0,
+            // 次のタブストップまでカーソルを進める (行末を越えたら次の文字で折り返す)
+            b'\t' => {
+                let screen = &mut self.screens[vt];
+                screen.col = ((screen.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols);
+            }
+            // 1つ前の桁に戻って空白にする (行頭では何もしない)
+            BACKSPACE => {
+                let blank = self.blank();
+                let screen = &mut self.screens[vt];
+                if screen.col > 0 {
+                    screen.col -= 1;
+                    let row = screen.row;
+                    screen.cells[row * self.cols + screen.col] = blank;
+                    self.mark_dirty(vt, row);
+                }
+            }
+            byte => {
+                if self.screens[vt].col >= self.cols {
+                    self.new_line(vt);
+                }
+                let cell = Cell { byte, fg: self.fg, bg: self.bg };
+                let screen = &mut self.screens[vt];
+                let row = screen.row;
+                screen.cells[row * self.cols + screen.col] = cell;
+                screen.col += 1;
+                self.mark_dirty(vt, row);
+            }
+        }
+    }
+
+    /// 表示中の端末の画面を背景色で塗りつぶす (その場で描く)
+    pub fn clear(&mut self) {
+        let bg = self.bg;
+        self.fill_rect(0, 0, self.info.width as usize, self.info.height as usize, bg);
+        let blank = self.blank();
+        let screen = &mut self.screens[vt::active()];
+        screen.cells.fill(blank);
+        screen.col = 0;
+        screen.row = 0;
+        self.shown.fill(blank);
+        self.dirty.fill(false);
+    }
+}
+
+/// ブートローダーが RGB のフレームバッファを用意していればマップする
+/// テキストコンソールの (桁数, 行数) を返す (メモリ管理の初期化後に呼ぶこと)
+pub fn init() -> Result<(usize, usize), &'static str> {
+    let info = crate::boot::framebuffer().ok_or("No linear framebuffer")?;
+    let bytes_per_pixel = (info.bpp as usize).div_ceil(8);
+    if !(2..=4).contains(&bytes_per_pixel) {
+        return Err("Unsupported framebuffer depth");
+    }
+
+    let size = info.pitch as usize * info.height as usize;
+    let base = crate::memory::map_mmio(info.addr, size)?.as_mut_ptr::<u8>();
+
+    let cols = info.width as usize / GLYPH_WIDTH;
+    let rows = info.height as usize / CELL_HEIGHT;
+    if cols == 0 || rows == 0 {
+        return Err("Framebuffer too small");
+    }
+    let blank = Cell { byte: b' ', fg: Rgb::WHITE, bg: Rgb::BLACK };
+    let screens = (0..NR_VTS)
+        .map(|_| TextScreen { col: 0, row: 0, cells: vec![blank; cols * rows] })
+        .collect();
+    let mut framebuffer = Framebuffer {
+        base,
+        info,
+        bytes_per_pixel,
+        cols,
+        rows,
+        fg: Rgb::WHITE,
+        bg: Rgb::BLACK,
+        screens,
+        shown: vec![blank; cols * rows],
+        dirty: vec![false; rows],
+    };
+    framebuffer.clear();
+
+    let size = (framebuffer.cols, framebuffer.rows);
+    *FRAMEBUFFER.lock() = Some(framebuffer);
+    Ok(size)
+}
+
+pub fn is_available() -> bool {
+    FRAMEBUFFER.lock().is_some()
+}
+
+/// 画面の解像度 (幅, 高さ)
+pub fn resolution() -> Option<(usize, usize)> {
+    FRAMEBUFFER.lock().as_ref().map(|fb| (fb.info.width as usize, fb.info.height as usize))
+}
+
+fn with_framebuffer(f: impl FnOnce(&mut Framebuffer)) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if let Some(framebuffer) = FRAMEBUFFER.lock().as_mut() {
+            f(framebuffer);
+        }
+    });
+}
+
+pub fn put_pixel(x: usize, y: usize, color: Rgb) {
+    with_framebuffer(|fb| fb.put_pixel(x, y, color));
+}
+
+pub fn fill_rect(x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+    with_framebuffer(|fb| fb.fill_rect(x, y, width, height, color));
+}
+
+pub fn clear() {
+    with_framebuffer(|fb| fb.clear());
+}
+
+pub fn set_colors(fg: Rgb, bg: Rgb) {
+    with_framebuffer(|fb| {
+        fb.fg = fg;
+        fb.bg = bg;
+    });
+}
+
+/// テキストコンソールの変更を画面に反映する
+pub fn flush() {
+    with_framebuffer(|fb| fb.flush());
+}
+
+/// 表示する端末が変わったので、次の flush で全行を描き直す
+pub fn redraw() {
+    with_framebuffer(|fb| fb.dirty.fill(true));
+}
+
+/// 端末 vt のテキストコンソールに文字列を書く (VGA と同じく表示できない文字は ■)
+/// 画面に出るのは flush したとき
+pub fn write_str(vt: usize, s: &str) {
+    with_framebuffer(|fb| {
+        for byte in s.bytes() {
+            fb.write_byte(vt, byte);
+        }
+    });
+}
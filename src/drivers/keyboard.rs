@@ -1,49 +1,130 @@
 use spin::Mutex;
-use alloc::collections::VecDeque;
+use crate::sync::IrqMutex;
 use x86_64::instructions::port::Port;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
+use super::vt;
 
-const KEYBOARD_BUFFER_SIZE: usize = 256;
+/// デコード待ちのスキャンコードを溜めておく数
+const SCANCODE_QUEUE_SIZE: usize = 64;
 
-static KEYBOARD: Mutex<Option<KeyboardDriver>> = Mutex::new(None);
+static KEYBOARD: IrqMutex<Option<KeyboardDriver>> = IrqMutex::new(None);
+// 割り込みハンドラが読んだスキャンコード (デコードは後半処理で行う)
+static SCANCODES: Mutex<ScancodeQueue> = Mutex::new(ScancodeQueue::new());
+
+/// 固定長のスキャンコードのリングバッファ (割り込み中に確保しない)
+struct ScancodeQueue {
+    codes: [u8; SCANCODE_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl ScancodeQueue {
+    const fn new() -> Self {
+        Self { codes: [0; SCANCODE_QUEUE_SIZE], head: 0, len: 0 }
+    }
+
+    /// 一杯なら捨てる
+    fn push(&mut self, scancode: u8) {
+        if self.len < SCANCODE_QUEUE_SIZE {
+            self.codes[(self.head + self.len) % SCANCODE_QUEUE_SIZE] = scancode;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let scancode = self.codes[self.head];
+        self.head = (self.head + 1) % SCANCODE_QUEUE_SIZE;
+        self.len -= 1;
+        Some(scancode)
+    }
+}
+
+/// キー配列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// US 104 キー
+    Us104,
+    /// 日本語 106/109 キー
+    Jp106,
+}
+
+impl Layout {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us104 => "us104",
+            Layout::Jp106 => "jp106",
+        }
+    }
+
+    /// "us" / "jp" などの名前から配列を選ぶ
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "us" | "us104" => Some(Layout::Us104),
+            "jp" | "jp106" | "jp109" => Some(Layout::Jp106),
+            _ => None,
+        }
+    }
+
+    fn to_any(self) -> layouts::AnyLayout {
+        match self {
+            Layout::Us104 => layouts::AnyLayout::Us104Key(layouts::Us104Key),
+            Layout::Jp106 => layouts::AnyLayout::Jis109Key(layouts::Jis109Key),
+        }
+    }
+}
 
 pub struct KeyboardDriver {
-    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
-    buffer: VecDeque<u8>,
+    layout: Layout,
+    keyboard: Keyboard<layouts::AnyLayout, ScancodeSet1>,
+    /// Alt キーが押されている (pc_keyboard は左 Alt を覚えていない)
+    alt: bool,
 }
 
 impl KeyboardDriver {
-    fn new() -> Self {
+    fn new(layout: Layout) -> Self {
         Self {
+            layout,
             keyboard: Keyboard::new(
                 ScancodeSet1::new(),
-                layouts::Us104Key,
+                layout.to_any(),
                 HandleControl::Ignore,
             ),
-            buffer: VecDeque::with_capacity(KEYBOARD_BUFFER_SIZE),
+            alt: false,
         }
     }
 
-    fn add_byte(&mut self, byte: u8) {
-        if self.buffer.len() < KEYBOARD_BUFFER_SIZE {
-            self.buffer.push_back(byte);
-        }
-    }
-
-    fn read_byte(&mut self) -> Option<u8> {
-        self.buffer.pop_front()
+    /// 配列を切り替える (修飾キーの状態はリセットされる)
+    fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        self.keyboard = Keyboard::new(ScancodeSet1::new(), layout.to_any(), HandleControl::Ignore);
     }
 
     fn process_scancode(&mut self, scancode: u8) {
         if let Ok(Some(key_event)) = self.keyboard.add_byte(scancode) {
+            if matches!(key_event.code, KeyCode::LAlt | KeyCode::RAltGr) {
+                self.alt = key_event.state != KeyState::Up;
+            }
+            // Alt+F1..F4 で仮想端末を切り替える
+            if self.alt && key_event.state == KeyState::Down {
+                if let Some(vt) = vt_key(key_event.code) {
+                    let _ = vt::switch(vt);
+                    return;
+                }
+            }
             if let Some(key) = self.keyboard.process_keyevent(key_event) {
                 match key {
                     DecodedKey::Unicode(character) => {
-                        self.add_byte(character as u8);
+                        // JP 配列の ¥ など ASCII 以外の文字は UTF-8 で渡す
+                        let mut utf8 = [0u8; 4];
+                        vt::push_input(character.encode_utf8(&mut utf8).as_bytes());
+                        crate::events::publish(crate::events::Event::Key(character));
                     }
                     DecodedKey::RawKey(key) => {
                         // 特殊キーの処理
-                        crate::println!("Raw key: {:?}", key);
+                        crate::debug_ratelimited!("Raw key: {:?}", key);
                     }
                 }
             }
@@ -51,44 +132,67 @@ impl KeyboardDriver {
     }
 }
 
+/// ファンクションキーに対応する仮想端末
+fn vt_key(key: KeyCode) -> Option<usize> {
+    match key {
+        KeyCode::F1 => Some(0),
+        KeyCode::F2 => Some(1),
+        KeyCode::F3 => Some(2),
+        KeyCode::F4 => Some(3),
+        _ => None,
+    }
+}
+
+/// コマンドラインの keymap=jp106 などで配列を選ぶ (既定は US)
+fn boot_layout() -> Layout {
+    match crate::cmdline::get("keymap") {
+        Some(name) => Layout::from_name(&name).unwrap_or_else(|| {
+            crate::warn!("Unknown keymap: {}", name);
+            Layout::Us104
+        }),
+        None => Layout::Us104,
+    }
+}
+
 pub fn init() {
-    *KEYBOARD.lock() = Some(KeyboardDriver::new());
+    let layout = boot_layout();
+    *KEYBOARD.lock() = Some(KeyboardDriver::new(layout));
+    crate::softirq::open(crate::softirq::Softirq::Keyboard, keyboard_softirq);
+    crate::info!("Keyboard layout: {}", layout.name());
+}
+
+/// 実行中に配列を切り替える
+pub fn set_layout(layout: Layout) {
+    if let Some(keyboard) = KEYBOARD.lock().as_mut() {
+        keyboard.set_layout(layout);
+    }
+}
+
+pub fn layout() -> Option<Layout> {
+    KEYBOARD.lock().as_ref().map(|keyboard| keyboard.layout)
 }
 
 /// 割り込みハンドラから呼び出される
 pub fn handle_interrupt() {
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
-
-    if let Some(keyboard) = KEYBOARD.lock().as_mut() {
-        keyboard.process_scancode(scancode);
-    }
+    SCANCODES.lock().push(scancode);
+    crate::rand::add_interrupt_randomness(scancode as u64);
 
     // 割り込みコントローラに通知
-    unsafe {
-        Port::<u8>::new(0x20).write(0x20);
-    }
+    crate::interrupts::end_of_interrupt(crate::interrupts::InterruptIndex::Keyboard);
+
+    crate::softirq::raise(crate::softirq::Softirq::Keyboard);
+    crate::softirq::irq_exit();
 }
 
-pub fn read_bytes(buf: &mut [u8]) -> usize {
+/// 溜まったスキャンコードをデコードして表示中の仮想端末の入力に入れる
+fn keyboard_softirq() {
     let mut keyboard = KEYBOARD.lock();
-    if let Some(keyboard) = keyboard.as_mut() {
-        let mut count = 0;
-        while count < buf.len() {
-            if let Some(byte) = keyboard.read_byte() {
-                buf[count] = byte;
-                count += 1;
-            } else {
-                break;
-            }
+    let mut scancodes = SCANCODES.lock();
+    while let Some(scancode) = scancodes.pop() {
+        if let Some(keyboard) = keyboard.as_mut() {
+            keyboard.process_scancode(scancode);
         }
-        count
-    } else {
-        0
     }
 }
-
-pub fn has_data() -> bool {
-    let keyboard = KEYBOARD.lock();
-    keyboard.as_ref().map_or(false, |k| !k.buffer.is_empty())
-}
@@ -1,9 +1,124 @@
 pub mod vga;
+pub mod font;
+pub mod framebuffer;
+pub mod console;
+pub mod vt;
 pub mod keyboard;
 pub mod timer;
+pub mod rtc;
+pub mod mouse;
+pub mod pci;
+pub mod block;
+pub mod partition;
+pub mod page_cache;
+pub mod ramdisk;
+pub mod virtio;
+pub mod virtio_blk;
+pub mod ahci;
+pub mod virtio_net;
+pub mod registry;
 
+use crate::errno::Errno;
+
+/// /dev 以下のキャラクタデバイスノードが指すデバイス番号
+pub const DEV_MOUSE: u32 = 1;
+/// /dev/random と /dev/urandom (どちらもブロックしない)
+pub const DEV_RANDOM: u32 = 2;
+/// 仮想端末のノード (/dev/tty1..) は DEV_TTY_BASE + 端末の番号 (読み込みはブロックしない)
+pub const DEV_TTY_BASE: u32 = 0x10;
+/// ブロックデバイスのノード (/dev/ram0 など) は DEV_BLOCK_BASE + ブロックデバイスの番号
+pub const DEV_BLOCK_BASE: u32 = 0x100;
+
+/// 仮想端末の番号に対応するデバイス番号
+pub fn tty_node(vt: usize) -> u32 {
+    DEV_TTY_BASE + vt as u32
+}
+
+/// 仮想端末のノードなら、端末の番号
+fn tty_index(rdev: u32) -> Option<usize> {
+    rdev.checked_sub(DEV_TTY_BASE).map(|vt| vt as usize).filter(|&vt| vt < vt::NR_VTS)
+}
+
+/// ブロックデバイスの番号に対応するデバイス番号
+pub fn block_node(index: usize) -> u32 {
+    DEV_BLOCK_BASE + index as u32
+}
+
+/// ブロックデバイスのノードなら、ブロックデバイスの番号
+pub fn block_index(rdev: u32) -> Option<usize> {
+    rdev.checked_sub(DEV_BLOCK_BASE).map(|index| index as usize)
+}
+
+/// 組み込みのドライバを登録し、依存順に初期化する
 pub fn init() {
-    vga::init();
-    keyboard::init();
-    timer::init();
+    registry::register("vga", &[], || {
+        vga::init();
+        Ok(())
+    });
+    registry::register("console", &["vga"], || {
+        console::init();
+        Ok(())
+    });
+    registry::register("keyboard", &[], || {
+        keyboard::init();
+        Ok(())
+    });
+    // コンソールのシャドウバッファはタイマーティックで反映する
+    registry::register("timer", &["console"], || {
+        timer::init();
+        console::enable_deferred_flush();
+        Ok(())
+    });
+    registry::register("pci", &[], || {
+        pci::init();
+        Ok(())
+    });
+    // マウスはキーボードと同じ 8042 コントローラにつながっている
+    registry::register("mouse", &["keyboard"], mouse::init);
+    registry::register("virtio-blk", &["pci"], virtio_blk::init);
+    registry::register("ahci", &["pci"], ahci::init);
+    registry::register("virtio-net", &["pci"], virtio_net::init);
+    registry::register("ramdisk", &[], ramdisk::init);
+    registry::init_all();
+}
+
+/// ドライバの一覧を表示する (lsdrv)
+pub fn print_inventory() {
+    crate::println!("{:<12} {:<24} {}", "DRIVER", "DEPENDS", "STATUS");
+    for driver in registry::inventory() {
+        crate::println!("{:<12} {:<24} {}", driver.name, driver.depends.join(","), driver.status);
+    }
+}
+
+/// デバイスノードからの読み込みを各ドライバに振り分ける
+pub fn device_read(rdev: u32, buf: &mut [u8]) -> Result<usize, Errno> {
+    match rdev {
+        DEV_MOUSE => Ok(mouse::read_bytes(buf)),
+        DEV_RANDOM => {
+            crate::rand::fill(buf);
+            Ok(buf.len())
+        }
+        _ => match tty_index(rdev) {
+            Some(vt) => Ok(vt::try_read(vt, buf)),
+            None => Err(Errno::ENODEV),
+        },
+    }
+}
+
+pub fn device_write(rdev: u32, buf: &[u8]) -> Result<usize, Errno> {
+    match rdev {
+        DEV_MOUSE => Err(Errno::EINVAL),
+        // 書き込まれたデータはプールに混ぜる
+        DEV_RANDOM => {
+            crate::rand::add_randomness(buf);
+            Ok(buf.len())
+        }
+        _ => match tty_index(rdev) {
+            Some(vt) => {
+                console::write_vt(vt, core::str::from_utf8(buf).map_err(|_| Errno::EINVAL)?);
+                Ok(buf.len())
+            }
+            None => Err(Errno::ENODEV),
+        },
+    }
 }
@@ -0,0 +1,195 @@
+use spin::Mutex;
+use alloc::collections::VecDeque;
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64; // 読み出し: ステータス、書き込み: コマンド
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const EVENT_QUEUE_SIZE: usize = 128;
+/// /dev/mouse から読み出す1イベントのバイト数
+pub const EVENT_SIZE: usize = 5;
+
+pub const BUTTON_LEFT: u8 = 1 << 0;
+pub const BUTTON_RIGHT: u8 = 1 << 1;
+pub const BUTTON_MIDDLE: u8 = 1 << 2;
+
+static MOUSE: Mutex<Option<MouseDriver>> = Mutex::new(None);
+
+/// 1パケット分の移動量とボタン状態
+/// dy は画面座標に合わせて下向きを正にしてある
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub buttons: u8,
+}
+
+impl MouseEvent {
+    /// dx (LE 2バイト), dy (LE 2バイト), buttons (1バイト)
+    pub fn to_bytes(&self) -> [u8; EVENT_SIZE] {
+        let dx = self.dx.to_le_bytes();
+        let dy = self.dy.to_le_bytes();
+        [dx[0], dx[1], dy[0], dy[1], self.buttons]
+    }
+}
+
+pub struct MouseDriver {
+    packet: [u8; 3],
+    cycle: usize,
+    events: VecDeque<MouseEvent>,
+}
+
+impl MouseDriver {
+    fn new() -> Self {
+        Self {
+            packet: [0; 3],
+            cycle: 0,
+            events: VecDeque::with_capacity(EVENT_QUEUE_SIZE),
+        }
+    }
+
+    fn process_byte(&mut self, byte: u8) {
+        // 先頭バイトは bit3 が常に立っているので、ずれたら読み直す
+        if self.cycle == 0 && byte & 0x08 == 0 {
+            return;
+        }
+        self.packet[self.cycle] = byte;
+        self.cycle += 1;
+        if self.cycle < 3 {
+            return;
+        }
+        self.cycle = 0;
+
+        let flags = self.packet[0];
+        // オーバーフローしたパケットは捨てる
+        if flags & 0xC0 != 0 {
+            return;
+        }
+
+        // 移動量は9ビットの2の補数 (符号ビットは先頭バイト)
+        let mut dx = self.packet[1] as i16;
+        if flags & 0x10 != 0 {
+            dx -= 0x100;
+        }
+        let mut dy = self.packet[2] as i16;
+        if flags & 0x20 != 0 {
+            dy -= 0x100;
+        }
+
+        let event = MouseEvent {
+            dx,
+            dy: -dy,
+            buttons: flags & (BUTTON_LEFT | BUTTON_RIGHT | BUTTON_MIDDLE),
+        };
+        if self.events.len() >= EVENT_QUEUE_SIZE {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        crate::events::publish(crate::events::Event::Mouse(event));
+    }
+}
+
+fn wait_write() -> bool {
+    let mut status = Port::<u8>::new(STATUS_PORT);
+    for _ in 0..100_000 {
+        if unsafe { status.read() } & STATUS_INPUT_FULL == 0 {
+            return true;
+        }
+    }
+    false
+}
+
+fn wait_read() -> bool {
+    let mut status = Port::<u8>::new(STATUS_PORT);
+    for _ in 0..100_000 {
+        if unsafe { status.read() } & STATUS_OUTPUT_FULL != 0 {
+            return true;
+        }
+    }
+    false
+}
+
+fn controller_command(command: u8) {
+    wait_write();
+    unsafe { Port::<u8>::new(STATUS_PORT).write(command) };
+}
+
+/// 補助デバイス (マウス) にコマンドを送り、ACK (0xFA) を確認する
+fn mouse_command(command: u8) -> Result<(), &'static str> {
+    controller_command(0xD4);
+    wait_write();
+    unsafe { Port::<u8>::new(DATA_PORT).write(command) };
+    if !wait_read() {
+        return Err("Mouse did not respond");
+    }
+    match unsafe { Port::<u8>::new(DATA_PORT).read() } {
+        0xFA => Ok(()),
+        _ => Err("Mouse did not acknowledge"),
+    }
+}
+
+pub fn init() -> Result<(), &'static str> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // 補助デバイスを有効化
+        controller_command(0xA8);
+
+        // コントローラ設定バイトで IRQ12 を有効にし、マウスクロックを有効にする
+        controller_command(0x20);
+        if !wait_read() {
+            return Err("PS/2 controller did not respond");
+        }
+        let config = unsafe { Port::<u8>::new(DATA_PORT).read() };
+        controller_command(0x60);
+        wait_write();
+        unsafe { Port::<u8>::new(DATA_PORT).write((config | 0x02) & !0x20) };
+
+        // 既定値に戻してからストリーミングを開始
+        mouse_command(0xF6)?;
+        mouse_command(0xF4)
+    })?;
+
+    *MOUSE.lock() = Some(MouseDriver::new());
+    crate::interrupts::enable_irq(crate::interrupts::InterruptIndex::Mouse);
+    crate::info!("PS/2 mouse initialized");
+    Ok(())
+}
+
+/// 割り込みハンドラから呼び出される
+pub fn handle_interrupt() {
+    let byte: u8 = unsafe { Port::new(DATA_PORT).read() };
+
+    if let Some(mouse) = MOUSE.lock().as_mut() {
+        mouse.process_byte(byte);
+    }
+
+    // 割り込みコントローラに通知
+    crate::interrupts::end_of_interrupt(crate::interrupts::InterruptIndex::Mouse);
+}
+
+pub fn read_event() -> Option<MouseEvent> {
+    MOUSE.lock().as_mut()?.events.pop_front()
+}
+
+/// 溜まっているイベントを buf に入るだけ書き出す (/dev/mouse)
+pub fn read_bytes(buf: &mut [u8]) -> usize {
+    let mut mouse = MOUSE.lock();
+    let mouse = match mouse.as_mut() {
+        Some(mouse) => mouse,
+        None => return 0,
+    };
+
+    let mut count = 0;
+    while count + EVENT_SIZE <= buf.len() {
+        match mouse.events.pop_front() {
+            Some(event) => {
+                buf[count..count + EVENT_SIZE].copy_from_slice(&event.to_bytes());
+                count += EVENT_SIZE;
+            }
+            None => break,
+        }
+    }
+    count
+}
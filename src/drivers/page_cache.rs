@@ -0,0 +1,265 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+
+/// キャッシュの単位 (8 セクタ)
+pub const PAGE_SIZE: usize = 4096;
+const SECTORS_PER_PAGE: u64 = (PAGE_SIZE / SECTOR_SIZE) as u64;
+/// キャッシュしておくページ数の上限 (1 MiB)
+const CAPACITY: usize = 256;
+/// 書き戻しスレッドが汚れたページを書き出す間隔
+const FLUSH_INTERVAL_MS: usize = 5000;
+
+/// (ブロックデバイス番号, ページ番号)
+type Key = (usize, u64);
+
+static CACHE: Mutex<PageCache> = Mutex::new(PageCache::new());
+
+struct CachedPage {
+    data: Vec<u8>,
+    /// デバイスにまだ書いていない変更がある
+    dirty: bool,
+    /// 最後に使ったときの PageCache::clock (LRU の順序)
+    last_used: u64,
+}
+
+/// キャッシュの統計
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub pages: usize,
+    pub dirty_pages: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub writebacks: u64,
+}
+
+struct PageCache {
+    pages: BTreeMap<Key, CachedPage>,
+    clock: u64,
+    stats: Stats,
+}
+
+impl PageCache {
+    const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            clock: 0,
+            stats: Stats { pages: 0, dirty_pages: 0, hits: 0, misses: 0, writebacks: 0 },
+        }
+    }
+
+    fn touch(&mut self, key: Key) -> Option<&mut CachedPage> {
+        self.clock += 1;
+        let clock = self.clock;
+        let page = self.pages.get_mut(&key)?;
+        page.last_used = clock;
+        Some(page)
+    }
+
+    /// ページを加え、溢れたら最も古いページを追い出す
+    /// 追い出したページが汚れていれば、書き戻せるよう返す
+    /// (同じページが先に入っていれば、そちらを優先して data は捨てる)
+    fn insert(&mut self, key: Key, data: Vec<u8>) -> Option<(Key, Vec<u8>)> {
+        self.clock += 1;
+        if self.pages.contains_key(&key) {
+            return None;
+        }
+        let mut evicted = None;
+        if self.pages.len() >= CAPACITY {
+            let oldest = self.pages.iter()
+                .min_by_key(|(_, page)| page.last_used)
+                .map(|(&key, _)| key);
+            if let Some(oldest) = oldest {
+                let page = self.pages.remove(&oldest).unwrap();
+                if page.dirty {
+                    evicted = Some((oldest, page.data));
+                }
+            }
+        }
+        self.pages.insert(key, CachedPage { data, dirty: false, last_used: self.clock });
+        evicted
+    }
+}
+
+/// ページの内容をデバイスから読む (デバイスの末尾を越える部分はゼロ)
+fn read_page(device: &Arc<dyn BlockDevice>, page: u64) -> Result<Vec<u8>, &'static str> {
+    let lba = page * SECTORS_PER_PAGE;
+    let sectors = device.sector_count().saturating_sub(lba).min(SECTORS_PER_PAGE) as usize;
+    if sectors == 0 {
+        return Err("Sector out of range");
+    }
+    let mut data = vec![0u8; PAGE_SIZE];
+    device.read_sectors(lba, &mut data[..sectors * SECTOR_SIZE])?;
+    Ok(data)
+}
+
+/// ページの内容をデバイスへ書く (デバイスの末尾を越える部分は捨てる)
+fn write_page(key: Key, data: &[u8]) -> Result<(), &'static str> {
+    let device = block::get(key.0).ok_or("No such block device")?;
+    let lba = key.1 * SECTORS_PER_PAGE;
+    let sectors = device.sector_count().saturating_sub(lba).min(SECTORS_PER_PAGE) as usize;
+    device.write_sectors(lba, &data[..sectors * SECTOR_SIZE])?;
+    CACHE.lock().stats.writebacks += 1;
+    Ok(())
+}
+
+/// 追い出されたページを書き戻す (キャッシュのロックを外してから呼ぶ)
+fn write_evicted(evicted: Option<(Key, Vec<u8>)>) -> Result<(), &'static str> {
+    match evicted {
+        Some((key, data)) => write_page(key, &data),
+        None => Ok(()),
+    }
+}
+
+/// キャッシュにあればそのページに f を適用し、なければデバイスから読み込んでから適用する
+/// デバイスの読み書きはキャッシュのロックを外して行う
+fn with_page<R>(device_index: usize, page: u64, f: impl Fn(&mut CachedPage) -> R) -> Result<R, &'static str> {
+    {
+        let mut cache = CACHE.lock();
+        if let Some(cached) = cache.touch((device_index, page)) {
+            let result = f(cached);
+            cache.stats.hits += 1;
+            return Ok(result);
+        }
+        cache.stats.misses += 1;
+    }
+
+    let device = block::get(device_index).ok_or("No such block device")?;
+    let data = read_page(&device, page)?;
+    let (result, evicted) = {
+        let mut cache = CACHE.lock();
+        let evicted = cache.insert((device_index, page), data);
+        let cached = cache.touch((device_index, page)).unwrap();
+        (f(cached), evicted)
+    };
+    write_evicted(evicted)?;
+    Ok(result)
+}
+
+/// lba から始まる count セクタがデバイスに収まっているか
+fn check_range(device: usize, lba: u64, count: usize) -> Result<(), &'static str> {
+    let sectors = block::get(device).ok_or("No such block device")?.sector_count();
+    match lba.checked_add(count as u64) {
+        Some(end) if end <= sectors => Ok(()),
+        _ => Err("Sector out of range"),
+    }
+}
+
+/// lba から buf.len() / SECTOR_SIZE セクタをキャッシュ越しに読む
+pub fn read(device: usize, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+    check_range(device, lba, buf.len() / SECTOR_SIZE)?;
+    for (i, sector) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
+        let lba = lba + i as u64;
+        let offset = (lba % SECTORS_PER_PAGE) as usize * SECTOR_SIZE;
+        let data = with_page(device, lba / SECTORS_PER_PAGE, |page| {
+            let mut data = [0u8; SECTOR_SIZE];
+            data.copy_from_slice(&page.data[offset..offset + SECTOR_SIZE]);
+            data
+        })?;
+        sector.copy_from_slice(&data[..sector.len()]);
+    }
+    Ok(())
+}
+
+/// lba から buf.len() / SECTOR_SIZE セクタをキャッシュに書く
+/// デバイスへは sync か書き戻しスレッド、追い出し時に書かれる
+pub fn write(device: usize, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+    check_range(device, lba, buf.len() / SECTOR_SIZE)?;
+    for (i, sector) in buf.chunks(SECTOR_SIZE).enumerate() {
+        let lba = lba + i as u64;
+        let offset = (lba % SECTORS_PER_PAGE) as usize * SECTOR_SIZE;
+        with_page(device, lba / SECTORS_PER_PAGE, |page| {
+            page.data[offset..offset + sector.len()].copy_from_slice(sector);
+            page.dirty = true;
+        })?;
+    }
+    Ok(())
+}
+
+/// 汚れたページをすべてデバイスへ書き、書いたページ数を返す
+/// 書いている間に変更されたページは汚れたまま残る
+pub fn sync() -> Result<usize, &'static str> {
+    let dirty: Vec<(Key, Vec<u8>)> = {
+        let mut cache = CACHE.lock();
+        cache.pages.iter_mut()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&key, page)| {
+                page.dirty = false;
+                (key, page.data.clone())
+            })
+            .collect()
+    };
+
+    let mut result = Ok(dirty.len());
+    for (key, data) in &dirty {
+        if let Err(e) = write_page(*key, data) {
+            crate::warn!("page cache: writeback of block {} page {} failed: {}", key.0, key.1, e);
+            // 書けなかったページは次の sync でもう一度試す
+            if let Some(page) = CACHE.lock().pages.get_mut(key) {
+                page.dirty = true;
+            }
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// デバイスのページをキャッシュから捨てる (汚れたページは先に書き戻す)
+pub fn invalidate(device: usize) -> Result<(), &'static str> {
+    sync()?;
+    CACHE.lock().pages.retain(|&(index, _), page| index != device || page.dirty);
+    Ok(())
+}
+
+pub fn stats() -> Stats {
+    let cache = CACHE.lock();
+    Stats {
+        pages: cache.pages.len(),
+        dirty_pages: cache.pages.values().filter(|page| page.dirty).count(),
+        ..cache.stats
+    }
+}
+
+/// 汚れたページを定期的に書き戻すカーネルスレッドを起動する (プロセス管理の初期化後に呼ぶ)
+pub fn start_flusher() {
+    crate::kthread::spawn("bdflush", || loop {
+        crate::drivers::timer::sleep_ms(FLUSH_INTERVAL_MS);
+        if let Err(e) = sync() {
+            crate::debug!("bdflush: {}", e);
+        }
+    });
+}
+
+/// キャッシュ越しにアクセスするブロックデバイス
+/// ディスクを使うファイルシステムは block::get の代わりにこれを使う
+pub struct CachedDevice {
+    index: usize,
+    device: Arc<dyn BlockDevice>,
+}
+
+impl BlockDevice for CachedDevice {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.device.sector_count()
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        read(self.index, lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        write(self.index, lba, buf)
+    }
+}
+
+/// 登録済みのブロックデバイスをキャッシュ越しに使う
+pub fn cached(index: usize) -> Option<Arc<dyn BlockDevice>> {
+    let device = block::get(index)?;
+    Some(Arc::new(CachedDevice { index, device }))
+}
@@ -0,0 +1,230 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+
+pub const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+// MBR のパーティション種別
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_EXTENDED_CHS: u8 = 0x05;
+const MBR_TYPE_EXTENDED_LBA: u8 = 0x0F;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+/// 論理パーティションの連鎖をたどる上限 (壊れたテーブルでループしないように)
+const MAX_LOGICAL_PARTITIONS: usize = 64;
+
+pub const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// 読み込むパーティションエントリ数の上限 (標準は 128)
+const GPT_MAX_ENTRIES: usize = 256;
+
+/// ディスク上の区間を1つのブロックデバイスとして見せる
+pub struct Partition {
+    name: String,
+    parent: Arc<dyn BlockDevice>,
+    start: u64,
+    sectors: u64,
+    /// MBR の種別 ("mbr 0x83") か GPT のパーティション名
+    kind: String,
+}
+
+impl Partition {
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    fn check(&self, lba: u64, len: usize) -> Result<u64, &'static str> {
+        match lba.checked_add((len / SECTOR_SIZE) as u64) {
+            Some(end) if end <= self.sectors => Ok(self.start + lba),
+            _ => Err("Sector out of range"),
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.parent.read_sectors(self.check(lba, buf.len())?, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.parent.write_sectors(self.check(lba, buf.len())?, buf)
+    }
+}
+
+/// パーティションテーブルから読み取った区間 (ディスク先頭からのセクタ)
+struct Entry {
+    number: usize,
+    start: u64,
+    sectors: u64,
+    kind: String,
+}
+
+fn read_sector(device: &Arc<dyn BlockDevice>, lba: u64) -> Result<Vec<u8>, &'static str> {
+    let mut sector = vec![0u8; SECTOR_SIZE];
+    device.read_sectors(lba, &mut sector)?;
+    Ok(sector)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// CRC-32 (IEEE 802.3)。GPT のヘッダとエントリ配列や ramfs のスナップショットの検査に使う
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+/// MBR の4つのエントリ (種別, 開始 LBA, セクタ数)
+fn mbr_entries(mbr: &[u8]) -> impl Iterator<Item = (u8, u64, u64)> + '_ {
+    (0..4).map(move |i| {
+        let entry = &mbr[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        (entry[4], u32_at(entry, 8) as u64, u32_at(entry, 12) as u64)
+    })
+}
+
+/// 拡張パーティション内の EBR の連鎖をたどり、論理パーティション (5番から) を集める
+fn parse_logical(device: &Arc<dyn BlockDevice>, extended: u64, entries: &mut Vec<Entry>) -> Result<(), &'static str> {
+    let mut ebr_lba = extended;
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        let ebr = read_sector(device, ebr_lba)?;
+        if ebr[510..512] != MBR_SIGNATURE {
+            return Err("Invalid EBR signature");
+        }
+        let mut links = mbr_entries(&ebr);
+        // 1つ目は EBR からの相対位置、2つ目は次の EBR の拡張パーティション先頭からの位置
+        let (kind, start, sectors) = links.next().unwrap();
+        if kind != MBR_TYPE_EMPTY && sectors > 0 {
+            entries.push(Entry { number, start: ebr_lba + start, sectors, kind: format!("mbr {:#04x}", kind) });
+        }
+        match links.next().unwrap() {
+            (MBR_TYPE_EXTENDED_CHS | MBR_TYPE_EXTENDED_LBA, next, _) if next > 0 => ebr_lba = extended + next,
+            _ => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+fn parse_mbr(device: &Arc<dyn BlockDevice>, mbr: &[u8]) -> Result<Vec<Entry>, &'static str> {
+    let mut entries = Vec::new();
+    for (i, (kind, start, sectors)) in mbr_entries(mbr).enumerate() {
+        match kind {
+            MBR_TYPE_EMPTY => {}
+            _ if sectors == 0 => {}
+            MBR_TYPE_EXTENDED_CHS | MBR_TYPE_EXTENDED_LBA => parse_logical(device, start, &mut entries)?,
+            _ => entries.push(Entry { number: i + 1, start, sectors, kind: format!("mbr {:#04x}", kind) }),
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_gpt(device: &Arc<dyn BlockDevice>) -> Result<Vec<Entry>, &'static str> {
+    let header = read_sector(device, 1)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err("Missing GPT header");
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if !(GPT_MIN_HEADER_SIZE..=SECTOR_SIZE).contains(&header_size) {
+        return Err("Invalid GPT header size");
+    }
+    let mut checked = header[..header_size].to_vec();
+    checked[16..20].fill(0);
+    if crc32(&checked) != u32_at(&header, 16) {
+        return Err("GPT header checksum mismatch");
+    }
+
+    let table_lba = u64_at(&header, 72);
+    let count = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if count > GPT_MAX_ENTRIES || entry_size < GPT_MIN_ENTRY_SIZE || SECTOR_SIZE % entry_size != 0 {
+        return Err("Unsupported GPT entry layout");
+    }
+    let mut table = vec![0u8; (count * entry_size).div_ceil(SECTOR_SIZE) * SECTOR_SIZE];
+    device.read_sectors(table_lba, &mut table)?;
+    if crc32(&table[..count * entry_size]) != u32_at(&header, 88) {
+        return Err("GPT entry checksum mismatch");
+    }
+
+    let mut entries = Vec::new();
+    for (i, entry) in table.chunks(entry_size).take(count).enumerate() {
+        // 種別 GUID がゼロのエントリは未使用
+        if entry[0..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first = u64_at(entry, 32);
+        let last = u64_at(entry, 40);
+        if last < first {
+            continue;
+        }
+        let name: Vec<u16> = entry[56..128].chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        entries.push(Entry {
+            number: i + 1,
+            start: first,
+            sectors: last - first + 1,
+            kind: format!("gpt {}", String::from_utf16_lossy(&name)),
+        });
+    }
+    Ok(entries)
+}
+
+/// ディスクのパーティションテーブル (GPT か MBR) を読み、各パーティションをブロックデバイスとして登録する
+/// 登録した数を返す (パーティションテーブルがなければ 0)
+pub fn scan(device: Arc<dyn BlockDevice>) -> Result<usize, &'static str> {
+    if device.sector_count() < 2 {
+        return Ok(0);
+    }
+    let mbr = read_sector(&device, 0)?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(0);
+    }
+    // 保護 MBR があれば GPT ディスク
+    let entries = if mbr_entries(&mbr).any(|(kind, _, _)| kind == MBR_TYPE_GPT_PROTECTIVE) {
+        parse_gpt(&device)?
+    } else {
+        parse_mbr(&device, &mbr)?
+    };
+
+    let mut registered = 0;
+    for entry in entries {
+        let end = entry.start.checked_add(entry.sectors);
+        if entry.start == 0 || end.is_none_or(|end| end > device.sector_count()) {
+            crate::warn!("{}: partition {} lies outside the disk", device.name(), entry.number);
+            continue;
+        }
+        let partition = Partition {
+            name: format!("{}p{}", device.name(), entry.number),
+            parent: device.clone(),
+            start: entry.start,
+            sectors: entry.sectors,
+            kind: entry.kind,
+        };
+        crate::debug!("{}: start {} ({})", partition.name, partition.start, partition.kind);
+        block::register_partition(Arc::new(partition));
+        registered += 1;
+    }
+    Ok(registered)
+}
@@ -0,0 +1,282 @@
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+// コンフィグレーション空間のオフセット
+pub const REG_VENDOR_ID: u8 = 0x00;
+pub const REG_COMMAND: u8 = 0x04;
+pub const REG_CLASS: u8 = 0x08; // リビジョン, prog IF, サブクラス, クラス
+pub const REG_HEADER_TYPE: u8 = 0x0E;
+pub const REG_BAR0: u8 = 0x10;
+pub const REG_SECONDARY_BUS: u8 = 0x19; // PCI-PCI ブリッジ
+pub const REG_INTERRUPT_LINE: u8 = 0x3C;
+pub const REG_INTERRUPT_PIN: u8 = 0x3D;
+
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+/// バス・デバイス・ファンクション番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    fn config_address(&self, offset: u8) -> u32 {
+        0x8000_0000
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset as u32 & 0xFC)
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        }
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read_u32(offset);
+        let new = (old & !(0xFFFF << shift)) | (value as u32) << shift;
+        self.write_u32(offset, new);
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read_u16(REG_VENDOR_ID)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.read_u16(REG_VENDOR_ID + 2)
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        self.read_u8(REG_INTERRUPT_LINE)
+    }
+
+    /// I/O 空間とバスマスタ DMA を有効にする
+    pub fn enable_bus_master(&self) {
+        let command = self.read_u16(REG_COMMAND);
+        self.write_u16(REG_COMMAND, command | COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    None,
+    Io { port: u16, size: u32 },
+    Memory { address: u64, size: u64, prefetchable: bool },
+}
+
+/// 列挙時に読み取ったデバイス情報
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub bars: [Bar; 6],
+    pub irq_line: u8,
+    pub irq_pin: u8,
+    /// このデバイスを使っているドライバ名
+    pub claimed_by: Option<&'static str>,
+}
+
+impl PciDevice {
+    fn probe(address: PciAddress) -> Self {
+        let class = address.read_u32(REG_CLASS);
+        let header_type = address.read_u8(REG_HEADER_TYPE) & 0x7F;
+        // 通常のデバイス (ヘッダタイプ0) は BAR が6本、ブリッジは2本
+        let bar_count = if header_type == 0 { 6 } else { 2 };
+
+        let mut bars = [Bar::None; 6];
+        let mut index = 0;
+        while index < bar_count {
+            let (bar, slots) = probe_bar(&address, index as u8);
+            bars[index] = bar;
+            index += slots;
+        }
+
+        Self {
+            address,
+            vendor_id: address.vendor_id(),
+            device_id: address.device_id(),
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            bars,
+            irq_line: address.interrupt_line(),
+            irq_pin: address.read_u8(REG_INTERRUPT_PIN),
+            claimed_by: None,
+        }
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "Storage controller",
+            (0x02, _) => "Network controller",
+            (0x03, _) => "Display controller",
+            (0x04, _) => "Multimedia controller",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, _) => "Serial bus controller",
+            _ => "Unknown device",
+        }
+    }
+}
+
+/// BAR の種類とサイズを調べる (全ビット1を書いて戻り値からサイズを求める)
+/// 64bit BAR は2本分使うので、消費した本数も返す
+fn probe_bar(address: &PciAddress, index: u8) -> (Bar, usize) {
+    let offset = REG_BAR0 + index * 4;
+    let original = address.read_u32(offset);
+
+    address.write_u32(offset, 0xFFFF_FFFF);
+    let mask = address.read_u32(offset);
+    address.write_u32(offset, original);
+
+    if mask == 0 {
+        return (Bar::None, 1);
+    }
+
+    if original & 1 != 0 {
+        let size = !(mask & !0x3) & 0xFFFF;
+        return (Bar::Io { port: (original & !0x3) as u16, size: size + 1 }, 1);
+    }
+
+    let prefetchable = original & 0x8 != 0;
+    match (original >> 1) & 0x3 {
+        // 64bit メモリ BAR
+        0x2 if index < 5 => {
+            let original_high = address.read_u32(offset + 4);
+            address.write_u32(offset + 4, 0xFFFF_FFFF);
+            let mask_high = address.read_u32(offset + 4);
+            address.write_u32(offset + 4, original_high);
+
+            let full_mask = (mask_high as u64) << 32 | (mask & !0xF) as u64;
+            let address = (original_high as u64) << 32 | (original & !0xF) as u64;
+            (Bar::Memory { address, size: !full_mask + 1, prefetchable }, 2)
+        }
+        _ => {
+            let size = (!(mask & !0xF)).wrapping_add(1) as u64;
+            (Bar::Memory { address: (original & !0xF) as u64, size, prefetchable }, 1)
+        }
+    }
+}
+
+fn scan_bus(bus: u8, devices: &mut Vec<PciDevice>) {
+    for device in 0..32u8 {
+        let address = PciAddress { bus, device, function: 0 };
+        if address.vendor_id() == 0xFFFF {
+            continue;
+        }
+
+        // ヘッダタイプの bit7 が立っていればマルチファンクション
+        let multifunction = address.read_u8(REG_HEADER_TYPE) & 0x80 != 0;
+        let functions = if multifunction { 8 } else { 1 };
+
+        for function in 0..functions {
+            let address = PciAddress { bus, device, function };
+            if address.vendor_id() == 0xFFFF {
+                continue;
+            }
+            let info = PciDevice::probe(address);
+            let bridge = info.class == CLASS_BRIDGE && info.subclass == SUBCLASS_PCI_BRIDGE;
+            devices.push(info);
+
+            // PCI-PCI ブリッジの先のバスもたどる
+            if bridge {
+                let secondary = address.read_u8(REG_SECONDARY_BUS);
+                if secondary > bus {
+                    scan_bus(secondary, devices);
+                }
+            }
+        }
+    }
+}
+
+/// PCI バスを列挙して一覧を表示する
+pub fn init() {
+    let mut devices = Vec::new();
+    scan_bus(0, &mut devices);
+
+    for device in devices.iter() {
+        crate::info!("PCI {:02x}:{:02x}.{} {:04x}:{:04x} {} (IRQ {})",
+            device.address.bus, device.address.device, device.address.function,
+            device.vendor_id, device.device_id, device.class_name(), device.irq_line);
+    }
+    crate::info!("PCI: {} device(s) found", devices.len());
+
+    *DEVICES.lock() = devices;
+}
+
+pub fn devices() -> Vec<PciDevice> {
+    DEVICES.lock().clone()
+}
+
+/// 条件に合う未使用のデバイスをドライバに割り当てる
+pub fn claim(driver: &'static str, matches: impl Fn(&PciDevice) -> bool) -> Option<PciDevice> {
+    let mut devices = DEVICES.lock();
+    let device = devices.iter_mut()
+        .find(|device| device.claimed_by.is_none() && matches(device))?;
+    device.claimed_by = Some(driver);
+    crate::debug!("PCI {:02x}:{:02x}.{} claimed by {}",
+        device.address.bus, device.address.device, device.address.function, driver);
+    Some(device.clone())
+}
+
+/// ベンダ/デバイスIDで未使用のデバイスを割り当てる
+pub fn claim_id(driver: &'static str, vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    claim(driver, |device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+/// クラス/サブクラスで未使用のデバイスを割り当てる
+pub fn claim_class(driver: &'static str, class: u8, subclass: u8) -> Option<PciDevice> {
+    claim(driver, |device| device.class == class && device.subclass == subclass)
+}
+
+/// ドライバの初期化に失敗した場合などにデバイスを返却する
+pub fn release(address: PciAddress) {
+    let mut devices = DEVICES.lock();
+    if let Some(device) = devices.iter_mut().find(|device| device.address == address) {
+        device.claimed_by = None;
+    }
+}
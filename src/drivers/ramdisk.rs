@@ -0,0 +1,99 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+
+/// 作れる RAM ディスクの大きさの上限 (ヒープから確保するので、その 1/4 まで)
+pub const MAX_SIZE: usize = crate::allocator::HEAP_MAX_SIZE / 4;
+
+/// 次に作る RAM ディスクの番号 (ram0, ram1, ...)
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// ヒープ上のメモリをディスクとして見せるブロックデバイス
+pub struct RamDisk {
+    name: String,
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    /// sectors セクタの 0 で埋めたディスク
+    pub fn new(name: &str, sectors: u64) -> Result<Self, &'static str> {
+        let size = (sectors as usize).checked_mul(SECTOR_SIZE).ok_or("Ramdisk too large")?;
+        let mut data = Vec::new();
+        data.try_reserve_exact(size).map_err(|_| "Out of memory")?;
+        data.resize(size, 0);
+        Ok(Self { name: String::from(name), data: Mutex::new(data) })
+    }
+
+    fn range(&self, lba: u64, len: usize, size: usize) -> Result<Range<usize>, &'static str> {
+        if len % SECTOR_SIZE != 0 {
+            return Err("Buffer is not a multiple of the sector size");
+        }
+        let start = (lba as usize).checked_mul(SECTOR_SIZE).ok_or("Sector out of range")?;
+        match start.checked_add(len) {
+            Some(end) if end <= size => Ok(start..end),
+            _ => Err("Sector out of range"),
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let data = self.data.lock();
+        let range = self.range(lba, buf.len(), data.len())?;
+        buf.copy_from_slice(&data[range]);
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let mut data = self.data.lock();
+        let range = self.range(lba, buf.len(), data.len())?;
+        data[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn write_sectors_polled(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let mut data = self.data.try_lock().ok_or("Ramdisk busy")?;
+        let range = self.range(lba, buf.len(), data.len())?;
+        data[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// size バイト (セクタ単位に切り上げ) の RAM ディスクを作り、ブロックデバイスとして登録する
+/// ファイルシステムがあれば /dev/ramN のノードも作る。ブロックデバイスの番号を返す
+pub fn create(size: usize) -> Result<usize, &'static str> {
+    if size == 0 || size > MAX_SIZE {
+        return Err("Invalid ramdisk size");
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let disk = RamDisk::new(&format!("ram{}", id), size.div_ceil(SECTOR_SIZE) as u64)?;
+    let index = block::register(Arc::new(disk));
+
+    let path = format!("/dev/ram{}", id);
+    if let Err(e) = crate::filesystem::mknod(&path, 0o660, crate::drivers::block_node(index)) {
+        crate::warn!("{}: {:?}", path, e);
+    }
+    Ok(index)
+}
+
+/// コマンドラインの ramdisk_size=<KiB> で起動時に /dev/ram0 を作る
+pub fn init() -> Result<(), &'static str> {
+    let kib: usize = crate::cmdline::get("ramdisk_size")
+        .ok_or("Not configured")?
+        .parse()
+        .map_err(|_| "Invalid ramdisk_size")?;
+    create(kib * 1024).map(|_| ())
+}
@@ -0,0 +1,128 @@
+use alloc::vec::Vec;
+use core::fmt;
+use crate::sync::RwLock;
+
+// 一覧を読むことのほうが多いので RwLock にする
+static REGISTRY: RwLock<DriverRegistry> = RwLock::new(DriverRegistry::new());
+
+/// ドライバの初期化結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverStatus {
+    /// 登録済みでまだ初期化していない
+    Registered,
+    Ready,
+    Failed(&'static str),
+    /// 依存するドライバ (名前) が使えないので初期化しなかった
+    Skipped(&'static str),
+}
+
+impl fmt::Display for DriverStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DriverStatus::Registered => write!(f, "registered"),
+            DriverStatus::Ready => write!(f, "ready"),
+            DriverStatus::Failed(e) => write!(f, "failed ({})", e),
+            DriverStatus::Skipped(dependency) => write!(f, "skipped (needs {})", dependency),
+        }
+    }
+}
+
+/// 登録されたドライバ1つ分
+#[derive(Debug, Clone, Copy)]
+pub struct DriverInfo {
+    pub name: &'static str,
+    /// 先に初期化しておく必要のあるドライバの名前
+    pub depends: &'static [&'static str],
+    pub status: DriverStatus,
+    init: fn() -> Result<(), &'static str>,
+}
+
+/// ドライバの一覧 (登録順)
+pub struct DriverRegistry {
+    drivers: Vec<DriverInfo>,
+}
+
+impl DriverRegistry {
+    const fn new() -> Self {
+        Self { drivers: Vec::new() }
+    }
+
+    fn find(&self, name: &str) -> Option<&DriverInfo> {
+        self.drivers.iter().find(|driver| driver.name == name)
+    }
+
+    /// 依存がすべて Ready になった未初期化のドライバを登録順に1つ選ぶ
+    /// 依存が失敗していればその場で Skipped にする
+    fn next_ready(&mut self) -> Option<usize> {
+        for index in 0..self.drivers.len() {
+            if self.drivers[index].status != DriverStatus::Registered {
+                continue;
+            }
+            let mut ready = true;
+            for &dependency in self.drivers[index].depends {
+                match self.find(dependency).map(|driver| driver.status) {
+                    Some(DriverStatus::Ready) => {}
+                    Some(DriverStatus::Registered) => ready = false,
+                    Some(DriverStatus::Failed(_) | DriverStatus::Skipped(_)) | None => {
+                        self.drivers[index].status = DriverStatus::Skipped(dependency);
+                        ready = false;
+                        break;
+                    }
+                }
+            }
+            if ready {
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+/// ドライバを登録する (同じ名前は1度だけ)
+pub fn register(name: &'static str, depends: &'static [&'static str], init: fn() -> Result<(), &'static str>) {
+    let mut registry = REGISTRY.write();
+    if registry.find(name).is_some() {
+        crate::warn!("Driver {} already registered", name);
+        return;
+    }
+    registry.drivers.push(DriverInfo { name, depends, status: DriverStatus::Registered, init });
+}
+
+/// 依存順に未初期化のドライバを初期化する
+/// 失敗しても起動は止めず、結果を記録して残りを続ける
+pub fn init_all() {
+    loop {
+        // 初期化中にログを出したりするのでロックを外して呼ぶ
+        let (index, driver) = {
+            let mut registry = REGISTRY.write();
+            match registry.next_ready() {
+                Some(index) => (index, registry.drivers[index]),
+                None => break,
+            }
+        };
+        let status = match (driver.init)() {
+            Ok(()) => {
+                crate::events::publish(crate::events::Event::DeviceAdded(driver.name));
+                DriverStatus::Ready
+            }
+            Err(e) => {
+                crate::debug!("Driver {} unavailable: {}", driver.name, e);
+                DriverStatus::Failed(e)
+            }
+        };
+        REGISTRY.write().drivers[index].status = status;
+    }
+
+    // 依存が循環しているものは初期化できない
+    for driver in REGISTRY.write().drivers.iter_mut() {
+        if driver.status == DriverStatus::Registered {
+            crate::warn!("Driver {} has a dependency cycle", driver.name);
+            driver.status = DriverStatus::Failed("dependency cycle");
+        }
+    }
+}
+
+/// 登録されているドライバとその状態 (lsdrv)
+pub fn inventory() -> Vec<DriverInfo> {
+    REGISTRY.read().drivers.clone()
+}
@@ -0,0 +1,96 @@
+use x86_64::instructions::port::Port;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// CMOSレジスタ
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        // bit 7 は NMI 無効化ビットなので立てたままにする
+        Port::<u8>::new(CMOS_ADDRESS).write(0x80 | reg);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & 0x80 != 0
+}
+
+fn read_raw() -> DateTime {
+    while update_in_progress() {
+        core::hint::spin_loop();
+    }
+    DateTime {
+        year: read_register(REG_YEAR) as u16,
+        month: read_register(REG_MONTH),
+        day: read_register(REG_DAY),
+        hour: read_register(REG_HOURS),
+        minute: read_register(REG_MINUTES),
+        second: read_register(REG_SECONDS),
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// RTCから現在時刻を読み取る
+pub fn read() -> DateTime {
+    // 更新中に読むと値が崩れるので、2回続けて同じ値になるまで読む
+    let mut last = read_raw();
+    loop {
+        let current = read_raw();
+        if current == last {
+            break;
+        }
+        last = current;
+    }
+
+    let status_b = read_register(REG_STATUS_B);
+    let is_binary = status_b & 0x04 != 0;
+    let is_24_hour = status_b & 0x02 != 0;
+
+    let mut dt = last;
+    let pm = dt.hour & 0x80 != 0;
+    dt.hour &= 0x7F;
+
+    if !is_binary {
+        dt.second = bcd_to_binary(dt.second);
+        dt.minute = bcd_to_binary(dt.minute);
+        dt.hour = bcd_to_binary(dt.hour);
+        dt.day = bcd_to_binary(dt.day);
+        dt.month = bcd_to_binary(dt.month);
+        dt.year = bcd_to_binary(dt.year as u8) as u16;
+    }
+
+    // 12時間表記を24時間表記に変換
+    if !is_24_hour {
+        dt.hour %= 12;
+        if pm {
+            dt.hour += 12;
+        }
+    }
+
+    // 世紀レジスタは機種依存なので 2000年代と仮定する
+    dt.year += 2000;
+    dt
+}
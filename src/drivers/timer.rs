@@ -1,13 +1,104 @@
 use x86_64::instructions::port::Port;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::PhysAddr;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use crate::sync::IrqMutex;
+use crate::cpu::{self, Feature};
 
 const PIT_FREQUENCY: usize = 1193182;
-const TARGET_FREQUENCY: usize = 100; // 100Hz (10ms tick)
+/// 既定のティックレート 100Hz (10ms tick)
+pub const DEFAULT_TICK_HZ: usize = 100;
+
+const IA32_TSC_DEADLINE_MSR: u32 = 0x6E0;
+
+// HPET レジスタ
+const HPET_CAPABILITIES: u64 = 0x000;
+const HPET_CONFIG: u64 = 0x010;
+const HPET_MAIN_COUNTER: u64 = 0x0F0;
+const HPET_ENABLE: u64 = 1 << 0;
 
 static TICKS: AtomicUsize = AtomicUsize::new(0);
+static TICK_HZ: AtomicUsize = AtomicUsize::new(DEFAULT_TICK_HZ);
+// 最後に Timer イベントを発行した起動からの秒数
+static LAST_SECOND: AtomicU64 = AtomicU64::new(0);
+
+static CLOCK_SOURCE: AtomicU8 = AtomicU8::new(ClockSource::Pit as u8);
+static TICK_SOURCE: AtomicU8 = AtomicU8::new(TickSource::Pit as u8);
+
+// 時計の基準点: 切り替え時点のナノ秒と、そのときのカウンタ値
+static BASE_NS: AtomicU64 = AtomicU64::new(0);
+static BASE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+static HPET_BASE: AtomicU64 = AtomicU64::new(0);
+static HPET_PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+static TSC_KHZ: AtomicU64 = AtomicU64::new(0);
+
+/// タイマーホイールのスロット数 (1スロット = 1ms、一周より先の期限は周回数で区別する)
+const WHEEL_SLOTS: usize = 256;
+
+// schedule から (割り込みを止めて) 登録し、タイマーの後半処理で期限の来たものを実行する
+static WHEEL: IrqMutex<TimerWheel> = IrqMutex::new(TimerWheel::new());
+
+/// 単調増加時計に使うカウンタ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClockSource {
+    Pit = 0,
+    Hpet = 1,
+    Tsc = 2,
+}
+
+/// 周期割り込み (スケジューラのティック) の発生源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TickSource {
+    Pit = 0,
+    /// Local APIC の TSC-deadline モード
+    TscDeadline = 1,
+}
+
+pub fn clock_source() -> ClockSource {
+    match CLOCK_SOURCE.load(Ordering::SeqCst) {
+        1 => ClockSource::Hpet,
+        2 => ClockSource::Tsc,
+        _ => ClockSource::Pit,
+    }
+}
+
+pub fn tick_source() -> TickSource {
+    match TICK_SOURCE.load(Ordering::SeqCst) {
+        1 => TickSource::TscDeadline,
+        _ => TickSource::Pit,
+    }
+}
 
 pub fn init() {
-    let divisor = PIT_FREQUENCY / TARGET_FREQUENCY;
+    crate::softirq::open(crate::softirq::Softirq::Timer, timer_softirq);
+    program_pit(TICK_HZ.load(Ordering::SeqCst));
+    crate::info!("Timer initialized: {} Hz", TICK_HZ.load(Ordering::SeqCst));
+
+    if let Err(e) = init_hpet() {
+        crate::debug!("HPET unavailable: {}", e);
+    }
+    calibrate_tsc();
+
+    // HPET > 不変 TSC > PIT の順に精度の高い時計を選ぶ
+    if HPET_BASE.load(Ordering::SeqCst) != 0 {
+        switch_clock(ClockSource::Hpet);
+    } else if has_invariant_tsc() && TSC_KHZ.load(Ordering::SeqCst) != 0 {
+        switch_clock(ClockSource::Tsc);
+    }
+
+    if has_tsc_deadline() && crate::apic::is_enabled() && TSC_KHZ.load(Ordering::SeqCst) != 0 {
+        start_tsc_deadline();
+    }
+
+    crate::info!("Clock source: {:?}, tick source: {:?}", clock_source(), tick_source());
+}
+
+fn program_pit(hz: usize) {
+    let divisor = PIT_FREQUENCY / hz;
 
     unsafe {
         // コマンドレジスタ: チャンネル0、ロー/ハイバイト、モード3
@@ -17,19 +108,178 @@ pub fn init() {
         Port::<u8>::new(0x40).write((divisor & 0xFF) as u8);
         Port::<u8>::new(0x40).write((divisor >> 8) as u8);
     }
+}
+
+fn init_hpet() -> Result<(), &'static str> {
+    let table = crate::acpi::find_table(b"HPET").ok_or("HPET table not found")?;
+    // ベースアドレスは Generic Address Structure (オフセット 40) の address フィールド
+    let address = unsafe {
+        let virt = crate::memory::phys_to_virt(PhysAddr::new(table + 44));
+        core::ptr::read_unaligned(virt.as_ptr::<u64>())
+    };
+    let base = crate::memory::map_mmio(PhysAddr::new(address), 4096)?.as_u64();
 
-    crate::println!("Timer initialized: {} Hz", TARGET_FREQUENCY);
+    let capabilities = unsafe { core::ptr::read_volatile((base + HPET_CAPABILITIES) as *const u64) };
+    let period_fs = capabilities >> 32;
+    if period_fs == 0 || period_fs > 100_000_000 {
+        return Err("Invalid HPET period");
+    }
+
+    unsafe {
+        let config = (base + HPET_CONFIG) as *mut u64;
+        core::ptr::write_volatile(config, core::ptr::read_volatile(config) | HPET_ENABLE);
+    }
+
+    HPET_PERIOD_FS.store(period_fs, Ordering::SeqCst);
+    HPET_BASE.store(base, Ordering::SeqCst);
+    crate::info!("HPET: {} MHz", 1_000_000_000 / period_fs);
+    Ok(())
+}
+
+fn hpet_counter() -> u64 {
+    let base = HPET_BASE.load(Ordering::SeqCst);
+    unsafe { core::ptr::read_volatile((base + HPET_MAIN_COUNTER) as *const u64) }
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+fn has_invariant_tsc() -> bool {
+    cpu::has(Feature::InvariantTsc)
+}
+
+fn has_tsc_deadline() -> bool {
+    cpu::has(Feature::TscDeadline)
+}
+
+/// PIT のティックを基準に TSC の周波数を測る
+fn calibrate_tsc() {
+    if !x86_64::instructions::interrupts::are_enabled() {
+        return;
+    }
+
+    const CALIBRATION_TICKS: usize = 5;
+    let hz = TICK_HZ.load(Ordering::SeqCst);
+
+    // ティックの境目から測り始める
+    let start_tick = get_ticks();
+    while get_ticks() == start_tick {
+        x86_64::instructions::hlt();
+    }
+    let tsc_start = rdtsc();
+    let target = get_ticks() + CALIBRATION_TICKS;
+    while get_ticks() < target {
+        x86_64::instructions::hlt();
+    }
+    let elapsed = rdtsc() - tsc_start;
+
+    let khz = elapsed * hz as u64 / CALIBRATION_TICKS as u64 / 1000;
+    TSC_KHZ.store(khz, Ordering::SeqCst);
+    crate::info!("TSC: {} MHz (calibrated against PIT)", khz / 1000);
+}
+
+fn read_counter(source: ClockSource) -> u64 {
+    match source {
+        ClockSource::Pit => get_ticks() as u64,
+        ClockSource::Hpet => hpet_counter(),
+        ClockSource::Tsc => rdtsc(),
+    }
+}
+
+fn counter_to_ns(source: ClockSource, count: u64) -> u64 {
+    let ns = match source {
+        ClockSource::Pit => count as u128 * 1_000_000_000 / TICK_HZ.load(Ordering::SeqCst) as u128,
+        ClockSource::Hpet => count as u128 * HPET_PERIOD_FS.load(Ordering::SeqCst) as u128 / 1_000_000,
+        ClockSource::Tsc => count as u128 * 1_000_000 / TSC_KHZ.load(Ordering::SeqCst) as u128,
+    };
+    ns as u64
+}
+
+/// 現在時刻を基準点にして時計を切り替える (時刻が巻き戻らないようにする)
+fn switch_clock(source: ClockSource) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        BASE_NS.store(now_ns(), Ordering::SeqCst);
+        BASE_COUNT.store(read_counter(source), Ordering::SeqCst);
+        CLOCK_SOURCE.store(source as u8, Ordering::SeqCst);
+    });
+}
+
+fn arm_tsc_deadline() {
+    let interval = TSC_KHZ.load(Ordering::SeqCst) * 1000 / TICK_HZ.load(Ordering::SeqCst) as u64;
+    unsafe {
+        Msr::new(IA32_TSC_DEADLINE_MSR).write(rdtsc() + interval);
+    }
+}
+
+/// ティックを PIT から Local APIC の TSC-deadline タイマーに切り替える
+fn start_tsc_deadline() {
+    use crate::interrupts::InterruptIndex;
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        crate::apic::mask_irq(crate::apic::isa_irq_to_gsi(0));
+        crate::apic::enable_tsc_deadline_timer(InterruptIndex::Timer as u8);
+        TICK_SOURCE.store(TickSource::TscDeadline as u8, Ordering::SeqCst);
+        arm_tsc_deadline();
+    });
+}
+
+/// ティックレートを変更する
+pub fn set_tick_rate(hz: usize) -> Result<(), &'static str> {
+    if hz < 19 || hz > 10_000 {
+        return Err("Tick rate out of range");
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // PIT を時計に使っている場合はティックの長さが変わるので基準点を取り直す
+        let pit_clock = clock_source() == ClockSource::Pit;
+        if pit_clock {
+            BASE_NS.store(now_ns(), Ordering::SeqCst);
+            BASE_COUNT.store(get_ticks() as u64, Ordering::SeqCst);
+        }
+        TICK_HZ.store(hz, Ordering::SeqCst);
+        if tick_source() == TickSource::Pit {
+            program_pit(hz);
+        }
+    });
+    crate::info!("Tick rate set to {} Hz", hz);
+    Ok(())
+}
+
+pub fn tick_rate() -> usize {
+    TICK_HZ.load(Ordering::SeqCst)
 }
 
 pub fn handle_interrupt() {
     TICKS.fetch_add(1, Ordering::SeqCst);
+    crate::rand::add_interrupt_randomness(0);
+    crate::vdso::update();
+
+    if tick_source() == TickSource::TscDeadline {
+        arm_tsc_deadline();
+    }
+
+    // 割り込みコントローラに通知
+    crate::interrupts::end_of_interrupt(crate::interrupts::InterruptIndex::Timer);
+
+    // 残りは割り込みを許可してから行う
+    crate::softirq::raise(crate::softirq::Softirq::Timer);
+    crate::softirq::irq_exit();
+}
+
+/// タイマー割り込みの後半処理
+fn timer_softirq() {
+    // コンソールのシャドウバッファを画面に反映
+    crate::drivers::console::tick();
 
     // スケジューラのティック処理
     crate::process::scheduler::tick();
 
-    // 割り込みコントローラに通知
-    unsafe {
-        Port::<u8>::new(0x20).write(0x20);
+    run_timers();
+
+    let seconds = now_ns() / 1_000_000_000;
+    if LAST_SECOND.swap(seconds, Ordering::Relaxed) != seconds {
+        crate::events::publish(crate::events::Event::Timer(seconds));
     }
 }
 
@@ -37,13 +287,175 @@ pub fn get_ticks() -> usize {
     TICKS.load(Ordering::SeqCst)
 }
 
+/// 起動からの経過時間 (ナノ秒、単調増加)
+pub fn now_ns() -> u64 {
+    let source = clock_source();
+    let elapsed = read_counter(source).wrapping_sub(BASE_COUNT.load(Ordering::SeqCst));
+    BASE_NS.load(Ordering::SeqCst) + counter_to_ns(source, elapsed)
+}
+
+/// 測った TSC の周波数 (kHz、まだ測っていなければ 0)
+pub fn tsc_khz() -> u64 {
+    TSC_KHZ.load(Ordering::SeqCst)
+}
+
 pub fn get_uptime_ms() -> usize {
-    (get_ticks() * 1000) / TARGET_FREQUENCY
+    (now_ns() / 1_000_000) as usize
 }
 
 pub fn sleep_ms(ms: usize) {
-    let target = get_uptime_ms() + ms;
-    while get_uptime_ms() < target {
-        x86_64::instructions::hlt();
+    sleep_ns(ms as u64 * 1_000_000);
+}
+
+pub fn sleep_us(us: usize) {
+    sleep_ns(us as u64 * 1000);
+}
+
+/// 割り込み禁止中でも使えるビジーウェイト (例外ハンドラ用)
+/// PIT しか無い場合はティックが進まないので、スピン回数による大まかな待ちになる
+pub fn busy_wait_ms(ms: usize) {
+    if clock_source() == ClockSource::Pit {
+        for _ in 0..ms * 100_000 {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+    let target = now_ns() + ms as u64 * 1_000_000;
+    while now_ns() < target {
+        core::hint::spin_loop();
+    }
+}
+
+fn sleep_ns(ns: u64) {
+    let target = now_ns() + ns;
+    // 1ティックより短い待ちは時計が細かければスピンで待つ
+    let tick_ns = 1_000_000_000 / TICK_HZ.load(Ordering::SeqCst) as u64;
+    if ns < tick_ns && clock_source() != ClockSource::Pit {
+        while now_ns() < target {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+
+    while now_ns() < target {
+        let tid = match crate::process::current_tid() {
+            Some(tid) => tid,
+            // プロセス外では割り込みごとに時計を見る
+            None => {
+                crate::sync::wait_for_interrupt();
+                continue;
+            }
+        };
+        // 先にブロックしてから登録し、登録直後に期限が来ても起床を取りこぼさない
+        crate::process::block_current();
+        let timer = schedule((target - now_ns()).div_ceil(1_000_000) as usize, wake_process, tid);
+        while crate::process::is_blocked(tid) {
+            crate::sync::wait_for_interrupt();
+        }
+        crate::process::resume(tid);
+        timer.cancel();
+    }
+}
+
+/// 期限が来たら tid を起こすタイマーのコールバック (sleep や poll のタイムアウト)
+pub fn wake_process(tid: usize) {
+    crate::process::scheduler::wake(tid);
+}
+
+/// schedule で登録したタイマー
+struct Timer {
+    id: u64,
+    /// 期限 (起動からの ms)
+    expires: usize,
+    func: fn(usize),
+    arg: usize,
+}
+
+/// 登録したタイマーを取り消すためのハンドル
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    id: u64,
+    slot: usize,
+}
+
+impl TimerHandle {
+    /// まだ実行されていなければ取り消して true を返す
+    pub fn cancel(self) -> bool {
+        let mut wheel = WHEEL.lock();
+        let timers = &mut wheel.slots[self.slot];
+        match timers.iter().position(|timer| timer.id == self.id) {
+            Some(index) => {
+                timers.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// まだ実行されずに残っているか
+    pub fn pending(&self) -> bool {
+        WHEEL.lock().slots[self.slot].iter().any(|timer| timer.id == self.id)
+    }
+}
+
+/// 期限 (ms) の下位ビットでスロットを選ぶハッシュ式のタイマーホイール
+struct TimerWheel {
+    slots: [Vec<Timer>; WHEEL_SLOTS],
+    /// 次に調べるスロットの時刻 (これより前のスロットは処理済み)
+    next_ms: usize,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        Self { slots: [const { Vec::new() }; WHEEL_SLOTS], next_ms: 0, next_id: 1 }
+    }
+
+    fn insert(&mut self, expires: usize, func: fn(usize), arg: usize) -> TimerHandle {
+        let id = self.next_id;
+        self.next_id += 1;
+        // 処理済みの時刻を指していたら次に調べるスロットに入れる
+        let slot = expires.max(self.next_ms) % WHEEL_SLOTS;
+        self.slots[slot].push(Timer { id, expires, func, arg });
+        TimerHandle { id, slot }
+    }
+
+    /// now までのスロットから期限の来たタイマーを取り出す
+    fn expire(&mut self, now: usize, expired: &mut Vec<Timer>) {
+        if now < self.next_ms {
+            return;
+        }
+        // 一周以上遅れていてもすべてのスロットを1回ずつ見れば足りる
+        let steps = (now - self.next_ms + 1).min(WHEEL_SLOTS);
+        for step in 0..steps {
+            let timers = &mut self.slots[(self.next_ms + step) % WHEEL_SLOTS];
+            let mut index = 0;
+            while index < timers.len() {
+                if timers[index].expires <= now {
+                    expired.push(timers.swap_remove(index));
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        self.next_ms = now + 1;
+    }
+}
+
+/// after_ms 後にタイマーの後半処理から func(arg) を呼ぶ
+/// func は割り込み出口で実行されるので、プロセスを起こすなど短い処理に留め、眠らないこと
+pub fn schedule(after_ms: usize, func: fn(usize), arg: usize) -> TimerHandle {
+    let expires = get_uptime_ms() + after_ms;
+    WHEEL.lock().insert(expires, func, arg)
+}
+
+/// 期限の来たタイマーを (ロックを外してから) 実行する
+fn run_timers() {
+    let mut expired = Vec::new();
+    WHEEL.lock().expire(get_uptime_ms(), &mut expired);
+    // 登録順 (同じ期限なら先に登録したもの) に呼ぶ
+    expired.sort_unstable_by_key(|timer| (timer.expires, timer.id));
+    for timer in expired {
+        (timer.func)(timer.arg);
     }
 }
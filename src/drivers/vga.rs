@@ -1,9 +1,13 @@
-use core::fmt;
-use core::ptr::{read_volatile, write_volatile};
+use core::ptr::write_volatile;
+use core::sync::atomic::{AtomicU32, Ordering};
+use super::vt::{self, NR_VTS};
 
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 const VGA_BUFFER: usize = 0xb8000;
+/// タブストップの間隔 (桁)
+const TAB_WIDTH: usize = 8;
+const BACKSPACE: u8 = 0x08;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -44,9 +48,30 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-static mut CURSOR_COL: usize = 0;
-static mut CURSOR_ROW: usize = 0;
-static mut CURRENT_COLOR: ColorCode = ColorCode(0x0f); // 白 on 黒
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode(0x0f), // 白 on 黒
+};
+
+/// 仮想端末1つ分の画面 (文字とカーソル、色)
+struct Screen {
+    cells: [ScreenChar; BUFFER_WIDTH * BUFFER_HEIGHT],
+    row: usize,
+    col: usize,
+    color: ColorCode,
+}
+
+impl Screen {
+    const fn new() -> Self {
+        Self { cells: [BLANK; BUFFER_WIDTH * BUFFER_HEIGHT], row: 0, col: 0, color: BLANK.color_code }
+    }
+}
+
+// 端末ごとの画面の影 (シャドウバッファ)。書き込みはまずここに行い、
+// flush で表示中の端末の変更のあった行だけ VRAM に写す
+static mut SCREENS: [Screen; NR_VTS] = [const { Screen::new() }; NR_VTS];
+// VRAM に未反映の行 (ビット n が行 n)
+static DIRTY_ROWS: AtomicU32 = AtomicU32::new(0);
 
 fn vga_ptr() -> *mut ScreenChar {
     VGA_BUFFER as *mut ScreenChar
@@ -56,105 +81,138 @@ fn index(row: usize, col: usize) -> usize {
     row * BUFFER_WIDTH + col
 }
 
-fn put_char(row: usize, col: usize, ch: ScreenChar) {
-    unsafe {
-        let ptr = vga_ptr().add(index(row, col));
-        write_volatile(ptr, ch);
+fn screen(vt: usize) -> &'static mut Screen {
+    unsafe { &mut *core::ptr::addr_of_mut!(SCREENS[vt]) }
+}
+
+fn put_char(vt: usize, row: usize, col: usize, ch: ScreenChar) {
+    let cell = &mut screen(vt).cells[index(row, col)];
+    if *cell != ch {
+        *cell = ch;
+        if vt == vt::active() {
+            DIRTY_ROWS.fetch_or(1 << row, Ordering::Relaxed);
+        }
     }
 }
 
-fn get_char(row: usize, col: usize) -> ScreenChar {
-    unsafe {
-        let ptr = vga_ptr().add(index(row, col));
-        read_volatile(ptr)
+fn get_char(vt: usize, row: usize, col: usize) -> ScreenChar {
+    screen(vt).cells[index(row, col)]
+}
+
+/// 表示中の端末の画面のうち変更のあった行を VRAM に書き出す
+pub fn flush() {
+    // 先に取り出しておけば、書き出し中に汚れた行は次の flush で拾える
+    let dirty = DIRTY_ROWS.swap(0, Ordering::Relaxed);
+    let vt = vt::active();
+    for row in (0..BUFFER_HEIGHT).filter(|row| dirty & (1 << row) != 0) {
+        // 1文字ずつではなく行単位でまとめて写す
+        let start = index(row, 0);
+        let line: [ScreenChar; BUFFER_WIDTH] = screen(vt).cells[start..start + BUFFER_WIDTH].try_into().unwrap();
+        unsafe { write_volatile(vga_ptr().add(start) as *mut [ScreenChar; BUFFER_WIDTH], line) };
     }
 }
 
-fn clear_row(row: usize) {
+/// 表示する端末が変わったので、次の flush で全行を書き直す
+pub fn redraw() {
+    DIRTY_ROWS.store((1 << BUFFER_HEIGHT) - 1, Ordering::Relaxed);
+}
+
+fn clear_row(vt: usize, row: usize) {
     let blank = ScreenChar {
         ascii_character: b' ',
-        color_code: unsafe { CURRENT_COLOR },
+        color_code: screen(vt).color,
     };
     for col in 0..BUFFER_WIDTH {
-        put_char(row, col, blank);
+        put_char(vt, row, col, blank);
     }
 }
 
-fn scroll_up() {
+fn scroll_up(vt: usize) {
     for row in 1..BUFFER_HEIGHT {
         for col in 0..BUFFER_WIDTH {
-            let ch = get_char(row, col);
-            put_char(row - 1, col, ch);
+            let ch = get_char(vt, row, col);
+            put_char(vt, row - 1, col, ch);
         }
     }
-    clear_row(BUFFER_HEIGHT - 1);
+    clear_row(vt, BUFFER_HEIGHT - 1);
 }
 
-fn new_line() {
-    unsafe {
-        if CURSOR_ROW < BUFFER_HEIGHT - 1 {
-            CURSOR_ROW += 1;
-            CURSOR_COL = 0;
-        } else {
-            scroll_up();
-            CURSOR_COL = 0;
-        }
+fn new_line(vt: usize) {
+    let screen = screen(vt);
+    screen.col = 0;
+    if screen.row < BUFFER_HEIGHT - 1 {
+        screen.row += 1;
+        return;
+    }
+    scroll_up(vt);
+}
+
+/// 1つ前の桁に戻って空白にする (行頭では何もしない)
+fn backspace(vt: usize) {
+    let (row, col, color) = {
+        let screen = screen(vt);
+        (screen.row, screen.col, screen.color)
+    };
+    if col == 0 {
+        return;
     }
+    put_char(vt, row, col - 1, ScreenChar { ascii_character: b' ', color_code: color });
+    screen(vt).col = col - 1;
 }
 
-fn write_byte(byte: u8) {
-    unsafe {
-        match byte {
-            b'\n' => new_line(),
-            byte => {
-                if CURSOR_COL >= BUFFER_WIDTH {
-                    new_line();
-                }
-                let row = CURSOR_ROW;
-                let col = CURSOR_COL;
-                let ch = ScreenChar {
-                    ascii_character: byte,
-                    color_code: CURRENT_COLOR,
-                };
-                put_char(row, col, ch);
-                CURSOR_COL += 1;
+fn write_byte(vt: usize, byte: u8) {
+    match byte {
+        b'\n' => new_line(vt),
+        b'\r' => screen(vt).col = 0,
+        // 次のタブストップまでカーソルを進める (行末を越えたら次の文字で折り返す)
+        b'\t' => {
+            let screen = screen(vt);
+            screen.col = ((screen.col / TAB_WIDTH + 1) * TAB_WIDTH).min(BUFFER_WIDTH);
+        }
+        BACKSPACE => backspace(vt),
+        byte => {
+            if screen(vt).col >= BUFFER_WIDTH {
+                new_line(vt);
             }
+            let (row, col, color) = {
+                let screen = screen(vt);
+                (screen.row, screen.col, screen.color)
+            };
+            let ch = ScreenChar {
+                ascii_character: byte,
+                color_code: color,
+            };
+            put_char(vt, row, col, ch);
+            screen(vt).col += 1;
         }
     }
 }
 
-fn write_str_impl(s: &str) {
+fn write_str_impl(vt: usize, s: &str) {
     for b in s.bytes() {
         match b {
-            0x20..=0x7e | b'\n' => write_byte(b),
-            _ => write_byte(0xfe),
+            0x20..=0x7e | b'\n' | b'\r' | b'\t' | BACKSPACE => write_byte(vt, b),
+            _ => write_byte(vt, 0xfe),
         }
     }
 }
 
 pub fn init() {
-    unsafe {
-        CURRENT_COLOR = ColorCode::new(Color::White, Color::Black);
-        CURSOR_COL = 0;
-        CURSOR_ROW = 0;
-    }
-    for row in 0..BUFFER_HEIGHT {
-        clear_row(row);
-    }
-}
-
-struct Writer;
-
-impl fmt::Write for Writer {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        write_str_impl(s);
-        Ok(())
+    for vt in 0..NR_VTS {
+        let screen = screen(vt);
+        screen.color = ColorCode::new(Color::White, Color::Black);
+        screen.col = 0;
+        screen.row = 0;
+        for row in 0..BUFFER_HEIGHT {
+            clear_row(vt, row);
+        }
     }
+    // 起動直後の VRAM にはブートローダーの表示が残っているので全行書き直す
+    redraw();
+    flush();
 }
 
-#[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    let mut w = Writer;
-    let _ = w.write_fmt(args); // エラーは握りつぶす（panic させない）
+/// 端末 vt の画面に文字列を書く (console から呼ばれる)
+pub fn write_str(vt: usize, s: &str) {
+    write_str_impl(vt, s);
 }
@@ -0,0 +1,244 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+
+// レガシー virtio の I/O レジスタ (BAR0 からのオフセット)
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_ADDRESS: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_DEVICE_STATUS: u16 = 0x12;
+const REG_ISR_STATUS: u16 = 0x13;
+/// デバイス固有設定の開始位置 (MSI-X 無効時)
+const REG_DEVICE_CONFIG: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 128;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// レガシーインターフェースの virtio デバイス (I/O ポート BAR0)
+#[derive(Clone, Copy)]
+pub struct LegacyDevice {
+    io_base: u16,
+}
+
+impl LegacyDevice {
+    /// リセットして ACKNOWLEDGE/DRIVER を立て、features のうちデバイスが
+    /// 対応するものだけを有効にする。有効になった機能ビットを返す
+    pub fn init(io_base: u16, features: u32) -> (Self, u32) {
+        let device = Self { io_base };
+        device.set_status(0);
+        device.set_status(STATUS_ACKNOWLEDGE);
+        device.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let offered = unsafe { Port::<u32>::new(io_base + REG_DEVICE_FEATURES).read() };
+        let accepted = offered & features;
+        unsafe { Port::<u32>::new(io_base + REG_GUEST_FEATURES).write(accepted) };
+        (device, accepted)
+    }
+
+    fn set_status(&self, status: u8) {
+        unsafe { Port::<u8>::new(self.io_base + REG_DEVICE_STATUS).write(status) };
+    }
+
+    pub fn driver_ok(&self) {
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+    }
+
+    pub fn fail(&self) {
+        self.set_status(STATUS_FAILED);
+    }
+
+    /// ISR を読んで割り込みを下げる (bit0: キューの更新)
+    pub fn read_isr(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.io_base + REG_ISR_STATUS).read() }
+    }
+
+    pub fn config_u8(&self, offset: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io_base + REG_DEVICE_CONFIG + offset).read() }
+    }
+
+    pub fn config_u32(&self, offset: u16) -> u32 {
+        unsafe { Port::<u32>::new(self.io_base + REG_DEVICE_CONFIG + offset).read() }
+    }
+
+    pub fn notify(&self, queue: u16) {
+        unsafe { Port::<u16>::new(self.io_base + REG_QUEUE_NOTIFY).write(queue) };
+    }
+
+    /// キューを選択して DMA 領域を割り当てる
+    pub fn setup_queue(&self, index: u16) -> Result<Virtqueue, &'static str> {
+        let size = unsafe {
+            Port::<u16>::new(self.io_base + REG_QUEUE_SELECT).write(index);
+            Port::<u16>::new(self.io_base + REG_QUEUE_SIZE).read()
+        };
+        if size == 0 {
+            return Err("Virtqueue not available");
+        }
+
+        let (avail_offset, used_offset, total) = Virtqueue::layout(size);
+        let (base, phys) = crate::memory::alloc_dma(total / 4096)?;
+        unsafe {
+            Port::<u32>::new(self.io_base + REG_QUEUE_ADDRESS).write((phys.as_u64() >> 12) as u32);
+        }
+
+        Ok(Virtqueue {
+            index,
+            size,
+            base,
+            avail_offset,
+            used_offset,
+            free: (0..size).rev().collect(),
+            last_used: 0,
+        })
+    }
+}
+
+#[repr(C)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// ディスクリプタに渡すバッファ (物理アドレス, 長さ, デバイスが書き込むか)
+#[derive(Clone, Copy)]
+pub struct Buffer {
+    pub phys: u64,
+    pub len: u32,
+    pub device_writable: bool,
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// レガシーレイアウトの virtqueue
+/// [ディスクリプタ表][available リング] (4KiB 境界) [used リング]
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    base: VirtAddr,
+    avail_offset: usize,
+    used_offset: usize,
+    /// 空いているディスクリプタ番号
+    free: Vec<u16>,
+    last_used: u16,
+}
+
+impl Virtqueue {
+    fn layout(size: u16) -> (usize, usize, usize) {
+        let n = size as usize;
+        let avail_offset = 16 * n;
+        let used_offset = align_up(avail_offset + 6 + 2 * n, 4096);
+        let total = used_offset + align_up(6 + 8 * n, 4096);
+        (avail_offset, used_offset, total)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    fn desc(&self, index: u16) -> *mut VirtqDesc {
+        (self.base.as_u64() as *mut VirtqDesc).wrapping_add(index as usize)
+    }
+
+    fn avail_idx(&self) -> *mut u16 {
+        (self.base.as_u64() as usize + self.avail_offset + 2) as *mut u16
+    }
+
+    fn avail_ring(&self, slot: u16) -> *mut u16 {
+        (self.base.as_u64() as usize + self.avail_offset + 4 + 2 * slot as usize) as *mut u16
+    }
+
+    fn used_idx(&self) -> u16 {
+        unsafe { core::ptr::read_volatile((self.base.as_u64() as usize + self.used_offset + 2) as *const u16) }
+    }
+
+    /// used リングの slot 番目の (先頭ディスクリプタ番号, 書き込まれた長さ)
+    fn used_elem(&self, slot: u16) -> (u16, u32) {
+        let addr = self.base.as_u64() as usize + self.used_offset + 4 + 8 * slot as usize;
+        unsafe {
+            let id = core::ptr::read_volatile(addr as *const u32);
+            let len = core::ptr::read_volatile((addr + 4) as *const u32);
+            (id as u16, len)
+        }
+    }
+
+    /// バッファを連結したディスクリプタチェーンを available リングに積む
+    /// 先頭のディスクリプタ番号を返す (完了時に pop_used で返ってくる)
+    pub fn push(&mut self, buffers: &[Buffer]) -> Result<u16, &'static str> {
+        if buffers.is_empty() || buffers.len() > self.free.len() {
+            return Err("Virtqueue full");
+        }
+
+        let indices: Vec<u16> = (0..buffers.len()).map(|_| self.free.pop().unwrap()).collect();
+        for (i, buffer) in buffers.iter().enumerate() {
+            let mut flags = if buffer.device_writable { VIRTQ_DESC_F_WRITE } else { 0 };
+            let next = if i + 1 < buffers.len() {
+                flags |= VIRTQ_DESC_F_NEXT;
+                indices[i + 1]
+            } else {
+                0
+            };
+            unsafe {
+                self.desc(indices[i]).write(VirtqDesc {
+                    addr: buffer.phys,
+                    len: buffer.len,
+                    flags,
+                    next,
+                });
+            }
+        }
+
+        let head = indices[0];
+        unsafe {
+            let idx = self.avail_idx().read_volatile();
+            self.avail_ring(idx % self.size).write_volatile(head);
+            fence(Ordering::SeqCst);
+            self.avail_idx().write_volatile(idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+        }
+        Ok(head)
+    }
+
+    pub fn has_used(&self) -> bool {
+        self.used_idx() != self.last_used
+    }
+
+    /// デバイスが処理し終えたチェーンを1つ取り出し、ディスクリプタを解放する
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let (head, len) = self.used_elem(self.last_used % self.size);
+        self.last_used = self.last_used.wrapping_add(1);
+
+        // チェーンをたどって空きリストに戻す
+        let mut index = head;
+        loop {
+            let desc = unsafe { &*self.desc(index) };
+            self.free.push(index);
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            index = desc.next;
+        }
+        Some((head, len))
+    }
+}
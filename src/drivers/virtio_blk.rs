@@ -0,0 +1,204 @@
+use alloc::sync::Arc;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::drivers::pci;
+use crate::drivers::virtio::{Buffer, LegacyDevice, Virtqueue, VIRTIO_VENDOR_ID};
+use crate::sync::WaitQueue;
+
+/// transitional (レガシーインターフェース対応) の virtio-blk
+const VIRTIO_BLK_DEVICE_ID: u16 = 0x1001;
+
+// デバイス固有設定
+const CONFIG_CAPACITY: u16 = 0x00; // u64, セクタ数
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+/// 1リクエストで転送できる最大セクタ数 (バウンスバッファ1ページ分)
+const MAX_SECTORS_PER_REQUEST: usize = 4096 / SECTOR_SIZE;
+/// 割り込みなしで完了を待つときに used リングを見る回数の上限
+/// (割り込みが止まっているとタイマーも進まないので、時間ではなく回数で区切る)
+const POLL_SPINS: usize = 100_000_000;
+
+static COMPLETION: WaitQueue = WaitQueue::new();
+static DEVICE: spin::Once<LegacyDevice> = spin::Once::new();
+
+#[repr(C)]
+struct BlkRequestHeader {
+    request_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+struct Inner {
+    device: LegacyDevice,
+    queue: Virtqueue,
+    // 1ページ目: リクエストヘッダとステータス、2ページ目: データのバウンスバッファ
+    buffer: VirtAddr,
+    buffer_phys: PhysAddr,
+    /// polled の完了待ちがタイムアウトした (デバイスがバッファを使っているかもしれないので以後使わない)
+    broken: bool,
+}
+
+pub struct VirtioBlk {
+    inner: Mutex<Inner>,
+    sectors: u64,
+}
+
+impl Inner {
+    /// ヘッダ・データ・ステータスの3つを連結したリクエストを発行し、完了を待つ
+    /// polled なら割り込みを待たずに used リングを見続ける
+    fn submit(&mut self, request_type: u32, sector: u64, len: usize, polled: bool) -> Result<(), &'static str> {
+        if self.broken {
+            return Err("virtio-blk device is broken");
+        }
+        let header = self.buffer.as_mut_ptr::<BlkRequestHeader>();
+        let status = (self.buffer.as_u64() + 16) as *mut u8;
+        unsafe {
+            header.write(BlkRequestHeader { request_type, reserved: 0, sector });
+            status.write_volatile(0xFF);
+        }
+
+        let phys = self.buffer_phys.as_u64();
+        self.queue.push(&[
+            Buffer { phys, len: core::mem::size_of::<BlkRequestHeader>() as u32, device_writable: false },
+            // 読み込みではデバイスがデータ領域に書き込む
+            Buffer { phys: phys + 4096, len: len as u32, device_writable: request_type == VIRTIO_BLK_T_IN },
+            Buffer { phys: phys + 16, len: 1, device_writable: true },
+        ])?;
+        self.device.notify(self.queue.index());
+
+        // 割り込みで起こされるが、条件は used リングを直接見るので
+        // 割り込みが届かない構成でもタイマー割り込みごとに進む
+        let queue = &self.queue;
+        if polled {
+            if !(0..POLL_SPINS).any(|_| {
+                core::hint::spin_loop();
+                queue.has_used()
+            }) {
+                self.broken = true;
+                return Err("virtio-blk request timed out");
+            }
+        } else {
+            COMPLETION.wait_until(|| queue.has_used());
+        }
+        self.queue.pop_used();
+
+        match unsafe { status.read_volatile() } {
+            VIRTIO_BLK_S_OK => Ok(()),
+            _ => Err("virtio-blk I/O error"),
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        "virtio-blk"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock();
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE).enumerate() {
+            let sector = lba + (i * MAX_SECTORS_PER_REQUEST) as u64;
+            if sector + (chunk.len() / SECTOR_SIZE) as u64 > self.sectors {
+                return Err("Sector out of range");
+            }
+            inner.submit(VIRTIO_BLK_T_IN, sector, chunk.len(), false)?;
+            let data = (inner.buffer.as_u64() + 4096) as *const u8;
+            unsafe {
+                core::ptr::copy_nonoverlapping(data, chunk.as_mut_ptr(), chunk.len());
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.write_chunks(&mut self.inner.lock(), lba, buf, false)
+    }
+
+    fn write_sectors_polled(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let mut inner = self.inner.try_lock().ok_or("virtio-blk busy")?;
+        self.write_chunks(&mut inner, lba, buf, true)
+    }
+}
+
+impl VirtioBlk {
+    fn write_chunks(&self, inner: &mut Inner, lba: u64, buf: &[u8], polled: bool) -> Result<(), &'static str> {
+        for (i, chunk) in buf.chunks(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE).enumerate() {
+            let sector = lba + (i * MAX_SECTORS_PER_REQUEST) as u64;
+            if sector + (chunk.len() / SECTOR_SIZE) as u64 > self.sectors {
+                return Err("Sector out of range");
+            }
+            let data = (inner.buffer.as_u64() + 4096) as *mut u8;
+            unsafe {
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), data, chunk.len());
+            }
+            inner.submit(VIRTIO_BLK_T_OUT, sector, chunk.len(), polled)?;
+        }
+        Ok(())
+    }
+}
+
+fn handle_interrupt() {
+    if let Some(device) = DEVICE.get() {
+        if device.read_isr() & 1 != 0 {
+            COMPLETION.wake_all();
+        }
+    }
+}
+
+fn setup(info: &pci::PciDevice) -> Result<VirtioBlk, &'static str> {
+    let io_base = match info.bars[0] {
+        pci::Bar::Io { port, .. } => port,
+        _ => return Err("BAR0 is not an I/O port"),
+    };
+    info.address.enable_bus_master();
+
+    // 追加機能は使わない
+    let (device, _) = LegacyDevice::init(io_base, 0);
+    let queue = device.setup_queue(0).map_err(|e| {
+        device.fail();
+        e
+    })?;
+    let (buffer, buffer_phys) = crate::memory::alloc_dma(2).map_err(|e| {
+        device.fail();
+        e
+    })?;
+
+    let sectors = (device.config_u32(CONFIG_CAPACITY + 4) as u64) << 32
+        | device.config_u32(CONFIG_CAPACITY) as u64;
+
+    DEVICE.call_once(|| device);
+    if let Err(e) = crate::interrupts::register_irq(info.irq_line, handle_interrupt) {
+        crate::warn!("virtio-blk: IRQ {} unavailable ({}), polling instead", info.irq_line, e);
+    }
+
+    device.driver_ok();
+
+    Ok(VirtioBlk {
+        inner: Mutex::new(Inner { device, queue, buffer, buffer_phys, broken: false }),
+        sectors,
+    })
+}
+
+/// virtio-blk デバイスを探して初期化し、ブロックデバイスとして登録する
+pub fn init() -> Result<(), &'static str> {
+    let info = pci::claim_id("virtio-blk", VIRTIO_VENDOR_ID, VIRTIO_BLK_DEVICE_ID)
+        .ok_or("No virtio-blk device")?;
+    match setup(&info) {
+        Ok(blk) => {
+            block::register(Arc::new(blk));
+            Ok(())
+        }
+        Err(e) => {
+            pci::release(info.address);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::drivers::pci;
+use crate::drivers::virtio::{Buffer, LegacyDevice, Virtqueue, VIRTIO_VENDOR_ID};
+use crate::net::{self, MacAddress, NetworkDevice};
+
+/// transitional (レガシーインターフェース対応) の virtio-net
+const VIRTIO_NET_DEVICE_ID: u16 = 0x1000;
+
+/// デバイス設定に MAC アドレスがある
+const VIRTIO_NET_F_MAC: u32 = 1 << 5;
+
+// デバイス固有設定
+const CONFIG_MAC: u16 = 0x00;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+/// 各フレームの前に付く virtio_net_hdr (MRG_RXBUF なしなら 10 バイト)
+const NET_HEADER_SIZE: usize = 10;
+/// 受信バッファ1つの大きさ (ヘッダ + 最大フレーム長が収まる)
+const RX_BUFFER_SIZE: usize = 2048;
+/// 受信キューに積んでおくバッファ数
+const RX_BUFFER_COUNT: usize = 16;
+/// 送信完了を待つときに used リングを見る回数の上限
+const TX_POLL_SPINS: usize = 100_000_000;
+
+static DEVICE: spin::Once<LegacyDevice> = spin::Once::new();
+
+struct RxBuffer {
+    virt: VirtAddr,
+    phys: PhysAddr,
+}
+
+struct Inner {
+    device: LegacyDevice,
+    rx_queue: Virtqueue,
+    tx_queue: Virtqueue,
+    rx_buffers: Vec<RxBuffer>,
+    /// ディスクリプタ番号 → rx_buffers の添字
+    rx_by_desc: Vec<Option<usize>>,
+    // 送信用のバウンスバッファ (先頭にヘッダ、オフセット 16 からフレーム)
+    tx_buffer: VirtAddr,
+    tx_buffer_phys: PhysAddr,
+    /// 送信完了待ちがタイムアウトした (デバイスがバウンスバッファを使っているかもしれないので以後送らない)
+    tx_broken: bool,
+}
+
+pub struct VirtioNet {
+    inner: Mutex<Inner>,
+    mac: MacAddress,
+}
+
+impl Inner {
+    /// 受信バッファを受信キューに (再び) 積む
+    fn post_rx(&mut self, index: usize) -> Result<(), &'static str> {
+        let buffer = &self.rx_buffers[index];
+        let head = self.rx_queue.push(&[Buffer {
+            phys: buffer.phys.as_u64(),
+            len: RX_BUFFER_SIZE as u32,
+            device_writable: true,
+        }])?;
+        self.rx_by_desc[head as usize] = Some(index);
+        Ok(())
+    }
+}
+
+impl NetworkDevice for VirtioNet {
+    fn name(&self) -> &str {
+        "virtio-net"
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<(), &'static str> {
+        if frame.len() > 4096 - 16 {
+            return Err("Frame too large");
+        }
+        let mut inner = self.inner.lock();
+        if inner.tx_broken {
+            return Err("virtio-net transmit queue is broken");
+        }
+        unsafe {
+            core::ptr::write_bytes(inner.tx_buffer.as_mut_ptr::<u8>(), 0, NET_HEADER_SIZE);
+            core::ptr::copy_nonoverlapping(frame.as_ptr(),
+                (inner.tx_buffer.as_u64() + 16) as *mut u8, frame.len());
+        }
+
+        // レガシーインターフェースではヘッダを別のディスクリプタにする
+        let phys = inner.tx_buffer_phys.as_u64();
+        inner.tx_queue.push(&[
+            Buffer { phys, len: NET_HEADER_SIZE as u32, device_writable: false },
+            Buffer { phys: phys + 16, len: frame.len() as u32, device_writable: false },
+        ])?;
+        inner.device.notify(TX_QUEUE);
+
+        // バウンスバッファは1つなので送信完了まで待つ (QEMU ではほぼ即座に終わる)
+        let queue = &inner.tx_queue;
+        if !(0..TX_POLL_SPINS).any(|_| {
+            core::hint::spin_loop();
+            queue.has_used()
+        }) {
+            inner.tx_broken = true;
+            return Err("transmit timed out");
+        }
+        inner.tx_queue.pop_used();
+        Ok(())
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock();
+        let (head, len) = inner.rx_queue.pop_used()?;
+        let index = inner.rx_by_desc[head as usize].take()?;
+
+        let len = (len as usize).min(RX_BUFFER_SIZE);
+        let frame = if len > NET_HEADER_SIZE {
+            let buffer = &inner.rx_buffers[index];
+            let data = unsafe {
+                core::slice::from_raw_parts((buffer.virt.as_u64() as usize + NET_HEADER_SIZE) as *const u8,
+                    len - NET_HEADER_SIZE)
+            };
+            data.to_vec()
+        } else {
+            Vec::new()
+        };
+
+        if inner.post_rx(index).is_ok() {
+            inner.device.notify(RX_QUEUE);
+        }
+        Some(frame)
+    }
+}
+
+fn handle_interrupt() {
+    if let Some(device) = DEVICE.get() {
+        if device.read_isr() & 1 != 0 {
+            net::rx_notify();
+        }
+    }
+}
+
+fn setup(info: &pci::PciDevice) -> Result<VirtioNet, &'static str> {
+    let io_base = match info.bars[0] {
+        pci::Bar::Io { port, .. } => port,
+        _ => return Err("BAR0 is not an I/O port"),
+    };
+    info.address.enable_bus_master();
+
+    let (device, features) = LegacyDevice::init(io_base, VIRTIO_NET_F_MAC);
+    if features & VIRTIO_NET_F_MAC == 0 {
+        device.fail();
+        return Err("Device has no MAC address");
+    }
+    let mut mac = [0u8; 6];
+    for (i, byte) in mac.iter_mut().enumerate() {
+        *byte = device.config_u8(CONFIG_MAC + i as u16);
+    }
+
+    let result = (|| {
+        let rx_queue = device.setup_queue(RX_QUEUE)?;
+        let tx_queue = device.setup_queue(TX_QUEUE)?;
+        let (tx_buffer, tx_buffer_phys) = crate::memory::alloc_dma(1)?;
+
+        // 1ページに2つずつ受信バッファを置く
+        let mut rx_buffers = Vec::with_capacity(RX_BUFFER_COUNT);
+        for _ in 0..RX_BUFFER_COUNT / 2 {
+            let (virt, phys) = crate::memory::alloc_dma(1)?;
+            rx_buffers.push(RxBuffer { virt, phys });
+            rx_buffers.push(RxBuffer { virt: virt + RX_BUFFER_SIZE as u64, phys: phys + RX_BUFFER_SIZE as u64 });
+        }
+
+        let mut inner = Inner {
+            device,
+            rx_by_desc: vec![None; rx_queue.size() as usize],
+            rx_queue,
+            tx_queue,
+            rx_buffers,
+            tx_buffer,
+            tx_buffer_phys,
+            tx_broken: false,
+        };
+        for index in 0..inner.rx_buffers.len() {
+            inner.post_rx(index)?;
+        }
+        Ok(inner)
+    })();
+    let inner = result.map_err(|e: &'static str| {
+        device.fail();
+        e
+    })?;
+
+    DEVICE.call_once(|| device);
+    if let Err(e) = crate::interrupts::register_irq(info.irq_line, handle_interrupt) {
+        crate::warn!("virtio-net: IRQ {} unavailable ({})", info.irq_line, e);
+    }
+
+    device.driver_ok();
+    device.notify(RX_QUEUE);
+
+    Ok(VirtioNet {
+        inner: Mutex::new(inner),
+        mac: MacAddress(mac),
+    })
+}
+
+/// virtio-net デバイスを探して初期化し、ネットワークデバイスとして登録する
+pub fn init() -> Result<(), &'static str> {
+    let info = pci::claim_id("virtio-net", VIRTIO_VENDOR_ID, VIRTIO_NET_DEVICE_ID)
+        .ok_or("No virtio-net device")?;
+    match setup(&info) {
+        Ok(nic) => net::register_device(Arc::new(nic)),
+        Err(e) => {
+            pci::release(info.address);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::poll::{PollTable, POLLIN};
+use crate::sync::{IrqMutex, WaitQueue};
+use super::{console, framebuffer, vga};
+
+/// 仮想端末の数 (Alt+F1..F4 で切り替える)
+pub const NR_VTS: usize = 4;
+/// カーネルのメッセージとログを出す端末 (VT1)
+pub const KERNEL_VT: usize = 0;
+/// ユーザープロセスの標準入出力をつなぐ端末 (VT2)
+pub const CONSOLE_VT: usize = 1;
+/// 端末ごとの入力バッファの大きさ
+const INPUT_BUFFER_SIZE: usize = 256;
+
+/// 画面に表示し、キーボードの入力を受け取っている端末
+static ACTIVE: AtomicUsize = AtomicUsize::new(KERNEL_VT);
+// キーボードの後半処理から書くので割り込みを止めて取る
+static INPUTS: IrqMutex<[InputQueue; NR_VTS]> = IrqMutex::new([const { InputQueue::new() }; NR_VTS]);
+// 入力が来るのを待つスレッド (端末ごと)
+static WAITERS: [WaitQueue; NR_VTS] = [const { WaitQueue::new() }; NR_VTS];
+
+/// 固定長の入力バイトのリングバッファ (割り込み中に確保しない)
+struct InputQueue {
+    bytes: [u8; INPUT_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl InputQueue {
+    const fn new() -> Self {
+        Self { bytes: [0; INPUT_BUFFER_SIZE], head: 0, len: 0 }
+    }
+
+    /// 一杯なら捨てる
+    fn push(&mut self, byte: u8) {
+        if self.len < INPUT_BUFFER_SIZE {
+            self.bytes[(self.head + self.len) % INPUT_BUFFER_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.head];
+        self.head = (self.head + 1) % INPUT_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// 表示する端末を切り替え、その端末の画面を描き直す
+pub fn switch(vt: usize) -> Result<(), &'static str> {
+    if vt >= NR_VTS {
+        return Err("No such virtual terminal");
+    }
+    if ACTIVE.swap(vt, Ordering::SeqCst) != vt {
+        vga::redraw();
+        framebuffer::redraw();
+        console::flush();
+        crate::debug!("Switched to tty{}", vt + 1);
+    }
+    Ok(())
+}
+
+/// キーボードから来た入力を表示中の端末に渡す
+pub fn push_input(bytes: &[u8]) {
+    let vt = active();
+    {
+        let mut inputs = INPUTS.lock();
+        for &byte in bytes {
+            inputs[vt].push(byte);
+        }
+    }
+    WAITERS[vt].wake_all();
+}
+
+/// vt に入力が来るまで呼び出したスレッドをブロックしてから読めるだけ読む
+/// (O_NONBLOCK なら呼び出し側が poll で確かめて EAGAIN を返す)
+pub fn read(vt: usize, buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    loop {
+        WAITERS[vt].wait_until(|| has_input(vt));
+        // 同じ端末を読む別のスレッドに先を越されたらもう一度待つ
+        match try_read(vt, buf) {
+            0 => continue,
+            count => return count,
+        }
+    }
+}
+
+/// vt の入力を読めるだけ読む (なければ 0、ブロックしない)
+pub fn try_read(vt: usize, buf: &mut [u8]) -> usize {
+    let mut inputs = INPUTS.lock();
+    let mut count = 0;
+    while count < buf.len() {
+        match inputs[vt].pop() {
+            Some(byte) => {
+                buf[count] = byte;
+                count += 1;
+            }
+            None => break,
+        }
+    }
+    count
+}
+
+pub fn has_input(vt: usize) -> bool {
+    INPUTS.lock()[vt].len != 0
+}
+
+/// 入力があれば POLLIN
+pub fn poll<'a>(vt: usize, table: &mut PollTable<'a>) -> i16 {
+    table.register(&WAITERS[vt]);
+    if has_input(vt) { POLLIN } else { 0 }
+}
@@ -0,0 +1,219 @@
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags as Flags;
+use x86_64::VirtAddr;
+use crate::errno::Errno;
+use crate::memory::{Vma, FRAME_SIZE, USER_HEAP_BASE, USER_IMAGE_BASE};
+use crate::process::{Process, USER_STACK_SIZE};
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3E;
+const PT_LOAD: u32 = 1;
+const PF_W: u32 = 0x2;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PAGE_SIZE: u64 = FRAME_SIZE;
+
+/// メモリに読み込む区間 (PT_LOAD)
+#[derive(Debug, Clone, Copy)]
+pub struct Segment<'a> {
+    pub vaddr: u64,
+    pub mem_size: u64,
+    /// ファイル上の内容 (mem_size に足りない分はゼロ埋めする、.bss など)
+    pub data: &'a [u8],
+    pub writable: bool,
+}
+
+/// 検証済みの ELF 実行ファイル
+#[derive(Debug)]
+pub struct ElfImage<'a> {
+    pub entry: u64,
+    pub segments: Vec<Segment<'a>>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// 静的リンクされた x86_64 の ELF64 実行ファイルを解析する (PIE や動的リンクは扱わない)
+/// 読み込む区間はユーザーイメージ領域 (USER_IMAGE_BASE から USER_HEAP_BASE まで) に収まっていること
+pub fn parse(data: &[u8]) -> Result<ElfImage<'_>, Errno> {
+    if data.len() < HEADER_SIZE || &data[..4] != ELF_MAGIC
+        || data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB {
+        return Err(Errno::ENOEXEC);
+    }
+    if read_u16(data, 16) != ET_EXEC || read_u16(data, 18) != EM_X86_64 {
+        return Err(Errno::ENOEXEC);
+    }
+
+    let entry = read_u64(data, 24);
+    let ph_offset = read_u64(data, 32) as usize;
+    let ph_size = read_u16(data, 54) as usize;
+    let ph_count = read_u16(data, 56) as usize;
+    if ph_size != PROGRAM_HEADER_SIZE {
+        return Err(Errno::ENOEXEC);
+    }
+    let table_end = ph_count.checked_mul(ph_size).and_then(|len| len.checked_add(ph_offset));
+    if table_end.is_none_or(|end| end > data.len()) {
+        return Err(Errno::ENOEXEC);
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..ph_count {
+        let header = &data[ph_offset + i * ph_size..ph_offset + (i + 1) * ph_size];
+        if read_u32(header, 0) != PT_LOAD {
+            continue;
+        }
+        let flags = read_u32(header, 4);
+        let offset = read_u64(header, 8) as usize;
+        let vaddr = read_u64(header, 16);
+        let file_size = read_u64(header, 32) as usize;
+        let mem_size = read_u64(header, 40);
+
+        let file_end = offset.checked_add(file_size).ok_or(Errno::ENOEXEC)?;
+        let mem_end = vaddr.checked_add(mem_size).ok_or(Errno::ENOEXEC)?;
+        if file_end > data.len() || file_size as u64 > mem_size {
+            return Err(Errno::ENOEXEC);
+        }
+        if vaddr < USER_IMAGE_BASE || mem_end > USER_HEAP_BASE {
+            return Err(Errno::ENOEXEC);
+        }
+        segments.push(Segment {
+            vaddr,
+            mem_size,
+            data: &data[offset..file_end],
+            writable: flags & PF_W != 0,
+        });
+    }
+
+    // 区間ごとにページの保護を変えるので、同じページを共有する配置は扱わない
+    segments.sort_by_key(|segment| segment.vaddr);
+    for pair in segments.windows(2) {
+        let previous_end = (pair[0].vaddr + pair[0].mem_size).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        if pair[1].vaddr < previous_end {
+            return Err(Errno::ENOEXEC);
+        }
+    }
+
+    let entry_mapped = segments.iter()
+        .any(|segment| entry >= segment.vaddr && entry < segment.vaddr + segment.mem_size);
+    if !entry_mapped {
+        return Err(Errno::ENOEXEC);
+    }
+    Ok(ElfImage { entry, segments })
+}
+
+/// 各区間をページ単位でマップして内容をコピーし、VMA としてプロセスに登録する
+pub fn load(image: &ElfImage, process: &mut Process) -> Result<(), Errno> {
+    for segment in &image.segments {
+        let start = segment.vaddr / PAGE_SIZE * PAGE_SIZE;
+        let end = (segment.vaddr + segment.mem_size).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let mut flags = Flags::PRESENT | Flags::USER_ACCESSIBLE;
+        if segment.writable {
+            flags |= Flags::WRITABLE;
+        }
+
+        // 途中で失敗しても呼び出し側が VMA を見てマップを外せるよう、先に登録する
+        process.vmas.push(Vma::anonymous(VirtAddr::new(start), VirtAddr::new(end), flags));
+        let mut page = start;
+        while page < end {
+            let mut contents = [0u8; PAGE_SIZE as usize];
+            // このページに入るファイル上の内容
+            let copy_start = segment.vaddr.max(page);
+            let copy_end = (segment.vaddr + segment.data.len() as u64).min(page + PAGE_SIZE);
+            if copy_start < copy_end {
+                let src = (copy_start - segment.vaddr) as usize..(copy_end - segment.vaddr) as usize;
+                let dst = (copy_start - page) as usize..(copy_end - page) as usize;
+                contents[dst].copy_from_slice(&segment.data[src]);
+            }
+            crate::memory::map_page_with_data(VirtAddr::new(page), flags, &contents)
+                .map_err(|_| Errno::ENOMEM)?;
+            page += PAGE_SIZE;
+            process.mapped_pages += 1;
+        }
+    }
+    process.main_thread_mut().context.rip = image.entry;
+    Ok(())
+}
+
+/// ELF 実行ファイルからユーザープロセスを作る (実行キューには積まない)
+pub fn create_process(name: &str, data: &[u8]) -> Result<Process, Errno> {
+    create_process_from_image(name, &parse(data)?)
+}
+
+/// 解析済みのイメージを読み込み、ユーザースタックを付けたプロセスを作る (argv[0] は name)
+pub fn create_process_from_image(name: &str, image: &ElfImage) -> Result<Process, Errno> {
+    create_process_with_args(name, image, &[name], &[])
+}
+
+/// create_process_from_image と同じだが、プログラムに渡す引数と環境変数を指定する
+pub fn create_process_with_args(name: &str, image: &ElfImage, argv: &[&str], envp: &[&str])
+    -> Result<Process, Errno> {
+    let stack = crate::memory::allocate_zeroed_pages((USER_STACK_SIZE / PAGE_SIZE) as usize)
+        .ok_or(Errno::ENOMEM)?;
+    let stack_top = stack + USER_STACK_SIZE;
+    let mut process = Process::new(image.entry)
+        .with_name(name)
+        .with_user_stack(stack_top);
+    let result = load(image, &mut process).and_then(|()| {
+        let (rsp, contents) = initial_stack(stack_top.as_u64(), argv, envp, image.entry)?;
+        crate::uaccess::user_access_begin();
+        unsafe { core::ptr::copy_nonoverlapping(contents.as_ptr(), rsp as *mut u8, contents.len()) };
+        crate::uaccess::user_access_end();
+        process.main_thread_mut().context.rsp = rsp;
+        Ok(())
+    });
+    if let Err(e) = result {
+        process.release_user_memory();
+        return Err(e);
+    }
+    Ok(process)
+}
+
+/// 初期スタックの補助ベクタに積む項目の数 (AT_NULL を含む)
+const AUXV_ENTRIES: usize = 3;
+
+/// System V の初期スタック (argc, argv, envp, auxv と文字列) の内容を作る
+/// スタックの上端が stack_top のとき、積んだ後の rsp (16 バイト境界) と rsp から上端までの内容を返す
+pub fn initial_stack(stack_top: u64, argv: &[&str], envp: &[&str], entry: u64) -> Result<(u64, Vec<u8>), Errno> {
+    use crate::abi::{AT_ENTRY, AT_NULL, AT_PAGESZ};
+
+    let strings_len: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + AUXV_ENTRIES * 2;
+    let size = (strings_len + words * 8).div_ceil(16) * 16;
+    // スタックの大半を引数で埋めてしまうと動けないので、1/4 までにする
+    if size as u64 > USER_STACK_SIZE / 4 {
+        return Err(Errno::E2BIG);
+    }
+    let rsp = stack_top - size as u64;
+
+    let mut contents = alloc::vec![0u8; size];
+    // 文字列は上端に詰めて置き、ポインタの配列はその下に置く
+    let mut string_offset = size - strings_len;
+    let mut pointers = Vec::with_capacity(words);
+    pointers.push(argv.len() as u64);
+    for list in [argv, envp] {
+        for s in list {
+            contents[string_offset..string_offset + s.len()].copy_from_slice(s.as_bytes());
+            pointers.push(rsp + string_offset as u64);
+            string_offset += s.len() + 1;
+        }
+        pointers.push(0);
+    }
+    pointers.extend_from_slice(&[AT_PAGESZ, PAGE_SIZE, AT_ENTRY, entry, AT_NULL, 0]);
+    for (i, value) in pointers.iter().enumerate() {
+        contents[i * 8..i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    }
+    Ok((rsp, contents))
+}
@@ -0,0 +1,155 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::drivers::mouse::MouseEvent;
+use crate::softirq::{self, Softirq};
+use crate::sync::IrqMutex;
+
+/// 配送待ちにできるイベントの数 (割り込み中に確保しないよう固定長)
+const EVENT_QUEUE_SIZE: usize = 128;
+/// 登録できる購読者の数
+pub const MAX_SUBSCRIBERS: usize = 16;
+
+// 購読するイベントの種類 (ビットマスク)
+pub const EVENT_KEY: u32 = 1 << 0;
+pub const EVENT_MOUSE: u32 = 1 << 1;
+pub const EVENT_TIMER: u32 = 1 << 2;
+pub const EVENT_NET_LINK: u32 = 1 << 3;
+pub const EVENT_DEVICE: u32 = 1 << 4;
+pub const EVENT_ALL: u32 = u32::MAX;
+
+/// ドライバが発行するイベント
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// デコード済みのキー入力
+    Key(char),
+    Mouse(MouseEvent),
+    /// 起動から1秒ごと (値は経過秒数)
+    Timer(u64),
+    /// ネットワークインターフェースのリンク状態
+    NetLink { up: bool },
+    /// ドライバの初期化が終わり、デバイスが使えるようになった
+    DeviceAdded(&'static str),
+    DeviceRemoved(&'static str),
+}
+
+impl Event {
+    /// 購読のフィルタと照らし合わせる種類のビット
+    pub fn kind(&self) -> u32 {
+        match self {
+            Event::Key(_) => EVENT_KEY,
+            Event::Mouse(_) => EVENT_MOUSE,
+            Event::Timer(_) => EVENT_TIMER,
+            Event::NetLink { .. } => EVENT_NET_LINK,
+            Event::DeviceAdded(_) | Event::DeviceRemoved(_) => EVENT_DEVICE,
+        }
+    }
+}
+
+/// 購読者 (名前は登録の重複を防ぐのと、表示用)
+#[derive(Clone, Copy)]
+struct Subscriber {
+    name: &'static str,
+    filter: u32,
+    handler: fn(&Event),
+}
+
+/// 固定長のリングバッファ
+struct EventQueue {
+    events: [Option<Event>; EVENT_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl EventQueue {
+    const fn new() -> Self {
+        Self { events: [None; EVENT_QUEUE_SIZE], head: 0, len: 0 }
+    }
+
+    fn push(&mut self, event: Event) -> bool {
+        if self.len == EVENT_QUEUE_SIZE {
+            return false;
+        }
+        self.events[(self.head + self.len) % EVENT_QUEUE_SIZE] = Some(event);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % EVENT_QUEUE_SIZE;
+        self.len -= 1;
+        event
+    }
+}
+
+// どちらも割り込みハンドラから取るので IrqMutex にする
+static QUEUE: IrqMutex<EventQueue> = IrqMutex::new(EventQueue::new());
+static SUBSCRIBERS: IrqMutex<[Option<Subscriber>; MAX_SUBSCRIBERS]> = IrqMutex::new([None; MAX_SUBSCRIBERS]);
+static PUBLISHED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    softirq::open(Softirq::Events, dispatch);
+}
+
+/// イベントを発行する (割り込みハンドラからも呼べる)
+/// 購読者には後半処理で配送するので、発行した側はすぐに戻る
+pub fn publish(event: Event) {
+    let queued = QUEUE.lock().push(event);
+    if queued {
+        PUBLISHED.fetch_add(1, Ordering::Relaxed);
+        softirq::raise(Softirq::Events);
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// filter に含まれる種類のイベントを handler で受け取る
+/// handler は割り込みの後半処理で呼ばれるので、ブロックしないこと
+pub fn subscribe(name: &'static str, filter: u32, handler: fn(&Event)) -> Result<(), &'static str> {
+    let mut subscribers = SUBSCRIBERS.lock();
+    if subscribers.iter().flatten().any(|subscriber| subscriber.name == name) {
+        return Err("Subscriber already registered");
+    }
+    let slot = subscribers.iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or("Too many subscribers")?;
+    *slot = Some(Subscriber { name, filter, handler });
+    Ok(())
+}
+
+pub fn unsubscribe(name: &str) -> bool {
+    let mut subscribers = SUBSCRIBERS.lock();
+    match subscribers.iter_mut().find(|slot| slot.is_some_and(|subscriber| subscriber.name == name)) {
+        Some(slot) => {
+            *slot = None;
+            true
+        }
+        None => false,
+    }
+}
+
+/// 溜まったイベントを購読者に配送する (後半処理から呼ばれる)
+/// ハンドラの中から発行されたイベントも続けて配送する
+pub fn dispatch() {
+    loop {
+        let event = QUEUE.lock().pop();
+        let Some(event) = event else {
+            break;
+        };
+        // ハンドラが subscribe しても固まらないよう、ロックを外してから呼ぶ
+        let subscribers = *SUBSCRIBERS.lock();
+        for subscriber in subscribers.iter().flatten() {
+            if subscriber.filter & event.kind() != 0 {
+                (subscriber.handler)(&event);
+            }
+        }
+    }
+}
+
+/// (発行したイベント数, キューが一杯で捨てた数)
+pub fn stats() -> (u64, u64) {
+    (PUBLISHED.load(Ordering::Relaxed), DROPPED.load(Ordering::Relaxed))
+}
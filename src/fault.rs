@@ -0,0 +1,116 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// わざと失敗させられる場所 (エラー処理の経路を試すため)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum FaultPoint {
+    /// 物理フレームの割り当て
+    Frame = 0,
+    /// ヒープの割り当て (alloc が null を返す)
+    Heap = 1,
+    /// ファイルシステムの操作 (EIO を返す)
+    Vfs = 2,
+}
+
+pub const FAULT_POINTS: [FaultPoint; 3] = [FaultPoint::Frame, FaultPoint::Heap, FaultPoint::Vfs];
+
+/// times に渡すと何回でも失敗させる
+pub const UNLIMITED: usize = usize::MAX;
+
+impl FaultPoint {
+    /// コマンドラインの fail_<名前>=<間隔> で使う名前
+    pub fn name(self) -> &'static str {
+        match self {
+            FaultPoint::Frame => "frame",
+            FaultPoint::Heap => "heap",
+            FaultPoint::Vfs => "vfs",
+        }
+    }
+
+    pub fn from_u32(value: u32) -> Option<Self> {
+        FAULT_POINTS.get(value as usize).copied()
+    }
+}
+
+/// 失敗させる間隔と回数
+/// アロケータの中からも呼ばれるので、ロックも割り当ても使わない
+struct FaultAttr {
+    /// interval 回に1回失敗させる (0 なら無効)
+    interval: AtomicUsize,
+    /// 残りの失敗回数 (UNLIMITED なら減らさない)
+    remaining: AtomicUsize,
+    calls: AtomicUsize,
+    injected: AtomicU64,
+}
+
+impl FaultAttr {
+    const fn new() -> Self {
+        Self {
+            interval: AtomicUsize::new(0),
+            remaining: AtomicUsize::new(0),
+            calls: AtomicUsize::new(0),
+            injected: AtomicU64::new(0),
+        }
+    }
+}
+
+static ATTRS: [FaultAttr; 3] = [const { FaultAttr::new() }; 3];
+
+/// point を interval 回に1回、あと times 回失敗させる (interval が 0 なら止める)
+pub fn configure(point: FaultPoint, interval: usize, times: usize) {
+    let attr = &ATTRS[point as usize];
+    // 数え直してから有効にする
+    attr.interval.store(0, Ordering::SeqCst);
+    attr.calls.store(0, Ordering::SeqCst);
+    attr.remaining.store(times, Ordering::SeqCst);
+    attr.interval.store(interval, Ordering::SeqCst);
+}
+
+pub fn disable(point: FaultPoint) {
+    configure(point, 0, 0);
+}
+
+/// 今回の point の操作を失敗させるか (フックから呼ぶ)
+#[inline]
+pub fn should_fail(point: FaultPoint) -> bool {
+    let attr = &ATTRS[point as usize];
+    let interval = attr.interval.load(Ordering::Relaxed);
+    if interval == 0 {
+        return false;
+    }
+    if (attr.calls.fetch_add(1, Ordering::Relaxed) + 1) % interval != 0 {
+        return false;
+    }
+    let allowed = attr.remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| match remaining {
+        0 => None,
+        UNLIMITED => Some(UNLIMITED),
+        remaining => Some(remaining - 1),
+    });
+    if allowed.is_err() {
+        return false;
+    }
+    attr.injected.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// これまでに point でわざと失敗させた回数
+pub fn injected(point: FaultPoint) -> u64 {
+    ATTRS[point as usize].injected.load(Ordering::Relaxed)
+}
+
+/// コマンドラインの fail_frame=<間隔> などで起動時から有効にする (cmdline::init の後に呼ぶ)
+pub fn init() {
+    for point in FAULT_POINTS {
+        let key = alloc::format!("fail_{}", point.name());
+        let Some(value) = crate::cmdline::get(&key) else {
+            continue;
+        };
+        match value.parse::<usize>() {
+            Ok(interval) => {
+                configure(point, interval, UNLIMITED);
+                crate::warn!("Fault injection: failing every {} {} operation(s)", interval, point.name());
+            }
+            Err(_) => crate::warn!("Invalid value for {}: {}", key, value),
+        }
+    }
+}
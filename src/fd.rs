@@ -0,0 +1,342 @@
+use alloc::sync::Arc;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicI32, Ordering};
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::drivers::vt;
+use crate::pipe::{PipeReader, PipeWriter};
+use crate::filesystem::{Stat, O_ACCMODE, O_APPEND, O_NONBLOCK, O_RDONLY, O_WRONLY, S_IFCHR, S_IFIFO, S_IFSOCK};
+use crate::net::socket::Socket;
+use crate::shm::ShmHandle;
+use crate::mqueue::MqHandle;
+use crate::errno::Errno;
+use crate::poll::{PollTable, POLLERR, POLLHUP, POLLIN, POLLOUT};
+
+/// 1プロセスあたりのファイルディスクリプタ数上限
+pub const MAX_FDS: usize = 64;
+
+// プロセスが存在しないカーネルコンテキストで使うディスクリプタテーブル
+static KERNEL_FDS: Mutex<Option<FdTable>> = Mutex::new(None);
+
+/// ディスクリプタが指すオープンファイル記述
+pub enum FileObject {
+    /// ユーザー用の仮想端末 (VT2) のキーボード入力
+    ConsoleIn,
+    /// ユーザー用の仮想端末 (VT2) の画面
+    ConsoleOut,
+    /// VFSのオープンファイル (VFS側のインデックス)
+    File(i32),
+    PipeRead(PipeReader),
+    PipeWrite(PipeWriter),
+    Socket(Socket),
+    /// shm_open で開いた共有メモリオブジェクト
+    Shm(ShmHandle),
+    /// mq_open で開いたメッセージキュー (読み書きは mq_timedsend/mq_timedreceive で行う)
+    MessageQueue(MqHandle),
+}
+
+impl FileObject {
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        match self {
+            FileObject::ConsoleIn => Ok(vt::read(vt::CONSOLE_VT, buf)),
+            FileObject::File(vfs_fd) => crate::filesystem::read(*vfs_fd, buf),
+            FileObject::PipeRead(reader) => reader.read(buf),
+            FileObject::Socket(socket) => socket.read(buf).map_err(|_| Errno::ECONNRESET),
+            FileObject::Shm(shm) => shm.read(buf),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// オフセットを指定して読む (ファイル以外はシークできない)
+    pub fn read_at(&self, buf: &mut [u8], offset: usize) -> Result<usize, Errno> {
+        match self {
+            FileObject::File(vfs_fd) => crate::filesystem::read_at(*vfs_fd, buf, offset),
+            FileObject::Shm(shm) => shm.read_at(buf, offset),
+            _ => Err(Errno::ESPIPE),
+        }
+    }
+
+    /// オフセットを指定して書く (ファイル以外はシークできない)
+    pub fn write_at(&self, buf: &[u8], offset: usize) -> Result<usize, Errno> {
+        match self {
+            FileObject::File(vfs_fd) => crate::filesystem::write_at(*vfs_fd, buf, offset),
+            FileObject::Shm(shm) => shm.write_at(buf, offset),
+            _ => Err(Errno::ESPIPE),
+        }
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        match self {
+            FileObject::ConsoleOut => {
+                let s = core::str::from_utf8(buf).map_err(|_| Errno::EINVAL)?;
+                crate::drivers::console::write_vt(vt::CONSOLE_VT, s);
+                Ok(buf.len())
+            }
+            FileObject::File(vfs_fd) => crate::filesystem::write(*vfs_fd, buf),
+            FileObject::PipeWrite(writer) => writer.write(buf),
+            FileObject::Socket(socket) => socket.write(buf).map_err(|_| Errno::EPIPE),
+            FileObject::Shm(shm) => shm.write(buf),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    pub fn stat(&self) -> Option<Stat> {
+        match self {
+            FileObject::File(vfs_fd) => crate::filesystem::fstat(*vfs_fd).ok(),
+            FileObject::ConsoleIn | FileObject::ConsoleOut => Some(Stat {
+                st_mode: S_IFCHR | 0o600,
+                ..Stat::default()
+            }),
+            FileObject::PipeRead(_) | FileObject::PipeWrite(_) => Some(Stat {
+                st_mode: S_IFIFO | 0o600,
+                ..Stat::default()
+            }),
+            FileObject::Socket(_) => Some(Stat {
+                st_mode: S_IFSOCK | 0o600,
+                ..Stat::default()
+            }),
+            FileObject::Shm(shm) => Some(shm.stat()),
+            FileObject::MessageQueue(queue) => Some(queue.stat()),
+        }
+    }
+
+    /// ディレクトリのエントリを読む (getdents64)
+    pub fn getdents(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        match self {
+            FileObject::File(vfs_fd) => crate::filesystem::getdents(*vfs_fd, buf),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    pub fn seek(&self, offset: i64, whence: i32) -> Result<usize, Errno> {
+        match self {
+            FileObject::File(vfs_fd) => crate::filesystem::lseek(*vfs_fd, offset, whence),
+            FileObject::Shm(shm) => shm.seek(offset, whence),
+            _ => Err(Errno::ESPIPE),
+        }
+    }
+
+    /// 読み書きできる状態か (POLLIN など) を返し、変わったときに起こされるよう table に登録する
+    pub fn poll<'a>(&'a self, table: &mut PollTable<'a>) -> i16 {
+        match self {
+            FileObject::ConsoleIn => vt::poll(vt::CONSOLE_VT, table),
+            FileObject::ConsoleOut => POLLOUT,
+            // 通常ファイルやデバイスは常に読み書きできる
+            FileObject::File(_) | FileObject::Shm(_) => POLLIN | POLLOUT,
+            FileObject::PipeRead(reader) => reader.poll(table),
+            FileObject::PipeWrite(writer) => writer.poll(table),
+            FileObject::Socket(socket) => socket.poll(table),
+            FileObject::MessageQueue(queue) => queue.poll(table),
+        }
+    }
+
+    /// サイズを変える (ftruncate、今のところ共有メモリオブジェクトのみ)
+    pub fn truncate(&self, size: usize) -> Result<(), Errno> {
+        match self {
+            FileObject::Shm(shm) => shm.truncate(size),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
+
+impl Drop for FileObject {
+    fn drop(&mut self) {
+        // 最後の参照が消えたときにVFS側も閉じる
+        if let FileObject::File(vfs_fd) = self {
+            let _ = crate::filesystem::close(*vfs_fd);
+        }
+    }
+}
+
+/// fcntl(F_SETFL) で変えられるフラグ (今のところ O_NONBLOCK のみ)
+const SETTABLE_FLAGS: i32 = O_NONBLOCK;
+
+/// オープンファイル記述 (オブジェクトと、open や fcntl で設定するフラグ)
+/// フラグは dup や fork で共有したディスクリプタすべてに効く
+pub struct OpenFile {
+    pub object: FileObject,
+    flags: AtomicI32,
+}
+
+impl OpenFile {
+    pub fn new(object: FileObject, flags: i32) -> Self {
+        Self {
+            object,
+            flags: AtomicI32::new(flags & (O_ACCMODE | O_APPEND | O_NONBLOCK)),
+        }
+    }
+
+    /// アクセスモードと状態フラグ (F_GETFL)
+    pub fn flags(&self) -> i32 {
+        self.flags.load(Ordering::Relaxed)
+    }
+
+    /// 状態フラグを変える (F_SETFL、変えられないフラグは無視する)
+    pub fn set_flags(&self, flags: i32) {
+        let _ = self.flags.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+            Some((old & !SETTABLE_FLAGS) | (flags & SETTABLE_FLAGS))
+        });
+    }
+
+    pub fn nonblocking(&self) -> bool {
+        self.flags() & O_NONBLOCK != 0
+    }
+
+    /// O_NONBLOCK で events がどれも起きていなければ、ブロックする代わりに EAGAIN を返す
+    pub fn check_ready(&self, events: i16) -> Result<(), Errno> {
+        if !self.nonblocking() {
+            return Ok(());
+        }
+        match self.object.poll(&mut PollTable::new(None)) & (events | POLLERR | POLLHUP) {
+            0 => Err(Errno::EAGAIN),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.check_ready(POLLIN)?;
+        self.object.read(buf)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        self.check_ready(POLLOUT)?;
+        match &self.object {
+            // 全部入るまで待たず、空いている分だけ書く
+            FileObject::PipeWrite(writer) if self.nonblocking() => writer.try_write(buf),
+            object => object.write(buf),
+        }
+    }
+}
+
+impl Deref for OpenFile {
+    type Target = FileObject;
+
+    fn deref(&self) -> &FileObject {
+        &self.object
+    }
+}
+
+/// 複数のディスクリプタ (dup, fork) で共有できるよう参照カウントする
+pub type FileRef = Arc<OpenFile>;
+
+#[derive(Clone)]
+pub struct FdTable {
+    entries: Vec<Option<FileRef>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![None; MAX_FDS],
+        }
+    }
+
+    /// stdin/stdout/stderr を設定済みのテーブルを作る
+    pub fn with_std_streams() -> Self {
+        let mut table = Self::new();
+        table.entries[0] = Some(Arc::new(OpenFile::new(FileObject::ConsoleIn, O_RDONLY)));
+        let console_out = Arc::new(OpenFile::new(FileObject::ConsoleOut, O_WRONLY));
+        table.entries[1] = Some(console_out.clone());
+        table.entries[2] = Some(console_out);
+        table
+    }
+
+    /// 空いている最小番号のディスクリプタに割り当てる
+    pub fn install(&mut self, file: FileRef) -> Option<i32> {
+        self.install_below(file, MAX_FDS)
+    }
+
+    /// limit 未満 (RLIMIT_NOFILE) で空いている最小番号のディスクリプタに割り当てる
+    pub fn install_below(&mut self, file: FileRef, limit: usize) -> Option<i32> {
+        let fd = self.entries.iter().take(limit).position(|entry| entry.is_none())?;
+        self.entries[fd] = Some(file);
+        Some(fd as i32)
+    }
+
+    pub fn get(&self, fd: i32) -> Option<FileRef> {
+        if fd < 0 {
+            return None;
+        }
+        self.entries.get(fd as usize)?.clone()
+    }
+
+    /// 同じオープンファイル記述を指す最小番号のディスクリプタを作る
+    pub fn dup(&mut self, fd: i32, limit: usize) -> Option<i32> {
+        let file = self.get(fd)?;
+        self.install_below(file, limit)
+    }
+
+    /// new_fd を old_fd と同じオープンファイル記述に置き換える
+    /// 置き換え前に new_fd が指していたものを返すので、ロック解放後にドロップすること
+    pub fn dup2(&mut self, old_fd: i32, new_fd: i32, limit: usize) -> Result<Option<FileRef>, ()> {
+        let file = self.get(old_fd).ok_or(())?;
+        if new_fd < 0 || new_fd as usize >= self.entries.len().min(limit) {
+            return Err(());
+        }
+        if old_fd == new_fd {
+            return Ok(None);
+        }
+        Ok(self.entries[new_fd as usize].replace(file))
+    }
+
+    /// ディスクリプタを外して返す
+    /// 呼び出し側でロックを解放してからドロップすること
+    pub fn remove(&mut self, fd: i32) -> Option<FileRef> {
+        if fd < 0 {
+            return None;
+        }
+        self.entries.get_mut(fd as usize)?.take()
+    }
+}
+
+/// 現在のプロセスのディスクリプタテーブルに対して操作する
+fn with_table<R>(f: impl FnOnce(&mut FdTable) -> R) -> R {
+    let mut f = Some(f);
+    if let Some(result) = crate::process::with_current_process(|process| {
+        (f.take().unwrap())(&mut process.fds)
+    }) {
+        return result;
+    }
+
+    let mut kernel_fds = KERNEL_FDS.lock();
+    let table = kernel_fds.get_or_insert_with(FdTable::with_std_streams);
+    (f.take().unwrap())(table)
+}
+
+pub fn get(fd: i32) -> Option<FileRef> {
+    with_table(|table| table.get(fd))
+}
+
+/// 現在のプロセスが開ける番号の上限 (RLIMIT_NOFILE)
+/// with_table の中ではプロセスのロックを取れないので、先に求めておく
+fn open_limit() -> usize {
+    crate::rlimit::current(crate::rlimit::RLIMIT_NOFILE)
+        .try_into()
+        .map_or(MAX_FDS, |limit: usize| limit.min(MAX_FDS))
+}
+
+/// flags は open と同じ (アクセスモードと O_NONBLOCK など)
+pub fn install(file: FileObject, flags: i32) -> Option<i32> {
+    let file = Arc::new(OpenFile::new(file, flags));
+    let limit = open_limit();
+    with_table(|table| table.install_below(file, limit))
+}
+
+pub fn dup(fd: i32) -> Option<i32> {
+    let limit = open_limit();
+    with_table(|table| table.dup(fd, limit))
+}
+
+pub fn dup2(old_fd: i32, new_fd: i32) -> Option<i32> {
+    let limit = open_limit();
+    let replaced = with_table(|table| table.dup2(old_fd, new_fd, limit));
+    // 置き換えられた記述はロック解放後にここでドロップされる
+    replaced.ok().map(|_| new_fd)
+}
+
+pub fn close(fd: i32) -> bool {
+    let removed = with_table(|table| table.remove(fd));
+    // ここでドロップされ、最後の参照ならVFSやパイプ端が閉じられる
+    removed.is_some()
+}
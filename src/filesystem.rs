@@ -1,47 +1,142 @@
 use alloc::string::String;
 
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::boxed::Box;
-use spin::Mutex;
 use alloc::vec;
 use alloc::vec::Vec;
+use crate::cred::{Credentials, MAY_EXEC, MAY_READ, MAY_WRITE};
+use crate::errno::Errno;
+use crate::sync::{IrqMutex, Rcu};
 
 const MAX_OPEN_FILES: usize = 1024;
 const MAX_FILE_SIZE: usize = 1024 * 1024; // 1MB
+/// ファイル内容の合計サイズの既定の上限 (ヒープの半分、tmpfs_size= で変えられる)
+const DEFAULT_SIZE_LIMIT: usize = crate::allocator::HEAP_MAX_SIZE / 2;
+const MAX_SYMLINK_DEPTH: usize = 8;
 
-static FILESYSTEM: Mutex<Option<VirtualFileSystem>> = Mutex::new(None);
+// open フラグ、*at の dirfd、lseek の whence、stat の構造体などは abi で定義する
+pub use crate::abi::{
+    Stat, AT_FDCWD, AT_REMOVEDIR, DT_CHR, DT_DIR, DT_LNK, DT_REG, O_ACCMODE, O_APPEND, O_CREAT, O_DIRECTORY,
+    O_EXCL, O_NONBLOCK, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, SEEK_CUR, SEEK_END, SEEK_SET, S_IFCHR, S_IFDIR,
+    S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK,
+};
+use crate::abi::DIRENT_HEADER_SIZE;
+
+// 持っている間に割り込まれ、割り込み側で取り直してデッドロックしないよう割り込みを止める
+static FILESYSTEM: IrqMutex<Option<VirtualFileSystem>> = IrqMutex::new(None);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     Regular,
     Directory,
     Device,
+    Symlink,
 }
 
+/// 所有者・グループ・その他それぞれの rwx
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FileMode {
-    pub read: bool,
-    pub write: bool,
-    pub execute: bool,
+    bits: u32,
 }
 
 impl FileMode {
-    pub fn from_bits(mode: u32) -> Self {
-        Self {
-            read: (mode & 0o400) != 0,
-            write: (mode & 0o200) != 0,
-            execute: (mode & 0o100) != 0,
-        }
+    pub const fn from_bits(mode: u32) -> Self {
+        Self { bits: mode & 0o777 }
     }
+
+    pub fn to_bits(&self) -> u32 {
+        self.bits
+    }
+}
+
+// statfs の f_type
+pub const TMPFS_MAGIC: i64 = 0x0102_1994;
+pub const PROC_SUPER_MAGIC: i64 = 0x9fa0;
+
+/// statfs が返すブロックサイズとファイル名の最大長
+const STATFS_BLOCK_SIZE: usize = 4096;
+const NAME_MAX: usize = 255;
+
+/// マウントポイント (/proc は procfs が中身を作る)
+#[derive(Debug, Clone, Copy)]
+pub struct Mount {
+    pub path: &'static str,
+    pub fs_type: &'static str,
+    pub magic: i64,
+}
+
+lazy_static::lazy_static! {
+    // パスを引くたびに読み、変わるのはマウントしたときだけなので RCU にする
+    static ref MOUNTS: Rcu<Vec<Mount>> = Rcu::new(vec![
+        Mount { path: "/", fs_type: "tmpfs", magic: TMPFS_MAGIC },
+        Mount { path: "/proc", fs_type: "proc", magic: PROC_SUPER_MAGIC },
+    ]);
+}
+
+pub fn mounts() -> Vec<Mount> {
+    MOUNTS.read().clone()
+}
+
+/// マウントポイントを登録する (同じパスは1度だけ)
+pub fn add_mount(mount: Mount) -> Result<(), Errno> {
+    MOUNTS.update(|mounts| {
+        if mounts.iter().any(|existing| existing.path == mount.path) {
+            return Err(Errno::EBUSY);
+        }
+        let mut mounts = mounts.clone();
+        mounts.push(mount);
+        Ok(mounts)
+    })
+}
+
+/// 絶対パスを含むマウントのうち、最も深いもの
+fn mount_of(path: &str) -> Mount {
+    *MOUNTS.read().iter()
+        .filter(|mount| {
+            mount.path == "/" || path == mount.path
+                || path.strip_prefix(mount.path).is_some_and(|rest| rest.starts_with('/'))
+        })
+        .max_by_key(|mount| mount.path.len())
+        .unwrap()
+}
+
+/// statfs が返すファイルシステムの情報 (Linux の struct statfs と同じ並び)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatFs {
+    pub f_type: i64,
+    pub f_bsize: i64,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_fsid: [i32; 2],
+    pub f_namelen: i64,
+    pub f_frsize: i64,
+    pub f_flags: i64,
+    pub f_spare: [i64; 4],
+}
+
+fn now() -> u64 {
+    crate::time::unix_time()
 }
 #[derive(Clone)]
 pub struct Inode {
     pub inode_num: usize,
     pub file_type: FileType,
     pub mode: FileMode,
+    /// 所有者
+    pub uid: u32,
+    pub gid: u32,
     pub size: usize,
     pub data: Vec<u8>,
     pub children: BTreeMap<String, usize>, // ディレクトリの場合
+    pub nlink: usize, // ハードリンク数
+    pub rdev: u32, // デバイスノードの場合のデバイス番号
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
 }
 
 impl Inode {
@@ -50,23 +145,95 @@ impl Inode {
             inode_num,
             file_type: FileType::Regular,
             mode,
+            uid: crate::cred::ROOT_UID,
+            gid: crate::cred::ROOT_GID,
             size: 0,
             data: Vec::new(),
             children: BTreeMap::new(),
+            nlink: 1,
+            rdev: 0,
+            atime: now(),
+            mtime: now(),
+            ctime: now(),
         }
     }
 
+    fn new_symlink(inode_num: usize, target: &str) -> Self {
+        let mut inode = Self::new_file(inode_num, FileMode::from_bits(0o777));
+        inode.file_type = FileType::Symlink;
+        inode.data = Vec::from(target.as_bytes());
+        inode.size = inode.data.len();
+        inode
+    }
+
+    fn new_device(inode_num: usize, mode: FileMode, rdev: u32) -> Self {
+        let mut inode = Self::new_file(inode_num, mode);
+        inode.file_type = FileType::Device;
+        inode.rdev = rdev;
+        inode
+    }
+
     fn new_dir(inode_num: usize, mode: FileMode) -> Self {
         Self {
             inode_num,
             file_type: FileType::Directory,
             mode,
+            uid: crate::cred::ROOT_UID,
+            gid: crate::cred::ROOT_GID,
             size: 0,
             data: Vec::new(),
             children: BTreeMap::new(),
+            nlink: 1,
+            rdev: 0,
+            atime: now(),
+            mtime: now(),
+            ctime: now(),
         }
     }
 }
+impl Inode {
+    /// 作成したプロセスの実効IDを所有者にする
+    fn owned_by(mut self, cred: &Credentials) -> Self {
+        self.uid = cred.euid;
+        self.gid = cred.egid;
+        self
+    }
+
+    /// cred で mask (MAY_READ など) のアクセスをしてよいか
+    pub fn permits(&self, cred: &Credentials, mask: u32) -> bool {
+        cred.may_access(self.uid, self.gid, self.mode.to_bits(), mask)
+    }
+
+    pub fn dirent_type(&self) -> u8 {
+        match self.file_type {
+            FileType::Regular => DT_REG,
+            FileType::Directory => DT_DIR,
+            FileType::Device => DT_CHR,
+            FileType::Symlink => DT_LNK,
+        }
+    }
+
+    pub fn stat(&self) -> Stat {
+        let file_type = match self.file_type {
+            FileType::Regular => S_IFREG,
+            FileType::Directory => S_IFDIR,
+            FileType::Device => S_IFCHR,
+            FileType::Symlink => S_IFLNK,
+        };
+        Stat {
+            st_ino: self.inode_num as u64,
+            st_mode: file_type | self.mode.to_bits(),
+            st_nlink: self.nlink as u64,
+            st_size: self.size as u64,
+            st_atime: self.atime,
+            st_mtime: self.mtime,
+            st_ctime: self.ctime,
+            st_uid: self.uid,
+            st_gid: self.gid,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct OpenFile {
     pub inode: usize,
@@ -79,6 +246,29 @@ pub struct VirtualFileSystem {
     open_files: Vec<Option<OpenFile>>,
     next_inode: usize,
     root_inode: usize,
+    /// 開かれたまま unlink された inode (最後の close で解放する)
+    orphans: BTreeSet<usize>,
+    /// 通常ファイルとシンボリックリンクの内容の合計バイト数
+    used_bytes: usize,
+    /// used_bytes の上限 (超える書き込みは ENOSPC)
+    size_limit: usize,
+    /// 操作しているプロセスの資格情報 (with_fs が設定する)
+    cred: Credentials,
+}
+
+/// ファイルシステムの使用量 (/proc/df や statfs で使う)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub used_bytes: usize,
+    pub size_limit: usize,
+    pub inodes_used: usize,
+    pub inodes_total: usize,
+}
+
+impl Usage {
+    pub fn free_bytes(&self) -> usize {
+        self.size_limit.saturating_sub(self.used_bytes)
+    }
 }
 
 impl VirtualFileSystem {
@@ -88,14 +278,14 @@ impl VirtualFileSystem {
             open_files: vec![None; MAX_OPEN_FILES],
             next_inode: 1,
             root_inode: 0,
+            orphans: BTreeSet::new(),
+            used_bytes: 0,
+            size_limit: DEFAULT_SIZE_LIMIT,
+            cred: Credentials::ROOT,
         };
 
         // ルートディレクトリを作成
-        let root = Inode::new_dir(0, FileMode {
-            read: true,
-            write: true,
-            execute: true,
-        });
+        let root = Inode::new_dir(0, FileMode::from_bits(0o755));
         vfs.inodes[0] = Some(root);
 
         vfs
@@ -104,94 +294,447 @@ impl VirtualFileSystem {
     fn allocate_inode(&mut self) -> Option<usize> {
         let inode_num = self.next_inode;
         if inode_num >= self.inodes.len() {
-            return None;
+            // 解放済みの inode を再利用する
+            return self.inodes.iter()
+                .enumerate()
+                .skip(1)
+                .find(|(_, inode)| inode.is_none())
+                .map(|(i, _)| i);
         }
         self.next_inode += 1;
         Some(inode_num)
     }
 
-    fn allocate_fd(&mut self) -> Option<usize> {
-        for (i, slot) in self.open_files.iter().enumerate() {
-            if slot.is_none() {
-                return Some(i);
+    /// 内容が old_len から new_len バイトになる分を使用量に反映する
+    /// 増える分が上限を超えるなら何も変えずに ENOSPC
+    fn charge(&mut self, old_len: usize, new_len: usize) -> Result<(), Errno> {
+        if new_len > old_len {
+            let used = self.used_bytes + (new_len - old_len);
+            if used > self.size_limit {
+                return Err(Errno::ENOSPC);
             }
+            self.used_bytes = used;
+        } else {
+            self.used_bytes -= old_len - new_len;
+        }
+        Ok(())
+    }
+
+    /// inode を消し、内容の分を使用量から引く
+    fn free_inode(&mut self, inode_num: usize) {
+        if let Some(inode) = self.inodes[inode_num].take() {
+            self.used_bytes -= inode.data.len();
+        }
+    }
+
+    pub fn usage(&self) -> Usage {
+        Usage {
+            used_bytes: self.used_bytes,
+            size_limit: self.size_limit,
+            inodes_used: self.inodes.iter().filter(|inode| inode.is_some()).count(),
+            inodes_total: self.inodes.len(),
+        }
+    }
+
+    /// path を含むマウントの情報
+    /// proc は内容をメモリ上に作るだけなので、ブロック数や inode 数は 0 とする
+    pub fn statfs(&self, path: &str) -> Result<StatFs, Errno> {
+        self.stat(path)?;
+        let mount = mount_of(&normalize_path(path));
+        let mut statfs = StatFs {
+            f_type: mount.magic,
+            f_bsize: STATFS_BLOCK_SIZE as i64,
+            f_namelen: NAME_MAX as i64,
+            f_frsize: STATFS_BLOCK_SIZE as i64,
+            ..StatFs::default()
+        };
+        if mount.magic == TMPFS_MAGIC {
+            let usage = self.usage();
+            statfs.f_blocks = (usage.size_limit / STATFS_BLOCK_SIZE) as u64;
+            statfs.f_bfree = (usage.free_bytes() / STATFS_BLOCK_SIZE) as u64;
+            statfs.f_bavail = statfs.f_bfree;
+            statfs.f_files = usage.inodes_total as u64;
+            statfs.f_ffree = (usage.inodes_total - usage.inodes_used) as u64;
+        }
+        Ok(statfs)
+    }
+
+    /// 上限を変える (使用中の量より小さくはできない)
+    pub fn set_size_limit(&mut self, limit: usize) -> Result<(), Errno> {
+        if limit < self.used_bytes {
+            return Err(Errno::EBUSY);
+        }
+        self.size_limit = limit;
+        Ok(())
+    }
+
+    /// ディレクトリの中身が変わったときに更新時刻を進める
+    fn touch(&mut self, inode_num: usize) {
+        if let Some(inode) = &mut self.inodes[inode_num] {
+            inode.mtime = now();
+            inode.ctime = inode.mtime;
         }
-        None
     }
 
-    pub fn create(&mut self, path: &str, mode: FileMode) -> Result<usize, &'static str> {
+    fn is_open(&self, inode_num: usize) -> bool {
+        self.open_files.iter().flatten().any(|f| f.inode == inode_num)
+    }
+
+    /// ハードリンクを1つ減らし、最後のリンクなら inode を解放する
+    fn drop_link(&mut self, inode_num: usize) {
+        let remaining = match &mut self.inodes[inode_num] {
+            Some(inode) => {
+                inode.nlink = inode.nlink.saturating_sub(1);
+                inode.ctime = now();
+                inode.nlink
+            }
+            None => return,
+        };
+        if remaining == 0 {
+            self.release_inode(inode_num);
+        }
+    }
+
+    /// 開かれていなければ inode を解放し、開かれていれば close まで遅延する
+    fn release_inode(&mut self, inode_num: usize) {
+        if self.is_open(inode_num) {
+            self.orphans.insert(inode_num);
+        } else {
+            self.free_inode(inode_num);
+        }
+    }
+
+    /// パスを親ディレクトリの inode と最後の要素名に分解する
+    fn resolve_parent<'a>(&self, path: &'a str) -> Result<(usize, &'a str), Errno> {
         let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        
+
         if parts.is_empty() {
-            return Err("Invalid path");
+            return Err(Errno::EINVAL);
         }
 
-        let filename = parts[parts.len() - 1];
+        let name = parts[parts.len() - 1];
+        if name == "." || name == ".." {
+            return Err(Errno::EINVAL);
+        }
         let parent_inode = self.traverse_path(&parts[..parts.len() - 1])?;
+        Ok((parent_inode, name))
+    }
+
+    fn lookup_child(&self, parent_inode: usize, name: &str) -> Result<usize, Errno> {
+        let parent = self.inodes[parent_inode].as_ref().ok_or(Errno::EIO)?;
+        if parent.file_type != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        parent.children.get(name).copied().ok_or(Errno::ENOENT)
+    }
+
+    /// ディレクトリにエントリを作ったり消したりしてよいか (書き込みと検索の権限)
+    fn check_dir_write(&self, dir_inode: usize) -> Result<(), Errno> {
+        let dir = self.inodes[dir_inode].as_ref().ok_or(Errno::EIO)?;
+        if !dir.permits(&self.cred, MAY_WRITE | MAY_EXEC) {
+            return Err(Errno::EACCES);
+        }
+        Ok(())
+    }
+
+    fn allocate_fd(&mut self) -> Option<usize> {
+        for (i, slot) in self.open_files.iter().enumerate() {
+            if slot.is_none() {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    pub fn create(&mut self, path: &str, mode: FileMode) -> Result<usize, Errno> {
+        let (parent_inode, filename) = self.resolve_parent(path)?;
 
         // 既に存在するかチェック
         if let Some(parent) = &self.inodes[parent_inode] {
             if parent.children.contains_key(filename) {
-                return Err("File already exists");
+                return Err(Errno::EEXIST);
             }
         }
 
         // 新しいinodeを割り当て
-        let inode_num = self.allocate_inode().ok_or("Out of inodes")?;
-        let inode = Inode::new_file(inode_num, mode);
+        let inode_num = self.allocate_inode().ok_or(Errno::ENOSPC)?;
+        let inode = Inode::new_file(inode_num, mode).owned_by(&self.cred);
         self.inodes[inode_num] = Some(inode);
 
         // 親ディレクトリに追加
         if let Some(parent) = &mut self.inodes[parent_inode] {
             parent.children.insert(String::from(filename), inode_num);
         }
+        self.touch(parent_inode);
 
         Ok(inode_num)
     }
 
-    pub fn mkdir(&mut self, path: &str, mode: FileMode) -> Result<usize, &'static str> {
-        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        
-        if parts.is_empty() {
-            return Err("Invalid path");
+    pub fn mkdir(&mut self, path: &str, mode: FileMode) -> Result<usize, Errno> {
+        let (parent_inode, dirname) = self.resolve_parent(path)?;
+        if self.lookup_child(parent_inode, dirname).is_ok() {
+            return Err(Errno::EEXIST);
         }
 
-        let dirname = parts[parts.len() - 1];
-        let parent_inode = self.traverse_path(&parts[..parts.len() - 1])?;
-
-        let inode_num = self.allocate_inode().ok_or("Out of inodes")?;
-        let inode = Inode::new_dir(inode_num, mode);
+        let inode_num = self.allocate_inode().ok_or(Errno::ENOSPC)?;
+        let inode = Inode::new_dir(inode_num, mode).owned_by(&self.cred);
         self.inodes[inode_num] = Some(inode);
 
         if let Some(parent) = &mut self.inodes[parent_inode] {
             parent.children.insert(String::from(dirname), inode_num);
         }
+        self.touch(parent_inode);
+
+        Ok(inode_num)
+    }
+
+    /// 内容ごと通常ファイルを作る (既にあれば中身を置き換える)
+    /// initramfs の展開に使うので、書き込み権限は確認しない
+    pub fn install_file(&mut self, path: &str, data: &[u8], mode: FileMode) -> Result<usize, Errno> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let inode_num = match self.traverse_path(&parts) {
+            Ok(inode_num) => inode_num,
+            Err(Errno::ENOENT) => self.create(path, mode)?,
+            Err(e) => return Err(e),
+        };
+
+        let inode = self.inodes[inode_num].as_ref().ok_or(Errno::EIO)?;
+        if inode.file_type != FileType::Regular {
+            return Err(Errno::EINVAL);
+        }
+        let old_len = inode.data.len();
+        self.charge(old_len, data.len())?;
+        let inode = self.inodes[inode_num].as_mut().ok_or(Errno::EIO)?;
+        inode.mode = mode;
+        inode.data = data.to_vec();
+        inode.size = data.len();
+        inode.mtime = now();
+        inode.ctime = inode.mtime;
+        Ok(inode_num)
+    }
+
+    /// デバイスノードを作成する
+    pub fn mknod(&mut self, path: &str, mode: FileMode, rdev: u32) -> Result<usize, Errno> {
+        let (parent_inode, name) = self.resolve_parent(path)?;
+        if self.lookup_child(parent_inode, name).is_ok() {
+            return Err(Errno::EEXIST);
+        }
+        self.check_dir_write(parent_inode)?;
+
+        let inode_num = self.allocate_inode().ok_or(Errno::ENOSPC)?;
+        self.inodes[inode_num] = Some(Inode::new_device(inode_num, mode, rdev).owned_by(&self.cred));
+
+        if let Some(parent) = &mut self.inodes[parent_inode] {
+            parent.children.insert(String::from(name), inode_num);
+        }
+        self.touch(parent_inode);
 
         Ok(inode_num)
     }
 
-    fn traverse_path(&self, parts: &[&str]) -> Result<usize, &'static str> {
-        let mut current = self.root_inode;
+    /// オープン中のファイルがデバイスノードならデバイス番号を返す
+    pub fn device_of(&self, fd: i32) -> Option<u32> {
+        let open_file = self.open_files.get(fd as usize)?.as_ref()?;
+        let inode = self.inodes[open_file.inode].as_ref()?;
+        match inode.file_type {
+            FileType::Device => Some(inode.rdev),
+            _ => None,
+        }
+    }
+
+    /// 最後の要素がシンボリックリンクでもたどらずに inode を探す
+    pub fn lookup_nofollow(&self, path: &str) -> Result<usize, Errno> {
+        if path.split('/').all(|part| part.is_empty()) {
+            return Ok(self.root_inode);
+        }
+        let (parent_inode, name) = self.resolve_parent(path)?;
+        self.lookup_child(parent_inode, name)
+    }
+
+    /// 所有者や時刻を直接書き換える (スナップショットの復元用、権限は確認しない)
+    pub fn inode_mut(&mut self, inode_num: usize) -> Option<&mut Inode> {
+        self.inodes.get_mut(inode_num)?.as_mut()
+    }
+
+    /// ルートから深さ優先で inode をたどり、絶対パスと inode ごとに visit を呼ぶ
+    /// シンボリックリンクはたどらない。visit が false を返したディレクトリの中には入らない
+    pub fn visit(&self, visit: &mut dyn FnMut(&str, &Inode) -> bool) {
+        self.visit_from(self.root_inode, &mut String::new(), visit);
+    }
+
+    fn visit_from(&self, inode_num: usize, path: &mut String, visit: &mut dyn FnMut(&str, &Inode) -> bool) {
+        let Some(inode) = self.inodes[inode_num].as_ref() else {
+            return;
+        };
+        let shown = if path.is_empty() { "/" } else { path.as_str() };
+        if !visit(shown, inode) || inode.file_type != FileType::Directory {
+            return;
+        }
+        for (name, &child) in &inode.children {
+            let len = path.len();
+            path.push('/');
+            path.push_str(name);
+            self.visit_from(child, path, visit);
+            path.truncate(len);
+        }
+    }
+
+    fn traverse_path(&self, parts: &[&str]) -> Result<usize, Errno> {
+        let stack = self.walk(parts)?;
+        Ok(stack[stack.len() - 1])
+    }
+
+    /// ルートから parts をたどり、通過したディレクトリの inode 列を返す
+    /// `..` はこの列をさかのぼるので、シンボリックリンク展開後も実際の親に戻る
+    fn walk(&self, parts: &[&str]) -> Result<Vec<usize>, Errno> {
+        let mut stack = vec![self.root_inode];
+        self.walk_into(&mut stack, parts, 0)?;
+        Ok(stack)
+    }
 
+    fn walk_into(&self, stack: &mut Vec<usize>, parts: &[&str], depth: usize) -> Result<(), Errno> {
         for part in parts {
-            if let Some(inode) = &self.inodes[current] {
+            match *part {
+                "." => continue,
+                ".." => {
+                    // ルートの親はルート自身
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let current = stack[stack.len() - 1];
+            let next = if let Some(inode) = &self.inodes[current] {
                 if inode.file_type != FileType::Directory {
-                    return Err("Not a directory");
+                    return Err(Errno::ENOTDIR);
+                }
+                *inode.children.get(*part).ok_or(Errno::ENOENT)?
+            } else {
+                return Err(Errno::EIO);
+            };
+
+            let inode = self.inodes[next].as_ref().ok_or(Errno::EIO)?;
+            if inode.file_type == FileType::Symlink {
+                if depth >= MAX_SYMLINK_DEPTH {
+                    return Err(Errno::ELOOP);
+                }
+                let target = core::str::from_utf8(&inode.data).map_err(|_| Errno::EIO)?;
+                let target_parts: Vec<&str> = target.split('/').filter(|s| !s.is_empty()).collect();
+                // 絶対パスはルートから、相対パスはリンクのあるディレクトリから解決
+                if target.starts_with('/') {
+                    stack.truncate(1);
                 }
-                current = *inode.children.get(*part).ok_or("Path not found")?;
+                self.walk_into(stack, &target_parts, depth + 1)?;
             } else {
-                return Err("Invalid inode");
+                stack.push(next);
             }
         }
 
-        Ok(current)
+        Ok(())
+    }
+
+    pub fn symlink(&mut self, target: &str, link_path: &str) -> Result<usize, Errno> {
+        let (parent_inode, name) = self.resolve_parent(link_path)?;
+        if self.lookup_child(parent_inode, name).is_ok() {
+            return Err(Errno::EEXIST);
+        }
+        self.check_dir_write(parent_inode)?;
+
+        let inode_num = self.allocate_inode().ok_or(Errno::ENOSPC)?;
+        self.charge(0, target.len())?;
+        self.inodes[inode_num] = Some(Inode::new_symlink(inode_num, target).owned_by(&self.cred));
+
+        if let Some(parent) = &mut self.inodes[parent_inode] {
+            parent.children.insert(String::from(name), inode_num);
+        }
+        self.touch(parent_inode);
+        Ok(inode_num)
+    }
+
+    pub fn link(&mut self, old_path: &str, new_path: &str) -> Result<(), Errno> {
+        let (old_parent, old_name) = self.resolve_parent(old_path)?;
+        let inode_num = self.lookup_child(old_parent, old_name)?;
+        let (new_parent, new_name) = self.resolve_parent(new_path)?;
+
+        if self.lookup_child(new_parent, new_name).is_ok() {
+            return Err(Errno::EEXIST);
+        }
+        self.check_dir_write(old_parent)?;
+        self.check_dir_write(new_parent)?;
+
+        let inode = self.inodes[inode_num].as_mut().ok_or(Errno::EIO)?;
+        if inode.file_type == FileType::Directory {
+            return Err(Errno::EPERM);
+        }
+        inode.nlink += 1;
+        inode.ctime = now();
+
+        if let Some(parent) = &mut self.inodes[new_parent] {
+            parent.children.insert(String::from(new_name), inode_num);
+        }
+        self.touch(new_parent);
+        Ok(())
+    }
+
+    pub fn readlink(&self, path: &str) -> Result<String, Errno> {
+        let (parent_inode, name) = self.resolve_parent(path)?;
+        let inode_num = self.lookup_child(parent_inode, name)?;
+        let inode = self.inodes[inode_num].as_ref().ok_or(Errno::EIO)?;
+
+        if inode.file_type != FileType::Symlink {
+            return Err(Errno::EINVAL);
+        }
+        let target = core::str::from_utf8(&inode.data).map_err(|_| Errno::EIO)?;
+        Ok(String::from(target))
     }
 
-    pub fn open(&mut self, path: &str, flags: i32) -> Result<i32, &'static str> {
+    pub fn open(&mut self, path: &str, flags: i32, mode: u32) -> Result<i32, Errno> {
         let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        let inode_num = self.traverse_path(&parts)?;
+        let (inode_num, created) = match self.traverse_path(&parts) {
+            Ok(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(Errno::EEXIST),
+            Ok(inode_num) => (inode_num, false),
+            Err(Errno::ENOENT) if flags & O_CREAT != 0 => {
+                let (parent_inode, _) = self.resolve_parent(path)?;
+                self.check_dir_write(parent_inode)?;
+                (self.create(path, FileMode::from_bits(mode))?, true)
+            }
+            Err(e) => return Err(e),
+        };
 
-        let fd = self.allocate_fd().ok_or("Too many open files")? as i32;
+        // 既存のファイルは、アクセスモードに応じた権限を確かめる
+        // (作ったばかりのファイルは mode に関係なく開ける)
+        let access = match flags & O_ACCMODE {
+            O_RDONLY => MAY_READ,
+            O_WRONLY => MAY_WRITE,
+            _ => MAY_READ | MAY_WRITE,
+        };
+        if !created && !self.inodes[inode_num].as_ref().ok_or(Errno::EIO)?.permits(&self.cred, access) {
+            return Err(Errno::EACCES);
+        }
+
+        if flags & O_DIRECTORY != 0
+            && self.inodes[inode_num].as_ref().ok_or(Errno::EIO)?.file_type != FileType::Directory
+        {
+            return Err(Errno::ENOTDIR);
+        }
+
+        if flags & O_TRUNC != 0 && flags & (O_WRONLY | O_RDWR) != 0 {
+            let inode = self.inodes[inode_num].as_mut().ok_or(Errno::EIO)?;
+            if inode.file_type == FileType::Directory {
+                return Err(Errno::EISDIR);
+            }
+            let old_len = inode.data.len();
+            inode.data.clear();
+            inode.size = 0;
+            inode.mtime = now();
+            inode.ctime = inode.mtime;
+            self.used_bytes -= old_len;
+        }
+
+        let fd = self.allocate_fd().ok_or(Errno::ENFILE)? as i32;
         
         self.open_files[fd as usize] = Some(OpenFile {
             inode: inode_num,
@@ -202,85 +745,368 @@ impl VirtualFileSystem {
         Ok(fd)
     }
 
-    pub fn close(&mut self, fd: i32) -> Result<(), &'static str> {
+    pub fn close(&mut self, fd: i32) -> Result<(), Errno> {
         if fd < 0 || fd as usize >= self.open_files.len() {
-            return Err("Invalid file descriptor");
+            return Err(Errno::EBADF);
         }
 
-        self.open_files[fd as usize] = None;
+        if let Some(open_file) = self.open_files[fd as usize].take() {
+            // unlink 済みで最後の参照なら inode を解放
+            if self.orphans.contains(&open_file.inode) && !self.is_open(open_file.inode) {
+                self.orphans.remove(&open_file.inode);
+                self.free_inode(open_file.inode);
+            }
+        }
         Ok(())
     }
 
-    pub fn read(&mut self, fd: i32, buf: &mut [u8]) -> Result<usize, &'static str> {
+    pub fn unlink(&mut self, path: &str) -> Result<(), Errno> {
+        let (parent_inode, name) = self.resolve_parent(path)?;
+        let inode_num = self.lookup_child(parent_inode, name)?;
+
+        let inode = self.inodes[inode_num].as_ref().ok_or(Errno::EIO)?;
+        if inode.file_type == FileType::Directory {
+            return Err(Errno::EISDIR);
+        }
+        self.check_dir_write(parent_inode)?;
+
+        if let Some(parent) = &mut self.inodes[parent_inode] {
+            parent.children.remove(name);
+        }
+        self.touch(parent_inode);
+        self.drop_link(inode_num);
+        Ok(())
+    }
+
+    pub fn rmdir(&mut self, path: &str) -> Result<(), Errno> {
+        let (parent_inode, name) = self.resolve_parent(path)?;
+        let inode_num = self.lookup_child(parent_inode, name)?;
+
+        let inode = self.inodes[inode_num].as_ref().ok_or(Errno::EIO)?;
+        if inode.file_type != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        if !inode.children.is_empty() {
+            return Err(Errno::ENOTEMPTY);
+        }
+        self.check_dir_write(parent_inode)?;
+
+        if let Some(parent) = &mut self.inodes[parent_inode] {
+            parent.children.remove(name);
+        }
+        self.touch(parent_inode);
+        self.release_inode(inode_num);
+        Ok(())
+    }
+
+    pub fn rename(&mut self, old_path: &str, new_path: &str) -> Result<(), Errno> {
+        let (old_parent, old_name) = self.resolve_parent(old_path)?;
+        let inode_num = self.lookup_child(old_parent, old_name)?;
+        let (new_parent, new_name) = self.resolve_parent(new_path)?;
+        // 移動元からエントリを消し、移動先に作る
+        self.check_dir_write(old_parent)?;
+        self.check_dir_write(new_parent)?;
+
+        let is_dir = self.inodes[inode_num].as_ref()
+            .ok_or(Errno::EIO)?
+            .file_type == FileType::Directory;
+
+        // ディレクトリを自分自身の配下へ移動することはできない
+        if is_dir {
+            let new_parts: Vec<&str> = new_path.split('/').filter(|s| !s.is_empty()).collect();
+            let ancestors = self.walk(&new_parts[..new_parts.len() - 1])?;
+            if ancestors.contains(&inode_num) {
+                return Err(Errno::EINVAL);
+            }
+        }
+
+        // 置き換え先が存在する場合
+        if let Ok(target) = self.lookup_child(new_parent, new_name) {
+            if target == inode_num {
+                return Ok(());
+            }
+            let target_inode = self.inodes[target].as_ref().ok_or(Errno::EIO)?;
+            match (is_dir, target_inode.file_type == FileType::Directory) {
+                (true, false) => return Err(Errno::ENOTDIR),
+                (false, true) => return Err(Errno::EISDIR),
+                (true, true) if !target_inode.children.is_empty() => {
+                    return Err(Errno::ENOTEMPTY);
+                }
+                _ => {}
+            }
+            if is_dir {
+                self.release_inode(target);
+            } else {
+                self.drop_link(target);
+            }
+        }
+
+        if let Some(parent) = &mut self.inodes[old_parent] {
+            parent.children.remove(old_name);
+        }
+        if let Some(parent) = &mut self.inodes[new_parent] {
+            parent.children.insert(String::from(new_name), inode_num);
+        }
+        self.touch(old_parent);
+        self.touch(new_parent);
+        Ok(())
+    }
+
+    pub fn read(&mut self, fd: i32, buf: &mut [u8]) -> Result<usize, Errno> {
         if fd < 0 || fd as usize >= self.open_files.len() {
-            return Err("Invalid file descriptor");
+            return Err(Errno::EBADF);
         }
 
-        let open_file = self.open_files[fd as usize].as_mut()
-            .ok_or("File not open")?;
+        let offset = self.open_files[fd as usize].as_ref()
+            .ok_or(Errno::EBADF)?
+            .offset;
+        let bytes_read = self.read_at(fd, buf, offset)?;
 
-        let inode = self.inodes[open_file.inode].as_ref()
-            .ok_or("Invalid inode")?;
+        if let Some(open_file) = self.open_files[fd as usize].as_mut() {
+            open_file.offset = offset + bytes_read;
+        }
+        Ok(bytes_read)
+    }
 
-        if !inode.mode.read {
-            return Err("Permission denied");
+    /// offset から読む (オープンファイルのオフセットは動かさない)
+    pub fn read_at(&mut self, fd: i32, buf: &mut [u8], offset: usize) -> Result<usize, Errno> {
+        if fd < 0 || fd as usize >= self.open_files.len() {
+            return Err(Errno::EBADF);
         }
 
-        let start = open_file.offset;
-        let end = core::cmp::min(start + buf.len(), inode.data.len());
-        let bytes_read = end - start;
+        let open_file = self.open_files[fd as usize].as_ref()
+            .ok_or(Errno::EBADF)?;
 
-        buf[..bytes_read].copy_from_slice(&inode.data[start..end]);
-        open_file.offset = end;
+        let inode = self.inodes[open_file.inode].as_mut()
+            .ok_or(Errno::EIO)?;
+
+        if !inode.permits(&self.cred, MAY_READ) {
+            return Err(Errno::EACCES);
+        }
+
+        if offset >= inode.data.len() {
+            return Ok(0);
+        }
+        let end = core::cmp::min(offset + buf.len(), inode.data.len());
+        let bytes_read = end - offset;
+
+        buf[..bytes_read].copy_from_slice(&inode.data[offset..end]);
+        inode.atime = now();
 
         Ok(bytes_read)
     }
 
-    pub fn write(&mut self, fd: i32, buf: &[u8]) -> Result<usize, &'static str> {
+    pub fn write(&mut self, fd: i32, buf: &[u8]) -> Result<usize, Errno> {
+        self.write_limited(fd, buf, usize::MAX)
+    }
+
+    /// ファイルの大きさを limit バイト (RLIMIT_FSIZE) までに抑えて書く
+    pub fn write_limited(&mut self, fd: i32, buf: &[u8], limit: usize) -> Result<usize, Errno> {
         if fd < 0 || fd as usize >= self.open_files.len() {
-            return Err("Invalid file descriptor");
+            return Err(Errno::EBADF);
         }
 
-        let inode_num = {
-            let open_file = self.open_files[fd as usize].as_ref()
-                .ok_or("File not open")?;
-            open_file.inode
+        let open_file = self.open_files[fd as usize].as_ref()
+            .ok_or(Errno::EBADF)?;
+        // O_APPEND なら毎回末尾から書き込む
+        let start = if open_file.flags & O_APPEND != 0 {
+            self.inodes[open_file.inode].as_ref().ok_or(Errno::EIO)?.data.len()
+        } else {
+            open_file.offset
         };
+        let written = self.write_at_limited(fd, buf, start, limit)?;
+
+        if let Some(open_file) = self.open_files[fd as usize].as_mut() {
+            open_file.offset = start + written;
+        }
+        Ok(written)
+    }
 
-        let inode = self.inodes[inode_num].as_mut()
-            .ok_or("Invalid inode")?;
+    /// offset へ書く (オープンファイルのオフセットは動かさない)
+    /// 末尾より先に書くと、間はゼロで埋まる
+    pub fn write_at(&mut self, fd: i32, buf: &[u8], start: usize) -> Result<usize, Errno> {
+        self.write_at_limited(fd, buf, start, usize::MAX)
+    }
 
-        if !inode.mode.write {
-            return Err("Permission denied");
+    /// limit を超える分は書かない (limit 以降から書こうとしたら EFBIG)
+    pub fn write_at_limited(&mut self, fd: i32, buf: &[u8], start: usize, limit: usize) -> Result<usize, Errno> {
+        if fd < 0 || fd as usize >= self.open_files.len() {
+            return Err(Errno::EBADF);
+        }
+        if !buf.is_empty() && start >= limit {
+            return Err(Errno::EFBIG);
         }
+        let buf = &buf[..buf.len().min(limit.saturating_sub(start))];
+
+        let inode_num = self.open_files[fd as usize].as_ref()
+            .ok_or(Errno::EBADF)?
+            .inode;
 
-        let open_file = self.open_files[fd as usize].as_mut().unwrap();
-        let start = open_file.offset;
+        let inode = self.inodes[inode_num].as_ref()
+            .ok_or(Errno::EIO)?;
+
+        if !inode.permits(&self.cred, MAY_WRITE) {
+            return Err(Errno::EACCES);
+        }
 
-        // データを拡張
-        if start + buf.len() > inode.data.len() {
-            if start + buf.len() > MAX_FILE_SIZE {
-                return Err("File too large");
+        // データを拡張 (増える分だけ使用量に加える)
+        let old_len = inode.data.len();
+        if start + buf.len() > old_len {
+            if start.saturating_add(buf.len()) > MAX_FILE_SIZE {
+                return Err(Errno::EFBIG);
             }
+            self.charge(old_len, start + buf.len())?;
+        }
+
+        let inode = self.inodes[inode_num].as_mut().unwrap();
+        if start + buf.len() > old_len {
             inode.data.resize(start + buf.len(), 0);
         }
 
         inode.data[start..start + buf.len()].copy_from_slice(buf);
         inode.size = core::cmp::max(inode.size, start + buf.len());
-        open_file.offset = start + buf.len();
+        inode.mtime = now();
+        inode.ctime = inode.mtime;
 
         Ok(buf.len())
     }
 
-    pub fn list_dir(&self, path: &str) -> Result<Vec<String>, &'static str> {
+    pub fn lseek(&mut self, fd: i32, offset: i64, whence: i32) -> Result<usize, Errno> {
+        if fd < 0 || fd as usize >= self.open_files.len() {
+            return Err(Errno::EBADF);
+        }
+
+        let open_file = self.open_files[fd as usize].as_mut()
+            .ok_or(Errno::EBADF)?;
+        let inode = self.inodes[open_file.inode].as_ref()
+            .ok_or(Errno::EIO)?;
+
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => open_file.offset as i64,
+            SEEK_END => inode.data.len() as i64,
+            _ => return Err(Errno::EINVAL),
+        };
+
+        let new_offset = base.checked_add(offset).ok_or(Errno::EINVAL)?;
+        if new_offset < 0 {
+            return Err(Errno::EINVAL);
+        }
+
+        open_file.offset = new_offset as usize;
+        Ok(open_file.offset)
+    }
+
+    /// 開いているディレクトリの絶対パス (*at システムコールの基準)
+    /// 開いた後に rename されても、その時点の位置を返す
+    pub fn dir_path(&self, fd: i32) -> Result<String, Errno> {
+        let open_file = self.open_files.get(fd as usize)
+            .and_then(|f| f.as_ref())
+            .ok_or(Errno::EBADF)?;
+        let inode = self.inodes[open_file.inode].as_ref().ok_or(Errno::EIO)?;
+        if inode.file_type != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        if open_file.inode == self.root_inode {
+            return Ok(String::from("/"));
+        }
+        let mut path = String::new();
+        // 削除済みのディレクトリはどこからもたどれない
+        if self.find_path(self.root_inode, open_file.inode, &mut path) { Ok(path) } else { Err(Errno::ENOENT) }
+    }
+
+    /// dir から target のディレクトリを探し、見つかれば path にそこまでのパスを足す
+    fn find_path(&self, dir: usize, target: usize, path: &mut String) -> bool {
+        let Some(inode) = &self.inodes[dir] else { return false };
+        for (name, &child) in &inode.children {
+            if !matches!(&self.inodes[child], Some(c) if c.file_type == FileType::Directory) {
+                continue;
+            }
+            let len = path.len();
+            path.push('/');
+            path.push_str(name);
+            if child == target || self.find_path(child, target, path) {
+                return true;
+            }
+            path.truncate(len);
+        }
+        false
+    }
+
+    pub fn stat(&self, path: &str) -> Result<Stat, Errno> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let inode_num = self.traverse_path(&parts)?;
+        let inode = self.inodes[inode_num].as_ref().ok_or(Errno::EIO)?;
+        Ok(inode.stat())
+    }
+
+    pub fn fstat(&self, fd: i32) -> Result<Stat, Errno> {
+        if fd < 0 || fd as usize >= self.open_files.len() {
+            return Err(Errno::EBADF);
+        }
+
+        let open_file = self.open_files[fd as usize].as_ref()
+            .ok_or(Errno::EBADF)?;
+        let inode = self.inodes[open_file.inode].as_ref()
+            .ok_or(Errno::EIO)?;
+        Ok(inode.stat())
+    }
+
+    /// 開いているディレクトリのエントリを linux_dirent64 の並びとして buf に詰める
+    /// オフセットは次に返すエントリの番号で、lseek(fd, 0, SEEK_SET) で先頭に戻せる
+    /// 末尾なら 0、最初のエントリすら入らなければ EINVAL
+    pub fn getdents(&mut self, fd: i32, buf: &mut [u8]) -> Result<usize, Errno> {
+        if fd < 0 || fd as usize >= self.open_files.len() {
+            return Err(Errno::EBADF);
+        }
+
+        let open_file = self.open_files[fd as usize].as_mut()
+            .ok_or(Errno::EBADF)?;
+        let dir = self.inodes[open_file.inode].as_ref()
+            .ok_or(Errno::EIO)?;
+        if dir.file_type != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        let mut written = 0;
+        for (index, (name, &child)) in dir.children.iter().enumerate().skip(open_file.offset) {
+            let d_type = match &self.inodes[child] {
+                Some(inode) => inode.dirent_type(),
+                None => continue,
+            };
+            // NUL 終端を含めて 8 バイト境界に揃える
+            let reclen = (DIRENT_HEADER_SIZE + name.len() + 1 + 7) & !7;
+            if written + reclen > buf.len() {
+                if written == 0 {
+                    return Err(Errno::EINVAL);
+                }
+                break;
+            }
+
+            let record = &mut buf[written..written + reclen];
+            record.fill(0);
+            record[0..8].copy_from_slice(&(child as u64).to_ne_bytes());
+            record[8..16].copy_from_slice(&((index + 1) as i64).to_ne_bytes());
+            record[16..18].copy_from_slice(&(reclen as u16).to_ne_bytes());
+            record[18] = d_type;
+            record[DIRENT_HEADER_SIZE..DIRENT_HEADER_SIZE + name.len()].copy_from_slice(name.as_bytes());
+
+            written += reclen;
+            open_file.offset = index + 1;
+        }
+
+        Ok(written)
+    }
+
+    pub fn list_dir(&self, path: &str) -> Result<Vec<String>, Errno> {
         let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
         let inode_num = self.traverse_path(&parts)?;
 
         let inode = self.inodes[inode_num].as_ref()
-            .ok_or("Invalid inode")?;
+            .ok_or(Errno::EIO)?;
 
         if inode.file_type != FileType::Directory {
-            return Err("Not a directory");
+            return Err(Errno::ENOTDIR);
         }
 
         Ok(inode.children.keys().cloned().collect())
@@ -289,82 +1115,298 @@ impl VirtualFileSystem {
 
 pub fn init() {
     let mut vfs = VirtualFileSystem::new();
+    if let Some(value) = crate::cmdline::get("tmpfs_size") {
+        match crate::cmdline::parse_size(&value) {
+            Some(limit) => vfs.size_limit = limit,
+            None => crate::warn!("Invalid tmpfs_size: {}", value),
+        }
+    }
 
     // いくつかのディレクトリを作成
-    vfs.mkdir("/dev", FileMode { read: true, write: true, execute: true }).ok();
-    vfs.mkdir("/tmp", FileMode { read: true, write: true, execute: true }).ok();
-    vfs.mkdir("/home", FileMode { read: true, write: true, execute: true }).ok();
+    vfs.mkdir("/dev", FileMode::from_bits(0o755)).ok();
+    vfs.mkdir("/tmp", FileMode::from_bits(0o777)).ok();
+    vfs.mkdir("/home", FileMode::from_bits(0o755)).ok();
+    // 中身は開くたびに procfs が作り直す
+    vfs.mkdir("/proc", FileMode::from_bits(0o555)).ok();
+
+    // デバイスノード
+    vfs.mknod("/dev/mouse", FileMode::from_bits(0o444), crate::drivers::DEV_MOUSE).ok();
+    for path in ["/dev/random", "/dev/urandom"] {
+        vfs.mknod(path, FileMode::from_bits(0o666), crate::drivers::DEV_RANDOM).ok();
+    }
+    for vt in 0..crate::drivers::vt::NR_VTS {
+        let path = alloc::format!("/dev/tty{}", vt + 1);
+        vfs.mknod(&path, FileMode::from_bits(0o620), crate::drivers::tty_node(vt)).ok();
+    }
 
     // テストファイルを作成
-    vfs.create("/hello.txt", FileMode { read: true, write: true, execute: false }).ok();
+    vfs.create("/hello.txt", FileMode::from_bits(0o644)).ok();
 
     *FILESYSTEM.lock() = Some(vfs);
 }
 
-// グローバルAPI
-pub fn open(path: &str, flags: i32, _mode: u32) -> i64 {
-    let mut fs = FILESYSTEM.lock();
-    if let Some(fs) = fs.as_mut() {
-        match fs.open(path, flags) {
-            Ok(fd) => fd as i64,
-            Err(_) => -1,
+// パス操作
+
+/// 相対パスを現在のプロセスのカレントディレクトリ基準の絶対パスにする
+pub fn absolute_path(path: &str) -> String {
+    if path.starts_with('/') {
+        return String::from(path);
+    }
+    let mut absolute = crate::process::current_cwd();
+    if !absolute.ends_with('/') {
+        absolute.push('/');
+    }
+    absolute.push_str(path);
+    absolute
+}
+
+/// `.` と `..` を字句的に取り除いた絶対パスを返す
+pub fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => { parts.pop(); }
+            part => parts.push(part),
         }
-    } else {
-        -1
     }
+
+    let mut normalized = String::new();
+    for part in parts {
+        normalized.push('/');
+        normalized.push_str(part);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+// グローバルAPI
+
+/// 初期化済みのファイルシステムに対して操作する
+/// 権限の確認には現在のプロセスの資格情報を使う (プロセスのロックは先に取って外しておく)
+pub(crate) fn with_fs<R>(f: impl FnOnce(&mut VirtualFileSystem) -> Result<R, Errno>) -> Result<R, Errno> {
+    if crate::fault::should_fail(crate::fault::FaultPoint::Vfs) {
+        return Err(Errno::EIO);
+    }
+    with_fs_nofail(f)
 }
 
-pub fn close(fd: i32) -> i64 {
+/// with_fs と同じだが故障注入の対象にしない
+/// close のような後始末に使う (失敗させると開いたファイルの枠が漏れる)
+fn with_fs_nofail<R>(f: impl FnOnce(&mut VirtualFileSystem) -> Result<R, Errno>) -> Result<R, Errno> {
+    let cred = crate::cred::current();
     let mut fs = FILESYSTEM.lock();
-    if let Some(fs) = fs.as_mut() {
-        match fs.close(fd) {
-            Ok(_) => 0,
-            Err(_) => -1,
+    let fs = fs.as_mut().ok_or(Errno::EIO)?;
+    fs.cred = cred;
+    f(fs)
+}
+
+pub fn open(path: &str, flags: i32, mode: u32) -> Result<i32, Errno> {
+    let path = absolute_path(path);
+    if crate::procfs::is_proc_path(&path) {
+        crate::procfs::refresh();
+    }
+    with_fs(|fs| fs.open(&path, flags, mode))
+}
+
+pub fn close(fd: i32) -> Result<(), Errno> {
+    with_fs_nofail(|fs| fs.close(fd))
+}
+
+/// ファイル全体を読み込む
+pub fn read_file(path: &str) -> Result<Vec<u8>, Errno> {
+    let fd = open(path, O_RDONLY, 0)?;
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    let result = loop {
+        match read(fd, &mut buf) {
+            Ok(0) => break Ok(data),
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) => break Err(e),
         }
-    } else {
-        -1
+    };
+    let _ = close(fd);
+    result
+}
+
+/// デバイスノードならデバイス番号を返す
+/// ドライバの処理中はファイルシステムのロックを持たないよう、先に調べておく
+fn device_of(fd: i32) -> Option<u32> {
+    FILESYSTEM.lock().as_ref()?.device_of(fd)
+}
+
+/// ブロックデバイスのノードの offset から読む
+fn block_read_at(index: usize, buf: &mut [u8], offset: usize) -> Result<usize, Errno> {
+    crate::drivers::block::read_bytes(index, offset, buf).map_err(|_| Errno::EIO)
+}
+
+/// ブロックデバイスのノードの offset に書く (デバイスの終わりから先は ENOSPC)
+fn block_write_at(index: usize, buf: &[u8], offset: usize) -> Result<usize, Errno> {
+    match crate::drivers::block::write_bytes(index, offset, buf) {
+        Ok(0) if !buf.is_empty() => Err(Errno::ENOSPC),
+        Ok(written) => Ok(written),
+        Err(_) => Err(Errno::EIO),
     }
 }
 
-pub fn read(fd: i32, buf: &mut [u8]) -> i64 {
-    let mut fs = FILESYSTEM.lock();
-    if let Some(fs) = fs.as_mut() {
-        match fs.read(fd, buf) {
-            Ok(n) => n as i64,
-            Err(_) => -1,
+/// ブロックデバイスのノードをファイルの位置で読み書きし、位置を進める
+fn block_node_io(fd: i32, io: impl FnOnce(usize) -> Result<usize, Errno>) -> Result<usize, Errno> {
+    let offset = with_fs(|fs| fs.lseek(fd, 0, SEEK_CUR))?;
+    let count = io(offset)?;
+    with_fs(|fs| fs.lseek(fd, (offset + count) as i64, SEEK_SET))?;
+    Ok(count)
+}
+
+pub fn read(fd: i32, buf: &mut [u8]) -> Result<usize, Errno> {
+    if let Some(rdev) = device_of(fd) {
+        if let Some(index) = crate::drivers::block_index(rdev) {
+            return block_node_io(fd, |offset| block_read_at(index, buf, offset));
         }
-    } else {
-        -1
+        return crate::drivers::device_read(rdev, buf);
     }
+    with_fs(|fs| fs.read(fd, buf))
 }
 
-pub fn write(fd: i32, buf: &[u8]) -> i64 {
-    let mut fs = FILESYSTEM.lock();
-    if let Some(fs) = fs.as_mut() {
-        match fs.write(fd, buf) {
-            Ok(n) => n as i64,
-            Err(_) => -1,
+/// キャラクタデバイスのノードはシークできないので ESPIPE
+pub fn read_at(fd: i32, buf: &mut [u8], offset: usize) -> Result<usize, Errno> {
+    if let Some(rdev) = device_of(fd) {
+        let index = crate::drivers::block_index(rdev).ok_or(Errno::ESPIPE)?;
+        return block_read_at(index, buf, offset);
+    }
+    with_fs(|fs| fs.read_at(fd, buf, offset))
+}
+
+pub fn write(fd: i32, buf: &[u8]) -> Result<usize, Errno> {
+    if let Some(rdev) = device_of(fd) {
+        if let Some(index) = crate::drivers::block_index(rdev) {
+            return block_node_io(fd, |offset| block_write_at(index, buf, offset));
         }
-    } else {
-        -1
+        return crate::drivers::device_write(rdev, buf);
     }
+    let limit = file_size_limit();
+    with_fs(|fs| fs.write_limited(fd, buf, limit))
 }
 
-pub fn create_file(path: &str) -> Result<(), &'static str> {
-    let mut fs = FILESYSTEM.lock();
-    if let Some(fs) = fs.as_mut() {
-        fs.create(path, FileMode { read: true, write: true, execute: false })?;
-        Ok(())
-    } else {
-        Err("Filesystem not initialized")
+/// キャラクタデバイスのノードはシークできないので ESPIPE
+pub fn write_at(fd: i32, buf: &[u8], offset: usize) -> Result<usize, Errno> {
+    if let Some(rdev) = device_of(fd) {
+        let index = crate::drivers::block_index(rdev).ok_or(Errno::ESPIPE)?;
+        return block_write_at(index, buf, offset);
     }
+    let limit = file_size_limit();
+    with_fs(|fs| fs.write_at_limited(fd, buf, offset, limit))
+}
+
+/// 現在のプロセスが書けるファイルの大きさ (RLIMIT_FSIZE)
+fn file_size_limit() -> usize {
+    crate::rlimit::current(crate::rlimit::RLIMIT_FSIZE).try_into().unwrap_or(usize::MAX)
+}
+
+/// VFS のオープンファイルがディレクトリならその絶対パス
+pub fn dir_path(fd: i32) -> Result<String, Errno> {
+    with_fs(|fs| fs.dir_path(fd))
+}
+
+pub fn symlink(target: &str, link_path: &str) -> Result<(), Errno> {
+    let link_path = absolute_path(link_path);
+    with_fs(|fs| fs.symlink(target, &link_path).map(|_| ()))
+}
+
+pub fn link(old_path: &str, new_path: &str) -> Result<(), Errno> {
+    let old_path = absolute_path(old_path);
+    let new_path = absolute_path(new_path);
+    with_fs(|fs| fs.link(&old_path, &new_path))
+}
+
+pub fn readlink(path: &str) -> Result<String, Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| fs.readlink(&path))
+}
+
+pub fn lseek(fd: i32, offset: i64, whence: i32) -> Result<usize, Errno> {
+    with_fs(|fs| fs.lseek(fd, offset, whence))
+}
+
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| fs.unlink(&path))
+}
+
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| fs.rmdir(&path))
+}
+
+pub fn rename(old_path: &str, new_path: &str) -> Result<(), Errno> {
+    let old_path = absolute_path(old_path);
+    let new_path = absolute_path(new_path);
+    with_fs(|fs| fs.rename(&old_path, &new_path))
+}
+
+/// ディレクトリを作る (既にあれば EEXIST)
+pub fn mkdir(path: &str, mode: u32) -> Result<(), Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| {
+        let (parent_inode, _) = fs.resolve_parent(&path)?;
+        fs.check_dir_write(parent_inode)?;
+        fs.mkdir(&path, FileMode::from_bits(mode)).map(|_| ())
+    })
+}
+
+/// デバイスノードを作る (rdev は drivers の DEV_*)
+pub fn mknod(path: &str, mode: u32, rdev: u32) -> Result<(), Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| fs.mknod(&path, FileMode::from_bits(mode), rdev).map(|_| ()))
+}
+
+pub fn install_file(path: &str, data: &[u8], mode: u32) -> Result<(), Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| fs.install_file(&path, data, FileMode::from_bits(mode)).map(|_| ()))
+}
+
+pub fn create_file(path: &str) -> Result<(), Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| {
+        let (parent_inode, _) = fs.resolve_parent(&path)?;
+        fs.check_dir_write(parent_inode)?;
+        fs.create(&path, FileMode::from_bits(0o644)).map(|_| ())
+    })
+}
+
+pub fn stat(path: &str) -> Result<Stat, Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| fs.stat(&path))
+}
+
+pub fn fstat(fd: i32) -> Result<Stat, Errno> {
+    with_fs(|fs| fs.fstat(fd))
+}
+
+pub fn getdents(fd: i32, buf: &mut [u8]) -> Result<usize, Errno> {
+    with_fs(|fs| fs.getdents(fd, buf))
+}
+
+pub fn statfs(path: &str) -> Result<StatFs, Errno> {
+    let path = absolute_path(path);
+    with_fs(|fs| fs.statfs(&path))
+}
+
+/// 現在の使用量と上限
+pub fn usage() -> Result<Usage, Errno> {
+    with_fs(|fs| Ok(fs.usage()))
+}
+
+/// ファイル内容の合計サイズの上限を変える
+pub fn set_size_limit(limit: usize) -> Result<(), Errno> {
+    with_fs(|fs| fs.set_size_limit(limit))
 }
 
-pub fn list_directory(path: &str) -> Result<Vec<String>, &'static str> {
-    let fs = FILESYSTEM.lock();
-    if let Some(fs) = fs.as_ref() {
-        fs.list_dir(path)
-    } else {
-        Err("Filesystem not initialized")
+pub fn list_directory(path: &str) -> Result<Vec<String>, Errno> {
+    let path = absolute_path(path);
+    if crate::procfs::is_proc_path(&path) {
+        crate::procfs::refresh();
     }
+    with_fs(|fs| fs.list_dir(&path))
 }
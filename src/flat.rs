@@ -0,0 +1,85 @@
+use alloc::vec;
+use crate::elf::{self, ElfImage, Segment};
+use crate::errno::Errno;
+use crate::memory::{USER_HEAP_BASE, USER_IMAGE_BASE};
+use crate::process::Process;
+
+/// フラットバイナリの先頭の識別子
+pub const FLAT_MAGIC: &[u8; 4] = b"RFLT";
+const FLAT_VERSION: u32 = 1;
+/// magic, version, エントリのオフセット, bss の大きさ
+pub const HEADER_SIZE: usize = 24;
+
+/// 検証済みのフラットバイナリ
+/// ヘッダを含むファイル全体を USER_IMAGE_BASE に置き、その後ろに bss をゼロ埋めで続ける
+#[derive(Debug, Clone, Copy)]
+pub struct FlatImage<'a> {
+    /// ファイルの先頭からのエントリのオフセット
+    pub entry: u64,
+    pub bss_size: u64,
+    pub data: &'a [u8],
+}
+
+impl FlatImage<'_> {
+    /// 1つの書き込みも実行もできる区間として ELF と同じ方法で読み込む
+    fn to_elf_image(&self) -> ElfImage<'_> {
+        ElfImage {
+            entry: USER_IMAGE_BASE + self.entry,
+            segments: vec![Segment {
+                vaddr: USER_IMAGE_BASE,
+                mem_size: self.data.len() as u64 + self.bss_size,
+                data: self.data,
+                writable: true,
+            }],
+        }
+    }
+}
+
+pub fn is_flat(data: &[u8]) -> bool {
+    data.starts_with(FLAT_MAGIC)
+}
+
+/// ヘッダを調べる (ELF より簡単な、exec の経路を確かめるための形式)
+pub fn parse(data: &[u8]) -> Result<FlatImage<'_>, Errno> {
+    if data.len() < HEADER_SIZE || !is_flat(data) {
+        return Err(Errno::ENOEXEC);
+    }
+    if u32::from_le_bytes(data[4..8].try_into().unwrap()) != FLAT_VERSION {
+        return Err(Errno::ENOEXEC);
+    }
+    let entry = u64::from_le_bytes(data[8..16].try_into().unwrap());
+    let bss_size = u64::from_le_bytes(data[16..24].try_into().unwrap());
+
+    // エントリはヘッダより後ろのファイルの中身を指していること
+    if entry < HEADER_SIZE as u64 || entry >= data.len() as u64 {
+        return Err(Errno::ENOEXEC);
+    }
+    let size = (data.len() as u64).checked_add(bss_size).ok_or(Errno::ENOEXEC)?;
+    if size > USER_HEAP_BASE - USER_IMAGE_BASE {
+        return Err(Errno::ENOEXEC);
+    }
+    Ok(FlatImage { entry, bss_size, data })
+}
+
+/// フラットバイナリからユーザープロセスを作る (実行キューには積まない)
+pub fn create_process(name: &str, data: &[u8]) -> Result<Process, Errno> {
+    let image = parse(data)?;
+    elf::create_process_from_image(name, &image.to_elf_image())
+}
+
+/// 実行ファイルの形式を見て、ELF かフラットバイナリからプロセスを作る
+pub fn create_any_process(name: &str, data: &[u8]) -> Result<Process, Errno> {
+    if is_flat(data) {
+        create_process(name, data)
+    } else {
+        elf::create_process(name, data)
+    }
+}
+
+/// ファイルシステム上の実行ファイルをこの CPU のリング3で実行し、終了コードを返す
+pub fn run_file(path: &str) -> Result<i32, Errno> {
+    let data = crate::filesystem::read_file(path)?;
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let process = create_any_process(name, &data)?;
+    Ok(crate::process::run_user_program(process))
+}
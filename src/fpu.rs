@@ -0,0 +1,134 @@
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+use crate::cpu::{self, Feature};
+
+/// 保存領域の大きさ (x87 + SSE + AVX を xsave で保存すると 832 バイト)
+const AREA_SIZE: usize = 1024;
+/// fxsave の保存領域の大きさ
+const FXSAVE_SIZE: usize = 512;
+/// 初期状態の x87 制御ワード (例外をすべてマスク、拡張倍精度)
+pub const DEFAULT_FCW: u16 = 0x037F;
+/// 初期状態の MXCSR (例外をすべてマスク)
+pub const DEFAULT_MXCSR: u32 = 0x1F80;
+
+static XSAVE_ENABLED: AtomicBool = AtomicBool::new(false);
+/// XCR0 に立てる (xsave で保存する) 状態のビット
+static XCR0_FEATURES: AtomicU64 = AtomicU64::new(0);
+
+/// 有効にした FPU 関係の機能
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    pub xsave: bool,
+    pub avx: bool,
+    /// スレッドごとに保存する領域の大きさ (バイト)
+    pub save_size: usize,
+}
+
+/// CPU が対応している保存方法を調べ、FPU/SSE (と AVX) を有効にする (BSP で呼ぶ)
+/// カーネル自身はソフトウェア浮動小数点でビルドしているので、レジスタを使うのはユーザーだけ
+pub fn init() -> Features {
+    let xsave = cpu::has(Feature::Xsave);
+    let avx = cpu::has(Feature::Avx);
+
+    let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+    if avx {
+        xcr0 |= XCr0Flags::AVX;
+    }
+    XSAVE_ENABLED.store(xsave, Ordering::SeqCst);
+    XCR0_FEATURES.store(xcr0.bits(), Ordering::SeqCst);
+    init_ap();
+    features()
+}
+
+/// BSP と同じ設定をこの CPU でも行う
+pub fn init_ap() {
+    let xsave = XSAVE_ENABLED.load(Ordering::Relaxed);
+    let mut cr4 = Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE;
+    cr4.set(Cr4Flags::OSXSAVE, xsave);
+    unsafe {
+        // x87 命令を例外にせず (EM)、例外は #MF で受ける (NE)
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| *flags |= cr4);
+        if xsave {
+            XCr0::write(XCr0Flags::from_bits_truncate(XCR0_FEATURES.load(Ordering::Relaxed)));
+        }
+        core::arch::asm!("fninit", options(nomem, nostack));
+    }
+}
+
+pub fn features() -> Features {
+    let xcr0 = XCR0_FEATURES.load(Ordering::Relaxed);
+    let xsave = XSAVE_ENABLED.load(Ordering::Relaxed);
+    let save_size = if xsave {
+        // 現在の XCR0 で必要な大きさ
+        unsafe { core::arch::x86_64::__cpuid_count(0xD, 0) }.ebx as usize
+    } else {
+        FXSAVE_SIZE
+    };
+    Features { xsave, avx: xcr0 & XCr0Flags::AVX.bits() != 0, save_size }
+}
+
+#[derive(Clone)]
+#[repr(C, align(64))]
+struct Area([u8; AREA_SIZE]);
+
+/// スレッドごとの x87/SSE/AVX レジスタの保存領域 (xsave か fxsave の形式)
+/// CPUから外れるときに save し、実行を始める前に restore する
+#[derive(Clone)]
+pub struct FpuState {
+    area: Box<Area>,
+}
+
+impl FpuState {
+    /// 初期状態 (fninit 直後と同じ制御ワード・MXCSR、xsave ヘッダは 0 で全部が初期状態)
+    pub fn new() -> Self {
+        let mut area = Box::new(Area([0; AREA_SIZE]));
+        area.0[0..2].copy_from_slice(&DEFAULT_FCW.to_le_bytes());
+        area.0[24..28].copy_from_slice(&DEFAULT_MXCSR.to_le_bytes());
+        Self { area }
+    }
+
+    /// 今の CPU のレジスタを保存する
+    pub fn save(&mut self) {
+        let area = self.area.0.as_mut_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                let mask = XCR0_FEATURES.load(Ordering::Relaxed);
+                core::arch::asm!("xsave64 [{}]", in(reg) area,
+                    in("eax") mask as u32, in("edx") (mask >> 32) as u32, options(nostack));
+            } else {
+                core::arch::asm!("fxsave64 [{}]", in(reg) area, options(nostack));
+            }
+        }
+    }
+
+    /// 保存したレジスタを今の CPU に戻す
+    pub fn restore(&self) {
+        let area = self.area.0.as_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                let mask = XCR0_FEATURES.load(Ordering::Relaxed);
+                core::arch::asm!("xrstor64 [{}]", in(reg) area,
+                    in("eax") mask as u32, in("edx") (mask >> 32) as u32, options(nostack, readonly));
+            } else {
+                core::arch::asm!("fxrstor64 [{}]", in(reg) area, options(nostack, readonly));
+            }
+        }
+    }
+
+    /// 保存されている MXCSR
+    pub fn mxcsr(&self) -> u32 {
+        u32::from_le_bytes(self.area.0[24..28].try_into().unwrap())
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
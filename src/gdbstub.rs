@@ -0,0 +1,475 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::VirtAddr;
+use crate::symbols::Sym;
+
+/// COM2 の I/O ポート (COM1 はコンソールとログが使う)
+const COM2: u16 = 0x2F8;
+
+/// 1パケットの最大長 (qSupported で PacketSize として伝える)
+const PACKET_SIZE: usize = 1024;
+/// 挿入できるソフトウェアブレークポイントの数
+const MAX_BREAKPOINTS: usize = 32;
+
+const DEBUG_VECTOR: u64 = 1;
+const BREAKPOINT_VECTOR: u64 = 3;
+const INT3: u8 = 0xCC;
+/// RFLAGS.TF (1命令ごとに #DB を起こす)
+const TRAP_FLAG: u64 = 1 << 8;
+/// 停止理由として返すシグナル (SIGTRAP)
+const STOP_REPLY: &[u8] = b"S05";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// c/s で再開した後なら、次の停止で停止理由を送る (接続直後は ? を待つ)
+static RESUMED: AtomicBool = AtomicBool::new(false);
+static PORT: Mutex<Option<SerialPort>> = Mutex::new(None);
+// (アドレス, 元の1バイト)
+static BREAKPOINTS: Mutex<[Option<(u64, u8)>; MAX_BREAKPOINTS]> = Mutex::new([None; MAX_BREAKPOINTS]);
+
+/// 例外の入口スタブが積むレジスタ (下位アドレスから)
+/// CPU が積んだ割り込みフレームが続き、ここを書き換えると iretq で戻る状態が変わる
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    /// GDB の amd64 レジスタ順 (rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8-r15, rip)
+    fn gprs_mut(&mut self) -> [&mut u64; 17] {
+        [
+            &mut self.rax, &mut self.rbx, &mut self.rcx, &mut self.rdx,
+            &mut self.rsi, &mut self.rdi, &mut self.rbp, &mut self.rsp,
+            &mut self.r8, &mut self.r9, &mut self.r10, &mut self.r11,
+            &mut self.r12, &mut self.r13, &mut self.r14, &mut self.r15,
+            &mut self.rip,
+        ]
+    }
+}
+
+// 汎用レジスタをすべて積んで TrapFrame を作り、handle_trap を呼ぶ
+// 割り込みフレーム (40 バイト) + 15 レジスタで rsp は 16 バイト境界に揃う
+// リング3から入った (積まれた CS の RPL が 3 の) ときは、入口と出口で swapgs する
+macro_rules! trap_entry {
+    ($name:ident, $vector:expr) => {
+        #[unsafe(naked)]
+        pub extern "C" fn $name() {
+            core::arch::naked_asm!(
+                "test qword ptr [rsp + 8], 3",
+                "jz 2f",
+                "swapgs",
+                "2:",
+                "push rax", "push rbx", "push rcx", "push rdx",
+                "push rsi", "push rdi", "push rbp",
+                "push r8", "push r9", "push r10", "push r11",
+                "push r12", "push r13", "push r14", "push r15",
+                "mov rdi, rsp",
+                "mov esi, {vector}",
+                "cld",
+                "call {handler}",
+                "pop r15", "pop r14", "pop r13", "pop r12",
+                "pop r11", "pop r10", "pop r9", "pop r8",
+                "pop rbp", "pop rdi", "pop rsi",
+                "pop rdx", "pop rcx", "pop rbx", "pop rax",
+                "test qword ptr [rsp + 8], 3",
+                "jz 3f",
+                "swapgs",
+                "3:",
+                "iretq",
+                vector = const $vector,
+                handler = sym handle_trap,
+            );
+        }
+    };
+}
+
+trap_entry!(breakpoint_entry, BREAKPOINT_VECTOR);
+trap_entry!(debug_entry, DEBUG_VECTOR);
+
+/// cmdline の gdb オプションを見てスタブを有効にする
+/// gdb=wait なら、その場でブレークしてホストの gdb が接続するのを待つ
+pub fn init() {
+    if !crate::cmdline::has("gdb") {
+        return;
+    }
+    *PORT.lock() = Some({
+        let mut port = unsafe { SerialPort::new(COM2) };
+        port.init();
+        port
+    });
+    ENABLED.store(true, Ordering::SeqCst);
+    crate::info!("gdbstub: listening on COM2");
+
+    if crate::cmdline::get("gdb").as_deref() == Some("wait") {
+        crate::info!("gdbstub: waiting for gdb to attach");
+        breakpoint();
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// デバッガに制御を渡す (スタブが無効なら例外として報告されるだけ)
+#[inline(always)]
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+/// #BP と #DB の共通ハンドラ (入口スタブから呼ばれる)
+extern "C" fn handle_trap(frame: &mut TrapFrame, vector: u64) {
+    if vector == DEBUG_VECTOR {
+        frame.rflags &= !TRAP_FLAG;
+    }
+    if !is_enabled() {
+        match vector {
+            BREAKPOINT_VECTOR => crate::println!("EXCEPTION: BREAKPOINT at {}\n{:#x?}", Sym(frame.rip), frame),
+            _ => crate::println!("EXCEPTION: DEBUG at {}\n{:#x?}", Sym(frame.rip), frame),
+        }
+        return;
+    }
+
+    // 自分で挿入した int3 なら、その命令のアドレスで止まったことにする
+    if vector == BREAKPOINT_VECTOR && find_breakpoint(frame.rip.wrapping_sub(1)).is_some() {
+        frame.rip -= 1;
+    }
+
+    crate::debug!("gdbstub: stopped at {}", Sym(frame.rip));
+    let mut port = PORT.lock();
+    let Some(port) = port.as_mut() else { return };
+    let mut session = Session { port, frame };
+    if RESUMED.swap(false, Ordering::SeqCst) {
+        session.send_packet(STOP_REPLY);
+    }
+    session.run();
+}
+
+/// 応答パケットの組み立て用バッファ (例外中はヒープを使わない)
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Self { buf: [0; PACKET_SIZE], len: 0 }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(HEX[(byte >> 4) as usize]);
+            self.push(HEX[(byte & 0xF) as usize]);
+        }
+    }
+
+    /// そのままの文字列を入れる ("OK" や停止理由)
+    fn push_str(&mut self, text: &[u8]) {
+        for &c in text {
+            self.push(c);
+        }
+    }
+
+    /// 成功なら "OK"、失敗なら "E01"
+    fn status(&mut self, result: Option<()>) {
+        self.push_str(if result.is_some() { b"OK" } else { b"E01" });
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// 16進数の数値 ("ffff8000,4" の各部分)
+fn parse_hex(text: &[u8]) -> Option<u64> {
+    if text.is_empty() || text.len() > 16 {
+        return None;
+    }
+    text.iter().try_fold(0u64, |value, &c| Some(value << 4 | hex_digit(c)? as u64))
+}
+
+/// 16進文字列をバイト列に戻す (dst に入りきる分だけ)
+fn decode_hex(text: &[u8], dst: &mut [u8]) -> Option<usize> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    let mut len = 0;
+    for (pair, byte) in text.chunks(2).zip(dst.iter_mut()) {
+        *byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        len += 1;
+    }
+    Some(len)
+}
+
+/// "addr,len" を分解する
+fn parse_range(args: &[u8]) -> Option<(u64, usize)> {
+    let comma = args.iter().position(|&c| c == b',')?;
+    Some((parse_hex(&args[..comma])?, parse_hex(&args[comma + 1..])? as usize))
+}
+
+/// 1バイト読む (マップされていないアドレスは None)
+/// 物理メモリのマッピング越しに読むので、SMAP や読み取り専用の保護に関係なく読める
+fn peek(addr: u64) -> Option<u8> {
+    let phys = crate::memory::try_virt_to_phys(VirtAddr::try_new(addr).ok()?)?;
+    Some(unsafe { *crate::memory::phys_to_virt(phys).as_ptr::<u8>() })
+}
+
+/// 1バイト書く (.text のような読み取り専用のページにも、物理メモリのマッピング越しに書く)
+fn poke(addr: u64, value: u8) -> Option<()> {
+    let phys = crate::memory::try_virt_to_phys(VirtAddr::try_new(addr).ok()?)?;
+    unsafe { *crate::memory::phys_to_virt(phys).as_mut_ptr::<u8>() = value };
+    Some(())
+}
+
+fn find_breakpoint(addr: u64) -> Option<usize> {
+    BREAKPOINTS.lock().iter().position(|slot| matches!(slot, Some((a, _)) if *a == addr))
+}
+
+fn insert_breakpoint(addr: u64) -> Option<()> {
+    if find_breakpoint(addr).is_some() {
+        return Some(());
+    }
+    let mut breakpoints = BREAKPOINTS.lock();
+    let slot = breakpoints.iter_mut().find(|slot| slot.is_none())?;
+    let original = peek(addr)?;
+    poke(addr, INT3)?;
+    *slot = Some((addr, original));
+    Some(())
+}
+
+fn remove_breakpoint(addr: u64) -> Option<()> {
+    let index = find_breakpoint(addr)?;
+    let mut breakpoints = BREAKPOINTS.lock();
+    let (addr, original) = breakpoints[index].take()?;
+    poke(addr, original)
+}
+
+/// 切断するときに挿入したブレークポイントをすべて戻す
+fn remove_all_breakpoints() {
+    for slot in BREAKPOINTS.lock().iter_mut() {
+        if let Some((addr, original)) = slot.take() {
+            let _ = poke(addr, original);
+        }
+    }
+}
+
+/// 停止中の CPU と gdb のやり取り
+struct Session<'a> {
+    port: &'a mut SerialPort,
+    frame: &'a mut TrapFrame,
+}
+
+impl Session<'_> {
+    /// $<data>#<checksum> を1つ受け取り、ack を返す
+    fn receive_packet(&mut self, buf: &mut [u8; PACKET_SIZE]) -> usize {
+        loop {
+            // '$' まで読み飛ばす (gdb の ack や Ctrl-C も含む)
+            while self.port.receive() != b'$' {}
+
+            let mut len = 0;
+            let mut sum = 0u8;
+            let mut overflow = false;
+            loop {
+                let c = self.port.receive();
+                if c == b'#' {
+                    break;
+                }
+                if len < PACKET_SIZE {
+                    buf[len] = c;
+                    len += 1;
+                } else {
+                    overflow = true;
+                }
+                sum = sum.wrapping_add(c);
+            }
+            let high = hex_digit(self.port.receive());
+            let low = hex_digit(self.port.receive());
+            let valid = matches!((high, low), (Some(h), Some(l)) if h << 4 | l == sum);
+            if valid && !overflow {
+                self.port.send_raw(b'+');
+                return len;
+            }
+            self.port.send_raw(b'-');
+        }
+    }
+
+    /// パケットを送り、gdb が '+' を返すまで再送する
+    fn send_packet(&mut self, data: &[u8]) {
+        let sum = data.iter().fold(0u8, |sum, &c| sum.wrapping_add(c));
+        loop {
+            self.port.send_raw(b'$');
+            for &c in data {
+                self.port.send_raw(c);
+            }
+            self.port.send_raw(b'#');
+            self.port.send_raw(HEX[(sum >> 4) as usize]);
+            self.port.send_raw(HEX[(sum & 0xF) as usize]);
+            // '-' なら再送する
+            if self.port.receive() != b'-' {
+                return;
+            }
+        }
+    }
+
+    /// 再開を指示されるまでパケットを処理する
+    fn run(&mut self) {
+        let mut buf = [0u8; PACKET_SIZE];
+        loop {
+            let len = self.receive_packet(&mut buf);
+            let packet = &buf[..len];
+            let (&command, args) = match packet.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+
+            let mut reply = Reply::new();
+            match command {
+                b'?' => reply.push_str(STOP_REPLY),
+                b'g' => self.read_registers(&mut reply),
+                b'G' => reply.status(self.write_registers(args)),
+                b'm' => self.read_memory(args, &mut reply),
+                b'M' => reply.status(write_memory(args)),
+                b'Z' | b'z' => match args.split_first() {
+                    // ソフトウェアブレークポイント ("Z0,addr,kind") だけ扱う
+                    Some((b'0', rest)) => {
+                        let addr = rest.get(1..)
+                            .and_then(|rest| rest.split(|&c| c == b',').next())
+                            .and_then(parse_hex);
+                        let result = addr.and_then(|addr| match command {
+                            b'Z' => insert_breakpoint(addr),
+                            _ => remove_breakpoint(addr),
+                        });
+                        reply.status(result);
+                    }
+                    _ => {}
+                },
+                b'c' | b's' => {
+                    if let Some(addr) = parse_hex(args) {
+                        self.frame.rip = addr;
+                    }
+                    if command == b's' {
+                        self.frame.rflags |= TRAP_FLAG;
+                    }
+                    RESUMED.store(true, Ordering::SeqCst);
+                    return;
+                }
+                b'D' | b'k' => {
+                    remove_all_breakpoints();
+                    if command == b'D' {
+                        self.send_packet(b"OK");
+                    }
+                    return;
+                }
+                b'H' => reply.push_str(b"OK"),
+                b'q' if args.starts_with(b"Supported") => reply.push_str(b"PacketSize=400"),
+                b'q' if args == b"Attached" => reply.push_str(b"1"),
+                // 知らないパケットには空の応答を返す (gdb が代わりの方法を選ぶ)
+                _ => {}
+            }
+            self.send_packet(reply.as_bytes());
+        }
+    }
+
+    /// g: 汎用レジスタ 17 個 (8 バイト) と eflags, cs, ss, ds, es, fs, gs (4 バイト)
+    fn read_registers(&mut self, reply: &mut Reply) {
+        for value in self.frame.gprs_mut() {
+            reply.push_hex(&value.to_le_bytes());
+        }
+        let (ds, es, fs, gs): (u16, u16, u16, u16);
+        unsafe {
+            core::arch::asm!(
+                "mov {0:x}, ds", "mov {1:x}, es", "mov {2:x}, fs", "mov {3:x}, gs",
+                out(reg) ds, out(reg) es, out(reg) fs, out(reg) gs,
+                options(nomem, nostack),
+            );
+        }
+        let frame = &*self.frame;
+        for value in [frame.rflags, frame.cs, frame.ss, ds as u64, es as u64, fs as u64, gs as u64] {
+            reply.push_hex(&(value as u32).to_le_bytes());
+        }
+    }
+
+    /// G: 汎用レジスタと rip, rflags を書き換える (セグメントは変えない)
+    fn write_registers(&mut self, args: &[u8]) -> Option<()> {
+        let mut bytes = [0u8; 17 * 8 + 4];
+        let len = decode_hex(args, &mut bytes)?;
+        if len < bytes.len() {
+            return None;
+        }
+        for (value, chunk) in self.frame.gprs_mut().into_iter().zip(bytes.chunks(8)) {
+            *value = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        self.frame.rflags = u32::from_le_bytes(bytes[17 * 8..].try_into().unwrap()) as u64;
+        Some(())
+    }
+
+    /// m addr,len: 読めたところまでを返す (最初のバイトから読めなければエラー)
+    fn read_memory(&mut self, args: &[u8], reply: &mut Reply) {
+        let Some((addr, len)) = parse_range(args) else {
+            reply.push_str(b"E01");
+            return;
+        };
+        for i in 0..len.min(PACKET_SIZE / 2) as u64 {
+            match peek(addr.wrapping_add(i)) {
+                Some(byte) => reply.push_hex(&[byte]),
+                None if i == 0 => {
+                    reply.push_str(b"E14");
+                    return;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// M addr,len:data
+fn write_memory(args: &[u8]) -> Option<()> {
+    let colon = args.iter().position(|&c| c == b':')?;
+    let (addr, len) = parse_range(&args[..colon])?;
+    let data = &args[colon + 1..];
+    if data.len() != len * 2 {
+        return None;
+    }
+    for (i, pair) in data.chunks(2).enumerate() {
+        let byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        poke(addr.wrapping_add(i as u64), byte)?;
+    }
+    Some(())
+}
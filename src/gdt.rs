@@ -2,55 +2,185 @@ use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
 use lazy_static::lazy_static;
+use core::mem::{offset_of, size_of};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use crate::smp::MAX_CPUS;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
+const STACK_SIZE: usize = 4096 * 5;
+// AP はヒープから確保するので小さめにする
+const AP_STACK_SIZE: usize = 4096 * 2;
+
+// リング3からの割り込み/システムコール時に使うカーネルスタック (RSP0)
+// プロセス切り替え時に set_kernel_stack で差し替える
+static mut PRIVILEGE_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+/// I/O 許可ビットマップの大きさ (ポート 1 つにつき 1 ビット、立っていれば禁止)
+pub const IO_BITMAP_BYTES: usize = 65536 / 8;
+
+/// TSS の直後に I/O 許可ビットマップを置く (iomap_base は TSS の大きさを指している)
+/// CPU はビットマップを 2 バイト単位で読むので、末尾にすべて立てた 1 バイトを足す
+#[repr(C)]
+struct Tss {
+    tss: TaskStateSegment,
+    io_bitmap: [u8; IO_BITMAP_BYTES],
+    io_bitmap_end: u8,
+}
+
+const _: () = assert!(offset_of!(Tss, io_bitmap) == size_of::<TaskStateSegment>());
+
+impl Tss {
+    /// どのポートも許さない状態で作る
+    const fn new() -> Self {
+        Self { tss: TaskStateSegment::new(), io_bitmap: [0xFF; IO_BITMAP_BYTES], io_bitmap_end: 0xFF }
+    }
+}
+
+/// TSS のディスクリプタ (リミットを I/O 許可ビットマップの末尾まで広げる)
+unsafe fn tss_descriptor(tss: *const Tss) -> Descriptor {
+    match Descriptor::tss_segment_unchecked(tss as *const TaskStateSegment) {
+        Descriptor::SystemSegment(low, high) => {
+            let limit = offset_of!(Tss, io_bitmap_end) as u64;
+            Descriptor::SystemSegment((low & !0xFFFF) | limit, high)
+        }
+        descriptor => descriptor,
+    }
+}
+
+// RSP0 と I/O 許可ビットマップを実行時に書き換えるため、TSS は可変な static に置く
+static mut TSS: Tss = Tss::new();
+
+/// CPUごとの TSS (AP の分は init_ap でヒープに作る)
+static CPU_TSS: [AtomicPtr<Tss>; MAX_CPUS] = [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CPUS];
+
+/// このCPUの TSS (init_ap 前の AP や gdt::init 前は BSP のもの)
+fn current_tss() -> &'static mut Tss {
+    let tss = CPU_TSS[crate::smp::cpu_id()].load(Ordering::SeqCst);
+    if tss.is_null() {
+        unsafe { &mut *core::ptr::addr_of_mut!(TSS) }
+    } else {
+        unsafe { &mut *tss }
+    }
+}
+
+fn stack_top(stack: *const [u8; STACK_SIZE]) -> VirtAddr {
+    VirtAddr::from_ptr(stack) + STACK_SIZE
+}
+
+fn init_tss() {
+    unsafe {
+        let tss = &mut (*core::ptr::addr_of_mut!(TSS)).tss;
+        tss.privilege_stack_table[0] = stack_top(core::ptr::addr_of!(PRIVILEGE_STACK));
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            stack_top(core::ptr::addr_of!(DOUBLE_FAULT_STACK));
+    }
 }
 
 lazy_static! {
+    // SYSRET の要件に合わせて user data → user code の順に並べる
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
         let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        let tss_selector = gdt.add_entry(unsafe { tss_descriptor(core::ptr::addr_of!(TSS)) });
         (
             gdt,
             Selectors {
                 code_selector,
                 data_selector,
+                user_code_selector,
+                user_data_selector,
                 tss_selector,
             },
         )
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    data_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+pub struct Selectors {
+    pub code_selector: SegmentSelector,
+    pub data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub tss_selector: SegmentSelector,
 }
 
 pub fn init() {
-    use x86_64::instructions::segmentation::{CS, DS, Segment};
+    use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
     use x86_64::instructions::tables::load_tss;
 
+    init_tss();
+    CPU_TSS[0].store(core::ptr::addr_of_mut!(TSS), Ordering::SeqCst);
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
+        SS::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// アプリケーションプロセッサ用に専用の TSS・スタック・GDT を作ってロードする
+/// セレクタの並びは BSP と同じなので selectors() はそのまま使える
+pub fn init_ap(cpu: usize) {
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
+    use x86_64::instructions::tables::load_tss;
+
+    fn alloc_stack() -> VirtAddr {
+        let stack: &'static mut [u8] = vec![0u8; AP_STACK_SIZE].leak();
+        VirtAddr::from_ptr(stack.as_ptr()) + AP_STACK_SIZE
+    }
+
+    // I/O 許可ビットマップの分だけ大きいので、AP の小さなスタックを経由せずヒープ上で初期化する
+    let tss: &'static mut Tss = unsafe {
+        let layout = core::alloc::Layout::new::<Tss>();
+        let ptr = alloc::alloc::alloc_zeroed(layout) as *mut Tss;
+        if ptr.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
+        &mut *ptr
+    };
+    tss.tss = TaskStateSegment::new();
+    tss.io_bitmap.fill(0xFF);
+    tss.io_bitmap_end = 0xFF;
+    tss.tss.privilege_stack_table[0] = alloc_stack();
+    tss.tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = alloc_stack();
+    CPU_TSS[cpu].store(tss, Ordering::SeqCst);
+
+    let gdt: &'static mut GlobalDescriptorTable = Box::leak(Box::new(GlobalDescriptorTable::new()));
+    let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+    let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+    gdt.add_entry(Descriptor::user_data_segment());
+    gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(unsafe { tss_descriptor(CPU_TSS[cpu].load(Ordering::SeqCst)) });
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(code_selector);
+        DS::set_reg(data_selector);
+        ES::set_reg(data_selector);
+        SS::set_reg(data_selector);
+        load_tss(tss_selector);
+    }
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// リング3 → リング0 遷移時に CPU が切り替えるスタック (このCPUの TSS.RSP0) を設定する
+pub fn set_kernel_stack(stack_top: VirtAddr) {
+    current_tss().tss.privilege_stack_table[0] = stack_top;
+}
+
+/// このCPUの I/O 許可ビットマップを書き換える (CPU は in/out のたびに読むので、すぐに効く)
+pub fn with_io_bitmap<R>(f: impl FnOnce(&mut [u8; IO_BITMAP_BYTES]) -> R) -> R {
+    f(&mut current_tss().io_bitmap)
+}
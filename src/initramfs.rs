@@ -0,0 +1,187 @@
+use alloc::string::String;
+use crate::filesystem::{self, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG};
+
+/// newc 形式 cpio のヘッダ長 ("070701" + 13 個の 8 桁 16 進数)
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// 展開した結果
+#[derive(Default)]
+struct Summary {
+    files: usize,
+    directories: usize,
+    symlinks: usize,
+    skipped: usize,
+}
+
+/// アーカイブ内のパスを絶対パスにする ("./bin/sh" → "/bin/sh")
+fn archive_path(name: &str) -> Option<String> {
+    let name = name.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/');
+    if name.is_empty() || name == "." {
+        return None;
+    }
+    Some(filesystem::normalize_path(&alloc::format!("/{}", name)))
+}
+
+/// 親ディレクトリがなければ作る (アーカイブがディレクトリを省略していることがある)
+fn create_parents(path: &str, summary: &mut Summary) {
+    let mut current = String::new();
+    let parts: alloc::vec::Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    for part in parts.iter().take(parts.len().saturating_sub(1)) {
+        current.push('/');
+        current.push_str(part);
+        if filesystem::mkdir(&current, 0o755).is_ok() {
+            summary.directories += 1;
+        }
+    }
+}
+
+fn install(path: &str, mode: u32, data: &[u8], summary: &mut Summary) {
+    create_parents(path, summary);
+    let permissions = mode & 0o777;
+    match mode & S_IFMT {
+        S_IFDIR => {
+            if filesystem::mkdir(path, permissions).is_ok() {
+                summary.directories += 1;
+            }
+        }
+        S_IFREG => match filesystem::install_file(path, data, permissions) {
+            Ok(()) => summary.files += 1,
+            Err(e) => {
+                crate::warn!("initramfs: {}: {}", path, e);
+                summary.skipped += 1;
+            }
+        },
+        S_IFLNK => match core::str::from_utf8(data) {
+            Ok(target) if filesystem::symlink(target, path).is_ok() => summary.symlinks += 1,
+            _ => summary.skipped += 1,
+        },
+        // デバイスノードや FIFO は今のところ作らない
+        _ => summary.skipped += 1,
+    }
+}
+
+fn hex_field(field: &[u8]) -> Option<u32> {
+    u32::from_str_radix(core::str::from_utf8(field).ok()?, 16).ok()
+}
+
+fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+/// newc 形式 (070701 / 070702) の cpio を展開する
+fn unpack_cpio(archive: &[u8], summary: &mut Summary) -> Result<(), &'static str> {
+    let mut offset = 0;
+    loop {
+        let header = archive.get(offset..offset + CPIO_HEADER_SIZE).ok_or("Truncated cpio header")?;
+        if &header[0..6] != b"070701" && &header[0..6] != b"070702" {
+            return Err("Bad cpio magic");
+        }
+        let field = |index: usize| hex_field(&header[6 + index * 8..14 + index * 8]).ok_or("Bad cpio header");
+        let mode = field(1)?;
+        let file_size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        let name_start = offset + CPIO_HEADER_SIZE;
+        let name = archive.get(name_start..name_start + name_size).ok_or("Truncated cpio name")?;
+        // 名前は NUL 終端
+        let name = core::str::from_utf8(&name[..name_size.saturating_sub(1)]).map_err(|_| "Bad cpio name")?;
+        let data_start = align4(name_start + name_size);
+        let data = archive.get(data_start..data_start + file_size).ok_or("Truncated cpio data")?;
+        offset = align4(data_start + file_size);
+
+        if name == CPIO_TRAILER {
+            return Ok(());
+        }
+        if let Some(path) = archive_path(name) {
+            install(&path, mode, data, summary);
+        }
+    }
+}
+
+fn octal_field(field: &[u8]) -> Option<usize> {
+    let text = core::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    usize::from_str_radix(text, 8).ok()
+}
+
+fn c_string(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
+
+/// ustar 形式の tar を展開する
+fn unpack_tar(archive: &[u8], summary: &mut Summary) -> Result<(), &'static str> {
+    let mut offset = 0;
+    while let Some(header) = archive.get(offset..offset + TAR_BLOCK_SIZE) {
+        // 空のブロックが終端
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = octal_field(&header[124..136]).ok_or("Bad tar header")?;
+        let mode = octal_field(&header[100..108]).ok_or("Bad tar header")? as u32 & 0o7777;
+        let type_flag = header[156];
+
+        // prefix (ustar) + name
+        let name = c_string(&header[0..100]);
+        let prefix = c_string(&header[345..500]);
+        let full_name = if prefix.is_empty() {
+            String::from(name)
+        } else {
+            alloc::format!("{}/{}", prefix, name)
+        };
+
+        let data_start = offset + TAR_BLOCK_SIZE;
+        let data = archive.get(data_start..data_start + size).ok_or("Truncated tar data")?;
+        offset = data_start + size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+
+        let path = match archive_path(&full_name) {
+            Some(path) => path,
+            None => continue,
+        };
+        match type_flag {
+            b'0' | 0 => install(&path, S_IFREG | mode, data, summary),
+            b'5' => install(&path, S_IFDIR | mode, &[], summary),
+            b'2' => install(&path, S_IFLNK | 0o777, c_string(&header[157..257]).as_bytes(), summary),
+            _ => summary.skipped += 1,
+        }
+    }
+    Ok(())
+}
+
+fn is_tar(archive: &[u8]) -> bool {
+    archive.len() >= TAR_BLOCK_SIZE && &archive[257..262] == b"ustar"
+}
+
+/// ブートローダーが渡したモジュールのうち cpio / tar のものを ramfs に展開する
+pub fn init() -> Result<usize, &'static str> {
+    let modules = crate::boot::modules();
+    let mut unpacked = 0;
+    for module in modules {
+        let archive = module.data();
+        let mut summary = Summary::default();
+        let result = if archive.starts_with(b"0707") {
+            unpack_cpio(archive, &mut summary)
+        } else if is_tar(archive) {
+            unpack_tar(archive, &mut summary)
+        } else {
+            crate::debug!("Skipping module '{}' (not an archive)", module.cmdline);
+            continue;
+        };
+
+        match result {
+            Ok(()) => crate::info!("initramfs '{}': {} files, {} directories, {} symlinks ({} skipped)",
+                module.cmdline, summary.files, summary.directories, summary.symlinks, summary.skipped),
+            Err(e) => crate::warn!("initramfs '{}': {} after {} files", module.cmdline, e, summary.files),
+        }
+        unpacked += 1;
+    }
+    if unpacked == 0 {
+        return Err("No initramfs module");
+    }
+    Ok(unpacked)
+}
@@ -1,18 +1,52 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{PrivilegeLevel, VirtAddr};
 use lazy_static::lazy_static;
+use pic8259::ChainedPics;
+use spin::Mutex;
 use crate::gdt;
+use crate::sync::IrqMutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const PIC_1_OFFSET: u8 = 32;
+const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// 1つの割り込み線を共有できるハンドラ数 (PCI の INTx は共有されうる)
+const MAX_SHARED_HANDLERS: usize = 4;
+
+// ドライバが登録した IRQ ハンドラ (ISA IRQ 番号で引く)
+// 割り込みハンドラからも引くので、登録中は割り込みを止める
+static IRQ_HANDLERS: IrqMutex<[[Option<fn()>; MAX_SHARED_HANDLERS]; 16]> =
+    IrqMutex::new([[None; MAX_SHARED_HANDLERS]; 16]);
+
+/// ダブルフォルト時に表示するログの末尾の大きさ
+const DOUBLE_FAULT_LOG_BYTES: usize = 2048;
+/// ダブルフォルト後にリセットするまでの待ち時間
+const DOUBLE_FAULT_RESET_DELAY_MS: usize = 10_000;
+
+// ダブルフォルト後にキーボードコントローラ経由でリセットするか
+static RESET_ON_DOUBLE_FAULT: AtomicBool = AtomicBool::new(false);
+
+// APIC が使えない場合のフォールバック
+static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         
         // 例外ハンドラ
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        // #BP と #DB は全レジスタを保存する gdbstub の入口を通す
+        unsafe {
+            idt.breakpoint.set_handler_addr(VirtAddr::new(crate::gdbstub::breakpoint_entry as usize as u64));
+            idt.debug.set_handler_addr(VirtAddr::new(crate::gdbstub::debug_entry as usize as u64));
+        }
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
+        // #PF は IST を使わない (処理中に再びフォルトしても、処理中のフレームを上書きしない)
+        // カーネルスタックのガードページに触れた場合はフレームを積めずにダブルフォルトになり、そちらで報告する
         idt.page_fault.set_handler_fn(page_fault_handler);
         idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
         
@@ -21,9 +55,30 @@ lazy_static! {
             .set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()]
+            .set_handler_fn(mouse_interrupt_handler);
+
+        // 残りの ISA IRQ はドライバが register_irq で登録するハンドラに振り分ける
+        // (IRQ2 はスレーブ PIC のカスケード)
+        macro_rules! set_irq_handlers {
+            ($($irq:literal),*) => {
+                $( idt[(PIC_1_OFFSET + $irq) as usize].set_handler_fn(irq_handler::<$irq>); )*
+            };
+        }
+        set_irq_handlers!(3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 15);
+        idt[crate::process::scheduler::RESCHEDULE_VECTOR as usize]
+            .set_handler_fn(reschedule_interrupt_handler);
+        idt[crate::memory::TLB_SHOOTDOWN_VECTOR as usize]
+            .set_handler_fn(tlb_shootdown_interrupt_handler);
+        idt[crate::apic::SPURIOUS_VECTOR as usize]
+            .set_handler_fn(spurious_interrupt_handler);
         
-        // システムコール (int 0x80)
-        idt[0x80].set_handler_fn(syscall_interrupt_handler);
+        // システムコール (int 0x80) - リング3から呼び出せるよう DPL=3 に設定
+        unsafe {
+            idt[0x80]
+                .set_handler_addr(VirtAddr::new(crate::syscall::syscall_interrupt_entry as usize as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
         
         idt
     };
@@ -32,8 +87,9 @@ lazy_static! {
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
-    Timer = 32,
-    Keyboard = 33,
+    Timer = PIC_1_OFFSET,
+    Keyboard = PIC_1_OFFSET + 1,
+    Mouse = PIC_1_OFFSET + 12,
 }
 
 impl InterruptIndex {
@@ -51,44 +107,208 @@ pub fn init_idt() {
     init_pics();
 }
 
+/// アプリケーションプロセッサに IDT をロードする (IDT は全CPUで共有)
+pub fn load_idt() {
+    IDT.load();
+}
+
 fn init_pics() {
-    use pic8259::ChainedPics;
-    use spin::Mutex;
+    unsafe {
+        PICS.lock().initialize();
+    }
+    x86_64::instructions::interrupts::enable();
+}
+
+/// ISA IRQ を有効にする
+pub fn enable_irq(index: InterruptIndex) {
+    unmask_irq(index.as_u8() - PIC_1_OFFSET);
+}
+
+/// 割り込み線 irq にハンドラを登録して有効にする (PCI デバイスなど)
+/// ハンドラは割り込みコンテキストで呼ばれ、EOI はこちらで送る
+/// 線を共有している場合はすべてのハンドラが呼ばれるので、各自デバイスの状態を確認すること
+pub fn register_irq(irq: u8, handler: fn()) -> Result<(), &'static str> {
+    if irq >= 16 || matches!(irq, 0 | 1 | 2 | 12) {
+        return Err("IRQ not available");
+    }
+    let mut handlers = IRQ_HANDLERS.lock();
+    let slot = handlers[irq as usize]
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or("Too many handlers on IRQ")?;
+    *slot = Some(handler);
+    drop(handlers);
 
-    const PIC_1_OFFSET: u8 = 32;
-    const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+    unmask_irq(irq);
+    Ok(())
+}
 
-    static PICS: Mutex<ChainedPics> =
-        Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+/// APIC が有効なら IOAPIC にルーティングし、そうでなければ PIC のマスクを外す
+fn unmask_irq(irq: u8) {
+    let vector = PIC_1_OFFSET + irq;
+    if crate::apic::is_enabled() {
+        crate::apic::set_irq(crate::apic::isa_irq_to_gsi(irq), vector, crate::apic::lapic_id());
+        return;
+    }
 
     unsafe {
-        PICS.lock().initialize();
+        let mut pics = PICS.lock();
+        let [mut master, mut slave] = pics.read_masks();
+        if irq < 8 {
+            master &= !(1 << irq);
+        } else {
+            slave &= !(1 << (irq - 8));
+            // スレーブ PIC はマスタの IRQ2 にカスケードされている
+            master &= !(1 << 2);
+        }
+        pics.write_masks(master, slave);
+    }
+}
+
+/// 割り込み処理の完了を割り込みコントローラに通知する
+/// APIC が有効なら Local APIC に、そうでなければ PIC に送る
+pub fn end_of_interrupt(index: InterruptIndex) {
+    end_of_interrupt_vector(index.as_u8());
+}
+
+fn end_of_interrupt_vector(vector: u8) {
+    if crate::apic::is_enabled() {
+        crate::apic::end_of_interrupt();
+    } else {
+        unsafe {
+            PICS.lock().notify_end_of_interrupt(vector);
+        }
     }
-    x86_64::instructions::interrupts::enable();
 }
 
 // 例外ハンドラ
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    crate::println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+/// ダブルフォルト後に自動でリセットするかを設定する
+pub fn set_reset_on_double_fault(enabled: bool) {
+    RESET_ON_DOUBLE_FAULT.store(enabled, Ordering::SeqCst);
 }
 
+/// ダブルフォルトは専用の IST スタック上で動く
+/// panic せずに診断情報を出してから停止 (またはリセット) する
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
-    _error_code: u64,
+    error_code: u64,
 ) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    use x86_64::registers::control::{Cr2, Cr3};
+
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    x86_64::instructions::interrupts::disable();
+
+    crate::println!("EXCEPTION: DOUBLE FAULT (error code {:#x})", error_code);
+    crate::println!("{:#?}", stack_frame);
+    crate::println!("CR2: {:#x}  CR3: {:#x}",
+        Cr2::read_raw(),
+        Cr3::read().0.start_address().as_u64());
+    match crate::process::try_current_pid() {
+        Some(pid) => crate::println!("Current PID: {}", pid),
+        None => crate::println!("Current PID: <none or locked>"),
+    }
+
+    // スタック溢れで例外フレームを積めなかった場合もここに来る
+    let overflow = crate::kstack::guard_owner(Cr2::read())
+        .or_else(|| crate::kstack::guard_owner(stack_frame.stack_pointer));
+    if let Some(pid) = overflow {
+        crate::println!("Cause: kernel stack overflow in PID {}", pid);
+    }
+
+    crate::println!("--- recent log ---");
+    if !crate::log::dump_tail(DOUBLE_FAULT_LOG_BYTES) {
+        crate::println!("<log buffer locked>");
+    }
+    crate::println!("------------------");
+
+    if RESET_ON_DOUBLE_FAULT.load(Ordering::SeqCst) {
+        crate::println!("Resetting in {} seconds...", DOUBLE_FAULT_RESET_DELAY_MS / 1000);
+        crate::drivers::timer::busy_wait_ms(DOUBLE_FAULT_RESET_DELAY_MS);
+        crate::power::reboot();
+    }
+
+    loop {
+        x86_64::instructions::hlt();
+    }
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
+    use x86_64::registers::rflags::RFlags;
+
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+
+    // 書き込み保護違反ならコピーオンライトを試みる
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE) {
+        if crate::memory::handle_cow_fault(Cr2::read()) {
+            return;
+        }
+    }
+
+    // 未マップのページならVMAを調べてデマンドページングする
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        let addr = Cr2::read();
+        if let Some(vma) = crate::process::find_vma(addr) {
+            // 読み取り専用領域への書き込みは不正アクセスとして扱う
+            let write_to_readonly = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+                && !vma.flags.contains(x86_64::structures::paging::PageTableFlags::WRITABLE);
+            if !write_to_readonly && vma.fault_in(addr).is_ok() {
+                crate::process::with_current_process(|process| process.mapped_pages += 1);
+                return;
+            }
+        }
+    }
+
+    // copy_from_user などがユーザーの不正なアドレスに触れた場合は、EFAULT を返すところから再開させる
+    if !error_code.contains(PageFaultErrorCode::USER_MODE) && Cr2::read().as_u64() < crate::uaccess::USER_SPACE_END {
+        if let Some(fixup) = crate::uaccess::fixup(stack_frame.instruction_pointer.as_u64()) {
+            unsafe {
+                stack_frame.as_mut().update(|frame| frame.instruction_pointer = VirtAddr::new(fixup));
+            }
+            return;
+        }
+    }
+
+    // カーネルからのユーザーページへのアクセス (SMEP/SMAP 違反) や、
+    // 読み取り専用のカーネルイメージへの書き込み、実行不可ページの実行 (W^X 違反) は区別して報告する
+    let violation = if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        let instruction_fetch = error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH);
+        let user_access = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+            None
+        } else {
+            let access_window = stack_frame.cpu_flags & RFlags::ALIGNMENT_CHECK.bits() != 0;
+            crate::uaccess::describe_fault(Cr2::read().as_u64(), instruction_fetch, access_window)
+        };
+        user_access.or_else(|| crate::memory::describe_wx_violation(
+            Cr2::read(),
+            error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE),
+            instruction_fetch,
+        ))
+    } else {
+        None
+    };
+
+    // ユーザーモードからの不正アクセスはプロセスを終了させる (SIGSEGV相当)
+    if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        match violation {
+            Some(violation) => crate::warn_ratelimited!("Segmentation fault at {:?} ({})", Cr2::read(), violation),
+            None => crate::warn_ratelimited!("Segmentation fault at {:?}", Cr2::read()),
+        }
+        crate::process::exit_and_reschedule(-11);
+    }
 
     crate::println!("EXCEPTION: PAGE FAULT");
+    if let Some(violation) = violation {
+        crate::println!("{} (at {:?}, rip {})", violation, Cr2::read(),
+            crate::symbols::Sym(stack_frame.instruction_pointer.as_u64()));
+    }
     crate::println!("Accessed Address: {:?}", Cr2::read());
     crate::println!("Error Code: {:?}", error_code);
+    crate::println!("Faulting Instruction: {}", crate::symbols::Sym(stack_frame.instruction_pointer.as_u64()));
     crate::println!("{:#?}", stack_frame);
     
     loop {
@@ -100,6 +320,21 @@ extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    // ユーザーモードからの #GP (許していない I/O ポートへのアクセスや特権命令) はプロセスを終了させる
+    if stack_frame.code_segment & 3 == 3 {
+        let rip = stack_frame.instruction_pointer.as_u64();
+        let mut code = [0u8; 4];
+        let port_access = crate::uaccess::copy_from_user(&mut code, rip).is_ok()
+            && crate::ioport::is_port_instruction(&code);
+        if port_access {
+            crate::warn_ratelimited!("I/O port access without permission at {:#x}", rip);
+        } else {
+            crate::warn_ratelimited!("General protection fault at {:#x} (error code {:#x})", rip, error_code);
+        }
+        crate::process::exit_and_reschedule(-11);
+    }
+
     crate::println!("EXCEPTION: GENERAL PROTECTION FAULT");
     crate::println!("Error Code: {:#x}", error_code);
     crate::println!("{:#?}", stack_frame);
@@ -111,24 +346,55 @@ extern "x86-interrupt" fn general_protection_fault_handler(
 
 // ハードウェア割り込みハンドラ
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    crate::profiler::sample(&stack_frame);
+    crate::watchdog::check(&stack_frame);
     crate::drivers::timer::handle_interrupt();
+
+    // RLIMIT_CPU を使い切ったユーザープロセスは終了させる (SIGXCPU相当)
+    if stack_frame.code_segment & 3 == 3 && crate::process::cpu_limit_exceeded() {
+        crate::warn!("CPU time limit exceeded");
+        crate::process::exit_and_reschedule(-24);
+    }
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
     crate::drivers::keyboard::handle_interrupt();
 }
 
-// システムコール割り込みハンドラ
-extern "x86-interrupt" fn syscall_interrupt_handler(mut stack_frame: InterruptStackFrame) {
-    // レジスタからシステムコール番号と引数を取得
-    // 注: 実際の実装ではスタックフレームからレジスタ値を取得
-    // この簡易版では、システムコールハンドラを直接呼び出すことはできない
-    
-    // システムコールの戻り値をraxに設定
-    // stack_frame に戻り値を設定する処理
-    
-    crate::println!("Syscall interrupt received");
+extern "x86-interrupt" fn mouse_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    crate::drivers::mouse::handle_interrupt();
+}
+
+extern "x86-interrupt" fn irq_handler<const IRQ: u8>(stack_frame: InterruptStackFrame) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    let handlers = IRQ_HANDLERS.lock()[IRQ as usize];
+    for handler in handlers.iter().flatten() {
+        handler();
+    }
+    end_of_interrupt_vector(PIC_1_OFFSET + IRQ);
+}
+
+// 他のCPUからの再スケジューリング要求 (IPI)
+extern "x86-interrupt" fn reschedule_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    crate::process::scheduler::tick();
+    crate::apic::end_of_interrupt();
+}
+
+// 他のCPUがページのマップを外したときの TLB 無効化要求 (IPI)
+extern "x86-interrupt" fn tlb_shootdown_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    crate::memory::handle_tlb_shootdown();
+    crate::apic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = crate::smp::KernelGs::enter(&stack_frame);
+    // スプリアス割り込みには EOI を送らない
 }
 
 #[test_case]
@@ -0,0 +1,133 @@
+//! ユーザープロセスの I/O ポートへのアクセス権 (ioperm/iopl)
+//! リング3は IOPL 0 で動かすので、in/out は TSS の I/O 許可ビットマップで許したポートにしか使えない
+//! 許していないポートに触れると #GP になり、プロセスを終了させる
+
+use alloc::boxed::Box;
+use alloc::vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::errno::Errno;
+use crate::gdt::IO_BITMAP_BYTES;
+use crate::smp::MAX_CPUS;
+
+/// ポートの数
+pub const IO_PORTS: usize = IO_BITMAP_BYTES * 8;
+/// iopl でこのレベルにするとすべてのポートを許す
+pub const IOPL_ALL: u8 = 3;
+
+/// プロセスごとの I/O ポートのアクセス権 (fork で子に引き継ぐ)
+#[derive(Debug, Clone, Default)]
+pub struct IoPermissions {
+    /// ioperm で許したポート (TSS と同じく、ビットが立っていれば禁止)
+    bitmap: Option<Box<[u8; IO_BITMAP_BYTES]>>,
+    /// iopl で設定したレベル
+    level: u8,
+}
+
+impl IoPermissions {
+    pub const fn new() -> Self {
+        Self { bitmap: None, level: 0 }
+    }
+
+    /// [from, from + num) のポートを許す/禁止する
+    pub fn set(&mut self, from: usize, num: usize, allow: bool) -> Result<(), Errno> {
+        let end = from.checked_add(num).filter(|&end| end <= IO_PORTS).ok_or(Errno::EINVAL)?;
+        if !allow && self.bitmap.is_none() {
+            return Ok(());
+        }
+        // 8KB あるので、カーネルスタックを経由せずヒープに作る
+        let bitmap = self.bitmap.get_or_insert_with(|| {
+            vec![0xFF; IO_BITMAP_BYTES].into_boxed_slice().try_into().unwrap()
+        });
+        for port in from..end {
+            if allow {
+                bitmap[port / 8] &= !(1 << (port % 8));
+            } else {
+                bitmap[port / 8] |= 1 << (port % 8);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn set_level(&mut self, level: u8) -> Result<(), Errno> {
+        if level > IOPL_ALL {
+            return Err(Errno::EINVAL);
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    pub fn allows(&self, port: u16) -> bool {
+        let port = port as usize;
+        self.level == IOPL_ALL
+            || self.bitmap.as_ref().is_some_and(|bitmap| bitmap[port / 8] & (1 << (port % 8)) == 0)
+    }
+}
+
+/// このCPUの TSS にどれかのポートを許したビットマップを読み込んでいるか
+static OPENED: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// permissions をこのCPUの I/O 許可ビットマップに読み込む (リング3に入る前に呼ぶ)
+pub fn load(permissions: &IoPermissions) {
+    let opened = &OPENED[crate::smp::cpu_id()];
+    match (&permissions.bitmap, permissions.level) {
+        (_, IOPL_ALL) => crate::gdt::with_io_bitmap(|bitmap| bitmap.fill(0)),
+        (Some(allowed), _) => crate::gdt::with_io_bitmap(|bitmap| bitmap.copy_from_slice(&allowed[..])),
+        // 何も許していなければ、前のプロセスの分が残っているときだけ閉じる
+        (None, _) => {
+            if opened.swap(false, Ordering::SeqCst) {
+                crate::gdt::with_io_bitmap(|bitmap| bitmap.fill(0xFF));
+            }
+            return;
+        }
+    }
+    opened.store(true, Ordering::SeqCst);
+}
+
+/// このCPUの I/O 許可ビットマップを閉じる (どのポートも許さない)
+pub fn revoke() {
+    load(&IoPermissions::new());
+}
+
+/// 現在のプロセスの [from, from + num) のポートを許す/禁止する (ioperm)
+/// 許すのは root だけ (禁止はだれでもできる)
+/// 同じプロセスのほかのCPUで動いているスレッドには、次にリング3に入るときから効く
+pub fn ioperm(from: usize, num: usize, turn_on: bool) -> Result<(), Errno> {
+    // 資格情報はプロセス管理のロックを取って読むので、先に取り出しておく
+    if turn_on && !crate::cred::current().is_root() {
+        return Err(Errno::EPERM);
+    }
+    crate::process::with_current_process(|process| {
+        process.io.set(from, num, turn_on)?;
+        load(&process.io);
+        Ok(())
+    })
+    .ok_or(Errno::ESRCH)?
+}
+
+/// 現在のプロセスの I/O 特権レベルを変える (iopl)
+/// Linux と同じく RFLAGS.IOPL は変えず、レベル 3 ならすべてのポートを許すビットマップで代える
+/// (cli/sti は許さない)。レベルを上げられるのは root だけ
+pub fn iopl(level: u32) -> Result<(), Errno> {
+    let level = u8::try_from(level).ok().filter(|&level| level <= IOPL_ALL).ok_or(Errno::EINVAL)?;
+    let cred = crate::cred::current();
+    crate::process::with_current_process(|process| {
+        if level > process.io.level() && !cred.is_root() {
+            return Err(Errno::EPERM);
+        }
+        process.io.set_level(level)?;
+        load(&process.io);
+        Ok(())
+    })
+    .ok_or(Errno::ESRCH)?
+}
+
+/// 命令の先頭 (プレフィックスを除く) が in/out/ins/outs か (#GP の原因を報告するのに使う)
+pub fn is_port_instruction(code: &[u8]) -> bool {
+    // オペランドサイズ・アドレスサイズ・rep と REX のプレフィックスを読み飛ばす
+    let opcode = code.iter().find(|&&byte| !matches!(byte, 0x66 | 0x67 | 0xF2 | 0xF3 | 0x40..=0x4F));
+    matches!(opcode, Some(0x6C..=0x6F | 0xE4..=0xE7 | 0xEC..=0xEF))
+}
@@ -0,0 +1,81 @@
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+const PAGE_SIZE: usize = 4096;
+
+/// プロセスごとのカーネルスタックのページ数 (ガードページを除く)
+pub const KERNEL_STACK_PAGES: usize = 2;
+
+// ガードページの先頭アドレス → 持ち主の PID
+// 例外ハンドラから参照するので、ハンドラ側は try_lock で読む
+static GUARD_PAGES: Mutex<BTreeMap<u64, usize>> = Mutex::new(BTreeMap::new());
+
+/// 最下位にガードページを持つカーネルスタック
+/// ページ境界に揃えてヒープから確保し、一番下のページを非PRESENTにする
+/// スタックがあふれるとガードページでページフォルトになる
+pub struct KernelStack {
+    base: *mut u8,
+    layout: Layout,
+    /// ガードページ化する前のフラグ (ガードを張れなかった場合は None)
+    guard_flags: Option<PageTableFlags>,
+}
+
+// スタック領域は所有者のプロセスからしか触らない
+unsafe impl Send for KernelStack {}
+
+impl KernelStack {
+    pub fn new(pid: usize, pages: usize) -> Self {
+        let layout = Layout::from_size_align((pages + 1) * PAGE_SIZE, PAGE_SIZE)
+            .expect("invalid kernel stack layout");
+        let base = unsafe { alloc_zeroed(layout) };
+        if base.is_null() {
+            handle_alloc_error(layout);
+        }
+
+        let guard = VirtAddr::from_ptr(base);
+        let guard_flags = match crate::memory::guard_page(guard) {
+            Ok(flags) => {
+                GUARD_PAGES.lock().insert(guard.as_u64(), pid);
+                Some(flags)
+            }
+            Err(e) => {
+                // ガードなしでも動作はするので続行する
+                crate::warn!("No guard page for PID {} kernel stack: {}", pid, e);
+                None
+            }
+        };
+
+        Self { base, layout, guard_flags }
+    }
+
+    /// スタックのトップ (ここから下位アドレスに向かって伸びる)
+    pub fn top(&self) -> VirtAddr {
+        VirtAddr::from_ptr(self.base) + self.layout.size()
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let guard = VirtAddr::from_ptr(self.base);
+        if let Some(flags) = self.guard_flags {
+            GUARD_PAGES.lock().remove(&guard.as_u64());
+            // ヒープに返す前にページを戻しておく
+            if crate::memory::unguard_page(guard, flags).is_err() {
+                // 戻せなければ領域ごとリークさせる
+                crate::warn!("Failed to restore guard page at {:?}", guard);
+                return;
+            }
+        }
+        unsafe { dealloc(self.base, self.layout) };
+    }
+}
+
+/// addr がカーネルスタックのガードページ内なら、そのスタックを持つ PID を返す
+/// 例外ハンドラから呼ばれるため、ロック中なら諦めて None を返す
+pub fn guard_owner(addr: VirtAddr) -> Option<usize> {
+    let page = addr.align_down(PAGE_SIZE as u64).as_u64();
+    GUARD_PAGES.try_lock()?.get(&page).copied()
+}
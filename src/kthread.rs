@@ -0,0 +1,80 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use spin::Mutex;
+use crate::process::{self, Process};
+use crate::sync::WaitQueue;
+
+/// スレッド開始時に渡すクロージャ (型を消してヒープに置く)
+type ThreadMain = Box<dyn FnOnce() + Send + 'static>;
+
+/// カーネルスレッドの終了を待って戻り値を受け取るハンドル
+pub struct JoinHandle<T> {
+    pid: usize,
+    result: Arc<Mutex<Option<T>>>,
+    done: Arc<WaitQueue>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn pid(&self) -> usize {
+        self.pid
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.result.lock().is_some()
+    }
+
+    /// スレッドが終了するまで待ち、クロージャの戻り値を返す
+    pub fn join(self) -> T {
+        self.done.wait_until(|| self.result.lock().is_some());
+        self.result.lock().take().unwrap()
+    }
+}
+
+/// 初回のコンテキストスイッチで ret により到達する入口
+/// switch_context が復元した rdi にクロージャのポインタが入っている
+extern "C" fn kthread_entry(main: *mut ThreadMain) -> ! {
+    let main = unsafe { Box::from_raw(main) };
+    main();
+
+    process::exit(0);
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// クロージャを実行するカーネルスレッドを作成する
+pub fn spawn<F, T>(name: &str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(Mutex::new(None));
+    let done = Arc::new(WaitQueue::new());
+    let slot = result.clone();
+    let waiters = done.clone();
+    let main: ThreadMain = Box::new(move || {
+        let value = f();
+        *slot.lock() = Some(value);
+        waiters.wake_all();
+    });
+    let main = Box::into_raw(Box::new(main));
+
+    let mut process = Process::new(kthread_entry as usize as u64).with_name(name);
+    let thread = process.main_thread_mut();
+
+    // switch_context の ret が kthread_entry に飛ぶよう、スタックトップに戻りアドレスを積む
+    // 関数入口で rsp + 8 が 16 バイト境界になるよう調整する
+    let stack_top = thread.kernel_stack_top().as_u64() & !0xF;
+    let return_slot = stack_top - 16;
+    unsafe {
+        *(return_slot as *mut u64) = kthread_entry as usize as u64;
+    }
+    thread.context.rsp = return_slot;
+    thread.context.rbp = 0;
+    thread.context.rdi = main as u64;
+
+    let pid = process::add_process(process);
+    crate::debug!("Spawned kernel thread '{}' (PID {})", name, pid);
+
+    JoinHandle { pid, result, done }
+}
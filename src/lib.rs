@@ -0,0 +1,162 @@
+#![no_std]
+#![cfg_attr(test, no_main)]
+
+#![feature(custom_test_frameworks)]
+#![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+pub mod abi;
+pub mod boot;
+pub mod bootstage;
+pub mod cmdline;
+pub mod config;
+pub mod cpu;
+pub mod log;
+pub mod memory;
+pub mod allocator;
+pub mod process;
+pub mod sched;
+pub mod rlimit;
+pub mod cred;
+pub mod ioport;
+pub mod elf;
+pub mod flat;
+pub mod kthread;
+pub mod kstack;
+pub mod fpu;
+pub mod sync;
+pub mod errno;
+pub mod fault;
+pub mod syscall;
+pub mod strace;
+pub mod uaccess;
+pub mod filesystem;
+pub mod procfs;
+pub mod fd;
+pub mod pipe;
+pub mod shm;
+pub mod mqueue;
+pub mod poll;
+pub mod drivers;
+pub mod interrupts;
+pub mod softirq;
+pub mod events;
+pub mod watchdog;
+pub mod power;
+pub mod rand;
+pub mod apic;
+pub mod acpi;
+pub mod smp;
+pub mod gdt;
+pub mod demo;
+pub mod backtrace;
+pub mod crashdump;
+pub mod symbols;
+pub mod profiler;
+pub mod gdbstub;
+pub mod time;
+pub mod vdso;
+pub mod net;
+pub mod initramfs;
+pub mod snapshot;
+pub mod serial;
+
+/// テストカーネル用の最小限の初期化 (GDT, IDT, メモリ, ヒープ)
+pub fn init(magic: u32, info: u32) {
+    boot::init(magic, info);
+    drivers::vga::init();
+    gdt::init();
+    interrupts::init_idt();
+    memory::init();
+    memory::init_heap().expect("Heap initialization failed");
+}
+
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// isa-debug-exit デバイスに書く終了コード
+/// QEMU の終了ステータスは (code << 1) | 1 になる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// isa-debug-exit (iobase=0xf4) 経由で QEMU を終了させる
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    use x86_64::instructions::port::Port;
+
+    unsafe {
+        let mut port = Port::new(0xf4);
+        port.write(exit_code as u32);
+    }
+}
+
+/// テストケース名を表示しながら実行する
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// テスト中の panic は失敗として QEMU を終了させる
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+#[cfg(test)]
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    init(magic, info);
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    panic!("allocation error: {:?}", layout)
+}
+
+// 簡易printlnマクロ
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::drivers::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
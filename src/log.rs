@@ -0,0 +1,340 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::sync::IrqMutex;
+
+/// リングバッファのサイズ (ヒープ初期化前から使えるよう静的に確保)
+const LOG_BUFFER_SIZE: usize = 16 * 1024;
+/// モジュール別レベル設定の最大数
+const MAX_MODULE_FILTERS: usize = 16;
+/// *_ratelimited! が出力を数える期間 (ミリ秒)
+pub const RATELIMIT_INTERVAL_MS: usize = 5000;
+/// *_ratelimited! が1期間に出力する数
+pub const RATELIMIT_BURST: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    fn from_u8(value: u8) -> Level {
+        match value {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl core::str::FromStr for Level {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Level, ()> {
+        match name {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+// リングバッファに記録する最大レベル
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(Level::Debug as u8);
+// 画面にも表示する最大レベル
+static CONSOLE_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+// 割り込みハンドラのログからも引くので IrqMutex にする
+static MODULE_LEVELS: IrqMutex<[Option<(&'static str, Level)>; MAX_MODULE_FILTERS]> =
+    IrqMutex::new([None; MAX_MODULE_FILTERS]);
+
+static LOG_BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+
+/// 最近のログを保持する固定長リングバッファ
+/// いっぱいになると古いものから上書きする
+struct LogBuffer {
+    data: [u8; LOG_BUFFER_SIZE],
+    head: usize, // 次に書き込む位置
+    len: usize,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            data: [0; LOG_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.data[self.head] = byte;
+        self.head = (self.head + 1) % LOG_BUFFER_SIZE;
+        if self.len < LOG_BUFFER_SIZE {
+            self.len += 1;
+        }
+    }
+
+    /// 古い順に index 番目のバイト
+    fn get(&self, index: usize) -> u8 {
+        let start = (self.head + LOG_BUFFER_SIZE - self.len) % LOG_BUFFER_SIZE;
+        self.data[(start + index) % LOG_BUFFER_SIZE]
+    }
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+pub fn set_level(level: Level) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn set_console_level(level: Level) {
+    CONSOLE_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// モジュール単位でレベルを上書きする (例: "process", "filesystem")
+pub fn set_module_level(module: &'static str, level: Level) -> Result<(), &'static str> {
+    let mut filters = MODULE_LEVELS.lock();
+    for slot in filters.iter_mut() {
+        match slot {
+            Some((name, existing)) if *name == module => {
+                *existing = level;
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+    let slot = filters.iter_mut().find(|slot| slot.is_none()).ok_or("Too many module filters")?;
+    *slot = Some((module, level));
+    Ok(())
+}
+
+/// module_path!() からクレート名を除いたモジュール名
+fn short_module(module_path: &str) -> &str {
+    match module_path.find("::") {
+        Some(pos) => &module_path[pos + 2..],
+        None => module_path,
+    }
+}
+
+fn max_level_for(module: &str) -> Level {
+    let filters = MODULE_LEVELS.lock();
+    for (name, level) in filters.iter().flatten() {
+        if module == *name || (module.starts_with(name) && module[name.len()..].starts_with("::")) {
+            return *level;
+        }
+    }
+    Level::from_u8(LOG_LEVEL.load(Ordering::Relaxed))
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module_path: &'static str, args: fmt::Arguments) {
+    let module = short_module(module_path);
+    if level > max_level_for(module) {
+        return;
+    }
+
+    let uptime = crate::drivers::timer::get_uptime_ms();
+    let wall = crate::time::unix_to_datetime(crate::time::unix_time());
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut buffer = LOG_BUFFER.lock();
+        let _ = writeln!(buffer, "[{:>5}.{:03}] {:02}:{:02}:{:02} {:<5} {}: {}",
+            uptime / 1000, uptime % 1000, wall.hour, wall.minute, wall.second,
+            level.as_str(), module, args);
+    });
+
+    if level as u8 <= CONSOLE_LEVEL.load(Ordering::Relaxed) {
+        crate::println!("[{}] {}: {}", level.as_str(), module, args);
+    }
+}
+
+/// リングバッファの内容を古い順に buf へコピーする (/proc/kmsg 相当)
+pub fn read_kmsg(buf: &mut [u8]) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let buffer = LOG_BUFFER.lock();
+        let count = core::cmp::min(buf.len(), buffer.len);
+        // 入り切らない場合は新しい方を優先する
+        let skip = buffer.len - count;
+        for (i, byte) in buf.iter_mut().take(count).enumerate() {
+            *byte = buffer.get(skip + i);
+        }
+        count
+    })
+}
+
+/// リングバッファの内容をすべて表示する (dmesg)
+pub fn dmesg() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let buffer = LOG_BUFFER.lock();
+        for i in 0..buffer.len {
+            let byte = buffer.get(i);
+            crate::print!("{}", byte as char);
+        }
+    });
+}
+
+/// リングバッファの末尾 max_bytes バイトを表示する (例外時の診断用)
+/// ロック中に落ちた場合に備え、取れなければ何もしない
+pub fn dump_tail(max_bytes: usize) -> bool {
+    for_each_tail_byte(max_bytes, |byte| crate::print!("{}", byte as char))
+}
+
+/// リングバッファの末尾 max_bytes バイトを (行の頭から) 古い順に f に渡す
+/// ロック中に落ちた場合に備え、取れなければ何もせず false を返す
+pub fn for_each_tail_byte(max_bytes: usize, mut f: impl FnMut(u8)) -> bool {
+    let buffer = match LOG_BUFFER.try_lock() {
+        Some(buffer) => buffer,
+        None => return false,
+    };
+    let count = core::cmp::min(max_bytes, buffer.len);
+    let mut start = buffer.len - count;
+    // 行の途中から始まらないよう次の改行まで進める
+    if start > 0 {
+        while start < buffer.len && buffer.get(start - 1) != b'\n' {
+            start += 1;
+        }
+    }
+    for i in start..buffer.len {
+        f(buffer.get(i));
+    }
+    true
+}
+
+/// 一定の期間に出力する数を制限する (Linux の printk_ratelimit 相当)
+/// 割り込みハンドラからも使えるよう、ロックを取らずアトミック変数だけで数える
+pub struct RateLimit {
+    interval_ms: usize,
+    burst: u32,
+    window_start: AtomicUsize,
+    printed: AtomicU32,
+    suppressed: AtomicU32,
+}
+
+impl RateLimit {
+    pub const fn new(interval_ms: usize, burst: u32) -> Self {
+        Self {
+            interval_ms,
+            burst,
+            window_start: AtomicUsize::new(0),
+            printed: AtomicU32::new(0),
+            suppressed: AtomicU32::new(0),
+        }
+    }
+
+    /// 出力してよければ、前回出力してから抑えた数を返す
+    pub fn check(&self) -> Option<u32> {
+        let now = crate::drivers::timer::get_uptime_ms();
+        let start = self.window_start.load(Ordering::Relaxed);
+        if now.wrapping_sub(start) >= self.interval_ms
+            && self.window_start.compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+        {
+            self.printed.store(0, Ordering::Relaxed);
+        }
+        if self.printed.fetch_add(1, Ordering::Relaxed) < self.burst {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => (
+        $crate::log::_log($level, module_path!(), format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
+
+/// 呼び出し箇所ごとに RATELIMIT_INTERVAL_MS の間 RATELIMIT_BURST 回までしか出力しない log!
+/// (ページフォルトなど、繰り返し起きうる箇所で使う)
+#[macro_export]
+macro_rules! log_ratelimited {
+    ($level:expr, $($arg:tt)*) => {{
+        static LIMIT: $crate::log::RateLimit =
+            $crate::log::RateLimit::new($crate::log::RATELIMIT_INTERVAL_MS, $crate::log::RATELIMIT_BURST);
+        if let Some(suppressed) = LIMIT.check() {
+            if suppressed > 0 {
+                $crate::log!($level, "{} messages suppressed", suppressed);
+            }
+            $crate::log!($level, $($arg)*);
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! warn_ratelimited {
+    ($($arg:tt)*) => ($crate::log_ratelimited!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug_ratelimited {
+    ($($arg:tt)*) => ($crate::log_ratelimited!($crate::log::Level::Debug, $($arg)*));
+}
+
+/// 呼び出し箇所ごとに出力する数を制限する println!
+#[macro_export]
+macro_rules! println_ratelimited {
+    ($($arg:tt)*) => {{
+        static LIMIT: $crate::log::RateLimit =
+            $crate::log::RateLimit::new($crate::log::RATELIMIT_INTERVAL_MS, $crate::log::RATELIMIT_BURST);
+        if let Some(suppressed) = LIMIT.check() {
+            if suppressed > 0 {
+                $crate::println!("({} messages suppressed)", suppressed);
+            }
+            $crate::println!($($arg)*);
+        }
+    }};
+}
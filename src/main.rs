@@ -1,30 +1,22 @@
-
 #![no_std]
 #![no_main]
 
-#![feature(custom_test_frameworks)]
-#![feature(abi_x86_interrupt)]
-#![feature(alloc_error_handler)]
-mod boot;
-extern crate alloc;
-
+#[macro_use]
+extern crate rust_os_kernel;
 
 use core::panic::PanicInfo;
-
-mod memory;
-mod process;
-mod syscall;
-mod filesystem;
-mod drivers;
-mod interrupts;
-mod gdt;
-mod demo;
+use rust_os_kernel::{
+    acpi, apic, backtrace, boot, bootstage, cmdline, config, cpu, crashdump, demo, drivers, events, fault,
+    filesystem, fpu, gdbstub, gdt, initramfs, interrupts, memory, net, process, profiler, rand, smp, snapshot,
+    softirq, symbols, syscall, time, uaccess, vdso, watchdog,
+};
 
 
 #[no_mangle]
-pub extern "C" fn _start(_magic: u32, _info: u32) -> ! {
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
     // unsafe { let vga = 0xb8000 as *mut u8; *vga = b'H'; *vga.add(1) = 0x0f; }
     
+    boot::init(magic, info);
     drivers::vga::init();
     println!("RustOS Kernel v0.1.0");
     println!("Booted via GRUB (Multiboot2)");
@@ -44,42 +36,173 @@ fn kernel_main() -> ! {
     println!("Initializing...");
 
     // GDT初期化
+    let stage = bootstage::begin("gdt");
     gdt::init();
-    println!("[OK] GDT initialized");
+    stage.ok("GDT initialized");
 
     // 割り込み初期化
+    let stage = bootstage::begin("idt");
     interrupts::init_idt();
-    println!("[OK] IDT initialized");
+    stage.ok("IDT initialized");
+
+    // CPU の機能を調べる (以降の初期化はこの結果を見て有効にするものを決める)
+    let stage = bootstage::begin("cpu");
+    let cpu = cpu::init();
+    stage.ok(format_args!("CPU detected ({} family {:#x} model {:#x} stepping {})",
+        cpu.vendor(), cpu.family, cpu.model, cpu.stepping));
+
+    // 割り込みの後半処理とイベントバス
+    let stage = bootstage::begin("softirq");
+    softirq::init();
+    events::init();
+    stage.done();
 
     // メモリ管理初期化
+    let stage = bootstage::begin("memory");
     memory::init();
-    println!("[OK] Memory management initialized");
+    stage.ok("Memory management initialized");
 
     // ヒープアロケータ初期化
+    let stage = bootstage::begin("heap");
     memory::init_heap().expect("Heap initialization failed");
-    println!("[OK] Heap allocator initialized");
+    stage.ok("Heap allocator initialized");
+
+    // カーネルイメージを W^X で保護し直す
+    let stage = bootstage::begin("protect");
+    match memory::protect_kernel() {
+        Ok(pages) => stage.ok(format_args!("Kernel image protected ({} pages, NX {})",
+            pages, if memory::nx_enabled() { "enabled" } else { "unsupported" })),
+        Err(e) => stage.fail(format_args!("Kernel image protection failed ({})", e)),
+    }
+
+    // ユーザー空間へのアクセスを uaccess の窓に限る (SMEP/SMAP/UMIP)
+    let stage = bootstage::begin("uaccess");
+    let protection = uaccess::init();
+    stage.ok(format_args!("User access protection (SMEP {}, SMAP {}, UMIP {})",
+        protection.smep, protection.smap, protection.umip));
+
+    // ユーザーの浮動小数点・SIMD レジスタをスレッドごとに保存できるようにする
+    let stage = bootstage::begin("fpu");
+    let fpu = fpu::init();
+    stage.ok(format_args!("FPU enabled (xsave {}, AVX {}, {} byte state)", fpu.xsave, fpu.avx, fpu.save_size));
+
+    // コマンドライン解析 (loglevel= などはここで反映)
+    let stage = bootstage::begin("cmdline");
+    cmdline::init();
+    config::init();
+    fault::init();
+    watchdog::init();
+
+    // gdb オプションがあれば COM2 でホストの gdb を待つ
+    gdbstub::init();
+    stage.done();
+
+    // 乱数の種 (RDSEED/RDRAND と TSC)
+    let stage = bootstage::begin("rand");
+    rand::init();
+    stage.done();
+
+    // ACPIテーブル解析
+    let stage = bootstage::begin("acpi");
+    match acpi::init() {
+        Ok(_) => stage.done(),
+        Err(e) => stage.skip(format_args!("ACPI unavailable ({})", e)),
+    }
+
+    // APIC初期化 (MMIOのマップが必要なのでメモリ管理の後)
+    let stage = bootstage::begin("apic");
+    match apic::init() {
+        Ok(mode) => stage.ok(format_args!("APIC initialized ({:?})", mode)),
+        Err(e) => stage.skip(format_args!("APIC unavailable ({}), using legacy PIC", e)),
+    }
 
     // プロセス管理初期化
+    let stage = bootstage::begin("process");
     process::init();
-    println!("[OK] Process manager initialized");
+    // ゼロ埋め済みページのプールを補充するスレッド
+    memory::start_page_zeroing();
+    stage.ok("Process manager initialized");
 
     // ファイルシステム初期化
+    let stage = bootstage::begin("filesystem");
     filesystem::init();
-    println!("[OK] Filesystem initialized");
+    stage.ok("Filesystem initialized");
+
+    // ブートモジュールの initramfs を展開
+    let stage = bootstage::begin("initramfs");
+    match initramfs::init() {
+        Ok(count) => stage.ok(format_args!("initramfs unpacked ({} archive(s))", count)),
+        Err(e) => stage.skip(format_args!("No initramfs ({})", e)),
+    }
+    // バックトレースなどでアドレスを関数名にする
+    let stage = bootstage::begin("symbols");
+    match symbols::init() {
+        Ok(count) => stage.ok(format_args!("Kernel symbols loaded ({} functions)", count)),
+        Err(e) => stage.skip(format_args!("Kernel symbols unavailable ({})", e)),
+    }
 
     // ドライバ初期化
+    let stage = bootstage::begin("drivers");
     drivers::init();
-    println!("[OK] Drivers initialized");
+    // ページキャッシュの書き戻しスレッド (ブロックデバイスの後)
+    drivers::page_cache::start_flusher();
+    stage.ok("Drivers initialized");
+
+    // 前回 persist した ramfs を復元する (ブロックデバイスの後)
+    let stage = bootstage::begin("snapshot");
+    match snapshot::init() {
+        Ok(summary) => stage.ok(format_args!("ramfs snapshot restored ({} entries)", summary.restored)),
+        Err(e) => stage.skip(format_args!("No ramfs snapshot ({})", e)),
+    }
+
+    // パニック時のクラッシュダンプの書き込み先 (ブロックデバイスの後)
+    let stage = bootstage::begin("crashdump");
+    match crashdump::init() {
+        Ok(name) => stage.ok(format_args!("Crash dumps go to serial and {}", name)),
+        Err(e) => stage.skip(format_args!("Crash dumps go to serial only ({})", e)),
+    }
+
+    // cmdline の profile でサンプリングプロファイラを始める
+    profiler::init();
+
+    // ネットワーク初期化 (NIC ドライバの後)
+    let stage = bootstage::begin("net");
+    match net::init() {
+        Ok(()) => stage.ok("Network initialized"),
+        Err(e) => stage.skip(format_args!("Network unavailable ({})", e)),
+    }
+
+    // アプリケーションプロセッサ起動 (タイマー割り込みで待ち時間を計るのでドライバの後)
+    let stage = bootstage::begin("smp");
+    match smp::init() {
+        Ok(count) => stage.ok(format_args!("SMP initialized ({} CPU(s))", count)),
+        Err(e) => stage.skip(format_args!("SMP unavailable ({}), running on BSP only", e)),
+    }
+
+    // 壁時計初期化
+    let stage = bootstage::begin("time");
+    time::init();
+    stage.ok("Wall clock initialized");
+
+    // ユーザーから時刻を読めるデータページ (壁時計の後)
+    let stage = bootstage::begin("vdso");
+    match vdso::init() {
+        Ok(()) => stage.ok("vDSO data page mapped"),
+        Err(e) => stage.skip(format_args!("vDSO unavailable ({})", e)),
+    }
 
     // システムコール初期化
+    let stage = bootstage::begin("syscall");
     syscall::init();
-    println!("[OK] Syscall handler initialized");
+    stage.ok("Syscall handler initialized");
 
     println!("\nKernel initialization complete!");
+    bootstage::print_summary();
     println!("Starting init process...\n");
 
     // デモ実行
     demo::run_complete_demo();
+    demo::compare_schedulers();
 
     // initプロセス起動
     process::spawn_init_process();
@@ -95,25 +218,20 @@ fn kernel_main() -> ! {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+
     println!("KERNEL PANIC: {}", info);
+    match process::try_current_pid() {
+        Some(pid) => println!("Current PID: {}", pid),
+        None => println!("Current PID: <none>"),
+    }
+    backtrace::print_registers();
+    backtrace::print_backtrace();
+    // CI などで集められるよう、シリアルポート (と crashdump= のデバイス) に機械で読める形でも書く
+    crashdump::write(&info.message(), info.location());
+
     loop {
         x86_64::instructions::hlt();
     }
 }
 
-#[alloc_error_handler]
-fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
-    panic!("allocation error: {:?}", layout)
-}
-
-// 簡易printlnマクロ
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::drivers::vga::_print(format_args!($($arg)*)));
-}
-
-#[macro_export]
-macro_rules! println {
-    () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
-}
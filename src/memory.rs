@@ -1,13 +1,20 @@
 use x86_64::{
     structures::paging::{
         PageTable, OffsetPageTable, Page, PhysFrame, Mapper, Size4KiB,
-        FrameAllocator, PageTableFlags as Flags,
+        FrameAllocator, PageTableFlags as Flags, Translate,
+        mapper::TranslateResult,
     },
     VirtAddr, PhysAddr,
 };
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-use linked_list_allocator::LockedHeap;
+use crate::allocator::{AllocStats, HeapReport, KernelAllocator};
 use spin::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use crate::smp::MAX_CPUS;
+use crate::sync::IrqMutex;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
 //use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
 
 /// 何もフレームを返さない空のアロケータ
@@ -19,16 +26,171 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+/// 払い出したフレーム数を数えるラッパー
+pub struct CountingFrameAllocator<A> {
+    inner: A,
+}
+
+unsafe impl<A: FrameAllocator<Size4KiB>> FrameAllocator<Size4KiB> for CountingFrameAllocator<A> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if crate::fault::should_fail(crate::fault::FaultPoint::Frame) {
+            return None;
+        }
+        let frame = self.inner.allocate_frame();
+        if frame.is_some() {
+            ALLOCATED_FRAMES.fetch_add(1, Ordering::Relaxed);
+        }
+        frame
+    }
+}
+
+// 物理フレームの統計 (フレーム数)
+static TOTAL_FRAMES: AtomicU64 = AtomicU64::new(0);
+static USABLE_FRAMES: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_FRAMES: AtomicU64 = AtomicU64::new(0);
+// EFER.NXE を立てた (PTE の NO_EXECUTE ビットが使える)
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+// カーネルのページテーブル (L4) の物理アドレス
+static KERNEL_ADDRESS_SPACE: AtomicU64 = AtomicU64::new(0);
+
+/// メモリ使用量のスナップショット
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryStats {
+    /// ブートローダーが報告した物理メモリ全体
+    pub total_frames: u64,
+    /// そのうち OS が使ってよい RAM
+    pub usable_frames: u64,
+    /// フレームアロケータが払い出して、まだマップされているもの
+    pub allocated_frames: u64,
+    pub heap_size: usize,
+    pub heap_used: usize,
+    pub heap_free: usize,
+}
+
+pub const FRAME_SIZE: u64 = 4096;
+
+/// ゼロ埋め済みフレームのプールに常に用意しておく数
+const ZERO_POOL_TARGET: usize = 64;
+/// プールを補充するカーネルスレッドの間隔
+const ZERO_POOL_INTERVAL_MS: usize = 100;
+
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
+/// ブートローダが物理メモリ全体をマップしている仮想アドレス
+const PHYS_OFFSET: u64 = 0xffff_8000_0000_0000;
+
+/// コピーオンライトページを示すPTEのソフトウェア定義ビット
+pub const COW_FLAG: Flags = Flags::BIT_9;
+/// 共有メモリのページを示すPTEのソフトウェア定義ビット (フレームは共有メモリオブジェクトが持つ)
+pub const SHARED_FLAG: Flags = Flags::BIT_10;
+
+/// mmap で予約する仮想アドレス領域の開始位置
+pub const MMAP_BASE: u64 = 0x0000_5000_0000_0000;
+
+/// ELF 実行ファイルを読み込める領域の先頭 (ユーザーヒープの手前まで)
+pub const USER_IMAGE_BASE: u64 = 0x0000_1000_0000_0000;
+
+/// 時刻などを読み出し専用で公開するページ (vdso のデータページ)
+/// fork したプロセスもこの L4 エントリはカーネルのページテーブルと共有するので、すべてのプロセスから同じアドレスで見える
+pub const VVAR_ADDR: u64 = 0x0000_0fff_ffff_f000;
+
+/// ユーザーヒープ (brk 領域) の既定の開始位置と最大サイズ
+pub const USER_HEAP_BASE: u64 = 0x0000_2000_0000_0000;
+pub const USER_HEAP_MAX: u64 = 0x0000_1000_0000_0000;
+
+/// プロセスの仮想メモリ領域 (VMA)
+/// 予約だけ行い、実際のフレームは初回アクセス時にページフォルトで割り当てる
+#[derive(Debug, Clone)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: Flags,
+    /// ファイルマッピングなら読み込み元 (無名マッピングは None)
+    pub file: Option<VmaFile>,
+}
+
+/// ファイルマッピングの読み込み元
+/// ファイルへの参照を持つので、ディスクリプタを閉じてもマッピングは有効なまま
+#[derive(Clone)]
+pub struct VmaFile {
+    pub file: crate::fd::FileRef,
+    /// vma.start に対応するファイル内のオフセット
+    pub offset: u64,
+    /// MAP_SHARED (共有メモリオブジェクトのフレームをそのままマップする)
+    pub shared: bool,
+}
+
+impl core::fmt::Debug for VmaFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "VmaFile {{ offset: {:#x}, shared: {} }}", self.offset, self.shared)
+    }
+}
+
+impl Vma {
+    pub fn anonymous(start: VirtAddr, end: VirtAddr, flags: Flags) -> Self {
+        Self { start, end, flags, file: None }
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    /// [start, end) の部分だけを持つVMA (ファイルオフセットもずらす)
+    pub fn slice(&self, start: VirtAddr, end: VirtAddr) -> Self {
+        let file = self.file.as_ref().map(|backing| VmaFile {
+            file: backing.file.clone(),
+            offset: backing.offset + (start - self.start),
+            shared: backing.shared,
+        });
+        Self { start, end, flags: self.flags, file }
+    }
+
+    /// addr を含むページにフレームを割り当てる
+    /// ファイルマッピングならそのページに当たる内容を読み込み、ファイル末尾より先はゼロ埋めする
+    /// 共有マッピングなら共有メモリオブジェクトのフレームをそのままマップする
+    pub fn fault_in(&self, addr: VirtAddr) -> Result<(), &'static str> {
+        let backing = match &self.file {
+            Some(backing) => backing,
+            None => return map_demand_page(addr, self.flags),
+        };
+
+        let page_start = addr.align_down(FRAME_SIZE);
+        let offset = backing.offset + (page_start - self.start);
+        if backing.shared {
+            let frame = match &backing.file.object {
+                crate::fd::FileObject::Shm(handle) => handle.object().frame(offset as usize)
+                    .map_err(|_| "access beyond shared memory object")?,
+                _ => return Err("not a shared memory object"),
+            };
+            return map_shared_frame(page_start, frame, self.flags);
+        }
+
+        let mut data = alloc::vec![0u8; FRAME_SIZE as usize];
+        let read = backing.file.read_at(&mut data, offset as usize)
+            .map_err(|_| "failed to read mapped file")?;
+        map_page_with_data(page_start, self.flags, &data[..read])
+    }
+}
+
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelAllocator = KernelAllocator::new();
 
 static MEMORY_MANAGER: Mutex<Option<MemoryManager>> = Mutex::new(None);
+// ゼロ埋め済みのフレーム (MEMORY_MANAGER を持ったまま取ってよいが、逆順では取らない)
+static ZERO_POOL: Mutex<Vec<PhysFrame>> = Mutex::new(Vec::new());
 pub struct MemoryManager {
-    pub mapper: OffsetPageTable<'static>,
-    pub frame_allocator: EmptyFrameAllocator,
+    pub frame_allocator: CountingFrameAllocator<EmptyFrameAllocator>,
+    /// COW共有されているフレームの参照カウント (物理アドレス → 参照数)
+    pub cow_refs: BTreeMap<u64, usize>,
+}
+
+impl MemoryManager {
+    /// このCPUで有効なページテーブル (CR3) を操作するマッパー
+    /// プロセスごとにページテーブルを切り替えるので、使うたびに CR3 から作る
+    pub fn mapper(&self) -> OffsetPageTable<'static> {
+        unsafe { init_mapper(VirtAddr::new(PHYS_OFFSET)) }
+    }
 }
 
 //pub struct MemoryManager {
@@ -68,20 +230,34 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 pub fn init() {
-    const PHYS_OFFSET: u64 = 0xffff_8000_0000_0000;
-    let phys_mem_offset = VirtAddr::new(PHYS_OFFSET);
-
-    let mapper = unsafe { init_mapper(phys_mem_offset) };
+    // 起動時のページテーブルをカーネルのものとし、fork したプロセスもカーネルの部分はこれを共有する
+    let (kernel_l4, _) = Cr3::read();
+    KERNEL_ADDRESS_SPACE.store(kernel_l4.start_address().as_u64(), Ordering::SeqCst);
+    // カーネルからの書き込みでも読み取り専用のページ (COW) でフォルトさせる
+    unsafe { Cr0::update(|cr0| cr0.insert(Cr0Flags::WRITE_PROTECT)) };
 
     // フレームアロケータは仮のものにする
-    let frame_allocator = EmptyFrameAllocator;
+    let frame_allocator = CountingFrameAllocator { inner: EmptyFrameAllocator };
 
     let manager = MemoryManager {
-        mapper,
         frame_allocator,
+        cow_refs: BTreeMap::new(),
     };
 
     *MEMORY_MANAGER.lock() = Some(manager);
+
+    // これ以降にマップするデータ領域 (ヒープなど) を実行不可にできるよう先に有効にする
+    enable_nx();
+
+    // メモリマップから物理メモリの総量を数える
+    let regions = crate::boot::memory_regions();
+    let total: u64 = regions.iter().map(|region| region.len / FRAME_SIZE).sum();
+    let usable: u64 = regions.iter()
+        .filter(|region| region.usable)
+        .map(|region| region.len / FRAME_SIZE)
+        .sum();
+    TOTAL_FRAMES.store(total, Ordering::Relaxed);
+    USABLE_FRAMES.store(usable, Ordering::Relaxed);
 }
 
 unsafe fn init_mapper(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
@@ -92,8 +268,6 @@ unsafe fn init_mapper(physical_memory_offset: VirtAddr) -> OffsetPageTable<'stat
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
     -> &'static mut PageTable
 {
-    use x86_64::registers::control::Cr3;
-
     let (level_4_table_frame, _) = Cr3::read();
     let phys = level_4_table_frame.start_address();
     let virt = physical_memory_offset + phys.as_u64();
@@ -118,22 +292,347 @@ pub fn init_heap() -> Result<(), &'static str> {
         let frame = manager.frame_allocator
             .allocate_frame()
             .ok_or("out of memory")?;
-        let flags = Flags::PRESENT | Flags::WRITABLE;
+        let flags = data_flags();
         unsafe {
-            manager.mapper.map_to(page, frame, flags, &mut manager.frame_allocator)
+            manager.mapper().map_to(page, frame, flags, &mut manager.frame_allocator)
                 .map_err(|_| "map_to failed")?
                 .flush();
         }
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
-        //ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.init(HEAP_START as *mut u8, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+/// ヒープ拡張用にページをマップする
+/// アロケータ内部から呼ばれるため、メモリマネージャがロック中なら失敗させる
+pub fn map_heap_pages(start: VirtAddr, count: usize) -> Result<(), &'static str> {
+    let mut manager = MEMORY_MANAGER.try_lock().ok_or("Memory manager busy")?;
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let start_page: Page<Size4KiB> = Page::containing_address(start);
+    for i in 0..count {
+        let page = start_page + i as u64;
+        let frame = manager.frame_allocator
+            .allocate_frame()
+            .ok_or("out of memory")?;
+        let flags = data_flags();
+        unsafe {
+            manager.mapper().map_to(page, frame, flags, &mut manager.frame_allocator)
+                .map_err(|_| "map_to failed")?
+                .flush();
+        }
+    }
+
+    Ok(())
+}
+
+/// 物理アドレスを物理メモリオフセット経由の仮想アドレスに変換する
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYS_OFFSET + phys.as_u64())
+}
+
+/// 仮想アドレスを現在のページテーブルで物理アドレスに変換する
+pub fn virt_to_phys(virt: VirtAddr) -> Option<PhysAddr> {
+    let manager = MEMORY_MANAGER.lock();
+    manager.as_ref()?.mapper().translate_addr(virt)
+}
+
+/// virt_to_phys と同じだが、メモリマネージャがロック中なら待たずに None を返す
+/// (例外ハンドラやデバッガから使う)
+pub fn try_virt_to_phys(virt: VirtAddr) -> Option<PhysAddr> {
+    let manager = MEMORY_MANAGER.try_lock()?;
+    manager.as_ref()?.mapper().translate_addr(virt)
+}
+
+/// デバイスの DMA 用に物理的に連続したページ領域をヒープから確保する
+/// 確保した領域は解放しない (ドライバが生きている間ずっと使う前提)
+pub fn alloc_dma(pages: usize) -> Result<(VirtAddr, PhysAddr), &'static str> {
+    use core::alloc::Layout;
+
+    let size = pages * 4096;
+    let layout = Layout::from_size_align(size, 4096).map_err(|_| "Invalid DMA size")?;
+    let ptr = unsafe { alloc::alloc::alloc_zeroed(layout) };
+    if ptr.is_null() {
+        return Err("Out of memory");
+    }
+
+    let virt = VirtAddr::from_ptr(ptr);
+    let phys = virt_to_phys(virt).ok_or("DMA buffer not mapped")?;
+    for i in 1..pages as u64 {
+        if virt_to_phys(virt + i * 4096) != Some(phys + i * 4096) {
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+            return Err("DMA buffer not physically contiguous");
+        }
+    }
+    Ok((virt, phys))
+}
+
+/// 物理ページを同じ仮想アドレスにマップする (AP 起動用トランポリンなど)
+pub fn identity_map_page(phys: PhysAddr, flags: Flags) -> Result<(), &'static str> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let virt = VirtAddr::new(phys.as_u64());
+    match manager.mapper().translate_addr(virt) {
+        Some(mapped) if mapped == phys => return Ok(()),
+        Some(_) => return Err("Address already mapped elsewhere"),
+        None => {}
     }
 
+    let frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    unsafe {
+        manager.mapper().identity_map(frame, flags | Flags::PRESENT, &mut manager.frame_allocator)
+            .map_err(|_| "identity_map failed")?
+            .flush();
+    }
     Ok(())
 }
 
+/// MMIO 領域を物理メモリオフセット上にキャッシュ無効でマップする
+/// 既にマップ済みならそのアドレスを返す
+pub fn map_mmio(phys: PhysAddr, size: usize) -> Result<VirtAddr, &'static str> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let virt = manager.mapper().phys_offset() + phys.as_u64();
+    let start_page: Page<Size4KiB> = Page::containing_address(virt);
+    let end_page: Page<Size4KiB> = Page::containing_address(virt + (size as u64 - 1));
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        if manager.mapper().translate_addr(page.start_address()).is_some() {
+            continue;
+        }
+        let frame = PhysFrame::<Size4KiB>::containing_address(
+            PhysAddr::new(page.start_address().as_u64() - manager.mapper().phys_offset().as_u64()),
+        );
+        let flags = data_flags() | Flags::NO_CACHE | Flags::WRITE_THROUGH;
+        unsafe {
+            manager.mapper().map_to(page, frame, flags, &mut manager.frame_allocator)
+                .map_err(|_| "map_to failed")?
+                .flush();
+        }
+    }
+
+    Ok(virt)
+}
+
+extern "C" {
+    // kernel.ld で定義するセクション境界 (ページ境界に揃えてある)
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __bss_end: u8;
+}
+
+/// カーネルイメージの区間
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelSection {
+    /// .text (読み取り専用 + 実行可)
+    Text,
+    /// .rodata (読み取り専用 + 実行不可)
+    Rodata,
+    /// .data と .bss (書き込み可 + 実行不可)
+    Data,
+}
+
+impl KernelSection {
+    /// 今のフラグから区間に合った保護のフラグを作る
+    fn protect(self, flags: Flags) -> Flags {
+        let base = flags - Flags::WRITABLE - Flags::NO_EXECUTE;
+        match self {
+            KernelSection::Text => base,
+            KernelSection::Rodata => base | no_execute(),
+            KernelSection::Data => base | Flags::WRITABLE | no_execute(),
+        }
+    }
+}
+
+/// 各区間の (種類, 開始アドレス, 終了アドレス)
+fn kernel_sections() -> [(KernelSection, u64, u64); 3] {
+    let addr = |symbol: &u8| symbol as *const u8 as u64;
+    unsafe {
+        [
+            (KernelSection::Text, addr(&__text_start), addr(&__text_end)),
+            (KernelSection::Rodata, addr(&__rodata_start), addr(&__rodata_end)),
+            (KernelSection::Data, addr(&__data_start) & !(FRAME_SIZE - 1), addr(&__bss_end)),
+        ]
+    }
+}
+
+/// 区間の (開始アドレス, 終了アドレス)
+pub fn section_range(section: KernelSection) -> (u64, u64) {
+    kernel_sections().into_iter()
+        .find(|&(kind, _, _)| kind == section)
+        .map(|(_, start, end)| (start, end))
+        .unwrap()
+}
+
+/// アドレスがカーネルイメージのどの区間にあるか
+pub fn kernel_section(addr: VirtAddr) -> Option<KernelSection> {
+    kernel_sections().into_iter()
+        .find(|&(_, start, end)| (start..end).contains(&addr.as_u64()))
+        .map(|(section, _, _)| section)
+}
+
+/// CPU が対応していれば EFER.NXE を立てる
+/// AP はトランポリンで立てるので、ここは BSP だけが呼ぶ
+pub fn enable_nx() -> bool {
+    use x86_64::registers::model_specific::{Efer, EferFlags};
+
+    if !crate::cpu::has(crate::cpu::Feature::Nx) {
+        return false;
+    }
+    unsafe {
+        Efer::update(|flags| *flags |= EferFlags::NO_EXECUTE_ENABLE);
+    }
+    NX_ENABLED.store(true, Ordering::Relaxed);
+    true
+}
+
+pub fn nx_enabled() -> bool {
+    NX_ENABLED.load(Ordering::Relaxed)
+}
+
+/// NX が有効なら NO_EXECUTE (無効なときに立てると予約ビット違反になる)
+pub fn no_execute() -> Flags {
+    if nx_enabled() { Flags::NO_EXECUTE } else { Flags::empty() }
+}
+
+/// カーネルのデータ領域 (ヒープ、スタック、MMIO) のフラグ
+pub fn data_flags() -> Flags {
+    Flags::PRESENT | Flags::WRITABLE | no_execute()
+}
+
+/// addr を含む 1 GiB / 2 MiB ページを 4 KiB ページに分割する
+/// ページごとに保護を変えるために使う (分割した範囲の対応とフラグは変えない)
+fn split_huge_page(manager: &mut MemoryManager, addr: VirtAddr) -> Result<(), &'static str> {
+    let offset = manager.mapper().phys_offset();
+    let mut table: *mut PageTable = manager.mapper().level_4_table();
+    for (level, index) in [addr.p4_index(), addr.p3_index(), addr.p2_index()].into_iter().enumerate() {
+        let entry = unsafe { &mut (&mut *table)[index] };
+        if entry.is_unused() {
+            return Ok(());
+        }
+        let flags = entry.flags();
+        if level > 0 && flags.contains(Flags::HUGE_PAGE) {
+            // P3 の大きいページは 2 MiB ページ 512 個、P2 のものは 4 KiB ページ 512 個にする
+            let (child_size, child_flags) = if level == 1 {
+                (0x20_0000, flags)
+            } else {
+                (FRAME_SIZE, flags - Flags::HUGE_PAGE)
+            };
+            let frame = zeroed_frame(manager).ok_or("out of memory")?;
+            let child = unsafe { &mut *(offset + frame.start_address().as_u64()).as_mut_ptr::<PageTable>() };
+            for (i, child_entry) in child.iter_mut().enumerate() {
+                child_entry.set_addr(entry.addr() + i as u64 * child_size, child_flags);
+            }
+            // 保護は子のエントリで決めるので、上位のエントリでは絞らない
+            let table_flags = flags - Flags::HUGE_PAGE - Flags::NO_EXECUTE - Flags::GLOBAL | Flags::WRITABLE;
+            entry.set_addr(frame.start_address(), table_flags);
+            x86_64::instructions::tlb::flush_all();
+        }
+        table = (offset + entry.addr().as_u64()).as_mut_ptr();
+    }
+    Ok(())
+}
+
+/// 起動後にカーネルイメージを区間ごとに保護し直す (W^X)
+/// ブートローダは全体を書き込み可・実行可でマップしているので、
+/// .text は読み取り専用 + 実行可、.rodata は読み取り専用 + 実行不可、.data/.bss は書き込み可 + 実行不可にする
+/// 変更したページ数を返す
+pub fn protect_kernel() -> Result<usize, &'static str> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let mut updated = 0;
+    for (section, start, end) in kernel_sections() {
+        if start >= end {
+            continue;
+        }
+        let first: Page<Size4KiB> = Page::containing_address(VirtAddr::new(start));
+        let last: Page<Size4KiB> = Page::containing_address(VirtAddr::new(end - 1));
+        for page in Page::range_inclusive(first, last) {
+            split_huge_page(manager, page.start_address())?;
+            let flags = match manager.mapper().translate(page.start_address()) {
+                TranslateResult::Mapped { flags, .. } => flags,
+                _ => continue,
+            };
+            let new_flags = section.protect(flags);
+            if new_flags == flags {
+                continue;
+            }
+            unsafe {
+                manager.mapper().update_flags(page, new_flags)
+                    .map_err(|_| "update_flags failed")?
+                    .flush();
+            }
+            updated += 1;
+        }
+    }
+    Ok(updated)
+}
+
+/// 保護違反のページフォルトが W^X 違反 (カーネルイメージへの書き込みか、実行不可ページの実行) なら説明を返す
+pub fn describe_wx_violation(addr: VirtAddr, write: bool, instruction_fetch: bool) -> Option<&'static str> {
+    if instruction_fetch {
+        return Some(match kernel_section(addr) {
+            Some(KernelSection::Rodata) => "W^X violation: instruction fetch from kernel .rodata",
+            Some(KernelSection::Data) => "W^X violation: instruction fetch from kernel .data/.bss",
+            _ => "W^X violation: instruction fetch from a non-executable page",
+        });
+    }
+    match (write, kernel_section(addr)) {
+        (true, Some(KernelSection::Text)) => Some("W^X violation: write to read-only kernel .text"),
+        (true, Some(KernelSection::Rodata)) => Some("W^X violation: write to read-only kernel .rodata"),
+        _ => None,
+    }
+}
+
+pub fn heap_stats() -> AllocStats {
+    ALLOCATOR.stats()
+}
+
+/// 生存中のヒープ割り当てと、その呼び出し元の集計
+pub fn heap_report() -> HeapReport {
+    ALLOCATOR.report()
+}
+
+/// 割り当てごとに呼び出し元を記録する (コマンドラインの heap_track でも有効になる)
+pub fn set_heap_tracking(enabled: bool) {
+    ALLOCATOR.set_tracking(enabled);
+}
+
+pub fn stats() -> MemoryStats {
+    let heap = heap_stats();
+    MemoryStats {
+        total_frames: TOTAL_FRAMES.load(Ordering::Relaxed),
+        usable_frames: USABLE_FRAMES.load(Ordering::Relaxed),
+        allocated_frames: ALLOCATED_FRAMES.load(Ordering::Relaxed),
+        heap_size: heap.heap_size,
+        heap_used: heap.heap_used,
+        heap_free: heap.heap_size - heap.heap_used,
+    }
+}
+
+/// /proc/meminfo 形式で表示する
+pub fn print_meminfo() {
+    let stats = stats();
+    let kib = |frames: u64| frames * FRAME_SIZE / 1024;
+    crate::println!("MemTotal:     {:>10} kB", kib(stats.usable_frames));
+    crate::println!("MemFree:      {:>10} kB", kib(stats.usable_frames.saturating_sub(stats.allocated_frames)));
+    crate::println!("MemMapped:    {:>10} kB", kib(stats.allocated_frames));
+    crate::println!("PhysTotal:    {:>10} kB", kib(stats.total_frames));
+    crate::println!("HeapTotal:    {:>10} kB", stats.heap_size / 1024);
+    crate::println!("HeapUsed:     {:>10} kB", stats.heap_used / 1024);
+    crate::println!("HeapFree:     {:>10} kB", stats.heap_free / 1024);
+}
+
 pub fn allocate_pages(count: usize) -> Option<VirtAddr> {
     let mut manager = MEMORY_MANAGER.lock();
     let manager = manager.as_mut()?;
@@ -147,7 +646,7 @@ pub fn allocate_pages(count: usize) -> Option<VirtAddr> {
         let flags = Flags::PRESENT | Flags::WRITABLE | Flags::USER_ACCESSIBLE;
         
         unsafe {
-            manager.mapper
+            manager.mapper()
                 .map_to(page, frame, flags, &mut manager.frame_allocator)
                 .ok()?
                 .flush();
@@ -157,6 +656,110 @@ pub fn allocate_pages(count: usize) -> Option<VirtAddr> {
     Some(start_page.start_address())
 }
 
+/// allocate_pages と同じだが、ゼロ埋めしたフレームを使う (以前の内容が見えない)
+pub fn allocate_zeroed_pages(count: usize) -> Option<VirtAddr> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut()?;
+
+    let start_page = find_free_pages(count)?;
+
+    for i in 0..count {
+        let page = start_page + i as u64;
+        let frame = zeroed_frame(manager)?;
+        let flags = Flags::PRESENT | Flags::WRITABLE | Flags::USER_ACCESSIBLE;
+
+        unsafe {
+            manager.mapper()
+                .map_to(page, frame, flags, &mut manager.frame_allocator)
+                .ok()?
+                .flush();
+        }
+    }
+
+    Some(start_page.start_address())
+}
+
+/// ゼロ埋め済みのフレームを1つ取る (プールが空ならその場でゼロ埋めする)
+fn zeroed_frame(manager: &mut MemoryManager) -> Option<PhysFrame> {
+    if let Some(frame) = ZERO_POOL.lock().pop() {
+        if crate::allocator::POISONING {
+            check_zeroed(frame);
+        }
+        return Some(frame);
+    }
+    let frame = manager.frame_allocator.allocate_frame()?;
+    zero_frame(frame);
+    Some(frame)
+}
+
+fn zero_frame(frame: PhysFrame) {
+    let ptr = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+    unsafe { core::ptr::write_bytes(ptr, 0, FRAME_SIZE as usize) };
+}
+
+/// プールにある間に書き換えられていないか確かめる (解放後にどこかから書かれていればパニック)
+fn check_zeroed(frame: PhysFrame) {
+    let ptr = phys_to_virt(frame.start_address()).as_ptr::<u64>();
+    let words = unsafe { core::slice::from_raw_parts(ptr, FRAME_SIZE as usize / 8) };
+    if let Some(index) = words.iter().position(|&word| word != 0) {
+        panic!("page: frame {:#x} modified at offset {:#x} after it was freed",
+            frame.start_address().as_u64(), index * 8);
+    }
+}
+
+/// マップを外すフレームを毒で埋める (古いマッピングからの読み書きを見つけやすくする)
+fn poison_frame(frame: PhysFrame) {
+    let ptr = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+    unsafe { core::ptr::write_bytes(ptr, crate::allocator::POISON_FREE, FRAME_SIZE as usize) };
+}
+
+/// ゼロ埋め済みのフレームを1つ確保する (マップは呼び出し側で行う)
+pub fn allocate_zeroed_frame() -> Option<PhysFrame> {
+    let mut manager = MEMORY_MANAGER.lock();
+    zeroed_frame(manager.as_mut()?)
+}
+
+/// 使い終わったフレームをゼロ埋めしてプールに戻す (どこにもマップされていないこと)
+pub fn free_frame(frame: PhysFrame) {
+    zero_frame(frame);
+    ZERO_POOL.lock().push(frame);
+}
+
+/// プールが目標数になるまでフレームを確保してゼロ埋めする (補充した数を返す)
+/// ゼロ埋めはロックを外して行うので、その間も他のCPUはページを割り当てられる
+pub fn refill_zero_pool() -> usize {
+    let mut added = 0;
+    while ZERO_POOL.lock().len() < ZERO_POOL_TARGET {
+        let frame = {
+            let mut manager = MEMORY_MANAGER.lock();
+            match manager.as_mut().and_then(|m| m.frame_allocator.allocate_frame()) {
+                Some(frame) => frame,
+                None => break,
+            }
+        };
+        zero_frame(frame);
+        ZERO_POOL.lock().push(frame);
+        added += 1;
+    }
+    added
+}
+
+/// プール内のゼロ埋め済みフレームの数
+pub fn zero_pool_size() -> usize {
+    ZERO_POOL.lock().len()
+}
+
+/// 空き時間にプールを補充するカーネルスレッドを起動する (プロセス管理の初期化後に呼ぶ)
+pub fn start_page_zeroing() {
+    // スレッドの中でプールを伸ばしてヒープを拡張しないよう先に確保しておく
+    ZERO_POOL.lock().reserve(ZERO_POOL_TARGET);
+    let handle = crate::kthread::spawn("kzerod", || loop {
+        refill_zero_pool();
+        crate::drivers::timer::sleep_ms(ZERO_POOL_INTERVAL_MS);
+    });
+    crate::process::set_priority(handle.pid(), crate::process::IDLE_PRIORITY);
+}
+
 fn find_free_pages(count: usize) -> Option<Page> {
     // 簡易実装: ユーザー空間の先頭から検索
     // 実際の実装ではビットマップなどで管理
@@ -165,7 +768,130 @@ fn find_free_pages(count: usize) -> Option<Page> {
     Some(Page::containing_address(start_addr))
 }
 
-pub fn deallocate_pages(addr: VirtAddr, count: usize) {
+/// 他の CPU に TLB の無効化を頼む割り込みベクタ
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xF1;
+/// CPU ごとに溜めておける無効化要求の数 (溢れたら TLB 全体を捨てる)
+const SHOOTDOWN_QUEUE_SIZE: usize = 16;
+/// これより多いページは invlpg を繰り返さずに TLB 全体を捨てる
+const SHOOTDOWN_FULL_FLUSH_PAGES: usize = 32;
+/// 相手の応答を待つ回数の上限 (割り込みを止めたまま固まった CPU を待ち続けない)
+const SHOOTDOWN_ACK_SPINS: usize = 10_000_000;
+
+/// ある CPU がまだ処理していない TLB の無効化要求
+struct ShootdownQueue {
+    ranges: [(u64, usize); SHOOTDOWN_QUEUE_SIZE],
+    len: usize,
+    /// 溢れたので TLB 全体を捨てる
+    flush_all: bool,
+}
+
+impl ShootdownQueue {
+    const fn new() -> Self {
+        Self { ranges: [(0, 0); SHOOTDOWN_QUEUE_SIZE], len: 0, flush_all: false }
+    }
+
+    fn push(&mut self, start: VirtAddr, pages: usize) {
+        if self.len == SHOOTDOWN_QUEUE_SIZE || pages > SHOOTDOWN_FULL_FLUSH_PAGES {
+            self.flush_all = true;
+        } else {
+            self.ranges[self.len] = (start.as_u64(), pages);
+            self.len += 1;
+        }
+    }
+}
+
+static SHOOTDOWN_QUEUES: [IrqMutex<ShootdownQueue>; MAX_CPUS] =
+    [const { IrqMutex::new(ShootdownQueue::new()) }; MAX_CPUS];
+// 同時に送れるシュートダウンは1つだけ (応答の数え方を単純にするため)
+static SHOOTDOWN_LOCK: Mutex<()> = Mutex::new(());
+// まだ応答していない CPU の数
+static SHOOTDOWN_PENDING: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWNS_SENT: AtomicU64 = AtomicU64::new(0);
+
+fn flush_range(start: VirtAddr, pages: usize) {
+    if pages > SHOOTDOWN_FULL_FLUSH_PAGES {
+        x86_64::instructions::tlb::flush_all();
+        return;
+    }
+    for i in 0..pages as u64 {
+        x86_64::instructions::tlb::flush(start + i * 4096);
+    }
+}
+
+/// この CPU 宛ての無効化要求を処理して応答する
+/// TLB_SHOOTDOWN_VECTOR の割り込みハンドラと、シュートダウンの順番待ちの間に呼ばれる
+pub fn handle_tlb_shootdown() {
+    let mut queue = SHOOTDOWN_QUEUES[crate::smp::cpu_id()].lock();
+    let handled = queue.len + queue.flush_all as usize;
+    if queue.flush_all {
+        x86_64::instructions::tlb::flush_all();
+    } else {
+        for &(start, pages) in &queue.ranges[..queue.len] {
+            flush_range(VirtAddr::new(start), pages);
+        }
+    }
+    queue.len = 0;
+    queue.flush_all = false;
+    drop(queue);
+    if handled != 0 {
+        SHOOTDOWN_PENDING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 他の CPU の TLB から範囲内のページを消し、応答した CPU の数を返す (自分の TLB は呼び出し側が消す)
+/// 相手が割り込みを止めて MEMORY_MANAGER を待っていると応答できないので、ロックを持ったまま呼ばないこと
+pub fn tlb_shootdown(start: VirtAddr, pages: usize) -> usize {
+    let me = crate::smp::cpu_id();
+    let targets: Vec<&crate::smp::PerCpu> = (0..crate::smp::cpu_count())
+        .filter(|&id| id != me)
+        .filter_map(crate::smp::cpu)
+        .filter(|cpu| !cpu.is_parked())
+        .collect();
+    if targets.is_empty() || pages == 0 {
+        return 0;
+    }
+
+    // 順番待ちの間も自分宛ての要求には応える (相手が自分の応答を待っているかもしれない)
+    let _guard = loop {
+        if let Some(guard) = SHOOTDOWN_LOCK.try_lock() {
+            break guard;
+        }
+        handle_tlb_shootdown();
+        core::hint::spin_loop();
+    };
+
+    SHOOTDOWN_PENDING.store(targets.len(), Ordering::SeqCst);
+    for cpu in &targets {
+        SHOOTDOWN_QUEUES[cpu.id()].lock().push(start, pages);
+        crate::apic::send_ipi(cpu.apic_id(), TLB_SHOOTDOWN_VECTOR);
+    }
+    SHOOTDOWNS_SENT.fetch_add(1, Ordering::Relaxed);
+
+    let mut spins = 0;
+    while SHOOTDOWN_PENDING.load(Ordering::SeqCst) != 0 {
+        spins += 1;
+        if spins == SHOOTDOWN_ACK_SPINS {
+            let missing = SHOOTDOWN_PENDING.swap(0, Ordering::SeqCst);
+            crate::warn!("TLB shootdown: {} CPU(s) did not respond", missing);
+            return targets.len() - missing;
+        }
+        core::hint::spin_loop();
+    }
+    targets.len()
+}
+
+/// これまでに送ったシュートダウンの回数
+pub fn tlb_shootdowns() -> u64 {
+    SHOOTDOWNS_SENT.load(Ordering::Relaxed)
+}
+
+/// 範囲内のページのマップを外し、外したページ数を返す
+/// 共有メモリのページはマップを外すだけで、フレームは共有メモリオブジェクトが解放する
+/// COW でほかのアドレス空間からも参照されているフレームは参照カウントを減らすだけにする
+/// 他の CPU の TLB にも残らないようシュートダウンしてから戻る
+pub fn deallocate_pages(addr: VirtAddr, count: usize) -> usize {
+    let mut unmapped = 0;
+    let mut shared = 0;
     let mut manager = MEMORY_MANAGER.lock();
     if let Some(manager) = manager.as_mut() {
         use x86_64::structures::paging::Size4KiB;
@@ -176,9 +902,349 @@ pub fn deallocate_pages(addr: VirtAddr, count: usize) {
         
         for i in 0..count {
             let page = start_page + i as u64;
-            if let Ok((_, flush)) = manager.mapper.unmap(page) {
+            let is_shared = matches!(manager.mapper().translate(page.start_address()),
+                TranslateResult::Mapped { flags, .. } if flags.contains(SHARED_FLAG));
+            if let Ok((frame, flush)) = manager.mapper().unmap(page) {
                 flush.flush();
+                // 共有メモリと COW で他にも参照があるフレームはまだ使われている
+                let still_used = release_cow_ref(manager, frame);
+                if crate::allocator::POISONING && !is_shared && !still_used {
+                    poison_frame(frame);
+                }
+                unmapped += 1;
+                if is_shared || still_used {
+                    shared += 1;
+                }
+            }
+        }
+    }
+    drop(manager);
+    if unmapped != 0 {
+        tlb_shootdown(addr.align_down(4096u64), count);
+    }
+    ALLOCATED_FRAMES.fetch_sub((unmapped - shared) as u64, Ordering::Relaxed);
+    unmapped
+}
+
+/// COW で共有しているフレームの参照を1つ減らし、まだほかに参照があれば true を返す
+fn release_cow_ref(manager: &mut MemoryManager, frame: PhysFrame) -> bool {
+    let addr = frame.start_address().as_u64();
+    match manager.cow_refs.get_mut(&addr) {
+        Some(refs) if *refs > 1 => {
+            *refs -= 1;
+            if *refs == 1 {
+                // 残った1つはふつうのページと同じ (COW フラグは書き込み時に外れる)
+                manager.cow_refs.remove(&addr);
+            }
+            true
+        }
+        Some(_) => {
+            manager.cow_refs.remove(&addr);
+            false
+        }
+        None => false,
+    }
+}
+
+/// 指定範囲のページを読み取り専用 + COW としてマークする
+/// fork時に親子で同じフレームを共有するために使う
+/// マップされていないページ (まだフォルトしていない) と共有メモリのページは飛ばす
+/// もともと読み取り専用のページは書き込めるようにしてはいけないので、参照カウントだけ増やす
+pub fn mark_copy_on_write(addr: VirtAddr, count: usize) -> Result<(), &'static str> {
+    let mut guard = MEMORY_MANAGER.lock();
+    let manager = guard.as_mut().ok_or("Memory manager not initialized")?;
+
+    let start_page: Page<Size4KiB> = Page::containing_address(addr);
+    let mut marked = false;
+    for i in 0..count {
+        let page = start_page + i as u64;
+        let (frame, flags) = match manager.mapper().translate(page.start_address()) {
+            TranslateResult::Mapped { frame, flags, .. } => (frame.start_address(), flags),
+            _ => continue,
+        };
+        if flags.contains(SHARED_FLAG) {
+            continue;
+        }
+
+        if flags.contains(Flags::WRITABLE) {
+            let new_flags = (flags - Flags::WRITABLE) | COW_FLAG;
+            unsafe {
+                manager.mapper().update_flags(page, new_flags)
+                    .map_err(|_| "update_flags failed")?
+                    .flush();
+            }
+            marked = true;
+        }
+
+        // 既存の所有者 + 新しい共有者
+        let refs = manager.cow_refs.entry(frame.as_u64()).or_insert(1);
+        *refs += 1;
+    }
+    drop(guard);
+
+    // 同じアドレス空間で動いているほかのCPUにも書き込める TLB エントリを残さない
+    if marked {
+        tlb_shootdown(addr.align_down(4096u64), count);
+    }
+    Ok(())
+}
+
+/// カーネルのページテーブル (プロセスに属さないスレッドと、fork していないプロセスが使う)
+pub fn kernel_address_space() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_ADDRESS_SPACE.load(Ordering::SeqCst)))
+}
+
+/// このCPUで有効なページテーブル
+pub fn current_address_space() -> PhysFrame {
+    Cr3::read().0
+}
+
+/// このCPUのページテーブルを l4 に切り替える (同じなら TLB を捨てないよう何もしない)
+pub fn switch_address_space(l4: PhysFrame) {
+    let (current, flags) = Cr3::read();
+    if current != l4 {
+        unsafe { Cr3::write(l4, flags) };
+    }
+}
+
+fn page_table(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() }
+}
+
+/// ページテーブル (level は 3..=1) を下位のテーブルごと複製する
+/// 失敗したときは途中まで作ったテーブルを解放する
+fn copy_page_table(manager: &mut MemoryManager, frame: PhysFrame, level: u8) -> Result<PhysFrame, &'static str> {
+    let copy = zeroed_frame(manager).ok_or("out of memory")?;
+    let (src, dst) = (page_table(frame), page_table(copy));
+    for (i, entry) in src.iter().enumerate() {
+        match entry.frame() {
+            Ok(table) if level > 1 => {
+                match copy_page_table(manager, table, level - 1) {
+                    Ok(table) => dst[i].set_frame(table, entry.flags()),
+                    Err(e) => {
+                        free_page_table(copy, level);
+                        return Err(e);
+                    }
+                }
+            }
+            // ページ (とヒュージページ、frame() はエラーになる) は同じフレームを指す
+            _ => dst[i] = entry.clone(),
+        }
+    }
+    Ok(copy)
+}
+
+/// copy_page_table で作ったテーブルを下位のテーブルごと解放する (ページのフレームは解放しない)
+fn free_page_table(frame: PhysFrame, level: u8) {
+    if level > 1 {
+        for entry in page_table(frame).iter() {
+            if let Ok(table) = entry.frame() {
+                free_page_table(table, level - 1);
+            }
+        }
+    }
+    free_frame(frame);
+}
+
+/// 現在のアドレス空間を fork 用に複製して、子のページテーブルを返す
+/// ranges (ユーザーの VMA とスタック) にマップ済みのページは、親の側を COW にしてから
+/// テーブルごと複製するので、親子のどちらも読み取り専用 + COW で同じフレームを指す
+/// ranges を含まない L4 エントリはカーネルのページテーブルと共有する
+pub fn fork_address_space(ranges: &[(VirtAddr, VirtAddr)]) -> Result<PhysFrame, &'static str> {
+    for &(start, end) in ranges {
+        mark_copy_on_write(start, ((end - start) / FRAME_SIZE) as usize)?;
+    }
+
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+    let l4 = zeroed_frame(manager).ok_or("out of memory")?;
+    let (current, kernel, dst) = (page_table(current_address_space()), page_table(kernel_address_space()), page_table(l4));
+    for i in 0..512 {
+        let private = ranges.iter().any(|&(start, end)| {
+            start < end && (usize::from(start.p4_index())..=usize::from((end - 1u64).p4_index())).contains(&i)
+        });
+        match (private, current[i].frame()) {
+            (true, Ok(table)) => match copy_page_table(manager, table, 3) {
+                Ok(table) => dst[i].set_frame(table, current[i].flags()),
+                Err(e) => {
+                    free_address_space(l4);
+                    return Err(e);
+                }
+            },
+            // まだ何もマップしていない
+            (true, Err(_)) => {}
+            (false, _) => dst[i] = kernel[i].clone(),
+        }
+    }
+    Ok(l4)
+}
+
+/// fork_address_space で作ったページテーブルを解放する (どのCPUでも使っていないこと)
+/// マップしていたページは先に deallocate_pages で外しておく
+pub fn free_address_space(l4: PhysFrame) {
+    let kernel = page_table(kernel_address_space());
+    for (i, entry) in page_table(l4).iter().enumerate() {
+        // カーネルと違うテーブルを指しているエントリは複製したもの
+        if let Ok(table) = entry.frame() {
+            if entry.addr() != kernel[i].addr() {
+                free_page_table(table, 3);
             }
         }
     }
+    free_frame(l4);
+}
+
+/// 書き込み保護違反のページフォルトを COW として処理する
+/// 処理できた場合は true を返す
+pub fn handle_cow_fault(addr: VirtAddr) -> bool {
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    let mut manager = MEMORY_MANAGER.lock();
+    let resolved = match manager.as_mut() {
+        Some(manager) => resolve_cow_fault(manager, page),
+        None => return false,
+    };
+    drop(manager);
+    // ほかのCPUに残っている書き込み禁止のエントリも捨てさせる
+    if resolved {
+        tlb_shootdown(page.start_address(), 1);
+    }
+    resolved
+}
+
+/// page を書き込み可能にする (ほかに参照があればフレームをコピーして付け替える)
+fn resolve_cow_fault(manager: &mut MemoryManager, page: Page<Size4KiB>) -> bool {
+    let (old_frame, flags) = match manager.mapper().translate(page.start_address()) {
+        TranslateResult::Mapped { frame, flags, .. } => (frame.start_address(), flags),
+        _ => return false,
+    };
+
+    if !flags.contains(COW_FLAG) {
+        return false;
+    }
+
+    let new_flags = (flags - COW_FLAG) | Flags::WRITABLE;
+    let refs = manager.cow_refs.get(&old_frame.as_u64()).copied().unwrap_or(1);
+
+    if refs <= 1 {
+        // 最後の参照なのでコピー不要、そのまま書き込み可能にする
+        manager.cow_refs.remove(&old_frame.as_u64());
+        return unsafe {
+            manager.mapper().update_flags(page, new_flags)
+                .map(|flush| flush.flush())
+                .is_ok()
+        };
+    }
+
+    // 新しいフレームを割り当ててページ内容をコピー
+    let new_frame = match manager.frame_allocator.allocate_frame() {
+        Some(frame) => frame,
+        None => return false,
+    };
+
+    let phys_offset = manager.mapper().phys_offset();
+    unsafe {
+        let src = (phys_offset + old_frame.as_u64()).as_ptr::<u8>();
+        let dst = (phys_offset + new_frame.start_address().as_u64()).as_mut_ptr::<u8>();
+        core::ptr::copy_nonoverlapping(src, dst, 4096);
+    }
+
+    let remapped = unsafe {
+        match manager.mapper().unmap(page) {
+            Ok((_, flush)) => flush.flush(),
+            Err(_) => return false,
+        }
+        manager.mapper()
+            .map_to(page, new_frame, new_flags, &mut manager.frame_allocator)
+            .map(|flush| flush.flush())
+            .is_ok()
+    };
+
+    if remapped {
+        if let Some(refs) = manager.cow_refs.get_mut(&old_frame.as_u64()) {
+            *refs -= 1;
+        }
+    }
+
+    remapped
+}
+
+/// 未マップのページにゼロ埋めしたフレームを割り当てる (デマンドページング)
+pub fn map_demand_page(addr: VirtAddr, flags: Flags) -> Result<(), &'static str> {
+    map_page_with_data(addr, flags, &[])
+}
+
+/// 未マップのページにフレームを割り当て、先頭に data をコピーする (残りはゼロ埋め)
+/// 書き込み不可のページでも、マップ前に物理メモリ側から書き込むので問題ない
+pub fn map_page_with_data(addr: VirtAddr, flags: Flags, data: &[u8]) -> Result<(), &'static str> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    // 以前の内容が見えないようゼロ埋めしたフレームを使う
+    let frame = zeroed_frame(manager).ok_or("out of memory")?;
+
+    unsafe {
+        let ptr = (manager.mapper().phys_offset() + frame.start_address().as_u64()).as_mut_ptr::<u8>();
+        let len = core::cmp::min(data.len(), 4096);
+        core::ptr::copy_nonoverlapping(data.as_ptr(), ptr, len);
+        manager.mapper()
+            .map_to(page, frame, flags | Flags::PRESENT, &mut manager.frame_allocator)
+            .map_err(|_| "map_to failed")?
+            .flush();
+    }
+
+    Ok(())
+}
+
+/// 共有メモリオブジェクトのフレームを未マップのページにマップする
+/// 複数のプロセスが同じフレームをマップするので、COW やデマンドページングのフレームとは区別する
+pub fn map_shared_frame(addr: VirtAddr, frame: PhysFrame, flags: Flags) -> Result<(), &'static str> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    unsafe {
+        manager.mapper()
+            .map_to(page, frame, flags | Flags::PRESENT | SHARED_FLAG, &mut manager.frame_allocator)
+            .map_err(|_| "map_to failed")?
+            .flush();
+    }
+    Ok(())
+}
+
+/// ページを非PRESENTにしてガードページにする
+/// フレームはページテーブルエントリに残したままなので、元のフラグを返して unguard_page で戻す
+pub fn guard_page(addr: VirtAddr) -> Result<Flags, &'static str> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    let flags = match manager.mapper().translate(page.start_address()) {
+        TranslateResult::Mapped { flags, .. } => flags,
+        _ => return Err("Page not mapped"),
+    };
+    if flags.contains(Flags::HUGE_PAGE) {
+        return Err("Cannot guard a huge page");
+    }
+
+    unsafe {
+        manager.mapper().update_flags(page, flags - Flags::PRESENT)
+            .map_err(|_| "update_flags failed")?
+            .flush();
+    }
+    Ok(flags)
+}
+
+/// guard_page で外したページを元のフラグで戻す
+pub fn unguard_page(addr: VirtAddr, flags: Flags) -> Result<(), &'static str> {
+    let mut manager = MEMORY_MANAGER.lock();
+    let manager = manager.as_mut().ok_or("Memory manager not initialized")?;
+
+    let page: Page<Size4KiB> = Page::containing_address(addr);
+    unsafe {
+        manager.mapper().update_flags(page, flags)
+            .map_err(|_| "update_flags failed")?
+            .flush();
+    }
+    Ok(())
 }
@@ -0,0 +1,225 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use crate::errno::Errno;
+use crate::filesystem::{Stat, O_CREAT, O_EXCL, O_NONBLOCK, O_RDWR, O_WRONLY, S_IFREG};
+use crate::poll::{PollTable, POLLIN, POLLOUT};
+use crate::sync::WaitQueue;
+
+/// 属性を指定せずに作ったキューの深さとメッセージの大きさ (Linux と同じ)
+pub const DEFAULT_MAXMSG: usize = 10;
+pub const DEFAULT_MSGSIZE: usize = 8192;
+/// 1つのキューに許す上限
+pub const MQ_MAXMSG_MAX: usize = 64;
+pub const MQ_MSGSIZE_MAX: usize = 8192;
+/// 優先度は 0 から MQ_PRIO_MAX - 1 まで
+pub const MQ_PRIO_MAX: u32 = 32768;
+
+// 名前 → キュー (名前も参照の1つとして持つ)
+static QUEUES: Mutex<BTreeMap<String, Arc<MessageQueue>>> = Mutex::new(BTreeMap::new());
+
+/// mq_open / mq_getsetattr でやり取りする属性 (Linux の struct mq_attr と同じ並び)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MqAttr {
+    /// O_NONBLOCK だけが意味を持つ
+    pub mq_flags: i64,
+    pub mq_maxmsg: i64,
+    pub mq_msgsize: i64,
+    /// 現在キューにあるメッセージ数 (設定時は無視する)
+    pub mq_curmsgs: i64,
+    pub reserved: [i64; 4],
+}
+
+struct Message {
+    priority: u32,
+    data: Vec<u8>,
+}
+
+/// 名前付きメッセージキュー
+/// メッセージは優先度の高い順、同じ優先度なら送った順に取り出す
+pub struct MessageQueue {
+    messages: Mutex<VecDeque<Message>>,
+    max_msgs: usize,
+    msg_size: usize,
+    mode: u32,
+    /// メッセージが来るのを待つ受信側
+    readable: WaitQueue,
+    /// 空きができるのを待つ送信側
+    writable: WaitQueue,
+}
+
+impl MessageQueue {
+    fn new(max_msgs: usize, msg_size: usize, mode: u32) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::with_capacity(max_msgs)),
+            max_msgs,
+            msg_size,
+            mode: mode & 0o777,
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.lock().len()
+    }
+
+    /// メッセージを積む (一杯なら空くまでブロックし、nonblock なら EAGAIN)
+    pub fn send(&self, data: &[u8], priority: u32, nonblock: bool) -> Result<(), Errno> {
+        if data.len() > self.msg_size {
+            return Err(Errno::EMSGSIZE);
+        }
+        if priority >= MQ_PRIO_MAX {
+            return Err(Errno::EINVAL);
+        }
+
+        loop {
+            {
+                let mut messages = self.messages.lock();
+                if messages.len() < self.max_msgs {
+                    // 同じ優先度のメッセージの後ろに入れる
+                    let pos = messages.iter()
+                        .position(|message| message.priority < priority)
+                        .unwrap_or(messages.len());
+                    messages.insert(pos, Message { priority, data: Vec::from(data) });
+                    break;
+                }
+            }
+            if nonblock {
+                return Err(Errno::EAGAIN);
+            }
+            self.writable.wait_until(|| self.messages.lock().len() < self.max_msgs);
+        }
+
+        self.readable.wake_all();
+        Ok(())
+    }
+
+    /// 先頭のメッセージを buf に取り出し (長さ, 優先度) を返す
+    /// buf はメッセージの最大長以上なければならない (POSIX と同じく EMSGSIZE)
+    pub fn receive(&self, buf: &mut [u8], nonblock: bool) -> Result<(usize, u32), Errno> {
+        if buf.len() < self.msg_size {
+            return Err(Errno::EMSGSIZE);
+        }
+
+        let message = loop {
+            if let Some(message) = self.messages.lock().pop_front() {
+                break message;
+            }
+            if nonblock {
+                return Err(Errno::EAGAIN);
+            }
+            self.readable.wait_until(|| !self.messages.lock().is_empty());
+        };
+
+        self.writable.wake_all();
+        buf[..message.data.len()].copy_from_slice(&message.data);
+        Ok((message.data.len(), message.priority))
+    }
+}
+
+/// mq_open で開いたディスクリプタが指すもの
+pub struct MqHandle {
+    queue: Arc<MessageQueue>,
+    nonblock: AtomicBool,
+    can_send: bool,
+    can_receive: bool,
+}
+
+impl MqHandle {
+    pub fn send(&self, data: &[u8], priority: u32) -> Result<(), Errno> {
+        if !self.can_send {
+            return Err(Errno::EBADF);
+        }
+        self.queue.send(data, priority, self.nonblock.load(Ordering::Relaxed))
+    }
+
+    pub fn receive(&self, buf: &mut [u8]) -> Result<(usize, u32), Errno> {
+        if !self.can_receive {
+            return Err(Errno::EBADF);
+        }
+        self.queue.receive(buf, self.nonblock.load(Ordering::Relaxed))
+    }
+
+    /// メッセージがあれば POLLIN、空きがあれば POLLOUT
+    pub fn poll<'a>(&'a self, table: &mut PollTable<'a>) -> i16 {
+        table.register(&self.queue.readable);
+        table.register(&self.queue.writable);
+        let len = self.queue.len();
+        let mut events = 0;
+        if len > 0 {
+            events |= POLLIN;
+        }
+        if len < self.queue.max_msgs {
+            events |= POLLOUT;
+        }
+        events
+    }
+
+    pub fn attr(&self) -> MqAttr {
+        MqAttr {
+            mq_flags: if self.nonblock.load(Ordering::Relaxed) { O_NONBLOCK as i64 } else { 0 },
+            mq_maxmsg: self.queue.max_msgs as i64,
+            mq_msgsize: self.queue.msg_size as i64,
+            mq_curmsgs: self.queue.len() as i64,
+            ..MqAttr::default()
+        }
+    }
+
+    /// mq_setattr で変えられるのは O_NONBLOCK だけ
+    pub fn set_flags(&self, flags: i64) {
+        self.nonblock.store(flags & O_NONBLOCK as i64 != 0, Ordering::Relaxed);
+    }
+
+    pub fn stat(&self) -> Stat {
+        Stat {
+            st_mode: S_IFREG | self.queue.mode,
+            st_nlink: 1,
+            ..Stat::default()
+        }
+    }
+}
+
+/// 名前付きメッセージキューを開く (O_CREAT なら attr の大きさで作る、None なら既定値)
+pub fn open(name: &str, flags: i32, mode: u32, attr: Option<MqAttr>) -> Result<MqHandle, Errno> {
+    crate::shm::check_name(name)?;
+
+    let queue = {
+        let mut queues = QUEUES.lock();
+        match queues.get(name) {
+            Some(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(Errno::EEXIST),
+            Some(queue) => queue.clone(),
+            None if flags & O_CREAT != 0 => {
+                let (max_msgs, msg_size) = match attr {
+                    Some(attr) => (attr.mq_maxmsg, attr.mq_msgsize),
+                    None => (DEFAULT_MAXMSG as i64, DEFAULT_MSGSIZE as i64),
+                };
+                if max_msgs <= 0 || max_msgs as usize > MQ_MAXMSG_MAX
+                    || msg_size <= 0 || msg_size as usize > MQ_MSGSIZE_MAX {
+                    return Err(Errno::EINVAL);
+                }
+                let queue = Arc::new(MessageQueue::new(max_msgs as usize, msg_size as usize, mode));
+                queues.insert(String::from(name), queue.clone());
+                queue
+            }
+            None => return Err(Errno::ENOENT),
+        }
+    };
+
+    Ok(MqHandle {
+        queue,
+        nonblock: AtomicBool::new(flags & O_NONBLOCK != 0),
+        can_send: flags & (O_WRONLY | O_RDWR) != 0,
+        can_receive: flags & O_WRONLY == 0,
+    })
+}
+
+/// 名前を消す (開いているディスクリプタは閉じるまで使える)
+pub fn unlink(name: &str) -> Result<(), Errno> {
+    crate::shm::check_name(name)?;
+    QUEUES.lock().remove(name).map(|_| ()).ok_or(Errno::ENOENT)
+}
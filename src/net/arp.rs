@@ -0,0 +1,152 @@
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use super::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use super::{Ipv4Addr, MacAddress};
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+const PACKET_SIZE: usize = 28;
+
+/// 応答を待つ時間と再送回数
+const RESOLVE_TIMEOUT_MS: usize = 500;
+const RESOLVE_RETRIES: usize = 3;
+
+static CACHE: Mutex<BTreeMap<Ipv4Addr, MacAddress>> = Mutex::new(BTreeMap::new());
+
+struct ArpPacket {
+    op: u16,
+    sender_mac: MacAddress,
+    sender_ip: Ipv4Addr,
+    target_mac: MacAddress,
+    target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_SIZE {
+            return None;
+        }
+        let htype = u16::from_be_bytes([data[0], data[1]]);
+        let ptype = u16::from_be_bytes([data[2], data[3]]);
+        // Ethernet 上の IPv4 のみ扱う
+        if htype != HTYPE_ETHERNET || ptype != ETHERTYPE_IPV4 || data[4] != 6 || data[5] != 4 {
+            return None;
+        }
+        let mut packet = Self {
+            op: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: MacAddress([0; 6]),
+            sender_ip: Ipv4Addr([0; 4]),
+            target_mac: MacAddress([0; 6]),
+            target_ip: Ipv4Addr([0; 4]),
+        };
+        packet.sender_mac.0.copy_from_slice(&data[8..14]);
+        packet.sender_ip.0.copy_from_slice(&data[14..18]);
+        packet.target_mac.0.copy_from_slice(&data[18..24]);
+        packet.target_ip.0.copy_from_slice(&data[24..28]);
+        Some(packet)
+    }
+
+    fn to_bytes(&self) -> [u8; PACKET_SIZE] {
+        let mut data = [0u8; PACKET_SIZE];
+        data[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        data[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        data[4] = 6;
+        data[5] = 4;
+        data[6..8].copy_from_slice(&self.op.to_be_bytes());
+        data[8..14].copy_from_slice(&self.sender_mac.0);
+        data[14..18].copy_from_slice(&self.sender_ip.0);
+        data[18..24].copy_from_slice(&self.target_mac.0);
+        data[24..28].copy_from_slice(&self.target_ip.0);
+        data
+    }
+}
+
+/// 受信した ARP パケットを処理する (ETHERTYPE_ARP のハンドラ)
+pub fn handle_frame(frame: &EthernetFrame) {
+    let packet = match ArpPacket::parse(frame.payload) {
+        Some(packet) => packet,
+        None => return,
+    };
+    let our_ip = super::ip_address();
+    let our_mac = match super::mac_address() {
+        Some(mac) => mac,
+        None => return,
+    };
+
+    // 自分宛て、またはすでに知っている相手なら対応表を更新する (RFC 826)
+    {
+        let mut cache = CACHE.lock();
+        if packet.target_ip == our_ip || cache.contains_key(&packet.sender_ip) {
+            cache.insert(packet.sender_ip, packet.sender_mac);
+        }
+    }
+
+    if packet.op == OP_REQUEST && packet.target_ip == our_ip {
+        let reply = ArpPacket {
+            op: OP_REPLY,
+            sender_mac: our_mac,
+            sender_ip: our_ip,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        if let Err(e) = super::send_frame(packet.sender_mac, ETHERTYPE_ARP, &reply.to_bytes()) {
+            crate::warn!("ARP reply to {} failed: {}", packet.sender_ip, e);
+        }
+    }
+}
+
+/// 受信したパケットから分かった対応を登録する
+pub fn learn(ip: Ipv4Addr, mac: MacAddress) {
+    if mac.is_multicast() {
+        return;
+    }
+    CACHE.lock().insert(ip, mac);
+}
+
+pub fn lookup(ip: Ipv4Addr) -> Option<MacAddress> {
+    CACHE.lock().get(&ip).copied()
+}
+
+fn send_request(ip: Ipv4Addr) -> Result<(), &'static str> {
+    let request = ArpPacket {
+        op: OP_REQUEST,
+        sender_mac: super::mac_address().ok_or("No network device")?,
+        sender_ip: super::ip_address(),
+        target_mac: MacAddress([0; 6]),
+        target_ip: ip,
+    };
+    super::send_frame(MacAddress::BROADCAST, ETHERTYPE_ARP, &request.to_bytes())
+}
+
+/// IP アドレスに対応する MAC アドレスを求める
+/// キャッシュになければ ARP 要求を送り、応答が来るまで待つ
+pub fn resolve(ip: Ipv4Addr) -> Result<MacAddress, &'static str> {
+    if ip == Ipv4Addr::BROADCAST {
+        return Ok(MacAddress::BROADCAST);
+    }
+    if let Some(mac) = lookup(ip) {
+        return Ok(mac);
+    }
+
+    for _ in 0..RESOLVE_RETRIES {
+        send_request(ip)?;
+        let deadline = crate::drivers::timer::get_uptime_ms() + RESOLVE_TIMEOUT_MS;
+        while crate::drivers::timer::get_uptime_ms() < deadline {
+            if let Some(mac) = lookup(ip) {
+                return Ok(mac);
+            }
+            crate::sync::wait_for_interrupt();
+        }
+    }
+    Err("ARP resolution timed out")
+}
+
+/// ARP テーブルを表示する
+pub fn print_cache() {
+    let cache = CACHE.lock();
+    crate::println!("ARP cache ({} entries):", cache.len());
+    for (ip, mac) in cache.iter() {
+        crate::println!("  {:<15} {}", ip, mac);
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::vec::Vec;
+use crate::drivers::timer;
+use super::udp::{Datagram, UdpSocket};
+use super::{InterfaceConfig, Ipv4Addr, DEFAULT_CONFIG};
+
+const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// 応答をブロードキャストで返してもらう (アドレス未設定なのでユニキャストを受けられない前提)
+const FLAG_BROADCAST: u16 = 0x8000;
+
+// オプション
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETER_LIST: u8 = 55;
+const OPTION_END: u8 = 255;
+
+// メッセージ種別
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+/// op から chaddr までの固定部分 + sname + file
+const FIXED_SIZE: usize = 236;
+const REPLY_TIMEOUT_MS: usize = 2000;
+const RETRIES: usize = 4;
+/// 取得に失敗したら再挑戦するまでの時間
+const RETRY_INTERVAL_MS: usize = 30_000;
+
+/// サーバーから受け取った設定
+struct Lease {
+    address: Ipv4Addr,
+    server: Ipv4Addr,
+    netmask: Option<Ipv4Addr>,
+    gateway: Option<Ipv4Addr>,
+    dns: Option<Ipv4Addr>,
+    lease_secs: u32,
+}
+
+struct Reply {
+    message_type: u8,
+    lease: Lease,
+}
+
+fn build_message(xid: u32, message_type: u8, requested: Option<(Ipv4Addr, Ipv4Addr)>) -> Vec<u8> {
+    let mac = super::mac_address().unwrap_or(super::MacAddress([0; 6]));
+    let mut message = Vec::with_capacity(FIXED_SIZE + 64);
+    message.push(OP_REQUEST);
+    message.push(1); // htype: Ethernet
+    message.push(6); // hlen
+    message.push(0); // hops
+    message.extend_from_slice(&xid.to_be_bytes());
+    message.extend_from_slice(&[0, 0]); // secs
+    message.extend_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    message.extend_from_slice(&[0; 16]); // ciaddr, yiaddr, siaddr, giaddr
+    message.extend_from_slice(&mac.0);
+    message.resize(FIXED_SIZE, 0);
+
+    message.extend_from_slice(&MAGIC_COOKIE);
+    message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+    if let Some((address, server)) = requested {
+        message.extend_from_slice(&[OPTION_REQUESTED_IP, 4]);
+        message.extend_from_slice(&address.0);
+        message.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+        message.extend_from_slice(&server.0);
+    }
+    message.extend_from_slice(&[OPTION_PARAMETER_LIST, 3, OPTION_SUBNET_MASK, OPTION_ROUTER, OPTION_DNS]);
+    message.push(OPTION_END);
+    message
+}
+
+fn parse_reply(xid: u32, data: &[u8]) -> Option<Reply> {
+    if data.len() < FIXED_SIZE + 4 || data[0] != OP_REPLY {
+        return None;
+    }
+    if u32::from_be_bytes([data[4], data[5], data[6], data[7]]) != xid {
+        return None;
+    }
+    if data[FIXED_SIZE..FIXED_SIZE + 4] != MAGIC_COOKIE {
+        return None;
+    }
+
+    let ip_at = |offset: usize| Ipv4Addr([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+    let mut reply = Reply {
+        message_type: 0,
+        lease: Lease {
+            address: ip_at(16), // yiaddr
+            server: ip_at(20),  // siaddr (サーバー識別子がなければこれを使う)
+            netmask: None,
+            gateway: None,
+            dns: None,
+            lease_secs: 0,
+        },
+    };
+
+    let mut options = &data[FIXED_SIZE + 4..];
+    while let Some(&code) = options.first() {
+        match code {
+            OPTION_END => break,
+            OPTION_PAD => {
+                options = &options[1..];
+                continue;
+            }
+            _ => {}
+        }
+        let len = *options.get(1)? as usize;
+        let value = options.get(2..2 + len)?;
+        let ip = || (len >= 4).then(|| Ipv4Addr([value[0], value[1], value[2], value[3]]));
+        match code {
+            OPTION_MESSAGE_TYPE if len >= 1 => reply.message_type = value[0],
+            OPTION_SUBNET_MASK => reply.lease.netmask = ip(),
+            // 複数あれば最初のものを使う
+            OPTION_ROUTER => reply.lease.gateway = ip(),
+            OPTION_DNS => reply.lease.dns = ip(),
+            OPTION_SERVER_ID => reply.lease.server = ip().unwrap_or(reply.lease.server),
+            OPTION_LEASE_TIME if len >= 4 => {
+                reply.lease.lease_secs = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+            }
+            _ => {}
+        }
+        options = &options[2 + len..];
+    }
+    Some(reply)
+}
+
+/// メッセージを送り、期待する種別の応答を待つ (届かなければ再送する)
+fn exchange(socket: &alloc::sync::Arc<UdpSocket>, xid: u32, message: &[u8], expected: &[u8]) -> Option<Reply> {
+    for _ in 0..RETRIES {
+        if socket.send_to(Ipv4Addr::BROADCAST, SERVER_PORT, message).is_err() {
+            return None;
+        }
+        let deadline = timer::get_uptime_ms() + REPLY_TIMEOUT_MS;
+        loop {
+            let now = timer::get_uptime_ms();
+            if now >= deadline {
+                break;
+            }
+            let Datagram { data, .. } = match socket.recv_timeout(deadline - now) {
+                Some(datagram) => datagram,
+                None => break,
+            };
+            if let Some(reply) = parse_reply(xid, &data) {
+                if expected.contains(&reply.message_type) {
+                    return Some(reply);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// DISCOVER → OFFER → REQUEST → ACK の手順でアドレスを取得する
+fn acquire(socket: &alloc::sync::Arc<UdpSocket>) -> Result<Lease, &'static str> {
+    let xid = timer::now_ns() as u32 ^ 0x5254_0000;
+
+    let offer = exchange(socket, xid, &build_message(xid, DHCPDISCOVER, None), &[DHCPOFFER])
+        .ok_or("No DHCP offer")?;
+    let request = build_message(xid, DHCPREQUEST, Some((offer.lease.address, offer.lease.server)));
+    let ack = exchange(socket, xid, &request, &[DHCPACK, DHCPNAK]).ok_or("No DHCP ack")?;
+    if ack.message_type == DHCPNAK {
+        return Err("DHCP request refused");
+    }
+    Ok(ack.lease)
+}
+
+fn apply(lease: &Lease) {
+    let config = InterfaceConfig {
+        address: lease.address,
+        netmask: lease.netmask.unwrap_or(DEFAULT_CONFIG.netmask),
+        gateway: lease.gateway.unwrap_or(DEFAULT_CONFIG.gateway),
+        dns: lease.dns.unwrap_or(DEFAULT_CONFIG.dns),
+    };
+    super::set_config(config);
+    crate::info!("DHCP: {} netmask {} gateway {} dns {} (lease {}s from {})",
+        config.address, config.netmask, config.gateway, config.dns, lease.lease_secs, lease.server);
+}
+
+/// アドレスを取得し、リースの半分が過ぎるたびに更新し続けるスレッド
+fn client_thread() {
+    let socket = UdpSocket::new();
+    if let Err(e) = socket.bind(CLIENT_PORT) {
+        crate::warn!("DHCP: cannot bind port {}: {}", CLIENT_PORT, e);
+        return;
+    }
+
+    let mut configured = false;
+    loop {
+        // 取得中は送信元アドレスを 0.0.0.0 にする
+        let previous = super::config();
+        if !configured {
+            super::set_config(InterfaceConfig { address: Ipv4Addr::UNSPECIFIED, ..previous });
+        }
+
+        let wait_ms = match acquire(&socket) {
+            Ok(lease) => {
+                apply(&lease);
+                configured = true;
+                // リース期間が無期限 (0xFFFFFFFF) や不明なら1時間ごとに更新する
+                let secs = match lease.lease_secs {
+                    0 | u32::MAX => 3600,
+                    secs => secs / 2,
+                };
+                secs as usize * 1000
+            }
+            Err(e) => {
+                if !configured {
+                    super::set_config(previous);
+                    crate::warn!("DHCP failed ({}), using static address {}", e, previous.address);
+                } else {
+                    crate::warn!("DHCP renewal failed ({}), keeping {}", e, previous.address);
+                }
+                RETRY_INTERVAL_MS
+            }
+        };
+        timer::sleep_ms(wait_ms);
+    }
+}
+
+pub fn init() {
+    crate::kthread::spawn("dhcp", client_thread);
+}
@@ -0,0 +1,150 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::drivers::timer;
+use super::udp::UdpSocket;
+use super::Ipv4Addr;
+
+const DNS_PORT: u16 = 53;
+const HEADER_SIZE: usize = 12;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+/// 再帰問い合わせを要求する
+const FLAG_RD: u16 = 0x0100;
+const FLAG_QR: u16 = 0x8000;
+const RCODE_MASK: u16 = 0x000F;
+
+const REPLY_TIMEOUT_MS: usize = 2000;
+const RETRIES: usize = 3;
+/// キャッシュに置く TTL の上限
+const MAX_CACHE_TTL_SECS: u32 = 3600;
+
+// ホスト名 → (アドレス, 期限 [ms])
+static CACHE: Mutex<BTreeMap<String, (Ipv4Addr, usize)>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: core::sync::atomic::AtomicU16 = core::sync::atomic::AtomicU16::new(1);
+
+fn build_query(id: u16, hostname: &str) -> Result<Vec<u8>, &'static str> {
+    let mut query = Vec::with_capacity(HEADER_SIZE + hostname.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RD.to_be_bytes());
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // QDCOUNT = 1
+
+    for label in hostname.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err("Invalid hostname");
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_A.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// 名前を読み飛ばした位置を返す (圧縮ポインタなら2バイトで終わる)
+fn skip_name(data: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(offset)? as usize;
+        if len == 0 {
+            return Some(offset + 1);
+        }
+        if len & 0xC0 == 0xC0 {
+            return Some(offset + 2);
+        }
+        offset += 1 + len;
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+/// 応答から最初の A レコードを取り出す (CNAME は読み飛ばす)
+fn parse_response(id: u16, data: &[u8]) -> Result<Option<(Ipv4Addr, u32)>, &'static str> {
+    if data.len() < HEADER_SIZE || read_u16(data, 0) != Some(id) {
+        return Ok(None); // 別の問い合わせへの応答
+    }
+    let flags = read_u16(data, 2).unwrap();
+    if flags & FLAG_QR == 0 {
+        return Ok(None);
+    }
+    if flags & RCODE_MASK != 0 {
+        return Err("Host not found");
+    }
+    let questions = read_u16(data, 4).unwrap();
+    let answers = read_u16(data, 6).unwrap();
+
+    let malformed = "Malformed DNS response";
+    let mut offset = HEADER_SIZE;
+    for _ in 0..questions {
+        offset = skip_name(data, offset).ok_or(malformed)? + 4;
+    }
+    for _ in 0..answers {
+        offset = skip_name(data, offset).ok_or(malformed)?;
+        let record_type = read_u16(data, offset).ok_or(malformed)?;
+        let class = read_u16(data, offset + 2).ok_or(malformed)?;
+        let ttl_high = read_u16(data, offset + 4).ok_or(malformed)? as u32;
+        let ttl_low = read_u16(data, offset + 6).ok_or(malformed)? as u32;
+        let len = read_u16(data, offset + 8).ok_or(malformed)? as usize;
+        offset += 10;
+        let rdata = data.get(offset..offset + len).ok_or(malformed)?;
+        if record_type == TYPE_A && class == CLASS_IN && len == 4 {
+            let ip = Ipv4Addr([rdata[0], rdata[1], rdata[2], rdata[3]]);
+            return Ok(Some((ip, ttl_high << 16 | ttl_low)));
+        }
+        offset += len;
+    }
+    Err("No address record")
+}
+
+fn query(hostname: &str) -> Result<(Ipv4Addr, u32), &'static str> {
+    let server = super::config().dns;
+    let socket = UdpSocket::new();
+
+    for _ in 0..RETRIES {
+        let id = NEXT_ID.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        socket.send_to(server, DNS_PORT, &build_query(id, hostname)?)?;
+
+        let deadline = timer::get_uptime_ms() + REPLY_TIMEOUT_MS;
+        loop {
+            let now = timer::get_uptime_ms();
+            if now >= deadline {
+                break;
+            }
+            let datagram = match socket.recv_timeout(deadline - now) {
+                Some(datagram) => datagram,
+                None => break,
+            };
+            if datagram.src != server || datagram.src_port != DNS_PORT {
+                continue;
+            }
+            if let Some(answer) = parse_response(id, &datagram.data)? {
+                return Ok(answer);
+            }
+        }
+    }
+    Err("DNS query timed out")
+}
+
+/// ホスト名を IPv4 アドレスに変換する
+/// "10.0.2.2" のようなアドレス表記はそのまま返す
+pub fn resolve(hostname: &str) -> Result<Ipv4Addr, &'static str> {
+    if let Some(ip) = Ipv4Addr::parse(hostname) {
+        return Ok(ip);
+    }
+
+    let now = timer::get_uptime_ms();
+    if let Some(&(ip, expires)) = CACHE.lock().get(hostname) {
+        if now < expires {
+            return Ok(ip);
+        }
+    }
+
+    let (ip, ttl) = query(hostname)?;
+    let ttl_ms = ttl.min(MAX_CACHE_TTL_SECS) as usize * 1000;
+    CACHE.lock().insert(String::from(hostname), (ip, now + ttl_ms));
+    crate::debug!("Resolved {} to {} (TTL {}s)", hostname, ip, ttl);
+    Ok(ip)
+}
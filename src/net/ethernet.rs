@@ -0,0 +1,51 @@
+use alloc::vec::Vec;
+use super::MacAddress;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+/// 宛先 MAC + 送信元 MAC + EtherType
+pub const HEADER_SIZE: usize = 14;
+/// ペイロードの最大長
+pub const MTU: usize = 1500;
+/// FCS を除いた最小フレーム長 (足りない分はパディングする)
+const MIN_FRAME_SIZE: usize = 60;
+
+/// 受信した Ethernet II フレーム
+pub struct EthernetFrame<'a> {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+        let mut dst = [0u8; 6];
+        let mut src = [0u8; 6];
+        dst.copy_from_slice(&data[0..6]);
+        src.copy_from_slice(&data[6..12]);
+        Some(Self {
+            dst: MacAddress(dst),
+            src: MacAddress(src),
+            ethertype: u16::from_be_bytes([data[12], data[13]]),
+            payload: &data[HEADER_SIZE..],
+        })
+    }
+}
+
+/// ヘッダを付けたフレームを組み立てる
+pub fn build(dst: MacAddress, src: MacAddress, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(core::cmp::max(HEADER_SIZE + payload.len(), MIN_FRAME_SIZE));
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    if frame.len() < MIN_FRAME_SIZE {
+        frame.resize(MIN_FRAME_SIZE, 0);
+    }
+    frame
+}
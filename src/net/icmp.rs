@@ -0,0 +1,31 @@
+use alloc::vec::Vec;
+use super::ipv4::{self, Ipv4Packet, PROTOCOL_ICMP};
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// type + code + checksum + identifier + sequence
+const HEADER_SIZE: usize = 8;
+
+/// 受信した ICMP メッセージを処理する (今のところエコー要求への応答のみ)
+pub fn handle_packet(packet: &Ipv4Packet) {
+    let data = packet.payload;
+    if data.len() < HEADER_SIZE || ipv4::checksum(data) != 0 {
+        return;
+    }
+    if data[0] != TYPE_ECHO_REQUEST {
+        return;
+    }
+
+    // 識別子・シーケンス番号・データはそのまま返す
+    let mut reply: Vec<u8> = data.to_vec();
+    reply[0] = TYPE_ECHO_REPLY;
+    reply[1] = 0;
+    reply[2..4].copy_from_slice(&[0, 0]);
+    let sum = ipv4::checksum(&reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    if let Err(e) = ipv4::send(packet.src, PROTOCOL_ICMP, &reply) {
+        crate::debug!("ICMP echo reply to {} failed: {}", packet.src, e);
+    }
+}
@@ -0,0 +1,151 @@
+use alloc::vec::Vec;
+use super::ethernet::{EthernetFrame, ETHERTYPE_IPV4, MTU};
+use super::{arp, Ipv4Addr};
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_UDP: u8 = 17;
+pub const PROTOCOL_TCP: u8 = 6;
+
+/// オプションなしのヘッダ長
+pub const HEADER_SIZE: usize = 20;
+/// 1パケットで送れるペイロードの最大長 (フラグメント化はしない)
+pub const MAX_PAYLOAD: usize = MTU - HEADER_SIZE;
+const DEFAULT_TTL: u8 = 64;
+/// Don't Fragment
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
+
+static NEXT_ID: core::sync::atomic::AtomicU16 = core::sync::atomic::AtomicU16::new(1);
+
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || data[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (data[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if header_len < HEADER_SIZE || total_len < header_len || total_len > data.len() {
+            return None;
+        }
+        if checksum(&data[..header_len]) != 0 {
+            return None;
+        }
+        // フラグメントは再構築しないので捨てる
+        let flags = u16::from_be_bytes([data[6], data[7]]);
+        if flags & FLAG_MF != 0 || flags & FRAGMENT_OFFSET_MASK != 0 {
+            return None;
+        }
+        Some(Self {
+            src: Ipv4Addr([data[12], data[13], data[14], data[15]]),
+            dst: Ipv4Addr([data[16], data[17], data[18], data[19]]),
+            protocol: data[9],
+            ttl: data[8],
+            // Ethernet のパディングを取り除く
+            payload: &data[header_len..total_len],
+        })
+    }
+}
+
+/// インターネットチェックサムの途中経過 (16ビット単位の和) に data を加える
+pub fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+/// 桁上がりを折り返して1の補数を取る
+pub fn checksum_finish(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+pub fn checksum(data: &[u8]) -> u16 {
+    checksum_finish(checksum_add(0, data))
+}
+
+/// UDP/TCP のチェックサムに含める疑似ヘッダの和
+pub fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let sum = checksum_add(0, &src.0);
+    let sum = checksum_add(sum, &dst.0);
+    sum + protocol as u32 + len as u32
+}
+
+/// 同じサブネットなら宛先そのもの、そうでなければゲートウェイに送る
+fn next_hop(dst: Ipv4Addr) -> Ipv4Addr {
+    let config = super::config();
+    let mask = config.netmask.to_u32();
+    if dst == Ipv4Addr::BROADCAST || dst.to_u32() & mask == config.address.to_u32() & mask {
+        dst
+    } else {
+        config.gateway
+    }
+}
+
+/// IPv4 ヘッダを付けて送信する
+pub fn send(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), &'static str> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err("Packet too large");
+    }
+    let src = super::ip_address();
+    let total_len = (HEADER_SIZE + payload.len()) as u16;
+    let id = NEXT_ID.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.push(0x45); // バージョン 4, ヘッダ長 5 ワード
+    packet.push(0);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.push(DEFAULT_TTL);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let header_checksum = checksum(&packet[..HEADER_SIZE]);
+    packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+    packet.extend_from_slice(payload);
+
+    let mac = arp::resolve(next_hop(dst))?;
+    super::send_frame(mac, ETHERTYPE_IPV4, &packet)
+}
+
+/// 受信した IPv4 パケットを上位プロトコルに振り分ける (ETHERTYPE_IPV4 のハンドラ)
+pub fn handle_frame(frame: &EthernetFrame) {
+    let packet = match Ipv4Packet::parse(frame.payload) {
+        Some(packet) => packet,
+        None => return,
+    };
+    let our_ip = super::ip_address();
+    // アドレス未設定の間 (DHCP 中) はユニキャストも受け取る
+    if packet.dst != our_ip && packet.dst != Ipv4Addr::BROADCAST && our_ip != Ipv4Addr::UNSPECIFIED {
+        return;
+    }
+
+    // 応答を返すときに ARP 要求を待たずに済むよう、同じサブネットの送信元を覚えておく
+    if next_hop(packet.src) == packet.src {
+        arp::learn(packet.src, frame.src);
+    }
+
+    match packet.protocol {
+        PROTOCOL_ICMP => super::icmp::handle_packet(&packet),
+        PROTOCOL_UDP => super::udp::handle_packet(&packet),
+        PROTOCOL_TCP => super::tcp::handle_packet(&packet),
+        protocol => crate::trace!("Dropped IPv4 packet with protocol {}", protocol),
+    }
+}
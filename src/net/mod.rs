@@ -0,0 +1,227 @@
+pub mod ethernet;
+pub mod arp;
+pub mod ipv4;
+pub mod icmp;
+pub mod udp;
+pub mod tcp;
+pub mod socket;
+pub mod dhcp;
+pub mod dns;
+pub mod syscalls;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use crate::sync::WaitQueue;
+use ethernet::EthernetFrame;
+
+/// 受信フレームを上位プロトコルに渡すハンドラ
+/// 受信スレッド ("net-rx") から呼ばれるので、ブロックする処理もできる
+pub type FrameHandler = fn(&EthernetFrame);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = &self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", m[0], m[1], m[2], m[3], m[4], m[5])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0; 4]);
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([255; 4]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Ipv4Addr([a, b, c, d])
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn from_u32(value: u32) -> Self {
+        Ipv4Addr(value.to_be_bytes())
+    }
+
+    /// "192.168.0.1" 形式の文字列を解釈する
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut octets = [0u8; 4];
+        let mut parts = text.split('.');
+        for octet in octets.iter_mut() {
+            *octet = parts.next()?.parse().ok()?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Ipv4Addr(octets))
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+/// NIC ドライバが実装するインターフェース
+pub trait NetworkDevice: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn mac_address(&self) -> MacAddress;
+
+    /// Ethernet フレーム (FCS なし) を1つ送信する
+    fn transmit(&self, frame: &[u8]) -> Result<(), &'static str>;
+
+    /// 受信済みのフレームを1つ取り出す (なければ None)
+    fn receive(&self) -> Option<Vec<u8>>;
+}
+
+/// インターフェースのアドレス設定
+/// DHCP で取得できなければ QEMU のユーザーモードネットワークの既定値を使う
+#[derive(Debug, Clone, Copy)]
+pub struct InterfaceConfig {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+}
+
+pub const DEFAULT_CONFIG: InterfaceConfig = InterfaceConfig {
+    address: Ipv4Addr::new(10, 0, 2, 15),
+    netmask: Ipv4Addr::new(255, 255, 255, 0),
+    gateway: Ipv4Addr::new(10, 0, 2, 2),
+    dns: Ipv4Addr::new(10, 0, 2, 3),
+};
+
+static DEVICE: Mutex<Option<Arc<dyn NetworkDevice>>> = Mutex::new(None);
+static CONFIG: Mutex<InterfaceConfig> = Mutex::new(DEFAULT_CONFIG);
+static HANDLERS: Mutex<Vec<(u16, FrameHandler)>> = Mutex::new(Vec::new());
+
+/// 受信スレッドを起こすための待ち行列 (ドライバの割り込みから rx_notify で起こす)
+static RX_WAIT: WaitQueue = WaitQueue::new();
+
+/// NIC を登録する (今のところインターフェースは1つだけ)
+pub fn register_device(device: Arc<dyn NetworkDevice>) -> Result<(), &'static str> {
+    let mut slot = DEVICE.lock();
+    if slot.is_some() {
+        return Err("Network device already registered");
+    }
+    crate::info!("Network device: {} (MAC {})", device.name(), device.mac_address());
+    *slot = Some(device);
+    Ok(())
+}
+
+fn device() -> Option<Arc<dyn NetworkDevice>> {
+    DEVICE.lock().clone()
+}
+
+pub fn mac_address() -> Option<MacAddress> {
+    device().map(|device| device.mac_address())
+}
+
+pub fn config() -> InterfaceConfig {
+    *CONFIG.lock()
+}
+
+pub fn set_config(config: InterfaceConfig) {
+    *CONFIG.lock() = config;
+}
+
+pub fn ip_address() -> Ipv4Addr {
+    CONFIG.lock().address
+}
+
+/// ホスト名を DNS で引く (アドレス表記ならそのまま返す)
+pub fn resolve(hostname: &str) -> Result<Ipv4Addr, &'static str> {
+    dns::resolve(hostname)
+}
+
+/// EtherType ごとの受信ハンドラを登録する
+pub fn register_handler(ethertype: u16, handler: FrameHandler) -> Result<(), &'static str> {
+    let mut handlers = HANDLERS.lock();
+    if handlers.iter().any(|(registered, _)| *registered == ethertype) {
+        return Err("EtherType already handled");
+    }
+    handlers.push((ethertype, handler));
+    Ok(())
+}
+
+/// ペイロードに Ethernet ヘッダを付けて送信する
+pub fn send_frame(dst: MacAddress, ethertype: u16, payload: &[u8]) -> Result<(), &'static str> {
+    if payload.len() > ethernet::MTU {
+        return Err("Payload exceeds MTU");
+    }
+    let device = device().ok_or("No network device")?;
+    let frame = ethernet::build(dst, device.mac_address(), ethertype, payload);
+    device.transmit(&frame)
+}
+
+/// ドライバが受信割り込みで呼ぶ
+/// 割り込みコンテキストではフレームを処理せず、受信スレッドを起こすだけにする
+pub fn rx_notify() {
+    RX_WAIT.wake_all();
+}
+
+fn dispatch(data: &[u8], our_mac: MacAddress) {
+    let frame = match EthernetFrame::parse(data) {
+        Some(frame) => frame,
+        None => return,
+    };
+    if frame.dst != our_mac && !frame.dst.is_multicast() {
+        return;
+    }
+
+    let handler = HANDLERS.lock().iter()
+        .find(|(ethertype, _)| *ethertype == frame.ethertype)
+        .map(|(_, handler)| *handler);
+    match handler {
+        Some(handler) => handler(&frame),
+        None => crate::trace!("Dropped frame with EtherType {:#06x}", frame.ethertype),
+    }
+}
+
+/// 受信フレームを取り出して上位プロトコルに振り分け続ける
+fn rx_thread(device: Arc<dyn NetworkDevice>) {
+    let our_mac = device.mac_address();
+    loop {
+        let mut frame = None;
+        RX_WAIT.wait_until(|| {
+            frame = device.receive();
+            frame.is_some()
+        });
+        if let Some(frame) = frame {
+            dispatch(&frame, our_mac);
+        }
+    }
+}
+
+/// ソケットのシステムコールとプロトコルハンドラを登録し、受信スレッドを起動する
+pub fn init() -> Result<(), &'static str> {
+    if !crate::config::enabled(crate::config::Feature::Net) {
+        return Err("Disabled by config");
+    }
+    syscalls::register()?;
+    let device = device().ok_or("No network device")?;
+    register_handler(ethernet::ETHERTYPE_ARP, arp::handle_frame)?;
+    register_handler(ethernet::ETHERTYPE_IPV4, ipv4::handle_frame)?;
+
+    crate::kthread::spawn("net-rx", move || rx_thread(device));
+    tcp::init();
+    dhcp::init();
+    crate::events::publish(crate::events::Event::NetLink { up: true });
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use alloc::sync::Arc;
+use super::tcp::TcpSocket;
+use super::udp::UdpSocket;
+use super::Ipv4Addr;
+
+// ソケットの定数と struct sockaddr_in は abi で定義する
+pub use crate::abi::{
+    SockAddrIn, AF_INET, IPPROTO_TCP, IPPROTO_UDP, SOCK_CLOEXEC, SOCK_DGRAM, SOCK_NONBLOCK, SOCK_STREAM,
+};
+
+impl SockAddrIn {
+    pub fn new(addr: Ipv4Addr, port: u16) -> Self {
+        Self {
+            sin_family: AF_INET,
+            sin_port: port.to_be(),
+            sin_addr: addr.0,
+            sin_zero: [0; 8],
+        }
+    }
+
+    pub fn addr(&self) -> Ipv4Addr {
+        Ipv4Addr(self.sin_addr)
+    }
+
+    pub fn port(&self) -> u16 {
+        u16::from_be(self.sin_port)
+    }
+}
+
+/// ファイルディスクリプタから参照されるソケット
+pub enum Socket {
+    Udp(Arc<UdpSocket>),
+    Tcp(Arc<TcpSocket>),
+}
+
+impl Socket {
+    pub fn create(domain: i32, socket_type: i32, protocol: i32) -> Result<Self, &'static str> {
+        if domain != AF_INET as i32 {
+            return Err("Address family not supported");
+        }
+        match (socket_type, protocol) {
+            (SOCK_DGRAM, 0) | (SOCK_DGRAM, IPPROTO_UDP) => Ok(Socket::Udp(UdpSocket::new())),
+            (SOCK_STREAM, 0) | (SOCK_STREAM, IPPROTO_TCP) => Ok(Socket::Tcp(TcpSocket::new())),
+            _ => Err("Socket type not supported"),
+        }
+    }
+
+    pub fn bind(&self, addr: &SockAddrIn) -> Result<(), &'static str> {
+        if addr.sin_family != AF_INET {
+            return Err("Address family not supported");
+        }
+        // インターフェースは1つなので、自分のアドレスか INADDR_ANY のみ受け付ける
+        if addr.addr() != Ipv4Addr::UNSPECIFIED && addr.addr() != super::ip_address() {
+            return Err("Address not available");
+        }
+        match self {
+            Socket::Udp(socket) => socket.bind(addr.port()).map(|_| ()),
+            Socket::Tcp(socket) => socket.bind(addr.port()),
+        }
+    }
+
+    pub fn listen(&self, backlog: usize) -> Result<(), &'static str> {
+        match self {
+            Socket::Tcp(socket) => socket.listen(backlog),
+            _ => Err("Operation not supported"),
+        }
+    }
+
+    /// 接続が確立するまでブロックする
+    pub fn connect(&self, addr: &SockAddrIn) -> Result<(), &'static str> {
+        if addr.sin_family != AF_INET {
+            return Err("Address family not supported");
+        }
+        match self {
+            Socket::Tcp(socket) => socket.connect(addr.addr(), addr.port()),
+            _ => Err("Operation not supported"),
+        }
+    }
+
+    /// 確立した接続を新しいソケットとして取り出す
+    pub fn accept(&self) -> Result<(Socket, SockAddrIn), &'static str> {
+        match self {
+            Socket::Tcp(socket) => {
+                let connection = socket.accept()?;
+                let (ip, port) = connection.peer().unwrap_or((Ipv4Addr::UNSPECIFIED, 0));
+                Ok((Socket::Tcp(connection), SockAddrIn::new(ip, port)))
+            }
+            _ => Err("Operation not supported"),
+        }
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        match self {
+            Socket::Udp(_) => Ok(self.recv_from(buf)?.0),
+            Socket::Tcp(socket) => socket.read(buf),
+        }
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, &'static str> {
+        match self {
+            // 接続していないデータグラムソケットは宛先が分からない
+            Socket::Udp(_) => Err("Destination address required"),
+            Socket::Tcp(socket) => socket.write(buf),
+        }
+    }
+
+    pub fn send_to(&self, buf: &[u8], addr: &SockAddrIn) -> Result<usize, &'static str> {
+        if addr.sin_family != AF_INET {
+            return Err("Address family not supported");
+        }
+        match self {
+            Socket::Udp(socket) => socket.send_to(addr.addr(), addr.port(), buf),
+            // ストリームソケットでは宛先を無視する
+            Socket::Tcp(socket) => socket.write(buf),
+        }
+    }
+
+    /// 受信して buf に入るだけコピーし、(長さ, 送信元) を返す
+    /// データグラムの残りは捨てる
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SockAddrIn), &'static str> {
+        match self {
+            Socket::Udp(socket) => {
+                let datagram = socket.recv_from();
+                let len = core::cmp::min(buf.len(), datagram.data.len());
+                buf[..len].copy_from_slice(&datagram.data[..len]);
+                Ok((len, SockAddrIn::new(datagram.src, datagram.src_port)))
+            }
+            Socket::Tcp(socket) => {
+                let len = socket.read(buf)?;
+                let (ip, port) = socket.peer().unwrap_or((Ipv4Addr::UNSPECIFIED, 0));
+                Ok((len, SockAddrIn::new(ip, port)))
+            }
+        }
+    }
+}
+
+impl Socket {
+    pub fn poll<'a>(&'a self, table: &mut crate::poll::PollTable<'a>) -> i16 {
+        match self {
+            Socket::Udp(socket) => socket.poll(table),
+            Socket::Tcp(socket) => socket.poll(table),
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // 最後のディスクリプタが閉じられたら接続も閉じる
+        if let Socket::Tcp(socket) = self {
+            socket.close();
+        }
+    }
+}
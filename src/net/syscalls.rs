@@ -0,0 +1,158 @@
+use alloc::vec;
+use crate::errno::{Errno, SysResult};
+use crate::net::socket::{SockAddrIn, Socket};
+use crate::syscall::{
+    self, get_file, install_fd, SyscallHandler, USER_CHUNK_SIZE, SYS_ACCEPT, SYS_BIND, SYS_CONNECT,
+    SYS_LISTEN, SYS_RECVFROM, SYS_SENDTO, SYS_SOCKET,
+};
+use crate::uaccess::{copy_from_user, copy_to_user, read_user, write_user};
+
+/// ソケットのシステムコールを syscall の表に登録する (NIC がなくてもループバックで使える)
+pub fn register() -> Result<(), &'static str> {
+    syscall::register(SYS_SOCKET, SyscallHandler::new("socket", |args| {
+        sys_socket(args[0] as i32, args[1] as i32, args[2] as i32)
+    }))?;
+    syscall::register(SYS_BIND, SyscallHandler::new("bind", |args| {
+        sys_bind(args[0] as i32, args[1] as *const SockAddrIn, args[2] as usize)
+    }))?;
+    syscall::register(SYS_LISTEN, SyscallHandler::new("listen", |args| {
+        sys_listen(args[0] as i32, args[1] as i32)
+    }))?;
+    syscall::register(SYS_CONNECT, SyscallHandler::new("connect", |args| {
+        sys_connect(args[0] as i32, args[1] as *const SockAddrIn, args[2] as usize)
+    }))?;
+    syscall::register(SYS_ACCEPT, SyscallHandler::new("accept", |args| {
+        sys_accept(args[0] as i32, args[1] as *mut SockAddrIn, args[2] as *mut u32)
+    }))?;
+    syscall::register(SYS_SENDTO, SyscallHandler::new("sendto", |args| {
+        sys_sendto(args[0] as i32, args[1] as *const u8, args[2] as usize, args[3] as i32,
+            args[4] as *const SockAddrIn, args[5] as usize)
+    }))?;
+    syscall::register(SYS_RECVFROM, SyscallHandler::new("recvfrom", |args| {
+        sys_recvfrom(args[0] as i32, args[1] as *mut u8, args[2] as usize, args[3] as i32,
+            args[4] as *mut SockAddrIn, args[5] as *mut u32)
+    }))?;
+    Ok(())
+}
+
+fn sys_socket(domain: i32, socket_type: i32, protocol: i32) -> SysResult {
+    use crate::net::socket::{AF_INET, SOCK_CLOEXEC, SOCK_DGRAM, SOCK_NONBLOCK, SOCK_STREAM};
+    use crate::filesystem::{O_NONBLOCK, O_RDWR};
+
+    // type には SOCK_NONBLOCK などのフラグを OR できる (SOCK_CLOEXEC は無視する)
+    let flags = if socket_type & SOCK_NONBLOCK != 0 { O_RDWR | O_NONBLOCK } else { O_RDWR };
+    let socket_type = socket_type & !(SOCK_NONBLOCK | SOCK_CLOEXEC);
+    let socket = Socket::create(domain, socket_type, protocol).map_err(|_| {
+        if domain != AF_INET as i32 {
+            Errno::EAFNOSUPPORT
+        } else if socket_type != SOCK_STREAM && socket_type != SOCK_DGRAM {
+            Errno::EINVAL
+        } else {
+            Errno::EPROTONOSUPPORT
+        }
+    })?;
+    install_fd(crate::fd::FileObject::Socket(socket), flags)
+}
+
+/// ディスクリプタがソケットなら f を呼ぶ
+fn with_socket(fd: i32, f: impl FnOnce(&Socket) -> SysResult) -> SysResult {
+    match &get_file(fd)?.object {
+        crate::fd::FileObject::Socket(socket) => f(socket),
+        _ => Err(Errno::ENOTSOCK),
+    }
+}
+
+fn sys_bind(fd: i32, addr: *const SockAddrIn, addrlen: usize) -> SysResult {
+    if addrlen < core::mem::size_of::<SockAddrIn>() {
+        return Err(Errno::EINVAL);
+    }
+    let addr = read_user(addr as u64)?;
+    with_socket(fd, |socket| match socket.bind(&addr) {
+        Ok(()) => Ok(0),
+        Err(_) => Err(Errno::EADDRINUSE),
+    })
+}
+
+fn sys_sendto(fd: i32, buf: *const u8, len: usize, _flags: i32,
+    dest_addr: *const SockAddrIn, addrlen: usize) -> SysResult {
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    if dest_addr.is_null() || addrlen < core::mem::size_of::<SockAddrIn>() {
+        return Err(Errno::EDESTADDRREQ);
+    }
+    // バウンスバッファは UDP の最大ペイロードより大きいので、
+    // 切り詰めたデータグラムが送られることはない (ストリームは短い書き込みになる)
+    let mut data = vec![0u8; core::cmp::min(len, USER_CHUNK_SIZE)];
+    copy_from_user(&mut data, buf as u64)?;
+    let addr = read_user(dest_addr as u64)?;
+    get_file(fd)?.check_ready(crate::poll::POLLOUT)?;
+    with_socket(fd, |socket| match socket.send_to(&data, &addr) {
+        Ok(sent) => Ok(sent as i64),
+        Err(_) if data.len() > crate::net::udp::MAX_PAYLOAD => Err(Errno::EMSGSIZE),
+        Err(_) => Err(Errno::EHOSTUNREACH),
+    })
+}
+
+fn sys_recvfrom(fd: i32, buf: *mut u8, len: usize, _flags: i32,
+    src_addr: *mut SockAddrIn, addrlen: *mut u32) -> SysResult {
+    if buf.is_null() || !crate::uaccess::access_ok(buf as u64, len, true) {
+        return Err(Errno::EFAULT);
+    }
+    let mut data = vec![0u8; core::cmp::min(len, USER_CHUNK_SIZE)];
+    get_file(fd)?.check_ready(crate::poll::POLLIN)?;
+    with_socket(fd, |socket| match socket.recv_from(&mut data) {
+        Ok((received, from)) => {
+            copy_to_user(buf as u64, &data[..received])?;
+            store_sockaddr(src_addr, addrlen, from)?;
+            Ok(received as i64)
+        }
+        Err(_) => Err(Errno::ECONNRESET),
+    })
+}
+
+/// 相手のアドレスをユーザーに返す (要らなければ addr は NULL でよい)
+fn store_sockaddr(addr: *mut SockAddrIn, addrlen: *mut u32, value: SockAddrIn) -> Result<(), Errno> {
+    if addr.is_null() || addrlen.is_null() {
+        return Ok(());
+    }
+    let len = read_user::<u32>(addrlen as u64)?;
+    if len as usize >= core::mem::size_of::<SockAddrIn>() {
+        write_user(addr as u64, &value)?;
+    }
+    write_user(addrlen as u64, &(core::mem::size_of::<SockAddrIn>() as u32))
+}
+
+fn sys_listen(fd: i32, backlog: i32) -> SysResult {
+    with_socket(fd, |socket| match socket.listen(backlog.max(0) as usize) {
+        Ok(()) => Ok(0),
+        Err(_) => Err(Errno::EOPNOTSUPP),
+    })
+}
+
+fn sys_connect(fd: i32, addr: *const SockAddrIn, addrlen: usize) -> SysResult {
+    if addrlen < core::mem::size_of::<SockAddrIn>() {
+        return Err(Errno::EINVAL);
+    }
+    let addr = read_user(addr as u64)?;
+    with_socket(fd, |socket| match socket.connect(&addr) {
+        Ok(()) => Ok(0),
+        Err(_) => Err(Errno::ECONNREFUSED),
+    })
+}
+
+fn sys_accept(fd: i32, addr: *mut SockAddrIn, addrlen: *mut u32) -> SysResult {
+    let file = get_file(fd)?;
+    let accepted = match &file.object {
+        crate::fd::FileObject::Socket(socket) => {
+            // O_NONBLOCK なら確立済みの接続がなければ待たない
+            file.check_ready(crate::poll::POLLIN)?;
+            socket.accept()
+        }
+        _ => return Err(Errno::ENOTSOCK),
+    };
+    let (connection, peer) = accepted.map_err(|_| Errno::EINVAL)?;
+    let new_fd = install_fd(crate::fd::FileObject::Socket(connection), crate::filesystem::O_RDWR)?;
+    store_sockaddr(addr, addrlen, peer)?;
+    Ok(new_fd)
+}
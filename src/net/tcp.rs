@@ -0,0 +1,952 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use crate::drivers::timer;
+use crate::sync::WaitQueue;
+use crate::poll::{PollTable, POLLERR, POLLHUP, POLLIN, POLLOUT};
+use super::ipv4::{self, Ipv4Packet, PROTOCOL_TCP};
+use super::Ipv4Addr;
+
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+
+/// オプションなしのヘッダ長
+const HEADER_SIZE: usize = 20;
+/// 送受信するセグメントの最大データ長
+const MSS: usize = ipv4::MAX_PAYLOAD - HEADER_SIZE;
+/// 送信・受信バッファの容量 (受信ウィンドウの最大値にもなる)
+const BUFFER_SIZE: usize = 16 * 1024;
+
+const INITIAL_RTO_MS: usize = 1000;
+const MAX_RTO_MS: usize = 16000;
+/// 再送してもACKが来なければ接続を切る回数
+const MAX_RETRIES: usize = 6;
+/// TIME_WAIT に留まる時間 (本来は 2MSL だが短くしている)
+const TIME_WAIT_MS: usize = 2000;
+const MAX_BACKLOG: usize = 16;
+/// connect で割り当てるポートの範囲
+const EPHEMERAL_START: u16 = 49152;
+
+/// (ローカルポート, 相手のアドレス, 相手のポート)
+type ConnectionKey = (u16, Ipv4Addr, u16);
+
+static CONNECTIONS: Mutex<BTreeMap<ConnectionKey, Arc<TcpSocket>>> = Mutex::new(BTreeMap::new());
+static LISTENERS: Mutex<BTreeMap<u16, Arc<TcpSocket>>> = Mutex::new(BTreeMap::new());
+// 再送・TIME_WAIT のタイマーが切れたことを tcp-timer スレッドに知らせる
+static TIMER_EXPIRED: AtomicBool = AtomicBool::new(false);
+static TIMER_EVENTS: WaitQueue = WaitQueue::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// シーケンス番号の比較 (2^32 で一周するので差の符号で判断する)
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// 送信するセグメント
+/// 接続のロックを持ったまま送ると ARP 解決などで長く待つことがあるので、
+/// いったん組み立ててからロックの外で送る
+struct Segment {
+    dst: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    data: Vec<u8>,
+}
+
+impl Segment {
+    fn transmit(&self) {
+        let src = super::ip_address();
+        // SYN には MSS オプションを付ける
+        let options: &[u8] = if self.flags & FLAG_SYN != 0 {
+            &[2, 4, (MSS >> 8) as u8, MSS as u8]
+        } else {
+            &[]
+        };
+        let header_len = HEADER_SIZE + options.len();
+
+        let mut segment = Vec::with_capacity(header_len + self.data.len());
+        segment.extend_from_slice(&self.src_port.to_be_bytes());
+        segment.extend_from_slice(&self.dst_port.to_be_bytes());
+        segment.extend_from_slice(&self.seq.to_be_bytes());
+        segment.extend_from_slice(&self.ack.to_be_bytes());
+        segment.push(((header_len / 4) as u8) << 4);
+        segment.push(self.flags);
+        segment.extend_from_slice(&self.window.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]); // チェックサム, 緊急ポインタ
+        segment.extend_from_slice(options);
+        segment.extend_from_slice(&self.data);
+
+        let sum = ipv4::pseudo_header_sum(src, self.dst, PROTOCOL_TCP, segment.len());
+        let checksum = ipv4::checksum_finish(ipv4::checksum_add(sum, &segment));
+        segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+        if let Err(e) = ipv4::send(self.dst, PROTOCOL_TCP, &segment) {
+            crate::debug!("TCP send to {}:{} failed: {}", self.dst, self.dst_port, e);
+        }
+    }
+}
+
+fn transmit_all(segments: Vec<Segment>) {
+    for segment in segments {
+        segment.transmit();
+    }
+}
+
+/// 受信したセグメント
+struct IncomingSegment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    data: &'a [u8],
+}
+
+impl<'a> IncomingSegment<'a> {
+    fn parse(packet: &Ipv4Packet<'a>) -> Option<Self> {
+        let data = packet.payload;
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+        let header_len = (data[12] >> 4) as usize * 4;
+        if header_len < HEADER_SIZE || header_len > data.len() {
+            return None;
+        }
+        let sum = ipv4::pseudo_header_sum(packet.src, packet.dst, PROTOCOL_TCP, data.len());
+        if ipv4::checksum_finish(ipv4::checksum_add(sum, data)) != 0 {
+            return None;
+        }
+
+        // オプションは MSS だけ見る
+        let mut mss = None;
+        let mut options = &data[HEADER_SIZE..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                0 => break,
+                1 => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        break;
+                    }
+                    if kind == 2 && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+
+        Some(Self {
+            src_port: u16::from_be_bytes([data[0], data[1]]),
+            dst_port: u16::from_be_bytes([data[2], data[3]]),
+            seq: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            ack: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            flags: data[13],
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss,
+            data: &data[header_len..],
+        })
+    }
+
+    /// SYN と FIN もシーケンス番号を1つ消費する
+    fn seq_len(&self) -> u32 {
+        let mut len = self.data.len() as u32;
+        if self.flags & FLAG_SYN != 0 {
+            len += 1;
+        }
+        if self.flags & FLAG_FIN != 0 {
+            len += 1;
+        }
+        len
+    }
+}
+
+/// 接続ごとの制御ブロック (TCB)
+struct Tcb {
+    state: State,
+    local_port: Option<u16>,
+    remote: Option<(Ipv4Addr, u16)>,
+
+    iss: u32,
+    /// 確認応答されていない最古のシーケンス番号
+    snd_una: u32,
+    snd_nxt: u32,
+    /// 相手の受信ウィンドウ
+    snd_wnd: u32,
+    snd_mss: usize,
+    rcv_nxt: u32,
+
+    /// 先頭が snd_una に対応する、未確認 + 未送信のデータ
+    send_buffer: VecDeque<u8>,
+    recv_buffer: VecDeque<u8>,
+
+    /// close 済みで、データを送り終えたら FIN を送る
+    fin_queued: bool,
+    fin_sent: bool,
+    /// 相手から FIN を受け取った
+    remote_closed: bool,
+
+    retransmit_at: Option<usize>,
+    rto: usize,
+    retries: usize,
+    time_wait_until: usize,
+
+    /// LISTEN: 確立して accept を待っている接続
+    backlog: VecDeque<Arc<TcpSocket>>,
+    backlog_max: usize,
+    /// SYN_RECEIVED: 確立したら backlog に入れる先
+    parent: Option<Weak<TcpSocket>>,
+
+    error: Option<&'static str>,
+}
+
+impl Tcb {
+    fn new() -> Self {
+        Self {
+            state: State::Closed,
+            local_port: None,
+            remote: None,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            snd_mss: MSS,
+            rcv_nxt: 0,
+            send_buffer: VecDeque::new(),
+            recv_buffer: VecDeque::new(),
+            fin_queued: false,
+            fin_sent: false,
+            remote_closed: false,
+            retransmit_at: None,
+            rto: INITIAL_RTO_MS,
+            retries: 0,
+            time_wait_until: 0,
+            backlog: VecDeque::new(),
+            backlog_max: 0,
+            parent: None,
+            error: None,
+        }
+    }
+
+    fn key(&self) -> Option<ConnectionKey> {
+        let (ip, port) = self.remote?;
+        Some((self.local_port?, ip, port))
+    }
+
+    fn recv_window(&self) -> u16 {
+        (BUFFER_SIZE - self.recv_buffer.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn segment(&self, seq: u32, flags: u8, data: Vec<u8>) -> Segment {
+        let (dst, dst_port) = self.remote.unwrap();
+        Segment {
+            dst,
+            src_port: self.local_port.unwrap(),
+            dst_port,
+            seq,
+            ack: if flags & FLAG_ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: self.recv_window(),
+            data,
+        }
+    }
+
+    fn ack_segment(&self) -> Segment {
+        self.segment(self.snd_nxt, FLAG_ACK, Vec::new())
+    }
+
+    fn arm_retransmit(&mut self) {
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(timer::get_uptime_ms() + self.rto);
+            timer::schedule(self.rto, timer_expired, 0);
+        }
+    }
+
+    /// ウィンドウの許す範囲で未送信のデータと FIN を送る
+    fn output(&mut self, out: &mut Vec<Segment>) {
+        match self.state {
+            State::SynSent => {
+                if self.snd_nxt == self.iss {
+                    out.push(self.segment(self.iss, FLAG_SYN, Vec::new()));
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                }
+                self.arm_retransmit();
+                return;
+            }
+            State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    out.push(self.segment(self.iss, FLAG_SYN | FLAG_ACK, Vec::new()));
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                }
+                self.arm_retransmit();
+                return;
+            }
+            State::Established | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck => {}
+            _ => return,
+        }
+
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.send_buffer.len().saturating_sub(in_flight);
+            let window = (self.snd_wnd as usize).saturating_sub(in_flight);
+            let len = unsent.min(window).min(self.snd_mss);
+            if len == 0 {
+                break;
+            }
+            let data: Vec<u8> = self.send_buffer.iter().skip(in_flight).take(len).copied().collect();
+            out.push(self.segment(self.snd_nxt, FLAG_ACK | FLAG_PSH, data));
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+
+        let all_sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize >= self.send_buffer.len();
+        if self.fin_queued && !self.fin_sent && all_sent {
+            out.push(self.segment(self.snd_nxt, FLAG_FIN | FLAG_ACK, Vec::new()));
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+        }
+
+        if self.snd_nxt != self.snd_una {
+            self.arm_retransmit();
+        }
+    }
+
+    fn fin_acked(&self) -> bool {
+        self.fin_sent && self.snd_una == self.snd_nxt
+    }
+
+    fn enter_time_wait(&mut self) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.time_wait_until = timer::get_uptime_ms() + TIME_WAIT_MS;
+        timer::schedule(TIME_WAIT_MS, timer_expired, 0);
+    }
+
+    fn reset(&mut self, error: &'static str) {
+        self.state = State::Closed;
+        self.error = Some(error);
+        self.retransmit_at = None;
+    }
+
+    /// 確立済み (SYN_SENT / LISTEN 以外) の接続に届いたセグメントを処理する
+    fn process(&mut self, seg: &IncomingSegment, out: &mut Vec<Segment>, events: &mut Events) {
+        if self.state == State::SynSent {
+            self.process_syn_sent(seg, out);
+            return;
+        }
+
+        if seg.flags & FLAG_RST != 0 {
+            self.reset("Connection reset by peer");
+            return;
+        }
+
+        // 重複した SYN には SYN-ACK を送り直す
+        if seg.flags & FLAG_SYN != 0 {
+            if self.state == State::SynReceived {
+                self.snd_nxt = self.iss;
+                self.output(out);
+            } else {
+                out.push(self.ack_segment());
+            }
+            return;
+        }
+        if seg.flags & FLAG_ACK == 0 {
+            return;
+        }
+
+        // ACK の処理
+        if self.state == State::SynReceived {
+            if seg.ack != self.iss.wrapping_add(1) {
+                return;
+            }
+            self.state = State::Established;
+            events.established = true;
+        }
+        if seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+            let mut acked = seg.ack.wrapping_sub(self.snd_una) as usize;
+            // SYN と FIN の分はバッファにない
+            if self.snd_una == self.iss {
+                acked -= 1;
+            }
+            let data_acked = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data_acked);
+            self.snd_una = seg.ack;
+            self.rto = INITIAL_RTO_MS;
+            self.retries = 0;
+            self.retransmit_at = None;
+            if self.snd_nxt != self.snd_una {
+                self.arm_retransmit();
+            }
+        }
+        self.snd_wnd = seg.window as u32;
+
+        if self.fin_acked() {
+            match self.state {
+                State::FinWait1 => self.state = State::FinWait2,
+                State::Closing => self.enter_time_wait(),
+                State::LastAck => {
+                    self.state = State::Closed;
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        // データの処理 (順序どおりのものだけ受け取り、先の分は捨てて再送させる)
+        let mut need_ack = false;
+        let mut data = seg.data;
+        let mut seq = seg.seq;
+        if seq_lt(seq, self.rcv_nxt) {
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            data = &data[skip.min(data.len())..];
+            seq = seq.wrapping_add(skip as u32);
+            need_ack = true;
+        }
+        if !data.is_empty() {
+            need_ack = true;
+            let receiving = matches!(self.state, State::Established | State::FinWait1 | State::FinWait2);
+            if seq == self.rcv_nxt && receiving {
+                let accepted = data.len().min(BUFFER_SIZE - self.recv_buffer.len());
+                self.recv_buffer.extend(&data[..accepted]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+            }
+        }
+
+        // データをすべて受け取れた場合だけ FIN を受理する
+        if seg.flags & FLAG_FIN != 0
+            && seg.seq.wrapping_add(seg.data.len() as u32) == self.rcv_nxt
+            && !self.remote_closed
+        {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.remote_closed = true;
+            need_ack = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 => {
+                    if self.fin_acked() {
+                        self.enter_time_wait();
+                    } else {
+                        self.state = State::Closing;
+                    }
+                }
+                State::FinWait2 => self.enter_time_wait(),
+                _ => {}
+            }
+        } else if seg.flags & FLAG_FIN != 0 && self.state == State::TimeWait {
+            // 相手が ACK を受け取れず FIN を再送してきた
+            need_ack = true;
+        }
+
+        if need_ack {
+            out.push(self.ack_segment());
+        }
+        self.output(out);
+    }
+
+    fn process_syn_sent(&mut self, seg: &IncomingSegment, out: &mut Vec<Segment>) {
+        let ack_ok = seg.flags & FLAG_ACK != 0 && seg.ack == self.iss.wrapping_add(1);
+        if seg.flags & FLAG_RST != 0 {
+            if ack_ok {
+                self.reset("Connection refused");
+            }
+            return;
+        }
+        if seg.flags & FLAG_SYN == 0 || !ack_ok {
+            return;
+        }
+
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.snd_una = seg.ack;
+        self.snd_wnd = seg.window as u32;
+        if let Some(mss) = seg.mss {
+            self.snd_mss = (mss as usize).min(MSS);
+        }
+        self.state = State::Established;
+        self.retransmit_at = None;
+        self.rto = INITIAL_RTO_MS;
+        self.retries = 0;
+        out.push(self.ack_segment());
+    }
+
+    /// 再送タイマーが切れた
+    fn on_timeout(&mut self, out: &mut Vec<Segment>) {
+        self.retransmit_at = None;
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.reset("Connection timed out");
+            return;
+        }
+        self.rto = (self.rto * 2).min(MAX_RTO_MS);
+
+        // 未確認の位置から送り直す (go-back-N)
+        self.snd_nxt = self.snd_una;
+        if self.fin_sent && !self.fin_acked() {
+            self.fin_sent = false;
+        }
+        self.output(out);
+    }
+}
+
+/// セグメント処理の結果、ロックを離してから行うこと
+#[derive(Default)]
+struct Events {
+    established: bool,
+}
+
+pub struct TcpSocket {
+    tcb: Mutex<Tcb>,
+    /// 状態やバッファが変わるたびに起こす
+    events: WaitQueue,
+}
+
+fn port_in_use(port: u16) -> bool {
+    LISTENERS.lock().contains_key(&port)
+        || CONNECTIONS.lock().keys().any(|(local, _, _)| *local == port)
+}
+
+/// 初期シーケンス番号 (時刻から作る)
+fn initial_sequence() -> u32 {
+    crate::rand::next_u32()
+}
+
+impl TcpSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            tcb: Mutex::new(Tcb::new()),
+            events: WaitQueue::new(),
+        })
+    }
+
+    pub fn state(&self) -> State {
+        self.tcb.lock().state
+    }
+
+    pub fn peer(&self) -> Option<(Ipv4Addr, u16)> {
+        self.tcb.lock().remote
+    }
+
+    pub fn bind(&self, port: u16) -> Result<(), &'static str> {
+        let mut tcb = self.tcb.lock();
+        if tcb.local_port.is_some() || tcb.state != State::Closed {
+            return Err("Socket already bound");
+        }
+        let port = if port == 0 {
+            (EPHEMERAL_START..=u16::MAX).find(|&port| !port_in_use(port)).ok_or("No free ports")?
+        } else if port_in_use(port) {
+            return Err("Address in use");
+        } else {
+            port
+        };
+        tcb.local_port = Some(port);
+        Ok(())
+    }
+
+    /// 接続を待ち受ける (事前に bind が必要)
+    pub fn listen(self: &Arc<Self>, backlog: usize) -> Result<(), &'static str> {
+        let port = {
+            let mut tcb = self.tcb.lock();
+            match tcb.state {
+                State::Listen => return Ok(()),
+                State::Closed => {}
+                _ => return Err("Socket already connected"),
+            }
+            let port = tcb.local_port.ok_or("Socket not bound")?;
+            tcb.state = State::Listen;
+            tcb.backlog_max = backlog.clamp(1, MAX_BACKLOG);
+            port
+        };
+        LISTENERS.lock().insert(port, self.clone());
+        Ok(())
+    }
+
+    /// 3ウェイハンドシェイクを行い、確立するか失敗するまでブロックする
+    pub fn connect(self: &Arc<Self>, ip: Ipv4Addr, port: u16) -> Result<(), &'static str> {
+        if self.tcb.lock().local_port.is_none() {
+            self.bind(0)?;
+        }
+
+        let mut out = Vec::new();
+        {
+            let mut tcb = self.tcb.lock();
+            if tcb.state != State::Closed {
+                return Err("Socket already connected");
+            }
+            tcb.remote = Some((ip, port));
+            tcb.iss = initial_sequence();
+            tcb.snd_una = tcb.iss;
+            tcb.snd_nxt = tcb.iss;
+            tcb.state = State::SynSent;
+            tcb.error = None;
+            CONNECTIONS.lock().insert(tcb.key().unwrap(), self.clone());
+            tcb.output(&mut out);
+        }
+        transmit_all(out);
+
+        self.events.wait_until(|| self.tcb.lock().state != State::SynSent);
+
+        let tcb = self.tcb.lock();
+        match tcb.state {
+            State::Established | State::CloseWait => Ok(()),
+            _ => Err(tcb.error.unwrap_or("Connection failed")),
+        }
+    }
+
+    /// 確立した接続が来るまでブロックする
+    pub fn accept(&self) -> Result<Arc<TcpSocket>, &'static str> {
+        let mut accepted = None;
+        self.events.wait_until(|| {
+            let mut tcb = self.tcb.lock();
+            accepted = tcb.backlog.pop_front();
+            accepted.is_some() || tcb.state != State::Listen
+        });
+        accepted.ok_or("Socket not listening")
+    }
+
+    /// 受信データを読む。相手が閉じていてデータがなければ 0 を返す
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.events.wait_until(|| {
+            let tcb = self.tcb.lock();
+            !tcb.recv_buffer.is_empty() || tcb.remote_closed
+                || matches!(tcb.state, State::Closed | State::Listen)
+        });
+
+        let mut out = Vec::new();
+        let count = {
+            let mut tcb = self.tcb.lock();
+            if tcb.recv_buffer.is_empty() {
+                if let Some(error) = tcb.error {
+                    return Err(error);
+                }
+                if tcb.state == State::Listen || (tcb.state == State::Closed && tcb.remote.is_none()) {
+                    return Err("Socket not connected");
+                }
+                return Ok(0);
+            }
+            let was_small = (tcb.recv_window() as usize) < MSS;
+            let count = buf.len().min(tcb.recv_buffer.len());
+            for (dst, byte) in buf.iter_mut().zip(tcb.recv_buffer.drain(..count)) {
+                *dst = byte;
+            }
+            // ウィンドウが小さくなっていたら空いたことを知らせる
+            if was_small && tcb.state != State::Closed {
+                out.push(tcb.ack_segment());
+            }
+            count
+        };
+        transmit_all(out);
+        Ok(count)
+    }
+
+    /// すべて送信バッファに入れ終わるまでブロックする
+    pub fn write(&self, buf: &[u8]) -> Result<usize, &'static str> {
+        let mut written = 0;
+        while written < buf.len() {
+            self.events.wait_until(|| {
+                let tcb = self.tcb.lock();
+                tcb.send_buffer.len() < BUFFER_SIZE
+                    || !matches!(tcb.state, State::Established | State::CloseWait)
+            });
+
+            let mut out = Vec::new();
+            {
+                let mut tcb = self.tcb.lock();
+                if !matches!(tcb.state, State::Established | State::CloseWait) || tcb.fin_queued {
+                    return Err(tcb.error.unwrap_or("Broken pipe"));
+                }
+                let count = (buf.len() - written).min(BUFFER_SIZE - tcb.send_buffer.len());
+                tcb.send_buffer.extend(&buf[written..written + count]);
+                written += count;
+                tcb.output(&mut out);
+            }
+            transmit_all(out);
+        }
+        Ok(written)
+    }
+
+    /// 読めるデータや accept できる接続があれば POLLIN、送信バッファに空きがあれば POLLOUT
+    /// 相手が閉じていれば POLLHUP、エラーで切断されていれば POLLERR
+    pub fn poll<'a>(&'a self, table: &mut PollTable<'a>) -> i16 {
+        table.register(&self.events);
+        let tcb = self.tcb.lock();
+        let mut events = 0;
+        if tcb.state == State::Listen {
+            return if tcb.backlog.is_empty() { 0 } else { POLLIN };
+        }
+        if !tcb.recv_buffer.is_empty() || tcb.remote_closed {
+            events |= POLLIN;
+        }
+        if matches!(tcb.state, State::Established | State::CloseWait)
+            && !tcb.fin_queued && tcb.send_buffer.len() < BUFFER_SIZE {
+            events |= POLLOUT;
+        }
+        if tcb.remote_closed || (tcb.state == State::Closed && tcb.remote.is_some()) {
+            events |= POLLHUP;
+        }
+        if tcb.error.is_some() {
+            events |= POLLERR;
+        }
+        events
+    }
+
+    /// 送信側を閉じる (データを送り終えたら FIN を送る)
+    pub fn close(&self) {
+        let mut out = Vec::new();
+        let mut orphans = VecDeque::new();
+        {
+            let mut tcb = self.tcb.lock();
+            match tcb.state {
+                State::Listen => {
+                    if let Some(port) = tcb.local_port {
+                        LISTENERS.lock().remove(&port);
+                    }
+                    orphans = core::mem::take(&mut tcb.backlog);
+                    tcb.state = State::Closed;
+                }
+                State::SynSent => {
+                    tcb.state = State::Closed;
+                    tcb.retransmit_at = None;
+                }
+                State::SynReceived | State::Established => {
+                    tcb.fin_queued = true;
+                    tcb.state = State::FinWait1;
+                    tcb.output(&mut out);
+                }
+                State::CloseWait => {
+                    tcb.fin_queued = true;
+                    tcb.state = State::LastAck;
+                    tcb.output(&mut out);
+                }
+                _ => {}
+            }
+        }
+        transmit_all(out);
+        self.reap();
+        self.events.wake_all();
+
+        // accept されなかった接続も閉じる
+        for socket in orphans {
+            socket.close();
+        }
+    }
+
+    /// CLOSED になった接続を表から外す
+    fn reap(&self) {
+        let key = {
+            let tcb = self.tcb.lock();
+            if tcb.state != State::Closed {
+                return;
+            }
+            tcb.key()
+        };
+        if let Some(key) = key {
+            let mut connections = CONNECTIONS.lock();
+            if connections.get(&key).map_or(false, |socket| core::ptr::eq(&**socket, self)) {
+                connections.remove(&key);
+            }
+        }
+    }
+
+    fn handle_segment(self: &Arc<Self>, seg: &IncomingSegment) {
+        let mut out = Vec::new();
+        let mut events = Events::default();
+        let parent = {
+            let mut tcb = self.tcb.lock();
+            tcb.process(seg, &mut out, &mut events);
+            tcb.parent.clone()
+        };
+        transmit_all(out);
+        self.reap();
+
+        if events.established {
+            let queued = match parent.and_then(|parent| parent.upgrade()) {
+                Some(parent) => {
+                    let mut tcb = parent.tcb.lock();
+                    let listening = tcb.state == State::Listen;
+                    if listening {
+                        tcb.backlog.push_back(self.clone());
+                    }
+                    drop(tcb);
+                    parent.events.wake_all();
+                    listening
+                }
+                None => false,
+            };
+            // 待ち受けが閉じられていたら誰も accept しない
+            if !queued {
+                self.close();
+            }
+        }
+        self.events.wake_all();
+    }
+
+    fn tick(&self, now: usize) {
+        let mut out = Vec::new();
+        {
+            let mut tcb = self.tcb.lock();
+            if tcb.state == State::TimeWait {
+                if now >= tcb.time_wait_until {
+                    tcb.state = State::Closed;
+                }
+            } else if tcb.retransmit_at.map_or(false, |at| now >= at) {
+                tcb.on_timeout(&mut out);
+            } else {
+                return;
+            }
+        }
+        transmit_all(out);
+        self.reap();
+        self.events.wake_all();
+    }
+}
+
+/// 接続のない宛先に RST を返す
+fn send_reset(src: Ipv4Addr, seg: &IncomingSegment) {
+    if seg.flags & FLAG_RST != 0 {
+        return;
+    }
+    let (seq, ack, flags) = if seg.flags & FLAG_ACK != 0 {
+        (seg.ack, 0, FLAG_RST)
+    } else {
+        (0, seg.seq.wrapping_add(seg.seq_len()), FLAG_RST | FLAG_ACK)
+    };
+    Segment {
+        dst: src,
+        src_port: seg.dst_port,
+        dst_port: seg.src_port,
+        seq,
+        ack,
+        flags,
+        window: 0,
+        data: Vec::new(),
+    }.transmit();
+}
+
+/// LISTEN 中のソケットに SYN が来たら SYN_RECEIVED の子を作る
+fn handle_syn(listener: &Arc<TcpSocket>, src: Ipv4Addr, seg: &IncomingSegment) {
+    // ロックの順序は TCB → 接続表なので、表のロックを持ったまま TCB を見ない
+    let sockets: Vec<Arc<TcpSocket>> = CONNECTIONS.lock().values().cloned().collect();
+    let pending = sockets.iter()
+        .filter(|socket| {
+            let tcb = socket.tcb.lock();
+            tcb.state == State::SynReceived && tcb.local_port == Some(seg.dst_port)
+        })
+        .count();
+    {
+        let tcb = listener.tcb.lock();
+        if tcb.state != State::Listen || tcb.backlog.len() + pending >= tcb.backlog_max {
+            return;
+        }
+    }
+
+    let child = TcpSocket::new();
+    let mut out = Vec::new();
+    {
+        let mut tcb = child.tcb.lock();
+        tcb.local_port = Some(seg.dst_port);
+        tcb.remote = Some((src, seg.src_port));
+        tcb.parent = Some(Arc::downgrade(listener));
+        tcb.iss = initial_sequence();
+        tcb.snd_una = tcb.iss;
+        tcb.snd_nxt = tcb.iss;
+        tcb.snd_wnd = seg.window as u32;
+        if let Some(mss) = seg.mss {
+            tcb.snd_mss = (mss as usize).min(MSS);
+        }
+        tcb.rcv_nxt = seg.seq.wrapping_add(1);
+        tcb.state = State::SynReceived;
+        CONNECTIONS.lock().insert(tcb.key().unwrap(), child.clone());
+        tcb.output(&mut out);
+    }
+    transmit_all(out);
+}
+
+/// 受信した TCP セグメントを接続に振り分ける
+pub fn handle_packet(packet: &Ipv4Packet) {
+    let seg = match IncomingSegment::parse(packet) {
+        Some(seg) => seg,
+        None => return,
+    };
+
+    let connection = CONNECTIONS.lock().get(&(seg.dst_port, packet.src, seg.src_port)).cloned();
+    if let Some(socket) = connection {
+        socket.handle_segment(&seg);
+        return;
+    }
+
+    let listener = LISTENERS.lock().get(&seg.dst_port).cloned();
+    match listener {
+        Some(listener) if seg.flags & (FLAG_SYN | FLAG_ACK | FLAG_RST) == FLAG_SYN => {
+            handle_syn(&listener, packet.src, &seg);
+        }
+        _ => send_reset(packet.src, &seg),
+    }
+}
+
+/// カーネルタイマーのコールバック (割り込み出口で呼ばれるので送信はスレッドに任せる)
+/// 再送を取りやめた後に切れることもあるが、スレッド側で期限を確かめ直す
+fn timer_expired(_: usize) {
+    TIMER_EXPIRED.store(true, Ordering::SeqCst);
+    TIMER_EVENTS.wake_all();
+}
+
+/// タイマーが切れるたびに再送と TIME_WAIT の期限を確認するスレッド
+fn timer_thread() {
+    loop {
+        TIMER_EVENTS.wait_until(|| TIMER_EXPIRED.swap(false, Ordering::SeqCst));
+        let now = timer::get_uptime_ms();
+        let sockets: Vec<Arc<TcpSocket>> = CONNECTIONS.lock().values().cloned().collect();
+        for socket in sockets {
+            socket.tick(now);
+        }
+    }
+}
+
+pub fn init() {
+    crate::kthread::spawn("tcp-timer", timer_thread);
+}
+
+/// 接続の一覧を表示する (netstat)
+pub fn print_connections() {
+    crate::println!("TCP connections:");
+    for (port, _) in LISTENERS.lock().iter() {
+        crate::println!("  0.0.0.0:{:<5} {:>21} LISTEN", port, "*");
+    }
+    let connections: Vec<(ConnectionKey, Arc<TcpSocket>)> = CONNECTIONS.lock()
+        .iter()
+        .map(|(key, socket)| (*key, socket.clone()))
+        .collect();
+    for ((local, ip, port), socket) in connections {
+        crate::println!("  :{:<5} {:>15}:{:<5} {:?}", local, ip, port, socket.state());
+    }
+}
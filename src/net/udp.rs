@@ -0,0 +1,186 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::sync::WaitQueue;
+use crate::poll::{PollTable, POLLIN, POLLOUT};
+use super::ipv4::{self, Ipv4Packet, PROTOCOL_UDP};
+use super::Ipv4Addr;
+
+/// 送信元ポート + 宛先ポート + 長さ + チェックサム
+const HEADER_SIZE: usize = 8;
+pub const MAX_PAYLOAD: usize = ipv4::MAX_PAYLOAD - HEADER_SIZE;
+/// 読まれずに溜まったデータグラムの上限 (超えたら捨てる)
+const MAX_QUEUED: usize = 64;
+/// bind せずに送信したソケットに割り当てるポートの範囲
+const EPHEMERAL_START: u16 = 49152;
+
+// ポート番号 → ソケット (ソケットが閉じられたら Drop で取り除く)
+static PORTS: Mutex<BTreeMap<u16, Weak<UdpSocket>>> = Mutex::new(BTreeMap::new());
+
+/// 受信したデータグラム
+pub struct Datagram {
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    pub data: Vec<u8>,
+}
+
+pub struct UdpSocket {
+    port: Mutex<Option<u16>>,
+    queue: Mutex<VecDeque<Datagram>>,
+    readable: WaitQueue,
+}
+
+impl UdpSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            port: Mutex::new(None),
+            queue: Mutex::new(VecDeque::new()),
+            readable: WaitQueue::new(),
+        })
+    }
+
+    pub fn local_port(&self) -> Option<u16> {
+        *self.port.lock()
+    }
+
+    /// ポートを割り当てる (0 なら空いているエフェメラルポート)
+    pub fn bind(self: &Arc<Self>, port: u16) -> Result<u16, &'static str> {
+        let mut bound = self.port.lock();
+        if bound.is_some() {
+            return Err("Socket already bound");
+        }
+
+        let mut ports = PORTS.lock();
+        let in_use = |ports: &BTreeMap<u16, Weak<UdpSocket>>, port: u16| {
+            ports.get(&port).map_or(false, |socket| socket.strong_count() > 0)
+        };
+        let port = if port == 0 {
+            (EPHEMERAL_START..=u16::MAX)
+                .find(|&port| !in_use(&ports, port))
+                .ok_or("No free ports")?
+        } else if in_use(&ports, port) {
+            return Err("Address in use");
+        } else {
+            port
+        };
+
+        ports.insert(port, Arc::downgrade(self));
+        *bound = Some(port);
+        Ok(port)
+    }
+
+    pub fn send_to(self: &Arc<Self>, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Result<usize, &'static str> {
+        if data.len() > MAX_PAYLOAD {
+            return Err("Datagram too large");
+        }
+        let src_port = match self.local_port() {
+            Some(port) => port,
+            None => self.bind(0)?,
+        };
+
+        let len = HEADER_SIZE + data.len();
+        let mut segment = Vec::with_capacity(len);
+        segment.extend_from_slice(&src_port.to_be_bytes());
+        segment.extend_from_slice(&dst_port.to_be_bytes());
+        segment.extend_from_slice(&(len as u16).to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(data);
+
+        let sum = ipv4::pseudo_header_sum(super::ip_address(), dst, PROTOCOL_UDP, len);
+        let checksum = match ipv4::checksum_finish(ipv4::checksum_add(sum, &segment)) {
+            // 0 は「チェックサムなし」を意味するので全ビット1で送る
+            0 => 0xFFFF,
+            checksum => checksum,
+        };
+        segment[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+        ipv4::send(dst, PROTOCOL_UDP, &segment)?;
+        Ok(data.len())
+    }
+
+    pub fn try_recv(&self) -> Option<Datagram> {
+        self.queue.lock().pop_front()
+    }
+
+    /// timeout_ms だけ待っても届かなければ None を返す
+    pub fn recv_timeout(&self, timeout_ms: usize) -> Option<Datagram> {
+        let deadline = crate::drivers::timer::get_uptime_ms() + timeout_ms;
+        loop {
+            if let Some(datagram) = self.try_recv() {
+                return Some(datagram);
+            }
+            if crate::drivers::timer::get_uptime_ms() >= deadline {
+                return None;
+            }
+            crate::sync::wait_for_interrupt();
+        }
+    }
+
+    /// データグラムが届くまでブロックする
+    pub fn recv_from(&self) -> Datagram {
+        let mut datagram = None;
+        self.readable.wait_until(|| {
+            datagram = self.queue.lock().pop_front();
+            datagram.is_some()
+        });
+        datagram.unwrap()
+    }
+
+    /// データグラムが届いていれば POLLIN (送信はいつでもできる)
+    pub fn poll<'a>(&'a self, table: &mut PollTable<'a>) -> i16 {
+        table.register(&self.readable);
+        if self.queue.lock().is_empty() { POLLOUT } else { POLLIN | POLLOUT }
+    }
+
+    fn deliver(&self, datagram: Datagram) {
+        {
+            let mut queue = self.queue.lock();
+            if queue.len() >= MAX_QUEUED {
+                return;
+            }
+            queue.push_back(datagram);
+        }
+        self.readable.wake_all();
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(port) = *self.port.lock() {
+            PORTS.lock().remove(&port);
+        }
+    }
+}
+
+/// 受信した UDP データグラムを宛先ポートのソケットに渡す
+pub fn handle_packet(packet: &Ipv4Packet) {
+    let data = packet.payload;
+    if data.len() < HEADER_SIZE {
+        return;
+    }
+    let src_port = u16::from_be_bytes([data[0], data[1]]);
+    let dst_port = u16::from_be_bytes([data[2], data[3]]);
+    let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let checksum = u16::from_be_bytes([data[6], data[7]]);
+    if len < HEADER_SIZE || len > data.len() {
+        return;
+    }
+    let data = &data[..len];
+    if checksum != 0 {
+        let sum = ipv4::pseudo_header_sum(packet.src, packet.dst, PROTOCOL_UDP, len);
+        if ipv4::checksum_finish(ipv4::checksum_add(sum, data)) != 0 {
+            return;
+        }
+    }
+
+    let socket = PORTS.lock().get(&dst_port).and_then(|socket| socket.upgrade());
+    match socket {
+        Some(socket) => socket.deliver(Datagram {
+            src: packet.src,
+            src_port,
+            data: data[HEADER_SIZE..].to_vec(),
+        }),
+        None => crate::trace!("UDP datagram to closed port {} from {}", dst_port, packet.src),
+    }
+}
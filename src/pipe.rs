@@ -0,0 +1,189 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+use crate::sync::WaitQueue;
+use crate::errno::Errno;
+use crate::poll::{PollTable, POLLERR, POLLHUP, POLLIN, POLLOUT};
+
+/// パイプのリングバッファ容量
+pub const PIPE_BUF_SIZE: usize = 4096;
+
+struct PipeBuffer {
+    data: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+/// カーネル内パイプオブジェクト
+/// 読み込み端・書き込み端はそれぞれ PipeReader / PipeWriter が保持し、
+/// すべての端が閉じられると EOF / EPIPE になる
+pub struct Pipe {
+    inner: Mutex<PipeBuffer>,
+    /// データが来るのを待つ読み手
+    readable: WaitQueue,
+    /// 空きができるのを待つ書き手
+    writable: WaitQueue,
+}
+
+impl Pipe {
+    fn new() -> Self {
+        Self {
+            inner: Mutex::new(PipeBuffer {
+                data: VecDeque::with_capacity(PIPE_BUF_SIZE),
+                readers: 0,
+                writers: 0,
+            }),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        }
+    }
+}
+
+/// 新しいパイプを作成し、読み込み端と書き込み端を返す
+pub fn create() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe::new());
+    (PipeReader::new(pipe.clone()), PipeWriter::new(pipe))
+}
+
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+impl PipeReader {
+    fn new(pipe: Arc<Pipe>) -> Self {
+        pipe.inner.lock().readers += 1;
+        Self { pipe }
+    }
+
+    /// データが来るまでブロックする
+    /// 書き込み端がすべて閉じられていて空なら 0 (EOF) を返す
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.pipe.readable.wait_until(|| {
+            let inner = self.pipe.inner.lock();
+            !inner.data.is_empty() || inner.writers == 0
+        });
+
+        let count = {
+            let mut inner = self.pipe.inner.lock();
+            let mut count = 0;
+            while count < buf.len() {
+                match inner.data.pop_front() {
+                    Some(byte) => {
+                        buf[count] = byte;
+                        count += 1;
+                    }
+                    None => break,
+                }
+            }
+            count
+        };
+
+        // 空きができたので書き手を起こす (count == 0 なら EOF)
+        self.pipe.writable.wake_all();
+        Ok(count)
+    }
+
+    /// データがあれば POLLIN、書き込み端がすべて閉じられていれば POLLHUP
+    pub fn poll<'a>(&'a self, table: &mut PollTable<'a>) -> i16 {
+        table.register(&self.pipe.readable);
+        let inner = self.pipe.inner.lock();
+        let mut events = 0;
+        if !inner.data.is_empty() {
+            events |= POLLIN;
+        }
+        if inner.writers == 0 {
+            events |= POLLHUP;
+        }
+        events
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.inner.lock().readers -= 1;
+        // 待っている書き手に EPIPE を返させる
+        self.pipe.writable.wake_all();
+    }
+}
+
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+impl PipeWriter {
+    fn new(pipe: Arc<Pipe>) -> Self {
+        pipe.inner.lock().writers += 1;
+        Self { pipe }
+    }
+
+    /// バッファに空きができるまでブロックしながらすべて書き込む
+    /// 読み込み端がすべて閉じられていれば EPIPE を返す
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            self.pipe.writable.wait_until(|| {
+                let inner = self.pipe.inner.lock();
+                inner.data.len() < PIPE_BUF_SIZE || inner.readers == 0
+            });
+
+            {
+                let mut inner = self.pipe.inner.lock();
+                if inner.readers == 0 {
+                    return Err(Errno::EPIPE);
+                }
+                while written < buf.len() && inner.data.len() < PIPE_BUF_SIZE {
+                    inner.data.push_back(buf[written]);
+                    written += 1;
+                }
+            }
+            self.pipe.readable.wake_all();
+        }
+
+        Ok(written)
+    }
+
+    /// ブロックせず、空いている分だけ書き込む (O_NONBLOCK)
+    /// 空きがなければ EAGAIN、読み込み端がすべて閉じられていれば EPIPE を返す
+    pub fn try_write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let written = {
+            let mut inner = self.pipe.inner.lock();
+            if inner.readers == 0 {
+                return Err(Errno::EPIPE);
+            }
+            let count = core::cmp::min(buf.len(), PIPE_BUF_SIZE - inner.data.len());
+            if count == 0 && !buf.is_empty() {
+                return Err(Errno::EAGAIN);
+            }
+            inner.data.extend(&buf[..count]);
+            count
+        };
+        self.pipe.readable.wake_all();
+        Ok(written)
+    }
+
+    /// 空きがあれば POLLOUT、読み込み端がすべて閉じられていれば POLLERR
+    pub fn poll<'a>(&'a self, table: &mut PollTable<'a>) -> i16 {
+        table.register(&self.pipe.writable);
+        let inner = self.pipe.inner.lock();
+        if inner.readers == 0 {
+            POLLERR
+        } else if inner.data.len() < PIPE_BUF_SIZE {
+            POLLOUT
+        } else {
+            0
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.inner.lock().writers -= 1;
+        // 待っている読み手に EOF を返させる
+        self.pipe.readable.wake_all();
+    }
+}
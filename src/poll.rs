@@ -0,0 +1,126 @@
+use alloc::vec::Vec;
+use crate::drivers::timer;
+use crate::errno::Errno;
+use crate::fd::FileRef;
+use crate::process;
+use crate::sync::WaitQueue;
+
+// poll のイベント (Linux と同じ値)
+pub const POLLIN: i16 = 0x001;
+pub const POLLPRI: i16 = 0x002;
+pub const POLLOUT: i16 = 0x004;
+pub const POLLERR: i16 = 0x008;
+pub const POLLHUP: i16 = 0x010;
+pub const POLLNVAL: i16 = 0x020;
+
+/// events に関係なく常に報告するイベント
+const ALWAYS: i16 = POLLERR | POLLHUP | POLLNVAL;
+
+/// ユーザーから渡される struct pollfd
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+/// 準備ができていないオブジェクトの待ち行列に現在のスレッドを登録しておく
+/// どれか1つでも起こされればブロックが解け、ドロップ時にすべての登録を外す
+pub struct PollTable<'a> {
+    /// 登録するスレッド (None なら登録しない)
+    tid: Option<usize>,
+    queues: Vec<&'a WaitQueue>,
+}
+
+impl<'a> PollTable<'a> {
+    pub fn new(tid: Option<usize>) -> Self {
+        Self { tid, queues: Vec::new() }
+    }
+
+    /// オブジェクトの poll から呼び、状態が変わったときに起こされるようにする
+    pub fn register(&mut self, queue: &'a WaitQueue) {
+        if let Some(tid) = self.tid {
+            if !self.queues.iter().any(|registered| core::ptr::eq(*registered, queue)) {
+                queue.add_waiter(tid);
+                self.queues.push(queue);
+            }
+        }
+    }
+}
+
+impl Drop for PollTable<'_> {
+    fn drop(&mut self) {
+        if let Some(tid) = self.tid {
+            for queue in &self.queues {
+                queue.remove_waiter(tid);
+            }
+        }
+    }
+}
+
+/// 各 fd の状態を調べて revents を埋め、イベントのあった fd の数を返す
+fn scan<'a>(fds: &mut [PollFd], files: &'a [Option<FileRef>], table: &mut PollTable<'a>) -> usize {
+    let mut ready = 0;
+    for (pollfd, file) in fds.iter_mut().zip(files) {
+        pollfd.revents = if pollfd.fd < 0 {
+            0
+        } else {
+            match file {
+                Some(file) => file.poll(table) & (pollfd.events | ALWAYS),
+                None => POLLNVAL,
+            }
+        };
+        if pollfd.revents != 0 {
+            ready += 1;
+        }
+    }
+    ready
+}
+
+/// どれかの fd でイベントが起きるか timeout_ms (負なら無期限) が過ぎるまで待つ
+pub fn poll(fds: &mut [PollFd], timeout_ms: i32) -> Result<usize, Errno> {
+    if fds.len() > crate::fd::MAX_FDS {
+        return Err(Errno::EINVAL);
+    }
+    let files: Vec<Option<FileRef>> = fds.iter()
+        .map(|pollfd| if pollfd.fd < 0 { None } else { crate::fd::get(pollfd.fd) })
+        .collect();
+    let deadline = (timeout_ms >= 0).then(|| timer::get_uptime_ms() + timeout_ms as usize);
+    let expired = || deadline.is_some_and(|deadline| timer::get_uptime_ms() >= deadline);
+    let tid = if timeout_ms == 0 { None } else { process::current_tid() };
+
+    loop {
+        let mut table = PollTable::new(tid);
+        let ready = scan(fds, &files, &mut table);
+        if ready > 0 || timeout_ms == 0 || expired() {
+            return Ok(ready);
+        }
+
+        let current = match tid {
+            Some(current) => current,
+            // プロセス外では割り込みごとに調べ直す
+            None => {
+                crate::sync::wait_for_interrupt();
+                continue;
+            }
+        };
+
+        // ブロックしてからもう一度調べ、登録から今までの間の起床を取りこぼさない
+        process::block_current();
+        // 期限が来たらタイマーに起こしてもらう
+        let timer = deadline.map(|deadline| {
+            let remaining = deadline.saturating_sub(timer::get_uptime_ms());
+            timer::schedule(remaining, timer::wake_process, current)
+        });
+        if scan(fds, &files, &mut PollTable::new(None)) == 0 {
+            while process::is_blocked(current) {
+                crate::sync::wait_for_interrupt();
+            }
+        }
+        process::resume(current);
+        if let Some(timer) = timer {
+            timer.cancel();
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+/// PM1 制御レジスタのビット
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_EN: u16 = 1 << 13;
+/// ACPI モードへの切り替えを待つ時間
+const ACPI_ENABLE_TIMEOUT_MS: usize = 300;
+
+/// 8042 のステータス/コマンドポートと、リセットパルスを出すコマンド
+const KBC_STATUS: u16 = 0x64;
+const KBC_RESET: u8 = 0xFE;
+/// リセットが効くのを待つ時間
+const RESET_WAIT_MS: usize = 50;
+
+/// QEMU (q35 / 新しい PIIX) と Bochs・古い QEMU の電源断ポート
+const QEMU_POWEROFF_PORT: u16 = 0x604;
+const BOCHS_POWEROFF_PORT: u16 = 0xB004;
+const POWEROFF_VALUE: u16 = 0x2000;
+
+/// 再起動する (8042 のリセット線 → トリプルフォールトの順に試す)
+pub fn reboot() -> ! {
+    crate::info!("Rebooting...");
+    x86_64::instructions::interrupts::disable();
+
+    pulse_reset_line();
+    crate::drivers::timer::busy_wait_ms(RESET_WAIT_MS);
+
+    // IDT を空にして例外を起こすとダブルフォールトも処理できずにリセットされる
+    let idt = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    unsafe {
+        x86_64::instructions::tables::lidt(&idt);
+        core::arch::asm!("int3", options(nomem, nostack));
+    }
+    halt_forever()
+}
+
+/// 電源を切る (ACPI S5 → QEMU/Bochs のポート → isa-debug-exit の順に試す)
+/// どれも効かなければ停止する
+pub fn power_off() -> ! {
+    crate::info!("Powering off...");
+    x86_64::instructions::interrupts::disable();
+
+    if let Err(e) = acpi_power_off() {
+        crate::warn!("ACPI poweroff failed: {}", e);
+    }
+    unsafe {
+        Port::<u16>::new(QEMU_POWEROFF_PORT).write(POWEROFF_VALUE);
+        Port::<u16>::new(BOCHS_POWEROFF_PORT).write(POWEROFF_VALUE);
+    }
+    crate::exit_qemu(crate::QemuExitCode::Success);
+
+    crate::warn!("Poweroff failed, halting");
+    halt_forever()
+}
+
+/// CPU を止める (割り込みも受け付けない)
+pub fn halt() -> ! {
+    crate::info!("System halted");
+    x86_64::instructions::interrupts::disable();
+    halt_forever()
+}
+
+fn halt_forever() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// キーボードコントローラ (8042) にリセットパルスを出させる
+/// 入力バッファが空くのを待ってから送る (コントローラがなければ待たずに諦める)
+fn pulse_reset_line() {
+    let mut status = Port::<u8>::new(KBC_STATUS);
+    unsafe {
+        for _ in 0..100_000 {
+            if status.read() & 0x02 == 0 {
+                status.write(KBC_RESET);
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// PM1 制御レジスタに SLP_TYP(S5) と SLP_EN を書く
+fn acpi_power_off() -> Result<(), &'static str> {
+    let sleep = crate::acpi::sleep_info().ok_or("no \\_S5 information")?;
+    let mut pm1a = Port::<u16>::new(sleep.pm1a_control);
+
+    // まだレガシーモードならファームウェアに ACPI モードへ切り替えてもらう
+    if unsafe { pm1a.read() } & SCI_EN == 0 {
+        if sleep.smi_cmd == 0 || sleep.acpi_enable == 0 {
+            return Err("ACPI mode cannot be enabled");
+        }
+        unsafe { Port::<u8>::new(sleep.smi_cmd).write(sleep.acpi_enable) };
+        let mut waited = 0;
+        while unsafe { pm1a.read() } & SCI_EN == 0 {
+            if waited >= ACPI_ENABLE_TIMEOUT_MS {
+                return Err("timed out enabling ACPI mode");
+            }
+            crate::drivers::timer::busy_wait_ms(1);
+            waited += 1;
+        }
+    }
+
+    unsafe {
+        pm1a.write((sleep.slp_typa << SLP_TYP_SHIFT) | SLP_EN);
+        if sleep.pm1b_control != 0 {
+            Port::<u16>::new(sleep.pm1b_control).write((sleep.slp_typb << SLP_TYP_SHIFT) | SLP_EN);
+        }
+    }
+    // 書いた直後に電源が落ちるはずなので、少し待っても生きていれば失敗
+    crate::drivers::timer::busy_wait_ms(RESET_WAIT_MS);
+    Err("system still running after S5")
+}
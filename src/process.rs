@@ -1,15 +1,42 @@
-use alloc::collections::VecDeque;
-
 use alloc::vec;
 use alloc::vec::Vec;
 
 use alloc::boxed::Box;
-use spin::Mutex;
+use alloc::string::String;
 use x86_64::VirtAddr;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::registers::model_specific::FsBase;
+use crate::memory::{Vma, VmaFile};
+use crate::fd::FdTable;
+use crate::rlimit::Limits;
+use crate::cred::Credentials;
+use crate::ioport::IoPermissions;
+use crate::fpu::FpuState;
+use crate::errno::Errno;
+use crate::sync::IrqMutex;
+use crate::kstack::{KernelStack, KERNEL_STACK_PAGES};
+use crate::sched::{CpuMask, Policy, SchedStats, Scheduler};
+use crate::smp::{cpu_id, MAX_CPUS};
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+/// 最も低い優先度 (数値が大きいほど優先度が低い、既定は 10)
+pub const IDLE_PRIORITY: u8 = u8::MAX;
+
+/// spawn_process が割り当てるユーザースタックの大きさ
+pub const USER_STACK_SIZE: u64 = 0x4000;
+
+// clone のフラグ (Linux と同じ値)
+/// アドレス空間を共有する
+pub const CLONE_VM: u64 = 0x0000_0100;
+/// ファイルディスクリプタテーブルを共有する
+pub const CLONE_FILES: u64 = 0x0000_0400;
+/// 新しいスレッドの FS ベースを tls 引数にする
+pub const CLONE_SETTLS: u64 = 0x0008_0000;
+
+// PID と TID は同じ番号空間から割り当てる
 static PID_COUNTER: AtomicUsize = AtomicUsize::new(1);
-static PROCESS_MANAGER: Mutex<Option<ProcessManager>> = Mutex::new(None);
+// タイマー割り込みのスケジューラからも取るので、持っている間は割り込みを止める
+static PROCESS_MANAGER: IrqMutex<Option<ProcessManager>> = IrqMutex::new(None);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProcessState {
@@ -67,163 +94,888 @@ impl Default for ProcessContext {
     }
 }
 
-pub struct Process {
+/// スケジューリングの単位 (レジスタ・カーネルスタック・状態を持つ)
+/// アドレス空間やファイルディスクリプタは所属するプロセスのものを共有する
+pub struct Thread {
+    pub tid: usize,
+    /// 所属するプロセスのPID (メインスレッドは tid == pid)
     pub pid: usize,
     pub state: ProcessState,
     pub context: ProcessContext,
-    pub kernel_stack: Vec<u8>,
+    pub kernel_stack: KernelStack,
+    /// カーネルが割り当てたユーザースタックのトップ (clone で作ったスレッドは呼び出し側が用意するので None)
     pub user_stack: Option<VirtAddr>,
-    pub page_table: Option<VirtAddr>,
     pub priority: u8,
     pub time_slice: usize,
+    /// 割り当て先 (最後に実行した) CPU
+    pub cpu: usize,
+    /// 実行してよい CPU (sched_setaffinity)
+    pub affinity: CpuMask,
+    /// 実行中に受けたタイマーティックの数 (CPU時間)
+    pub cpu_ticks: usize,
+    /// 作成時のタイマーティック
+    pub start_ticks: usize,
+    /// 最後に実行可能キューに積まれたときのスケジューラのティック (待ち時間の計測用)
+    pub ready_since: usize,
+    /// 自分からCPUを手放した回数 (ブロック)
+    pub voluntary_switches: usize,
+    /// 横取りされた回数 (プリエンプション)
+    pub involuntary_switches: usize,
+    /// TLS 用の FS ベース (arch_prctl(ARCH_SET_FS) か clone の tls で設定する)
+    pub fs_base: u64,
+    /// x87/SSE/AVX レジスタ
+    pub fpu: FpuState,
 }
 
-impl Process {
-    pub fn new(entry_point: u64) -> Self {
-        let pid = PID_COUNTER.fetch_add(1, Ordering::SeqCst);
-        let mut kernel_stack = vec![0u8; 8192]; // 8KB カーネルスタック
-        
+impl Thread {
+    fn new(tid: usize, pid: usize, entry_point: u64) -> Self {
+        let kernel_stack = KernelStack::new(tid, KERNEL_STACK_PAGES);
+
         let mut context = ProcessContext::default();
         context.rip = entry_point;
-        context.rsp = (kernel_stack.as_ptr() as u64) + 8192;
+        context.rsp = kernel_stack.top().as_u64();
         context.rbp = context.rsp;
 
         Self {
+            tid,
             pid,
             state: ProcessState::Ready,
             context,
             kernel_stack,
             user_stack: None,
-            page_table: None,
             priority: 10,
             time_slice: 10,
+            cpu: 0,
+            affinity: crate::sched::default_affinity(),
+            cpu_ticks: 0,
+            start_ticks: crate::drivers::timer::get_ticks(),
+            ready_since: 0,
+            voluntary_switches: 0,
+            involuntary_switches: 0,
+            fs_base: 0,
+            fpu: FpuState::new(),
         }
     }
 
+    /// CPUから外れるときに、汎用レジスタ以外の実行状態を保存する
+    /// FPU の状態は遅延させず毎回保存・復元する
+    fn save_state(&mut self) {
+        self.fs_base = FsBase::read().as_u64();
+        self.fpu.save();
+    }
+
+    /// このCPUで実行を始める前に、保存しておいた実行状態を戻す
+    fn restore_state(&self) {
+        FsBase::write(VirtAddr::new(self.fs_base));
+        self.fpu.restore();
+    }
+
+    /// リング3から割り込まれた際に使うカーネルスタックのトップ
+    pub fn kernel_stack_top(&self) -> VirtAddr {
+        self.kernel_stack.top()
+    }
+}
+
+/// アドレス空間・ファイルディスクリプタなど、スレッドが共有する資源を持つ
+pub struct Process {
+    pub pid: usize,
+    /// fork した親の PID (カーネルが作ったプロセスは 0)
+    pub ppid: usize,
+    /// fork で作った自分のページテーブル (None ならカーネルのページテーブルで動く)
+    pub page_table: Option<PhysFrame>,
+    pub vmas: Vec<Vma>,
+    pub fds: FdTable,
+    pub cwd: String,
+    /// デバッグ用の名前
+    pub name: String,
+    /// ユーザー領域にマップ済みのページ数 (デマンドページングで増える)
+    pub mapped_pages: usize,
+    /// システムコールをカーネルログにトレースする (strace)
+    pub trace_syscalls: bool,
+    /// ヒープ (brk 領域) の先頭
+    pub heap_start: VirtAddr,
+    /// 現在のプログラムブレーク
+    pub brk: VirtAddr,
+    /// リソース制限 (getrlimit/setrlimit)
+    pub limits: Limits,
+    /// ユーザーID・グループID
+    pub cred: Credentials,
+    /// I/O ポートへのアクセス権 (ioperm/iopl)
+    pub io: IoPermissions,
+    /// threads[0] がメインスレッド
+    pub threads: Vec<Thread>,
+}
+
+/// CPUごとの時間の内訳 (スケジューラのティック数、/proc/stat が使う)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuTime {
+    /// プロセスを実行していたティック
+    pub busy_ticks: usize,
+    /// アイドルタスクが hlt していたティック
+    pub idle_ticks: usize,
+}
+
+impl CpuTime {
+    pub fn total_ticks(&self) -> usize {
+        self.busy_ticks + self.idle_ticks
+    }
+
+    /// 使用率 (%)、まだティックがなければ 0
+    pub fn busy_percent(&self) -> usize {
+        match self.total_ticks() {
+            0 => 0,
+            total => self.busy_ticks * 100 / total,
+        }
+    }
+}
+
+/// ps / top 向けのプロセス情報 (snapshot が返す)
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: usize,
+    pub ppid: usize,
+    pub name: String,
+    pub state: ProcessState,
+    pub priority: u8,
+    pub cpu: usize,
+    /// メインスレッドの実行してよい CPU
+    pub affinity: CpuMask,
+    pub cpu_ticks: usize,
+    pub start_ticks: usize,
+    pub voluntary_switches: usize,
+    pub involuntary_switches: usize,
+    pub mapped_pages: usize,
+    pub uid: u32,
+    pub gid: u32,
+    /// 終了していないスレッドの数
+    pub threads: usize,
+}
+
+impl Process {
+    /// メインスレッドが entry_point から始まるプロセスを作る
+    pub fn new(entry_point: u64) -> Self {
+        let pid = PID_COUNTER.fetch_add(1, Ordering::SeqCst);
+
+        Self {
+            pid,
+            ppid: 0,
+            page_table: None,
+            vmas: Vec::new(),
+            fds: FdTable::with_std_streams(),
+            cwd: String::from("/"),
+            name: String::new(),
+            mapped_pages: 0,
+            trace_syscalls: false,
+            heap_start: VirtAddr::new(crate::memory::USER_HEAP_BASE),
+            brk: VirtAddr::new(crate::memory::USER_HEAP_BASE),
+            limits: Limits::new(),
+            cred: Credentials::ROOT,
+            io: IoPermissions::new(),
+            threads: vec![Thread::new(pid, pid, entry_point)],
+        }
+    }
+
+    /// スレッドの値をまとめたプロセス全体の情報
+    pub fn info(&self) -> ProcessInfo {
+        let main = self.main_thread();
+        let live = self.threads.iter().filter(|t| t.state != ProcessState::Terminated);
+        ProcessInfo {
+            pid: self.pid,
+            ppid: self.ppid,
+            name: self.name.clone(),
+            state: self.state(),
+            priority: main.priority,
+            cpu: main.cpu,
+            affinity: main.affinity,
+            cpu_ticks: self.cpu_ticks(),
+            start_ticks: main.start_ticks,
+            voluntary_switches: self.threads.iter().map(|t| t.voluntary_switches).sum(),
+            involuntary_switches: self.threads.iter().map(|t| t.involuntary_switches).sum(),
+            mapped_pages: self.mapped_pages,
+            uid: self.cred.uid,
+            gid: self.cred.gid,
+            threads: live.count(),
+        }
+    }
+
+    pub fn main_thread(&self) -> &Thread {
+        &self.threads[0]
+    }
+
+    pub fn main_thread_mut(&mut self) -> &mut Thread {
+        &mut self.threads[0]
+    }
+
+    pub fn thread_mut(&mut self, tid: usize) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|t| t.tid == tid)
+    }
+
+    /// スレッドの状態から見たプロセスの状態
+    /// どれかが動いていれば Running、全部終わっていれば Terminated
+    pub fn state(&self) -> ProcessState {
+        let states = || self.threads.iter().map(|t| t.state);
+        [ProcessState::Running, ProcessState::Ready, ProcessState::Blocked].into_iter()
+            .find(|&state| states().any(|s| s == state))
+            .unwrap_or(ProcessState::Terminated)
+    }
+
+    /// 全スレッドの CPU 時間 (ティック)
+    pub fn cpu_ticks(&self) -> usize {
+        self.threads.iter().map(|t| t.cpu_ticks).sum()
+    }
+
     pub fn with_user_stack(mut self, stack_addr: VirtAddr) -> Self {
-        self.user_stack = Some(stack_addr);
-        self.context.rsp = stack_addr.as_u64();
+        let main = self.main_thread_mut();
+        main.user_stack = Some(stack_addr);
+        main.context.rsp = stack_addr.as_u64();
+        self
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = String::from(name);
         self
     }
+
+    /// 空いている仮想アドレス範囲を予約してVMAとして登録する
+    /// file を渡すとファイルマッピングになり、ページはフォルト時にファイルから読み込む
+    pub fn reserve_region(&mut self, length: usize, flags: x86_64::structures::paging::PageTableFlags,
+        file: Option<VmaFile>) -> Result<VirtAddr, Errno> {
+        let size = ((length + 4095) / 4096 * 4096) as u64;
+        self.check_address_space(size)?;
+        let start = self.vmas.iter()
+            .filter(|vma| vma.start.as_u64() >= crate::memory::MMAP_BASE)
+            .map(|vma| vma.end)
+            .max()
+            .unwrap_or(VirtAddr::new(crate::memory::MMAP_BASE));
+
+        self.vmas.push(Vma { start, end: start + size, flags, file });
+        Ok(start)
+    }
+
+    /// VMA の合計の大きさ (バイト)
+    pub fn address_space_size(&self) -> u64 {
+        self.vmas.iter().map(|vma| vma.end - vma.start).sum()
+    }
+
+    /// growth バイト増やしても RLIMIT_AS を超えないか
+    fn check_address_space(&self, growth: u64) -> Result<(), Errno> {
+        if self.address_space_size().saturating_add(growth) > self.limits.cur(crate::rlimit::RLIMIT_AS) {
+            return Err(Errno::ENOMEM);
+        }
+        Ok(())
+    }
+
+    /// CPU時間が RLIMIT_CPU を使い切ったか
+    pub fn cpu_limit_exceeded(&self) -> bool {
+        self.limits.cpu_ticks().is_some_and(|limit| self.cpu_ticks() >= limit)
+    }
+
+    /// 指定範囲をVMAから取り除く (範囲の途中なら分割する)
+    pub fn release_region(&mut self, start: VirtAddr, length: usize) {
+        let end = start + ((length + 4095) / 4096 * 4096) as u64;
+        let mut remaining = Vec::new();
+
+        for vma in self.vmas.drain(..) {
+            if vma.end <= start || vma.start >= end {
+                remaining.push(vma);
+                continue;
+            }
+            if vma.start < start {
+                remaining.push(vma.slice(vma.start, start));
+            }
+            if vma.end > end {
+                remaining.push(vma.slice(end, vma.end));
+            }
+        }
+
+        self.vmas = remaining;
+    }
+
+    /// VMA と各スレッドのユーザースタックのページのマップを外す (プロセスを捨てる前に呼ぶ)
+    /// fork で作ったページテーブルがあれば、それも解放する (どのCPUでも使っていないこと)
+    /// COW で親子が共有しているフレームは、参照が残っていれば外すだけにする
+    pub fn release_user_memory(&mut self) {
+        // ページはこのプロセスのページテーブルから外す
+        let current = crate::memory::current_address_space();
+        crate::memory::switch_address_space(self.address_space());
+        for vma in self.vmas.drain(..) {
+            let pages = ((vma.end - vma.start) / 4096) as usize;
+            crate::memory::deallocate_pages(vma.start, pages);
+        }
+        for thread in self.threads.iter_mut() {
+            if let Some(top) = thread.user_stack.take() {
+                crate::memory::deallocate_pages(top - USER_STACK_SIZE, (USER_STACK_SIZE / 4096) as usize);
+            }
+        }
+        self.mapped_pages = 0;
+        // 解放するページテーブルで動き続けないよう、それが元のものならカーネルのものに戻す
+        let page_table = self.page_table.take();
+        if page_table == Some(current) {
+            crate::memory::switch_address_space(crate::memory::kernel_address_space());
+        } else {
+            crate::memory::switch_address_space(current);
+        }
+        if let Some(page_table) = page_table {
+            crate::memory::free_address_space(page_table);
+        }
+    }
+
+    /// このプロセスが動くページテーブル
+    pub fn address_space(&self) -> PhysFrame {
+        self.page_table.unwrap_or_else(crate::memory::kernel_address_space)
+    }
+
+    pub fn find_vma(&self, addr: VirtAddr) -> Option<&Vma> {
+        self.vmas.iter().find(|vma| vma.contains(addr))
+    }
+
+    /// addr がリング3から実行できるコードか (ユーザーに見えて実行を禁止していない VMA の中にあるか)
+    pub fn is_user_code(&self, addr: VirtAddr) -> bool {
+        use x86_64::structures::paging::PageTableFlags as Flags;
+
+        self.find_vma(addr).is_some_and(|vma| {
+            vma.flags.contains(Flags::USER_ACCESSIBLE) && !vma.flags.contains(Flags::NO_EXECUTE)
+        })
+    }
+
+    /// プログラムブレークを new_brk に動かす
+    /// ヒープのVMAを伸縮するだけで、フレームは初回アクセス時に割り当てる
+    /// 縮めた場合は解放すべき範囲 (先頭, ページ数) を返す
+    pub fn set_brk(&mut self, new_brk: VirtAddr) -> Result<Option<(VirtAddr, usize)>, Errno> {
+        use x86_64::structures::paging::PageTableFlags as Flags;
+
+        if new_brk < self.heap_start || new_brk - self.heap_start > crate::memory::USER_HEAP_MAX {
+            return Err(Errno::ENOMEM);
+        }
+
+        let old_end = self.brk.align_up(4096u64);
+        let new_end = new_brk.align_up(4096u64);
+        let mut freed = None;
+
+        if new_end > old_end {
+            // ヒープの大きさは RLIMIT_DATA、全体は RLIMIT_AS まで
+            if (new_end - self.heap_start) > self.limits.cur(crate::rlimit::RLIMIT_DATA) {
+                return Err(Errno::ENOMEM);
+            }
+            self.check_address_space(new_end - old_end)?;
+            // 伸ばした先が他の領域と重なってはいけない
+            if self.vmas.iter().any(|vma| vma.start < new_end && vma.end > old_end) {
+                return Err(Errno::ENOMEM);
+            }
+            match self.vmas.iter_mut().find(|vma| vma.start == self.heap_start) {
+                Some(heap) => heap.end = new_end,
+                None => self.vmas.push(Vma::anonymous(
+                    self.heap_start,
+                    new_end,
+                    Flags::PRESENT | Flags::USER_ACCESSIBLE | Flags::WRITABLE,
+                )),
+            }
+        } else if new_end < old_end {
+            let length = (old_end - new_end) as usize;
+            self.release_region(new_end, length);
+            freed = Some((new_end, length / 4096));
+        }
+
+        self.brk = new_brk;
+        Ok(freed)
+    }
+
+    /// 新しいイメージを読み込んだ後 (execve) に、イメージ末尾からヒープを作り直す
+    /// 古いヒープのVMAは取り除くので、ページは呼び出し側で解放しておくこと
+    pub fn reset_heap(&mut self, image_end: VirtAddr) {
+        let heap_start = self.heap_start;
+        self.vmas.retain(|vma| vma.start != heap_start);
+        self.heap_start = image_end.align_up(4096u64);
+        self.brk = self.heap_start;
+    }
+
+    /// [start, end) がいずれかのスレッドのユーザースタックか VMA で覆われているか (write なら書き込み可能か)
+    /// カーネルスレッド (メインスレッドにユーザースタックなし) は常に true
+    pub fn user_range_ok(&self, start: u64, end: u64, write: bool) -> bool {
+        if self.main_thread().user_stack.is_none() {
+            return true;
+        }
+        let stacks: Vec<(u64, u64)> = self.threads.iter()
+            .filter_map(|t| t.user_stack)
+            .map(|top| (top.as_u64() - USER_STACK_SIZE, top.as_u64()))
+            .collect();
+
+        let mut cursor = start;
+        while cursor < end {
+            if let Some(&(_, stack_top)) = stacks.iter().find(|&&(bottom, top)| cursor >= bottom && cursor < top) {
+                cursor = stack_top;
+                continue;
+            }
+            match self.find_vma(VirtAddr::new(cursor)) {
+                Some(vma) if !write || vma.flags.contains(x86_64::structures::paging::PageTableFlags::WRITABLE) => {
+                    cursor = vma.end.as_u64();
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// メインスレッドのカーネルスタックのトップ
+    pub fn kernel_stack_top(&self) -> VirtAddr {
+        self.main_thread().kernel_stack_top()
+    }
 }
 
 pub struct ProcessManager {
     processes: Vec<Process>,
-    ready_queue: VecDeque<usize>,
-    current_pid: Option<usize>,
+    /// CPUごとの実行可能キューと、次に実行するスレッドの選び方
+    scheduler: Box<dyn Scheduler>,
+    stats: SchedStats,
+    /// CPUごとに実行中のスレッドの TID (None ならアイドルタスクが動いている)
+    current: Vec<Option<usize>>,
+    /// CPUごとのビジー・アイドル時間
+    cpu_times: Vec<CpuTime>,
+    /// CPUごとに run_user_program で実行中のスレッド (終了するまでティックで横取りしない)
+    foreground: Vec<Option<usize>>,
     scheduler_ticks: usize,
 }
 
+/// schedule が選んだもの
+pub enum Scheduled<'a> {
+    Thread(&'a mut Thread),
+    /// 実行できるスレッドがないので、このCPUのアイドルタスクに戻る
+    Idle,
+}
+
 impl ProcessManager {
-    fn new() -> Self {
+    fn new(policy: Policy) -> Self {
         Self {
             processes: Vec::new(),
-            ready_queue: VecDeque::new(),
-            current_pid: None,
+            scheduler: policy.create(),
+            stats: SchedStats::default(),
+            current: vec![None; MAX_CPUS],
+            cpu_times: vec![CpuTime::default(); MAX_CPUS],
+            foreground: vec![None; MAX_CPUS],
             scheduler_ticks: 0,
         }
     }
 
     pub fn add_process(&mut self, process: Process) -> usize {
         let pid = process.pid;
+        let tids: Vec<usize> = process.threads.iter().map(|t| t.tid).collect();
         self.processes.push(process);
-        self.ready_queue.push_back(pid);
+        for tid in tids {
+            self.enqueue(tid);
+        }
         pid
     }
 
+    /// tid のスレッドの位置 (プロセスの添字, スレッドの添字)
+    fn locate(&self, tid: usize) -> Option<(usize, usize)> {
+        self.processes.iter().enumerate().find_map(|(p, process)| {
+            process.threads.iter().position(|t| t.tid == tid).map(|t| (p, t))
+        })
+    }
+
+    fn thread_mut(&mut self, tid: usize) -> Option<&mut Thread> {
+        let (p, t) = self.locate(tid)?;
+        Some(&mut self.processes[p].threads[t])
+    }
+
+    /// 実行してよいCPUのうち最も空いているもののキューに積む
+    /// 実行してよいCPUがまだ起動していなければ、起動済みのCPUから選ぶ
+    fn enqueue(&mut self, tid: usize) -> usize {
+        let online = CpuMask::online();
+        let allowed = self.locate(tid)
+            .map(|(p, t)| self.processes[p].threads[t].affinity.intersect(online))
+            .filter(|allowed| !allowed.is_empty())
+            .unwrap_or(online);
+        let cpu = allowed.iter()
+            .min_by_key(|&cpu| self.scheduler.queued(cpu) + self.current[cpu].is_some() as usize)
+            .unwrap_or(0);
+        self.push_ready(cpu, tid);
+        cpu
+    }
+
+    /// tid を cpu で実行してはいけないか (実行してよいCPUが1つも起動していなければ、どこで実行してもよい)
+    fn must_migrate(&self, tid: usize, cpu: usize) -> bool {
+        self.locate(tid).is_some_and(|(p, t)| {
+            let affinity = self.processes[p].threads[t].affinity;
+            !affinity.contains(cpu) && !affinity.intersect(CpuMask::online()).is_empty()
+        })
+    }
+
+    /// cpu のキューに積み、待ち時間を測り始める
+    fn push_ready(&mut self, cpu: usize, tid: usize) {
+        let now = self.scheduler_ticks;
+        let Some((p, t)) = self.locate(tid) else {
+            return;
+        };
+        let thread = &mut self.processes[p].threads[t];
+        thread.cpu = cpu;
+        thread.ready_since = now;
+        self.scheduler.enqueue(cpu, tid, thread.priority);
+    }
+
+    fn current_tid(&self) -> Option<usize> {
+        self.current[cpu_id()]
+    }
+
+    fn current_pid(&self) -> Option<usize> {
+        let (p, _) = self.locate(self.current_tid()?)?;
+        Some(self.processes[p].pid)
+    }
+
+    fn current_ppid(&self) -> Option<usize> {
+        let (p, _) = self.locate(self.current_tid()?)?;
+        Some(self.processes[p].ppid)
+    }
+
     pub fn get_current_process(&self) -> Option<&Process> {
-        self.current_pid
-            .and_then(|pid| self.processes.iter().find(|p| p.pid == pid))
+        let (p, _) = self.locate(self.current_tid()?)?;
+        Some(&self.processes[p])
     }
 
     pub fn get_current_process_mut(&mut self) -> Option<&mut Process> {
-        self.current_pid
-            .and_then(|pid| self.processes.iter_mut().find(|p| p.pid == pid))
+        let (p, _) = self.locate(self.current_tid()?)?;
+        Some(&mut self.processes[p])
+    }
+
+    pub fn get_current_thread_mut(&mut self) -> Option<&mut Thread> {
+        self.thread_mut(self.current_tid()?)
+    }
+
+    /// 他のCPUのキューから1つ盗む (長いキューから順に、このCPUで実行してよいものを末尾から)
+    fn steal(&mut self, cpu: usize) -> Option<usize> {
+        let mut victims: Vec<usize> = (0..crate::smp::cpu_count())
+            .filter(|&other| other != cpu && self.scheduler.queued(other) > 0)
+            .collect();
+        victims.sort_by_key(|&other| core::cmp::Reverse(self.scheduler.queued(other)));
+        let processes = &self.processes;
+        let allowed = |tid: usize| processes.iter()
+            .flat_map(|process| process.threads.iter())
+            .any(|thread| thread.tid == tid && thread.affinity.contains(cpu));
+        for victim in victims {
+            if let Some(tid) = self.scheduler.steal_from(victim, &allowed) {
+                crate::trace!("CPU {} stole TID {} from CPU {}", cpu, tid, victim);
+                return Some(tid);
+            }
+        }
+        None
+    }
+
+    /// リング3で始めるスレッドを選ぶ
+    /// 入口がユーザーページのコードでないもの (カーネルの関数を入口にしたものなど) は、
+    /// リング3からは最初の命令も実行できないので、プロセスごと終了させて次を選ぶ
+    fn pick_user_thread(&mut self, cpu: usize) -> Option<usize> {
+        loop {
+            let tid = self.scheduler.pick_next(cpu)?;
+            let Some((p, t)) = self.locate(tid) else { continue };
+            let entry = VirtAddr::new(self.processes[p].threads[t].context.rip);
+            if self.processes[p].is_user_code(entry) {
+                return Some(tid);
+            }
+            crate::warn!("PID {} does not start in user code ({:?}), not entering ring 3",
+                self.processes[p].pid, entry);
+            for thread in self.processes[p].threads.iter_mut() {
+                thread.state = ProcessState::Terminated;
+                self.scheduler.forget(thread.tid);
+            }
+        }
     }
 
-    pub fn schedule(&mut self) -> Option<&mut Process> {
+    /// 現在のCPUで次に実行するスレッドを選ぶ
+    /// 実行中のスレッドはポリシーが横取りすると決めるまで続けさせる
+    /// 状態が Ready のものだけを Running にするので、同じスレッドが
+    /// 2つのCPUで同時に選ばれることはない (マネージャ全体のロック下で行う)
+    /// どのキューも空ならアイドルタスクに戻す
+    pub fn schedule(&mut self) -> Scheduled<'_> {
         self.scheduler_ticks += 1;
-let pid = if let Some(current) = self.get_current_process_mut() {
-    current.pid
-} else {
-    return None;
-};
+        let cpu = cpu_id();
 
-self.ready_queue.push_back(pid);
+        // 直前のティックをビジーかアイドルかに振り分ける
+        match self.current[cpu] {
+            Some(_) => self.cpu_times[cpu].busy_ticks += 1,
+            None => self.cpu_times[cpu].idle_ticks += 1,
+        }
 
-        // 現在のプロセスをReadyに戻す
-        //if let Some(current) = self.get_current_process_mut() {
-            //if current.state == ProcessState::Running {
-             //   current.state = ProcessState::Ready;
-             //   self.ready_queue.push_back(current.pid);
-           // }
-       // }
+        // フォアグラウンドのスレッドは実際には切り替えられないので、そのまま続けさせる
+        if let Some(tid) = self.current[cpu].filter(|&tid| self.foreground[cpu] == Some(tid)) {
+            if let Some((p, t)) = self.locate(tid) {
+                let thread = &mut self.processes[p].threads[t];
+                thread.cpu_ticks += 1;
+                return Scheduled::Thread(thread);
+            }
+        }
 
-        // 次のプロセスを選択
-        //while let Some(pid) = self.ready_queue.pop_front() {
-         //   if let Some(process) = self.processes.iter_mut().find(|p| p.pid == pid) {
-        //        if process.state == ProcessState::Ready {
-        //            process.state = ProcessState::Running;
-         //           self.current_pid = Some(pid);
-         //           return Some(process);
-         //       }
-         //   }
-       // }
+        // 実行中のスレッドにティックを課金し、横取りするなら自分のキューに戻す
+        self.stats.ticks += 1;
+        let mut preempted = None;
+        if let Some(tid) = self.current[cpu] {
+            if let Some((p, t)) = self.locate(tid) {
+                let thread = &mut self.processes[p].threads[t];
+                thread.cpu_ticks += 1;
+                let running = thread.state == ProcessState::Running;
+                // 実行してよいCPUから外されたら、ポリシーによらずほかのCPUへ移す
+                let migrate = running && self.must_migrate(tid, cpu);
+                let thread = &mut self.processes[p].threads[t];
+                if running && !self.scheduler.tick(cpu, tid, thread.priority) && !migrate {
+                    return Scheduled::Thread(&mut self.processes[p].threads[t]);
+                }
+                thread.save_state();
+                if running {
+                    thread.state = ProcessState::Ready;
+                    if migrate {
+                        let target = self.enqueue(tid);
+                        scheduler::request_reschedule(target);
+                    } else {
+                        self.push_ready(cpu, tid);
+                    }
+                    preempted = Some(tid);
+                }
+            }
+            self.current[cpu] = None;
+        }
 
-       // None
-       while let Some(pid) = self.ready_queue.pop_front() {
-    if let Some(index) = self.processes.iter().position(|p| p.pid == pid) {
-        if self.processes[index].state == ProcessState::Ready {
-            self.processes[index].state = ProcessState::Running;
-            self.current_pid = Some(pid);
-            return Some(&mut self.processes[index]);
+        loop {
+            let tid = match self.scheduler.pick_next(cpu).or_else(|| self.steal(cpu)) {
+                Some(tid) => tid,
+                None => return Scheduled::Idle,
+            };
+            // 積まれた後で実行してよいCPUが変わっていれば、そちらに積み直す
+            if self.must_migrate(tid, cpu) {
+                let target = self.enqueue(tid);
+                scheduler::request_reschedule(target);
+                continue;
+            }
+            if let Some((p, t)) = self.locate(tid) {
+                if self.processes[p].threads[t].state == ProcessState::Ready {
+                    // 別のスレッドに切り替わったなら横取りされたことになる
+                    if let Some(previous) = preempted.filter(|&previous| previous != tid) {
+                        if let Some(thread) = self.thread_mut(previous) {
+                            thread.involuntary_switches += 1;
+                        }
+                        self.stats.preemptions += 1;
+                    }
+                    let now = self.scheduler_ticks;
+                    let thread = &mut self.processes[p].threads[t];
+                    self.stats.record_dispatch(now - thread.ready_since);
+                    thread.state = ProcessState::Running;
+                    thread.cpu = cpu;
+                    self.current[cpu] = Some(tid);
+                    return Scheduled::Thread(thread);
+                }
+            }
         }
     }
-}
 
-None
+    /// 現在のプロセスを複製して子プロセスのPIDを返す
+    /// 子のメインスレッドは呼び出したスレッドのレジスタを、
+    /// 子プロセスはファイルディスクリプタ・カレントディレクトリ・VMAを引き継ぐ
+    /// アドレス空間は子のページテーブルを作って複製し、マップ済みのページは親子でコピーオンライトにする
+    pub fn fork_current(&mut self) -> Result<usize, Errno> {
+        let (p, t) = self.current_tid().and_then(|tid| self.locate(tid)).ok_or(Errno::ESRCH)?;
+        // 呼び出したスレッドは実行中なので、引き継ぐ前にレジスタの状態を取り込む
+        self.processes[p].threads[t].save_state();
+        let parent = &self.processes[p];
+        let caller = &parent.threads[t];
+
+        let mut child = Process::new(caller.context.rip);
+        child.ppid = parent.pid;
+        let main = child.main_thread_mut();
+        let kernel_rsp = main.context.rsp;
+        main.context = caller.context.clone();
+        main.context.rax = 0; // 子プロセスでの fork の戻り値
+        if caller.user_stack.is_none() {
+            main.context.rsp = kernel_rsp;
+            main.context.rbp = kernel_rsp;
+        }
+        main.user_stack = caller.user_stack;
+        main.priority = caller.priority;
+        main.affinity = caller.affinity;
+        main.fs_base = caller.fs_base;
+        main.fpu = caller.fpu.clone();
+        // VMA と呼び出したスレッドのユーザースタック (子のメインスレッドが同じアドレスで使う)
+        let mut ranges: Vec<(VirtAddr, VirtAddr)> = parent.vmas.iter().map(|vma| (vma.start, vma.end)).collect();
+        if let Some(top) = caller.user_stack {
+            ranges.push((top - USER_STACK_SIZE, top));
+        }
+        child.page_table = Some(crate::memory::fork_address_space(&ranges).map_err(|_| Errno::ENOMEM)?);
+        child.vmas = parent.vmas.clone();
+        // オープンファイル記述は参照カウントで親と共有する
+        child.fds = parent.fds.clone();
+        child.cwd = parent.cwd.clone();
+        child.name = parent.name.clone();
+        // 子のページテーブルにも同じページがマップされている (COW で親と共有)
+        child.mapped_pages = parent.mapped_pages;
+        // strace -f と同様に子もトレースする
+        child.trace_syscalls = parent.trace_syscalls;
+        // ヒープのVMAは上で複製済みなので、ブレークも揃える
+        child.heap_start = parent.heap_start;
+        child.brk = parent.brk;
+        child.limits = parent.limits.clone();
+        child.cred = parent.cred;
+        child.io = parent.io.clone();
 
+        Ok(self.add_process(child))
     }
 
-    pub fn terminate_current(&mut self) {
-        if let Some(pid) = self.current_pid {
-            if let Some(process) = self.processes.iter_mut().find(|p| p.pid == pid) {
-                process.state = ProcessState::Terminated;
+    /// 現在のプロセスに新しいスレッドを作って TID を返す
+    /// アドレス空間とファイルディスクリプタは共有し、レジスタは呼び出したスレッドから引き継ぐ
+    /// stack を渡すとそこを新しいスレッドのスタックポインタに、tls を渡すと FS ベースにする
+    pub fn clone_thread(&mut self, stack: Option<u64>, tls: Option<u64>) -> Option<usize> {
+        let (p, t) = self.locate(self.current_tid()?)?;
+        let tid = PID_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let process = &mut self.processes[p];
+        process.threads[t].save_state();
+        let caller = &process.threads[t];
+
+        let mut thread = Thread::new(tid, process.pid, caller.context.rip);
+        let kernel_rsp = thread.context.rsp;
+        thread.context = caller.context.clone();
+        thread.context.rax = 0; // 新しいスレッドでの clone の戻り値
+        thread.priority = caller.priority;
+        thread.affinity = caller.affinity;
+        thread.fs_base = tls.unwrap_or(caller.fs_base);
+        thread.fpu = caller.fpu.clone();
+        match stack {
+            Some(rsp) => {
+                thread.context.rsp = rsp;
+                thread.context.rbp = rsp;
+            }
+            None if caller.user_stack.is_none() => {
+                thread.context.rsp = kernel_rsp;
+                thread.context.rbp = kernel_rsp;
             }
-            self.current_pid = None;
+            None => {}
         }
+        process.threads.push(thread);
+
+        self.enqueue(tid);
+        Some(tid)
+    }
+
+    /// 現在のプロセスをすべてのスレッドごと終了する
+    pub fn terminate_current(&mut self) {
+        let cpu = cpu_id();
+        let Some((p, _)) = self.current[cpu].take().and_then(|tid| self.locate(tid)) else {
+            return;
+        };
+        for thread in self.processes[p].threads.iter_mut() {
+            thread.state = ProcessState::Terminated;
+            self.scheduler.forget(thread.tid);
+            self.stats.completed += 1;
+        }
+        // ほかのCPUで実行中だったスレッドはそのCPUの次のティックで外れる
     }
 
     pub fn block_current(&mut self) {
-        if let Some(process) = self.get_current_process_mut() {
-            process.state = ProcessState::Blocked;
+        if let Some(thread) = self.get_current_thread_mut() {
+            thread.state = ProcessState::Blocked;
+            thread.voluntary_switches += 1;
         }
-        self.current_pid = None;
+        self.current[cpu_id()] = None;
     }
 
-    pub fn unblock_process(&mut self, pid: usize) {
-        if let Some(process) = self.processes.iter_mut().find(|p| p.pid == pid) {
-            if process.state == ProcessState::Blocked {
-                process.state = ProcessState::Ready;
-                self.ready_queue.push_back(pid);
+    /// 起床済みのスレッドを、待っていたこのCPUで実行中に戻す
+    pub fn resume(&mut self, tid: usize) {
+        self.scheduler.dequeue(tid);
+        let cpu = cpu_id();
+        if let Some(thread) = self.thread_mut(tid) {
+            thread.state = ProcessState::Running;
+            thread.cpu = cpu;
+        }
+        // 待っている間に選ばれていたスレッドは Ready に戻す
+        if let Some(other) = self.current[cpu].replace(tid).filter(|&other| other != tid) {
+            if let Some(thread) = self.thread_mut(other) {
+                if thread.state == ProcessState::Running {
+                    thread.state = ProcessState::Ready;
+                    self.push_ready(cpu, other);
+                }
             }
         }
     }
+
+    /// 起床させたスレッドを積んだCPUを返す
+    pub fn unblock_thread(&mut self, tid: usize) -> Option<usize> {
+        let thread = self.thread_mut(tid)?;
+        if thread.state != ProcessState::Blocked {
+            return None;
+        }
+        thread.state = ProcessState::Ready;
+        Some(self.enqueue(tid))
+    }
+
+    /// tid (0 なら現在のスレッド) を実行してよいCPUを変える
+    /// 積まれていれば実行してよいCPUのキューに積み直し、外されたCPUで実行中ならそのCPUを返す
+    fn set_affinity(&mut self, tid: usize, mask: CpuMask, caller: &Credentials) -> Result<Option<usize>, Errno> {
+        let tid = if tid == 0 { self.current_tid().ok_or(Errno::ESRCH)? } else { tid };
+        let (p, t) = self.locate(tid).ok_or(Errno::ESRCH)?;
+        let target = &self.processes[p].cred;
+        if !caller.is_root() && caller.euid != target.uid && caller.euid != target.euid {
+            return Err(Errno::EPERM);
+        }
+        let thread = &mut self.processes[p].threads[t];
+        thread.affinity = mask;
+        let running_on = (thread.state == ProcessState::Running && !mask.contains(thread.cpu))
+            .then_some(thread.cpu);
+        if self.scheduler.dequeue(tid) {
+            self.enqueue(tid);
+        }
+        Ok(running_on)
+    }
+
+    fn affinity(&self, tid: usize) -> Result<CpuMask, Errno> {
+        let tid = if tid == 0 { self.current_tid().ok_or(Errno::ESRCH)? } else { tid };
+        let (p, t) = self.locate(tid).ok_or(Errno::ESRCH)?;
+        Ok(self.processes[p].threads[t].affinity)
+    }
 }
 
+/// ポリシーはコマンドラインの sched= で、既定の実行先は isolcpus= で選ぶ (cmdline::init の後に呼ぶ)
 pub fn init() {
-    *PROCESS_MANAGER.lock() = Some(ProcessManager::new());
+    crate::sched::init();
+    let policy = crate::sched::configured_policy();
+    *PROCESS_MANAGER.lock() = Some(ProcessManager::new(policy));
+    crate::info!("Scheduler policy: {}", policy.name());
+}
+
+/// tid (0 なら現在のスレッド) を mask のCPUだけで実行させる (sched_setaffinity)
+/// 起動済みのCPUを1つも含まなければ EINVAL、ほかのユーザーのスレッドなら EPERM
+pub fn set_affinity(tid: usize, mask: CpuMask) -> Result<(), Errno> {
+    if mask.intersect(CpuMask::online()).is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    // 資格情報はプロセス管理のロックを取って読むので、先に取り出しておく
+    let caller = crate::cred::current();
+    let running_on = {
+        let mut manager = PROCESS_MANAGER.lock();
+        manager.as_mut().ok_or(Errno::ESRCH)?.set_affinity(tid, mask, &caller)?
+    };
+    // 外されたCPUで実行中なら、次のティックを待たずに移させる
+    if let Some(cpu) = running_on {
+        scheduler::request_reschedule(cpu);
+    }
+    Ok(())
+}
+
+/// tid (0 なら現在のスレッド) を実行してよいCPU (sched_getaffinity)
+pub fn affinity(tid: usize) -> Result<CpuMask, Errno> {
+    let manager = PROCESS_MANAGER.lock();
+    manager.as_ref().ok_or(Errno::ESRCH)?.affinity(tid)
+}
+
+/// 使っているスケジューリングポリシーと、起動してからの統計
+pub fn sched_stats() -> Option<(Policy, SchedStats)> {
+    let manager = PROCESS_MANAGER.lock();
+    manager.as_ref().map(|m| (m.scheduler.policy(), m.stats))
 }
 
 pub fn spawn_process(entry_point: u64) -> usize {
     let mut manager = PROCESS_MANAGER.lock();
     if let Some(manager) = manager.as_mut() {
         // ユーザースタック割り当て
-        let stack_addr = crate::memory::allocate_pages(4) // 16KB
+        let stack_addr = crate::memory::allocate_zeroed_pages((USER_STACK_SIZE / 4096) as usize)
             .expect("Failed to allocate user stack");
         
         let process = Process::new(entry_point)
-            .with_user_stack(stack_addr + 0x4000u64); // スタックトップ
+            .with_user_stack(stack_addr + USER_STACK_SIZE); // スタックトップ
         
         manager.add_process(process)
     } else {
@@ -231,10 +983,16 @@ pub fn spawn_process(entry_point: u64) -> usize {
     }
 }
 
+/// 作成済みのプロセスを登録して実行可能にする
+pub fn add_process(process: Process) -> usize {
+    let mut manager = PROCESS_MANAGER.lock();
+    manager.as_mut().expect("Process manager not initialized").add_process(process)
+}
+
 pub fn spawn_init_process() {
     // initプロセスのエントリーポイント
     extern "C" fn init_process() {
-        crate::println!("Init process started (PID: 1)");
+        crate::info!("Init process started (PID: 1)");
         
         // いくつかのテストプロセスを起動
         spawn_process(test_process_1 as u64);
@@ -265,32 +1023,487 @@ extern "C" fn test_process_2() {
     exit(0);
 }
 
-pub fn exit(code: i32) {
+/// 現在のPIDを返す
+/// パニック時など、ロックを保持したまま呼ばれうる場所で使えるよう try_lock する
+pub fn try_current_pid() -> Option<usize> {
+    PROCESS_MANAGER.try_lock()?.as_ref()?.current_pid()
+}
+
+/// プロセスを順に f に渡す (クラッシュダンプ用)
+/// パニック時に使うので try_lock し、取れなければ何もせず false を返す
+pub fn try_for_each_process(mut f: impl FnMut(&Process)) -> bool {
+    let Some(manager) = PROCESS_MANAGER.try_lock() else {
+        return false;
+    };
+    for process in manager.iter().flat_map(|manager| manager.processes.iter()) {
+        f(process);
+    }
+    true
+}
+
+/// 現在のプロセスが RLIMIT_CPU を使い切ったか (割り込みから呼ぶので try_lock)
+pub fn cpu_limit_exceeded() -> bool {
+    PROCESS_MANAGER.try_lock()
+        .and_then(|manager| Some(manager.as_ref()?.get_current_process()?.cpu_limit_exceeded()))
+        .unwrap_or(false)
+}
+
+pub fn current_pid() -> Option<usize> {
+    PROCESS_MANAGER.lock().as_ref()?.current_pid()
+}
+
+/// 現在のプロセスの親の PID
+pub fn current_ppid() -> Option<usize> {
+    PROCESS_MANAGER.lock().as_ref()?.current_ppid()
+}
+
+/// 現在のスレッドの TID (ブロック・起床はスレッド単位で行う)
+pub fn current_tid() -> Option<usize> {
+    PROCESS_MANAGER.lock().as_ref()?.current_tid()
+}
+
+/// 現在のスレッドを Blocked にする (起こすのは scheduler::wake)
+pub fn block_current() {
+    if let Some(manager) = PROCESS_MANAGER.lock().as_mut() {
+        manager.block_current();
+    }
+}
+
+pub fn is_blocked(tid: usize) -> bool {
+    let manager = PROCESS_MANAGER.lock();
+    manager.as_ref()
+        .and_then(|m| m.locate(tid).map(|(p, t)| m.processes[p].threads[t].state))
+        .map_or(false, |state| state == ProcessState::Blocked)
+}
+
+pub fn resume(tid: usize) {
+    if let Some(manager) = PROCESS_MANAGER.lock().as_mut() {
+        manager.resume(tid);
+    }
+}
+
+/// 現在のプロセスを複製する (カーネルコンテキストからは ESRCH)
+pub fn fork() -> Result<usize, Errno> {
     let mut manager = PROCESS_MANAGER.lock();
-    if let Some(manager) = manager.as_mut() {
-        manager.terminate_current();
+    manager.as_mut().ok_or(Errno::ESRCH)?.fork_current()
+}
+
+/// clone システムコールの本体
+/// CLONE_VM|CLONE_FILES なら現在のプロセスにスレッドを作って TID を、どちらもなければ fork して PID を返す
+/// アドレス空間もファイルディスクリプタもプロセスが持つので、片方だけの共有はできない
+/// CLONE_SETTLS があれば新しいスレッドの FS ベースを tls にする
+pub fn clone(flags: u64, stack: Option<u64>, tls: u64) -> Result<usize, Errno> {
+    let tls = if flags & CLONE_SETTLS != 0 {
+        check_fs_base(tls)?;
+        Some(tls)
+    } else {
+        None
+    };
+    let mut manager = PROCESS_MANAGER.lock();
+    let manager = manager.as_mut().ok_or(Errno::ESRCH)?;
+    match flags & (CLONE_VM | CLONE_FILES) {
+        0 => {
+            let pid = manager.fork_current()?;
+            if let (Some(tls), Some(main)) = (tls, manager.thread_mut(pid)) {
+                main.fs_base = tls;
+            }
+            Ok(pid)
+        }
+        shared if shared == CLONE_VM | CLONE_FILES => manager.clone_thread(stack, tls).ok_or(Errno::ESRCH),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// FS ベースにできるのはユーザー空間のアドレスだけ
+fn check_fs_base(addr: u64) -> Result<(), Errno> {
+    if addr >= crate::uaccess::USER_SPACE_END {
+        return Err(Errno::EPERM);
+    }
+    Ok(())
+}
+
+/// 現在のスレッドの FS ベースを変え、このCPUの FS_BASE にもすぐ反映する
+pub fn set_fs_base(addr: u64) -> Result<(), Errno> {
+    check_fs_base(addr)?;
+    with_current_thread(|thread| thread.fs_base = addr).ok_or(Errno::ESRCH)?;
+    FsBase::write(VirtAddr::new(addr));
+    Ok(())
+}
+
+/// 現在のスレッドの FS ベース
+pub fn fs_base() -> Option<u64> {
+    with_current_thread(|thread| thread.fs_base)
+}
+
+/// 現在のプロセスに対して操作する (プロセスがなければ None)
+pub fn with_current_process<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    let mut manager = PROCESS_MANAGER.lock();
+    let process = manager.as_mut()?.get_current_process_mut()?;
+    Some(f(process))
+}
+
+/// 現在のスレッドに対して操作する (スレッドがなければ None)
+pub fn with_current_thread<R>(f: impl FnOnce(&mut Thread) -> R) -> Option<R> {
+    let mut manager = PROCESS_MANAGER.lock();
+    let thread = manager.as_mut()?.get_current_thread_mut()?;
+    Some(f(thread))
+}
+
+/// 現在のプロセスのカレントディレクトリ (プロセスがなければルート)
+pub fn current_cwd() -> String {
+    with_current_process(|process| process.cwd.clone())
+        .unwrap_or_else(|| String::from("/"))
+}
+
+pub fn set_cwd(path: String) -> bool {
+    with_current_process(|process| process.cwd = path).is_some()
+}
+
+/// 現在のプロセスのアドレス空間に仮想領域を予約する
+pub fn reserve_region(length: usize, flags: x86_64::structures::paging::PageTableFlags,
+    file: Option<VmaFile>) -> Result<VirtAddr, Errno> {
+    let mut manager = PROCESS_MANAGER.lock();
+    let process = manager.as_mut()
+        .and_then(|m| m.get_current_process_mut())
+        .ok_or(Errno::ENOMEM)?;
+    process.reserve_region(length, flags, file)
+}
+
+pub fn release_region(start: VirtAddr, length: usize) {
+    let mut manager = PROCESS_MANAGER.lock();
+    if let Some(process) = manager.as_mut().and_then(|m| m.get_current_process_mut()) {
+        process.release_region(start, length);
+    }
+}
+
+/// 現在のプロセスのプログラムブレーク
+pub fn current_brk() -> Option<VirtAddr> {
+    with_current_process(|process| process.brk)
+}
+
+/// 現在のプロセスのプログラムブレークを動かす (縮めた分のページはここで解放する)
+pub fn set_brk(new_brk: VirtAddr) -> Result<(), Errno> {
+    let freed = {
+        let mut manager = PROCESS_MANAGER.lock();
+        let process = manager.as_mut()
+            .and_then(|m| m.get_current_process_mut())
+            .ok_or(Errno::ESRCH)?;
+        process.set_brk(new_brk)?
+    };
+
+    if let Some((start, pages)) = freed {
+        let unmapped = crate::memory::deallocate_pages(start, pages);
+        with_current_process(|process| {
+            process.mapped_pages = process.mapped_pages.saturating_sub(unmapped);
+        });
     }
+    Ok(())
+}
+
+/// プロセスのすべてのスレッドの優先度を変える
+pub fn set_priority(pid: usize, priority: u8) -> bool {
+    let mut manager = PROCESS_MANAGER.lock();
+    match manager.as_mut().and_then(|m| m.processes.iter_mut().find(|p| p.pid == pid)) {
+        Some(process) => {
+            for thread in process.threads.iter_mut() {
+                thread.priority = priority;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// 指定したプロセスのシステムコールトレースを切り替える
+pub fn set_trace(pid: usize, enabled: bool) -> bool {
+    let mut manager = PROCESS_MANAGER.lock();
+    match manager.as_mut().and_then(|m| m.processes.iter_mut().find(|p| p.pid == pid)) {
+        Some(process) => {
+            process.trace_syscalls = enabled;
+            true
+        }
+        None => false,
+    }
+}
+
+/// プロセス数 (終了したものを除く)
+pub fn count() -> usize {
+    let manager = PROCESS_MANAGER.lock();
+    manager.as_ref().map_or(0, |m| {
+        m.processes.iter().filter(|p| p.state() != ProcessState::Terminated).count()
+    })
+}
+
+/// 全プロセスの情報 (終了したものを除く、PID順)
+pub fn snapshot() -> Vec<ProcessInfo> {
+    let manager = PROCESS_MANAGER.lock();
+    let mut processes: Vec<ProcessInfo> = manager.as_ref().map_or(Vec::new(), |m| {
+        m.processes.iter()
+            .filter(|p| p.state() != ProcessState::Terminated)
+            .map(|p| p.info())
+            .collect()
+    });
+    processes.sort_by_key(|p| p.pid);
+    processes
+}
+
+/// 起動しているCPUそれぞれのビジー・アイドル時間
+pub fn cpu_times() -> Vec<CpuTime> {
+    let manager = PROCESS_MANAGER.lock();
+    let count = crate::smp::cpu_count();
+    manager.as_ref().map_or(Vec::new(), |m| m.cpu_times[..count].to_vec())
+}
+
+/// プロセスごとのマップ済みページ数 (PID, 名前, ページ数)
+pub fn memory_usage() -> Vec<(usize, String, usize)> {
+    let manager = PROCESS_MANAGER.lock();
+    manager.as_ref().map_or(Vec::new(), |m| {
+        m.processes.iter()
+            .filter(|p| p.state() != ProcessState::Terminated)
+            .map(|p| (p.pid, p.name.clone(), p.mapped_pages))
+            .collect()
+    })
+}
+
+/// 現在のプロセスで addr を含むVMAを探す
+pub fn find_vma(addr: VirtAddr) -> Option<Vma> {
+    let manager = PROCESS_MANAGER.lock();
+    manager.as_ref()?.get_current_process()?.find_vma(addr).cloned()
+}
+
+/// 現在のプロセスをすべてのスレッドごと終了する
+/// run_user_program が待っていれば (システムコールや例外の途中でも) そこへ戻って終了コードを返す
+pub fn exit(code: i32) {
+    {
+        let mut manager = PROCESS_MANAGER.lock();
+        if let Some(manager) = manager.as_mut() {
+            manager.terminate_current();
+        }
+    }
+    let return_rsp = crate::smp::current_cpu().user_return_rsp().swap(0, Ordering::SeqCst);
+    if return_rsp != 0 {
+        unsafe { return_to_runner(return_rsp, code as i64) }
+    }
+}
+
+/// 現在のスレッドのプロセスを終了させ、このCPUで次に実行するものへ移る (戻らない)
+/// run_user_program から実行していればそこへ戻り、そうでなければ次のスレッドを
+/// リング3で始めるか、なければアイドルタスクに入る (例外ハンドラから呼んでもよい)
+pub fn exit_and_reschedule(code: i32) -> ! {
+    exit(code);
+    scheduler::start()
+}
+
+/// 作成済みのユーザープロセスをこのCPUのリング3で実行し、終了するまで待って終了コードを返す
+/// 終わったプロセスは一覧から取り除き、マップしていたページも外す
+/// (コンテキストスイッチがないので、実行中はタイマーティックでほかのプロセスに切り替えない)
+pub fn run_user_program(process: Process) -> i32 {
+    let cpu = cpu_id();
+    let pid = process.pid;
+    let (entry_point, user_stack, kernel_stack, address_space) = {
+        let mut manager = PROCESS_MANAGER.lock();
+        let manager = manager.as_mut().expect("Process manager not initialized");
+        let address_space = process.address_space();
+        manager.processes.push(process);
+        crate::ioport::load(&manager.processes.last().unwrap().io);
+        let main = manager.processes.last_mut().unwrap().main_thread_mut();
+        main.state = ProcessState::Running;
+        main.cpu = cpu;
+        main.restore_state();
+        let info = (main.context.rip, main.context.rsp, main.kernel_stack_top(), address_space);
+        // 待っている間に選ばれていたスレッドは Ready に戻す (メインスレッドの TID は PID と同じ)
+        manager.resume(pid);
+        manager.foreground[cpu] = Some(pid);
+        info
+    };
+
+    crate::smp::set_kernel_stack(kernel_stack);
+    crate::memory::switch_address_space(address_space);
+    let code = unsafe { run_until_exit(entry_point, user_stack, crate::smp::current_cpu().user_return_rsp().as_ptr()) };
+    // システムコールや例外ハンドラの途中から戻ってくるので割り込みは止まっている
+    x86_64::instructions::interrupts::enable();
+    // 許していたポートを次にこのCPUで動くものに残さない
+    crate::ioport::revoke();
+    crate::memory::switch_address_space(crate::memory::kernel_address_space());
+
+    let process = {
+        let mut manager = PROCESS_MANAGER.lock();
+        manager.as_mut().and_then(|manager| {
+            manager.foreground[cpu] = None;
+            let index = manager.processes.iter().position(|p| p.pid == pid)?;
+            let process = manager.processes.remove(index);
+            for thread in &process.threads {
+                manager.scheduler.forget(thread.tid);
+            }
+            Some(process)
+        })
+    };
+    // ページの解放はメモリ管理のロックを取るので、プロセス管理のロックを外してから行う
+    if let Some(mut process) = process {
+        process.release_user_memory();
+    }
+    code as i32
+}
+
+/// カーネルの callee-saved レジスタを積んでスタックポインタを saved_rsp に記録し、
+/// リング3へ入る (exit から return_to_runner で戻ってきたときに終了コードを返す)
+#[unsafe(naked)]
+unsafe extern "C" fn run_until_exit(entry_point: u64, user_stack: u64, saved_rsp: *mut u64) -> i64 {
+    core::arch::naked_asm!(
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdx], rsp",
+        "jmp {enter}",
+        enter = sym enter_user_trampoline,
+    );
+}
+
+extern "C" fn enter_user_trampoline(entry_point: u64, user_stack: u64) -> ! {
+    unsafe { enter_user_mode(entry_point, user_stack) }
+}
+
+/// run_until_exit が積んだレジスタを戻し、その呼び出し元に code を返す
+#[unsafe(naked)]
+unsafe extern "C" fn return_to_runner(saved_rsp: u64, code: i64) -> ! {
+    core::arch::naked_asm!(
+        "mov rsp, rdi",
+        "mov rax, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "ret",
+    );
+}
+
+/// 指定したスレッドをリング3で開始する（戻らない）
+pub fn start_user_thread(tid: usize) -> ! {
+    let (entry_point, user_stack, kernel_stack, address_space) = {
+        let mut manager = PROCESS_MANAGER.lock();
+        let manager = manager.as_mut().expect("Process manager not initialized");
+        let (p, _) = manager.locate(tid).expect("No such thread");
+        crate::ioport::load(&manager.processes[p].io);
+        let address_space = manager.processes[p].address_space();
+        let thread = manager.thread_mut(tid).expect("No such thread");
+
+        thread.state = ProcessState::Running;
+        thread.cpu = cpu_id();
+        thread.restore_state();
+        let info = (thread.context.rip, thread.context.rsp, thread.kernel_stack_top(), address_space);
+        manager.current[cpu_id()] = Some(tid);
+        info
+    };
+
+    // 割り込み/システムコールでリング0に戻る際のスタックを設定
+    crate::smp::set_kernel_stack(kernel_stack);
+    crate::memory::switch_address_space(address_space);
+
+    unsafe { enter_user_mode(entry_point, user_stack) }
+}
+
+/// iretq でリング3へ遷移する
+/// スタックに SS, RSP, RFLAGS, CS, RIP の順で積んでから iretq を実行する
+/// GS ベースは swapgs でユーザーのものにする (per-CPU 領域は次にカーネルに入るまで KernelGsBase に置く)
+pub unsafe fn enter_user_mode(entry_point: u64, user_stack: u64) -> ! {
+    let selectors = crate::gdt::selectors();
+    let user_cs = selectors.user_code_selector.0 as u64;
+    let user_ss = selectors.user_data_selector.0 as u64;
+
+    core::arch::asm!(
+        "mov ds, {ss:x}",
+        "mov es, {ss:x}",
+        "push {ss}",
+        "push {rsp}",
+        "push {rflags}",
+        "push {cs}",
+        "push {rip}",
+        "swapgs",
+        "iretq",
+        ss = in(reg) user_ss,
+        rsp = in(reg) user_stack,
+        rflags = in(reg) 0x202u64, // IF (割り込み有効)
+        cs = in(reg) user_cs,
+        rip = in(reg) entry_point,
+        options(noreturn)
+    );
 }
 
 pub mod scheduler {
     use super::*;
 
+    /// 他のCPUに再スケジューリングを要求する割り込みベクタ
+    pub const RESCHEDULE_VECTOR: u8 = 0xF0;
+
     pub fn start() -> ! {
+        // 最初のスレッドをリング3で開始
+        let first = {
+            let mut manager = PROCESS_MANAGER.lock();
+            manager.as_mut().and_then(|m| m.pick_user_thread(cpu_id()))
+        };
+        if let Some(tid) = first {
+            start_user_thread(tid);
+        }
+
+        // 終了したプロセスのページテーブルで待たない
+        crate::memory::switch_address_space(crate::memory::kernel_address_space());
+        idle_task()
+    }
+
+    /// CPUごとのアイドルタスク
+    /// 実行できるプロセスがないときはここで割り込みを待ち、
+    /// ティックごとにスケジューラがアイドル時間として数える
+    pub fn idle_task() -> ! {
+        x86_64::instructions::interrupts::enable();
         loop {
-            tick();
             x86_64::instructions::hlt();
         }
     }
 
+    /// 割り込みハンドラから呼ばれるため、ロックが取れなければ今回は見送る
+    /// (同じCPUでロック保持中に割り込まれた場合のデッドロックを避ける)
     pub fn tick() {
-        let mut manager = PROCESS_MANAGER.lock();
+        let mut manager = match PROCESS_MANAGER.try_lock() {
+            Some(manager) => manager,
+            None => return,
+        };
+        crate::watchdog::touch();
         if let Some(manager) = manager.as_mut() {
-            if let Some(_next_process) = manager.schedule() {
-                // コンテキストスイッチ実行
-                // 実際の実装ではアセンブリでレジスタを保存/復元
+            match manager.schedule() {
+                Scheduled::Thread(_next_thread) => {
+                    // コンテキストスイッチ実行
+                    // 実際の実装ではアセンブリでレジスタを保存/復元
+                }
+                Scheduled::Idle => {}
             }
         }
     }
+
+    /// 指定したCPUに再スケジューリングを要求する
+    pub fn request_reschedule(cpu: usize) {
+        if cpu == cpu_id() {
+            return;
+        }
+        if let Some(target) = crate::smp::cpu(cpu).filter(|target| !target.is_parked()) {
+            crate::apic::send_ipi(target.apic_id(), RESCHEDULE_VECTOR);
+        }
+    }
+
+    /// ブロック中のスレッドを起こし、積んだ先のCPUに通知する
+    pub fn wake(tid: usize) {
+        let cpu = {
+            let mut manager = PROCESS_MANAGER.lock();
+            manager.as_mut().and_then(|m| m.unblock_thread(tid))
+        };
+        if let Some(cpu) = cpu {
+            request_reschedule(cpu);
+        }
+    }
 }
 
 // コンテキストスイッチ用のアセンブリ関数
@@ -0,0 +1,243 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::errno::Errno;
+use crate::filesystem::{FileMode, StatFs, VirtualFileSystem};
+use crate::allocator::HeapReport;
+use crate::cpu::CpuInfo;
+use crate::drivers::block::{BlockDevice, SECTOR_SIZE};
+use crate::drivers::registry::DriverInfo;
+use crate::process::{CpuTime, ProcessInfo, ProcessState};
+use crate::sched::{Policy, SchedStats};
+
+/// /proc 以下のファイルは読み取り専用
+const FILE_MODE: FileMode = FileMode::from_bits(0o444);
+const DIR_MODE: FileMode = FileMode::from_bits(0o555);
+
+/// path が /proc 以下を指しているか
+pub fn is_proc_path(path: &str) -> bool {
+    let path = crate::filesystem::normalize_path(path);
+    path == "/proc" || path.starts_with("/proc/")
+}
+
+/// /proc の中身を現在のプロセス一覧で作り直す
+/// 内容は開いた時点のもので、開いたまま読み続けても更新されない
+pub fn refresh() {
+    let processes = crate::process::snapshot();
+    let uptime_ms = crate::drivers::timer::get_uptime_ms();
+    let heap = heap(&crate::memory::heap_report());
+    let stat = stat(&crate::process::cpu_times());
+    let drivers = drivers(&crate::drivers::registry::inventory());
+    let partitions = partitions(&crate::drivers::block::devices());
+    let profile = crate::profiler::collapsed();
+    let crashdump = crate::crashdump::previous();
+    let sched = crate::process::sched_stats().map(|(policy, stats)| sched(policy, &stats)).unwrap_or_default();
+
+    let result = crate::filesystem::with_fs(|fs| {
+        ensure_dir(fs, "/proc")?;
+        remove_stale(fs, &processes)?;
+        for process in &processes {
+            let dir = format!("/proc/{}", process.pid);
+            ensure_dir(fs, &dir)?;
+            fs.install_file(&format!("{}/status", dir), status(process).as_bytes(), FILE_MODE)?;
+        }
+        let uptime = format!("{}.{:02}\n", uptime_ms / 1000, uptime_ms % 1000 / 10);
+        fs.install_file("/proc/uptime", uptime.as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/heap", heap.as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/stat", stat.as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/sched", sched.as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/drivers", drivers.as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/mounts", mounts().as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/partitions", partitions.as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/profile", profile.as_bytes(), FILE_MODE)?;
+        fs.install_file("/proc/cpuinfo", cpuinfo(crate::cpu::info()).as_bytes(), FILE_MODE)?;
+        // 前回の起動で crashdump= のデバイスに書かれたダンプ
+        if let Some(dump) = &crashdump {
+            fs.install_file("/proc/crashdump", dump, FILE_MODE)?;
+        }
+        // 他のファイルを作り終えた後の使用量を載せる
+        let mut usage = Vec::new();
+        for mount in crate::filesystem::mounts() {
+            usage.push((mount.fs_type, mount.path, fs.statfs(mount.path)?));
+        }
+        fs.install_file("/proc/df", df(&usage).as_bytes(), FILE_MODE)?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        crate::debug!("procfs refresh failed: {}", e);
+    }
+}
+
+/// mkdir は既存のディレクトリを置き換えてしまうので、なければ作る
+fn ensure_dir(fs: &mut VirtualFileSystem, path: &str) -> Result<(), Errno> {
+    match fs.stat(path) {
+        Ok(_) => Ok(()),
+        Err(Errno::ENOENT) => fs.mkdir(path, DIR_MODE).map(|_| ()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 終了したプロセスのディレクトリを消す
+fn remove_stale(fs: &mut VirtualFileSystem, processes: &[ProcessInfo]) -> Result<(), Errno> {
+    let stale: Vec<String> = fs.list_dir("/proc")?
+        .into_iter()
+        .filter(|name| match name.parse::<usize>() {
+            Ok(pid) => !processes.iter().any(|p| p.pid == pid),
+            Err(_) => false,
+        })
+        .collect();
+    for name in stale {
+        let _ = fs.unlink(&format!("/proc/{}/status", name));
+        fs.rmdir(&format!("/proc/{}", name))?;
+    }
+    Ok(())
+}
+
+fn state_name(state: ProcessState) -> &'static str {
+    match state {
+        ProcessState::Running => "R (running)",
+        ProcessState::Ready => "R (ready)",
+        ProcessState::Blocked => "S (sleeping)",
+        ProcessState::Terminated => "Z (zombie)",
+    }
+}
+
+/// /proc/<pid>/status の内容 (Linux と同じ「キー:\t値」形式)
+fn status(process: &ProcessInfo) -> String {
+    format!(
+        "Name:\t{}\nPid:\t{}\nPPid:\t{}\nState:\t{}\nUid:\t{}\nGid:\t{}\nThreads:\t{}\nPriority:\t{}\nCpu:\t{}\nCpus_allowed:\t{:x}\n\
+         CpuTicks:\t{}\nStartTicks:\t{}\nVmPages:\t{}\nvoluntary_ctxt_switches:\t{}\nnonvoluntary_ctxt_switches:\t{}\n",
+        process.name,
+        process.pid,
+        process.ppid,
+        state_name(process.state),
+        process.uid,
+        process.gid,
+        process.threads,
+        process.priority,
+        process.cpu,
+        process.affinity.bits(),
+        process.cpu_ticks,
+        process.start_ticks,
+        process.mapped_pages,
+        process.voluntary_switches,
+        process.involuntary_switches,
+    )
+}
+
+/// /proc/cpuinfo の内容 (BSP で調べた値。AP も同じとして扱う)
+fn cpuinfo(info: &CpuInfo) -> String {
+    let flags: Vec<&str> = crate::cpu::FEATURES.iter()
+        .filter(|&&feature| info.has(feature))
+        .map(|feature| feature.name())
+        .collect();
+    format!(
+        "vendor_id\t: {}\ncpu family\t: {}\nmodel\t\t: {}\nmodel name\t: {}\nstepping\t: {}\nflags\t\t: {}\n",
+        info.vendor(),
+        info.family,
+        info.model,
+        info.brand(),
+        info.stepping,
+        flags.join(" "),
+    )
+}
+
+/// /proc/stat の内容
+/// 1行目が全CPUの合計、続いてCPUごとに「ビジー アイドル ビジー% アイドル%」(ティック数)
+fn stat(cpus: &[CpuTime]) -> String {
+    let total = cpus.iter().fold(CpuTime::default(), |sum, cpu| CpuTime {
+        busy_ticks: sum.busy_ticks + cpu.busy_ticks,
+        idle_ticks: sum.idle_ticks + cpu.idle_ticks,
+    });
+    let line = |name: &str, time: &CpuTime| {
+        let busy = time.busy_percent();
+        let idle = if time.total_ticks() == 0 { 0 } else { 100 - busy };
+        format!("{} {} {} {} {}\n", name, time.busy_ticks, time.idle_ticks, busy, idle)
+    };
+
+    let mut text = line("cpu ", &total);
+    for (id, time) in cpus.iter().enumerate() {
+        text.push_str(&line(&format!("cpu{}", id), time));
+    }
+    text
+}
+
+/// /proc/sched の内容 (1行に「名前 値」、待ち時間はティック)
+fn sched(policy: Policy, stats: &SchedStats) -> String {
+    let avg = stats.avg_wait_centiticks();
+    format!("policy {}\nticks {}\ndispatches {}\npreemptions {}\navg_wait {}.{:02}\nmax_wait {}\ncompleted {}\n",
+        policy.name(), stats.ticks, stats.dispatches, stats.preemptions, avg / 100, avg % 100,
+        stats.max_wait_ticks, stats.completed)
+}
+
+/// /proc/drivers の内容 (1行に「名前 依存 (カンマ区切り、なければ -) 状態」)
+fn drivers(drivers: &[DriverInfo]) -> String {
+    let mut text = String::new();
+    for driver in drivers {
+        let depends = if driver.depends.is_empty() { String::from("-") } else { driver.depends.join(",") };
+        text.push_str(&format!("{} {} {}\n", driver.name, depends, driver.status));
+    }
+    text
+}
+
+/// /proc/partitions の内容 (1行に「番号 1Kブロック数 名前」)
+fn partitions(devices: &[Arc<dyn BlockDevice>]) -> String {
+    let mut text = String::from("index  #blocks name\n");
+    for (index, device) in devices.iter().enumerate() {
+        let blocks = device.sector_count() * SECTOR_SIZE as u64 / 1024;
+        text.push_str(&format!("{:>5} {:>8} {}\n", index, blocks, device.name()));
+    }
+    text
+}
+
+/// /proc/mounts の内容 (Linux と同じ「デバイス マウントポイント 種類 オプション 0 0」)
+fn mounts() -> String {
+    let mut text = String::new();
+    for mount in crate::filesystem::mounts() {
+        text.push_str(&format!("{} {} {} rw 0 0\n", mount.fs_type, mount.path, mount.fs_type));
+    }
+    text
+}
+
+/// /proc/df の内容 (df コマンドと同じ 1K ブロック単位の表)
+/// df と同様に、ブロックを持たない擬似ファイルシステムは載せない
+fn df(usage: &[(&str, &str, StatFs)]) -> String {
+    let mut text = String::from("Filesystem     1K-blocks      Used Available Use% Mounted on\n");
+    for (fs_type, path, statfs) in usage.iter().filter(|(_, _, statfs)| statfs.f_blocks > 0) {
+        let kib = |blocks: u64| blocks * statfs.f_bsize as u64 / 1024;
+        let used = statfs.f_blocks - statfs.f_bfree;
+        let percent = (used * 100).div_ceil(statfs.f_blocks);
+        text.push_str(&format!(
+            "{:<14} {:>9} {:>9} {:>9} {:>3}% {}\n",
+            fs_type,
+            kib(statfs.f_blocks),
+            kib(used),
+            kib(statfs.f_bavail),
+            percent,
+            path,
+        ));
+    }
+    text
+}
+
+/// /proc/heap の内容 (生存中の割り当てと、多い順の呼び出し元)
+fn heap(report: &HeapReport) -> String {
+    let mut text = format!(
+        "LiveAllocs:\t{}\nLiveBytes:\t{}\nPeakBytes:\t{}\nHeapSize:\t{}\nTracking:\t{}\nUntracked:\t{}\n",
+        report.stats.live_allocs,
+        report.stats.live_bytes,
+        report.stats.peak_bytes,
+        report.stats.heap_size,
+        if report.tracking { "on" } else { "off" },
+        report.untracked,
+    );
+    for site in &report.sites {
+        text.push_str(&format!("{:>10} {:>6}", site.bytes, site.count));
+        for frame in site.frames.iter().take_while(|&&frame| frame != 0) {
+            text.push_str(&format!(" {:#x}", frame));
+        }
+        text.push('\n');
+    }
+    text
+}
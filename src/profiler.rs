@@ -0,0 +1,212 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// 集計しておく異なるスタックの数 (溢れたサンプルは dropped に数える)
+const TABLE_SIZE: usize = 1024;
+/// 1サンプルに残す呼び出しの深さ
+const MAX_DEPTH: usize = 16;
+/// 割り込みハンドラ自身のフレームも含めてたどる深さ
+const WALK_DEPTH: usize = MAX_DEPTH + 8;
+
+#[derive(Clone, Copy)]
+struct Sample {
+    pid: usize,
+    depth: usize,
+    /// frames[0] が割り込まれた RIP、続いて呼び出し元
+    frames: [u64; MAX_DEPTH],
+    /// 0 なら空きスロット
+    count: u64,
+}
+
+impl Sample {
+    const EMPTY: Self = Self { pid: 0, depth: 0, frames: [0; MAX_DEPTH], count: 0 };
+
+    fn same_stack(&self, other: &Sample) -> bool {
+        self.pid == other.pid && self.frames[..self.depth] == other.frames[..other.depth]
+    }
+
+    fn hash(&self) -> usize {
+        // FNV-1a
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        for &value in core::iter::once(&(self.pid as u64)).chain(&self.frames[..self.depth]) {
+            hash = (hash ^ value).wrapping_mul(0x100_0000_01b3);
+        }
+        hash as usize
+    }
+}
+
+// タイマー割り込みから書くので、読み書きとも割り込みを止めて取る (割り込み側は try_lock)
+static SAMPLES: Mutex<[Sample; TABLE_SIZE]> = Mutex::new([Sample::EMPTY; TABLE_SIZE]);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// 何ティックに1回サンプルを取るか
+static INTERVAL: AtomicUsize = AtomicUsize::new(1);
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+static TOTAL: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// サンプリングの統計
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub running: bool,
+    pub interval_ticks: usize,
+    pub samples: u64,
+    /// テーブルが一杯かロック中で数えられなかったサンプル
+    pub dropped: u64,
+}
+
+/// interval_ticks ティックごとのサンプリングを始める
+pub fn start(interval_ticks: usize) {
+    INTERVAL.store(interval_ticks.max(1), Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+    crate::info!("profiler: sampling every {} tick(s)", interval_ticks.max(1));
+}
+
+pub fn stop() {
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+/// 集めたサンプルを捨てる
+pub fn reset() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        SAMPLES.lock().fill(Sample::EMPTY);
+    });
+    TOTAL.store(0, Ordering::SeqCst);
+    DROPPED.store(0, Ordering::SeqCst);
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        running: ENABLED.load(Ordering::SeqCst),
+        interval_ticks: INTERVAL.load(Ordering::SeqCst),
+        samples: TOTAL.load(Ordering::SeqCst),
+        dropped: DROPPED.load(Ordering::SeqCst),
+    }
+}
+
+/// タイマー割り込みから呼ばれ、割り込まれた RIP と呼び出し元を記録する
+/// ヒープは使わない (割り込まれたコードがアロケータのロックを持っていることがある)
+pub fn sample(stack_frame: &InterruptStackFrame) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if TICKS.fetch_add(1, Ordering::Relaxed) % INTERVAL.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+    TOTAL.fetch_add(1, Ordering::Relaxed);
+
+    let rip = stack_frame.instruction_pointer.as_u64();
+    let mut sample = Sample { pid: crate::process::try_current_pid().unwrap_or(0), depth: 1, ..Sample::EMPTY };
+    sample.frames[0] = rip;
+
+    // カーネルモードなら、このハンドラのフレームから割り込まれたコードのフレームへ続けてたどる
+    // (割り込みハンドラのフレームの戻りアドレスが割り込まれた RIP になっている)
+    if stack_frame.code_segment & 3 == 0 {
+        let mut walked = [0u64; WALK_DEPTH];
+        let count = crate::backtrace::collect_checked(&mut walked);
+        if let Some(position) = walked[..count].iter().position(|&addr| addr == rip) {
+            let callers = &walked[position + 1..count];
+            let depth = callers.len().min(MAX_DEPTH - 1);
+            sample.frames[1..1 + depth].copy_from_slice(&callers[..depth]);
+            sample.depth = 1 + depth;
+        }
+    }
+
+    let Some(mut samples) = SAMPLES.try_lock() else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    let start = sample.hash() % TABLE_SIZE;
+    for i in 0..TABLE_SIZE {
+        let slot = &mut samples[(start + i) % TABLE_SIZE];
+        if slot.count == 0 {
+            *slot = Sample { count: 1, ..sample };
+            return;
+        }
+        if slot.same_stack(&sample) {
+            slot.count += 1;
+            return;
+        }
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+fn snapshot() -> Vec<Sample> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        SAMPLES.lock().iter().filter(|sample| sample.count > 0).copied().collect()
+    })
+}
+
+/// アドレスを関数名にする (シンボルマップがなければ16進数)
+fn frame_name(addr: u64) -> String {
+    match crate::symbols::resolve(addr) {
+        Some(symbol) => String::from(symbol.name),
+        None => format!("{:#x}", addr),
+    }
+}
+
+/// flamegraph.pl などが読む collapsed 形式 ("pid 1;呼び出し元;...;関数 回数")
+/// 同じ関数の並びになるスタックはまとめる
+pub fn collapsed() -> String {
+    let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+    for sample in snapshot() {
+        let mut line = format!("pid {}", sample.pid);
+        for &addr in sample.frames[..sample.depth].iter().rev() {
+            line.push(';');
+            line.push_str(&frame_name(addr));
+        }
+        *stacks.entry(line).or_insert(0) += sample.count;
+    }
+
+    let mut text = String::new();
+    for (stack, count) in stacks {
+        text.push_str(&format!("{} {}\n", stack, count));
+    }
+    text
+}
+
+/// 割り込まれた関数ごとのサンプル数 (多い順)
+pub fn top_symbols() -> Vec<(String, u64)> {
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    for sample in snapshot() {
+        *counts.entry(frame_name(sample.frames[0])).or_insert(0) += sample.count;
+    }
+    let mut top: Vec<(String, u64)> = counts.into_iter().collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1));
+    top
+}
+
+/// collapsed 形式をシリアルポートへ出す (ホスト側で区切り行の間を切り出して flamegraph.pl に渡す)
+pub fn dump_serial() {
+    let stats = stats();
+    crate::serial_println!("--- profile begin ({} samples, {} dropped) ---", stats.samples, stats.dropped);
+    for line in collapsed().lines() {
+        crate::serial_println!("{}", line);
+    }
+    crate::serial_println!("--- profile end ---");
+}
+
+/// cmdline の profile オプションでサンプリングを始める
+/// profile=<秒> なら、その時間が経ったら止めてシリアルポートへ結果を出す
+/// profile_interval=<ティック> でサンプリングの間隔を変える (プロセス管理の初期化後に呼ぶ)
+pub fn init() {
+    if !crate::cmdline::has("profile") {
+        return;
+    }
+    let interval = crate::cmdline::get("profile_interval")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1);
+    start(interval);
+
+    if let Some(seconds) = crate::cmdline::get("profile").and_then(|value| value.parse::<usize>().ok()) {
+        crate::kthread::spawn("profiler", move || {
+            crate::drivers::timer::sleep_ms(seconds * 1000);
+            stop();
+            dump_serial();
+        });
+    }
+}
@@ -0,0 +1,189 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::IrqMutex;
+use crate::cpu::{self, Feature};
+
+/// ChaCha20 のブロックの大きさ
+const BLOCK_SIZE: usize = 64;
+/// 起動時に RDSEED/RDRAND から読む回数
+const INITIAL_SEED_WORDS: usize = 8;
+
+// 割り込みハンドラからもエントロピーを混ぜるので割り込みを止めて持つ
+static POOL: IrqMutex<EntropyPool> = IrqMutex::new(EntropyPool::new());
+static HAS_RDRAND: AtomicBool = AtomicBool::new(false);
+static HAS_RDSEED: AtomicBool = AtomicBool::new(false);
+
+/// 割り込みのタイミングなどを溜めるプールと、そこから鍵を作る ChaCha20 の乱数生成器
+struct EntropyPool {
+    /// まだ鍵に混ぜていないエントロピー
+    pool: [u64; 4],
+    /// 次に混ぜる pool の位置
+    cursor: usize,
+    key: [u32; 8],
+    counter: u64,
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        Self { pool: [0; 4], cursor: 0, key: [0; 8], counter: 0 }
+    }
+
+    fn mix(&mut self, value: u64) {
+        let i = self.cursor;
+        self.pool[i] = (self.pool[i] ^ value).rotate_left(23).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        self.pool[(i + 1) % 4] ^= self.pool[i] >> 29;
+        self.cursor = (i + 1) % 4;
+    }
+
+    /// 溜めたエントロピー (と CPU の乱数) を鍵に混ぜる
+    fn reseed(&mut self) {
+        for (i, value) in self.pool.iter_mut().enumerate() {
+            let value = core::mem::take(value) ^ hardware_random().unwrap_or(0);
+            self.key[i * 2] ^= value as u32;
+            self.key[i * 2 + 1] ^= (value >> 32) as u32;
+        }
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        self.reseed();
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            let block = chacha20_block(&self.key, self.counter);
+            self.counter = self.counter.wrapping_add(1);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+
+        // 出力した後に鍵を作り直し、後から過去の出力を復元できないようにする
+        let block = chacha20_block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        for (i, word) in self.key.iter_mut().enumerate() {
+            *word = u32::from_le_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+        }
+    }
+}
+
+/// CPU の乱数命令が使えるか調べ、起動時の種を集める
+pub fn init() {
+    HAS_RDRAND.store(cpu::has(Feature::Rdrand), Ordering::SeqCst);
+    HAS_RDSEED.store(cpu::has(Feature::Rdseed), Ordering::SeqCst);
+
+    let mut pool = POOL.lock();
+    for _ in 0..INITIAL_SEED_WORDS {
+        let value = hardware_random().unwrap_or(0);
+        pool.mix(value ^ rdtsc());
+    }
+    pool.mix(crate::drivers::timer::now_ns());
+    pool.reseed();
+    drop(pool);
+
+    crate::info!("Random: rdrand={} rdseed={}",
+        HAS_RDRAND.load(Ordering::SeqCst), HAS_RDSEED.load(Ordering::SeqCst));
+}
+
+/// RDSEED (なければ RDRAND) の値 (どちらも使えないか失敗したら None)
+fn hardware_random() -> Option<u64> {
+    if HAS_RDSEED.load(Ordering::Relaxed) {
+        if let Some(value) = rdseed() {
+            return Some(value);
+        }
+    }
+    if HAS_RDRAND.load(Ordering::Relaxed) {
+        return rdrand();
+    }
+    None
+}
+
+fn rdrand() -> Option<u64> {
+    // 一時的に失敗することがあるので何度か試す
+    for _ in 0..10 {
+        let (value, ok): (u64, u8);
+        unsafe {
+            core::arch::asm!("rdrand {0}", "setc {1}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn rdseed() -> Option<u64> {
+    let (value, ok): (u64, u8);
+    unsafe {
+        core::arch::asm!("rdseed {0}", "setc {1}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+    }
+    (ok != 0).then_some(value)
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// 割り込みハンドラから呼び、発生タイミングのゆらぎをプールに混ぜる
+pub fn add_interrupt_randomness(source: u64) {
+    let value = rdtsc() ^ source.rotate_left(56);
+    POOL.lock().mix(value);
+}
+
+/// 任意のデータをプールに混ぜる (/dev/random への書き込みなど)
+pub fn add_randomness(data: &[u8]) {
+    let mut pool = POOL.lock();
+    for chunk in data.chunks(8) {
+        let mut bytes = [0u8; 8];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        pool.mix(u64::from_le_bytes(bytes));
+    }
+}
+
+/// buf を乱数で埋める
+pub fn fill(buf: &mut [u8]) {
+    POOL.lock().fill(buf);
+}
+
+pub fn next_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+pub fn next_u32() -> u32 {
+    next_u64() as u32
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// ChaCha20 (RFC 7539) の1ブロック (ノンスは 0、カウンタは 64 ビット)
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u8; BLOCK_SIZE] {
+    let mut initial = [0u32; 16];
+    // "expand 32-byte k"
+    initial[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    initial[4..12].copy_from_slice(key);
+    initial[12] = counter as u32;
+    initial[13] = (counter >> 32) as u32;
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut output = [0u8; BLOCK_SIZE];
+    for (i, word) in state.iter().enumerate() {
+        let word = word.wrapping_add(initial[i]);
+        output[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    output
+}
@@ -0,0 +1,104 @@
+use crate::errno::Errno;
+
+// リソース番号 (Linux と同じ)
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_RSS: usize = 5;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIM_NLIMITS: usize = 16;
+
+/// 無制限
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// getrlimit/setrlimit がユーザー空間とやり取りする形
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RLimit {
+    /// ソフトリミット (実際に効く値)
+    pub rlim_cur: u64,
+    /// ハードリミット (ソフトリミットを上げられる上限)
+    pub rlim_max: u64,
+}
+
+impl RLimit {
+    pub const INFINITY: Self = Self { rlim_cur: RLIM_INFINITY, rlim_max: RLIM_INFINITY };
+
+    pub const fn new(rlim_cur: u64, rlim_max: u64) -> Self {
+        Self { rlim_cur, rlim_max }
+    }
+}
+
+/// プロセスごとのリソース制限 (fork で子に引き継ぐ)
+#[derive(Debug, Clone)]
+pub struct Limits {
+    limits: [RLimit; RLIM_NLIMITS],
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        let mut limits = [RLimit::INFINITY; RLIM_NLIMITS];
+        // ディスクリプタテーブルの大きさを超えては開けない
+        let max_fds = crate::fd::MAX_FDS as u64;
+        limits[RLIMIT_NOFILE] = RLimit::new(max_fds, max_fds);
+        Self { limits }
+    }
+
+    pub fn get(&self, resource: usize) -> Result<RLimit, Errno> {
+        self.limits.get(resource).copied().ok_or(Errno::EINVAL)
+    }
+
+    /// 制限を変える
+    /// ハードリミットは下げることしかできず、ソフトリミットはハードリミットまで
+    pub fn set(&mut self, resource: usize, limit: RLimit) -> Result<(), Errno> {
+        let current = self.limits.get_mut(resource).ok_or(Errno::EINVAL)?;
+        if limit.rlim_cur > limit.rlim_max {
+            return Err(Errno::EINVAL);
+        }
+        if limit.rlim_max > current.rlim_max {
+            return Err(Errno::EPERM);
+        }
+        *current = limit;
+        Ok(())
+    }
+
+    /// ソフトリミット (無制限なら u64::MAX)
+    pub fn cur(&self, resource: usize) -> u64 {
+        self.limits.get(resource).map_or(RLIM_INFINITY, |limit| limit.rlim_cur)
+    }
+
+    /// CPU時間の制限をタイマーティックに直したもの (無制限なら None)
+    pub fn cpu_ticks(&self) -> Option<usize> {
+        match self.cur(RLIMIT_CPU) {
+            RLIM_INFINITY => None,
+            seconds => Some((seconds as usize).saturating_mul(crate::drivers::timer::tick_rate())),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 現在のプロセスのソフトリミット (プロセスがなければ無制限)
+pub fn current(resource: usize) -> u64 {
+    crate::process::with_current_process(|process| process.limits.cur(resource))
+        .unwrap_or(RLIM_INFINITY)
+}
+
+pub fn getrlimit(resource: usize) -> Result<RLimit, Errno> {
+    crate::process::with_current_process(|process| process.limits.get(resource))
+        .unwrap_or_else(|| Limits::new().get(resource))
+}
+
+pub fn setrlimit(resource: usize, limit: RLimit) -> Result<(), Errno> {
+    crate::process::with_current_process(|process| process.limits.set(resource, limit))
+        .ok_or(Errno::ESRCH)?
+}
@@ -0,0 +1,457 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::smp::MAX_CPUS;
+
+/// 実行可能なスレッドの選び方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// 到着順に time slice ずつ回す
+    RoundRobin,
+    /// 優先度で重み付けした実行時間 (vruntime) が最も少ないものを選ぶ
+    Fair,
+}
+
+pub const POLICIES: [Policy; 2] = [Policy::RoundRobin, Policy::Fair];
+
+impl Policy {
+    /// コマンドラインの sched=<名前> で使う名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Policy::RoundRobin => "rr",
+            Policy::Fair => "fair",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        POLICIES.iter().copied().find(|policy| policy.name() == name)
+    }
+
+    /// ビルド時の既定値 (cargo の feature sched-fair)
+    pub fn compiled_default() -> Self {
+        if cfg!(feature = "sched-fair") { Policy::Fair } else { Policy::RoundRobin }
+    }
+
+    pub fn create(self) -> Box<dyn Scheduler> {
+        match self {
+            Policy::RoundRobin => Box::new(RoundRobin::new()),
+            Policy::Fair => Box::new(Fair::new()),
+        }
+    }
+}
+
+/// コマンドラインの sched=rr / sched=fair で選んだポリシー (読めない値なら既定値)
+/// コマンドラインの解析後に呼ぶこと
+pub fn configured_policy() -> Policy {
+    match crate::cmdline::get("sched") {
+        Some(value) => Policy::from_name(&value).unwrap_or_else(|| {
+            crate::warn!("Invalid value for sched: {} (expected rr/fair)", value);
+            Policy::compiled_default()
+        }),
+        None => Policy::compiled_default(),
+    }
+}
+
+/// スレッドを実行してよい CPU の集合 (ビット i が CPU i)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuMask(u64);
+
+/// sched_getaffinity が書き込む cpumask のバイト数
+pub const CPU_MASK_BYTES: usize = 8;
+
+impl CpuMask {
+    pub const ALL: CpuMask = CpuMask(u64::MAX >> (64 - MAX_CPUS));
+    pub const EMPTY: CpuMask = CpuMask(0);
+
+    /// MAX_CPUS 以上のビットは捨てる
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn single(cpu: usize) -> Self {
+        Self::from_bits(1u64.checked_shl(cpu as u32).unwrap_or(0))
+    }
+
+    /// スレッドを実行できる CPU (起動済みで、休ませている AP を除く。BSP は常に含む)
+    pub fn online() -> Self {
+        let bits = (1..crate::smp::cpu_count().min(MAX_CPUS))
+            .filter(|&cpu| crate::smp::cpu(cpu).is_some_and(|cpu| !cpu.is_parked()))
+            .fold(1, |bits, cpu| bits | 1 << cpu);
+        Self::from_bits(bits)
+    }
+
+    pub fn contains(self, cpu: usize) -> bool {
+        cpu < MAX_CPUS && self.0 & (1 << cpu) != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn intersect(self, other: CpuMask) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn without(self, other: CpuMask) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = usize> {
+        (0..MAX_CPUS).filter(move |&cpu| self.contains(cpu))
+    }
+}
+
+/// 新しく作るスレッドの実行先 (isolcpus で外した CPU を除く)
+static DEFAULT_AFFINITY: AtomicU64 = AtomicU64::new(CpuMask::ALL.bits());
+
+pub fn default_affinity() -> CpuMask {
+    CpuMask::from_bits(DEFAULT_AFFINITY.load(Ordering::Relaxed))
+}
+
+/// "1,3" や "2-5" の形の CPU の一覧を読む
+pub fn parse_cpu_list(list: &str) -> Option<CpuMask> {
+    let mut mask = CpuMask::EMPTY;
+    for item in list.split(',').filter(|item| !item.is_empty()) {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (first.parse::<usize>().ok()?, last.parse::<usize>().ok()?),
+            None => {
+                let cpu = item.parse::<usize>().ok()?;
+                (cpu, cpu)
+            }
+        };
+        if first > last || last >= MAX_CPUS {
+            return None;
+        }
+        for cpu in first..=last {
+            mask = CpuMask(mask.0 | CpuMask::single(cpu).0);
+        }
+    }
+    Some(mask)
+}
+
+/// コマンドラインの isolcpus=<一覧> の CPU を既定の実行先から外す
+/// (計測用の CPU に、明示的に割り当てたスレッドしか載らないようにする)
+pub fn init() {
+    let Some(value) = crate::cmdline::get("isolcpus") else {
+        return;
+    };
+    match parse_cpu_list(&value) {
+        Some(isolated) if !CpuMask::ALL.without(isolated).is_empty() => {
+            DEFAULT_AFFINITY.store(CpuMask::ALL.without(isolated).bits(), Ordering::Relaxed);
+            crate::info!("Isolated CPUs from scheduling: {}", value);
+        }
+        Some(_) => crate::warn!("isolcpus={} would leave no CPU to run on, ignored", value),
+        None => crate::warn!("Invalid value for isolcpus: {}", value),
+    }
+}
+
+/// CPUごとの実行可能キューを持ち、次に実行するスレッドを決める
+/// プロセス管理のロック下で呼ばれ、スレッドは TID で扱う
+pub trait Scheduler: Send {
+    fn policy(&self) -> Policy;
+    /// 実行可能になったスレッドを cpu のキューに積む
+    fn enqueue(&mut self, cpu: usize, tid: usize, priority: u8);
+    /// cpu で次に実行するスレッドをキューから取り出す
+    fn pick_next(&mut self, cpu: usize) -> Option<usize>;
+    /// cpu で実行中の tid に1ティック課金し、横取りすべきなら true を返す
+    fn tick(&mut self, cpu: usize, tid: usize, priority: u8) -> bool;
+    /// キューに積まれていれば取り除く
+    fn dequeue(&mut self, tid: usize) -> bool;
+    /// 終了したスレッドの記録を捨てる
+    fn forget(&mut self, tid: usize) {
+        self.dequeue(tid);
+    }
+    fn queued(&self, cpu: usize) -> usize;
+    /// 他のCPUに移すため、cpu のキューで allowed を満たすもののうち最も後回しのものを取り出す
+    fn steal_from(&mut self, cpu: usize, allowed: &dyn Fn(usize) -> bool) -> Option<usize>;
+}
+
+/// ラウンドロビンで1回に走らせるティック数
+pub const TIME_SLICE_TICKS: usize = 5;
+
+pub struct RoundRobin {
+    queues: Vec<VecDeque<usize>>,
+    /// CPUごとに実行中のスレッドの time slice の残り
+    remaining: Vec<usize>,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        Self {
+            queues: (0..MAX_CPUS).map(|_| VecDeque::new()).collect(),
+            remaining: vec![0; MAX_CPUS],
+        }
+    }
+}
+
+impl Default for RoundRobin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn policy(&self) -> Policy {
+        Policy::RoundRobin
+    }
+
+    fn enqueue(&mut self, cpu: usize, tid: usize, _priority: u8) {
+        self.queues[cpu].push_back(tid);
+    }
+
+    fn pick_next(&mut self, cpu: usize) -> Option<usize> {
+        let tid = self.queues[cpu].pop_front()?;
+        self.remaining[cpu] = TIME_SLICE_TICKS;
+        Some(tid)
+    }
+
+    fn tick(&mut self, cpu: usize, _tid: usize, _priority: u8) -> bool {
+        self.remaining[cpu] = self.remaining[cpu].saturating_sub(1);
+        self.remaining[cpu] == 0
+    }
+
+    fn dequeue(&mut self, tid: usize) -> bool {
+        let mut found = false;
+        for queue in self.queues.iter_mut() {
+            let before = queue.len();
+            queue.retain(|&queued| queued != tid);
+            found |= queue.len() != before;
+        }
+        found
+    }
+
+    fn queued(&self, cpu: usize) -> usize {
+        self.queues[cpu].len()
+    }
+
+    fn steal_from(&mut self, cpu: usize, allowed: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let index = self.queues[cpu].iter().rposition(|&tid| allowed(tid))?;
+        self.queues[cpu].remove(index)
+    }
+}
+
+/// 優先度 10 (nice 0) の重み
+const NICE_0_WEIGHT: u64 = 1024;
+/// nice -20..19 の重み (Linux の sched_prio_to_weight と同じ、1段ごとに約 1.25 倍)
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906, 3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423, 335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+/// 優先度 10 のスレッドが1ティック走ったときに進む vruntime
+const VRUNTIME_PER_TICK: u64 = 1000;
+/// 最も遅れているスレッドにこれだけ先行されたら横取りする
+const GRANULARITY: u64 = VRUNTIME_PER_TICK;
+
+/// 優先度の重み (優先度 10 が nice 0 で、数値が1つ小さいごとに約 1.25 倍)
+pub fn weight(priority: u8) -> u64 {
+    let nice = (priority as i32 - 10).clamp(-20, 19);
+    WEIGHTS[(nice + 20) as usize]
+}
+
+pub struct Fair {
+    /// CPUごとの (vruntime, TID) (先頭が最も遅れているもの)
+    queues: Vec<BTreeSet<(u64, usize)>>,
+    vruntimes: BTreeMap<usize, u64>,
+    /// CPUごとにこれまで選んだ vruntime の最大 (新しく積むスレッドの起点)
+    min_vruntime: Vec<u64>,
+}
+
+impl Fair {
+    pub fn new() -> Self {
+        Self {
+            queues: (0..MAX_CPUS).map(|_| BTreeSet::new()).collect(),
+            vruntimes: BTreeMap::new(),
+            min_vruntime: vec![0; MAX_CPUS],
+        }
+    }
+
+    pub fn vruntime(&self, tid: usize) -> Option<u64> {
+        self.vruntimes.get(&tid).copied()
+    }
+}
+
+impl Default for Fair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler for Fair {
+    fn policy(&self) -> Policy {
+        Policy::Fair
+    }
+
+    fn enqueue(&mut self, cpu: usize, tid: usize, _priority: u8) {
+        // 眠っていた分を取り返して CPU を独占しないよう、起点より大きく遅れていれば追いつかせる
+        // 新しいスレッドは起点から始める
+        let start = self.min_vruntime[cpu];
+        let vruntime = match self.vruntimes.get(&tid) {
+            Some(&vruntime) => vruntime.max(start.saturating_sub(GRANULARITY)),
+            None => start,
+        };
+        self.vruntimes.insert(tid, vruntime);
+        self.queues[cpu].insert((vruntime, tid));
+    }
+
+    fn pick_next(&mut self, cpu: usize) -> Option<usize> {
+        let (vruntime, tid) = self.queues[cpu].pop_first()?;
+        self.min_vruntime[cpu] = self.min_vruntime[cpu].max(vruntime);
+        Some(tid)
+    }
+
+    fn tick(&mut self, cpu: usize, tid: usize, priority: u8) -> bool {
+        let vruntime = self.vruntimes.entry(tid).or_insert(self.min_vruntime[cpu]);
+        *vruntime += VRUNTIME_PER_TICK * NICE_0_WEIGHT / weight(priority);
+        let current = *vruntime;
+        self.queues[cpu].first().is_some_and(|&(leftmost, _)| current > leftmost + GRANULARITY)
+    }
+
+    fn dequeue(&mut self, tid: usize) -> bool {
+        let Some(&vruntime) = self.vruntimes.get(&tid) else {
+            return false;
+        };
+        self.queues.iter_mut().any(|queue| queue.remove(&(vruntime, tid)))
+    }
+
+    fn forget(&mut self, tid: usize) {
+        self.dequeue(tid);
+        self.vruntimes.remove(&tid);
+    }
+
+    fn queued(&self, cpu: usize) -> usize {
+        self.queues[cpu].len()
+    }
+
+    fn steal_from(&mut self, cpu: usize, allowed: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let entry = *self.queues[cpu].iter().rev().find(|&&(_, tid)| allowed(tid))?;
+        self.queues[cpu].remove(&entry);
+        Some(entry.1)
+    }
+}
+
+/// スケジューラの統計 (ポリシーの待ち時間と処理量を比べる)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedStats {
+    /// スケジューラのティック
+    pub ticks: usize,
+    /// スレッドを実行に移した回数
+    pub dispatches: usize,
+    /// 実行中のスレッドを横取りして別のスレッドに切り替えた回数
+    pub preemptions: usize,
+    /// 実行可能になってから実行されるまでの待ち時間の合計と最大 (ティック)
+    pub wait_ticks: usize,
+    pub max_wait_ticks: usize,
+    /// 終えた仕事の数 (実際のスケジューラでは終了したスレッド、シミュレーションでは走り終えたバースト)
+    pub completed: usize,
+}
+
+impl SchedStats {
+    pub fn record_dispatch(&mut self, wait_ticks: usize) {
+        self.dispatches += 1;
+        self.wait_ticks += wait_ticks;
+        self.max_wait_ticks = self.max_wait_ticks.max(wait_ticks);
+    }
+
+    /// 平均の待ち時間 (1/100 ティック単位、まだ選んでいなければ 0)
+    pub fn avg_wait_centiticks(&self) -> usize {
+        match self.dispatches {
+            0 => 0,
+            dispatches => self.wait_ticks * 100 / dispatches,
+        }
+    }
+
+    /// 1000 ティックあたりに終えた仕事の数
+    pub fn throughput_per_1000_ticks(&self) -> usize {
+        match self.ticks {
+            0 => 0,
+            ticks => self.completed * 1000 / ticks,
+        }
+    }
+}
+
+/// simulate で走らせるタスク
+/// run ティック CPU を使うごとに仕事を1つ終え、sleep ティック眠る (0 なら眠らずに続ける)
+#[derive(Debug, Clone, Copy)]
+pub struct SimTask {
+    pub priority: u8,
+    pub run: usize,
+    pub sleep: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimResult {
+    pub stats: SchedStats,
+    /// タスクごとに得た CPU のティック
+    pub cpu_ticks: Vec<usize>,
+}
+
+/// 1つの CPU で tasks を ticks ティックぶん policy でスケジュールしてみる
+/// プロセス管理とは独立に、同じ負荷でポリシーを比べるためのもの (TID はタスクの添字)
+pub fn simulate(policy: Policy, tasks: &[SimTask], ticks: usize) -> SimResult {
+    const CPU: usize = 0;
+
+    let mut scheduler = policy.create();
+    let mut stats = SchedStats::default();
+    let mut cpu_ticks = vec![0; tasks.len()];
+    // 今のバーストで走ったティック、実行可能になったティック、眠りから覚めるティック
+    let mut ran = vec![0; tasks.len()];
+    let mut ready_since = vec![0; tasks.len()];
+    let mut wake_at: Vec<Option<usize>> = vec![None; tasks.len()];
+    let mut current: Option<usize> = None;
+
+    for (tid, task) in tasks.iter().enumerate() {
+        scheduler.enqueue(CPU, tid, task.priority);
+    }
+
+    for now in 0..ticks {
+        for (tid, wake) in wake_at.iter_mut().enumerate() {
+            if wake.is_some_and(|at| at <= now) {
+                *wake = None;
+                ready_since[tid] = now;
+                scheduler.enqueue(CPU, tid, tasks[tid].priority);
+            }
+        }
+
+        let mut preempted = None;
+        if let Some(tid) = current {
+            let task = &tasks[tid];
+            cpu_ticks[tid] += 1;
+            ran[tid] += 1;
+            let preempt = scheduler.tick(CPU, tid, task.priority);
+            if ran[tid] >= task.run {
+                ran[tid] = 0;
+                stats.completed += 1;
+            }
+            if ran[tid] == 0 && task.sleep > 0 {
+                wake_at[tid] = Some(now + task.sleep);
+                current = None;
+            } else if preempt {
+                ready_since[tid] = now;
+                scheduler.enqueue(CPU, tid, task.priority);
+                current = None;
+                preempted = Some(tid);
+            }
+        }
+
+        if current.is_none() {
+            if let Some(next) = scheduler.pick_next(CPU) {
+                if preempted.is_some_and(|previous| previous != next) {
+                    stats.preemptions += 1;
+                }
+                stats.record_dispatch(now - ready_since[next]);
+                current = Some(next);
+            }
+        }
+        stats.ticks += 1;
+    }
+
+    SimResult { stats, cpu_ticks }
+}
@@ -0,0 +1,53 @@
+use spin::Mutex;
+use uart_16550::SerialPort;
+
+/// COM1 の I/O ポート
+const COM1: u16 = 0x3F8;
+
+// QEMU の -serial stdio でホスト側に出力が出る
+static SERIAL1: Mutex<Option<SerialPort>> = Mutex::new(None);
+
+fn open() -> SerialPort {
+    let mut port = unsafe { SerialPort::new(COM1) };
+    port.init();
+    port
+}
+
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        let _ = serial.get_or_insert_with(open).write_fmt(args);
+    });
+}
+
+/// そのままのバイト列を出力する (パニック時のクラッシュダンプ用)
+/// 出力の途中で止まった CPU がロックを持っていても待たずに書く
+pub fn force_write(bytes: &[u8]) {
+    let mut locked = SERIAL1.try_lock();
+    let mut unlocked;
+    let port = match locked.as_mut() {
+        Some(serial) => serial.get_or_insert_with(open),
+        None => {
+            unlocked = unsafe { SerialPort::new(COM1) };
+            &mut unlocked
+        }
+    };
+    for &byte in bytes {
+        port.send_raw(byte);
+    }
+}
+
+/// シリアルポートへ出力する
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+/// シリアルポートへ改行付きで出力する
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,267 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+use crate::errno::Errno;
+use crate::filesystem::{Stat, O_CREAT, O_EXCL, O_RDWR, O_TRUNC, O_WRONLY, S_IFREG, SEEK_CUR, SEEK_END, SEEK_SET};
+use crate::memory::FRAME_SIZE;
+
+/// 1つの共有メモリオブジェクトの最大サイズ
+pub const MAX_SHM_SIZE: usize = 4 * 1024 * 1024;
+/// 名前 (先頭の '/' を含む) の最大長
+const NAME_MAX: usize = 255;
+const PAGE_SIZE: usize = FRAME_SIZE as usize;
+
+// 名前 → オブジェクト (名前も参照の1つとして持つ)
+static OBJECTS: Mutex<BTreeMap<String, Arc<SharedMemory>>> = Mutex::new(BTreeMap::new());
+
+/// 名前付き共有メモリオブジェクト
+/// 名前・ディスクリプタ・マッピングがそれぞれ Arc で参照し、すべて消えたときにフレームを解放する
+pub struct SharedMemory {
+    inner: Mutex<ShmInner>,
+    mode: u32,
+}
+
+struct ShmInner {
+    size: usize,
+    /// ページごとのフレーム (初めて触れたときに割り当てる)
+    frames: Vec<Option<PhysFrame>>,
+}
+
+impl SharedMemory {
+    fn new(mode: u32) -> Self {
+        Self {
+            inner: Mutex::new(ShmInner { size: 0, frames: Vec::new() }),
+            mode: mode & 0o777,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.lock().size
+    }
+
+    /// サイズを変える (伸ばした分はゼロとして読める)
+    /// 縮めた分はゼロ埋めするだけで、他のプロセスがマップしているかもしれないので
+    /// フレームは最後の参照が消えるまで持っておく
+    pub fn truncate(&self, size: usize) -> Result<(), Errno> {
+        if size > MAX_SHM_SIZE {
+            return Err(Errno::EFBIG);
+        }
+        let mut inner = self.inner.lock();
+        if size < inner.size {
+            let old_size = inner.size;
+            copy_range(&inner.frames, size, old_size - size, |page, _| page.fill(0));
+        }
+        let pages = size.div_ceil(PAGE_SIZE);
+        if inner.frames.len() < pages {
+            inner.frames.resize(pages, None);
+        }
+        inner.size = size;
+        Ok(())
+    }
+
+    /// offset を含むページのフレーム (まだなければゼロ埋めしたものを割り当てる)
+    /// サイズより先は EFAULT (Linux では SIGBUS になるアクセス)
+    pub fn frame(&self, offset: usize) -> Result<PhysFrame, Errno> {
+        let mut inner = self.inner.lock();
+        if offset >= inner.size {
+            return Err(Errno::EFAULT);
+        }
+        let slot = &mut inner.frames[offset / PAGE_SIZE];
+        if let Some(frame) = slot {
+            return Ok(*frame);
+        }
+        let frame = crate::memory::allocate_zeroed_frame().ok_or(Errno::ENOMEM)?;
+        *slot = Some(frame);
+        Ok(frame)
+    }
+
+    /// 割り当てていないページはゼロとして読む
+    pub fn read_at(&self, buf: &mut [u8], offset: usize) -> usize {
+        let inner = self.inner.lock();
+        if offset >= inner.size {
+            return 0;
+        }
+        let len = core::cmp::min(buf.len(), inner.size - offset);
+        buf[..len].fill(0);
+        copy_range(&inner.frames, offset, len, |page, done| {
+            buf[done..done + page.len()].copy_from_slice(page);
+        });
+        len
+    }
+
+    /// 末尾を越えて書くとその分サイズを伸ばす
+    pub fn write_at(&self, buf: &[u8], offset: usize) -> Result<usize, Errno> {
+        let end = offset.checked_add(buf.len()).ok_or(Errno::EFBIG)?;
+        if end > self.size() {
+            self.truncate(end)?;
+        }
+        // 書き込む範囲のフレームを先に用意する
+        let mut page = offset / PAGE_SIZE * PAGE_SIZE;
+        while page < end {
+            self.frame(page)?;
+            page += PAGE_SIZE;
+        }
+
+        let inner = self.inner.lock();
+        copy_range(&inner.frames, offset, buf.len(), |page, done| {
+            page.copy_from_slice(&buf[done..done + page.len()]);
+        });
+        Ok(buf.len())
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for frame in self.inner.get_mut().frames.drain(..).flatten() {
+            crate::memory::free_frame(frame);
+        }
+    }
+}
+
+/// [offset, offset + len) のうちフレームのある部分をページごとに f に渡す
+/// f には (ページ内の該当部分, 範囲の先頭からの位置) を渡す
+fn copy_range(frames: &[Option<PhysFrame>], offset: usize, len: usize, mut f: impl FnMut(&mut [u8], usize)) {
+    let mut done = 0;
+    while done < len {
+        let pos = offset + done;
+        let in_page = pos % PAGE_SIZE;
+        let chunk = core::cmp::min(PAGE_SIZE - in_page, len - done);
+        if let Some(Some(frame)) = frames.get(pos / PAGE_SIZE) {
+            let ptr = crate::memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+            let page = unsafe { core::slice::from_raw_parts_mut(ptr.add(in_page), chunk) };
+            f(page, done);
+        }
+        done += chunk;
+    }
+}
+
+/// shm_open で開いたディスクリプタが指すもの (オブジェクトと読み書き位置)
+pub struct ShmHandle {
+    shm: Arc<SharedMemory>,
+    offset: Mutex<usize>,
+    writable: bool,
+}
+
+impl ShmHandle {
+    pub fn object(&self) -> &Arc<SharedMemory> {
+        &self.shm
+    }
+
+    /// O_RDWR で開いたか (書き込み可能な MAP_SHARED には必要)
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let read = self.shm.read_at(buf, *offset);
+        *offset += read;
+        Ok(read)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if !self.writable {
+            return Err(Errno::EBADF);
+        }
+        let mut offset = self.offset.lock();
+        let written = self.shm.write_at(buf, *offset)?;
+        *offset += written;
+        Ok(written)
+    }
+
+    pub fn read_at(&self, buf: &mut [u8], offset: usize) -> Result<usize, Errno> {
+        Ok(self.shm.read_at(buf, offset))
+    }
+
+    pub fn write_at(&self, buf: &[u8], offset: usize) -> Result<usize, Errno> {
+        if !self.writable {
+            return Err(Errno::EBADF);
+        }
+        self.shm.write_at(buf, offset)
+    }
+
+    pub fn seek(&self, offset: i64, whence: i32) -> Result<usize, Errno> {
+        let mut current = self.offset.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *current as i64,
+            SEEK_END => self.shm.size() as i64,
+            _ => return Err(Errno::EINVAL),
+        };
+        let new_offset = base.checked_add(offset).filter(|&o| o >= 0).ok_or(Errno::EINVAL)?;
+        *current = new_offset as usize;
+        Ok(*current)
+    }
+
+    pub fn truncate(&self, size: usize) -> Result<(), Errno> {
+        if !self.writable {
+            return Err(Errno::EINVAL);
+        }
+        self.shm.truncate(size)
+    }
+
+    pub fn stat(&self) -> Stat {
+        Stat {
+            st_mode: S_IFREG | self.shm.mode,
+            st_nlink: 1,
+            st_size: self.shm.size() as u64,
+            ..Stat::default()
+        }
+    }
+}
+
+/// 名前は "/name" の形 (先頭以外に '/' を含まない、メッセージキューも同じ)
+pub(crate) fn check_name(name: &str) -> Result<(), Errno> {
+    if name.len() > NAME_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    match name.strip_prefix('/') {
+        Some(rest) if !rest.is_empty() && !rest.contains('/') => Ok(()),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// 名前付き共有メモリオブジェクトを開く (O_CREAT なら作る)
+/// 作った直後のサイズは 0 なので、ftruncate で大きさを決めてからマップする
+pub fn open(name: &str, flags: i32, mode: u32) -> Result<ShmHandle, Errno> {
+    check_name(name)?;
+    // 書き込み専用では開けない
+    if flags & O_WRONLY != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let writable = flags & O_RDWR != 0;
+
+    let shm = {
+        let mut objects = OBJECTS.lock();
+        match objects.get(name) {
+            Some(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(Errno::EEXIST),
+            Some(shm) => shm.clone(),
+            None if flags & O_CREAT != 0 => {
+                let shm = Arc::new(SharedMemory::new(mode));
+                objects.insert(String::from(name), shm.clone());
+                shm
+            }
+            None => return Err(Errno::ENOENT),
+        }
+    };
+
+    if flags & O_TRUNC != 0 {
+        if !writable {
+            return Err(Errno::EACCES);
+        }
+        shm.truncate(0)?;
+    }
+    Ok(ShmHandle { shm, offset: Mutex::new(0), writable })
+}
+
+/// 名前を消す (開いているディスクリプタやマッピングは有効なまま)
+pub fn unlink(name: &str) -> Result<(), Errno> {
+    check_name(name)?;
+    let removed = OBJECTS.lock().remove(name).ok_or(Errno::ENOENT)?;
+    // 最後の参照ならロックを外したここでフレームが解放される
+    drop(removed);
+    Ok(())
+}
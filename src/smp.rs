@@ -0,0 +1,346 @@
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::PageTableFlags as Flags;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// サポートするCPU数の上限
+pub const MAX_CPUS: usize = 16;
+
+/// AP 起動用トランポリンを置く物理アドレス (1 MiB 未満かつ 4 KiB 境界)
+/// ブートローダが使っていた領域だが、カーネルに制御が移った後は不要
+const TRAMPOLINE_ADDR: u64 = 0x8000;
+const AP_STACK_SIZE: usize = 4096 * 4;
+
+// リアルモードで起動した AP を直接ロングモードに移行させ、ap_entry を呼ぶ
+// TRAMPOLINE_ADDR にコピーして実行するので、絶対アドレスはそこを基準に計算する
+global_asm!(r#"
+    .pushsection .rodata.ap_trampoline, "a"
+    .code16
+    .global ap_trampoline_start
+ap_trampoline_start:
+    cli
+    cld
+    xorw %ax, %ax
+    movw %ax, %ds
+    lgdtl (0x8000 + (ap_trampoline_gdt_ptr - ap_trampoline_start))
+
+    # PAE を有効化し、BSP と同じページテーブルを使う
+    movl %cr4, %eax
+    orl $(1 << 5), %eax
+    movl %eax, %cr4
+    movl (0x8000 + (ap_trampoline_cr3 - ap_trampoline_start)), %eax
+    movl %eax, %cr3
+
+    # EFER.LME と EFER.NXE
+    movl $0xC0000080, %ecx
+    rdmsr
+    orl $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+
+    # PE と PG を同時に立ててロングモードへ
+    movl %cr0, %eax
+    orl $0x80000001, %eax
+    movl %eax, %cr0
+    ljmpl $0x08, $(0x8000 + (ap_trampoline_long - ap_trampoline_start))
+
+    .code64
+ap_trampoline_long:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    movq (0x8000 + (ap_trampoline_stack - ap_trampoline_start)), %rsp
+    movq (0x8000 + (ap_trampoline_entry - ap_trampoline_start)), %rax
+    callq *%rax
+    ud2
+
+    .align 8
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00af9a000000ffff
+    .quad 0x00cf92000000ffff
+ap_trampoline_gdt_ptr:
+    .word ap_trampoline_gdt_ptr - ap_trampoline_gdt - 1
+    .long 0x8000 + (ap_trampoline_gdt - ap_trampoline_start)
+
+    .align 8
+    .global ap_trampoline_cr3
+ap_trampoline_cr3:
+    .quad 0
+    .global ap_trampoline_stack
+ap_trampoline_stack:
+    .quad 0
+    .global ap_trampoline_entry
+ap_trampoline_entry:
+    .quad 0
+    .global ap_trampoline_end
+ap_trampoline_end:
+    .popsection
+"#, options(att_syntax));
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// CPUごとのデータ領域
+/// カーネルで動いている間は GS ベースがこの構造体を指し、先頭に自分自身へのポインタを置く
+/// リング3で動いている間は swapgs で KernelGsBase に退避し、GS ベースはユーザーのものにする
+/// (ユーザーが GS を書き換えても、カーネルに入るときの swapgs で必ずこちらに戻る)
+#[repr(C)]
+pub struct PerCpu {
+    self_ptr: AtomicU64,
+    /// リング3から入るときのカーネルスタックのトップ (syscall の入口が GS 相対で読む)
+    kernel_stack: AtomicU64,
+    /// syscall の入口でユーザーの rsp を一時的に置く場所
+    user_rsp: AtomicU64,
+    /// run_user_program が待っていれば、そこへ戻るためのスタックポインタ (待っていなければ 0)
+    user_return_rsp: AtomicU64,
+    id: AtomicUsize,
+    apic_id: AtomicU32,
+    online: AtomicBool,
+    /// 割り込みを止めて休ませている AP か
+    parked: AtomicBool,
+}
+
+/// syscall の入口スタブが使う PerCpu のフィールドのオフセット
+pub const PER_CPU_KERNEL_STACK: usize = core::mem::offset_of!(PerCpu, kernel_stack);
+pub const PER_CPU_USER_RSP: usize = core::mem::offset_of!(PerCpu, user_rsp);
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            self_ptr: AtomicU64::new(0),
+            kernel_stack: AtomicU64::new(0),
+            user_rsp: AtomicU64::new(0),
+            user_return_rsp: AtomicU64::new(0),
+            id: AtomicUsize::new(0),
+            apic_id: AtomicU32::new(0),
+            online: AtomicBool::new(false),
+            parked: AtomicBool::new(false),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id.load(Ordering::SeqCst)
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::SeqCst)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+
+    /// 起動はしたがスレッドの実行も割り込みの処理もしていない AP か (IPI を送っても応答しない)
+    pub fn is_parked(&self) -> bool {
+        self.parked.load(Ordering::SeqCst)
+    }
+
+    pub fn user_return_rsp(&self) -> &AtomicU64 {
+        &self.user_return_rsp
+    }
+}
+
+static PER_CPU: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+// 起動中の AP に渡す CPU 番号
+static BOOTING_CPU: AtomicUsize = AtomicUsize::new(0);
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// 現在のCPUの GS ベースを per-CPU 領域に、リング3に入るときの GS ベースを 0 に設定する
+fn install_per_cpu(id: usize, apic_id: u32) {
+    let cpu = &PER_CPU[id];
+    cpu.self_ptr.store(cpu as *const PerCpu as u64, Ordering::SeqCst);
+    cpu.id.store(id, Ordering::SeqCst);
+    cpu.apic_id.store(apic_id, Ordering::SeqCst);
+    GsBase::write(VirtAddr::from_ptr(cpu as *const PerCpu));
+    KernelGsBase::write(VirtAddr::zero());
+    cpu.online.store(true, Ordering::SeqCst);
+}
+
+/// 現在のCPUの per-CPU 領域
+pub fn current_cpu() -> &'static PerCpu {
+    let ptr: u64;
+    unsafe {
+        core::arch::asm!("mov {}, gs:[0]", out(reg) ptr, options(nostack, readonly, preserves_flags));
+    }
+    if ptr == 0 {
+        // smp::init 前は BSP とみなす
+        return &PER_CPU[0];
+    }
+    unsafe { &*(ptr as *const PerCpu) }
+}
+
+/// リング3から割り込み・例外で入ったときに swapgs で GS ベースを per-CPU 領域に切り替え、
+/// ハンドラから戻るときに (drop で) ユーザーのものに戻す
+/// current_cpu より先に使うよう、ハンドラの先頭で作ること
+pub struct KernelGs(bool);
+
+impl KernelGs {
+    #[inline(always)]
+    pub fn enter(stack_frame: &InterruptStackFrame) -> Self {
+        let from_user = stack_frame.code_segment & 3 == 3;
+        if from_user {
+            unsafe { core::arch::asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+        Self(from_user)
+    }
+}
+
+impl Drop for KernelGs {
+    #[inline(always)]
+    fn drop(&mut self) {
+        if self.0 {
+            unsafe { core::arch::asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
+
+pub fn cpu_id() -> usize {
+    current_cpu().id()
+}
+
+/// リング3からこのCPUに入るときのカーネルスタック (割り込みの TSS.RSP0 と syscall の入口) を設定する
+pub fn set_kernel_stack(stack_top: VirtAddr) {
+    crate::gdt::set_kernel_stack(stack_top);
+    current_cpu().kernel_stack.store(stack_top.as_u64(), Ordering::SeqCst);
+}
+
+pub fn cpu(id: usize) -> Option<&'static PerCpu> {
+    PER_CPU.get(id).filter(|cpu| cpu.is_online())
+}
+
+/// 起動済みのCPU数
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::SeqCst)
+}
+
+fn copy_trampoline() -> Result<(), &'static str> {
+    let start = core::ptr::addr_of!(ap_trampoline_start) as usize;
+    let end = core::ptr::addr_of!(ap_trampoline_end) as usize;
+    let size = end - start;
+
+    // ページングを有効にした直後もトランポリンを実行できるよう恒等マップする
+    crate::memory::identity_map_page(PhysAddr::new(TRAMPOLINE_ADDR), Flags::WRITABLE)?;
+
+    let dst = crate::memory::phys_to_virt(PhysAddr::new(TRAMPOLINE_ADDR)).as_mut_ptr::<u8>();
+    unsafe {
+        core::ptr::copy_nonoverlapping(start as *const u8, dst, size);
+    }
+    Ok(())
+}
+
+/// トランポリン内の 64bit パラメータを書き込む
+fn set_trampoline_param(symbol: *const u8, value: u64) {
+    let start = core::ptr::addr_of!(ap_trampoline_start) as u64;
+    let offset = symbol as u64 - start;
+    let addr = crate::memory::phys_to_virt(PhysAddr::new(TRAMPOLINE_ADDR + offset));
+    unsafe {
+        core::ptr::write_volatile(addr.as_mut_ptr::<u64>(), value);
+    }
+}
+
+fn start_ap(id: usize, apic_id: u32) -> bool {
+    use alloc::vec;
+
+    let stack: &'static mut [u8] = vec![0u8; AP_STACK_SIZE].leak();
+    let stack_top = (stack.as_ptr() as u64 + AP_STACK_SIZE as u64) & !0xF;
+    set_trampoline_param(core::ptr::addr_of!(ap_trampoline_stack), stack_top);
+
+    BOOTING_CPU.store(id, Ordering::SeqCst);
+    AP_STARTED.store(false, Ordering::SeqCst);
+
+    // INIT-SIPI-SIPI シーケンス
+    crate::apic::send_init(apic_id);
+    crate::drivers::timer::sleep_ms(10);
+    for _ in 0..2 {
+        crate::apic::send_startup(apic_id, (TRAMPOLINE_ADDR >> 12) as u8);
+        crate::drivers::timer::sleep_us(200);
+        if AP_STARTED.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+
+    // 最大 100ms 待つ
+    let deadline = crate::drivers::timer::get_uptime_ms() + 100;
+    while !AP_STARTED.load(Ordering::SeqCst) {
+        if crate::drivers::timer::get_uptime_ms() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// アプリケーションプロセッサのエントリポイント (トランポリンから呼ばれる)
+extern "C" fn ap_entry() -> ! {
+    let id = BOOTING_CPU.load(Ordering::SeqCst);
+
+    crate::gdt::init_ap(id);
+    crate::interrupts::load_idt();
+    crate::uaccess::init_ap();
+    crate::fpu::init_ap();
+    crate::apic::init_ap();
+    install_per_cpu(id, crate::apic::lapic_id());
+
+    PER_CPU[id].parked.store(true, Ordering::SeqCst);
+    AP_STARTED.store(true, Ordering::SeqCst);
+    park()
+}
+
+/// AP は割り込みを止めたまま休ませる
+/// スケジューラはまだ AP でスレッドを切り替えられないので、割り込み (再スケジューリングの IPI など) を
+/// 受けるとスレッドを実行中にしたまま放置してしまう。CpuMask::online にも数えず、IPI も送らない
+/// (TLB も無効化しないので、起こすときは TLB を消してから始めること)
+fn park() -> ! {
+    loop {
+        x86_64::instructions::interrupts::disable();
+        x86_64::instructions::hlt();
+    }
+}
+
+/// MADT に列挙された AP を起動する
+pub fn init() -> Result<usize, &'static str> {
+    install_per_cpu(0, crate::apic::lapic_id());
+
+    if !crate::config::enabled(crate::config::Feature::Smp) {
+        return Err("Disabled by config");
+    }
+    if !crate::apic::is_enabled() {
+        return Err("APIC not enabled");
+    }
+    let madt = crate::acpi::madt().ok_or("MADT not available")?;
+
+    copy_trampoline()?;
+    let (cr3, _) = x86_64::registers::control::Cr3::read();
+    // トランポリンは 32bit で CR3 をロードするため 4 GiB 未満である必要がある
+    if cr3.start_address().as_u64() > u32::MAX as u64 {
+        return Err("Page table above 4 GiB");
+    }
+    set_trampoline_param(core::ptr::addr_of!(ap_trampoline_cr3), cr3.start_address().as_u64());
+    set_trampoline_param(core::ptr::addr_of!(ap_trampoline_entry), ap_entry as usize as u64);
+
+    let bsp_apic_id = crate::apic::lapic_id();
+    for info in madt.cpus.iter().filter(|cpu| cpu.enabled && cpu.apic_id != bsp_apic_id) {
+        let id = CPU_COUNT.load(Ordering::SeqCst);
+        if id >= MAX_CPUS {
+            crate::warn!("Too many CPUs, ignoring APIC ID {}", info.apic_id);
+            break;
+        }
+        if start_ap(id, info.apic_id) {
+            CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+            crate::info!("CPU {} (APIC ID {}) online", id, info.apic_id);
+        } else {
+            crate::warn!("CPU with APIC ID {} did not respond", info.apic_id);
+        }
+    }
+
+    Ok(cpu_count())
+}
@@ -0,0 +1,272 @@
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::drivers::block::{self, SECTOR_SIZE};
+use crate::drivers::partition::crc32;
+use crate::errno::Errno;
+use crate::filesystem::{self, FileMode, FileType, Inode};
+
+/// スナップショットの先頭に置く識別子
+const MAGIC: &[u8; 8] = b"RSNAPSHT";
+const VERSION: u32 = 1;
+/// magic, version, レコード数, レコード部の長さ, レコード部の CRC-32
+const HEADER_SIZE: usize = 28;
+/// レコードの tag と length
+const RECORD_HEADER_SIZE: usize = 5;
+/// レコードの値のうちパスより前の部分 (mode, uid, gid, mtime, パスの長さ)
+const ENTRY_HEADER_SIZE: usize = 22;
+
+// レコードの種類 (tag)
+const TAG_DIRECTORY: u8 = 1;
+const TAG_FILE: u8 = 2;
+const TAG_SYMLINK: u8 = 3;
+const TAG_DEVICE: u8 = 4;
+
+/// 保存しないディレクトリ (中身は起動のたびに作り直される)
+const SKIPPED_PATHS: [&str; 2] = ["/proc", "/dev"];
+
+/// persist が書き込むブロックデバイス (コマンドラインの persist=<デバイス名>)
+static TARGET: Mutex<Option<usize>> = Mutex::new(None);
+
+/// 復元した結果
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub restored: usize,
+    pub skipped: usize,
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// inode 1つ分のレコード (tag, length, 値) を out に足す
+/// 値は mode, uid, gid, mtime, パスの長さ, パス, 内容 (ファイルの中身・リンク先・デバイス番号)
+fn push_record(out: &mut Vec<u8>, path: &str, inode: &Inode) {
+    let (tag, payload): (u8, &[u8]) = match inode.file_type {
+        FileType::Directory => (TAG_DIRECTORY, &[]),
+        FileType::Regular => (TAG_FILE, &inode.data),
+        FileType::Symlink => (TAG_SYMLINK, &inode.data),
+        FileType::Device => (TAG_DEVICE, &[]),
+    };
+    let rdev = inode.rdev.to_le_bytes();
+    let payload = if tag == TAG_DEVICE { &rdev[..] } else { payload };
+
+    out.push(tag);
+    push_u32(out, (ENTRY_HEADER_SIZE + path.len() + payload.len()) as u32);
+    push_u32(out, inode.mode.to_bits());
+    push_u32(out, inode.uid);
+    push_u32(out, inode.gid);
+    push_u64(out, inode.mtime);
+    push_u16(out, path.len() as u16);
+    out.extend_from_slice(path.as_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// ramfs の inode の木を TLV 形式のスナップショットにする
+/// /proc と /dev は保存しない。ハードリンクはそれぞれ別のファイルとして保存する
+pub fn serialize() -> Result<Vec<u8>, Errno> {
+    let mut records = Vec::new();
+    let mut count = 0u32;
+    filesystem::with_fs(|fs| {
+        fs.visit(&mut |path, inode| {
+            if SKIPPED_PATHS.contains(&path) {
+                return false;
+            }
+            // ルートは復元先に必ずあるので保存しない
+            if path != "/" {
+                push_record(&mut records, path, inode);
+                count += 1;
+            }
+            true
+        });
+        Ok(())
+    })?;
+
+    let mut image = Vec::with_capacity(HEADER_SIZE + records.len());
+    image.extend_from_slice(MAGIC);
+    push_u32(&mut image, VERSION);
+    push_u32(&mut image, count);
+    push_u64(&mut image, records.len() as u64);
+    push_u32(&mut image, crc32(&records));
+    image.extend_from_slice(&records);
+    Ok(image)
+}
+
+/// ヘッダを調べ、スナップショット全体の長さを返す
+fn parse_header(header: &[u8]) -> Result<usize, &'static str> {
+    if header.len() < HEADER_SIZE || &header[0..8] != MAGIC {
+        return Err("No snapshot");
+    }
+    if u32_at(header, 8) != VERSION {
+        return Err("Unsupported snapshot version");
+    }
+    let length = usize::try_from(u64_at(header, 16)).map_err(|_| "Snapshot too large")?;
+    HEADER_SIZE.checked_add(length).ok_or("Snapshot too large")
+}
+
+/// レコード1つを ramfs に作る (既にあるディレクトリとファイルは属性と中身を上書きする)
+fn restore_entry(tag: u8, value: &[u8]) -> Result<(), Errno> {
+    if value.len() < ENTRY_HEADER_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let mode = FileMode::from_bits(u32_at(value, 0));
+    let (uid, gid, mtime) = (u32_at(value, 4), u32_at(value, 8), u64_at(value, 12));
+    let path_len = u16_at(value, 20) as usize;
+    let path = value.get(ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + path_len).ok_or(Errno::EINVAL)?;
+    let path = core::str::from_utf8(path).map_err(|_| Errno::EINVAL)?;
+    let payload = &value[ENTRY_HEADER_SIZE + path_len..];
+
+    filesystem::with_fs(|fs| {
+        let existing = fs.lookup_nofollow(path);
+        let inode_num = match tag {
+            TAG_DIRECTORY => match existing {
+                Ok(inode_num) => inode_num,
+                Err(_) => fs.mkdir(path, mode)?,
+            },
+            TAG_FILE => fs.install_file(path, payload, mode)?,
+            TAG_SYMLINK if existing.is_err() => {
+                fs.symlink(core::str::from_utf8(payload).map_err(|_| Errno::EINVAL)?, path)?
+            }
+            TAG_DEVICE if existing.is_err() => {
+                let rdev = payload.get(..4).ok_or(Errno::EINVAL)?;
+                fs.mknod(path, mode, u32::from_le_bytes(rdev.try_into().unwrap()))?
+            }
+            TAG_SYMLINK | TAG_DEVICE => return Err(Errno::EEXIST),
+            _ => return Err(Errno::EINVAL),
+        };
+        let inode = fs.inode_mut(inode_num).ok_or(Errno::EIO)?;
+        if inode.file_type == FileType::Directory {
+            inode.mode = mode;
+        }
+        inode.uid = uid;
+        inode.gid = gid;
+        inode.mtime = mtime;
+        Ok(())
+    })
+}
+
+/// スナップショットを ramfs に展開する
+/// 親ディレクトリは子より前に並んでいるので、先頭から順に作ればよい
+pub fn restore(image: &[u8]) -> Result<Summary, &'static str> {
+    let total = parse_header(image)?;
+    let records = image.get(HEADER_SIZE..total).ok_or("Truncated snapshot")?;
+    if crc32(records) != u32_at(image, 24) {
+        return Err("Snapshot checksum mismatch");
+    }
+
+    let mut summary = Summary::default();
+    let mut offset = 0;
+    while offset < records.len() {
+        let header = records.get(offset..offset + RECORD_HEADER_SIZE).ok_or("Truncated snapshot record")?;
+        let length = u32_at(header, 1) as usize;
+        let start = offset + RECORD_HEADER_SIZE;
+        let value = records.get(start..start + length).ok_or("Truncated snapshot record")?;
+        match restore_entry(header[0], value) {
+            Ok(()) => summary.restored += 1,
+            Err(e) => {
+                crate::debug!("snapshot: record at {}: {}", offset, e);
+                summary.skipped += 1;
+            }
+        }
+        offset = start + length;
+    }
+    Ok(summary)
+}
+
+/// スナップショットを index 番のブロックデバイスの先頭に書き、書いたバイト数を返す
+pub fn save_to(index: usize) -> Result<usize, &'static str> {
+    let image = serialize().map_err(|_| "Filesystem unavailable")?;
+    let device = block::get(index).ok_or("No such block device")?;
+    if image.len() > device.sector_count() as usize * SECTOR_SIZE {
+        return Err("Snapshot does not fit on the device");
+    }
+    block::write_bytes(index, 0, &image)?;
+    crate::drivers::page_cache::sync()?;
+    Ok(image.len())
+}
+
+/// index 番のブロックデバイスの先頭にあるスナップショットを ramfs に展開する
+pub fn load_from(index: usize) -> Result<Summary, &'static str> {
+    let mut header = [0u8; HEADER_SIZE];
+    if block::read_bytes(index, 0, &mut header)? < HEADER_SIZE {
+        return Err("No snapshot");
+    }
+    let total = parse_header(&header)?;
+    let device = block::get(index).ok_or("No such block device")?;
+    if total > device.sector_count() as usize * SECTOR_SIZE {
+        return Err("Truncated snapshot");
+    }
+    let mut image = Vec::new();
+    image.try_reserve_exact(total).map_err(|_| "Out of memory")?;
+    image.resize(total, 0);
+    block::read_bytes(index, 0, &mut image)?;
+    restore(&image)
+}
+
+/// persist コマンド: 設定されたデバイスに今の ramfs を保存する
+pub fn persist() -> Result<usize, &'static str> {
+    let index = (*TARGET.lock()).ok_or("No persist device")?;
+    let written = save_to(index)?;
+    crate::info!("ramfs snapshot saved ({} bytes)", written);
+    Ok(written)
+}
+
+pub fn is_configured() -> bool {
+    TARGET.lock().is_some()
+}
+
+/// ブートモジュールとコマンドラインの persist=<デバイス名> からスナップショットを復元する
+/// (ブロックデバイスのドライバの初期化後に呼ぶ)
+pub fn init() -> Result<Summary, &'static str> {
+    let mut total = Summary::default();
+    let mut found = false;
+    for module in crate::boot::modules() {
+        if module.data().starts_with(MAGIC) {
+            let summary = restore(module.data())?;
+            crate::info!("snapshot '{}': {} entries restored", module.cmdline, summary.restored);
+            total.restored += summary.restored;
+            total.skipped += summary.skipped;
+            found = true;
+        }
+    }
+
+    if let Some(name) = crate::cmdline::get("persist") {
+        let index = block::find(&name).ok_or("Persist device not found")?;
+        *TARGET.lock() = Some(index);
+        // まだ何も保存していないデバイスなら空のまま始める
+        match load_from(index) {
+            Ok(summary) => {
+                crate::info!("snapshot {}: {} entries restored", name, summary.restored);
+                total.restored += summary.restored;
+                total.skipped += summary.skipped;
+                found = true;
+            }
+            Err(e) => crate::info!("snapshot {}: {}", name, e),
+        }
+    }
+
+    if !found {
+        return Err("No snapshot");
+    }
+    Ok(total)
+}
+
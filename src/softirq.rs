@@ -0,0 +1,140 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use spin::Mutex;
+use crate::smp::{cpu_id, MAX_CPUS};
+
+/// 割り込みの後半処理 (ボトムハーフ) の種類
+/// 割り込みハンドラは raise で印を付けるだけにし、重い処理は割り込み出口で行う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Softirq {
+    /// スケジューラのティック・コンソールの反映
+    Timer = 0,
+    /// スキャンコードのデコード
+    Keyboard = 1,
+    /// schedule_work で積まれた作業
+    Work = 2,
+    /// イベントバスの配送
+    Events = 3,
+}
+
+const NR_SOFTIRQS: usize = 4;
+/// 作業キューの大きさ (割り込み中に確保しないよう固定長)
+const WORK_QUEUE_SIZE: usize = 64;
+/// 1回の割り込み出口で処理し直す最大回数 (処理中に再び raise され続けた場合)
+const MAX_RESTART: usize = 10;
+
+// CPUごとの保留中ビット (ビット n が Softirq n)
+static PENDING: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+// CPUごとに後半処理を実行中か (ネストした割り込みの出口では実行しない)
+static ACTIVE: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+static HANDLERS: Mutex<[Option<fn()>; NR_SOFTIRQS]> = Mutex::new([None; NR_SOFTIRQS]);
+static WORK_QUEUE: Mutex<WorkQueue> = Mutex::new(WorkQueue::new());
+
+/// 後で実行する関数と引数
+#[derive(Clone, Copy)]
+struct Work {
+    func: fn(usize),
+    arg: usize,
+}
+
+/// 固定長のリングバッファ
+struct WorkQueue {
+    items: [Option<Work>; WORK_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl WorkQueue {
+    const fn new() -> Self {
+        Self { items: [None; WORK_QUEUE_SIZE], head: 0, len: 0 }
+    }
+
+    fn push(&mut self, work: Work) -> bool {
+        if self.len == WORK_QUEUE_SIZE {
+            return false;
+        }
+        self.items[(self.head + self.len) % WORK_QUEUE_SIZE] = Some(work);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Work> {
+        if self.len == 0 {
+            return None;
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % WORK_QUEUE_SIZE;
+        self.len -= 1;
+        work
+    }
+}
+
+pub fn init() {
+    open(Softirq::Work, run_work);
+}
+
+/// 後半処理のハンドラを登録する
+pub fn open(softirq: Softirq, handler: fn()) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        HANDLERS.lock()[softirq as usize] = Some(handler);
+    });
+}
+
+/// このCPUで後半処理を保留にする (割り込みハンドラから呼ぶ)
+pub fn raise(softirq: Softirq) {
+    PENDING[cpu_id()].fetch_or(1 << softirq as u32, Ordering::SeqCst);
+}
+
+/// 関数を後半処理として実行するよう積む (キューが一杯なら false)
+pub fn schedule_work(func: fn(usize), arg: usize) -> bool {
+    let queued = x86_64::instructions::interrupts::without_interrupts(|| {
+        WORK_QUEUE.lock().push(Work { func, arg })
+    });
+    if queued {
+        raise(Softirq::Work);
+    }
+    queued
+}
+
+fn run_work() {
+    loop {
+        let work = x86_64::instructions::interrupts::without_interrupts(|| WORK_QUEUE.lock().pop());
+        match work {
+            Some(work) => (work.func)(work.arg),
+            None => break,
+        }
+    }
+}
+
+/// 割り込みハンドラの最後 (EOI の後) に呼ぶ
+/// 保留中の後半処理を割り込みを許可した状態で実行し、割り込み禁止に戻して返る
+pub fn irq_exit() {
+    let cpu = cpu_id();
+    if PENDING[cpu].load(Ordering::SeqCst) == 0 {
+        return;
+    }
+    // ネストした割り込みなら外側の irq_exit に任せる
+    if ACTIVE[cpu].swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    for _ in 0..MAX_RESTART {
+        let pending = PENDING[cpu].swap(0, Ordering::SeqCst);
+        if pending == 0 {
+            break;
+        }
+        let handlers = *HANDLERS.lock();
+
+        x86_64::instructions::interrupts::enable();
+        for (i, handler) in handlers.iter().enumerate() {
+            if pending & (1 << i) != 0 {
+                if let Some(handler) = handler {
+                    handler();
+                }
+            }
+        }
+        x86_64::instructions::interrupts::disable();
+    }
+
+    ACTIVE[cpu].store(false, Ordering::SeqCst);
+}
@@ -0,0 +1,178 @@
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::syscall::*;
+
+/// トレースで表示するパス名の最大長
+const MAX_TRACE_PATH: usize = 64;
+
+// すべてのプロセスのシステムコールをトレースするか
+static TRACE_ALL: AtomicBool = AtomicBool::new(false);
+
+/// 引数の表示方法
+#[derive(Clone, Copy)]
+enum Arg {
+    /// ファイルディスクリプタ・整数
+    Int,
+    /// バイト数
+    Size,
+    /// NUL終端のパス名
+    Path,
+    /// ポインタ・フラグ
+    Hex,
+    /// パーミッション
+    Mode,
+}
+
+/// システムコール名と引数の並び
+fn signature(number: u64) -> Option<(&'static str, &'static [Arg])> {
+    use Arg::*;
+
+    Some(match number {
+        SYS_READ => ("read", &[Int, Hex, Size]),
+        SYS_WRITE => ("write", &[Int, Hex, Size]),
+        SYS_PREAD64 => ("pread64", &[Int, Hex, Size, Int]),
+        SYS_PWRITE64 => ("pwrite64", &[Int, Hex, Size, Int]),
+        SYS_READV => ("readv", &[Int, Hex, Int]),
+        SYS_WRITEV => ("writev", &[Int, Hex, Int]),
+        SYS_OPEN => ("open", &[Path, Hex, Mode]),
+        SYS_CLOSE => ("close", &[Int]),
+        SYS_STAT => ("stat", &[Path, Hex]),
+        SYS_FSTAT => ("fstat", &[Int, Hex]),
+        SYS_STATFS => ("statfs", &[Path, Hex]),
+        SYS_LSEEK => ("lseek", &[Int, Int, Int]),
+        SYS_MMAP => ("mmap", &[Hex, Size, Hex, Hex, Int, Int]),
+        SYS_MUNMAP => ("munmap", &[Hex, Size]),
+        SYS_BRK => ("brk", &[Hex]),
+        SYS_DUP => ("dup", &[Int]),
+        SYS_DUP2 => ("dup2", &[Int, Int]),
+        SYS_SLEEP => ("sleep", &[Int]),
+        SYS_GETPID => ("getpid", &[]),
+        SYS_GETPPID => ("getppid", &[]),
+        SYS_GETTID => ("gettid", &[]),
+        SYS_GETUID => ("getuid", &[]),
+        SYS_GETGID => ("getgid", &[]),
+        SYS_GETEUID => ("geteuid", &[]),
+        SYS_GETEGID => ("getegid", &[]),
+        SYS_SETUID => ("setuid", &[Int]),
+        SYS_SETGID => ("setgid", &[Int]),
+        SYS_SOCKET => ("socket", &[Int, Int, Int]),
+        SYS_CONNECT => ("connect", &[Int, Hex, Size]),
+        SYS_ACCEPT => ("accept", &[Int, Hex, Hex]),
+        SYS_SENDTO => ("sendto", &[Int, Hex, Size, Hex, Hex, Size]),
+        SYS_RECVFROM => ("recvfrom", &[Int, Hex, Size, Hex, Hex, Hex]),
+        SYS_BIND => ("bind", &[Int, Hex, Size]),
+        SYS_LISTEN => ("listen", &[Int, Int]),
+        SYS_FORK => ("fork", &[]),
+        SYS_CLONE => ("clone", &[Hex, Hex, Hex, Hex, Hex]),
+        SYS_ARCH_PRCTL => ("arch_prctl", &[Hex, Hex]),
+        SYS_EXECVE => ("execve", &[Path, Hex, Hex]),
+        SYS_EXIT => ("exit", &[Int]),
+        SYS_GETCWD => ("getcwd", &[Hex, Size]),
+        SYS_CHDIR => ("chdir", &[Path]),
+        SYS_RENAME => ("rename", &[Path, Path]),
+        SYS_MKDIR => ("mkdir", &[Path, Mode]),
+        SYS_RMDIR => ("rmdir", &[Path]),
+        SYS_LINK => ("link", &[Path, Path]),
+        SYS_UNLINK => ("unlink", &[Path]),
+        SYS_SYMLINK => ("symlink", &[Path, Path]),
+        SYS_READLINK => ("readlink", &[Path, Hex, Size]),
+        SYS_OPENAT => ("openat", &[Int, Path, Hex, Mode]),
+        SYS_MKDIRAT => ("mkdirat", &[Int, Path, Mode]),
+        SYS_NEWFSTATAT => ("newfstatat", &[Int, Path, Hex, Hex]),
+        SYS_UNLINKAT => ("unlinkat", &[Int, Path, Hex]),
+        SYS_RENAMEAT => ("renameat", &[Int, Path, Int, Path]),
+        SYS_READLINKAT => ("readlinkat", &[Int, Path, Hex, Size]),
+        SYS_GETDENTS64 => ("getdents64", &[Int, Hex, Size]),
+        SYS_GETRANDOM => ("getrandom", &[Hex, Size, Hex]),
+        SYS_FTRUNCATE => ("ftruncate", &[Int, Size]),
+        SYS_SHM_OPEN => ("shm_open", &[Path, Hex, Mode]),
+        SYS_SHM_UNLINK => ("shm_unlink", &[Path]),
+        SYS_POLL => ("poll", &[Hex, Int, Int]),
+        SYS_FCNTL => ("fcntl", &[Int, Int, Hex]),
+        SYS_MQ_OPEN => ("mq_open", &[Path, Hex, Mode, Hex]),
+        SYS_MQ_UNLINK => ("mq_unlink", &[Path]),
+        SYS_MQ_TIMEDSEND => ("mq_timedsend", &[Int, Hex, Size, Int, Hex]),
+        SYS_MQ_TIMEDRECEIVE => ("mq_timedreceive", &[Int, Hex, Size, Hex, Hex]),
+        SYS_MQ_GETSETATTR => ("mq_getsetattr", &[Int, Hex, Hex]),
+        SYS_SYNC => ("sync", &[]),
+        SYS_REBOOT => ("reboot", &[Hex, Hex, Hex]),
+        SYS_FAULT_INJECT => ("fault_inject", &[Int, Size, Size]),
+        SYS_SYSINFO => ("sysinfo", &[Hex]),
+        SYS_GETRLIMIT => ("getrlimit", &[Int, Hex]),
+        SYS_SETRLIMIT => ("setrlimit", &[Int, Hex]),
+        SYS_IOPERM => ("ioperm", &[Hex, Size, Int]),
+        SYS_IOPL => ("iopl", &[Int]),
+        SYS_SCHED_SETAFFINITY => ("sched_setaffinity", &[Int, Size, Hex]),
+        SYS_SCHED_GETAFFINITY => ("sched_getaffinity", &[Int, Size, Hex]),
+        SYS_CLOCK_GETTIME => ("clock_gettime", &[Int, Hex]),
+        SYS_GETTIMEOFDAY => ("gettimeofday", &[Hex, Hex]),
+        SYS_PIPE2 => ("pipe2", &[Hex, Hex]),
+        _ => return None,
+    })
+}
+
+/// すべてのプロセスのトレースを切り替える
+pub fn set_global(enabled: bool) {
+    TRACE_ALL.store(enabled, Ordering::SeqCst);
+}
+
+/// 現在のシステムコールをトレースするか (全体設定かプロセスごとのフラグ)
+pub fn enabled() -> bool {
+    TRACE_ALL.load(Ordering::Relaxed)
+        || crate::process::with_current_process(|process| process.trace_syscalls).unwrap_or(false)
+}
+
+fn format_path(ptr: u64) -> String {
+    if ptr == 0 {
+        return String::from("NULL");
+    }
+    // 読めない・長すぎるパスはアドレスだけ出す
+    match crate::uaccess::strncpy_from_user(ptr, MAX_TRACE_PATH) {
+        Ok(path) => format!("{:?}", path),
+        Err(_) => format!("{:#x}", ptr),
+    }
+}
+
+/// "open(\"/etc/motd\", 0x0, 0o644)" の形に整形する
+pub fn format_call(number: u64, args: &[u64; 6]) -> String {
+    let (name, kinds) = match signature(number) {
+        Some(signature) => signature,
+        // 登録されたシステムコールは引数の種類がわからないので16進数で出す
+        None => return match crate::syscall::registered(number) {
+            Some(handler) => format!("{}({:#x}, {:#x}, {:#x})", handler.name, args[0], args[1], args[2]),
+            None => format!("syscall_{}({:#x}, {:#x}, {:#x})", number, args[0], args[1], args[2]),
+        },
+    };
+
+    let mut call = format!("{}(", name);
+    for (i, (kind, &value)) in kinds.iter().zip(args.iter()).enumerate() {
+        if i > 0 {
+            call.push_str(", ");
+        }
+        call.push_str(&match kind {
+            Arg::Int => format!("{}", value as i64),
+            Arg::Size => format!("{}", value),
+            Arg::Path => format_path(value),
+            Arg::Hex => format!("{:#x}", value),
+            Arg::Mode => format!("{:#o}", value),
+        });
+    }
+    call.push(')');
+    call
+}
+
+/// 戻り値と一緒にカーネルログへ出す (失敗は "= -2 ENOENT (No such file or directory)")
+pub fn log(call: &str, result: &crate::errno::SysResult) {
+    let pid = crate::process::current_pid().unwrap_or(0);
+    match result {
+        Ok(value) => crate::info!("[{}] {} = {}", pid, call, value),
+        Err(errno) => crate::info!("[{}] {} = {} {} ({})", pid, call, errno.to_neg(), errno.name(), errno),
+    }
+}
+
+/// 戻らないシステムコール (exit など) は呼び出し時に出す
+pub fn log_noreturn(call: &str) {
+    let pid = crate::process::current_pid().unwrap_or(0);
+    crate::info!("[{}] {} = ?", pid, call);
+}
@@ -0,0 +1,127 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Once;
+use crate::memory::KernelSection;
+
+/// initramfs に入れるシンボルマップ (make initramfs が `nm -n -C` の出力を置く)
+pub const SYMBOL_MAP_PATH: &str = "/boot/System.map";
+
+/// アドレス順に並んだ関数シンボル
+/// 名前は1つの文字列にまとめて持つ (数千個の String を作らない)
+struct SymbolTable {
+    /// (実行時のアドレス, names の開始位置, 名前の長さ)
+    entries: Vec<(u64, u32, u32)>,
+    names: String,
+    text_end: u64,
+}
+
+static TABLE: Once<SymbolTable> = Once::new();
+
+/// アドレスを解決した結果 ("関数名+オフセット")
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// アドレスを %pS のように表示する (解決できなければ16進数だけ)
+/// 例: println!("{}", Sym(rip))
+#[derive(Debug, Clone, Copy)]
+pub struct Sym(pub u64);
+
+impl fmt::Display for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match resolve(self.0) {
+            Some(symbol) => write!(f, "{:#018x} <{}>", self.0, symbol),
+            None => write!(f, "{:#018x}", self.0),
+        }
+    }
+}
+
+/// `nm -n` 形式 ("<16進アドレス> <種類> <名前>") の1行を読む
+/// 名前には空白が入ることがある (<impl Foo for Bar>::baz)
+fn parse_line(line: &str) -> Option<(u64, char, &str)> {
+    let mut parts = line.splitn(3, ' ');
+    let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let kind = parts.next()?.chars().next()?;
+    Some((addr, kind, parts.next()?.trim_end()))
+}
+
+/// シンボルマップを読み込む (最初の1回だけ有効)
+/// カーネルは位置独立なので、__text_start と __text_end の位置からずれを求め、
+/// 両方の間隔が一致しない (別のビルドの) マップは受け付けない
+pub fn load(map: &str) -> Result<usize, &'static str> {
+    if is_loaded() {
+        return Err("Symbol map already loaded");
+    }
+    let (text_start, text_end) = crate::memory::section_range(KernelSection::Text);
+    let anchor = |name: &str| map.lines()
+        .filter_map(parse_line)
+        .find(|&(_, _, symbol)| symbol == name)
+        .map(|(addr, _, _)| addr);
+    let map_start = anchor("__text_start").ok_or("__text_start not in symbol map")?;
+    let map_end = anchor("__text_end").ok_or("__text_end not in symbol map")?;
+    if map_end.wrapping_sub(map_start) != text_end - text_start {
+        return Err("Symbol map does not match this kernel");
+    }
+    let slide = text_start.wrapping_sub(map_start);
+
+    let mut entries = Vec::new();
+    let mut names = String::new();
+    for (addr, kind, name) in map.lines().filter_map(parse_line) {
+        // 関数 (テキストセクションのシンボル) だけを持つ
+        if !matches!(kind, 'T' | 't' | 'W' | 'w') || name.starts_with("__text_") {
+            continue;
+        }
+        let addr = addr.wrapping_add(slide);
+        if !(text_start..text_end).contains(&addr) {
+            continue;
+        }
+        entries.push((addr, names.len() as u32, name.len() as u32));
+        names.push_str(name);
+    }
+    entries.sort_by_key(|&(addr, _, _)| addr);
+
+    let count = entries.len();
+    TABLE.call_once(|| SymbolTable { entries, names, text_end });
+    Ok(count)
+}
+
+/// initramfs のシンボルマップを読み込む (initramfs の展開後に呼ぶ)
+pub fn init() -> Result<usize, &'static str> {
+    let data = crate::filesystem::read_file(SYMBOL_MAP_PATH).map_err(|_| "No symbol map")?;
+    let map = core::str::from_utf8(&data).map_err(|_| "Symbol map is not UTF-8")?;
+    load(map)
+}
+
+pub fn is_loaded() -> bool {
+    TABLE.is_completed()
+}
+
+/// アドレスを含む関数とその先頭からのオフセット
+/// ロックを取らないので、パニックや例外ハンドラからも使える
+pub fn resolve(addr: u64) -> Option<Symbol> {
+    let table = TABLE.get()?;
+    if addr >= table.text_end {
+        return None;
+    }
+    let index = table.entries.partition_point(|&(start, _, _)| start <= addr).checked_sub(1)?;
+    let (start, name_start, name_len) = table.entries[index];
+    let name = &table.names[name_start as usize..(name_start + name_len) as usize];
+    Some(Symbol { name, offset: addr - start })
+}
+
+/// 名前からアドレスを引く (完全一致の最初のもの)
+pub fn lookup(name: &str) -> Option<u64> {
+    let table = TABLE.get()?;
+    table.entries.iter()
+        .find(|&&(_, start, len)| &table.names[start as usize..(start + len) as usize] == name)
+        .map(|&(addr, _, _)| addr)
+}
@@ -0,0 +1,541 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use crate::process;
+use crate::smp::MAX_CPUS;
+
+/// 割り込みが来るまで CPU を止める
+/// システムコール中は SFMASK で IF が落ちているので、hlt の間だけ割り込みを許し、戻ったら元の状態に戻す
+/// (sti と hlt の間には割り込みが入らないので、直前に確かめた条件の変化を取りこぼさない)
+pub fn wait_for_interrupt() {
+    use x86_64::instructions::interrupts;
+
+    let enabled = interrupts::are_enabled();
+    interrupts::enable_and_hlt();
+    if !enabled {
+        interrupts::disable();
+    }
+}
+
+/// 条件が満たされるまでスレッドをブロックさせる待ち行列
+/// パイプ・TTY・スリープなど、待ち合わせが必要な箇所で共通に使う
+pub struct WaitQueue {
+    waiters: spin::Mutex<VecDeque<usize>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: spin::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// condition が true になるまで現在のスレッドをブロックする
+    /// 条件の確認と待ち行列への登録を同じロックの下で行うので、
+    /// 「条件を変えてから wake する」側との間で起床を取りこぼさない
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            // 割り込みハンドラからも wake されるので、ロック中は割り込みを止める
+            // 条件が満たされていれば None、そうでなければ Some(ブロックしたスレッド)
+            let blocked = x86_64::instructions::interrupts::without_interrupts(|| {
+                let mut waiters = self.waiters.lock();
+                if condition() {
+                    return None;
+                }
+                Some(process::current_tid().map(|tid| {
+                    process::block_current();
+                    waiters.push_back(tid);
+                    tid
+                }))
+            });
+
+            match blocked {
+                None => return,
+                Some(Some(tid)) => {
+                    // 起こされるまで待ち、再びこのCPUで実行中に戻す
+                    while process::is_blocked(tid) {
+                        wait_for_interrupt();
+                    }
+                    process::resume(tid);
+                }
+                // プロセス外 (カーネル初期化中など) では割り込みを待つだけ
+                Some(None) => wait_for_interrupt(),
+            }
+        }
+    }
+
+    /// 条件を調べずに tid を登録する (poll で複数の待ち行列を同時に待つ場合)
+    /// ブロックや解除は呼び出し側で行い、終わったら remove_waiter で外すこと
+    pub fn add_waiter(&self, tid: usize) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            if !waiters.contains(&tid) {
+                waiters.push_back(tid);
+            }
+        });
+    }
+
+    pub fn remove_waiter(&self, tid: usize) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            self.waiters.lock().retain(|&waiter| waiter != tid);
+        });
+    }
+
+    /// 待っているスレッドを1つ起こす
+    pub fn wake_one(&self) -> bool {
+        let tid = x86_64::instructions::interrupts::without_interrupts(|| {
+            self.waiters.lock().pop_front()
+        });
+        match tid {
+            Some(tid) => {
+                process::scheduler::wake(tid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 待っているスレッドをすべて起こす
+    pub fn wake_all(&self) -> usize {
+        let waiters: VecDeque<usize> = x86_64::instructions::interrupts::without_interrupts(|| {
+            core::mem::take(&mut *self.waiters.lock())
+        });
+        let count = waiters.len();
+        for tid in waiters {
+            process::scheduler::wake(tid);
+        }
+        count
+    }
+}
+
+/// 取得できなければ呼び出し元をブロックするミューテックス
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            self.queue.wait_until(|| !self.locked.load(Ordering::Relaxed));
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.queue.wake_one();
+    }
+}
+
+/// 読み取りは並行に、書き込みは排他的に行うロック
+/// 取得できなければ Mutex と同じく呼び出し元をブロックする (書き込み側は読み取りが途切れるまで待つ)
+pub struct RwLock<T> {
+    /// WRITER ビットが書き込み中、残りのビットが読み取り中の数
+    state: AtomicUsize,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & WRITER != 0 {
+                return None;
+            }
+            match self.state.compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Some(RwLockReadGuard { lock: self }),
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            self.queue.wait_until(|| self.state.load(Ordering::Relaxed) & WRITER == 0);
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            self.queue.wait_until(|| self.state.load(Ordering::Relaxed) == 0);
+        }
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // 最後の読み手だけが、待っている書き手を起こす
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.queue.wake_all();
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.queue.wake_all();
+    }
+}
+
+/// 計数セマフォ
+pub struct Semaphore {
+    count: spin::Mutex<usize>,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: spin::Mutex::new(count),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count > 0 {
+            *count -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// カウントが正になるまでブロックしてから1減らす
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.queue.wait_until(|| *self.count.lock() > 0);
+        }
+    }
+
+    pub fn release(&self) {
+        *self.count.lock() += 1;
+        self.queue.wake_one();
+    }
+}
+
+/// 条件変数
+pub struct Condvar {
+    // notify のたびに進める世代番号 (待機中に通知があったかの判定に使う)
+    generation: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            generation: AtomicUsize::new(0),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// ロックを解放して通知を待ち、再取得したガードを返す
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let mutex = guard.mutex;
+        drop(guard);
+        self.queue.wait_until(|| self.generation.load(Ordering::SeqCst) != generation);
+        mutex.lock()
+    }
+
+    pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.queue.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.queue.wake_all();
+    }
+}
+
+/// 保持している間は割り込みを禁止するスピンロック
+/// 割り込みハンドラ (タイマーなど) からも取るロックに使う
+/// 取得時の RFLAGS.IF を覚えておき、解放時に元に戻す
+pub struct IrqMutex<T> {
+    inner: spin::Mutex<T>,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: spin::Mutex::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let interrupts_enabled = save_and_disable_interrupts();
+        IrqMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let interrupts_enabled = save_and_disable_interrupts();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqMutexGuard {
+                guard: ManuallyDrop::new(guard),
+                interrupts_enabled,
+            }),
+            None => {
+                restore_interrupts(interrupts_enabled);
+                None
+            }
+        }
+    }
+}
+
+/// 複数のガードを持つ場合は取得と逆の順に解放すること
+/// (先に取ったガードを先に落とすと、後のガードを持ったまま割り込みが許可される)
+pub struct IrqMutexGuard<'a, T> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    interrupts_enabled: bool,
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // ロックを解放してから割り込みを戻す
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        restore_interrupts(self.interrupts_enabled);
+    }
+}
+
+/// RFLAGS.IF を返して割り込みを禁止する
+fn save_and_disable_interrupts() -> bool {
+    use x86_64::registers::rflags::{self, RFlags};
+    let enabled = rflags::read().contains(RFlags::INTERRUPT_FLAG);
+    x86_64::instructions::interrupts::disable();
+    enabled
+}
+
+fn restore_interrupts(enabled: bool) {
+    if enabled {
+        x86_64::instructions::interrupts::enable();
+    }
+}
+
+/// CPUごとの RCU の読み取り区間の状態
+struct RcuCpu {
+    /// 入れ子の深さ (割り込みハンドラの中でも読める)
+    depth: AtomicUsize,
+    /// 一番外側の区間に入るときと出るときに 1 ずつ増える (奇数なら区間内)
+    sequence: AtomicUsize,
+}
+
+static RCU_CPUS: [RcuCpu; MAX_CPUS] = [const { RcuCpu { depth: AtomicUsize::new(0), sequence: AtomicUsize::new(0) } }; MAX_CPUS];
+
+fn rcu_read_lock() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let cpu = &RCU_CPUS[crate::smp::cpu_id()];
+        if cpu.depth.fetch_add(1, Ordering::SeqCst) == 0 {
+            cpu.sequence.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+}
+
+fn rcu_read_unlock() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let cpu = &RCU_CPUS[crate::smp::cpu_id()];
+        if cpu.depth.fetch_sub(1, Ordering::SeqCst) == 1 {
+            cpu.sequence.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+}
+
+/// 今読み取り区間にいる CPU がすべて区間を抜けるまで待つ (猶予期間)
+/// 読み取り区間の中で呼ぶと自分を待ち続けるので呼ばないこと
+pub fn synchronize_rcu() {
+    for cpu in RCU_CPUS.iter().take(crate::smp::cpu_count()) {
+        let sequence = cpu.sequence.load(Ordering::SeqCst);
+        if sequence & 1 == 0 {
+            continue;
+        }
+        while cpu.sequence.load(Ordering::SeqCst) == sequence {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// 読み取りがほとんどのデータを、読み手がロックを取らずに読めるようにする (RCU の簡易版)
+/// 更新は値を丸ごと差し替え、古い値は読み取り中の CPU がいなくなってから解放する
+/// 読み取りのガードを持ったままブロックしないこと
+pub struct Rcu<T> {
+    current: AtomicPtr<T>,
+    /// 更新どうしを直列にする
+    writer: spin::Mutex<()>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+unsafe impl<T: Send> Send for Rcu<T> {}
+
+impl<T> Rcu<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: spin::Mutex::new(()),
+        }
+    }
+
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        rcu_read_lock();
+        let value = unsafe { &*self.current.load(Ordering::Acquire) };
+        RcuReadGuard { value, _not_send: PhantomData }
+    }
+
+    /// 今の値から作った新しい値に差し替え、猶予期間の後に古い値を捨てる
+    /// update が Err を返したら差し替えない
+    pub fn update<E>(&self, update: impl FnOnce(&T) -> Result<T, E>) -> Result<(), E> {
+        let _writer = self.writer.lock();
+        let old = self.current.load(Ordering::Acquire);
+        let new = update(unsafe { &*old })?;
+        self.current.store(Box::into_raw(Box::new(new)), Ordering::Release);
+        synchronize_rcu();
+        drop(unsafe { Box::from_raw(old) });
+        Ok(())
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+    }
+}
+
+/// Rcu の読み取り区間 (区間に入った CPU で手放すので Send にしない)
+pub struct RcuReadGuard<'a, T> {
+    value: &'a T,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for RcuReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> Drop for RcuReadGuard<'_, T> {
+    fn drop(&mut self) {
+        rcu_read_unlock();
+    }
+}
@@ -1,21 +1,90 @@
 use x86_64::structures::idt::InterruptStackFrame;
 use spin::Mutex;
+use crate::sync::RwLock;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::errno::{Errno, SysResult};
+use crate::filesystem::{AT_FDCWD, AT_REMOVEDIR};
+use crate::mqueue::MqAttr;
+use crate::rlimit::RLimit;
+use crate::uaccess::{copy_from_user, copy_to_user, read_user, write_user};
 
-// システムコール番号
-pub const SYS_READ: u64 = 0;
-pub const SYS_WRITE: u64 = 1;
-pub const SYS_OPEN: u64 = 2;
-pub const SYS_CLOSE: u64 = 3;
-pub const SYS_EXIT: u64 = 60;
-pub const SYS_FORK: u64 = 57;
-pub const SYS_EXECVE: u64 = 59;
-pub const SYS_GETPID: u64 = 39;
-pub const SYS_SLEEP: u64 = 35;
-pub const SYS_MMAP: u64 = 9;
-pub const SYS_MUNMAP: u64 = 11;
+// システムコール番号は abi で定義する (ユーザー向けのヘッダと同じもの)
+pub use crate::abi::syscalls::*;
+
+// getrandom のフラグ (乱数生成器はブロックしないので受け付けるだけ)
+pub const GRND_NONBLOCK: u32 = 0x1;
+pub const GRND_RANDOM: u32 = 0x2;
+
+// reboot のマジック番号とコマンド (Linux と同じ)
+pub const LINUX_REBOOT_MAGIC1: u32 = 0xfee1_dead;
+pub const LINUX_REBOOT_MAGIC2: u32 = 672274793;
+pub const LINUX_REBOOT_MAGIC2A: u32 = 85072278;
+pub const LINUX_REBOOT_MAGIC2B: u32 = 369367448;
+pub const LINUX_REBOOT_MAGIC2C: u32 = 537993216;
+pub const LINUX_REBOOT_CMD_RESTART: u32 = 0x0123_4567;
+pub const LINUX_REBOOT_CMD_HALT: u32 = 0xCDEF_0123;
+pub const LINUX_REBOOT_CMD_POWER_OFF: u32 = 0x4321_FEDC;
+pub const LINUX_REBOOT_CMD_CAD_ON: u32 = 0x89AB_CDEF;
+pub const LINUX_REBOOT_CMD_CAD_OFF: u32 = 0;
+
+// fcntl のコマンド
+pub const F_GETFL: i32 = 3;
+pub const F_SETFL: i32 = 4;
+
+// mmap のフラグは abi で定義する
+pub use crate::abi::{MAP_ANONYMOUS, MAP_PRIVATE, MAP_SHARED, PROT_EXEC, PROT_READ, PROT_WRITE};
 
 static SYSCALL_STATS: Mutex<SyscallStats> = Mutex::new(SyscallStats::new());
 
+/// register で登録できるシステムコール番号の上限
+pub const MAX_SYSCALLS: usize = 1024;
+
+/// システムコールの引数 (rdi, rsi, rdx, r10, r8, r9 の順)
+pub type SyscallArgs = [u64; 6];
+
+/// サブシステムが登録するシステムコール
+#[derive(Clone, Copy)]
+pub struct SyscallHandler {
+    /// strace で表示する名前
+    pub name: &'static str,
+    pub call: fn(&SyscallArgs) -> SysResult,
+}
+
+impl SyscallHandler {
+    pub const fn new(name: &'static str, call: fn(&SyscallArgs) -> SysResult) -> Self {
+        Self { name, call }
+    }
+}
+
+// 登録されたシステムコール (番号で引く)
+// 読むのはシステムコールのたび、書くのは初期化時だけなので RwLock にする (読み手どうしは待たない)
+static SYSCALL_TABLE: RwLock<[Option<SyscallHandler>; MAX_SYSCALLS]> = RwLock::new([None; MAX_SYSCALLS]);
+
+/// number のシステムコールを登録する
+/// syscall_handler の match にある番号は組み込みの処理が先に呼ばれるので、登録しても使われない
+pub fn register(number: u64, handler: SyscallHandler) -> Result<(), &'static str> {
+    let mut table = SYSCALL_TABLE.write();
+    let slot = table.get_mut(number as usize).ok_or("Syscall number out of range")?;
+    if slot.is_some() {
+        return Err("Syscall already registered");
+    }
+    *slot = Some(handler);
+    crate::debug!("Registered syscall {} ({})", handler.name, number);
+    Ok(())
+}
+
+/// 登録を外す (外したものを返す)
+pub fn unregister(number: u64) -> Option<SyscallHandler> {
+    SYSCALL_TABLE.write().get_mut(number as usize)?.take()
+}
+
+/// 登録されているシステムコール
+pub fn registered(number: u64) -> Option<SyscallHandler> {
+    *SYSCALL_TABLE.read().get(number as usize)?
+}
+
 struct SyscallStats {
     total_calls: u64,
     calls_by_type: [u64; 256],
@@ -30,10 +99,146 @@ impl SyscallStats {
     }
 }
 
+// syscall 命令で入ってきた際に切り替えるカーネルスタック
+const SYSCALL_STACK_SIZE: usize = 4096 * 4;
+
+#[repr(C, align(16))]
+struct SyscallStack([u8; SYSCALL_STACK_SIZE]);
+
+static mut SYSCALL_STACK: SyscallStack = SyscallStack([0; SYSCALL_STACK_SIZE]);
+
 pub fn init() {
-    // システムコール用の割り込みを設定
-    // x86_64では通常 int 0x80 またはsyscall命令を使用
-    crate::println!("Syscall handler registered");
+    use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+    use x86_64::registers::rflags::RFlags;
+    use x86_64::VirtAddr;
+
+    let stack_top = VirtAddr::from_ptr(core::ptr::addr_of!(SYSCALL_STACK))
+        + SYSCALL_STACK_SIZE;
+    crate::smp::set_kernel_stack(stack_top);
+
+    // syscall/sysret の高速パスを設定
+    // int 0x80 はフォールバックとして IDT に残しておく (syscall_interrupt_entry)
+    let selectors = crate::gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    ).expect("Invalid GDT layout for SYSCALL/SYSRET");
+    LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+    // ハンドラ実行中は割り込みを禁止し、ユーザーが立てた AC も下ろす (SMAP を素通りさせない)
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::ALIGNMENT_CHECK);
+    unsafe {
+        Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS);
+    }
+
+    crate::info!("Syscall handler registered");
+}
+
+/// syscall 命令のエントリポイント
+/// 入口では rcx = ユーザーRIP, r11 = ユーザーRFLAGS, rsp = ユーザースタック
+/// レジスタを保存してから syscall_handler の C ABI に引数を並べ替えて呼び出す
+#[unsafe(naked)]
+extern "C" fn syscall_entry() {
+    core::arch::naked_asm!(
+        // GS ベースを per-CPU 領域に切り替えてから、このCPUのカーネルスタックへ切り替える
+        // (SFMask で割り込みは止まっているので、swapgs の前に割り込まれることはない)
+        "swapgs",
+        "mov gs:[{user_rsp}], rsp",
+        "mov rsp, gs:[{kernel_stack}]",
+        "push qword ptr gs:[{user_rsp}]",
+
+        // sysretq で使う rcx/r11 と、呼び出し側のレジスタを保存
+        "push rcx",
+        "push r11",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r8",
+        "push r9",
+        "push r10",
+
+        // 引数を並べ替え (rax, rdi, rsi, rdx, r10, r8, r9)
+        //           → (rdi, rsi, rdx, rcx, r8,  r9,  [rsp])
+        "push r9",
+        "mov r9, r8",
+        "mov r8, r10",
+        "mov rcx, rdx",
+        "mov rdx, rsi",
+        "mov rsi, rdi",
+        "mov rdi, rax",
+        "call {handler}",
+        "add rsp, 8",
+
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "swapgs",
+        "sysretq",
+        handler = sym syscall_handler,
+        user_rsp = const crate::smp::PER_CPU_USER_RSP,
+        kernel_stack = const crate::smp::PER_CPU_KERNEL_STACK,
+    );
+}
+
+/// int 0x80 のエントリポイント (syscall 命令を使えない呼び出し側のフォールバック)
+/// レジスタの使い方は syscall 命令と同じで、rax 以外のレジスタは保存して戻す
+/// リング3から入った (積まれた CS の RPL が 3 の) ときは、入口と出口で swapgs する
+#[unsafe(naked)]
+pub extern "C" fn syscall_interrupt_entry() {
+    core::arch::naked_asm!(
+        "test qword ptr [rsp + 8], 3",
+        "jz 2f",
+        "swapgs",
+        "2:",
+
+        // 戻り値を書き込む rax と、呼び出しで壊れるレジスタを保存
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+
+        // 割り込みフレーム (40 バイト) + 9 レジスタ + 7番目の引数で 16 バイト境界に揃える
+        "sub rsp, 8",
+        "push r9",
+        "mov r9, r8",
+        "mov r8, r10",
+        "mov rcx, rdx",
+        "mov rdx, rsi",
+        "mov rsi, rdi",
+        "mov rdi, rax",
+        "cld",
+        "call {handler}",
+        "add rsp, 16",
+        "mov [rsp + 64], rax",
+
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        "test qword ptr [rsp + 8], 3",
+        "jz 3f",
+        "swapgs",
+        "3:",
+        "iretq",
+        handler = sym syscall_handler,
+    );
 }
 
 /// システムコールハンドラ
@@ -65,152 +270,951 @@ pub extern "C" fn syscall_handler(
         }
     }
 
-    let result = match syscall_number {
+    // strace 相当: 引数は呼び出し前に整形しておく (パス名などが書き換わる前に)
+    let trace = if crate::strace::enabled() {
+        let call = crate::strace::format_call(syscall_number, &[arg1, arg2, arg3, arg4, arg5, arg6]);
+        if syscall_number == SYS_EXIT {
+            crate::strace::log_noreturn(&call);
+            None
+        } else {
+            Some(call)
+        }
+    } else {
+        None
+    };
+
+    let result: SysResult = match syscall_number {
         SYS_READ => sys_read(arg1 as i32, arg2 as *mut u8, arg3 as usize),
         SYS_WRITE => sys_write(arg1 as i32, arg2 as *const u8, arg3 as usize),
-        SYS_OPEN => sys_open(arg1 as *const u8, arg2 as i32, arg3 as u32),
+        SYS_PREAD64 => sys_pread64(arg1 as i32, arg2 as *mut u8, arg3 as usize, arg4 as i64),
+        SYS_PWRITE64 => sys_pwrite64(arg1 as i32, arg2 as *const u8, arg3 as usize, arg4 as i64),
+        SYS_READV => sys_readv(arg1 as i32, arg2 as *const IoVec, arg3 as i32),
+        SYS_WRITEV => sys_writev(arg1 as i32, arg2 as *const IoVec, arg3 as i32),
+        SYS_OPEN => sys_openat(AT_FDCWD, arg1 as *const u8, arg2 as i32, arg3 as u32),
+        SYS_OPENAT => sys_openat(arg1 as i32, arg2 as *const u8, arg3 as i32, arg4 as u32),
         SYS_CLOSE => sys_close(arg1 as i32),
+        SYS_STAT => sys_newfstatat(AT_FDCWD, arg1 as *const u8, arg2 as *mut crate::filesystem::Stat, 0),
+        SYS_NEWFSTATAT => sys_newfstatat(arg1 as i32, arg2 as *const u8, arg3 as *mut crate::filesystem::Stat, arg4 as i32),
+        SYS_FSTAT => sys_fstat(arg1 as i32, arg2 as *mut crate::filesystem::Stat),
+        SYS_STATFS => sys_statfs(arg1 as *const u8, arg2 as *mut crate::filesystem::StatFs),
+        SYS_LSEEK => sys_lseek(arg1 as i32, arg2 as i64, arg3 as i32),
+        SYS_DUP => sys_dup(arg1 as i32),
+        SYS_DUP2 => sys_dup2(arg1 as i32, arg2 as i32),
         SYS_EXIT => sys_exit(arg1 as i32),
         SYS_FORK => sys_fork(),
+        SYS_CLONE => sys_clone(arg1, arg2, arg5),
+        SYS_ARCH_PRCTL => sys_arch_prctl(arg1 as i32, arg2),
         SYS_EXECVE => sys_execve(arg1 as *const u8, arg2 as *const *const u8, arg3 as *const *const u8),
         SYS_GETPID => sys_getpid(),
+        SYS_GETPPID => sys_getppid(),
+        SYS_GETTID => sys_gettid(),
+        SYS_GETUID | SYS_GETGID | SYS_GETEUID | SYS_GETEGID => sys_getid(syscall_number),
+        SYS_SETUID => sys_setuid(arg1 as u32),
+        SYS_SETGID => sys_setgid(arg1 as u32),
         SYS_SLEEP => sys_sleep(arg1),
         SYS_MMAP => sys_mmap(arg1 as u64, arg2 as usize, arg3 as i32, arg4 as i32, arg5 as i32, arg6 as i64),
         SYS_MUNMAP => sys_munmap(arg1 as u64, arg2 as usize),
-        _ => {
-            crate::println!("Unknown syscall: {}", syscall_number);
-            -1 // ENOSYS
-        }
+        SYS_BRK => sys_brk(arg1),
+        SYS_SYSINFO => sys_sysinfo(arg1 as *mut SysInfo),
+        SYS_GETRLIMIT => sys_getrlimit(arg1 as usize, arg2 as *mut RLimit),
+        SYS_SETRLIMIT => sys_setrlimit(arg1 as usize, arg2 as *const RLimit),
+        SYS_IOPERM => sys_ioperm(arg1 as usize, arg2 as usize, arg3 as i32),
+        SYS_IOPL => sys_iopl(arg1 as u32),
+        SYS_SCHED_SETAFFINITY => sys_sched_setaffinity(arg1 as usize, arg2 as usize, arg3 as *const u8),
+        SYS_SCHED_GETAFFINITY => sys_sched_getaffinity(arg1 as usize, arg2 as usize, arg3 as *mut u8),
+        SYS_CLOCK_GETTIME => sys_clock_gettime(arg1 as i32, arg2 as *mut crate::time::Timespec),
+        SYS_GETTIMEOFDAY => sys_gettimeofday(arg1 as *mut crate::time::Timeval),
+        SYS_PIPE2 => sys_pipe2(arg1 as *mut i32, arg2 as i32),
+        SYS_GETCWD => sys_getcwd(arg1 as *mut u8, arg2 as usize),
+        SYS_CHDIR => sys_chdir(arg1 as *const u8),
+        SYS_RENAME => sys_renameat(AT_FDCWD, arg1 as *const u8, AT_FDCWD, arg2 as *const u8),
+        SYS_RENAMEAT => sys_renameat(arg1 as i32, arg2 as *const u8, arg3 as i32, arg4 as *const u8),
+        SYS_MKDIR => sys_mkdirat(AT_FDCWD, arg1 as *const u8, arg2 as u32),
+        SYS_MKDIRAT => sys_mkdirat(arg1 as i32, arg2 as *const u8, arg3 as u32),
+        SYS_RMDIR => sys_unlinkat(AT_FDCWD, arg1 as *const u8, AT_REMOVEDIR),
+        SYS_UNLINK => sys_unlinkat(AT_FDCWD, arg1 as *const u8, 0),
+        SYS_UNLINKAT => sys_unlinkat(arg1 as i32, arg2 as *const u8, arg3 as i32),
+        SYS_LINK => sys_link(arg1 as *const u8, arg2 as *const u8),
+        SYS_SYMLINK => sys_symlink(arg1 as *const u8, arg2 as *const u8),
+        SYS_READLINK => sys_readlinkat(AT_FDCWD, arg1 as *const u8, arg2 as *mut u8, arg3 as usize),
+        SYS_READLINKAT => sys_readlinkat(arg1 as i32, arg2 as *const u8, arg3 as *mut u8, arg4 as usize),
+        SYS_GETDENTS64 => sys_getdents64(arg1 as i32, arg2 as *mut u8, arg3 as usize),
+        SYS_GETRANDOM => sys_getrandom(arg1 as *mut u8, arg2 as usize, arg3 as u32),
+        SYS_FTRUNCATE => sys_ftruncate(arg1 as i32, arg2 as i64),
+        SYS_SHM_OPEN => sys_shm_open(arg1 as *const u8, arg2 as i32, arg3 as u32),
+        SYS_SHM_UNLINK => sys_shm_unlink(arg1 as *const u8),
+        SYS_FCNTL => sys_fcntl(arg1 as i32, arg2 as i32, arg3),
+        SYS_POLL => sys_poll(arg1 as *mut crate::poll::PollFd, arg2 as usize, arg3 as i32),
+        SYS_MQ_OPEN => sys_mq_open(arg1 as *const u8, arg2 as i32, arg3 as u32, arg4 as *const MqAttr),
+        SYS_MQ_UNLINK => sys_mq_unlink(arg1 as *const u8),
+        SYS_MQ_TIMEDSEND => sys_mq_timedsend(arg1 as i32, arg2 as *const u8, arg3 as usize, arg4 as u32,
+            arg5 as *const crate::time::Timespec),
+        SYS_MQ_TIMEDRECEIVE => sys_mq_timedreceive(arg1 as i32, arg2 as *mut u8, arg3 as usize, arg4 as *mut u32,
+            arg5 as *const crate::time::Timespec),
+        SYS_MQ_GETSETATTR => sys_mq_getsetattr(arg1 as i32, arg2 as *const MqAttr, arg3 as *mut MqAttr),
+        SYS_SYNC => sys_sync(),
+        SYS_REBOOT => sys_reboot(arg1 as u32, arg2 as u32, arg3 as u32),
+        SYS_FAULT_INJECT => sys_fault_inject(arg1 as u32, arg2 as usize, arg3 as usize),
+        // 組み込みでなければ登録されたハンドラを探す
+        // (ロックは registered の中で外れるので、ハンドラがブロックしても register を止めない)
+        _ => match registered(syscall_number) {
+            Some(handler) => (handler.call)(&[arg1, arg2, arg3, arg4, arg5, arg6]),
+            None => {
+                crate::warn!("Unknown syscall: {}", syscall_number);
+                Err(Errno::ENOSYS)
+            }
+        },
     };
 
-    result
+    if let Some(call) = trace {
+        crate::strace::log(&call, &result);
+    }
+
+    // 失敗は -errno としてユーザーに返す
+    match result {
+        Ok(value) => value,
+        Err(errno) => errno.to_neg(),
+    }
 }
 
 // システムコール実装
 
-fn sys_read(fd: i32, buf: *mut u8, count: usize) -> i64 {
-    if fd < 0 || buf.is_null() {
-        return -1; // EINVAL
+/// read/write でユーザーバッファとの間に使うバウンスバッファの大きさ
+pub(crate) const USER_CHUNK_SIZE: usize = 4096;
+
+/// ユーザーから渡されたNUL終端のパス名を読み取る
+fn user_path(pathname: *const u8) -> Result<String, Errno> {
+    if pathname.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    crate::uaccess::strncpy_from_user(pathname as u64, crate::uaccess::PATH_MAX)
+}
+
+/// *at システムコールのパスを絶対パスにする
+/// 相対パスは dirfd のディレクトリ (AT_FDCWD ならカレントディレクトリ) を基準にする
+fn user_path_at(dirfd: i32, pathname: *const u8) -> Result<String, Errno> {
+    let path = user_path(pathname)?;
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.starts_with('/') || dirfd == AT_FDCWD {
+        return Ok(crate::filesystem::absolute_path(&path));
+    }
+    let dir = match &**get_file(dirfd)? {
+        crate::fd::FileObject::File(vfs_fd) => crate::filesystem::dir_path(*vfs_fd)?,
+        _ => return Err(Errno::ENOTDIR),
+    };
+    Ok(alloc::format!("{}/{}", dir.trim_end_matches('/'), path))
+}
+
+pub(crate) fn get_file(fd: i32) -> Result<crate::fd::FileRef, Errno> {
+    crate::fd::get(fd).ok_or(Errno::EBADF)
+}
+
+pub(crate) fn install_fd(file: crate::fd::FileObject, flags: i32) -> SysResult {
+    crate::fd::install(file, flags).map(|fd| fd as i64).ok_or(Errno::EMFILE)
+}
+
+/// readv/writev に渡すバッファの並び (struct iovec)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoVec {
+    pub iov_base: *mut u8,
+    pub iov_len: usize,
+}
+
+/// readv/writev で一度に渡せる iovec の数
+const IOV_MAX: i32 = 1024;
+
+fn sys_read(fd: i32, buf: *mut u8, count: usize) -> SysResult {
+    read_to_user(&get_file(fd)?, buf, count, None)
+}
+
+fn sys_write(fd: i32, buf: *const u8, count: usize) -> SysResult {
+    write_from_user(&get_file(fd)?, buf, count, None)
+}
+
+/// offset から読む (ファイルのオフセットは動かさない)
+fn sys_pread64(fd: i32, buf: *mut u8, count: usize, offset: i64) -> SysResult {
+    if offset < 0 {
+        return Err(Errno::EINVAL);
     }
+    read_to_user(&get_file(fd)?, buf, count, Some(offset as usize))
+}
 
-    match fd {
-        0 => { // stdin
-            // キーボード入力から読み込み
-            let read = crate::drivers::keyboard::read_bytes(
-                unsafe { core::slice::from_raw_parts_mut(buf, count) }
-            );
-            read as i64
+/// offset へ書く (ファイルのオフセットは動かさない)
+fn sys_pwrite64(fd: i32, buf: *const u8, count: usize, offset: i64) -> SysResult {
+    if offset < 0 {
+        return Err(Errno::EINVAL);
+    }
+    write_from_user(&get_file(fd)?, buf, count, Some(offset as usize))
+}
+
+/// 各バッファへ順に読む (途中で短い読み込みになればそこで終える)
+fn sys_readv(fd: i32, iov: *const IoVec, iovcnt: i32) -> SysResult {
+    let file = get_file(fd)?;
+    let iovecs = read_iovecs(iov, iovcnt, true)?;
+    let mut total = 0;
+    for iovec in &iovecs {
+        let read = match read_to_user(&file, iovec.iov_base, iovec.iov_len, None) {
+            Ok(read) => read as usize,
+            // 途中まで読めていればその分を返す
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += read;
+        if read < iovec.iov_len {
+            break;
         }
-        _ => {
-            // ファイルシステムから読み込み
-            crate::filesystem::read(fd, unsafe { core::slice::from_raw_parts_mut(buf, count) })
+    }
+    Ok(total as i64)
+}
+
+/// 各バッファの内容を順に書く (途中で短い書き込みになればそこで終える)
+fn sys_writev(fd: i32, iov: *const IoVec, iovcnt: i32) -> SysResult {
+    let file = get_file(fd)?;
+    let iovecs = read_iovecs(iov, iovcnt, false)?;
+    let mut total = 0;
+    for iovec in &iovecs {
+        let written = match write_from_user(&file, iovec.iov_base, iovec.iov_len, None) {
+            Ok(written) => written as usize,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += written;
+        if written < iovec.iov_len {
+            break;
+        }
+    }
+    Ok(total as i64)
+}
+
+/// iovec の配列を読み取り、転送を始める前にすべてのバッファを検証する
+/// 数が範囲外か合計が ssize_t に収まらなければ EINVAL、アクセスできないバッファがあれば EFAULT
+fn read_iovecs(iov: *const IoVec, iovcnt: i32, write: bool) -> Result<Vec<IoVec>, Errno> {
+    if !(0..=IOV_MAX).contains(&iovcnt) {
+        return Err(Errno::EINVAL);
+    }
+    let mut iovecs = Vec::with_capacity(iovcnt as usize);
+    let mut total: usize = 0;
+    for i in 0..iovcnt as usize {
+        let iovec = read_user::<IoVec>(iov.wrapping_add(i) as u64)?;
+        total = total.checked_add(iovec.iov_len)
+            .filter(|&total| total <= isize::MAX as usize)
+            .ok_or(Errno::EINVAL)?;
+        if iovec.iov_len > 0 && !crate::uaccess::access_ok(iovec.iov_base as u64, iovec.iov_len, write) {
+            return Err(Errno::EFAULT);
         }
+        iovecs.push(iovec);
     }
+    Ok(iovecs)
 }
 
-fn sys_write(fd: i32, buf: *const u8, count: usize) -> i64 {
-    if fd < 0 || buf.is_null() {
-        return -1; // EINVAL
+/// file から buf へ最大 count バイト読む (offset を指定するとその位置から)
+fn read_to_user(file: &crate::fd::FileRef, buf: *mut u8, count: usize, offset: Option<usize>) -> SysResult {
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    if !crate::uaccess::access_ok(buf as u64, count, true) {
+        return Err(Errno::EFAULT);
     }
 
-    match fd {
-        1 | 2 => { // stdout, stderr
-            let slice = unsafe { core::slice::from_raw_parts(buf, count) };
-            if let Ok(s) = core::str::from_utf8(slice) {
-                crate::print!("{}", s);
-                count as i64
-            } else {
-                -1
-            }
+    // 一度に読むのはバウンスバッファ分まで (短い読み込みは許される)
+    let mut chunk = vec![0u8; core::cmp::min(count, USER_CHUNK_SIZE)];
+    let read = match offset {
+        Some(offset) => file.read_at(&mut chunk, offset)?,
+        None => file.read(&mut chunk)?,
+    };
+    copy_to_user(buf as u64, &chunk[..read])?;
+    Ok(read as i64)
+}
+
+/// buf の count バイトを file へ書く (offset を指定するとその位置へ)
+fn write_from_user(file: &crate::fd::FileRef, buf: *const u8, count: usize, offset: Option<usize>) -> SysResult {
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    if !crate::uaccess::access_ok(buf as u64, count, false) {
+        return Err(Errno::EFAULT);
+    }
+
+    let mut chunk = vec![0u8; core::cmp::min(count, USER_CHUNK_SIZE)];
+    let mut written = 0;
+    while written < count {
+        let len = core::cmp::min(count - written, chunk.len());
+        copy_from_user(&mut chunk[..len], buf.wrapping_add(written) as u64)?;
+        let result = match offset {
+            Some(offset) => file.write_at(&chunk[..len], offset + written),
+            None => file.write(&chunk[..len]),
+        };
+        let result = match result {
+            Ok(result) => result,
+            // 途中まで書けていればその分を返す
+            Err(_) if written > 0 => break,
+            Err(e) => return Err(e),
+        };
+        written += result;
+        if result < len {
+            break;
         }
-        _ => {
-            // ファイルシステムへ書き込み
-            crate::filesystem::write(fd, unsafe { core::slice::from_raw_parts(buf, count) })
+    }
+    Ok(written as i64)
+}
+
+fn sys_openat(dirfd: i32, pathname: *const u8, flags: i32, mode: u32) -> SysResult {
+    let path = user_path_at(dirfd, pathname)?;
+    let vfs_fd = crate::filesystem::open(&path, flags, mode)?;
+
+    match crate::fd::install(crate::fd::FileObject::File(vfs_fd), flags) {
+        Some(fd) => Ok(fd as i64),
+        None => {
+            let _ = crate::filesystem::close(vfs_fd);
+            Err(Errno::EMFILE)
         }
     }
 }
 
-fn sys_open(pathname: *const u8, flags: i32, mode: u32) -> i64 {
-    if pathname.is_null() {
-        return -1; // EINVAL
+/// F_GETFL / F_SETFL のみ (F_SETFL で変えられるのは O_NONBLOCK だけ)
+fn sys_fcntl(fd: i32, cmd: i32, arg: u64) -> SysResult {
+    let file = get_file(fd)?;
+    match cmd {
+        F_GETFL => Ok(file.flags() as i64),
+        F_SETFL => {
+            file.set_flags(arg as i32);
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn sys_ftruncate(fd: i32, length: i64) -> SysResult {
+    if length < 0 {
+        return Err(Errno::EINVAL);
+    }
+    get_file(fd)?.truncate(length as usize)?;
+    Ok(0)
+}
+
+fn sys_shm_open(name: *const u8, flags: i32, mode: u32) -> SysResult {
+    let name = user_path(name)?;
+    let handle = crate::shm::open(&name, flags, mode)?;
+    install_fd(crate::fd::FileObject::Shm(handle), flags)
+}
+
+fn sys_shm_unlink(name: *const u8) -> SysResult {
+    let name = user_path(name)?;
+    crate::shm::unlink(&name)?;
+    Ok(0)
+}
+
+fn sys_poll(fds: *mut crate::poll::PollFd, nfds: usize, timeout_ms: i32) -> SysResult {
+    if nfds > crate::fd::MAX_FDS {
+        return Err(Errno::EINVAL);
+    }
+    let mut pollfds = alloc::vec::Vec::with_capacity(nfds);
+    for i in 0..nfds {
+        pollfds.push(read_user(fds.wrapping_add(i) as u64)?);
+    }
+
+    let ready = crate::poll::poll(&mut pollfds, timeout_ms)?;
+    for (i, pollfd) in pollfds.iter().enumerate() {
+        write_user(fds.wrapping_add(i) as u64, pollfd)?;
+    }
+    Ok(ready as i64)
+}
+
+fn sys_mq_open(name: *const u8, flags: i32, mode: u32, attr: *const MqAttr) -> SysResult {
+    let name = user_path(name)?;
+    let attr = if attr.is_null() { None } else { Some(read_user(attr as u64)?) };
+    let handle = crate::mqueue::open(&name, flags, mode, attr)?;
+    install_fd(crate::fd::FileObject::MessageQueue(handle), flags)
+}
+
+fn sys_mq_unlink(name: *const u8) -> SysResult {
+    let name = user_path(name)?;
+    crate::mqueue::unlink(&name)?;
+    Ok(0)
+}
+
+/// fd がメッセージキューならそのハンドルに対して f を呼ぶ
+fn with_mqueue<R>(fd: i32, f: impl FnOnce(&crate::mqueue::MqHandle) -> Result<R, Errno>) -> Result<R, Errno> {
+    let file = get_file(fd)?;
+    match &file.object {
+        crate::fd::FileObject::MessageQueue(queue) => f(queue),
+        _ => Err(Errno::EBADF),
+    }
+}
+
+/// タイムアウトには対応していないので NULL (無期限に待つ) のみ受け付ける
+fn sys_mq_timedsend(fd: i32, msg: *const u8, len: usize, priority: u32,
+    timeout: *const crate::time::Timespec) -> SysResult {
+    if !timeout.is_null() {
+        return Err(Errno::EINVAL);
+    }
+    if len > crate::mqueue::MQ_MSGSIZE_MAX {
+        return Err(Errno::EMSGSIZE);
+    }
+
+    let mut data = vec![0u8; len];
+    copy_from_user(&mut data, msg as u64)?;
+    with_mqueue(fd, |queue| queue.send(&data, priority))?;
+    Ok(0)
+}
+
+fn sys_mq_timedreceive(fd: i32, msg: *mut u8, len: usize, priority: *mut u32,
+    timeout: *const crate::time::Timespec) -> SysResult {
+    if !timeout.is_null() {
+        return Err(Errno::EINVAL);
+    }
+    if !crate::uaccess::access_ok(msg as u64, len, true) {
+        return Err(Errno::EFAULT);
     }
 
-    // パス名を読み取る
-    let path = unsafe {
-        let mut len = 0;
-        while len < 4096 && *pathname.add(len) != 0 {
-            len += 1;
+    let mut data = vec![0u8; core::cmp::min(len, crate::mqueue::MQ_MSGSIZE_MAX)];
+    let (received, prio) = with_mqueue(fd, |queue| queue.receive(&mut data))?;
+    copy_to_user(msg as u64, &data[..received])?;
+    if !priority.is_null() {
+        write_user(priority as u64, &prio)?;
+    }
+    Ok(received as i64)
+}
+
+/// new_attr があれば O_NONBLOCK を設定し、old_attr には変更前の属性を返す
+fn sys_mq_getsetattr(fd: i32, new_attr: *const MqAttr, old_attr: *mut MqAttr) -> SysResult {
+    let new_attr = if new_attr.is_null() { None } else { Some(read_user::<MqAttr>(new_attr as u64)?) };
+    with_mqueue(fd, |queue| {
+        if !old_attr.is_null() {
+            write_user(old_attr as u64, &queue.attr())?;
         }
-        core::str::from_utf8_unchecked(core::slice::from_raw_parts(pathname, len))
-    };
+        if let Some(attr) = new_attr {
+            queue.set_flags(attr.mq_flags);
+        }
+        Ok(0)
+    })
+}
+
+fn sys_close(fd: i32) -> SysResult {
+    if crate::fd::close(fd) { Ok(0) } else { Err(Errno::EBADF) }
+}
+
+fn sys_getcwd(buf: *mut u8, size: usize) -> SysResult {
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+
+    let cwd = crate::process::current_cwd();
+    // NUL終端分も含めて収まらなければ ERANGE
+    if cwd.len() + 1 > size {
+        return Err(Errno::ERANGE);
+    }
+
+    let mut bytes = cwd.into_bytes();
+    bytes.push(0);
+    copy_to_user(buf as u64, &bytes)?;
+    Ok(bytes.len() as i64)
+}
+
+fn sys_chdir(pathname: *const u8) -> SysResult {
+    use crate::filesystem::{self, S_IFDIR, S_IFMT};
+
+    let path = filesystem::absolute_path(&user_path(pathname)?);
+    let stat = filesystem::stat(&path)?;
+    if stat.st_mode & S_IFMT != S_IFDIR {
+        return Err(Errno::ENOTDIR);
+    }
+    if crate::process::set_cwd(filesystem::normalize_path(&path)) { Ok(0) } else { Err(Errno::ESRCH) }
+}
+
+fn sys_mkdirat(dirfd: i32, pathname: *const u8, mode: u32) -> SysResult {
+    crate::filesystem::mkdir(&user_path_at(dirfd, pathname)?, mode)?;
+    Ok(0)
+}
+
+/// flags に AT_REMOVEDIR があれば rmdir、なければ unlink
+fn sys_unlinkat(dirfd: i32, pathname: *const u8, flags: i32) -> SysResult {
+    let path = user_path_at(dirfd, pathname)?;
+    match flags {
+        0 => crate::filesystem::unlink(&path)?,
+        AT_REMOVEDIR => crate::filesystem::rmdir(&path)?,
+        _ => return Err(Errno::EINVAL),
+    }
+    Ok(0)
+}
+
+fn sys_renameat(olddirfd: i32, oldpath: *const u8, newdirfd: i32, newpath: *const u8) -> SysResult {
+    crate::filesystem::rename(&user_path_at(olddirfd, oldpath)?, &user_path_at(newdirfd, newpath)?)?;
+    Ok(0)
+}
+
+fn sys_link(oldpath: *const u8, newpath: *const u8) -> SysResult {
+    crate::filesystem::link(&user_path(oldpath)?, &user_path(newpath)?)?;
+    Ok(0)
+}
+
+fn sys_symlink(target: *const u8, linkpath: *const u8) -> SysResult {
+    crate::filesystem::symlink(&user_path(target)?, &user_path(linkpath)?)?;
+    Ok(0)
+}
+
+fn sys_readlinkat(dirfd: i32, pathname: *const u8, buf: *mut u8, bufsiz: usize) -> SysResult {
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
 
-    crate::filesystem::open(path, flags, mode)
+    let target = crate::filesystem::readlink(&user_path_at(dirfd, pathname)?)?;
+    // readlink は NUL 終端しない
+    let len = core::cmp::min(target.len(), bufsiz);
+    copy_to_user(buf as u64, &target.as_bytes()[..len])?;
+    Ok(len as i64)
 }
 
-fn sys_close(fd: i32) -> i64 {
-    crate::filesystem::close(fd)
+fn sys_getdents64(fd: i32, dirp: *mut u8, count: usize) -> SysResult {
+    if dirp.is_null() || !crate::uaccess::access_ok(dirp as u64, count, true) {
+        return Err(Errno::EFAULT);
+    }
+    let file = get_file(fd)?;
+
+    // 入りきらなかったエントリは次の呼び出しで返す
+    let mut chunk = vec![0u8; core::cmp::min(count, USER_CHUNK_SIZE)];
+    let filled = file.getdents(&mut chunk)?;
+    copy_to_user(dirp as u64, &chunk[..filled])?;
+    Ok(filled as i64)
+}
+
+/// ページキャッシュの汚れたページをすべてディスクへ書き戻す (失敗しても 0 を返す)
+/// persist= が指定されていれば ramfs のスナップショットも保存する
+fn sys_sync() -> SysResult {
+    if crate::snapshot::is_configured() {
+        if let Err(e) = crate::snapshot::persist() {
+            crate::warn!("persist: {}", e);
+        }
+    }
+    let _ = crate::drivers::page_cache::sync();
+    Ok(0)
+}
+
+/// point (fault::FaultPoint の番号) を interval 回に1回、times 回失敗させる (root のみ)
+/// interval が 0 なら止める。これまでに失敗させた回数を返す
+fn sys_fault_inject(point: u32, interval: usize, times: usize) -> SysResult {
+    if !crate::cred::current().is_root() {
+        return Err(Errno::EPERM);
+    }
+    let point = crate::fault::FaultPoint::from_u32(point).ok_or(Errno::EINVAL)?;
+    let injected = crate::fault::injected(point);
+    crate::fault::configure(point, interval, times);
+    Ok(injected as i64)
+}
+
+/// 再起動・電源断・停止 (成功すれば戻らない)
+fn sys_reboot(magic1: u32, magic2: u32, cmd: u32) -> SysResult {
+    let magic2_ok = matches!(magic2,
+        LINUX_REBOOT_MAGIC2 | LINUX_REBOOT_MAGIC2A | LINUX_REBOOT_MAGIC2B | LINUX_REBOOT_MAGIC2C);
+    if magic1 != LINUX_REBOOT_MAGIC1 || !magic2_ok {
+        return Err(Errno::EINVAL);
+    }
+    match cmd {
+        LINUX_REBOOT_CMD_RESTART => crate::power::reboot(),
+        LINUX_REBOOT_CMD_POWER_OFF => crate::power::power_off(),
+        LINUX_REBOOT_CMD_HALT => crate::power::halt(),
+        // Ctrl-Alt-Del の扱いは切り替えられないので受け付けるだけ
+        LINUX_REBOOT_CMD_CAD_ON | LINUX_REBOOT_CMD_CAD_OFF => Ok(0),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn sys_getrandom(buf: *mut u8, count: usize, flags: u32) -> SysResult {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    if buf.is_null() || !crate::uaccess::access_ok(buf as u64, count, true) {
+        return Err(Errno::EFAULT);
+    }
+
+    // 一度に返すのはバウンスバッファ分まで (短い読み込みは許される)
+    let mut chunk = vec![0u8; core::cmp::min(count, USER_CHUNK_SIZE)];
+    crate::rand::fill(&mut chunk);
+    copy_to_user(buf as u64, &chunk)?;
+    Ok(chunk.len() as i64)
+}
+
+/// シンボリックリンクは常にたどる (AT_SYMLINK_NOFOLLOW などのフラグは未対応)
+fn sys_newfstatat(dirfd: i32, pathname: *const u8, statbuf: *mut crate::filesystem::Stat, flags: i32) -> SysResult {
+    if statbuf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let stat = crate::filesystem::stat(&user_path_at(dirfd, pathname)?)?;
+    write_user(statbuf as u64, &stat)?;
+    Ok(0)
+}
+
+fn sys_fstat(fd: i32, statbuf: *mut crate::filesystem::Stat) -> SysResult {
+    if statbuf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+
+    let stat = get_file(fd)?.stat().ok_or(Errno::EBADF)?;
+    write_user(statbuf as u64, &stat)?;
+    Ok(0)
+}
+
+fn sys_statfs(pathname: *const u8, buf: *mut crate::filesystem::StatFs) -> SysResult {
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+
+    let statfs = crate::filesystem::statfs(&user_path(pathname)?)?;
+    write_user(buf as u64, &statfs)?;
+    Ok(0)
+}
+
+fn sys_lseek(fd: i32, offset: i64, whence: i32) -> SysResult {
+    Ok(get_file(fd)?.seek(offset, whence)? as i64)
+}
+
+/// flags は O_NONBLOCK のみ反映する (他は無視する)
+fn sys_pipe2(fds: *mut i32, flags: i32) -> SysResult {
+    use crate::fd::FileObject;
+    use crate::filesystem::{O_NONBLOCK, O_RDONLY, O_WRONLY};
+
+    if fds.is_null() || !crate::uaccess::access_ok(fds as u64, 2 * core::mem::size_of::<i32>(), true) {
+        return Err(Errno::EFAULT);
+    }
+
+    let (reader, writer) = crate::pipe::create();
+    let nonblock = flags & O_NONBLOCK;
+    let read_fd = crate::fd::install(FileObject::PipeRead(reader), O_RDONLY | nonblock).ok_or(Errno::EMFILE)?;
+    let write_fd = match crate::fd::install(FileObject::PipeWrite(writer), O_WRONLY | nonblock) {
+        Some(fd) => fd,
+        None => {
+            crate::fd::close(read_fd);
+            return Err(Errno::EMFILE);
+        }
+    };
+
+    // 事前に範囲は確認済み
+    if let Err(e) = write_user(fds as u64, &[read_fd, write_fd]) {
+        crate::fd::close(read_fd);
+        crate::fd::close(write_fd);
+        return Err(e);
+    }
+    Ok(0)
 }
 
-fn sys_exit(status: i32) -> i64 {
-    crate::println!("Process exiting with status: {}", status);
+fn sys_exit(status: i32) -> SysResult {
+    crate::info!("Process exiting with status: {}", status);
     crate::process::exit(status);
-    
+
     // プロセスを終了させるのでここには戻らない
     unreachable!()
 }
 
-fn sys_fork() -> i64 {
+fn sys_fork() -> SysResult {
     // fork実装 - 現在のプロセスを複製
-    crate::println!("fork() called - not fully implemented");
-    -1 // ENOSYS - 簡略版では未実装
+    // 親には子のPID、子には0が返る
+    Ok(crate::process::fork()? as i64)
 }
 
-fn sys_execve(filename: *const u8, argv: *const *const u8, envp: *const *const u8) -> i64 {
+/// clone(flags, newsp, parent_tid, child_tid, tls)
+/// CLONE_VM|CLONE_FILES で同じプロセスにスレッドを作り (TID を返す)、フラグがなければ fork と同じ
+/// newsp が 0 でなければ新しいスレッドのスタックポインタにする
+fn sys_clone(flags: u64, newsp: u64, tls: u64) -> SysResult {
+    let stack = (newsp != 0).then_some(newsp);
+    Ok(crate::process::clone(flags, stack, tls)? as i64)
+}
+
+// arch_prctl のコード
+pub const ARCH_SET_FS: i32 = 0x1002;
+pub const ARCH_GET_FS: i32 = 0x1003;
+
+/// arch_prctl(code, addr)
+/// FS ベース (TLS) の設定と取得だけに対応する (GS ベースはカーネルがCPUごとのデータに使っている)
+fn sys_arch_prctl(code: i32, addr: u64) -> SysResult {
+    match code {
+        ARCH_SET_FS => crate::process::set_fs_base(addr)?,
+        ARCH_GET_FS => {
+            let fs_base = crate::process::fs_base().ok_or(Errno::ESRCH)?;
+            write_user(addr, &fs_base)?;
+        }
+        _ => return Err(Errno::EINVAL),
+    }
+    Ok(0)
+}
+
+fn sys_dup(fd: i32) -> SysResult {
+    get_file(fd)?;
+    crate::fd::dup(fd).map(|new_fd| new_fd as i64).ok_or(Errno::EMFILE)
+}
+
+fn sys_dup2(old_fd: i32, new_fd: i32) -> SysResult {
+    get_file(old_fd)?;
+    crate::fd::dup2(old_fd, new_fd).map(|fd| fd as i64).ok_or(Errno::EBADF)
+}
+
+fn sys_execve(filename: *const u8, argv: *const *const u8, envp: *const *const u8) -> SysResult {
     if filename.is_null() {
-        return -1; // EINVAL
+        return Err(Errno::EFAULT);
     }
 
-    crate::println!("execve() called - not fully implemented");
-    -1 // ENOSYS
+    crate::warn!("execve() called - not fully implemented");
+    Err(Errno::ENOSYS)
+}
+
+/// getuid/getgid/geteuid/getegid
+fn sys_getid(number: u64) -> SysResult {
+    let cred = crate::cred::current();
+    let id = match number {
+        SYS_GETUID => cred.uid,
+        SYS_GETGID => cred.gid,
+        SYS_GETEUID => cred.euid,
+        _ => cred.egid,
+    };
+    Ok(id as i64)
+}
+
+fn sys_setuid(uid: u32) -> SysResult {
+    crate::cred::setuid(uid)?;
+    Ok(0)
+}
+
+fn sys_setgid(gid: u32) -> SysResult {
+    crate::cred::setgid(gid)?;
+    Ok(0)
+}
+
+/// 呼び出したプロセスの PID (プロセスのないカーネルの文脈では 0)
+fn sys_getpid() -> SysResult {
+    Ok(crate::process::current_pid().unwrap_or(0) as i64)
+}
+
+/// 親プロセスの PID (カーネルが起動したプロセスは 0)
+fn sys_getppid() -> SysResult {
+    Ok(crate::process::current_ppid().unwrap_or(0) as i64)
 }
 
-fn sys_getpid() -> i64 {
-    // 現在のプロセスIDを返す
-    // 簡略版: 固定値を返す
-    1
+/// 呼び出したスレッドの TID (メインスレッドなら PID と同じ)
+fn sys_gettid() -> SysResult {
+    Ok(crate::process::current_tid().unwrap_or(0) as i64)
 }
 
-fn sys_sleep(nanoseconds: u64) -> i64 {
+fn sys_sleep(nanoseconds: u64) -> SysResult {
     // プロセスをスリープ
-    crate::println!("sleep({}) called", nanoseconds);
-    
+    crate::debug!("sleep({}) called", nanoseconds);
+
     // 簡易実装: ビジーウェイト
     for _ in 0..nanoseconds / 1000 {
         unsafe { core::arch::asm!("pause"); }
     }
-    
-    0
+
+    Ok(0)
 }
 
-fn sys_mmap(addr: u64, length: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> i64 {
-    // メモリマッピング
-    let pages = (length + 4095) / 4096;
-    
-    if let Some(virt_addr) = crate::memory::allocate_pages(pages) {
-        virt_addr.as_u64() as i64
+fn sys_clock_gettime(clock_id: i32, tp: *mut crate::time::Timespec) -> SysResult {
+    if tp.is_null() {
+        return Err(Errno::EFAULT);
+    }
+
+    let ts = crate::time::clock_gettime(clock_id).ok_or(Errno::EINVAL)?;
+    write_user(tp as u64, &ts)?;
+    Ok(0)
+}
+
+/// タイムゾーン (第2引数) は扱わない
+fn sys_gettimeofday(tv: *mut crate::time::Timeval) -> SysResult {
+    if tv.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let now = crate::time::realtime_ns(crate::time::monotonic_ns());
+    write_user(tv as u64, &crate::time::Timeval::from_ns(now))?;
+    Ok(0)
+}
+
+fn sys_mmap(addr: u64, length: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> SysResult {
+    use x86_64::structures::paging::PageTableFlags as Flags;
+
+    if length == 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    // fd を渡さない呼び出しは無名マッピングとして扱う
+    let file = if flags & MAP_ANONYMOUS == 0 && fd >= 0 {
+        Some(file_mapping(fd, prot, flags, offset)?)
     } else {
-        -1 // ENOMEM
+        None
+    };
+
+    // 領域を予約するだけで、フレームは初回アクセス時に割り当てる
+    // PROT_WRITE がなければ書き込みを、PROT_EXEC がなければ実行を禁止する
+    let mut page_flags = Flags::PRESENT | Flags::USER_ACCESSIBLE;
+    if prot & PROT_WRITE != 0 {
+        page_flags |= Flags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        page_flags |= crate::memory::no_execute();
     }
+
+    let virt_addr = crate::process::reserve_region(length, page_flags, file)?;
+    Ok(virt_addr.as_u64() as i64)
 }
 
-fn sys_munmap(addr: u64, length: usize) -> i64 {
+/// ファイルマッピングの読み込み元を用意する
+/// MAP_PRIVATE の書き込みはフォルト時にコピーしたページに対して行われ、ファイルには反映されない
+/// MAP_SHARED は共有メモリオブジェクトだけが対象で、同じオブジェクトをマップしたプロセス同士でフレームを共有する
+fn file_mapping(fd: i32, prot: i32, flags: i32, offset: i64) -> Result<crate::memory::VmaFile, Errno> {
+    use crate::filesystem::{S_IFMT, S_IFREG};
+    use crate::fd::FileObject;
+
+    let shared = match flags & (MAP_PRIVATE | MAP_SHARED) {
+        MAP_PRIVATE => false,
+        MAP_SHARED => true,
+        _ => return Err(Errno::EINVAL),
+    };
+    if offset < 0 || offset % 4096 != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let file = get_file(fd)?;
+    if shared {
+        match &file.object {
+            FileObject::Shm(shm) if prot & PROT_WRITE != 0 && !shm.writable() => return Err(Errno::EACCES),
+            FileObject::Shm(_) => {}
+            _ => return Err(Errno::ENODEV),
+        }
+    }
+    match file.stat() {
+        Some(stat) if stat.st_mode & S_IFMT == S_IFREG => {}
+        _ => return Err(Errno::ENODEV),
+    }
+    Ok(crate::memory::VmaFile { file, offset: offset as u64, shared })
+}
+
+fn sys_munmap(addr: u64, length: usize) -> SysResult {
+    if addr % 4096 != 0 || addr >= crate::uaccess::USER_SPACE_END {
+        return Err(Errno::EINVAL);
+    }
+
     // メモリマッピング解除
+    // 共有メモリはVMAが最後の参照のことがあるので、フレームが解放される前にマップを外しておく
     let pages = (length + 4095) / 4096;
-    crate::memory::deallocate_pages(x86_64::VirtAddr::new(addr), pages);
-    0
+    let unmapped = crate::memory::deallocate_pages(x86_64::VirtAddr::new(addr), pages);
+    crate::process::release_region(x86_64::VirtAddr::new(addr), length);
+    crate::process::with_current_process(|process| {
+        process.mapped_pages = process.mapped_pages.saturating_sub(unmapped);
+    });
+    Ok(0)
+}
+
+/// Linux と同じく、成功しても失敗しても現在のブレークを返す (brk(0) で問い合わせ)
+/// sbrk はユーザー側でこれを使って実装する
+fn sys_brk(addr: u64) -> SysResult {
+    let current = crate::process::current_brk().ok_or(Errno::ESRCH)?;
+    if addr == 0 {
+        return Ok(current.as_u64() as i64);
+    }
+
+    let new_brk = match x86_64::VirtAddr::try_new(addr) {
+        Ok(new_brk) if addr < crate::uaccess::USER_SPACE_END => new_brk,
+        _ => return Ok(current.as_u64() as i64),
+    };
+    match crate::process::set_brk(new_brk) {
+        Ok(()) => Ok(new_brk.as_u64() as i64),
+        Err(_) => Ok(current.as_u64() as i64),
+    }
+}
+
+/// Linux の struct sysinfo と同じレイアウト
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SysInfo {
+    pub uptime: i64,
+    pub loads: [u64; 3],
+    pub totalram: u64,
+    pub freeram: u64,
+    pub sharedram: u64,
+    pub bufferram: u64,
+    pub totalswap: u64,
+    pub freeswap: u64,
+    pub procs: u16,
+    pub pad: u16,
+    pub totalhigh: u64,
+    pub freehigh: u64,
+    pub mem_unit: u32,
+}
+
+fn sys_sysinfo(info: *mut SysInfo) -> SysResult {
+    if info.is_null() {
+        return Err(Errno::EFAULT);
+    }
+
+    let stats = crate::memory::stats();
+    let result = SysInfo {
+        uptime: (crate::drivers::timer::get_uptime_ms() / 1000) as i64,
+        totalram: stats.usable_frames,
+        freeram: stats.usable_frames.saturating_sub(stats.allocated_frames),
+        // カーネルヒープはバッファ相当として報告する
+        bufferram: (stats.heap_size as u64) / crate::memory::FRAME_SIZE,
+        procs: crate::process::count() as u16,
+        mem_unit: crate::memory::FRAME_SIZE as u32,
+        ..SysInfo::default()
+    };
+    write_user(info as u64, &result)?;
+    Ok(0)
+}
+
+fn sys_getrlimit(resource: usize, limit: *mut RLimit) -> SysResult {
+    if limit.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    write_user(limit as u64, &crate::rlimit::getrlimit(resource)?)?;
+    Ok(0)
+}
+
+fn sys_setrlimit(resource: usize, limit: *const RLimit) -> SysResult {
+    if limit.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    crate::rlimit::setrlimit(resource, read_user(limit as u64)?)?;
+    Ok(0)
+}
+
+fn sys_ioperm(from: usize, num: usize, turn_on: i32) -> SysResult {
+    crate::ioport::ioperm(from, num, turn_on != 0)?;
+    Ok(0)
+}
+
+fn sys_iopl(level: u32) -> SysResult {
+    crate::ioport::iopl(level)?;
+    Ok(0)
+}
+
+/// mask は CPU 番号をビット番号にしたビット列 (リトルエンディアン)
+/// CPU_MASK_BYTES より長い部分は存在しない CPU なので読まない
+fn sys_sched_setaffinity(tid: usize, len: usize, mask: *const u8) -> SysResult {
+    use crate::sched::{CpuMask, CPU_MASK_BYTES};
+    let mut bytes = [0u8; CPU_MASK_BYTES];
+    let len = len.min(CPU_MASK_BYTES);
+    copy_from_user(&mut bytes[..len], mask as u64)?;
+    let mask = CpuMask::from_bits(u64::from_le_bytes(bytes));
+    crate::process::set_affinity(tid, mask)?;
+    Ok(0)
+}
+
+/// 書き込んだバイト数を返す (Linux と同じ)
+fn sys_sched_getaffinity(tid: usize, len: usize, mask: *mut u8) -> SysResult {
+    use crate::sched::{CpuMask, CPU_MASK_BYTES};
+    if len < CPU_MASK_BYTES {
+        return Err(Errno::EINVAL);
+    }
+    let allowed = crate::process::affinity(tid)?.intersect(CpuMask::online());
+    copy_to_user(mask as u64, &allowed.bits().to_le_bytes())?;
+    Ok(CPU_MASK_BYTES as i64)
 }
 
 // ユーザー空間から呼び出すためのラッパー関数（例）
+// initramfs のユーザープログラムは user/librost を使う (こちらはカーネル内のテスト用)
 pub mod user {
     use super::*;
 
@@ -241,14 +1245,60 @@ pub mod user {
         unsafe { syscall0(SYS_GETPID) as i32 }
     }
 
+    #[inline(always)]
+    pub fn getppid() -> i32 {
+        unsafe { syscall0(SYS_GETPPID) as i32 }
+    }
+
+    #[inline(always)]
+    pub fn gettid() -> i32 {
+        unsafe { syscall0(SYS_GETTID) as i32 }
+    }
+
+    /// times に usize::MAX を渡すと何回でも失敗させる
+    #[inline(always)]
+    pub fn fault_inject(point: u32, interval: usize, times: usize) -> i64 {
+        unsafe { syscall3(SYS_FAULT_INJECT, point as u64, interval as u64, times as u64) }
+    }
+
+    /// vdso のデータページ (カーネルが起動時にマップしておく)
+    #[inline(always)]
+    fn vdso_data() -> crate::vdso::VdsoData {
+        // VVAR_ADDR はカーネルがすべてのプロセスにマップしている
+        unsafe { crate::vdso::read_data(crate::memory::VVAR_ADDR as *const crate::vdso::VdsoData) }
+    }
+
+    /// REALTIME/MONOTONIC はデータページから読み、システムコールを発行しない
+    #[inline(always)]
+    pub fn clock_gettime(clock_id: i32, tp: &mut crate::time::Timespec) -> isize {
+        match vdso_data().clock_gettime(clock_id) {
+            Some(now) => {
+                *tp = now;
+                0
+            }
+            None => unsafe {
+                syscall2(SYS_CLOCK_GETTIME, clock_id as u64, tp as *mut crate::time::Timespec as u64) as isize
+            },
+        }
+    }
+
+    #[inline(always)]
+    pub fn gettimeofday(tv: &mut crate::time::Timeval) -> isize {
+        *tv = vdso_data().gettimeofday();
+        0
+    }
+
     // システムコールを発行するアセンブリラッパー
+    // syscall 命令は rcx/r11 を破壊する
     #[inline(always)]
     unsafe fn syscall0(number: u64) -> i64 {
         let ret: i64;
         core::arch::asm!(
-            "int 0x80",
+            "syscall",
             in("rax") number,
             lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
         );
         ret
     }
@@ -257,10 +1307,27 @@ pub mod user {
     unsafe fn syscall1(number: u64, arg1: u64) -> i64 {
         let ret: i64;
         core::arch::asm!(
-            "int 0x80",
+            "syscall",
             in("rax") number,
             in("rdi") arg1,
             lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
+        );
+        ret
+    }
+
+    #[inline(always)]
+    unsafe fn syscall2(number: u64, arg1: u64, arg2: u64) -> i64 {
+        let ret: i64;
+        core::arch::asm!(
+            "syscall",
+            in("rax") number,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
         );
         ret
     }
@@ -269,12 +1336,14 @@ pub mod user {
     unsafe fn syscall3(number: u64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
         let ret: i64;
         core::arch::asm!(
-            "int 0x80",
+            "syscall",
             in("rax") number,
             in("rdi") arg1,
             in("rsi") arg2,
             in("rdx") arg3,
             lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
         );
         ret
     }
@@ -0,0 +1,137 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::drivers::rtc::{self, DateTime};
+
+pub const CLOCK_REALTIME: i32 = 0;
+pub const CLOCK_MONOTONIC: i32 = 1;
+
+// 起動時点の Unix 時刻 (秒) と、そのときの稼働時間 (ミリ秒)
+static BOOT_EPOCH: AtomicU64 = AtomicU64::new(0);
+static BOOT_UPTIME_MS: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl Timespec {
+    pub const fn from_ns(ns: u64) -> Self {
+        Self {
+            tv_sec: (ns / 1_000_000_000) as i64,
+            tv_nsec: (ns % 1_000_000_000) as i64,
+        }
+    }
+}
+
+/// gettimeofday が返す時刻 (マイクロ秒単位)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+impl Timeval {
+    pub const fn from_ns(ns: u64) -> Self {
+        Self {
+            tv_sec: (ns / 1_000_000_000) as i64,
+            tv_usec: (ns % 1_000_000_000 / 1000) as i64,
+        }
+    }
+}
+
+pub fn init() {
+    let now = rtc::read();
+    BOOT_UPTIME_MS.store(crate::drivers::timer::get_uptime_ms() as u64, Ordering::SeqCst);
+    BOOT_EPOCH.store(datetime_to_unix(&now), Ordering::SeqCst);
+    crate::info!("Wall clock: {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        now.year, now.month, now.day, now.hour, now.minute, now.second);
+}
+
+fn is_leap_year(year: u64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// 日時を 1970-01-01 00:00:00 UTC からの秒数に変換する
+pub fn datetime_to_unix(dt: &DateTime) -> u64 {
+    const DAYS_BEFORE_MONTH: [u64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+    let year = dt.year as u64;
+    let mut days = (1970..year).map(|y| if is_leap_year(y) { 366 } else { 365 }).sum::<u64>();
+    days += DAYS_BEFORE_MONTH[(dt.month.clamp(1, 12) - 1) as usize];
+    if dt.month > 2 && is_leap_year(year) {
+        days += 1;
+    }
+    days += dt.day.max(1) as u64 - 1;
+
+    days * 86400 + dt.hour as u64 * 3600 + dt.minute as u64 * 60 + dt.second as u64
+}
+
+/// Unix 時刻を日時に変換する
+pub fn unix_to_datetime(timestamp: u64) -> DateTime {
+    let mut days = timestamp / 86400;
+    let secs = timestamp % 86400;
+
+    let mut year = 1970u64;
+    loop {
+        let year_days = if is_leap_year(year) { 366 } else { 365 };
+        if days < year_days {
+            break;
+        }
+        days -= year_days;
+        year += 1;
+    }
+
+    let month_days = [31, if is_leap_year(year) { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let mut month = 0;
+    while days >= month_days[month] {
+        days -= month_days[month];
+        month += 1;
+    }
+
+    DateTime {
+        year: year as u16,
+        month: month as u8 + 1,
+        day: days as u8 + 1,
+        hour: (secs / 3600) as u8,
+        minute: (secs % 3600 / 60) as u8,
+        second: (secs % 60) as u8,
+    }
+}
+
+/// 起動からの経過時間 (ミリ秒)
+pub fn monotonic_ms() -> u64 {
+    crate::drivers::timer::get_uptime_ms() as u64
+}
+
+/// 起動からの経過時間 (ナノ秒)
+pub fn monotonic_ns() -> u64 {
+    crate::drivers::timer::now_ns()
+}
+
+/// 現在の Unix 時刻 (ミリ秒)
+pub fn unix_time_ms() -> u64 {
+    let elapsed = monotonic_ms().saturating_sub(BOOT_UPTIME_MS.load(Ordering::SeqCst));
+    BOOT_EPOCH.load(Ordering::SeqCst) * 1000 + elapsed
+}
+
+/// 現在の Unix 時刻 (秒)
+pub fn unix_time() -> u64 {
+    unix_time_ms() / 1000
+}
+
+/// Unix 時刻 (ナノ秒) を起動からの経過時間 monotonic (ナノ秒) から求める
+pub fn realtime_ns(monotonic: u64) -> u64 {
+    let elapsed = monotonic.saturating_sub(BOOT_UPTIME_MS.load(Ordering::SeqCst) * 1_000_000);
+    BOOT_EPOCH.load(Ordering::SeqCst) * 1_000_000_000 + elapsed
+}
+
+pub fn clock_gettime(clock_id: i32) -> Option<Timespec> {
+    let ns = match clock_id {
+        CLOCK_REALTIME => realtime_ns(monotonic_ns()),
+        CLOCK_MONOTONIC => monotonic_ns(),
+        _ => return None,
+    };
+    Some(Timespec::from_ns(ns))
+}
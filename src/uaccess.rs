@@ -0,0 +1,207 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::errno::Errno;
+use crate::cpu::{self, Feature};
+
+/// ユーザー空間の上限 (正規アドレスの下半分)
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+/// パス名の最大長 (NUL を含む)
+pub const PATH_MAX: usize = 4096;
+
+// BSP で有効にした保護 (AP も同じものを有効にする)
+static SMEP_ENABLED: AtomicBool = AtomicBool::new(false);
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+static UMIP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// ハードウェアによるユーザー空間の保護
+/// SMEP: カーネルがユーザーページを実行すると、SMAP: user_access_begin/end の外でユーザーページに触れるとページフォルトになる
+/// UMIP: ユーザーモードの sgdt/sidt/sldt/smsw/str を禁止する
+#[derive(Debug, Clone, Copy)]
+pub struct Protection {
+    pub smep: bool,
+    pub smap: bool,
+    pub umip: bool,
+}
+
+/// CPU が対応している保護を CR4 で有効にする (BSP で呼ぶ)
+pub fn init() -> Protection {
+    SMEP_ENABLED.store(cpu::has(Feature::Smep), Ordering::SeqCst);
+    SMAP_ENABLED.store(cpu::has(Feature::Smap), Ordering::SeqCst);
+    UMIP_ENABLED.store(cpu::has(Feature::Umip), Ordering::SeqCst);
+    init_ap();
+    protection()
+}
+
+/// BSP と同じ保護をこの CPU でも有効にする
+pub fn init_ap() {
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+
+    let protection = protection();
+    let mut flags = Cr4Flags::empty();
+    flags.set(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION, protection.smep);
+    flags.set(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION, protection.smap);
+    flags.set(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION, protection.umip);
+    // 割り込み元が立てた AC が残っていても、ここから先は閉じた状態で始める
+    user_access_end();
+    unsafe {
+        Cr4::update(|cr4| *cr4 |= flags);
+    }
+}
+
+pub fn protection() -> Protection {
+    Protection {
+        smep: SMEP_ENABLED.load(Ordering::Relaxed),
+        smap: SMAP_ENABLED.load(Ordering::Relaxed),
+        umip: UMIP_ENABLED.load(Ordering::Relaxed),
+    }
+}
+
+/// ユーザー空間に触れる区間の始まり (RFLAGS.AC を立てて SMAP を一時的に外す)
+/// 必ず user_access_end と対にし、その間では範囲を確認済みのアクセスだけを行う
+#[inline(always)]
+pub fn user_access_begin() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe { core::arch::asm!("stac", options(nomem, nostack)) };
+    }
+}
+
+/// ユーザー空間に触れる区間の終わり (RFLAGS.AC を下ろす)
+#[inline(always)]
+pub fn user_access_end() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe { core::arch::asm!("clac", options(nomem, nostack)) };
+    }
+}
+
+/// カーネルモードでのユーザーアドレスへのフォルトが SMEP/SMAP 違反なら説明を返す
+/// access_window は フォルト時に RFLAGS.AC が立っていたか (user_access_begin の中だったか)
+pub fn describe_fault(addr: u64, instruction_fetch: bool, access_window: bool) -> Option<&'static str> {
+    if addr >= USER_SPACE_END {
+        return None;
+    }
+    let protection = protection();
+    if instruction_fetch && protection.smep {
+        Some("SMEP violation: kernel tried to execute a user page")
+    } else if !instruction_fetch && protection.smap && !access_window {
+        Some("SMAP violation: kernel accessed user memory outside user_access_begin/end")
+    } else {
+        None
+    }
+}
+
+/// [addr, addr + len) が呼び出し元プロセスからアクセスできる範囲か調べる
+/// ユーザープロセスなら VMA かユーザースタックに収まっている必要がある
+/// カーネルスレッドなら範囲チェックのみ行い、プロセスがない (カーネルコンテキスト) なら拒否する
+pub fn access_ok(addr: u64, len: usize, write: bool) -> bool {
+    if len == 0 {
+        return true;
+    }
+    if addr == 0 {
+        return false;
+    }
+    let end = match addr.checked_add(len as u64) {
+        Some(end) => end,
+        None => return false,
+    };
+    if end > USER_SPACE_END {
+        return false;
+    }
+
+    crate::process::with_current_process(|process| process.user_range_ok(addr, end, write))
+        .unwrap_or(false)
+}
+
+// ユーザー空間とのコピー (rdi = dst, rsi = src, rdx = len)。コピーできなかったバイト数を返す
+// rep movsb でフォルトしてデマンドページングでも解決できなければ、
+// ページフォルトハンドラが uaccess_copy_fixup から再開させる (rcx に残りのバイト数が入っている)
+global_asm!(r#"
+.global uaccess_copy
+uaccess_copy:
+    mov rcx, rdx
+.global uaccess_copy_insn
+uaccess_copy_insn:
+    rep movsb
+    xor eax, eax
+    ret
+.global uaccess_copy_fixup
+uaccess_copy_fixup:
+    mov rax, rcx
+    ret
+"#);
+
+extern "C" {
+    fn uaccess_copy(dst: u64, src: u64, len: usize) -> usize;
+    static uaccess_copy_insn: u8;
+    static uaccess_copy_fixup: u8;
+}
+
+/// ユーザー空間へのアクセスでフォルトした命令なら、再開するアドレスを返す
+/// (カーネルモードのページフォルトで、アドレスがユーザー空間のときにだけ使う)
+pub fn fixup(rip: u64) -> Option<u64> {
+    let insn = core::ptr::addr_of!(uaccess_copy_insn) as u64;
+    (rip == insn).then(|| core::ptr::addr_of!(uaccess_copy_fixup) as u64)
+}
+
+/// 範囲を確認済みの [src, src + len) を dst へコピーする。途中でフォルトしたら EFAULT
+fn copy(dst: u64, src: u64, len: usize) -> Result<(), Errno> {
+    user_access_begin();
+    let remaining = unsafe { uaccess_copy(dst, src, len) };
+    user_access_end();
+    if remaining == 0 { Ok(()) } else { Err(Errno::EFAULT) }
+}
+
+/// ユーザー空間の src から dst.len() バイトをコピーする
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), Errno> {
+    if !access_ok(src, dst.len(), false) {
+        return Err(Errno::EFAULT);
+    }
+    copy(dst.as_mut_ptr() as u64, src, dst.len())
+}
+
+/// ユーザー空間の dst へ src をコピーする
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), Errno> {
+    if !access_ok(dst, src.len(), true) {
+        return Err(Errno::EFAULT);
+    }
+    copy(dst, src.as_ptr() as u64, src.len())
+}
+
+/// ユーザー空間の構造体を読み取る
+pub fn read_user<T: Copy>(src: u64) -> Result<T, Errno> {
+    if !access_ok(src, core::mem::size_of::<T>(), false) {
+        return Err(Errno::EFAULT);
+    }
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    copy(value.as_mut_ptr() as u64, src, core::mem::size_of::<T>())?;
+    // T: Copy はどのビット列でも構わない POD 構造体として使っている
+    Ok(unsafe { value.assume_init() })
+}
+
+/// ユーザー空間へ構造体を書き込む
+pub fn write_user<T: Copy>(dst: u64, value: &T) -> Result<(), Errno> {
+    if !access_ok(dst, core::mem::size_of::<T>(), true) {
+        return Err(Errno::EFAULT);
+    }
+    copy(dst, value as *const T as u64, core::mem::size_of::<T>())
+}
+
+/// NUL 終端の文字列を最大 max バイトまで読み取る
+/// ページをまたぐたびに範囲を確認するので、文字列が VMA の終わりをまたいでも安全に止まる
+pub fn strncpy_from_user(src: u64, max: usize) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    for i in 0..max {
+        let addr = src.checked_add(i as u64).ok_or(Errno::EFAULT)?;
+        if (i == 0 || addr % 4096 == 0) && !access_ok(addr, 1, false) {
+            return Err(Errno::EFAULT);
+        }
+        let mut byte = 0u8;
+        copy(&mut byte as *mut u8 as u64, addr, 1)?;
+        if byte == 0 {
+            return String::from_utf8(bytes).map_err(|_| Errno::EINVAL);
+        }
+        bytes.push(byte);
+    }
+    Err(Errno::ENAMETOOLONG)
+}
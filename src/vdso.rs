@@ -0,0 +1,110 @@
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
+use x86_64::structures::paging::PageTableFlags as Flags;
+use x86_64::VirtAddr;
+use crate::memory::VVAR_ADDR;
+use crate::time::{Timespec, Timeval, CLOCK_MONOTONIC, CLOCK_REALTIME};
+
+/// VVAR_ADDR のページに置く時刻 (タイマー割り込みごとに更新する)
+/// 読む側は seq が偶数で、読む前後で変わっていないことを確かめる (seqlock)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VdsoData {
+    /// 更新中は奇数
+    pub seq: u32,
+    pub tick_hz: u32,
+    pub ticks: u64,
+    /// 更新時点の起動からの経過時間 (ナノ秒)
+    pub monotonic_ns: u64,
+    /// 更新時点の Unix 時刻 (ナノ秒)
+    pub realtime_ns: u64,
+}
+
+/// データページのカーネル側のアドレス (物理メモリのマッピング経由で書く、0 なら未初期化)
+static KERNEL_ADDR: AtomicU64 = AtomicU64::new(0);
+/// 複数のCPUのタイマー割り込みが同時に書かないようにする
+static UPDATING: AtomicBool = AtomicBool::new(false);
+
+/// データページを確保し、ユーザーから読み出し専用でマップする
+pub fn init() -> Result<(), &'static str> {
+    let frame = crate::memory::allocate_zeroed_frame().ok_or("Out of memory")?;
+    let flags = Flags::PRESENT | Flags::USER_ACCESSIBLE | crate::memory::no_execute();
+    crate::memory::map_shared_frame(VirtAddr::new(VVAR_ADDR), frame, flags)?;
+    KERNEL_ADDR.store(crate::memory::phys_to_virt(frame.start_address()).as_u64(), Ordering::SeqCst);
+    update();
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    KERNEL_ADDR.load(Ordering::Relaxed) != 0
+}
+
+/// 現在の時刻をデータページに書く (タイマー割り込みから呼ぶ)
+pub fn update() {
+    let addr = KERNEL_ADDR.load(Ordering::Relaxed);
+    if addr == 0 || UPDATING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let data = addr as *mut VdsoData;
+    let monotonic = crate::time::monotonic_ns();
+    unsafe {
+        let seq = core::ptr::read_volatile(&(*data).seq);
+        core::ptr::write_volatile(&mut (*data).seq, seq.wrapping_add(1));
+        fence(Ordering::Release);
+        core::ptr::write_volatile(data, VdsoData {
+            seq: seq.wrapping_add(1),
+            tick_hz: crate::drivers::timer::tick_rate() as u32,
+            ticks: crate::drivers::timer::get_ticks() as u64,
+            monotonic_ns: monotonic,
+            realtime_ns: crate::time::realtime_ns(monotonic),
+        });
+        fence(Ordering::Release);
+        core::ptr::write_volatile(&mut (*data).seq, seq.wrapping_add(2));
+    }
+    UPDATING.store(false, Ordering::Release);
+}
+
+/// seqlock で一貫した内容を読む
+/// ユーザーモードからは VVAR_ADDR、カーネルからはカーネル側のアドレスを渡す
+///
+/// # Safety
+/// data はマップ済みで、VdsoData を指していること
+#[inline(always)]
+pub unsafe fn read_data(data: *const VdsoData) -> VdsoData {
+    loop {
+        let seq = unsafe { core::ptr::read_volatile(&(*data).seq) };
+        if seq & 1 != 0 {
+            core::hint::spin_loop();
+            continue;
+        }
+        fence(Ordering::Acquire);
+        let snapshot = unsafe { core::ptr::read_volatile(data) };
+        fence(Ordering::Acquire);
+        if unsafe { core::ptr::read_volatile(&(*data).seq) } == seq {
+            return snapshot;
+        }
+    }
+}
+
+/// カーネルから見たデータページの内容 (/proc やテスト用)
+pub fn snapshot() -> Option<VdsoData> {
+    match KERNEL_ADDR.load(Ordering::Relaxed) {
+        0 => None,
+        // KERNEL_ADDR は init でマップしたデータページを指している
+        addr => Some(unsafe { read_data(addr as *const VdsoData) }),
+    }
+}
+
+impl VdsoData {
+    /// clock_gettime の結果 (最後のタイマー割り込みの時点なので、精度はティック単位)
+    pub fn clock_gettime(&self, clock_id: i32) -> Option<Timespec> {
+        match clock_id {
+            CLOCK_REALTIME => Some(Timespec::from_ns(self.realtime_ns)),
+            CLOCK_MONOTONIC => Some(Timespec::from_ns(self.monotonic_ns)),
+            _ => None,
+        }
+    }
+
+    pub fn gettimeofday(&self) -> Timeval {
+        Timeval::from_ns(self.realtime_ns)
+    }
+}
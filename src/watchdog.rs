@@ -0,0 +1,101 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::smp::{cpu_id, MAX_CPUS};
+
+/// 既定のしきい値 (秒)
+const DEFAULT_THRESHOLD_SECS: usize = 10;
+/// カーネルタイマーが動いていることを記録する周期
+const HEARTBEAT_MS: usize = 1000;
+
+// 0 なら無効
+static THRESHOLD_MS: AtomicUsize = AtomicUsize::new(DEFAULT_THRESHOLD_SECS * 1000);
+// ロックアップを検出したらリセットする
+static RESET_ON_LOCKUP: AtomicBool = AtomicBool::new(false);
+// CPUごとにスケジューラが最後に進んだ時刻 (0 ならまだ監視していない)
+static LAST_PROGRESS_MS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+// CPUごとに最後に報告した時刻 (しきい値ごとに1回だけ報告する)
+static LAST_REPORT_MS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+// 心拍タイマーが最後に実行された時刻と、止まっていると最後に報告した時刻
+static LAST_HEARTBEAT_MS: AtomicUsize = AtomicUsize::new(0);
+static LAST_STALL_REPORT_MS: AtomicUsize = AtomicUsize::new(0);
+
+/// コマンドラインの watchdog=<秒> (0 で無効) と watchdog_reset を反映する
+pub fn init() {
+    if let Some(value) = crate::cmdline::get("watchdog") {
+        match value.parse::<usize>() {
+            Ok(secs) => set_threshold(secs),
+            Err(_) => crate::warn!("Invalid watchdog threshold: {}", value),
+        }
+    }
+    RESET_ON_LOCKUP.store(crate::cmdline::has("watchdog_reset"), Ordering::SeqCst);
+    // 最初の心拍が来るまで (割り込みを許可するまで) は監視しない
+    crate::drivers::timer::schedule(HEARTBEAT_MS, heartbeat, 0);
+}
+
+/// 周期的に自分を登録し直すカーネルタイマー
+/// タイマーの後半処理が止まると更新されなくなり、check で検出される
+fn heartbeat(_: usize) {
+    LAST_HEARTBEAT_MS.store(crate::drivers::timer::get_uptime_ms().max(1), Ordering::Relaxed);
+    crate::drivers::timer::schedule(HEARTBEAT_MS, heartbeat, 0);
+}
+
+pub fn set_threshold(secs: usize) {
+    THRESHOLD_MS.store(secs * 1000, Ordering::SeqCst);
+}
+
+/// スケジューラが進んだことを記録する (スケジューラのティックから呼ばれる)
+pub fn touch() {
+    // 起動直後の 0ms と「未監視」を区別するため 1 以上にする
+    let now = crate::drivers::timer::get_uptime_ms().max(1);
+    LAST_PROGRESS_MS[cpu_id()].store(now, Ordering::Relaxed);
+}
+
+/// タイマー割り込みから呼ばれ、このCPUのスケジューラとカーネルタイマーが止まっていないか調べる
+/// (スピンロックのデッドロックなどでティック処理が進まないと検出される)
+pub fn check(stack_frame: &InterruptStackFrame) {
+    let threshold = THRESHOLD_MS.load(Ordering::Relaxed);
+    if threshold == 0 {
+        return;
+    }
+    let now = crate::drivers::timer::get_uptime_ms();
+    let cpu = cpu_id();
+
+    let last = LAST_PROGRESS_MS[cpu].load(Ordering::Relaxed);
+    if last != 0 {
+        let stuck = now.saturating_sub(last);
+        if stuck >= threshold && now.saturating_sub(LAST_REPORT_MS[cpu].load(Ordering::Relaxed)) >= threshold {
+            LAST_REPORT_MS[cpu].store(now, Ordering::Relaxed);
+            crate::error!("Soft lockup: CPU {} stuck for {}s", cpu, stuck / 1000);
+            report(stack_frame);
+        }
+    }
+
+    // 心拍は HEARTBEAT_MS ごとなので、その分を見込んでおく
+    let last = LAST_HEARTBEAT_MS.load(Ordering::Relaxed);
+    if last != 0 {
+        let stuck = now.saturating_sub(last);
+        if stuck >= threshold + HEARTBEAT_MS
+            && now.saturating_sub(LAST_STALL_REPORT_MS.load(Ordering::Relaxed)) >= threshold {
+            LAST_STALL_REPORT_MS.store(now, Ordering::Relaxed);
+            crate::error!("Kernel timers stalled for {}s (CPU {})", stuck / 1000, cpu);
+            report(stack_frame);
+        }
+    }
+}
+
+/// 割り込まれた場所を表示し、指定されていればリセットする
+fn report(stack_frame: &InterruptStackFrame) {
+    if let Some(pid) = crate::process::try_current_pid() {
+        crate::println!("Current PID: {}", pid);
+    }
+    crate::println!("RIP: {}  RSP: {:#018x}  RFLAGS: {:#x}",
+        crate::symbols::Sym(stack_frame.instruction_pointer.as_u64()),
+        stack_frame.stack_pointer.as_u64(),
+        stack_frame.cpu_flags);
+    crate::backtrace::print_backtrace();
+
+    if RESET_ON_LOCKUP.load(Ordering::SeqCst) {
+        crate::error!("Resetting after lockup");
+        crate::power::reboot();
+    }
+}
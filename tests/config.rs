@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn config_switches_parse_and_features_round_trip() {
+    use rust_os_kernel::config::{self, Feature, FEATURES};
+
+    assert_eq!(config::parse_switch("off"), Some(false));
+    assert_eq!(config::parse_switch("on"), Some(true));
+    assert_eq!(config::parse_switch("maybe"), None);
+    for feature in FEATURES {
+        assert_eq!(Feature::from_name(feature.name()), Some(feature));
+        // テストカーネルはコマンドラインを解析しないので、ビルド時の既定値になる
+        assert_eq!(config::enabled(feature), feature.compiled_default());
+    }
+}
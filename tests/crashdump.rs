@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use core::panic::PanicInfo;
+use rust_os_kernel::drivers::block::{self, SECTOR_SIZE};
+use rust_os_kernel::drivers::ramdisk;
+use rust_os_kernel::filesystem;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    // ラムディスクは /dev にノードを作る
+    filesystem::init();
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn crash_dump_renders_verifies_and_round_trips_through_a_block_device() {
+    use rust_os_kernel::crashdump::{self, IMAGE_HEADER_SIZE};
+
+    let mut image = vec![0u8; 64 * 1024];
+    let len = crashdump::render(&mut image[IMAGE_HEADER_SIZE..], &"test crash\nsecond line", None, false);
+    let text = image[IMAGE_HEADER_SIZE..IMAGE_HEADER_SIZE + len].to_vec();
+    assert!(crashdump::verify(&text).is_ok());
+    let dump = core::str::from_utf8(&text).unwrap();
+    assert!(dump.starts_with(crashdump::BEGIN_MARKER));
+    assert!(dump.trim_end().ends_with(crashdump::END_MARKER));
+    // 改行はエスケープされ、1レコード1行のまま
+    assert!(dump.lines().any(|line| line == "message test crash\\nsecond line"));
+    assert!(dump.lines().any(|line| line.starts_with("reg rip 0x")));
+
+    let mut corrupted = text.clone();
+    corrupted[crashdump::BEGIN_MARKER.len() + 1] ^= 0x20;
+    assert_eq!(crashdump::verify(&corrupted), Err("Crash dump checksum mismatch"));
+
+    let device = block::get(ramdisk::create(128 * 1024).unwrap()).unwrap();
+    assert_eq!(crashdump::load(device.as_ref()), Err("No crash dump"));
+    assert!(crashdump::store(device.as_ref(), &mut image, len).is_ok());
+    assert_eq!(crashdump::load(device.as_ref()).unwrap(), text);
+
+    // デバイスに入り切らなければ書かない
+    let small = block::get(ramdisk::create(2 * SECTOR_SIZE).unwrap()).unwrap();
+    assert_eq!(crashdump::store(small.as_ref(), &mut image, len), Err("Crash dump does not fit on the device"));
+}
+
+#[test_case]
+fn crash_dump_device_must_be_empty_or_hold_a_dump() {
+    use rust_os_kernel::crashdump::{self, IMAGE_HEADER_SIZE};
+    use rust_os_kernel::drivers::partition::{GPT_SIGNATURE, MBR_SIGNATURE};
+
+    let device = block::get(ramdisk::create(128 * 1024).unwrap()).unwrap();
+    assert_eq!(crashdump::check_device(device.as_ref()), Ok(()));
+    let mut image = vec![0u8; 64 * 1024];
+    let len = crashdump::render(&mut image[IMAGE_HEADER_SIZE..], &"test crash", None, false);
+    crashdump::store(device.as_ref(), &mut image, len).unwrap();
+    // 前回のダンプは上書きしてよい
+    assert_eq!(crashdump::check_device(device.as_ref()), Ok(()));
+
+    let mut sector = [0u8; SECTOR_SIZE];
+    sector[510..].copy_from_slice(&MBR_SIGNATURE);
+    let mbr = block::get(ramdisk::create(128 * 1024).unwrap()).unwrap();
+    mbr.write_sectors(0, &sector).unwrap();
+    assert_eq!(crashdump::check_device(mbr.as_ref()), Err("Crashdump device has a partition table"));
+
+    let mut sector = [0u8; SECTOR_SIZE];
+    sector[..8].copy_from_slice(GPT_SIGNATURE);
+    let gpt = block::get(ramdisk::create(128 * 1024).unwrap()).unwrap();
+    gpt.write_sectors(1, &sector).unwrap();
+    assert_eq!(crashdump::check_device(gpt.as_ref()), Err("Crashdump device has a partition table"));
+
+    // ext2 のスーパーブロックはセクタ 2 から始まる
+    let mut sector = [0u8; SECTOR_SIZE];
+    sector[56..58].copy_from_slice(&0xEF53u16.to_le_bytes());
+    let ext2 = block::get(ramdisk::create(128 * 1024).unwrap()).unwrap();
+    ext2.write_sectors(2, &sector).unwrap();
+    assert_eq!(crashdump::check_device(ext2.as_ref()), Err("Crashdump device is not empty"));
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use rust_os_kernel::errno::Errno;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn flat_binary_headers_are_validated() {
+    use rust_os_kernel::flat::{self, FLAT_MAGIC, HEADER_SIZE};
+
+    let mut image = Vec::new();
+    image.extend_from_slice(FLAT_MAGIC);
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    image.extend_from_slice(&4096u64.to_le_bytes());
+    image.extend_from_slice(&[0x90, 0xcc]);
+    let parsed = flat::parse(&image).unwrap();
+    assert_eq!((parsed.entry, parsed.bss_size), (HEADER_SIZE as u64, 4096));
+
+    // エントリがファイルの外を指している
+    image[8] = 0xff;
+    assert_eq!(flat::parse(&image).map(|_| ()), Err(Errno::ENOEXEC));
+    assert_eq!(flat::parse(b"\x7fELF").map(|_| ()), Err(Errno::ENOEXEC));
+}
+
+#[test_case]
+fn initial_stack_holds_arguments_and_environment() {
+    use rust_os_kernel::abi::{AT_ENTRY, AT_NULL, AT_PAGESZ};
+    use rust_os_kernel::elf;
+
+    let top = 0x7000_0000u64;
+    let (rsp, contents) = elf::initial_stack(top, &["prog", "-v"], &["HOME=/"], 0x1234).unwrap();
+    assert_eq!(rsp % 16, 0);
+    assert_eq!(rsp + contents.len() as u64, top);
+
+    let word = |i: usize| u64::from_le_bytes(contents[i * 8..i * 8 + 8].try_into().unwrap());
+    let string = |ptr: u64| {
+        let start = (ptr - rsp) as usize;
+        let len = contents[start..].iter().position(|&b| b == 0).unwrap();
+        core::str::from_utf8(&contents[start..start + len]).unwrap()
+    };
+    // argc, argv[0..2], NULL, envp[0], NULL, auxv
+    assert_eq!(word(0), 2);
+    assert_eq!((string(word(1)), string(word(2)), word(3)), ("prog", "-v", 0));
+    assert_eq!((string(word(4)), word(5)), ("HOME=/", 0));
+    assert_eq!([word(6), word(7), word(8), word(9), word(10)], [AT_PAGESZ, 4096, AT_ENTRY, 0x1234, AT_NULL]);
+
+    let huge = vec![""; 1024];
+    assert_eq!(elf::initial_stack(top, &huge, &[], 0).map(|_| ()), Err(Errno::E2BIG));
+}
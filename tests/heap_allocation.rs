@@ -0,0 +1,175 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn many_boxes() {
+    // 解放した領域が再利用されなければヒープを使い切る
+    for i in 0..rust_os_kernel::memory::HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..rust_os_kernel::memory::HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn tracking_reports_live_allocations() {
+    use rust_os_kernel::memory;
+
+    memory::set_heap_tracking(true);
+    let before = memory::heap_report();
+    let leaked = Box::new([0u8; 4000]);
+    let during = memory::heap_report();
+    assert!(during.stats.live_bytes >= before.stats.live_bytes + 4000);
+    assert!(during.sites.iter().any(|site| site.bytes >= 4000));
+    drop(leaked);
+    memory::set_heap_tracking(false);
+
+    let after = memory::heap_report();
+    assert!(after.sites.iter().all(|site| site.bytes < 4000));
+}
+
+#[test_case]
+fn poisoned_allocations_keep_their_requested_size() {
+    use rust_os_kernel::{allocator, memory};
+
+    let before = memory::heap_stats().live_bytes;
+    let mut values: Vec<u8> = Vec::with_capacity(24);
+    assert_eq!(memory::heap_stats().live_bytes, before + 24);
+    if allocator::POISONING {
+        // 割り当てたばかりの領域は初期化し忘れが分かる値で埋まっている
+        let fresh = unsafe { core::slice::from_raw_parts(values.as_ptr(), 24) };
+        assert!(fresh.iter().all(|&b| b == allocator::POISON_INUSE));
+    }
+    values.extend_from_slice(&[1; 24]);
+    drop(values);
+    assert_eq!(memory::heap_stats().live_bytes, before);
+    // 同じ大きさのブロックを再利用しても毒の検査を通る
+    let reused = Box::new([2u8; 24]);
+    assert_eq!(reused[23], 2);
+}
+
+#[test_case]
+fn injected_heap_fault_fails_one_allocation() {
+    use rust_os_kernel::fault::{self, FaultPoint};
+
+    let mut values: Vec<u8> = Vec::new();
+    fault::configure(FaultPoint::Heap, 1, 1);
+    assert!(values.try_reserve(64).is_err());
+    assert!(values.try_reserve(64).is_ok());
+    fault::disable(FaultPoint::Heap);
+}
+
+#[test_case]
+fn kernel_image_is_protected_by_section() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use rust_os_kernel::memory::{self, KernelSection};
+    use x86_64::VirtAddr;
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    static MESSAGE: &str = "read-only";
+
+    memory::protect_kernel().unwrap();
+    let section = |addr: u64| memory::kernel_section(VirtAddr::new(addr));
+    assert_eq!(section(rust_os_kernel::hlt_loop as usize as u64), Some(KernelSection::Text));
+    assert_eq!(section(MESSAGE.as_ptr() as u64), Some(KernelSection::Rodata));
+    assert_eq!(section(&COUNTER as *const _ as u64), Some(KernelSection::Data));
+    assert_eq!(section(Box::into_raw(Box::new(0u8)) as u64), None);
+
+    // データ領域は保護後も書ける
+    COUNTER.fetch_add(1, Ordering::Relaxed);
+    assert_eq!(COUNTER.load(Ordering::Relaxed), 1);
+}
+
+#[test_case]
+fn vdso_page_is_mapped_and_updated() {
+    use rust_os_kernel::{memory, vdso};
+    use x86_64::VirtAddr;
+
+    vdso::init().unwrap();
+    assert!(vdso::is_enabled());
+    assert!(memory::try_virt_to_phys(VirtAddr::new(memory::VVAR_ADDR)).is_some());
+
+    let before = vdso::snapshot().unwrap();
+    assert_eq!(before.seq % 2, 0);
+    vdso::update();
+    let after = vdso::snapshot().unwrap();
+    assert_eq!(after.seq, before.seq + 2);
+    assert_eq!(after.tick_hz as usize, rust_os_kernel::drivers::timer::tick_rate());
+}
+
+#[test_case]
+fn forked_address_space_copies_on_write() {
+    use rust_os_kernel::memory;
+    use x86_64::structures::paging::PageTableFlags as Flags;
+    use x86_64::VirtAddr;
+
+    // カーネルのヒープともほかのテストとも重ならない L4 エントリを使う
+    let addr = VirtAddr::new(0x0000_6000_0000_0000);
+    let value = addr.as_mut_ptr::<u64>();
+    memory::map_demand_page(addr, Flags::PRESENT | Flags::WRITABLE).unwrap();
+    unsafe { value.write_volatile(1) };
+
+    let parent = memory::current_address_space();
+    let child = memory::fork_address_space(&[(addr, addr + 4096u64)]).unwrap();
+    assert_ne!(child, parent);
+
+    // 子で書き込むとフレームがコピーされ、親のページは変わらない
+    memory::switch_address_space(child);
+    assert_eq!(unsafe { value.read_volatile() }, 1);
+    unsafe { value.write_volatile(2) };
+    assert_eq!(unsafe { value.read_volatile() }, 2);
+    memory::deallocate_pages(addr, 1);
+
+    memory::switch_address_space(parent);
+    assert_eq!(unsafe { value.read_volatile() }, 1);
+    memory::free_address_space(child);
+    memory::deallocate_pages(addr, 1);
+}
@@ -0,0 +1,251 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_os_kernel::drivers::timer;
+use rust_os_kernel::errno::Errno;
+use rust_os_kernel::filesystem::{self, O_RDONLY, O_RDWR};
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    // init_idt で割り込みは有効になっている
+    timer::init();
+    // 仮想端末のノードは /dev に作られる
+    filesystem::init();
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn breakpoint_returns() {
+    // ハンドラから戻ってこられれば成功
+    x86_64::instructions::interrupts::int3();
+}
+
+#[test_case]
+fn timer_ticks_advance() {
+    let start = timer::get_ticks();
+    while timer::get_ticks() == start {
+        x86_64::instructions::hlt();
+    }
+    assert!(timer::get_ticks() > start);
+}
+
+static FIRED: AtomicUsize = AtomicUsize::new(0);
+
+fn record_timer(value: usize) {
+    FIRED.fetch_add(value, Ordering::SeqCst);
+}
+
+#[test_case]
+fn timer_wheel_runs_and_cancels() {
+    let kept = timer::schedule(20, record_timer, 1);
+    let cancelled = timer::schedule(20, record_timer, 100);
+    assert!(cancelled.cancel());
+    while kept.pending() {
+        x86_64::instructions::hlt();
+    }
+    assert_eq!(FIRED.load(Ordering::SeqCst), 1);
+    assert!(!kept.cancel());
+}
+
+fn noop_handler() {}
+
+#[test_case]
+fn shared_irq_registration() {
+    // 使われていない IRQ 線に複数のハンドラを登録できる
+    for _ in 0..4 {
+        rust_os_kernel::interrupts::register_irq(10, noop_handler).unwrap();
+    }
+    assert!(rust_os_kernel::interrupts::register_irq(10, noop_handler).is_err());
+}
+
+#[test_case]
+fn fpu_state_round_trips() {
+    use rust_os_kernel::fpu::{self, FpuState, DEFAULT_MXCSR};
+
+    fpu::init();
+    let initial = FpuState::new();
+    assert_eq!(initial.mxcsr(), DEFAULT_MXCSR);
+    initial.restore();
+    let mut saved = FpuState::new();
+    saved.save();
+    assert_eq!(saved.mxcsr(), DEFAULT_MXCSR);
+}
+
+static KEYS_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+fn count_key(event: &rust_os_kernel::events::Event) {
+    if let rust_os_kernel::events::Event::Key(_) = event {
+        KEYS_SEEN.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn event_bus_delivers_matching_events() {
+    use rust_os_kernel::events::{self, Event, EVENT_KEY};
+
+    assert_eq!(events::subscribe("test", EVENT_KEY, count_key), Ok(()));
+    assert!(events::subscribe("test", EVENT_KEY, count_key).is_err());
+    events::publish(Event::Key('a'));
+    events::publish(Event::NetLink { up: true });
+    events::dispatch();
+    assert_eq!(KEYS_SEEN.load(Ordering::SeqCst), 1);
+    assert!(events::unsubscribe("test"));
+    events::publish(Event::Key('b'));
+    events::dispatch();
+    assert_eq!(KEYS_SEEN.load(Ordering::SeqCst), 1);
+}
+
+#[test_case]
+fn cpu_features_are_detected_once() {
+    use rust_os_kernel::cpu::{self, Feature};
+
+    let info = cpu::info();
+    assert!(core::ptr::eq(info, cpu::info()));
+    assert!(!info.vendor().is_empty());
+    assert!(info.family != 0);
+    // x86_64 の CPU はすべて NX と APIC を持つ
+    assert!(cpu::has(Feature::Apic));
+    assert!(cpu::has(Feature::Nx));
+    if cpu::has(Feature::Avx) {
+        assert!(cpu::has(Feature::Xsave));
+    }
+}
+
+#[test_case]
+fn tlb_shootdown_without_other_cpus_sends_nothing() {
+    use rust_os_kernel::memory;
+    use x86_64::VirtAddr;
+
+    let sent = memory::tlb_shootdowns();
+    assert_eq!(memory::tlb_shootdown(VirtAddr::new(0x4000_0000), 4), 0);
+    // 自分宛ての要求がなければ何もしない
+    memory::handle_tlb_shootdown();
+    assert_eq!(memory::tlb_shootdowns(), sent);
+}
+
+#[test_case]
+fn boot_stages_are_recorded_in_order() {
+    use rust_os_kernel::bootstage::{self, Status};
+
+    bootstage::begin("test-a").done();
+    let long = "a stage result that is much longer than the space kept for each record";
+    bootstage::begin("test-b").skip(long);
+    let records = bootstage::records();
+    let a = records.iter().position(|record| record.name == "test-a").unwrap();
+    let b = &records[a + 1];
+    assert_eq!(b.name, "test-b");
+    assert_eq!(b.status, Status::Skipped);
+    // 説明は固定長に切り詰められる
+    assert!(long.starts_with(b.detail()) && b.detail().len() < long.len());
+}
+
+#[test_case]
+fn scheduler_policies_share_cpu_differently() {
+    use rust_os_kernel::sched::{self, Policy, SimTask};
+
+    let tasks = [
+        SimTask { priority: 5, run: 20, sleep: 0 },
+        SimTask { priority: 10, run: 20, sleep: 0 },
+        SimTask { priority: 15, run: 20, sleep: 0 },
+    ];
+    // ラウンドロビンは優先度によらず均等に分ける
+    let rr = sched::simulate(Policy::RoundRobin, &tasks, 600).cpu_ticks;
+    assert!(rr.iter().all(|&ticks| ticks.abs_diff(rr[0]) <= sched::TIME_SLICE_TICKS));
+    // fair は優先度の高い (数値の小さい) ものほど多く走らせる
+    let fair = sched::simulate(Policy::Fair, &tasks, 600);
+    assert!(fair.cpu_ticks[0] > fair.cpu_ticks[1] && fair.cpu_ticks[1] > fair.cpu_ticks[2]);
+    assert_eq!(fair.stats.ticks, 600);
+    assert!(fair.stats.dispatches > fair.stats.preemptions);
+}
+
+#[test_case]
+fn steal_respects_cpu_affinity() {
+    use rust_os_kernel::sched::{parse_cpu_list, CpuMask, RoundRobin, Scheduler};
+
+    let mask = parse_cpu_list("0,2-3").unwrap();
+    assert_eq!(mask.bits(), 0b1101);
+    assert!(mask.contains(2) && !mask.contains(1));
+    assert_eq!(mask.without(CpuMask::single(0)), CpuMask::from_bits(0b1100));
+    assert!(parse_cpu_list("3-1").is_none());
+    assert!(parse_cpu_list("64").is_none());
+
+    // CPU 1 で実行してよいのは TID 10 だけ
+    let mut scheduler = RoundRobin::new();
+    scheduler.enqueue(0, 10, 10);
+    scheduler.enqueue(0, 11, 10);
+    assert_eq!(scheduler.steal_from(0, &|tid| tid == 10), Some(10));
+    assert_eq!(scheduler.steal_from(0, &|tid| tid == 10), None);
+    assert_eq!(scheduler.queued(0), 1);
+}
+
+#[test_case]
+fn io_permissions_track_allowed_ports() {
+    use rust_os_kernel::ioport::{is_port_instruction, IoPermissions, IO_PORTS};
+
+    let mut io = IoPermissions::new();
+    assert!(!io.allows(0x3F8));
+    io.set(0x3F8, 8, true).unwrap();
+    assert!(io.allows(0x3F8) && io.allows(0x3FF));
+    assert!(!io.allows(0x3F7) && !io.allows(0x400));
+    io.set(0x3FA, 1, false).unwrap();
+    assert!(!io.allows(0x3FA) && io.allows(0x3FB));
+    assert!(io.set(IO_PORTS - 1, 2, true).is_err());
+
+    // レベル 3 ならすべて許す
+    io.set_level(3).unwrap();
+    assert!(io.allows(0x60) && io.allows(0xFFFF));
+    assert!(io.set_level(4).is_err());
+
+    assert!(is_port_instruction(&[0xEC]));             // in al, dx
+    assert!(is_port_instruction(&[0x66, 0xEF]));       // out dx, ax
+    assert!(is_port_instruction(&[0xF3, 0x6E]));       // rep outsb
+    assert!(!is_port_instruction(&[0xFA]));            // cli
+    assert!(!is_port_instruction(&[0x0F, 0x01, 0xF8])); // swapgs
+}
+
+#[test_case]
+fn keyboard_input_goes_to_the_active_vt() {
+    use rust_os_kernel::drivers::vt;
+
+    assert!(vt::switch(vt::NR_VTS).is_err());
+    vt::switch(2).unwrap();
+    vt::push_input(b"ls\n");
+    vt::switch(vt::KERNEL_VT).unwrap();
+    assert!(!vt::has_input(vt::KERNEL_VT));
+
+    let fd = filesystem::open("/dev/tty3", O_RDWR, 0).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(filesystem::read(fd, &mut buf), Ok(3));
+    assert_eq!(&buf[..3], b"ls\n");
+    assert_eq!(filesystem::write(fd, b"hello"), Ok(5));
+    assert_eq!(filesystem::close(fd), Ok(()));
+}
+
+#[test_case]
+fn nonblocking_console_read_without_input_is_eagain() {
+    use rust_os_kernel::drivers::vt;
+    use rust_os_kernel::fd::{FileObject, OpenFile};
+    use rust_os_kernel::filesystem::O_NONBLOCK;
+
+    let stdin = OpenFile::new(FileObject::ConsoleIn, O_RDONLY | O_NONBLOCK);
+    let mut buf = [0u8; 4];
+    assert_eq!(stdin.read(&mut buf), Err(Errno::EAGAIN));
+
+    vt::switch(vt::CONSOLE_VT).unwrap();
+    vt::push_input(b"y");
+    vt::switch(vt::KERNEL_VT).unwrap();
+    assert_eq!(stdin.read(&mut buf), Ok(1));
+    assert_eq!(buf[0], b'y');
+}
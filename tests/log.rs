@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn symbol_map_resolves_function_and_offset() {
+    use rust_os_kernel::memory::{self, KernelSection};
+    use rust_os_kernel::symbols;
+
+    // リンク時のアドレスが実行時と 0x1000 ずれているマップ
+    let (start, end) = memory::section_range(KernelSection::Text);
+    let function = rust_os_kernel::hlt_loop as usize as u64;
+    let map = alloc::format!(
+        "{:016x} T __text_start\n{:016x} t <impl Foo for Bar>::hlt_loop\n{:016x} T __text_end\n",
+        start - 0x1000, function - 0x1000, end - 0x1000,
+    );
+    assert_eq!(symbols::load(&map), Ok(1));
+
+    let symbol = symbols::resolve(function + 3).unwrap();
+    assert_eq!(symbol.name, "<impl Foo for Bar>::hlt_loop");
+    assert_eq!(symbol.offset, 3);
+    assert_eq!(symbols::lookup("<impl Foo for Bar>::hlt_loop"), Some(function));
+    assert!(symbols::resolve(end).is_none());
+}
+
+#[test_case]
+fn ratelimit_allows_a_burst_then_counts_suppressed() {
+    use rust_os_kernel::log::RateLimit;
+
+    let limit = RateLimit::new(usize::MAX, 2);
+    assert_eq!(limit.check(), Some(0));
+    assert_eq!(limit.check(), Some(0));
+    assert_eq!(limit.check(), None);
+    assert_eq!(limit.check(), None);
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use rust_os_kernel::errno::Errno;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn rlimit_hard_limit_only_lowers() {
+    use rust_os_kernel::rlimit::{Limits, RLimit, RLIMIT_FSIZE, RLIMIT_NOFILE, RLIM_INFINITY};
+
+    let mut limits = Limits::new();
+    assert_eq!(limits.cur(RLIMIT_NOFILE), rust_os_kernel::fd::MAX_FDS as u64);
+    assert_eq!(limits.cur(RLIMIT_FSIZE), RLIM_INFINITY);
+
+    assert_eq!(limits.set(RLIMIT_FSIZE, RLimit::new(4096, 8192)), Ok(()));
+    assert_eq!(limits.set(RLIMIT_FSIZE, RLimit::new(8192, 4096)), Err(Errno::EINVAL));
+    assert_eq!(limits.set(RLIMIT_FSIZE, RLimit::new(4096, RLIM_INFINITY)), Err(Errno::EPERM));
+    assert_eq!(limits.set(RLIMIT_FSIZE, RLimit::new(8192, 8192)), Ok(()));
+    assert_eq!(limits.get(RLIMIT_FSIZE), Ok(RLimit::new(8192, 8192)));
+    assert_eq!(limits.get(64), Err(Errno::EINVAL));
+}
+
+#[test_case]
+fn credentials_check_owner_group_other_bits() {
+    use rust_os_kernel::cred::{Credentials, MAY_EXEC, MAY_READ, MAY_WRITE};
+
+    let user = Credentials::new(1000, 100);
+    assert!(user.may_access(1000, 0, 0o600, MAY_READ | MAY_WRITE));
+    assert!(!user.may_access(0, 100, 0o604, MAY_READ));
+    assert!(user.may_access(0, 100, 0o640, MAY_READ));
+    assert!(user.may_access(0, 0, 0o604, MAY_READ));
+    assert!(!user.may_access(0, 0, 0o604, MAY_WRITE));
+
+    // root は所有者に関係なく読み書きできるが、誰にもないビットは許さない
+    assert!(Credentials::ROOT.may_access(1000, 100, 0o600, MAY_READ | MAY_WRITE));
+    assert!(!Credentials::ROOT.may_access(0, 0, 0o444, MAY_WRITE));
+    assert!(!Credentials::ROOT.may_access(0, 0, 0o666, MAY_EXEC));
+
+    let mut user = user;
+    assert_eq!(user.setuid(0), Err(Errno::EPERM));
+    let mut root = Credentials::ROOT;
+    assert_eq!(root.setuid(1000), Ok(()));
+    assert_eq!((root.uid, root.euid), (1000, 1000));
+    assert_eq!(root.setuid(0), Err(Errno::EPERM));
+}
+
+#[test_case]
+fn process_starts_with_main_thread() {
+    use rust_os_kernel::process::{Process, ProcessState};
+
+    let mut process = Process::new(0x1000);
+    assert_eq!(process.threads.len(), 1);
+    assert_eq!(process.main_thread().tid, process.pid);
+    assert_eq!(process.main_thread().context.rip, 0x1000);
+    assert_eq!(process.state(), ProcessState::Ready);
+    assert_eq!(process.info().threads, 1);
+
+    process.main_thread_mut().state = ProcessState::Terminated;
+    assert_eq!(process.state(), ProcessState::Terminated);
+    assert_eq!(process.info().threads, 0);
+}
+
+#[test_case]
+fn fs_base_must_be_a_user_address() {
+    use rust_os_kernel::process::{self, CLONE_FILES, CLONE_SETTLS, CLONE_VM};
+    use rust_os_kernel::uaccess::USER_SPACE_END;
+
+    assert_eq!(process::set_fs_base(USER_SPACE_END), Err(Errno::EPERM));
+    let flags = CLONE_VM | CLONE_FILES | CLONE_SETTLS;
+    assert_eq!(process::clone(flags, None, USER_SPACE_END), Err(Errno::EPERM));
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use core::panic::PanicInfo;
+use rust_os_kernel::errno::Errno;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn rwlock_readers_share_and_rcu_replaces() {
+    use rust_os_kernel::sync::{Rcu, RwLock};
+
+    let lock = RwLock::new(1);
+    let first = lock.read();
+    let second = lock.try_read().expect("readers should not exclude each other");
+    assert_eq!(*first + *second, 2);
+    assert!(lock.try_write().is_none());
+    drop((first, second));
+    *lock.try_write().expect("no readers left") = 5;
+    assert_eq!(*lock.read(), 5);
+
+    let rcu = Rcu::new(vec![1, 2]);
+    let old = rcu.read().clone();
+    assert_eq!(rcu.update(|values| Ok::<_, Errno>(values.iter().map(|v| v * 10).collect())), Ok(()));
+    assert_eq!(rcu.update(|_| Err(Errno::EBUSY)), Err(Errno::EBUSY));
+    assert_eq!(old, vec![1, 2]);
+    assert_eq!(*rcu.read(), vec![10, 20]);
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use rust_os_kernel::errno::Errno;
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn registered_syscall_is_dispatched() {
+    use rust_os_kernel::syscall::{self, SyscallHandler};
+
+    const SYS_TEST_ADD: u64 = 900;
+    let handler = SyscallHandler::new("test_add", |args| Ok((args[0] + args[1]) as i64));
+    assert_eq!(syscall::register(SYS_TEST_ADD, handler), Ok(()));
+    assert!(syscall::register(SYS_TEST_ADD, handler).is_err());
+    assert!(syscall::register(syscall::MAX_SYSCALLS as u64, handler).is_err());
+    assert_eq!(syscall::syscall_handler(SYS_TEST_ADD, 2, 3, 0, 0, 0, 0), 5);
+
+    assert!(syscall::unregister(SYS_TEST_ADD).is_some());
+    assert_eq!(syscall::syscall_handler(SYS_TEST_ADD, 2, 3, 0, 0, 0, 0), Errno::ENOSYS.to_neg());
+}
+
+#[test_case]
+fn getpid_without_a_process_is_zero() {
+    use rust_os_kernel::syscall::{self, SYS_GETPID, SYS_GETPPID, SYS_GETTID};
+
+    // テストカーネルにはプロセスがないので、カーネルの文脈として 0 が返る
+    for number in [SYS_GETPID, SYS_GETPPID, SYS_GETTID] {
+        assert_eq!(syscall::syscall_handler(number, 0, 0, 0, 0, 0, 0), 0);
+    }
+}
+
+#[test_case]
+fn abi_tables_match_the_definitions() {
+    use rust_os_kernel::abi::{self, syscalls};
+    use rust_os_kernel::filesystem::Stat;
+
+    // 番号が重なっていればヘッダを生成しても使えない
+    for (i, (name, number)) in syscalls::SYSCALLS.iter().enumerate() {
+        assert!(syscalls::SYSCALLS[i + 1..].iter().all(|(_, other)| other != number), "{} reused", name);
+    }
+    assert!(abi::CONSTANTS.contains(&("AT_FDCWD", -100)));
+    let stat = abi::STRUCTS.iter().find(|def| def.c_name == "stat").unwrap();
+    assert_eq!(stat.size, core::mem::size_of::<Stat>());
+    assert_eq!(stat.fields.len(), 9);
+}
+
+#[test_case]
+fn int80_passes_arguments_and_returns_in_rax() {
+    use rust_os_kernel::syscall::{self, SyscallHandler};
+
+    const SYS_TEST_PACK: u64 = 901;
+    let handler = SyscallHandler::new("test_pack", |args| {
+        Ok(args.iter().enumerate().fold(0, |packed, (i, &arg)| packed | arg << (i * 8)) as i64)
+    });
+    syscall::register(SYS_TEST_PACK, handler).unwrap();
+    let result: i64;
+    unsafe {
+        core::arch::asm!(
+            "int 0x80",
+            inlateout("rax") SYS_TEST_PACK as i64 => result,
+            in("rdi") 1u64, in("rsi") 2u64, in("rdx") 3u64,
+            in("r10") 4u64, in("r8") 5u64, in("r9") 6u64,
+        );
+    }
+    assert_eq!(result, 0x0605_0403_0201);
+    assert!(syscall::unregister(SYS_TEST_PACK).is_some());
+}
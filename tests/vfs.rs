@@ -0,0 +1,454 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_os_kernel::drivers::block::{self, BlockDevice, SECTOR_SIZE};
+use rust_os_kernel::drivers::page_cache;
+use rust_os_kernel::errno::Errno;
+use rust_os_kernel::filesystem::{self, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC};
+
+#[no_mangle]
+pub extern "C" fn _start(magic: u32, info: u32) -> ! {
+    rust_os_kernel::init(magic, info);
+    filesystem::init();
+    test_main();
+    rust_os_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn write_then_read() {
+    let fd = filesystem::open("/tmp_rw.txt", O_CREAT | O_RDWR | O_TRUNC, 0o644).unwrap();
+    assert_eq!(filesystem::write(fd, b"hello"), Ok(5));
+    assert_eq!(filesystem::close(fd), Ok(()));
+
+    let fd = filesystem::open("/tmp_rw.txt", O_RDONLY, 0).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(filesystem::read(fd, &mut buf), Ok(5));
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(filesystem::close(fd), Ok(()));
+}
+
+#[test_case]
+fn open_missing_file_fails() {
+    assert_eq!(filesystem::open("/no/such/file", O_RDONLY, 0), Err(Errno::ENOENT));
+}
+
+#[test_case]
+fn mkdir_and_list() {
+    assert_eq!(filesystem::mkdir("/testdir", 0o755), Ok(()));
+    // 既に存在するディレクトリは作れない
+    assert_eq!(filesystem::mkdir("/testdir", 0o755), Err(Errno::EEXIST));
+    filesystem::create_file("/testdir/a").unwrap();
+
+    let entries = filesystem::list_directory("/testdir").unwrap();
+    assert!(entries.iter().any(|name| name == "a"));
+    // 同じ名前のファイルがあってもディレクトリは作れない
+    assert_eq!(filesystem::mkdir("/testdir/a", 0o755), Err(Errno::EEXIST));
+}
+
+#[test_case]
+fn open_excl_fails_on_existing_file() {
+    let fd = filesystem::open("/excl.txt", O_CREAT | O_EXCL | O_RDWR, 0o644).unwrap();
+    assert_eq!(filesystem::close(fd), Ok(()));
+    assert_eq!(filesystem::open("/excl.txt", O_CREAT | O_EXCL | O_RDWR, 0o644), Err(Errno::EEXIST));
+    // O_CREAT がなければ O_EXCL は無視する
+    let fd = filesystem::open("/excl.txt", O_EXCL | O_RDONLY, 0).unwrap();
+    assert_eq!(filesystem::close(fd), Ok(()));
+}
+
+#[test_case]
+fn unlink_removes_file() {
+    filesystem::create_file("/to_remove").unwrap();
+    assert!(filesystem::stat("/to_remove").is_ok());
+    assert_eq!(filesystem::unlink("/to_remove"), Ok(()));
+    assert!(filesystem::stat("/to_remove").is_err());
+}
+
+#[test_case]
+fn rename_moves_file() {
+    filesystem::install_file("/old_name", b"data", 0o644).unwrap();
+    assert_eq!(filesystem::rename("/old_name", "/new_name"), Ok(()));
+    assert!(filesystem::stat("/old_name").is_err());
+    assert_eq!(filesystem::stat("/new_name").unwrap().st_size, 4);
+}
+
+#[test_case]
+fn getdents_continues_from_offset() {
+    filesystem::mkdir("/dents", 0o755).unwrap();
+    filesystem::create_file("/dents/a").unwrap();
+    filesystem::mkdir("/dents/b", 0o755).unwrap();
+
+    let fd = filesystem::open("/dents", O_RDONLY, 0).unwrap();
+    // 1件分しか入らないバッファで2回に分けて読む
+    let mut buf = [0u8; 24];
+    assert_eq!(filesystem::getdents(fd, &mut buf), Ok(24));
+    assert_eq!(buf[18], filesystem::DT_REG);
+    assert_eq!(buf[19], b'a');
+    assert_eq!(filesystem::getdents(fd, &mut buf), Ok(24));
+    assert_eq!(buf[18], filesystem::DT_DIR);
+    assert_eq!(buf[19], b'b');
+    assert_eq!(filesystem::getdents(fd, &mut buf), Ok(0));
+    filesystem::close(fd).unwrap();
+}
+
+#[test_case]
+fn proc_files_are_generated_on_open() {
+    let fd = filesystem::open("/proc/uptime", O_RDONLY, 0).unwrap();
+    let mut buf = [0u8; 32];
+    let len = filesystem::read(fd, &mut buf).unwrap();
+    assert!(len > 0 && buf[len - 1] == b'\n');
+    assert_eq!(filesystem::close(fd), Ok(()));
+
+    // 読み取り専用
+    assert_eq!(filesystem::open("/proc/uptime", O_RDWR | O_TRUNC, 0), Err(Errno::EACCES));
+    assert!(filesystem::list_directory("/proc").unwrap().iter().any(|name| name == "uptime"));
+}
+
+#[test_case]
+fn urandom_returns_fresh_bytes() {
+    let fd = filesystem::open("/dev/urandom", O_RDONLY, 0).unwrap();
+    let mut first = [0u8; 32];
+    let mut second = [0u8; 32];
+    assert_eq!(filesystem::read(fd, &mut first), Ok(32));
+    assert_eq!(filesystem::read(fd, &mut second), Ok(32));
+    assert_ne!(first, second);
+    assert_eq!(filesystem::close(fd), Ok(()));
+}
+
+#[test_case]
+fn shm_names_and_sizes() {
+    use rust_os_kernel::shm;
+    use filesystem::O_EXCL;
+
+    let handle = shm::open("/test_shm", O_CREAT | O_EXCL | O_RDWR, 0o600).unwrap();
+    assert_eq!(shm::open("/test_shm", O_CREAT | O_EXCL | O_RDWR, 0o600).err(), Some(Errno::EEXIST));
+    assert_eq!(handle.truncate(8192), Ok(()));
+
+    // 同じ名前で開くと同じオブジェクトが見える
+    let other = shm::open("/test_shm", O_RDONLY, 0).unwrap();
+    assert_eq!(other.stat().st_size, 8192);
+    assert_eq!(other.truncate(0), Err(Errno::EINVAL));
+
+    assert_eq!(shm::unlink("/test_shm"), Ok(()));
+    assert_eq!(shm::open("/test_shm", O_RDWR, 0).err(), Some(Errno::ENOENT));
+    assert_eq!(shm::open("bad/name", O_CREAT | O_RDWR, 0).err(), Some(Errno::EINVAL));
+    // 名前を消しても開いているハンドルは有効なまま
+    assert_eq!(handle.stat().st_size, 8192);
+}
+
+#[test_case]
+fn mqueue_orders_by_priority() {
+    use rust_os_kernel::mqueue::{self, MqAttr};
+    use filesystem::O_NONBLOCK;
+
+    let attr = MqAttr { mq_maxmsg: 2, mq_msgsize: 16, ..MqAttr::default() };
+    let queue = mqueue::open("/test_mq", O_CREAT | O_RDWR | O_NONBLOCK, 0o600, Some(attr)).unwrap();
+    assert_eq!(queue.send(b"low", 1), Ok(()));
+    assert_eq!(queue.send(b"high", 5), Ok(()));
+    // 一杯なら待たずに EAGAIN
+    assert_eq!(queue.send(b"full", 0), Err(Errno::EAGAIN));
+    assert_eq!(queue.send(&[0u8; 17], 0), Err(Errno::EMSGSIZE));
+
+    let mut buf = [0u8; 16];
+    assert_eq!(queue.receive(&mut buf), Ok((4, 5)));
+    assert_eq!(&buf[..4], b"high");
+    assert_eq!(queue.receive(&mut buf), Ok((3, 1)));
+    assert_eq!(queue.receive(&mut buf), Err(Errno::EAGAIN));
+    assert_eq!(mqueue::unlink("/test_mq"), Ok(()));
+}
+
+#[test_case]
+fn poll_reports_pipe_readiness() {
+    use rust_os_kernel::fd::{self, FileObject};
+    use rust_os_kernel::poll::{self, PollFd, POLLIN, POLLOUT};
+
+    let (reader, writer) = rust_os_kernel::pipe::create();
+    let read_fd = fd::install(FileObject::PipeRead(reader), O_RDONLY).unwrap();
+    let write_fd = fd::install(FileObject::PipeWrite(writer), filesystem::O_WRONLY).unwrap();
+
+    let mut fds = [
+        PollFd { fd: read_fd, events: POLLIN, revents: 0 },
+        PollFd { fd: write_fd, events: POLLOUT, revents: 0 },
+    ];
+    assert_eq!(poll::poll(&mut fds, 0), Ok(1));
+    assert_eq!(fds[0].revents, 0);
+    assert_eq!(fds[1].revents, POLLOUT);
+
+    assert_eq!(fd::get(write_fd).unwrap().write(b"x"), Ok(1));
+    assert_eq!(poll::poll(&mut fds, 0), Ok(2));
+    assert_eq!(fds[0].revents, POLLIN);
+
+    assert!(fd::close(read_fd));
+    assert!(fd::close(write_fd));
+}
+
+#[test_case]
+fn nonblocking_pipe_read_returns_eagain() {
+    use rust_os_kernel::fd::{self, FileObject};
+    use filesystem::{O_NONBLOCK, O_WRONLY};
+
+    let (reader, writer) = rust_os_kernel::pipe::create();
+    let read_fd = fd::install(FileObject::PipeRead(reader), O_RDONLY | O_NONBLOCK).unwrap();
+    let write_fd = fd::install(FileObject::PipeWrite(writer), O_WRONLY).unwrap();
+    let mut buf = [0u8; 4];
+    assert_eq!(fd::get(read_fd).unwrap().read(&mut buf), Err(Errno::EAGAIN));
+
+    // O_NONBLOCK を外せば F_GETFL からも消える
+    let file = fd::get(read_fd).unwrap();
+    file.set_flags(0);
+    assert_eq!(file.flags(), O_RDONLY);
+    file.set_flags(O_NONBLOCK);
+
+    assert_eq!(fd::get(write_fd).unwrap().write(b"ok"), Ok(2));
+    assert_eq!(file.read(&mut buf), Ok(2));
+    assert!(fd::close(read_fd));
+    assert!(fd::close(write_fd));
+}
+
+#[test_case]
+fn writes_beyond_size_limit_fail_with_enospc() {
+    let usage = filesystem::usage().unwrap();
+    filesystem::set_size_limit(usage.used_bytes + 100).unwrap();
+
+    let fd = filesystem::open("/quota.txt", O_CREAT | O_RDWR | O_TRUNC, 0o644).unwrap();
+    assert_eq!(filesystem::write(fd, &[0u8; 64]), Ok(64));
+    assert_eq!(filesystem::write(fd, &[0u8; 64]), Err(Errno::ENOSPC));
+    assert_eq!(filesystem::usage().unwrap().used_bytes, usage.used_bytes + 64);
+    assert_eq!(filesystem::close(fd), Ok(()));
+
+    // 消せば空きが戻る
+    assert_eq!(filesystem::unlink("/quota.txt"), Ok(()));
+    assert_eq!(filesystem::usage().unwrap().used_bytes, usage.used_bytes);
+    filesystem::set_size_limit(usage.size_limit).unwrap();
+}
+
+#[test_case]
+fn statfs_reports_the_containing_mount() {
+    let root = filesystem::statfs("/tmp").unwrap();
+    assert_eq!(root.f_type, filesystem::TMPFS_MAGIC);
+    assert!(root.f_blocks >= root.f_bfree && root.f_blocks > 0);
+    assert!(root.f_files > root.f_ffree);
+
+    filesystem::open("/proc/uptime", O_RDONLY, 0).and_then(filesystem::close).unwrap();
+    assert_eq!(filesystem::statfs("/proc/uptime").unwrap().f_type, filesystem::PROC_SUPER_MAGIC);
+    assert_eq!(filesystem::statfs("/no/such/path").map(|s| s.f_type), Err(Errno::ENOENT));
+}
+
+#[test_case]
+fn positional_io_keeps_the_file_offset() {
+    let fd = filesystem::open("/pio.txt", O_CREAT | O_RDWR | O_TRUNC, 0o644).unwrap();
+    assert_eq!(filesystem::write(fd, b"abcdef"), Ok(6));
+    assert_eq!(filesystem::write_at(fd, b"XY", 2), Ok(2));
+    // 末尾より先に書くと間はゼロになる
+    assert_eq!(filesystem::write_at(fd, b"!", 8), Ok(1));
+
+    let mut buf = [0xffu8; 9];
+    assert_eq!(filesystem::read_at(fd, &mut buf, 0), Ok(9));
+    assert_eq!(&buf, b"abXYef\0\0!");
+    // オフセットは最初の write の後のまま
+    assert_eq!(filesystem::write(fd, b"g"), Ok(1));
+    assert_eq!(filesystem::read_at(fd, &mut buf[..1], 6), Ok(1));
+    assert_eq!(buf[0], b'g');
+    assert_eq!(filesystem::close(fd), Ok(()));
+}
+
+#[test_case]
+fn open_directory_resolves_its_path() {
+    filesystem::mkdir("/at_base", 0o755).unwrap();
+    filesystem::mkdir("/at_base/sub", 0o755).unwrap();
+    filesystem::create_file("/at_base/file").unwrap();
+    assert_eq!(filesystem::open("/at_base/file", O_RDONLY | O_DIRECTORY, 0), Err(Errno::ENOTDIR));
+
+    let fd = filesystem::open("/at_base/sub", O_RDONLY | O_DIRECTORY, 0).unwrap();
+    assert_eq!(filesystem::dir_path(fd).as_deref(), Ok("/at_base/sub"));
+    // 開いた後に移動しても今の位置を返す
+    filesystem::rename("/at_base/sub", "/at_base/moved").unwrap();
+    assert_eq!(filesystem::dir_path(fd).as_deref(), Ok("/at_base/moved"));
+    assert_eq!(filesystem::close(fd), Ok(()));
+}
+
+/// 読み書きの回数を数えるメモリ上のディスク
+struct MemDisk {
+    data: spin::Mutex<Vec<u8>>,
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl BlockDevice for MemDisk {
+    fn name(&self) -> &str {
+        "memdisk"
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        let start = lba as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        let start = lba as usize * SECTOR_SIZE;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[test_case]
+fn page_cache_serves_reads_and_writes_back_on_sync() {
+    let disk = Arc::new(MemDisk {
+        data: spin::Mutex::new(vec![0x5a; 16 * SECTOR_SIZE]),
+        reads: AtomicUsize::new(0),
+        writes: AtomicUsize::new(0),
+    });
+    let index = block::register(disk.clone());
+
+    let mut buf = [0u8; SECTOR_SIZE];
+    page_cache::read(index, 1, &mut buf).unwrap();
+    page_cache::read(index, 2, &mut buf).unwrap();
+    // 同じページの2回目はディスクを読まない
+    assert_eq!(disk.reads.load(Ordering::SeqCst), 1);
+    assert_eq!(buf[0], 0x5a);
+
+    page_cache::write(index, 3, &[0xa5; SECTOR_SIZE]).unwrap();
+    assert_eq!(disk.writes.load(Ordering::SeqCst), 0);
+    page_cache::read(index, 3, &mut buf).unwrap();
+    assert_eq!(buf[0], 0xa5);
+
+    assert_eq!(page_cache::sync(), Ok(1));
+    assert_eq!(disk.data.lock()[3 * SECTOR_SIZE], 0xa5);
+    assert_eq!(page_cache::write(index, 16, &buf), Err("Sector out of range"));
+}
+
+#[test_case]
+fn mbr_partitions_become_block_devices() {
+    let mut data = vec![0u8; 64 * SECTOR_SIZE];
+    // 1番: LBA 8 から 16 セクタ、2番: LBA 32 から 32 セクタ
+    for (i, (kind, start, sectors)) in [(0x83u8, 8u32, 16u32), (0x0c, 32, 32)].iter().enumerate() {
+        let entry = &mut data[446 + i * 16..446 + (i + 1) * 16];
+        entry[4] = *kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+    data[510] = 0x55;
+    data[511] = 0xaa;
+    data[32 * SECTOR_SIZE] = 0x42;
+    let disk = Arc::new(MemDisk {
+        data: spin::Mutex::new(data),
+        reads: AtomicUsize::new(0),
+        writes: AtomicUsize::new(0),
+    });
+
+    let index = block::register(disk);
+    assert_eq!(block::count(), index + 3);
+    let second = block::get(index + 2).unwrap();
+    assert_eq!(second.name(), "memdiskp2");
+    assert_eq!(second.sector_count(), 32);
+
+    let mut buf = [0u8; SECTOR_SIZE];
+    second.read_sectors(0, &mut buf).unwrap();
+    assert_eq!(buf[0], 0x42);
+    assert!(second.read_sectors(32, &mut buf).is_err());
+}
+
+#[test_case]
+fn ramdisk_node_reads_and_writes_at_the_file_offset() {
+    use rust_os_kernel::drivers::ramdisk;
+
+    let index = ramdisk::create(4 * SECTOR_SIZE).unwrap();
+    let path = alloc::format!("/dev/{}", block::get(index).unwrap().name());
+    let fd = filesystem::open(&path, O_RDWR, 0).unwrap();
+    assert_eq!(filesystem::write_at(fd, b"across", SECTOR_SIZE - 3), Ok(6));
+    let mut buf = [0u8; 6];
+    assert_eq!(filesystem::read_at(fd, &mut buf, SECTOR_SIZE - 3), Ok(6));
+    assert_eq!(&buf, b"across");
+
+    assert_eq!(filesystem::lseek(fd, (4 * SECTOR_SIZE - 2) as i64, filesystem::SEEK_SET), Ok(4 * SECTOR_SIZE - 2));
+    assert_eq!(filesystem::write(fd, b"end"), Ok(2));
+    assert_eq!(filesystem::write(fd, b"x"), Err(Errno::ENOSPC));
+    assert_eq!(filesystem::close(fd), Ok(()));
+}
+
+#[test_case]
+fn ramfs_snapshot_round_trips_through_a_block_device() {
+    use rust_os_kernel::drivers::ramdisk;
+    use rust_os_kernel::snapshot;
+
+    assert_eq!(filesystem::mkdir("/persisted", 0o750), Ok(()));
+    assert_eq!(filesystem::install_file("/persisted/notes.txt", b"keep me", 0o640), Ok(()));
+    assert_eq!(filesystem::symlink("notes.txt", "/persisted/link"), Ok(()));
+
+    let index = ramdisk::create(256 * 1024).unwrap();
+    let written = snapshot::save_to(index).unwrap();
+    assert!(written > 0);
+
+    assert_eq!(filesystem::install_file("/persisted/notes.txt", b"changed", 0o600), Ok(()));
+    assert_eq!(filesystem::unlink("/persisted/link"), Ok(()));
+
+    let summary = snapshot::load_from(index).unwrap();
+    assert!(summary.restored >= 3);
+    assert_eq!(filesystem::read_file("/persisted/notes.txt").unwrap(), b"keep me");
+    assert_eq!(filesystem::stat("/persisted/notes.txt").unwrap().st_mode & 0o777, 0o640);
+    assert_eq!(filesystem::readlink("/persisted/link").as_deref(), Ok("notes.txt"));
+
+    let mut image = snapshot::serialize().unwrap();
+    let last = image.len() - 1;
+    image[last] ^= 0xff;
+    assert_eq!(snapshot::restore(&image), Err("Snapshot checksum mismatch"));
+}
+
+#[test_case]
+fn injected_vfs_faults_fail_every_nth_operation() {
+    use rust_os_kernel::fault::{self, FaultPoint};
+
+    let before = fault::injected(FaultPoint::Vfs);
+    fault::configure(FaultPoint::Vfs, 2, 1);
+    assert!(filesystem::stat("/").is_ok());
+    assert_eq!(filesystem::stat("/").map(|_| ()), Err(Errno::EIO));
+    // 回数を使い切ったら元に戻る
+    assert!(filesystem::stat("/").is_ok());
+    assert!(filesystem::stat("/").is_ok());
+    fault::disable(FaultPoint::Vfs);
+    assert_eq!(fault::injected(FaultPoint::Vfs), before + 1);
+
+    // close は失敗させない (開いたファイルの枠が漏れるので)
+    let fd = filesystem::open("/fault_close.txt", O_CREAT | O_RDWR, 0o644).unwrap();
+    fault::configure(FaultPoint::Vfs, 1, 1);
+    assert_eq!(filesystem::close(fd), Ok(()));
+    fault::disable(FaultPoint::Vfs);
+}
+
+#[test_case]
+fn non_root_cannot_rename_out_of_a_root_directory() {
+    use rust_os_kernel::cred::{self, Credentials};
+
+    assert_eq!(filesystem::mkdir("/rootonly", 0o755), Ok(()));
+    assert_eq!(filesystem::install_file("/rootonly/file", b"x", 0o644), Ok(()));
+
+    cred::with_credentials(Credentials::new(1000, 100), || {
+        assert_eq!(filesystem::rename("/rootonly/file", "/moved"), Err(Errno::EACCES));
+        assert_eq!(filesystem::link("/rootonly/file", "/rootonly/hard"), Err(Errno::EACCES));
+        assert_eq!(filesystem::symlink("file", "/rootonly/soft"), Err(Errno::EACCES));
+        assert_eq!(filesystem::create_file("/rootonly/new"), Err(Errno::EACCES));
+    });
+    assert!(filesystem::stat("/rootonly/file").is_ok());
+    assert_eq!(filesystem::stat("/moved").map(|_| ()), Err(Errno::ENOENT));
+}
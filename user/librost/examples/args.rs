@@ -0,0 +1,18 @@
+//! 引数、環境変数、補助ベクタが _start から読めるか確かめる
+
+#![no_std]
+#![no_main]
+
+use librost::{env, println};
+
+librost::main!(main);
+
+fn main() -> i32 {
+    println!("argc={}", env::argc());
+    for (i, arg) in env::args().enumerate() {
+        println!("argv[{}]={}", i, arg);
+    }
+    println!("envc={}", env::vars().count());
+    println!("pagesz={}", env::auxv(librost::abi::AT_PAGESZ).unwrap_or(0));
+    0
+}
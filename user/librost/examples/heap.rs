@@ -0,0 +1,39 @@
+//! brk のヒープと mmap の大きい割り当て、終了時の処理を確かめる
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use librost::{println, process};
+
+librost::main!(main);
+
+fn goodbye() {
+    println!("at_exit ok");
+}
+
+fn main() -> Result<(), librost::Errno> {
+    process::at_exit(goodbye);
+
+    let mut small = Vec::new();
+    for i in 0..1000u32 {
+        small.push(i);
+    }
+    println!("sum={}", small.iter().sum::<u32>());
+
+    // MMAP_THRESHOLD を超えるので mmap で取られる
+    let large = alloc::vec![0x5Au8; 64 * 1024];
+    println!("large ok={}", large.iter().all(|&b| b == 0x5A));
+    drop(large);
+
+    let mut text = String::new();
+    for word in ["brk", "heap", "ok"] {
+        text.push_str(word);
+        text.push(' ');
+    }
+    println!("{}", text.trim_end());
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! 起動時にカーネルがスタックに積んだ引数、環境変数、補助ベクタ
+
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+static ENVP: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+static AUXV: AtomicPtr<u64> = AtomicPtr::new(core::ptr::null_mut());
+
+/// 初期スタック (argc, argv..., NULL, envp..., NULL, auxv...) を読む (_start から1回だけ呼ぶ)
+///
+/// # Safety
+/// sp はカーネルが用意した初期スタックの先頭であること
+pub(crate) unsafe fn init(sp: *const u64) {
+    let argc = *sp as usize;
+    let argv = sp.add(1) as *mut *const u8;
+    let envp = argv.add(argc + 1);
+    let mut end = envp;
+    while !(*end).is_null() {
+        end = end.add(1);
+    }
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv, Ordering::Relaxed);
+    ENVP.store(envp, Ordering::Relaxed);
+    AUXV.store(end.add(1) as *mut u64, Ordering::Relaxed);
+}
+
+/// NUL 終端の文字列 (UTF-8 でなければ空文字列にする)
+unsafe fn c_str(ptr: *const u8) -> &'static str {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+}
+
+/// NULL で終わるポインタの配列をたどる
+pub struct Strings {
+    next: *const *const u8,
+}
+
+impl Iterator for Strings {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        if self.next.is_null() {
+            return None;
+        }
+        let ptr = unsafe { *self.next };
+        if ptr.is_null() {
+            return None;
+        }
+        self.next = unsafe { self.next.add(1) };
+        Some(unsafe { c_str(ptr) })
+    }
+}
+
+/// 引数の数 (argv[0] のプログラム名を含む)
+pub fn argc() -> usize {
+    ARGC.load(Ordering::Relaxed)
+}
+
+/// 引数 (最初はプログラム名)
+pub fn args() -> Strings {
+    Strings { next: ARGV.load(Ordering::Relaxed) }
+}
+
+/// "名前=値" の形の環境変数
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    Strings { next: ENVP.load(Ordering::Relaxed) }
+        .map(|entry| entry.split_once('=').unwrap_or((entry, "")))
+}
+
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find(|&(key, _)| key == name).map(|(_, value)| value)
+}
+
+/// 補助ベクタの値 (abi::AT_PAGESZ など)
+pub fn auxv(kind: u64) -> Option<u64> {
+    let mut entry = AUXV.load(Ordering::Relaxed) as *const u64;
+    if entry.is_null() {
+        return None;
+    }
+    loop {
+        let (key, value) = unsafe { (*entry, *entry.add(1)) };
+        if key == crate::abi::AT_NULL {
+            return None;
+        }
+        if key == kind {
+            return Some(value);
+        }
+        entry = unsafe { entry.add(2) };
+    }
+}
@@ -0,0 +1,108 @@
+//! ユーザープロセスのヒープ
+//! 小さい割り当てはサイズクラスごとのフリーリストから取り、足りなければ brk でブレークを伸ばして切り出す
+//! MMAP_THRESHOLD を超えるものはページ単位で mmap し、解放時に munmap する
+
+use crate::sys;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// サイズクラス (ブロックはサイズの倍数の境界に置くので、アラインメントもここまで満たせる)
+const CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, MMAP_THRESHOLD];
+/// これより大きい割り当ては mmap する
+pub const MMAP_THRESHOLD: usize = 2048;
+const PAGE_SIZE: usize = 4096;
+/// ブレークを伸ばす単位
+const GROW_SIZE: usize = 64 * 1024;
+
+struct Heap {
+    /// 解放されたブロック (先頭の 8 バイトに次のブロックのアドレスを置く)
+    free: [*mut u8; CLASSES.len()],
+    /// brk で確保済みの未使用部分 [next, end)
+    next: usize,
+    end: usize,
+}
+
+/// スレッドを作るプログラムもあるので、スピンロックで守る
+pub struct RostAllocator {
+    locked: AtomicBool,
+    heap: core::cell::UnsafeCell<Heap>,
+}
+
+unsafe impl Sync for RostAllocator {}
+
+#[global_allocator]
+static ALLOCATOR: RostAllocator = RostAllocator {
+    locked: AtomicBool::new(false),
+    heap: core::cell::UnsafeCell::new(Heap { free: [ptr::null_mut(); CLASSES.len()], next: 0, end: 0 }),
+};
+
+fn class_index(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    CLASSES.iter().position(|&class| class >= size)
+}
+
+impl Heap {
+    /// ブレークを伸ばして size バイト (size の境界) を切り出す
+    fn carve(&mut self, size: usize) -> *mut u8 {
+        if self.end == 0 {
+            self.next = sys::brk(0);
+            self.end = self.next;
+        }
+        let start = (self.next + size - 1) & !(size - 1);
+        if start + size > self.end {
+            let want = (start + size - self.end).div_ceil(GROW_SIZE) * GROW_SIZE;
+            let new_end = sys::brk(self.end + want);
+            if new_end < start + size {
+                return ptr::null_mut();
+            }
+            self.end = new_end;
+        }
+        self.next = start + size;
+        start as *mut u8
+    }
+}
+
+impl RostAllocator {
+    fn with_heap<T>(&self, f: impl FnOnce(&mut Heap) -> T) -> T {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl GlobalAlloc for RostAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match class_index(&layout) {
+            Some(index) => self.with_heap(|heap| {
+                let block = heap.free[index];
+                if block.is_null() {
+                    heap.carve(CLASSES[index])
+                } else {
+                    heap.free[index] = *(block as *mut *mut u8);
+                    block
+                }
+            }),
+            // mmap はページ境界より大きいアラインメントを保証できない
+            None if layout.align() <= PAGE_SIZE => {
+                sys::mmap_anonymous(layout.size().div_ceil(PAGE_SIZE) * PAGE_SIZE).unwrap_or(ptr::null_mut())
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        match class_index(&layout) {
+            Some(index) => self.with_heap(|heap| {
+                *(block as *mut *mut u8) = heap.free[index];
+                heap.free[index] = block;
+            }),
+            None => {
+                let _ = sys::munmap(block, layout.size().div_ceil(PAGE_SIZE) * PAGE_SIZE);
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! 標準入出力 (sys_write / sys_read をそのまま使い、バッファはしない)
+
+use crate::sys::{self, Result};
+use core::fmt;
+
+pub const STDIN: i32 = 0;
+pub const STDOUT: i32 = 1;
+pub const STDERR: i32 = 2;
+
+/// fd に書き込む fmt::Write (短い書き込みは残りを書き直す)
+pub struct Fd(pub i32);
+
+impl Fd {
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match sys::write(self.0, buf) {
+                Ok(0) => return Err(sys::Errno::EAGAIN),
+                Ok(n) => buf = &buf[n..],
+                Err(sys::Errno::EINTR) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Write for Fd {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// 標準入力から読む (0 なら EOF)
+pub fn read(buf: &mut [u8]) -> Result<usize> {
+    sys::read(STDIN, buf)
+}
+
+#[doc(hidden)]
+pub fn _print(fd: i32, args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Fd(fd), args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,45 @@
+//! RomanticOS のユーザープログラム向けの実行時ライブラリ
+//! _start から main を呼ぶまでの準備 (argv/envp の解析、ヒープ)、システムコールのラッパー、
+//! println! と終了処理をまとめてあり、initramfs のテストプログラムを Rust で書ける
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! librost::main!(main);
+//!
+//! fn main() -> i32 {
+//!     librost::println!("hello from {}", librost::env::args().next().unwrap_or("?"));
+//!     0
+//! }
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+/// カーネルと同じ ABI の定義 (システムコール番号、定数、構造体)
+#[allow(dead_code)]
+#[path = "../../../src/abi.rs"]
+pub mod abi;
+
+pub mod env;
+pub mod heap;
+pub mod io;
+pub mod process;
+pub mod sys;
+
+mod rt;
+
+pub use sys::Errno;
+
+/// プログラムの main を指定する (戻り値は i32 か () か Result<(), Errno>)
+#[macro_export]
+macro_rules! main {
+    ($main:path) => {
+        #[no_mangle]
+        extern "Rust" fn __rost_main() -> i32 {
+            $crate::process::Termination::report($main())
+        }
+    };
+}
@@ -0,0 +1,70 @@
+//! プロセスの終了と終了時の処理
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// at_exit で登録できる関数の数
+const MAX_AT_EXIT: usize = 16;
+
+static HANDLERS: [AtomicUsize; MAX_AT_EXIT] = [const { AtomicUsize::new(0) }; MAX_AT_EXIT];
+static HANDLER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// 終了時に呼ぶ関数を登録する (登録と逆の順に呼ぶ)
+/// いっぱいなら登録せずに false を返す
+pub fn at_exit(handler: fn()) -> bool {
+    let index = HANDLER_COUNT.fetch_add(1, Ordering::SeqCst);
+    if index >= MAX_AT_EXIT {
+        HANDLER_COUNT.fetch_sub(1, Ordering::SeqCst);
+        return false;
+    }
+    HANDLERS[index].store(handler as usize, Ordering::SeqCst);
+    true
+}
+
+/// 登録された関数を呼んでから終了する
+pub fn exit(status: i32) -> ! {
+    // ハンドラの中から exit されても同じ関数を二度呼ばないよう、取り出しながら呼ぶ
+    loop {
+        let count = HANDLER_COUNT.load(Ordering::SeqCst);
+        if count == 0 {
+            break;
+        }
+        if HANDLER_COUNT.compare_exchange(count, count - 1, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            continue;
+        }
+        let handler = HANDLERS[count - 1].swap(0, Ordering::SeqCst);
+        if handler != 0 {
+            let handler: fn() = unsafe { core::mem::transmute(handler) };
+            handler();
+        }
+    }
+    crate::sys::exit(status)
+}
+
+/// main の戻り値を終了コードにする
+pub trait Termination {
+    fn report(self) -> i32;
+}
+
+impl Termination for () {
+    fn report(self) -> i32 {
+        0
+    }
+}
+
+impl Termination for i32 {
+    fn report(self) -> i32 {
+        self
+    }
+}
+
+impl Termination for Result<(), crate::Errno> {
+    fn report(self) -> i32 {
+        match self {
+            Ok(()) => 0,
+            Err(e) => {
+                crate::eprintln!("error: {}", e);
+                1
+            }
+        }
+    }
+}
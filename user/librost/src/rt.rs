@@ -0,0 +1,37 @@
+//! プログラムの入口 (_start) とパニック時の処理
+
+/// パニックしたときの終了コード (Rust の std と同じ)
+const PANIC_EXIT_CODE: i32 = 101;
+
+// カーネルは rsp に argc を指した状態で _start に飛んでくる
+// rbp を 0 にしてバックトレースをここで止め、16 バイト境界にそろえてから Rust に入る
+core::arch::global_asm!(
+    ".globl _start",
+    "_start:",
+    "xor ebp, ebp",
+    "mov rdi, rsp",
+    "and rsp, -16",
+    "call {start}",
+    "ud2",
+    start = sym start,
+);
+
+extern "Rust" {
+    /// main! で定義される
+    fn __rost_main() -> i32;
+}
+
+unsafe extern "C" fn start(sp: *const u64) -> ! {
+    crate::env::init(sp);
+    let status = __rost_main();
+    crate::process::exit(status)
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    match info.location() {
+        Some(location) => crate::eprintln!("panicked at {}: {}", location, info.message()),
+        None => crate::eprintln!("panicked: {}", info.message()),
+    }
+    crate::process::exit(PANIC_EXIT_CODE)
+}
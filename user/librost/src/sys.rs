@@ -0,0 +1,206 @@
+//! システムコールのラッパー
+//! 失敗したときはカーネルが返した -errno を Errno にする
+
+use crate::abi::syscalls::*;
+use core::arch::asm;
+use core::fmt;
+
+/// カーネルが返したエラー番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub i32);
+
+impl Errno {
+    pub const EPERM: Errno = Errno(1);
+    pub const ENOENT: Errno = Errno(2);
+    pub const EINTR: Errno = Errno(4);
+    pub const EBADF: Errno = Errno(9);
+    pub const EAGAIN: Errno = Errno(11);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EINVAL: Errno = Errno(22);
+    pub const ENAMETOOLONG: Errno = Errno(36);
+    pub const ENOSYS: Errno = Errno(38);
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "errno {}", self.0)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+/// 戻り値のうち -4095..-1 はエラー番号 (Linux と同じ)
+fn check(ret: i64) -> Result<usize> {
+    if (-4095..0).contains(&ret) {
+        Err(Errno(-ret as i32))
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+// syscall 命令は rcx/r11 を破壊する
+// 第4引数は rcx ではなく r10 で渡す
+
+/// # Safety
+/// 引数はシステムコールが求めるもの (指す先が有効なポインタなど) であること
+#[inline(always)]
+pub unsafe fn syscall0(number: u64) -> i64 {
+    let ret: i64;
+    asm!("syscall", inlateout("rax") number as i64 => ret,
+        out("rcx") _, out("r11") _, options(nostack));
+    ret
+}
+
+/// # Safety
+/// syscall0 と同じ
+#[inline(always)]
+pub unsafe fn syscall1(number: u64, arg1: u64) -> i64 {
+    let ret: i64;
+    asm!("syscall", inlateout("rax") number as i64 => ret, in("rdi") arg1,
+        out("rcx") _, out("r11") _, options(nostack));
+    ret
+}
+
+/// # Safety
+/// syscall0 と同じ
+#[inline(always)]
+pub unsafe fn syscall2(number: u64, arg1: u64, arg2: u64) -> i64 {
+    let ret: i64;
+    asm!("syscall", inlateout("rax") number as i64 => ret, in("rdi") arg1, in("rsi") arg2,
+        out("rcx") _, out("r11") _, options(nostack));
+    ret
+}
+
+/// # Safety
+/// syscall0 と同じ
+#[inline(always)]
+pub unsafe fn syscall3(number: u64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    let ret: i64;
+    asm!("syscall", inlateout("rax") number as i64 => ret, in("rdi") arg1, in("rsi") arg2, in("rdx") arg3,
+        out("rcx") _, out("r11") _, options(nostack));
+    ret
+}
+
+/// # Safety
+/// syscall0 と同じ
+#[inline(always)]
+pub unsafe fn syscall6(number: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64, arg6: u64) -> i64 {
+    let ret: i64;
+    asm!("syscall", inlateout("rax") number as i64 => ret, in("rdi") arg1, in("rsi") arg2, in("rdx") arg3,
+        in("r10") arg4, in("r8") arg5, in("r9") arg6,
+        out("rcx") _, out("r11") _, options(nostack));
+    ret
+}
+
+/// パスの最大長 (NUL を含む)
+const PATH_MAX: usize = 256;
+
+/// path を NUL 終端にしてスタック上のバッファで f に渡す
+fn with_c_path<T>(path: &str, f: impl FnOnce(*const u8) -> Result<T>) -> Result<T> {
+    let mut buf = [0u8; PATH_MAX];
+    if path.len() >= PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    if path.as_bytes().contains(&0) {
+        return Err(Errno::EINVAL);
+    }
+    buf[..path.len()].copy_from_slice(path.as_bytes());
+    f(buf.as_ptr())
+}
+
+pub fn read(fd: i32, buf: &mut [u8]) -> Result<usize> {
+    check(unsafe { syscall3(SYS_READ, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64) })
+}
+
+pub fn write(fd: i32, buf: &[u8]) -> Result<usize> {
+    check(unsafe { syscall3(SYS_WRITE, fd as u64, buf.as_ptr() as u64, buf.len() as u64) })
+}
+
+pub fn open(path: &str, flags: i32, mode: u32) -> Result<i32> {
+    with_c_path(path, |path| {
+        check(unsafe { syscall3(SYS_OPEN, path as u64, flags as u64, mode as u64) }).map(|fd| fd as i32)
+    })
+}
+
+pub fn close(fd: i32) -> Result<()> {
+    check(unsafe { syscall1(SYS_CLOSE, fd as u64) }).map(|_| ())
+}
+
+pub fn lseek(fd: i32, offset: i64, whence: i32) -> Result<u64> {
+    check(unsafe { syscall3(SYS_LSEEK, fd as u64, offset as u64, whence as u64) }).map(|pos| pos as u64)
+}
+
+pub fn unlink(path: &str) -> Result<()> {
+    with_c_path(path, |path| check(unsafe { syscall1(SYS_UNLINK, path as u64) }).map(|_| ()))
+}
+
+pub fn mkdir(path: &str, mode: u32) -> Result<()> {
+    with_c_path(path, |path| check(unsafe { syscall2(SYS_MKDIR, path as u64, mode as u64) }).map(|_| ()))
+}
+
+pub fn getpid() -> i32 {
+    unsafe { syscall0(SYS_GETPID) as i32 }
+}
+
+pub fn getppid() -> i32 {
+    unsafe { syscall0(SYS_GETPPID) as i32 }
+}
+
+pub fn gettid() -> i32 {
+    unsafe { syscall0(SYS_GETTID) as i32 }
+}
+
+/// プログラムブレークを addr に動かし、動かした後のブレークを返す (0 なら問い合わせ)
+/// 動かせなかったときは今のブレークが返る
+pub fn brk(addr: usize) -> usize {
+    unsafe { syscall1(SYS_BRK, addr as u64) as usize }
+}
+
+/// 無名の private マッピングを作る (内容はゼロ)
+pub fn mmap_anonymous(length: usize) -> Result<*mut u8> {
+    use crate::abi::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+    let ret = unsafe {
+        syscall6(SYS_MMAP, 0, length as u64, (PROT_READ | PROT_WRITE) as u64,
+            (MAP_PRIVATE | MAP_ANONYMOUS) as u64, -1i64 as u64, 0)
+    };
+    check(ret).map(|addr| addr as *mut u8)
+}
+
+/// # Safety
+/// [addr, addr + length) をこの後使わないこと
+pub unsafe fn munmap(addr: *mut u8, length: usize) -> Result<()> {
+    check(syscall2(SYS_MUNMAP, addr as u64, length as u64)).map(|_| ())
+}
+
+/// [from, from + num) の I/O ポートへのアクセスを許す/禁止する (許すには root が要る)
+pub fn ioperm(from: u16, num: usize, turn_on: bool) -> Result<()> {
+    check(unsafe { syscall3(SYS_IOPERM, from as u64, num as u64, turn_on as u64) }).map(|_| ())
+}
+
+/// I/O 特権レベルを変える (3 ですべてのポートを許す。cli/sti は許されない)
+pub fn iopl(level: u32) -> Result<()> {
+    check(unsafe { syscall1(SYS_IOPL, level as u64) }).map(|_| ())
+}
+
+/// スレッド tid (0 なら自分) を mask のビットが立った CPU だけで実行させる
+pub fn sched_setaffinity(tid: i32, mask: u64) -> Result<()> {
+    let bytes = mask.to_le_bytes();
+    check(unsafe { syscall3(SYS_SCHED_SETAFFINITY, tid as u64, bytes.len() as u64, bytes.as_ptr() as u64) })
+        .map(|_| ())
+}
+
+/// スレッド tid (0 なら自分) を実行してよい CPU
+pub fn sched_getaffinity(tid: i32) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    check(unsafe { syscall3(SYS_SCHED_GETAFFINITY, tid as u64, bytes.len() as u64, bytes.as_mut_ptr() as u64) })?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// プロセスを終える (終了処理は呼ばない、普通は process::exit を使う)
+pub fn exit(status: i32) -> ! {
+    unsafe {
+        syscall1(SYS_EXIT, status as u64);
+    }
+    unreachable!()
+}